@@ -0,0 +1,68 @@
+// Copyright (C) 2025 Ryan Daum <ryan.daum@gmail.com> This program is free
+// software: you can redistribute it and/or modify it under the terms of the GNU
+// General Public License as published by the Free Software Foundation, version
+// 3.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+//! Recursive fib, which is nearly all calls and returns, to measure what frame creation costs.
+//! Run with `cargo bench --bench fib`; it takes `N` and `RUNS` from the command line, as
+//! `cargo bench --bench fib -- 27 5`.
+
+use std::time::{Duration, Instant};
+use wasbox::{Execution, FramePool, Module, Value, VectorMemory};
+
+const FIB: &str = r#"(module (func $fib (export "fib") (param i32) (result i32)
+    (if (result i32) (i32.lt_u (local.get 0) (i32.const 2))
+        (then (local.get 0))
+        (else (i32.add
+            (call $fib (i32.sub (local.get 0) (i32.const 1)))
+            (call $fib (i32.sub (local.get 0) (i32.const 2))))))))"#;
+
+/// How many calls `fib(n)` makes, itself included.
+fn calls(n: u32) -> u64 {
+    let (mut a, mut b) = (1u64, 1u64);
+    for _ in 0..n {
+        (a, b) = (b, a + b + 1);
+    }
+    a
+}
+
+fn bench(name: &str, n: u32, runs: u32, mut execution: Execution<VectorMemory>) {
+    let mut best = Duration::MAX;
+    for _ in 0..runs {
+        let start = Instant::now();
+        let result = execution.invoke("fib", &[Value::I32(n as i32)]).unwrap();
+        best = best.min(start.elapsed());
+        std::hint::black_box(result);
+    }
+    let per_call = best.as_nanos() as f64 / calls(n) as f64;
+    println!(
+        "{name:>12}: fib({n}) best of {runs} {best:?}, {per_call:.1}ns a call, {} frames allocated",
+        execution.frame_pool().misses()
+    );
+}
+
+fn main() {
+    let mut args = std::env::args()
+        .skip(1)
+        .filter(|a| !a.starts_with('-'))
+        .map(|a| a.parse::<u32>().expect("N and RUNS are numbers"));
+    let n = args.next().unwrap_or(25);
+    let runs = args.next().unwrap_or(5);
+
+    let module = Module::load(&wat::parse_str(FIB).unwrap()).unwrap();
+    let instance = wasbox::mk_instance(module).unwrap();
+    let execution = || Execution::new(instance.clone(), VectorMemory::new(0, None));
+
+    bench("pooled", n, runs, execution());
+    let mut preallocated = execution();
+    preallocated.set_frame_pool(FramePool::with_capacity(n as usize, 8));
+    bench("preallocated", n, runs, preallocated);
+}
@@ -0,0 +1,139 @@
+// Copyright (C) 2025 Ryan Daum <ryan.daum@gmail.com> This program is free
+// software: you can redistribute it and/or modify it under the terms of the GNU
+// General Public License as published by the Free Software Foundation, version
+// 3.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+//! Reporting of guest behaviour that looks like it's going wrong, without stopping it: loops
+//! which keep going round, and calls nested unusually deep.
+
+use crate::frame::Frame;
+use crate::stack::SlotStorage;
+
+/// Something the guest did which crossed one of the `AnomalyThresholds`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Anomaly {
+    /// A loop in the function went back to its start more times than allowed, in one go. `pc`
+    /// is where the loop's body starts. Reported once per time the loop is entered.
+    LoopIterations {
+        funcidx: u32,
+        pc: usize,
+        iterations: u64,
+    },
+    /// A call to the function took the call stack deeper than allowed. Reported each time the
+    /// stack grows past the threshold, not for every call beyond it.
+    CallDepth { funcidx: u32, depth: usize },
+}
+
+/// Where reporting starts. `None` leaves that kind of anomaly unreported.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AnomalyThresholds {
+    pub loop_iterations: Option<u64>,
+    pub call_depth: Option<usize>,
+}
+
+/// Told about each anomaly as it happens. Execution carries on regardless.
+pub type AnomalyHook = Box<dyn FnMut(Anomaly) + Send>;
+
+pub(crate) struct AnomalyMonitor {
+    pub(crate) thresholds: AnomalyThresholds,
+    pub(crate) hook: AnomalyHook,
+}
+
+impl AnomalyMonitor {
+    /// A branch has just gone back to the start of the innermost loop in `frame`.
+    pub(crate) fn looped<S: SlotStorage>(&mut self, frame: &Frame<S>) {
+        let Some(limit) = self.thresholds.loop_iterations else {
+            return;
+        };
+        if let Some(control) = frame.control_stack.last() {
+            if control.back_edges == limit.saturating_add(1) {
+                (self.hook)(Anomaly::LoopIterations {
+                    funcidx: frame.funcidx,
+                    pc: control.start_pc,
+                    iterations: control.back_edges,
+                });
+            }
+        }
+    }
+
+    /// A frame for `funcidx` has just been pushed, making the call stack `depth` deep.
+    pub(crate) fn called(&mut self, funcidx: u32, depth: usize) {
+        if self
+            .thresholds
+            .call_depth
+            .map(|limit| limit.saturating_add(1))
+            == Some(depth)
+        {
+            (self.hook)(Anomaly::CallDepth { funcidx, depth });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exec::{Execution, Value};
+    use crate::{mk_instance, Module, VectorMemory};
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn anomalies_are_reported_without_stopping() {
+        let wat = r#"(module
+            (func $spin (export "spin") (param i32) (result i32) (local $i i32)
+                (loop $l
+                    (local.set $i (i32.add (local.get $i) (i32.const 1)))
+                    (br_if $l (i32.lt_u (local.get $i) (local.get 0))))
+                (local.get $i))
+            (func $down (export "down") (param i32) (result i32)
+                (if (result i32) (i32.eqz (local.get 0))
+                    (then (i32.const 0))
+                    (else (i32.add (i32.const 1)
+                        (call $down (i32.sub (local.get 0) (i32.const 1))))))))"#;
+        let instance = mk_instance(Module::load(&wat::parse_str(wat).unwrap()).unwrap()).unwrap();
+        let spin = instance.find_funcidx("spin").unwrap();
+        let down = instance.find_funcidx("down").unwrap();
+        let mut execution = Execution::new(instance, VectorMemory::new(0, None));
+        let seen = Arc::new(Mutex::new(vec![]));
+        let log = seen.clone();
+        let thresholds = AnomalyThresholds {
+            loop_iterations: Some(100),
+            call_depth: Some(5),
+        };
+        execution.on_anomaly(thresholds, move |a| log.lock().unwrap().push(a));
+
+        let mut call = |funcidx, arg| {
+            execution.prepare(funcidx, &[Value::I32(arg)]).unwrap();
+            execution.run().unwrap();
+            execution.result().unwrap()[0]
+        };
+        assert_eq!(call(spin, 100), Value::I32(100));
+        assert!(seen.lock().unwrap().is_empty());
+        assert_eq!(call(spin, 1000), Value::I32(1000));
+        assert_eq!(call(spin, 150), Value::I32(150));
+        assert_eq!(call(down, 10), Value::I32(10));
+
+        let seen = seen.lock().unwrap();
+        assert_eq!(seen.len(), 3);
+        assert!(matches!(
+            seen[0],
+            Anomaly::LoopIterations { funcidx, iterations: 101, .. } if funcidx == spin
+        ));
+        assert_eq!(seen[0], seen[1]);
+        assert_eq!(
+            seen[2],
+            Anomaly::CallDepth {
+                funcidx: down,
+                depth: 6
+            }
+        );
+    }
+}
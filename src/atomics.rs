@@ -0,0 +1,319 @@
+// Copyright (C) 2025 Ryan Daum <ryan.daum@gmail.com> This program is free
+// software: you can redistribute it and/or modify it under the terms of the GNU
+// General Public License as published by the Free Software Foundation, version
+// 3.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+//! The atomic memory instructions from the threads proposal, with single-threaded semantics.
+//! There's only ever one thread of execution, so every access is trivially sequentially
+//! consistent, `wait` never blocks, and `notify` never has anyone to wake.
+
+use crate::decode::{read_memarg, DecodeError};
+use crate::exec::{adjust_memarg, Fault};
+use crate::module::LEB128Reader;
+use crate::op::MemArg;
+use crate::opcode::AtomicOpCode;
+use crate::stack::{SlotStorage, Stack};
+use crate::Memory;
+
+/// The type and width of an atomic access. The narrower accesses are zero extended when loaded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AtomicWidth {
+    I32,
+    I64,
+    I32From8,
+    I32From16,
+    I64From8,
+    I64From16,
+    I64From32,
+}
+
+impl AtomicWidth {
+    /// Loads, stores, and each read-modify-write op come in runs of seven opcodes, one per width,
+    /// in this order.
+    const ORDER: [AtomicWidth; 7] = [
+        AtomicWidth::I32,
+        AtomicWidth::I64,
+        AtomicWidth::I32From8,
+        AtomicWidth::I32From16,
+        AtomicWidth::I64From8,
+        AtomicWidth::I64From16,
+        AtomicWidth::I64From32,
+    ];
+
+    fn bytes(&self) -> usize {
+        match self {
+            AtomicWidth::I32From8 | AtomicWidth::I64From8 => 1,
+            AtomicWidth::I32From16 | AtomicWidth::I64From16 => 2,
+            AtomicWidth::I32 | AtomicWidth::I64From32 => 4,
+            AtomicWidth::I64 => 8,
+        }
+    }
+
+    fn is_i64(&self) -> bool {
+        matches!(
+            self,
+            AtomicWidth::I64
+                | AtomicWidth::I64From8
+                | AtomicWidth::I64From16
+                | AtomicWidth::I64From32
+        )
+    }
+
+    /// log2 of the access size, which is the only alignment an atomic memarg may have.
+    fn align(&self) -> u8 {
+        self.bytes().trailing_zeros() as u8
+    }
+
+    fn mask(&self) -> u64 {
+        match self.bytes() {
+            8 => u64::MAX,
+            n => (1 << (n * 8)) - 1,
+        }
+    }
+
+    fn pop_operand<S: SlotStorage>(&self, stack: &mut Stack<S>) -> Result<u64, Fault> {
+        if self.is_i64() {
+            stack.pop_u64()
+        } else {
+            stack.pop_u32().map(|v| v as u64)
+        }
+    }
+
+    fn push_result<S: SlotStorage>(&self, stack: &mut Stack<S>, value: u64) {
+        if self.is_i64() {
+            stack.push_u64(value);
+        } else {
+            stack.push_u32(value as u32);
+        }
+    }
+
+    fn load<M: Memory>(&self, memory: &M, addr: usize) -> Result<u64, Fault> {
+        Ok(match self.bytes() {
+            1 => memory.get_u8(addr)? as u64,
+            2 => memory.get_u16(addr)? as u64,
+            4 => memory.get_u32(addr)? as u64,
+            _ => memory.get_u64(addr)?,
+        })
+    }
+
+    fn store<M: Memory>(&self, memory: &mut M, addr: usize, value: u64) -> Result<(), Fault> {
+        match self.bytes() {
+            1 => memory.set_u8(addr, value as u8),
+            2 => memory.set_u16(addr, value as u16),
+            4 => memory.set_u32(addr, value as u32),
+            _ => memory.set_u64(addr, value),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RmwOp {
+    Add,
+    Sub,
+    And,
+    Or,
+    Xor,
+    Xchg,
+}
+
+impl RmwOp {
+    fn apply(&self, old: u64, operand: u64) -> u64 {
+        match self {
+            RmwOp::Add => old.wrapping_add(operand),
+            RmwOp::Sub => old.wrapping_sub(operand),
+            RmwOp::And => old & operand,
+            RmwOp::Or => old | operand,
+            RmwOp::Xor => old ^ operand,
+            RmwOp::Xchg => operand,
+        }
+    }
+}
+
+/// The decoded form of the 0xFE-prefixed instructions.
+#[derive(Clone, Debug, PartialEq)]
+pub enum AtomicOp {
+    Notify(MemArg),
+    Wait32(MemArg),
+    Wait64(MemArg),
+    Fence,
+    Load(AtomicWidth, MemArg),
+    Store(AtomicWidth, MemArg),
+    Rmw(RmwOp, AtomicWidth, MemArg),
+    Cmpxchg(AtomicWidth, MemArg),
+}
+
+impl AtomicOp {
+    /// The memarg of any op but a fence.
+    pub(crate) fn memarg(&self) -> Option<&MemArg> {
+        match self {
+            AtomicOp::Notify(memarg)
+            | AtomicOp::Wait32(memarg)
+            | AtomicOp::Wait64(memarg)
+            | AtomicOp::Load(_, memarg)
+            | AtomicOp::Store(_, memarg)
+            | AtomicOp::Rmw(_, _, memarg)
+            | AtomicOp::Cmpxchg(_, memarg) => Some(memarg),
+            AtomicOp::Fence => None,
+        }
+    }
+
+    /// For an op which writes to memory: how many stack slots sit above its address operand, its
+    /// memarg, and how many bytes it writes.
+    pub(crate) fn memory_write(&self) -> Option<(usize, &MemArg, usize)> {
+        match self {
+            AtomicOp::Store(width, memarg) | AtomicOp::Rmw(_, width, memarg) => {
+                Some((1, memarg, width.bytes()))
+            }
+            AtomicOp::Cmpxchg(width, memarg) => Some((2, memarg, width.bytes())),
+            _ => None,
+        }
+    }
+
+    /// Decode the instruction following an 0xFE prefix.
+    pub(crate) fn read(reader: &mut LEB128Reader) -> Result<Self, DecodeError> {
+        let code = AtomicOpCode::read(reader)?;
+        let atomic_memarg = |reader: &mut LEB128Reader, width: AtomicWidth| {
+            read_exact_memarg(reader, width.align())
+        };
+        // Loads, stores and read-modify-writes come in runs of seven, one per width.
+        let sub_opcode = code as u32;
+        Ok(match code {
+            AtomicOpCode::MemoryAtomicNotify => AtomicOp::Notify(read_exact_memarg(reader, 2)?),
+            AtomicOpCode::MemoryAtomicWait32 => AtomicOp::Wait32(read_exact_memarg(reader, 2)?),
+            AtomicOpCode::MemoryAtomicWait64 => AtomicOp::Wait64(read_exact_memarg(reader, 3)?),
+            AtomicOpCode::AtomicFence => {
+                let reserved = reader.load_imm_u8()?;
+                if reserved != 0 {
+                    return Err(DecodeError::FailedToDecode(format!(
+                        "Expected atomic.fence 0x00, got {reserved:#0x}"
+                    )));
+                }
+                AtomicOp::Fence
+            }
+            _ if sub_opcode <= 0x16 => {
+                let width = AtomicWidth::ORDER[(sub_opcode - 0x10) as usize];
+                AtomicOp::Load(width, atomic_memarg(reader, width)?)
+            }
+            _ if sub_opcode <= 0x1d => {
+                let width = AtomicWidth::ORDER[(sub_opcode - 0x17) as usize];
+                AtomicOp::Store(width, atomic_memarg(reader, width)?)
+            }
+            _ if sub_opcode <= 0x47 => {
+                let group = (sub_opcode - 0x1e) / 7;
+                let width = AtomicWidth::ORDER[((sub_opcode - 0x1e) % 7) as usize];
+                let op = [
+                    RmwOp::Add,
+                    RmwOp::Sub,
+                    RmwOp::And,
+                    RmwOp::Or,
+                    RmwOp::Xor,
+                    RmwOp::Xchg,
+                ][group as usize];
+                AtomicOp::Rmw(op, width, atomic_memarg(reader, width)?)
+            }
+            _ => {
+                let width = AtomicWidth::ORDER[(sub_opcode - 0x48) as usize];
+                AtomicOp::Cmpxchg(width, atomic_memarg(reader, width)?)
+            }
+        })
+    }
+
+    pub(crate) fn execute<M: Memory, S: SlotStorage>(
+        &self,
+        stack: &mut Stack<S>,
+        memory: &mut M,
+    ) -> Result<(), Fault> {
+        match *self {
+            AtomicOp::Fence => {}
+            AtomicOp::Notify(memarg) => {
+                let _count = stack.pop_u32()?;
+                checked_addr(stack, &memarg, 4, memory)?;
+                // Nobody else exists to be waiting.
+                stack.push_u32(0);
+            }
+            AtomicOp::Wait32(memarg) | AtomicOp::Wait64(memarg) => {
+                let width = if matches!(self, AtomicOp::Wait32(_)) {
+                    AtomicWidth::I32
+                } else {
+                    AtomicWidth::I64
+                };
+                let _timeout = stack.pop_i64()?;
+                let expected = width.pop_operand(stack)?;
+                let addr = checked_addr(stack, &memarg, width.bytes(), memory)?;
+                let current = width.load(memory, addr)?;
+                // 1 is "not-equal". Otherwise we'd block until woken, which nothing else can do,
+                // so report 2, "timed-out", straight away rather than hang forever.
+                stack.push_u32(if current != expected { 1 } else { 2 });
+            }
+            AtomicOp::Load(width, memarg) => {
+                let addr = checked_addr(stack, &memarg, width.bytes(), memory)?;
+                let value = width.load(memory, addr)?;
+                width.push_result(stack, value);
+            }
+            AtomicOp::Store(width, memarg) => {
+                let value = width.pop_operand(stack)?;
+                let addr = checked_addr(stack, &memarg, width.bytes(), memory)?;
+                width.store(memory, addr, value)?;
+            }
+            AtomicOp::Rmw(op, width, memarg) => {
+                let operand = width.pop_operand(stack)? & width.mask();
+                let addr = checked_addr(stack, &memarg, width.bytes(), memory)?;
+                let old = width.load(memory, addr)?;
+                width.store(memory, addr, op.apply(old, operand) & width.mask())?;
+                width.push_result(stack, old);
+            }
+            AtomicOp::Cmpxchg(width, memarg) => {
+                let replacement = width.pop_operand(stack)? & width.mask();
+                let expected = width.pop_operand(stack)? & width.mask();
+                let addr = checked_addr(stack, &memarg, width.bytes(), memory)?;
+                let old = width.load(memory, addr)?;
+                if old == expected {
+                    width.store(memory, addr, replacement)?;
+                }
+                width.push_result(stack, old);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Atomic memargs must give exactly the natural alignment, not just at most it.
+fn read_exact_memarg(reader: &mut LEB128Reader, align: u8) -> Result<MemArg, DecodeError> {
+    let memarg = read_memarg(reader, align)?;
+    if memarg.align != align as u32 {
+        return Err(DecodeError::MalformedMemory(format!(
+            "Atomic access must be naturally aligned, got {:#0x}",
+            memarg.align
+        )));
+    }
+    Ok(memarg)
+}
+
+/// Pop the address for an atomic access, which unlike other accesses must actually be aligned.
+/// Bounds are checked first, as out of bounds takes precedence over unaligned.
+fn checked_addr<M: Memory, S: SlotStorage>(
+    stack: &mut Stack<S>,
+    memarg: &MemArg,
+    bytes: usize,
+    memory: &M,
+) -> Result<usize, Fault> {
+    let addr = adjust_memarg(stack, memarg, memory.size())?;
+    if addr
+        .checked_add(bytes)
+        .is_none_or(|end| end > memory.size())
+    {
+        return Err(Fault::MemoryOutOfBounds);
+    }
+    if addr % bytes != 0 {
+        return Err(Fault::UnalignedAtomic);
+    }
+    Ok(addr)
+}
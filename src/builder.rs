@@ -0,0 +1,478 @@
+// Copyright (C) 2025 Ryan Daum <ryan.daum@gmail.com> This program is free
+// software: you can redistribute it and/or modify it under the terms of the GNU
+// General Public License as published by the Free Software Foundation, version
+// 3.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+use crate::decode::decode_function;
+use crate::instance::{link, LinkError};
+use crate::linker::Linker;
+use crate::module::{Data, ElementMode, Elements, Import, ImportExportKind};
+use crate::op::Op;
+use crate::visit::OpVisitor;
+use crate::{Instance, Module, VectorMemory};
+use std::collections::HashSet;
+
+/// Caps on what a module may ask for when it's instantiated. A module whose declared minimums
+/// exceed them fails to instantiate; declared maximums are lowered to them.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct InstanceLimits {
+    /// Most pages a memory the module defines may start with or grow to.
+    pub max_memory_pages: Option<u32>,
+    /// Most elements a table the module defines may start with or grow to.
+    pub max_table_elements: Option<u32>,
+    /// Most ticks instantiating the module may take, between evaluating its constant
+    /// expressions, writing its active segments -- a tick an element, and a tick per 64 bytes of
+    /// data -- and running its start function. Instantiation which would take more fails with
+    /// `LinkError::InstantiationBudgetExceeded`.
+    pub instantiation_fuel: Option<u64>,
+    /// Most values, struct fields and array elements together, the instance's GC heap may hold.
+    /// Allocating past it faults with `Fault::GcHeapExhausted`. Without it the heap is held to
+    /// `DEFAULT_MAX_GC_VALUES`, so a guest can't ask for an array big enough to take the host
+    /// down.
+    #[cfg(feature = "gc")]
+    pub max_gc_values: Option<u64>,
+}
+
+impl InstanceLimits {
+    pub(crate) fn memory(
+        &self,
+        limits: (u32, Option<u32>),
+    ) -> Result<(u32, Option<u32>), LinkError> {
+        clamp(limits, self.max_memory_pages, "memory pages")
+    }
+
+    pub(crate) fn table(
+        &self,
+        limits: (u32, Option<u32>),
+    ) -> Result<(u32, Option<u32>), LinkError> {
+        clamp(limits, self.max_table_elements, "table elements")
+    }
+}
+
+fn clamp(
+    (min, max): (u32, Option<u32>),
+    cap: Option<u32>,
+    what: &str,
+) -> Result<(u32, Option<u32>), LinkError> {
+    let Some(cap) = cap else {
+        return Ok((min, max));
+    };
+    if min > cap {
+        return Err(LinkError::LimitExceeded(format!(
+            "{min} {what} requested, limit is {cap}"
+        )));
+    }
+    Ok((min, Some(max.map_or(cap, |max| max.min(cap)))))
+}
+
+/// Makes the memories an instance starts with, given their initial and maximum size in bytes.
+pub type MemoryBackend<'a> = Box<dyn FnMut(usize, Option<usize>) -> VectorMemory + 'a>;
+
+/// Instantiates a module, with whatever imports, limits and checks are asked for. Each setting
+/// has a default, so `InstanceBuilder::new(module).build()` behaves like `mk_instance`.
+pub struct InstanceBuilder<'a> {
+    module: Module,
+    linker: Option<&'a Linker>,
+    memory_backend: MemoryBackend<'a>,
+    limits: InstanceLimits,
+    validate: bool,
+    lazy_decoding: bool,
+    resettable: bool,
+}
+
+impl<'a> InstanceBuilder<'a> {
+    /// By default no imports are provided, and calling an imported function traps.
+    pub fn new(module: Module) -> Self {
+        InstanceBuilder {
+            module,
+            linker: None,
+            memory_backend: Box::new(VectorMemory::new),
+            limits: InstanceLimits::default(),
+            validate: false,
+            lazy_decoding: false,
+            resettable: false,
+        }
+    }
+
+    /// Create memories with `backend` rather than zeroed vectors of their minimum size.
+    pub fn memory_backend(
+        mut self,
+        backend: impl FnMut(usize, Option<usize>) -> VectorMemory + 'a,
+    ) -> Self {
+        self.memory_backend = Box::new(backend);
+        self
+    }
+
+    /// Resolve imports against `linker`.
+    pub fn imports(mut self, linker: &'a Linker) -> Self {
+        self.linker = Some(linker);
+        self
+    }
+
+    pub fn limits(mut self, limits: InstanceLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Check, before anything is instantiated, that every index in the module refers to
+    /// something that exists and that it uses nothing we don't support.
+    pub fn validate(mut self, validate: bool) -> Self {
+        self.validate = validate;
+        self
+    }
+
+    /// Decode each function body the first time it's called rather than while instantiating,
+    /// for large modules of which only a few functions are used. A body that fails to decode
+    /// then makes calls to it fail, rather than instantiation.
+    pub fn lazy_decoding(mut self, lazy: bool) -> Self {
+        self.lazy_decoding = lazy;
+        self
+    }
+
+    /// Keep a copy of the instance's state once it's instantiated, so that `Instance::reset` can
+    /// put it back. This costs a second copy of its memories.
+    pub fn resettable(mut self, resettable: bool) -> Self {
+        self.resettable = resettable;
+        self
+    }
+
+    pub fn build(mut self) -> Result<Instance, LinkError> {
+        if self.validate {
+            validate(&self.module)?;
+        }
+        let mut unresolved = Linker::new();
+        unresolved.allow_unresolved(true);
+        let linker = self.linker.unwrap_or(&unresolved);
+        let imports = linker.resolve_imports(&self.module, &mut self.memory_backend)?;
+        link(
+            self.module,
+            imports,
+            &self.limits,
+            &mut self.memory_backend,
+            self.lazy_decoding,
+            self.resettable,
+        )
+    }
+}
+
+/// Every global a function body sets, in order.
+#[derive(Default)]
+struct GlobalSets(Vec<u32>);
+
+impl OpVisitor for GlobalSets {
+    fn variable(&mut self, _pc: usize, op: &Op) {
+        if let Op::SetGlobal(g) = op {
+            self.0.push(*g);
+        }
+    }
+}
+
+fn validate(module: &Module) -> Result<(), LinkError> {
+    let invalid = |what: String| Err(LinkError::InvalidModule(what));
+
+    let (mut funcs, mut tables, mut memories, mut globals) = (0, 0, 0, 0);
+    for (_, _, import) in &module.imports {
+        match import {
+            Import::Func(typeidx) => {
+                if *typeidx as usize >= module.types.len() {
+                    return invalid(format!("imported function has unknown type {typeidx}"));
+                }
+                funcs += 1;
+            }
+            Import::Table(_, _) => tables += 1,
+            Import::Memory(_) => memories += 1,
+            Import::Global(_, _) => globals += 1,
+        }
+    }
+    funcs += module.functions.len();
+    tables += module.tables.len();
+    memories += module.memories.len();
+    globals += module.globals.len();
+
+    for (i, typeidx) in module.functions.iter().enumerate() {
+        if *typeidx >= module.types.len() {
+            return invalid(format!("function {i} has unknown type {typeidx}"));
+        }
+    }
+    let mut names = HashSet::new();
+    for export in module.exports() {
+        if !names.insert(export.name.as_str()) {
+            return invalid(format!("export {:?} appears twice", export.name));
+        }
+        let count = match export.kind {
+            ImportExportKind::Function => funcs,
+            ImportExportKind::Table => tables,
+            ImportExportKind::Memory => memories,
+            ImportExportKind::Global => globals,
+        };
+        if export.index as usize >= count {
+            return invalid(format!(
+                "export {:?} refers to unknown {:?} {}",
+                export.name, export.kind, export.index
+            ));
+        }
+    }
+    if let Some(start) = module.start_function {
+        if start >= funcs {
+            return invalid(format!("unknown start function {start}"));
+        }
+    }
+    for segment in &module.element_segments {
+        if let ElementMode::Active { table_index, .. } = segment.mode {
+            if table_index as usize >= tables {
+                return invalid(format!("element segment for unknown table {table_index}"));
+            }
+        }
+        if let Elements::Function(indices) = &segment.elements {
+            if let Some(funcidx) = indices.iter().find(|idx| **idx as usize >= funcs) {
+                return invalid(format!(
+                    "element segment refers to unknown function {funcidx}"
+                ));
+            }
+        }
+    }
+    for data in &module.data {
+        let memidx = match data {
+            Data::Active { .. } => 0,
+            Data::ActiveMemIdx { memidx, .. } => *memidx as usize,
+            Data::Passive { .. } => continue,
+        };
+        if memidx >= memories {
+            return invalid(format!("data segment for unknown memory {memidx}"));
+        }
+    }
+
+    // Which globals, counting imports, can be set.
+    let mut mutable: Vec<bool> = module
+        .imports
+        .iter()
+        .filter_map(|(_, _, import)| match import {
+            Import::Global(_, mutable) => Some(*mutable),
+            _ => None,
+        })
+        .collect();
+    mutable.extend(module.globals.iter().map(|g| g.mutable));
+    for (i, typeidx) in module.functions.iter().enumerate() {
+        // Bodies which don't decode are left for instantiation to report.
+        let (Some(body), Some(func_type)) = (module.body(i), module.types.get(*typeidx)) else {
+            continue;
+        };
+        let Ok(program) = decode_function(body, &module.types, func_type) else {
+            continue;
+        };
+        let mut sets = GlobalSets::default();
+        program.visit(&mut sets);
+        for g in sets.0 {
+            match mutable.get(g as usize) {
+                Some(true) => {}
+                Some(false) => return invalid(format!("function {i} sets immutable global {g}")),
+                None => return invalid(format!("function {i} sets unknown global {g}")),
+            }
+        }
+    }
+
+    let unsupported = module.check_support();
+    if !unsupported.is_empty() {
+        let list: Vec<_> = unsupported.iter().map(|u| u.to_string()).collect();
+        return Err(LinkError::UnsupportedFeature(list.join("; ")));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builder::{InstanceBuilder, InstanceLimits};
+    use crate::decode::DecodeError;
+    use crate::exec::{ExecError, Execution, Fault, Value};
+    use crate::instance::LinkError;
+    use crate::linker::Linker;
+    use crate::memory::VectorMemory;
+    use crate::module::Module;
+
+    const WAT: &str = r#"(module
+        (import "env" "base" (global $base i32))
+        (memory 1 4)
+        (table 2 funcref)
+        (func (export "grow") (param i32) (result i32)
+            (i32.add (global.get $base) (memory.grow (local.get 0)))))"#;
+
+    fn load() -> Module {
+        Module::load(&wat::parse_str(WAT).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn builder_applies_imports_limits_and_backend() {
+        let mut linker = Linker::new();
+        linker.global("env", "base", Value::I32(100), false);
+        let mut created = vec![];
+        let instance = InstanceBuilder::new(load())
+            .imports(&linker)
+            .limits(InstanceLimits {
+                max_memory_pages: Some(2),
+                max_table_elements: Some(2),
+                ..Default::default()
+            })
+            .memory_backend(|min, max| {
+                created.push((min, max));
+                VectorMemory::new(min, max)
+            })
+            .validate(true)
+            .build()
+            .unwrap();
+        assert_eq!(created, vec![(65536, Some(2 * 65536))]);
+        assert_eq!(instance.tables[0].limits, (2, Some(2)));
+
+        // The memory's maximum was lowered to the limit.
+        let funcidx = instance.find_funcidx("grow").unwrap();
+        let memory = instance.memories[0].clone();
+        let mut execution = Execution::new(instance, memory);
+        let mut grow = |pages| {
+            execution.prepare(funcidx, &[Value::I32(pages)]).unwrap();
+            execution.run().unwrap();
+            execution.result().unwrap().to_vec()
+        };
+        assert_eq!(grow(1), vec![Value::I32(101)]);
+        assert_eq!(grow(1), vec![Value::I32(99)]);
+
+        // A module asking for more than the limits up front fails to instantiate.
+        assert!(matches!(
+            InstanceBuilder::new(load())
+                .limits(InstanceLimits {
+                    max_table_elements: Some(1),
+                    ..Default::default()
+                })
+                .build(),
+            Err(LinkError::LimitExceeded(_))
+        ));
+    }
+
+    #[test]
+    fn instantiation_fuel_covers_segments_and_start() {
+        let wat = r#"(module
+            (memory 1)
+            (global $n (mut i32) (i32.const 0))
+            (data (i32.const 0) "0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef0")
+            (func $start
+                (loop $l
+                    (global.set $n (i32.add (global.get $n) (i32.const 1)))
+                    (br_if $l (i32.lt_u (global.get $n) (i32.const 100)))))
+            (start $start))"#;
+        let build = |fuel| {
+            InstanceBuilder::new(Module::load(&wat::parse_str(wat).unwrap()).unwrap())
+                .limits(InstanceLimits {
+                    instantiation_fuel: fuel,
+                    ..Default::default()
+                })
+                .build()
+        };
+        assert!(build(None).is_ok());
+        assert!(build(Some(10_000)).is_ok());
+        // Enough for the global and the data, which takes two ticks, but not the start function.
+        assert!(matches!(
+            build(Some(10)),
+            Err(LinkError::InstantiationBudgetExceeded)
+        ));
+        // Not even enough for the data.
+        assert!(matches!(
+            build(Some(2)),
+            Err(LinkError::InstantiationBudgetExceeded)
+        ));
+    }
+
+    #[test]
+    fn validation_catches_bad_indices() {
+        let wat = r#"(module (func) (export "f" (func 3)))"#;
+        let load = || Module::load(&wat::parse_str(wat).unwrap()).unwrap();
+        assert!(InstanceBuilder::new(load()).build().is_ok());
+        assert!(matches!(
+            InstanceBuilder::new(load()).validate(true).build(),
+            Err(LinkError::InvalidModule(_))
+        ));
+    }
+
+    #[test]
+    fn malformed_branches_rejected_before_running() {
+        let build = |body: &str| {
+            let wat = format!(r#"(module (func (result i32) {body}))"#);
+            InstanceBuilder::new(Module::load(&wat::parse_str(wat).unwrap()).unwrap()).build()
+        };
+        assert!(build("(block (block (br 2))) (i32.const 0)").is_ok());
+        assert!(matches!(
+            build("(block (block (br 3))) (i32.const 0)"),
+            Err(LinkError::DecodeError(DecodeError::UnknownLabel(3)))
+        ));
+        assert!(matches!(
+            build("(block (br_if 2 (i32.const 1))) (i32.const 0)"),
+            Err(LinkError::DecodeError(DecodeError::UnknownLabel(2)))
+        ));
+        // The function's label takes one value, the block's none.
+        assert!(matches!(
+            build("(i32.const 0) (block (br_table 0 1 (i32.const 0)))"),
+            Err(LinkError::DecodeError(DecodeError::BranchArityMismatch))
+        ));
+    }
+
+    #[test]
+    fn lazy_decoding_defers_bodies() {
+        // `bad` uses an op we can't decode.
+        let wat = r#"(module
+            (memory 1)
+            (data $d "x")
+            (func (export "good") (result i32) (i32.const 7))
+            (func (export "bad") (memory.init $d (i32.const 0) (i32.const 0) (i32.const 1))))"#;
+        let load = || Module::load(&wat::parse_str(wat).unwrap()).unwrap();
+        assert!(matches!(
+            InstanceBuilder::new(load()).build(),
+            Err(LinkError::DecodeError(_))
+        ));
+
+        let instance = InstanceBuilder::new(load())
+            .lazy_decoding(true)
+            .build()
+            .unwrap();
+        assert!(instance.programs.iter().all(|p| p.get().is_none()));
+        let good = instance.find_funcidx("good").unwrap();
+        let bad = instance.find_funcidx("bad").unwrap();
+        let mut execution = Execution::new(instance, VectorMemory::new(0, None));
+        execution.prepare(good, &[]).unwrap();
+        execution.run().unwrap();
+        assert_eq!(execution.result().unwrap(), &[Value::I32(7)]);
+        assert!(execution.instance().programs[0].get().is_some());
+        assert!(execution.instance().programs[1].get().is_none());
+        assert!(matches!(
+            execution.prepare(bad, &[]),
+            Err(ExecError::LinkageError(LinkError::DecodeError(_)))
+        ));
+    }
+
+    #[test]
+    fn immutable_globals_cant_be_set() {
+        let wat = r#"(module
+            (global $g i32 (i32.const 1))
+            (func (export "f") (global.set $g (i32.const 2))))"#;
+        let load = || Module::load(&wat::parse_str(wat).unwrap()).unwrap();
+        assert!(matches!(
+            InstanceBuilder::new(load()).validate(true).build(),
+            Err(LinkError::InvalidModule(_))
+        ));
+
+        // Without validation, it's caught when it runs.
+        let instance = InstanceBuilder::new(load()).build().unwrap();
+        let funcidx = instance.find_funcidx("f").unwrap();
+        let mut execution = Execution::new(instance, VectorMemory::new(0, None));
+        execution.prepare(funcidx, &[]).unwrap();
+        assert!(matches!(
+            execution.run(),
+            Err(ExecError::ExecutionFault(Fault::ImmutableGlobal(0)))
+        ));
+        assert_eq!(execution.instance().globals[0].value, Value::I32(1));
+    }
+}
@@ -0,0 +1,299 @@
+// Copyright (C) 2025 Ryan Daum <ryan.daum@gmail.com> This program is free
+// software: you can redistribute it and/or modify it under the terms of the GNU
+// General Public License as published by the Free Software Foundation, version
+// 3.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+//! Modules kept loaded and instantiated between uses, for embedders which run the same few
+//! modules over and over, each time in an instance of its own: one per request, say.
+
+use crate::builder::InstanceBuilder;
+use crate::error::Error;
+use crate::exec::Execution;
+use crate::instance::{Instance, LinkError};
+use crate::linker::Linker;
+use crate::memory::{DirtyTrackingMemory, VectorMemory};
+use crate::module::Module;
+use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+/// An execution handed out by a `ModuleCache`, running against its instance's own memory.
+pub type PooledExecution = Execution<DirtyTrackingMemory<VectorMemory>>;
+
+/// How many idle instances of each module are kept, unless told otherwise.
+pub const DEFAULT_MAX_POOLED: usize = 16;
+
+/// Loaded modules, by the contents of their binaries, each with a pool of instances ready to run.
+///
+/// Each module is loaded, decoded and instantiated once, against the cache's linker. After that,
+/// an instance of it is a copy of that first one, so its start function isn't run again. Instances
+/// handed back with `recycle` are reset to how they were when instantiated and pooled, up to a
+/// limit, for the next caller.
+pub struct ModuleCache {
+    linker: Linker,
+    max_pooled: usize,
+    entries: HashMap<u64, Vec<CacheEntry>>,
+}
+
+struct CacheEntry {
+    template: Instance,
+    pool: Vec<Instance>,
+}
+
+fn content_hash(bytes: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    bytes.hash(&mut hasher);
+    hasher.finish()
+}
+
+impl ModuleCache {
+    /// Modules will be instantiated against `linker`.
+    pub fn new(linker: Linker) -> Self {
+        ModuleCache {
+            linker,
+            max_pooled: DEFAULT_MAX_POOLED,
+            entries: HashMap::new(),
+        }
+    }
+
+    /// Keep at most `max` idle instances of each module; any more handed back are dropped.
+    pub fn set_max_pooled(&mut self, max: usize) -> &mut Self {
+        self.max_pooled = max;
+        self
+    }
+
+    /// An instance of the module `bytes` holds, fresh from instantiation, loading the module if
+    /// it isn't cached yet.
+    pub fn instantiate(&mut self, bytes: &[u8]) -> Result<Instance, Error> {
+        let entry = self.entry(bytes)?;
+        Ok(entry.pool.pop().unwrap_or_else(|| entry.template.clone()))
+    }
+
+    /// Make sure there are `count` instances of the module `bytes` holds ready, up to the pool's
+    /// limit, so that the next callers don't have to wait for them.
+    pub fn prewarm(&mut self, bytes: &[u8], count: usize) -> Result<(), Error> {
+        let max_pooled = self.max_pooled;
+        let entry = self.entry(bytes)?;
+        while entry.pool.len() < count.min(max_pooled) {
+            entry.pool.push(entry.template.clone());
+        }
+        Ok(())
+    }
+
+    /// Hand back an instance which came from `instantiate`, to be reset and reused. One taken
+    /// over by an execution has to be taken back with `Execution::into_instance_with_memory`
+    /// first. Instances of modules the cache doesn't hold, and any past the pool's limit, are
+    /// dropped.
+    pub fn recycle(&mut self, mut instance: Instance) -> Result<(), LinkError> {
+        let max_pooled = self.max_pooled;
+        let Some(entry) = self.find_mut(&instance.module.module_data) else {
+            return Ok(());
+        };
+        if entry.pool.len() < max_pooled {
+            instance.reset()?;
+            entry.pool.push(instance);
+        }
+        Ok(())
+    }
+
+    /// An execution of a fresh instance of the module `bytes` holds, running against the
+    /// instance's own memory rather than a copy, and tracking the pages stored to so that
+    /// `recycle_execution` only has to put those back.
+    pub fn execution(&mut self, bytes: &[u8]) -> Result<PooledExecution, Error> {
+        let mut instance = self.instantiate(bytes)?;
+        let memory = match instance.memories.first_mut() {
+            Some(memory) => std::mem::replace(memory, VectorMemory::new(0, None)),
+            None => VectorMemory::new(0, None),
+        };
+        Ok(Execution::new(instance, DirtyTrackingMemory::new(memory)))
+    }
+
+    /// Hand back an execution which came from `execution`, resetting only what it changed. That's
+    /// every page written since it was made, including any the host has since taken out of the
+    /// dirty set with `Execution::take_dirty_pages`.
+    pub fn recycle_execution(&mut self, execution: PooledExecution) -> Result<(), LinkError> {
+        let dirty = execution.memory().pages_written();
+        let mut instance = execution.into_instance_with_memory();
+        let max_pooled = self.max_pooled;
+        let Some(entry) = self.find_mut(&instance.module.module_data) else {
+            return Ok(());
+        };
+        if entry.pool.len() < max_pooled {
+            instance.reset_pages(&dirty)?;
+            entry.pool.push(instance);
+        }
+        Ok(())
+    }
+
+    /// Idle instances of the module `bytes` holds, or 0 if it isn't cached.
+    pub fn pooled(&self, bytes: &[u8]) -> usize {
+        self.entries
+            .get(&content_hash(bytes))
+            .and_then(|entries| {
+                entries
+                    .iter()
+                    .find(|e| e.template.module.module_data == bytes)
+            })
+            .map_or(0, |entry| entry.pool.len())
+    }
+
+    /// How many modules are cached.
+    pub fn len(&self) -> usize {
+        self.entries.values().map(Vec::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Forget the module `bytes` holds, along with its idle instances.
+    pub fn evict(&mut self, bytes: &[u8]) {
+        let hash = content_hash(bytes);
+        if let Some(entries) = self.entries.get_mut(&hash) {
+            entries.retain(|e| e.template.module.module_data != bytes);
+            if entries.is_empty() {
+                self.entries.remove(&hash);
+            }
+        }
+    }
+
+    fn find_mut(&mut self, bytes: &[u8]) -> Option<&mut CacheEntry> {
+        // Binaries with the same hash are told apart by comparing them in full.
+        self.entries
+            .get_mut(&content_hash(bytes))?
+            .iter_mut()
+            .find(|e| e.template.module.module_data == bytes)
+    }
+
+    fn entry(&mut self, bytes: &[u8]) -> Result<&mut CacheEntry, Error> {
+        if self.find_mut(bytes).is_none() {
+            let module = Module::load(bytes)?;
+            let template = InstanceBuilder::new(module)
+                .imports(&self.linker)
+                .resettable(true)
+                .build()?;
+            self.entries
+                .entry(content_hash(bytes))
+                .or_default()
+                .push(CacheEntry {
+                    template,
+                    pool: vec![],
+                });
+        }
+        Ok(self.find_mut(bytes).expect("module was just cached"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cache::ModuleCache;
+    use crate::exec::{Execution, Value};
+    use crate::instance::LinkError;
+    use crate::linker::Linker;
+    use crate::marshal;
+    use crate::memory::Memory;
+    use crate::module::Module;
+
+    const WAT: &str = r#"(module
+        (memory (export "mem") 1)
+        (global $count (mut i32) (i32.const 0))
+        (data (i32.const 0) "\05")
+        (func $start (global.set $count (i32.const 10)))
+        (start $start)
+        (func (export "bump") (result i32)
+            (i32.store8 (i32.const 0) (i32.add (i32.load8_u (i32.const 0)) (i32.const 1)))
+            (global.set $count (i32.add (global.get $count) (i32.load8_u (i32.const 0))))
+            (global.get $count))
+        (func (export "grow") (result i32) (memory.grow (i32.const 1))))"#;
+
+    #[test]
+    fn recycled_instances_start_over() {
+        let bytes = wat::parse_str(WAT).unwrap();
+        let mut cache = ModuleCache::new(Linker::new());
+        cache.prewarm(&bytes, 2).unwrap();
+        assert_eq!((cache.len(), cache.pooled(&bytes)), (1, 2));
+
+        for _ in 0..3 {
+            let instance = cache.instantiate(&bytes).unwrap();
+            let memory = instance.memories[0].clone();
+            let mut execution = Execution::new(instance, memory);
+            // The start function's effects are kept; the run's are thrown away.
+            assert_eq!(execution.invoke("bump", &[]).unwrap(), vec![Value::I32(16)]);
+            assert_eq!(execution.invoke("bump", &[]).unwrap(), vec![Value::I32(23)]);
+            assert_eq!(execution.memory().data()[0], 7);
+            cache
+                .recycle(execution.into_instance_with_memory())
+                .unwrap();
+        }
+        assert_eq!(cache.pooled(&bytes), 2);
+
+        // Executions from the cache only put back the pages they stored to, and undo growth.
+        for _ in 0..2 {
+            let mut execution = cache.execution(&bytes).unwrap();
+            assert_eq!(execution.invoke("bump", &[]).unwrap(), vec![Value::I32(16)]);
+            assert_eq!(execution.invoke("grow", &[]).unwrap(), vec![Value::I32(1)]);
+            assert_eq!(execution.memory().dirty_pages(), Some(vec![0]));
+            cache.recycle_execution(execution).unwrap();
+        }
+
+        cache.evict(&bytes);
+        assert!(cache.is_empty());
+
+        // Only instances built to be reset can be.
+        let module = Module::load(&bytes).unwrap();
+        let mut instance = Linker::new().instantiate(module).unwrap();
+        assert!(matches!(instance.reset(), Err(LinkError::NotResettable)));
+    }
+
+    #[test]
+    fn host_writes_are_reset() {
+        let wat = r#"(module (memory 3)
+            (func (export "peek") (param i32) (result i32) (i32.load8_u (local.get 0))))"#;
+        let bytes = wat::parse_str(wat).unwrap();
+        let mut cache = ModuleCache::new(Linker::new());
+        let peek = |execution: &mut super::PooledExecution, addr: i32| {
+            execution.invoke("peek", &[Value::I32(addr)]).unwrap()
+        };
+
+        // Writing through `range_mut`, as the marshalling helpers do, marks only what's written.
+        let mut execution = cache.execution(&bytes).unwrap();
+        marshal::write_bytes(execution.memory_mut(), 70000, &[42]).unwrap();
+        assert_eq!(execution.memory().dirty_pages(), Some(vec![1]));
+        assert_eq!(peek(&mut execution, 70000), vec![Value::I32(42)]);
+        cache.recycle_execution(execution).unwrap();
+
+        // Writing through `data_mut` marks everything.
+        let mut execution = cache.execution(&bytes).unwrap();
+        assert_eq!(peek(&mut execution, 70000), vec![Value::I32(0)]);
+        execution.memory_mut().data_mut()[140000] = 42;
+        assert_eq!(execution.memory().dirty_pages(), Some(vec![0, 1, 2]));
+        cache.recycle_execution(execution).unwrap();
+
+        let mut execution = cache.execution(&bytes).unwrap();
+        assert_eq!(peek(&mut execution, 140000), vec![Value::I32(0)]);
+    }
+
+    #[test]
+    fn taken_dirty_pages_are_still_reset() {
+        let wat = r#"(module (memory 1)
+            (func (export "poke") (i32.store (i32.const 8) (i32.const 0x5ec2e7)))
+            (func (export "peek") (result i32) (i32.load (i32.const 8))))"#;
+        let bytes = wat::parse_str(wat).unwrap();
+        let mut cache = ModuleCache::new(Linker::new());
+
+        let mut execution = cache.execution(&bytes).unwrap();
+        execution.invoke("poke", &[]).unwrap();
+        assert_eq!(execution.take_dirty_pages(), Some(vec![0]));
+        cache.recycle_execution(execution).unwrap();
+
+        let mut execution = cache.execution(&bytes).unwrap();
+        assert_eq!(execution.invoke("peek", &[]).unwrap(), vec![Value::I32(0)]);
+    }
+}
@@ -0,0 +1,204 @@
+// Copyright (C) 2025 Ryan Daum <ryan.daum@gmail.com> This program is free
+// software: you can redistribute it and/or modify it under the terms of the GNU
+// General Public License as published by the Free Software Foundation, version
+// 3.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+//! Which ops have been executed, across every execution in the process, for finding the
+//! interpreter paths a test suite never reaches. Only built with the `coverage` feature.
+//!
+//! ```ignore
+//! // ... run the test suite ...
+//! let report = wasbox::coverage::report();
+//! assert!(report.fraction() >= 0.9, "{report}");
+//! ```
+
+use crate::op::Op;
+use std::cell::RefCell;
+use std::collections::{BTreeSet, HashSet};
+use std::fmt::{Display, Formatter};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+/// Names the ops, and lists them all. Every `Op` variant has to be listed here, or the match
+/// in `op_name` won't compile.
+macro_rules! op_names {
+    ($($(#[$meta:meta])* $name:ident),* $(,)?) => {
+        fn op_name(op: &Op) -> &'static str {
+            match op {
+                $($(#[$meta])* Op::$name { .. } => stringify!($name),)*
+            }
+        }
+
+        /// Every op this build of the interpreter has, by name.
+        // Pushed one at a time, as some are only there with some features.
+        #[allow(clippy::vec_init_then_push)]
+        pub fn all_ops() -> Vec<&'static str> {
+            let mut all = vec![];
+            $($(#[$meta])* all.push(stringify!($name));)*
+            all
+        }
+    };
+}
+
+op_names! {
+    Nop, Unreachable, Throw, ThrowRef, StartScope, EndScope, If, Else, Br, BrIf, BrTable, Return,
+    Call, CallIndirect, Drop, Select, GetLocal, SetLocal, TeeLocal, GetGlobal, SetGlobal, TableGet,
+    TableSet, LoadI32, LoadI64, LoadF32, LoadF64, Load8SE, Load8Ze, Load16Se, Load16Ze, Load8I64Se,
+    Load8I64Ze, Load16I64Se, Load16I64Ze, Load32I64Se, Load32I64Ze, StoreI32, StoreI64, StoreF32,
+    StoreF64, Store8_32, Store16_32, Store8_64, Store16_64, Store32_64, I32Const, I64Const,
+    F32Const, F64Const, MemorySize, MemoryGrow, I32Eqz, I32Eq, I32Ne, I32LtS, I32LtU, I32GtS,
+    I32GtU, I32LeS, I32LeU, I32GeS, I32GeU, I64Eqz, I64Eq, I64Ne, I64LtS, I64LtU, I64GtS, I64GtU,
+    I64LeS, I64LeU, I64GeS, I64GeU, F32Eq, F32Ne, F32Lt, F32Gt, F32Le, F32Ge, F64Eq, F64Ne, F64Lt,
+    F64Gt, F64Le, F64Ge, I32Clz, I32Ctz, I32Popcnt, I32Add, I32Sub, I32Mul, I32DivS, I32DivU,
+    I32RemS, I32RemU, I32And, I32Or, I32Xor, I32Shl, I32ShrS, I32ShrU, I32Rotl, I32Rotr, I64Clz,
+    I64Ctz, I64Popcnt, I64Add, I64Sub, I64Mul, I64DivS, I64DivU, I64RemS, I64RemU, I64And, I64Or,
+    I64Xor, I64Shl, I64ShrS, I64ShrU, I64Rotl, I64Rotr, F32Abs, F32Neg, F32Ceil, F32Floor,
+    F32Trunc, F32Nearest, F32Sqrt, F32Add, F32Sub, F32Mul, F32Div, F32Min, F32Max, F32Copysign,
+    F64Add, F64Sub, F64Mul, F64Div, F64Min, F64Max, F64Copysign, F64Abs, F64Neg, F64Ceil, F64Floor,
+    F64Trunc, F64Nearest, F64Sqrt, I32WrapI64, I32TruncF32S, I32TruncF32U, I32TruncF64S,
+    I32TruncF64U, I64ExtendI32S, I64ExtendI32U, I64TruncF32S, I64TruncF32U, I64TruncF64S,
+    I64TruncF64U, I32TruncSatF32S, I32TruncSatF32U, I32TruncSatF64S, I32TruncSatF64U,
+    I64TruncSatF32S, I64TruncSatF32U, I64TruncSatF64S, I64TruncSatF64U, F32ConvertI32S,
+    F32ConvertI32U, F32ConvertI64S, F32ConvertI64U, F32DemoteF64, F64ConvertI32S, F64ConvertI32U,
+    F64ConvertI64S, F64ConvertI64U, F64PromoteF32, I32ReinterpretF32, I64ReinterpretF64,
+    F32ReinterpretI32, F64ReinterpretI64, I32Extend8S, I32Extend16S, I64Extend8S, I64Extend16S,
+    I64Extend32S, RefNull, RefFunc, RefIsNull, RefAsNonNull, RefEq, SelectT,
+    #[cfg(feature = "gc")]
+    Gc,
+    #[cfg(feature = "atomics")]
+    Atomic,
+    #[cfg(feature = "optimize")]
+    LocalI32AddConst,
+    #[cfg(feature = "optimize")]
+    LocalLoadI32,
+    #[cfg(feature = "optimize")]
+    ConstStoreI32,
+    #[cfg(feature = "optimize")]
+    BrIfI32Cmp,
+    #[cfg(feature = "optimize")]
+    BrIfEqz,
+}
+
+/// Every op executed since the last `reset`.
+static EXECUTED: Mutex<BTreeSet<&'static str>> = Mutex::new(BTreeSet::new());
+/// Bumped by `reset`, so each thread knows to forget what it's already passed on.
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+thread_local! {
+    /// The ops this thread has already added to `EXECUTED`, and as of which generation, so the
+    /// shared set is only locked the first time each op is seen.
+    static SEEN: RefCell<(u64, HashSet<&'static str>)> = RefCell::new((0, HashSet::new()));
+}
+
+/// Note that `op` has been executed.
+#[inline]
+pub(crate) fn record(op: &Op) {
+    let name = op_name(op);
+    SEEN.with(|seen| {
+        let mut seen = seen.borrow_mut();
+        let generation = GENERATION.load(Ordering::Acquire);
+        if seen.0 != generation {
+            *seen = (generation, HashSet::new());
+        }
+        if seen.1.insert(name) {
+            EXECUTED.lock().unwrap().insert(name);
+        }
+    });
+}
+
+/// Every op executed since the last `reset`, in name order.
+pub fn executed() -> Vec<&'static str> {
+    EXECUTED.lock().unwrap().iter().copied().collect()
+}
+
+/// Forget everything executed so far.
+pub fn reset() {
+    let mut executed = EXECUTED.lock().unwrap();
+    GENERATION.fetch_add(1, Ordering::AcqRel);
+    executed.clear();
+}
+
+/// How much of the interpreter has been exercised.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CoverageReport {
+    /// Ops executed at least once, in name order.
+    pub executed: Vec<&'static str>,
+    /// Ops never executed, in name order.
+    pub missed: Vec<&'static str>,
+}
+
+impl CoverageReport {
+    /// The fraction of all ops executed, from 0 to 1.
+    pub fn fraction(&self) -> f64 {
+        let total = self.executed.len() + self.missed.len();
+        match total {
+            0 => 1.0,
+            _ => self.executed.len() as f64 / total as f64,
+        }
+    }
+}
+
+impl Display for CoverageReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} of {} ops executed ({:.1}%)",
+            self.executed.len(),
+            self.executed.len() + self.missed.len(),
+            self.fraction() * 100.0
+        )?;
+        if !self.missed.is_empty() {
+            write!(f, "; never executed: {}", self.missed.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Compare what's been executed against every op there is.
+pub fn report() -> CoverageReport {
+    let executed = executed();
+    let mut missed: Vec<_> = all_ops()
+        .into_iter()
+        .filter(|op| executed.binary_search(op).is_err())
+        .collect();
+    missed.sort_unstable();
+    CoverageReport { executed, missed }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::coverage::{all_ops, report};
+    use crate::exec::{Execution, Value};
+    use crate::instance::mk_instance;
+    use crate::memory::VectorMemory;
+    use crate::module::Module;
+
+    #[test]
+    fn executed_ops_are_reported() {
+        let wat = r#"(module
+            (func (export "f") (param i32) (result i32)
+                (i32.rotl (local.get 0) (i32.const 3))))"#;
+        let module = Module::load(&wat::parse_str(wat).unwrap()).unwrap();
+        let linked = mk_instance(module).unwrap();
+        let funcidx = linked.find_funcidx("f").unwrap();
+        let mut execution = Execution::new(linked, VectorMemory::new(0, None));
+        execution.prepare(funcidx, &[Value::I32(1)]).unwrap();
+        execution.run().unwrap();
+
+        // Other tests run alongside this one, so there may be more covered, but never less.
+        let report = report();
+        assert!(report.executed.contains(&"I32Rotl"));
+        assert!(!report.missed.contains(&"I32Rotl"));
+        assert_eq!(report.executed.len() + report.missed.len(), all_ops().len());
+        assert!(report.fraction() > 0.0 && report.fraction() <= 1.0);
+        assert!(report.to_string().contains(" ops executed "));
+    }
+}
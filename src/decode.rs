@@ -13,19 +13,25 @@
 
 use crate::module::LEB128Reader;
 use crate::op::{MemArg, Op};
-use crate::opcode::OpCode;
-use crate::{TypeSignature, ValueType};
+use crate::opcode::{MiscOpCode, OpCode, SimdOpCode};
+use crate::{FuncType, TypeSignature, ValueType};
 use std::error::Error;
 use std::fmt::{Debug, Display, Formatter};
 
+/// A decoded function body or expression: the same ops the interpreter runs, so tools analysing a
+/// module see exactly what will execute.
 #[derive(Debug, Clone, PartialEq)]
 pub struct Program {
     pub ops: Vec<Op>,
     pub local_types: Vec<ValueType>,
+    /// Where each local starts in the frame's local slots, plus a final entry for the total, so
+    /// that local `i` spans `local_offsets[i]..local_offsets[i + 1]`.
+    pub local_offsets: Vec<usize>,
     pub return_types: Vec<ValueType>,
 }
 
 #[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
 pub enum DecodeError {
     InvalidOpcode(u8),
     UnimplementedOpcode(u8, String),
@@ -34,6 +40,19 @@ pub enum DecodeError {
     InvalidDataSegmentType(u32),
     UnsupportedType(u32, String),
     MalformedMemory(String),
+    NonConstantInstruction(u8),
+    /// A function body went over its op limit, having decoded this many ops when it stopped.
+    TooManyOps(usize),
+    /// A branch to a label further out than the scopes it's in.
+    UnknownLabel(u32),
+    /// The targets of a `br_table` take different numbers of values.
+    BranchArityMismatch,
+    /// A prefix byte followed by a sub-opcode it doesn't have.
+    InvalidSubOpcode(u8, u32),
+    /// A prefixed instruction we don't implement: its prefix, sub-opcode and name.
+    UnimplementedSubOpcode(u8, u32, String),
+    /// The code ran out with this many scopes still open, the outermost's `end` among them.
+    UnclosedScopes(usize),
 }
 
 impl Display for DecodeError {
@@ -60,15 +79,62 @@ impl Display for DecodeError {
             DecodeError::MalformedMemory(reason) => {
                 write!(f, "Malformed memory: {reason}")
             }
+            DecodeError::NonConstantInstruction(opcode) => {
+                write!(
+                    f,
+                    "Instruction {opcode:#0x} not allowed in a constant expression"
+                )
+            }
+            DecodeError::TooManyOps(ops) => {
+                write!(f, "Function body over its op limit after {ops} ops")
+            }
+            DecodeError::UnknownLabel(depth) => write!(f, "Branch to unknown label {depth}"),
+            DecodeError::BranchArityMismatch => {
+                write!(f, "Branch table targets take different numbers of values")
+            }
+            DecodeError::InvalidSubOpcode(prefix, sub_opcode) => {
+                write!(f, "Invalid opcode: {prefix:#0x} {sub_opcode:#0x}")
+            }
+            DecodeError::UnimplementedSubOpcode(prefix, sub_opcode, name) => {
+                write!(
+                    f,
+                    "Unimplemented opcode: {prefix:#0x} {sub_opcode:#0x} ({name})"
+                )
+            }
+            DecodeError::UnclosedScopes(open) => {
+                write!(f, "Code ended with {open} scopes still open")
+            }
         }
     }
 }
 
 impl Error for DecodeError {}
 
+impl DecodeError {
+    /// Stable numeric code for this error, in the 2000s.
+    pub fn code(&self) -> u32 {
+        match self {
+            DecodeError::InvalidOpcode(_) => 2001,
+            DecodeError::UnimplementedOpcode(_, _) => 2002,
+            DecodeError::InvalidSignature(_) => 2003,
+            DecodeError::FailedToDecode(_) => 2004,
+            DecodeError::InvalidDataSegmentType(_) => 2005,
+            DecodeError::UnsupportedType(_, _) => 2006,
+            DecodeError::MalformedMemory(_) => 2007,
+            DecodeError::NonConstantInstruction(_) => 2008,
+            DecodeError::TooManyOps(_) => 2009,
+            DecodeError::UnknownLabel(_) => 2010,
+            DecodeError::BranchArityMismatch => 2011,
+            DecodeError::InvalidSubOpcode(_, _) => 2012,
+            DecodeError::UnimplementedSubOpcode(_, _, _) => 2013,
+            DecodeError::UnclosedScopes(_) => 2014,
+        }
+    }
+}
+
 const MAX_MEMORY_OFFSET: u32 = 0xffff_ffff;
 
-fn read_memarg(reader: &mut LEB128Reader, max_align: u8) -> Result<MemArg, DecodeError> {
+pub(crate) fn read_memarg(reader: &mut LEB128Reader, max_align: u8) -> Result<MemArg, DecodeError> {
     // align, offset in mem
     let align = reader.load_imm_varuint32()?;
     // we load offset as a u64, but then check it's within the bounds of a u32, this seemed
@@ -93,6 +159,7 @@ fn read_memarg(reader: &mut LEB128Reader, max_align: u8) -> Result<MemArg, Decod
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
+#[non_exhaustive]
 pub enum ScopeType {
     Program,
     Function,
@@ -101,13 +168,70 @@ pub enum ScopeType {
     IfElse,
 }
 
+/// The shape of a scope as the interpreter sees it: how many stack slots it takes in, and how
+/// many it leaves behind. This is resolved from the block type (or function type) at decode time,
+/// so nothing needs to look at types when entering, leaving or branching out of a scope.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ScopeSig {
+    pub params: u32,
+    pub results: u32,
+}
+
+impl ScopeSig {
+    /// How many stack slots a branch to a scope of this shape carries: its params for a loop,
+    /// whose label is its start, and its results for anything else.
+    pub fn branch_arity(&self, scope_type: ScopeType) -> u32 {
+        match scope_type {
+            ScopeType::Loop => self.params,
+            _ => self.results,
+        }
+    }
+
+    fn of_function(types: &[ValueType], results: &[ValueType]) -> Self {
+        ScopeSig {
+            params: types.iter().map(|t| t.slot_width()).sum(),
+            results: results.iter().map(|t| t.slot_width()).sum(),
+        }
+    }
+
+    fn resolve(types: &[FuncType], signature: TypeSignature) -> Result<Self, DecodeError> {
+        match signature {
+            TypeSignature::ValueType(vt) => Ok(ScopeSig {
+                params: 0,
+                results: vt.slot_width(),
+            }),
+            TypeSignature::Index(idx) => {
+                let ft = types
+                    .get(idx as usize)
+                    .ok_or(DecodeError::InvalidSignature(idx))?;
+                Ok(Self::of_function(&ft.params, &ft.results))
+            }
+        }
+    }
+}
+
+/// Whether a block type's results are exactly its params, so that an `if` with nothing to do on
+/// one side can leave the stack as it found it.
+fn passes_through(types: &[FuncType], signature: TypeSignature) -> bool {
+    match signature {
+        TypeSignature::ValueType(vt) => vt.slot_width() == 0,
+        TypeSignature::Index(idx) => types
+            .get(idx as usize)
+            .is_some_and(|ft| ft.params == ft.results),
+    }
+}
+
 struct Scope {
     scope_type: ScopeType,
-    #[allow(dead_code)] // May be used for future optimization
-    signature: TypeSignature,
+    signature: ScopeSig,
     #[allow(dead_code)] // May be used for future optimization
     /// Position where this scope ends (for structured control flow)
     end_position: Option<usize>,
+    /// For an `if`, whether it may leave out its `else` arm: a missing arm passes the params
+    /// straight through as results, so that's only allowed when the two are the same types.
+    else_optional: bool,
+    /// For an `if`, whether its `else` has been seen yet.
+    has_else: bool,
 }
 
 impl Default for Program {
@@ -121,70 +245,166 @@ impl Program {
         Program {
             ops: vec![],
             local_types: vec![],
+            local_offsets: vec![0],
             return_types: vec![],
         }
     }
 
+    pub fn set_local_types(&mut self, local_types: Vec<ValueType>) {
+        self.local_offsets.clear();
+        let mut offset = 0;
+        self.local_offsets.push(offset);
+        for ty in &local_types {
+            offset += match ty {
+                ValueType::V128 => 2,
+                _ => 1,
+            };
+            self.local_offsets.push(offset);
+        }
+        self.local_types = local_types;
+    }
+
     pub fn push(&mut self, op: Op) {
         self.ops.push(op);
     }
+
+    /// Each op with how many scopes it's inside. A `StartScope` and its `EndScope`, and any `If`
+    /// or `Else` between them, are at the depth outside the scope they delimit.
+    pub fn ops_with_depth(&self) -> impl Iterator<Item = (usize, &Op)> + '_ {
+        let mut depth = 0usize;
+        self.ops.iter().map(move |op| {
+            let at = match op {
+                Op::StartScope(..) => {
+                    depth += 1;
+                    depth - 1
+                }
+                Op::EndScope(_) => {
+                    depth = depth.saturating_sub(1);
+                    depth
+                }
+                Op::If(_) | Op::Else(_) => depth.saturating_sub(1),
+                _ => depth,
+            };
+            (at, op)
+        })
+    }
+}
+
+/// The arity of the label `depth` scopes out from the innermost of `scope_stack`, checking there
+/// is one.
+fn label_arity(scope_stack: &[Scope], depth: u32) -> Result<u32, DecodeError> {
+    let scope = (depth as usize)
+        .checked_add(1)
+        .and_then(|up| scope_stack.len().checked_sub(up))
+        .map(|at| &scope_stack[at])
+        .ok_or(DecodeError::UnknownLabel(depth))?;
+    Ok(scope.signature.branch_arity(scope.scope_type))
 }
 
 fn mk_program() -> Scope {
     Scope {
         scope_type: ScopeType::Program,
-        signature: TypeSignature::ValueType(ValueType::Unit),
+        signature: ScopeSig::default(),
         end_position: None,
+        else_optional: true,
+        has_else: false,
     }
 }
 
-fn mk_loop(signature: TypeSignature) -> Scope {
+fn mk_loop(signature: ScopeSig) -> Scope {
     Scope {
         scope_type: ScopeType::Loop,
         signature,
         end_position: None,
+        else_optional: true,
+        has_else: false,
     }
 }
 
-fn mk_block(signature: TypeSignature) -> Scope {
+fn mk_block(signature: ScopeSig) -> Scope {
     Scope {
         scope_type: ScopeType::Block,
         signature,
         end_position: None,
+        else_optional: true,
+        has_else: false,
     }
 }
 
-fn mk_if_else(signature: TypeSignature) -> Scope {
+fn mk_if_else(signature: ScopeSig, else_optional: bool) -> Scope {
     Scope {
         scope_type: ScopeType::IfElse,
         signature,
         end_position: None,
+        else_optional,
+        has_else: false,
     }
 }
 
-// Unused function - kept for potential future use
-#[allow(dead_code)]
-fn mk_function(return_types: Vec<ValueType>) -> Scope {
+fn mk_function(signature: ScopeSig) -> Scope {
     Scope {
         scope_type: ScopeType::Function,
-        signature: TypeSignature::ValueType(if return_types.len() == 1 {
-            return_types[0]
-        } else {
-            ValueType::Unit // Multi-value returns need FunctionType handling
-        }),
+        signature,
         end_position: None,
+        else_optional: true,
+        has_else: false,
     }
 }
 
-pub fn decode(program_stream: &[u8]) -> Result<Program, DecodeError> {
+/// Decode a function body. The function itself is the outermost scope of the resulting program,
+/// opened by its first op, so that branches to it behave like any other scope.
+pub fn decode_function(
+    program_stream: &[u8],
+    types: &[FuncType],
+    func_type: &FuncType,
+) -> Result<Program, DecodeError> {
+    decode_function_capped(program_stream, types, func_type, None)
+}
+
+/// As `decode_function`, but given `max_ops`, giving up with `DecodeError::TooManyOps` as soon as
+/// the body has decoded to more than that many ops, however much of it is left.
+pub(crate) fn decode_function_capped(
+    program_stream: &[u8],
+    types: &[FuncType],
+    func_type: &FuncType,
+    max_ops: Option<usize>,
+) -> Result<Program, DecodeError> {
+    // A function's parameters live in its locals, not on its stack, so its scope takes no params.
+    let signature = ScopeSig::of_function(&[], &func_type.results);
     let mut prg = Program::new();
+    prg.push(Op::StartScope(signature, ScopeType::Function));
+    prg.return_types = func_type.results.clone();
+    decode_into(prg, program_stream, types, mk_function(signature), max_ops)
+}
+
+/// Decode a free-standing expression, such as a global initializer or segment offset.
+pub fn decode(program_stream: &[u8]) -> Result<Program, DecodeError> {
+    decode_into(Program::new(), program_stream, &[], mk_program(), None)
+}
+
+fn decode_into(
+    mut prg: Program,
+    program_stream: &[u8],
+    types: &[FuncType],
+    outer_scope: Scope,
+    max_ops: Option<usize>,
+) -> Result<Program, DecodeError> {
     // The assumption is that program_stream is after locals, where the opcodes begin.
     let mut reader = LEB128Reader::new(program_stream, 0);
 
-    let mut scope_stack = vec![mk_program()];
+    // A function's body closes its own scope; a free-standing expression's `end` isn't given to
+    // us, so its scope is left open.
+    let closes_outer = outer_scope.scope_type == ScopeType::Function;
+    let mut scope_stack = vec![outer_scope];
+
+    let check_ops = |prg: &Program| match max_ops {
+        Some(max_ops) if prg.ops.len() > max_ops => Err(DecodeError::TooManyOps(prg.ops.len())),
+        _ => Ok(()),
+    };
 
     // Decode the raw program stream and translate it into our ADT Op
     while reader.remaining() != 0 {
+        check_ops(&prg)?;
         let opcode_o = reader.load_imm_u8()?;
         let opcode: OpCode =
             OpCode::from_repr(opcode_o).ok_or(DecodeError::InvalidOpcode(opcode_o))?;
@@ -199,36 +419,55 @@ pub fn decode(program_stream: &[u8]) -> Result<Program, DecodeError> {
             }
 
             OpCode::Block => {
-                let signature = ValueType::read_signature(&mut reader)?;
+                let signature = ScopeSig::resolve(types, ValueType::read_signature(&mut reader)?)?;
                 let block = mk_block(signature);
                 scope_stack.push(block);
                 prg.push(Op::StartScope(signature, ScopeType::Block));
             }
             OpCode::Loop => {
-                let signature = ValueType::read_signature(&mut reader)?;
+                let signature = ScopeSig::resolve(types, ValueType::read_signature(&mut reader)?)?;
                 let block = mk_loop(signature);
                 prg.push(Op::StartScope(signature, ScopeType::Loop));
                 scope_stack.push(block);
             }
             OpCode::If => {
-                let signature = ValueType::read_signature(&mut reader)?;
-                let block = mk_if_else(signature);
+                let block_type = ValueType::read_signature(&mut reader)?;
+                let signature = ScopeSig::resolve(types, block_type)?;
+                let block = mk_if_else(signature, passes_through(types, block_type));
 
                 prg.push(Op::StartScope(signature, ScopeType::IfElse));
-                prg.push(Op::If);
+                // Where each arm jumps to is filled in by `match_if_arms` once the body's done.
+                prg.push(Op::If(0));
 
                 scope_stack.push(block);
             }
             OpCode::Else => {
-                // The last block on the stack should be an IfBlock, otherwise that's corrupt program.
-                let if_block = scope_stack.last().unwrap();
-                assert_eq!(if_block.scope_type, ScopeType::IfElse);
+                // The last block on the stack should be an if without an else of its own,
+                // otherwise that's a corrupt program.
+                match scope_stack.last_mut() {
+                    Some(block) if block.scope_type == ScopeType::IfElse && !block.has_else => {
+                        block.has_else = true;
+                    }
+                    _ => {
+                        return Err(DecodeError::FailedToDecode(
+                            "else outside of an if".to_string(),
+                        ))
+                    }
+                }
 
                 // No more implicit branches - just mark else position
-                prg.push(Op::Else);
+                prg.push(Op::Else(0));
             }
             OpCode::End => {
-                let block = scope_stack.pop().unwrap();
+                let block = scope_stack.pop().ok_or_else(|| {
+                    DecodeError::FailedToDecode("end without an enclosing block".to_string())
+                })?;
+                if block.scope_type == ScopeType::IfElse && !block.has_else && !block.else_optional
+                {
+                    return Err(DecodeError::FailedToDecode(
+                        "if without an else must produce its params as results".to_string(),
+                    ));
+                }
 
                 // Always push an EndScope.
                 prg.push(Op::EndScope(block.scope_type));
@@ -236,17 +475,27 @@ pub fn decode(program_stream: &[u8]) -> Result<Program, DecodeError> {
 
             OpCode::Br => {
                 let depth = reader.load_imm_varuint32()?;
+                label_arity(&scope_stack, depth)?;
                 // Store the relative depth directly instead of converting to absolute label
                 prg.push(Op::Br(depth));
             }
             OpCode::BrIf => {
                 let depth = reader.load_imm_varuint32()?;
+                label_arity(&scope_stack, depth)?;
                 // Store the relative depth directly instead of converting to absolute label
                 prg.push(Op::BrIf(depth));
             }
             OpCode::BrTable => {
                 let depth_table = reader.load_array_varu32()?;
                 let default = reader.load_imm_varuint32()?;
+                // Only slot counts are known here, which is as much as the interpreter needs to
+                // carry the values across; their types are left to the ops which use them.
+                let arity = label_arity(&scope_stack, default)?;
+                for depth in &depth_table {
+                    if label_arity(&scope_stack, *depth)? != arity {
+                        return Err(DecodeError::BranchArityMismatch);
+                    }
+                }
                 // Store the relative depths directly instead of converting to absolute labels
                 prg.push(Op::BrTable(depth_table, default));
             }
@@ -420,7 +669,7 @@ pub fn decode(program_stream: &[u8]) -> Result<Program, DecodeError> {
                 prg.push(Op::F32Const(value));
             }
             OpCode::F64Const => {
-                let value = reader.load_imm_f64();
+                let value = reader.load_imm_f64()?;
                 prg.push(Op::F64Const(value));
             }
             OpCode::I32Eqz => {
@@ -826,11 +1075,17 @@ pub fn decode(program_stream: &[u8]) -> Result<Program, DecodeError> {
                 }
                 prg.push(Op::SelectT(types));
             }
+            #[cfg(not(feature = "gc"))]
             OpCode::RefNull => {
                 let type_byte = reader.load_imm_u8()?;
                 let val_type = ValueType::from_u32(type_byte as u32)?;
                 prg.push(Op::RefNull(val_type));
             }
+            #[cfg(feature = "gc")]
+            OpCode::RefNull => {
+                let heap_type = crate::gc::HeapType::read(&mut reader)?;
+                prg.push(Op::RefNull(heap_type.value_type()));
+            }
             OpCode::IsNull => {
                 prg.push(Op::RefIsNull);
             }
@@ -845,7 +1100,28 @@ pub fn decode(program_stream: &[u8]) -> Result<Program, DecodeError> {
                 prg.push(Op::RefEq);
             }
 
-            OpCode::Try | OpCode::Catch | OpCode::Throw | OpCode::Rethrow | OpCode::ThrowRef => {
+            // Throwing faults when it's reached, and so nothing's ever caught, which lets a
+            // `try_table` run as a plain block and modules with exceptions run their other paths.
+            OpCode::Throw => {
+                let tag_index = reader.load_imm_varuint32()?;
+                prg.push(Op::Throw(tag_index));
+            }
+            OpCode::ThrowRef => {
+                prg.push(Op::ThrowRef);
+            }
+            OpCode::TryTable => {
+                let signature = ScopeSig::resolve(types, ValueType::read_signature(&mut reader)?)?;
+                for _ in 0..reader.load_imm_varuint32()? {
+                    // catch and catch_ref name a tag, catch_all and catch_all_ref don't.
+                    if reader.load_imm_u8()? < 2 {
+                        reader.load_imm_varuint32()?;
+                    }
+                    reader.load_imm_varuint32()?;
+                }
+                scope_stack.push(mk_block(signature));
+                prg.push(Op::StartScope(signature, ScopeType::Block));
+            }
+            OpCode::Try | OpCode::Catch | OpCode::Rethrow => {
                 return Err(DecodeError::UnimplementedOpcode(
                     opcode_o,
                     "Exceptions proposal not supported".to_string(),
@@ -862,7 +1138,7 @@ pub fn decode(program_stream: &[u8]) -> Result<Program, DecodeError> {
                 ));
             }
 
-            OpCode::Delegate | OpCode::CatchAll | OpCode::TryTable => {
+            OpCode::Delegate | OpCode::CatchAll => {
                 return Err(DecodeError::UnimplementedOpcode(
                     opcode_o,
                     "Exception handling proposal not supported".to_string(),
@@ -874,136 +1150,91 @@ pub fn decode(program_stream: &[u8]) -> Result<Program, DecodeError> {
                     "Reference types proposal not supported".to_string(),
                 ));
             }
-            OpCode::FCExtension => {
-                // FC extension contains nontrapping float-to-int conversions
-                let sub_opcode = reader.load_imm_varuint32()?;
-                match sub_opcode {
-                    0 => prg.push(Op::I32TruncSatF32S),
-                    1 => prg.push(Op::I32TruncSatF32U),
-                    2 => prg.push(Op::I32TruncSatF64S),
-                    3 => prg.push(Op::I32TruncSatF64U),
-                    4 => prg.push(Op::I64TruncSatF32S),
-                    5 => prg.push(Op::I64TruncSatF32U),
-                    6 => prg.push(Op::I64TruncSatF64S),
-                    7 => prg.push(Op::I64TruncSatF64U),
-                    _ => {
-                        return Err(DecodeError::UnimplementedOpcode(
-                            sub_opcode as u8,
-                            format!("FC extension sub-opcode {sub_opcode} not supported"),
-                        ));
-                    }
-                }
-            }
-            OpCode::SIMDExtension => {
-                return Err(DecodeError::UnimplementedOpcode(
-                    opcode_o,
-                    "SIMD proposal not supported".to_string(),
-                ));
+            // Only the nontrapping float-to-int conversions are supported from here.
+            OpCode::FCExtension => match MiscOpCode::read(&mut reader)? {
+                MiscOpCode::I32TruncSatF32S => prg.push(Op::I32TruncSatF32S),
+                MiscOpCode::I32TruncSatF32U => prg.push(Op::I32TruncSatF32U),
+                MiscOpCode::I32TruncSatF64S => prg.push(Op::I32TruncSatF64S),
+                MiscOpCode::I32TruncSatF64U => prg.push(Op::I32TruncSatF64U),
+                MiscOpCode::I64TruncSatF32S => prg.push(Op::I64TruncSatF32S),
+                MiscOpCode::I64TruncSatF32U => prg.push(Op::I64TruncSatF32U),
+                MiscOpCode::I64TruncSatF64S => prg.push(Op::I64TruncSatF64S),
+                MiscOpCode::I64TruncSatF64U => prg.push(Op::I64TruncSatF64U),
+                other => return Err(other.unimplemented()),
+            },
+            OpCode::SIMDExtension => return Err(SimdOpCode::read(&mut reader)?.unimplemented()),
+            #[cfg(feature = "gc")]
+            OpCode::GCExtension => {
+                prg.push(Op::Gc(crate::gc::GcOp::read(&mut reader)?));
             }
+            #[cfg(not(feature = "gc"))]
             OpCode::GCExtension => {
-                return Err(DecodeError::UnimplementedOpcode(
-                    opcode_o,
-                    "Garbage collection proposal not supported".to_string(),
-                ));
+                return Err(crate::opcode::GcOpCode::read(&mut reader)?.unimplemented())
             }
+            #[cfg(feature = "atomics")]
             OpCode::ThreadsExtension => {
-                return Err(DecodeError::UnimplementedOpcode(
-                    opcode_o,
-                    "Threads proposal not supported".to_string(),
-                ));
+                prg.push(Op::Atomic(crate::atomics::AtomicOp::read(&mut reader)?));
+            }
+            #[cfg(not(feature = "atomics"))]
+            OpCode::ThreadsExtension => {
+                return Err(crate::opcode::AtomicOpCode::read(&mut reader)?.unimplemented())
             }
         }
     }
 
+    check_ops(&prg)?;
+    if closes_outer && !scope_stack.is_empty() {
+        return Err(DecodeError::UnclosedScopes(scope_stack.len()));
+    }
+    match_if_arms(&mut prg.ops);
     Ok(prg)
 }
 
-/// Scan instructions but don't decode. Used for loading expressions in modules.
-pub fn scan(reader: &mut LEB128Reader) -> Result<usize, DecodeError> {
-    let mut scope_stack = vec![];
+/// Point every `If` and `Else` at the op it jumps to, by pairing up scope markers. This runs once
+/// a body is decoded, and again whenever ops have been added or removed. Anything left unmatched
+/// in a malformed body jumps off the end of the program rather than somewhere arbitrary.
+pub(crate) fn match_if_arms(ops: &mut [Op]) {
+    // For each open scope, the positions of its `If` and `Else`, if it has them.
+    let mut open: Vec<(Option<usize>, Option<usize>)> = vec![];
+    let past_end = ops.len() as u32;
+    for pc in 0..ops.len() {
+        match ops[pc] {
+            Op::StartScope(..) => open.push((None, None)),
+            Op::If(_) => {
+                ops[pc] = Op::If(past_end);
+                if let Some(scope) = open.last_mut() {
+                    scope.0 = Some(pc);
+                }
+            }
+            Op::Else(_) => {
+                ops[pc] = Op::Else(past_end);
+                if let Some((Some(if_pc), else_pc)) = open.last_mut() {
+                    ops[*if_pc] = Op::If(pc as u32 + 1);
+                    *else_pc = Some(pc);
+                }
+            }
+            Op::EndScope(_) => match open.pop() {
+                Some((_, Some(else_pc))) => ops[else_pc] = Op::Else(pc as u32),
+                Some((Some(if_pc), None)) => ops[if_pc] = Op::If(pc as u32),
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+}
 
-    // Decode the raw program stream and translate it into our ADT Op
+/// Scan a constant expression, as used for global initializers and segment offsets, returning
+/// the position just past its `end`. Only the instructions allowed in constant expressions are
+/// accepted, including the integer arithmetic from the extended-const proposal; anything else is
+/// rejected here at load time rather than left to fail when it's evaluated.
+pub fn scan(reader: &mut LEB128Reader) -> Result<usize, DecodeError> {
     while reader.remaining() != 0 {
         let opcode_o = reader.load_imm_u8()?;
         let opcode: OpCode =
             OpCode::from_repr(opcode_o).ok_or(DecodeError::InvalidOpcode(opcode_o))?;
 
-        // Block-stack... we need to keep track of the current block we're while decoding.
         match opcode {
-            OpCode::Block | OpCode::Loop | OpCode::If => {
-                ValueType::read_signature(reader)?;
-                scope_stack.push(opcode);
-            }
-            OpCode::Else => {
-                if scope_stack.is_empty() {
-                    return Err(DecodeError::FailedToDecode("Else without If".to_string()));
-                }
-                let last_scope = scope_stack.last().unwrap();
-                if *last_scope != OpCode::If {
-                    return Err(DecodeError::FailedToDecode("Else without If".to_string()));
-                }
-            }
-            OpCode::End => {
-                if scope_stack.is_empty() {
-                    break;
-                }
-                let Some(_) = scope_stack.pop() else {
-                    return Err(DecodeError::FailedToDecode("End without block".to_string()));
-                };
-            }
-            OpCode::Br | OpCode::BrIf => {
-                reader.load_imm_varuint32()?;
-            }
-            OpCode::BrTable => {
-                reader.load_array_varu32()?;
-                reader.load_imm_varuint32()?;
-            }
-            OpCode::GetLocal
-            | OpCode::SetLocal
-            | OpCode::Tee
-            | OpCode::GetGlobal
-            | OpCode::SetGlobal
-            | OpCode::Call
-            | OpCode::RefFunc => {
-                reader.load_imm_varuint32()?;
-            }
-            OpCode::CurrentMemorySize | OpCode::GrowMemory => {
-                // Should be followed by a single u8, which is expected to be 0x00, or this is a
-                // malformed memory instruction.
-                let m_idx = reader.load_imm_u8()?;
-                if m_idx != 0x00 {
-                    return Err(DecodeError::FailedToDecode(format!(
-                        "Expected GrowMemory 0x00, got {m_idx:#0x}"
-                    )));
-                }
-            }
-            OpCode::LoadI32
-            | OpCode::LoadI64
-            | OpCode::LoadF32
-            | OpCode::LoadF64
-            | OpCode::Load8Se
-            | OpCode::Load16Se
-            | OpCode::Load8I64Se
-            | OpCode::Load8I64Ze
-            | OpCode::Load16I64Se
-            | OpCode::Load32I64Se
-            | OpCode::Load8Ze
-            | OpCode::Load16Ze
-            | OpCode::Load16I64Ze
-            | OpCode::Load32I64Ze
-            | OpCode::StoreI32
-            | OpCode::StoreI64
-            | OpCode::StoreF32
-            | OpCode::StoreF64
-            | OpCode::Store8_32
-            | OpCode::Store16_32
-            | OpCode::Store8_64
-            | OpCode::Store16_64
-            | OpCode::Store32_64 => {
-                // For scan phase we don't bother enforcing max alignment, that will get caught
-                // during decode
-                read_memarg(reader, 3)?;
-            }
+            OpCode::End => break,
             OpCode::I32Const => {
                 reader.load_imm_signed_varint32()?;
             }
@@ -1014,88 +1245,61 @@ pub fn scan(reader: &mut LEB128Reader) -> Result<usize, DecodeError> {
                 reader.load_imm_f32()?;
             }
             OpCode::F64Const => {
-                reader.load_imm_f64();
+                reader.load_imm_f64()?;
             }
+            #[cfg(not(feature = "gc"))]
             OpCode::RefNull => {
                 reader.load_imm_u8()?;
             }
-
-            OpCode::I32Extend8S
-            | OpCode::I32Extend16S
-            | OpCode::I64Extend8S
-            | OpCode::I64Extend16S
-            | OpCode::I64Extend32S => {
-                return Err(DecodeError::UnimplementedOpcode(
-                    opcode_o,
-                    "Sign-extension operators proposal not supported".to_string(),
-                ));
-            }
-
-            OpCode::Try => {
-                return Err(DecodeError::UnimplementedOpcode(
-                    opcode_o,
-                    "Exceptions proposal not supported".to_string(),
-                ));
-            }
-
-            OpCode::ReturnCall
-            | OpCode::ReturnCallIndirect
-            | OpCode::CallRef
-            | OpCode::ReturnCallRef => {
-                return Err(DecodeError::UnimplementedOpcode(
-                    opcode_o,
-                    "Typed function references proposal not supported".to_string(),
-                ));
-            }
-
-            OpCode::Delegate | OpCode::CatchAll | OpCode::TryTable => {
-                return Err(DecodeError::UnimplementedOpcode(
-                    opcode_o,
-                    "Exception handling proposal not supported".to_string(),
-                ));
-            }
-            OpCode::BrOnNull | OpCode::BrOnNonNull => {
-                return Err(DecodeError::UnimplementedOpcode(
-                    opcode_o,
-                    "Reference types proposal not supported".to_string(),
-                ));
-            }
-            OpCode::FCExtension => {
-                // FC extension contains nontrapping float-to-int conversions
-                let sub_opcode = reader.load_imm_varuint32()?;
-                match sub_opcode {
-                    0..=7 => {
-                        // trunc_sat operations - just consume the sub-opcode
-                    }
-                    _ => {
-                        return Err(DecodeError::UnimplementedOpcode(
-                            sub_opcode as u8,
-                            format!("FC extension sub-opcode {sub_opcode} not supported"),
-                        ));
-                    }
-                }
-            }
-            OpCode::SIMDExtension => {
-                return Err(DecodeError::UnimplementedOpcode(
-                    opcode_o,
-                    "SIMD proposal not supported".to_string(),
-                ));
-            }
-            OpCode::GCExtension => {
-                return Err(DecodeError::UnimplementedOpcode(
-                    opcode_o,
-                    "Garbage collection proposal not supported".to_string(),
-                ));
+            #[cfg(feature = "gc")]
+            OpCode::RefNull => {
+                crate::gc::HeapType::read(reader)?;
             }
-            OpCode::ThreadsExtension => {
-                return Err(DecodeError::UnimplementedOpcode(
-                    opcode_o,
-                    "Threads proposal not supported".to_string(),
-                ));
+            OpCode::RefFunc | OpCode::GetGlobal => {
+                reader.load_imm_varuint32()?;
             }
-            _ => continue,
+            OpCode::I32Add
+            | OpCode::I32Sub
+            | OpCode::I32Mul
+            | OpCode::I64Add
+            | OpCode::I64Sub
+            | OpCode::I64Mul => {}
+            _ => return Err(DecodeError::NonConstantInstruction(opcode_o)),
         }
     }
 
     Ok(reader.position())
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{decode_function, Module, Op, ScopeType};
+
+    #[test]
+    fn ops_with_depth_follows_nesting() {
+        let wat = r#"(module (func (param i32) (result i32)
+            (block (result i32) (if (result i32) (local.get 0) (then (i32.const 1)) (else (i32.const 2))))))"#;
+        let module = Module::load(&wat::parse_str(wat).unwrap()).unwrap();
+        let program = decode_function(module.code(0), &module.types, &module.types[0]).unwrap();
+        let depths: Vec<_> = program.ops_with_depth().collect();
+        assert_eq!(depths.len(), program.ops.len());
+        assert_eq!(depths[0], (0, &program.ops[0]));
+        assert!(matches!(
+            depths.last(),
+            Some((0, Op::EndScope(ScopeType::Function)))
+        ));
+        // The if's arms are two scopes in, under the function and the block.
+        let consts: Vec<_> = depths
+            .iter()
+            .filter(|(_, op)| matches!(op, Op::I32Const(_)))
+            .map(|(depth, _)| *depth)
+            .collect();
+        assert_eq!(consts, [3, 3]);
+        let else_depth = depths
+            .iter()
+            .find(|(_, op)| matches!(op, Op::Else(_)))
+            .unwrap()
+            .0;
+        assert_eq!(else_depth, 2);
+    }
+}
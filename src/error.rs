@@ -0,0 +1,168 @@
+// Copyright (C) 2025 Ryan Daum <ryan.daum@gmail.com> This program is free
+// software: you can redistribute it and/or modify it under the terms of the GNU
+// General Public License as published by the Free Software Foundation, version
+// 3.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+use crate::exec::Continuation;
+use crate::{DecodeError, ExecError, Fault, LinkError, LoaderError};
+use std::fmt::{Display, Formatter};
+
+/// The broad stage an [`Error`] came from.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ErrorCategory {
+    /// The module binary's structure: preamble, sections, segments.
+    Load,
+    /// Instructions and types within the binary.
+    Decode,
+    /// Resolving imports and instantiating.
+    Link,
+    /// Running guest code.
+    Execution,
+}
+
+/// Any error from loading, linking or running a module. Nested errors are flattened, so a decode
+/// failure is always `Decode` whether it turned up while loading or while linking.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub enum Error {
+    Load(LoaderError),
+    Decode(DecodeError),
+    Link(LinkError),
+    Execution(Fault),
+}
+
+impl Error {
+    pub fn category(&self) -> ErrorCategory {
+        match self {
+            Error::Load(_) => ErrorCategory::Load,
+            Error::Decode(_) => ErrorCategory::Decode,
+            Error::Link(_) => ErrorCategory::Link,
+            Error::Execution(_) => ErrorCategory::Execution,
+        }
+    }
+
+    /// A stable numeric code for the error. The thousands digit gives the category: 1 load,
+    /// 2 decode, 3 link, 4 execution.
+    pub fn code(&self) -> u32 {
+        match self {
+            Error::Load(e) => e.code(),
+            Error::Decode(e) => e.code(),
+            Error::Link(e) => e.code(),
+            Error::Execution(e) => e.code(),
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Load(e) => write!(f, "{e}"),
+            Error::Decode(e) => write!(f, "{e}"),
+            Error::Link(e) => write!(f, "{e}"),
+            Error::Execution(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Load(e) => Some(e),
+            Error::Decode(e) => Some(e),
+            Error::Link(e) => Some(e),
+            Error::Execution(e) => Some(e),
+        }
+    }
+}
+
+impl From<LoaderError> for Error {
+    fn from(e: LoaderError) -> Self {
+        match e {
+            LoaderError::DecoderError(e) => Error::Decode(e),
+            e => Error::Load(e),
+        }
+    }
+}
+
+impl From<DecodeError> for Error {
+    fn from(e: DecodeError) -> Self {
+        Error::Decode(e)
+    }
+}
+
+impl From<LinkError> for Error {
+    fn from(e: LinkError) -> Self {
+        match e {
+            LinkError::DecodeError(e) => Error::Decode(e),
+            e => Error::Link(e),
+        }
+    }
+}
+
+impl From<Fault> for Error {
+    fn from(e: Fault) -> Self {
+        Error::Execution(e)
+    }
+}
+
+impl From<ExecError> for Error {
+    fn from(e: ExecError) -> Self {
+        match e {
+            ExecError::LinkageError(e) => e.into(),
+            ExecError::ExecutionFault(e) => e.into(),
+            // Whoever's turning this into an error isn't going to resume it.
+            ExecError::Suspended(reason) => {
+                Fault::UnexpectedResult(Continuation::Suspend(reason)).into()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::{Error, ErrorCategory};
+    use crate::exec::{Execution, Value};
+    use crate::instance::mk_instance;
+    use crate::memory::VectorMemory;
+    use crate::module::Module;
+
+    #[test]
+    fn errors_flatten_into_categories() {
+        let load = |bytes: &[u8]| -> Result<Module, Error> { Ok(Module::load(bytes)?) };
+
+        let e = load(b"\0asm\x02\x00\x00\x00").err().unwrap();
+        assert_eq!(e.category(), ErrorCategory::Load);
+        assert_eq!(e.code(), 1002);
+
+        // A malformed type section fails in the decoder, and comes out as a decode error.
+        let e = load(b"\0asm\x01\x00\x00\x00\x01\x02\x01\x61")
+            .err()
+            .unwrap();
+        assert_eq!(e.category(), ErrorCategory::Decode);
+        assert_eq!(e.code(), 2004);
+
+        let wat =
+            r#"(module (func (export "f") (result i32) (i32.div_s (i32.const 1) (i32.const 0))))"#;
+        let run = || -> Result<Vec<Value>, Error> {
+            let instance = mk_instance(load(&wat::parse_str(wat).unwrap())?)?;
+            let funcidx = instance.find_funcidx("f").unwrap();
+            let mut execution = Execution::new(instance, VectorMemory::new(0, None));
+            execution.prepare(funcidx, &[])?;
+            execution.run()?;
+            Ok(execution.result().unwrap().to_vec())
+        };
+        let e = run().err().unwrap();
+        assert_eq!(e.category(), ErrorCategory::Execution);
+        assert_eq!(e.code(), 4018);
+        assert_eq!(e.to_string(), "integer divide by zero");
+    }
+}
@@ -11,33 +11,291 @@
 // this program. If not, see <https://www.gnu.org/licenses/>.
 //
 
+use crate::anomaly::{Anomaly, AnomalyMonitor, AnomalyThresholds};
 use crate::decode::{decode, ScopeType};
-use crate::frame::Frame;
-use crate::instance::{LinkError, TableInstance, WASM_PAGE_SIZE};
-use crate::memory::Memory;
-use crate::memory::SliceMemory;
-use crate::module::Global;
+use crate::externs::ExternTable;
+use crate::frame::{Frame, FramePool, FrameView, TraceFrame};
+use crate::guest_coverage::GuestCoverage;
+use crate::hibernate::{self, HibernateError, HibernateOptions, ThawError};
+use crate::instance::{Fuel, FuncHandle, LinkError, TableInstance};
+use crate::linker::{Caller, Extern, HostFunction, Linker, Reenter, DEFAULT_MAX_REENTRY};
+use crate::memory::{bytes_for_pages, Memory, MAX_WASM_PAGES, WASM_PAGE_SIZE};
+use crate::memory::{SliceMemory, VectorMemory};
+use crate::module::{Global, ImportExportKind};
+use crate::numeric;
 use crate::op::{MemArg, Op};
-use crate::stack::Stack;
-use crate::{FuncType, Instance, Type, TypeSignature, ValueType};
+use crate::rewind::{Checkpoint, History};
+use crate::stack::{HeapSlots, SlotStorage, Stack};
+use crate::trace::{Trace, TraceLevel, Tracer};
+use crate::watch::{WatchHit, Watchpoints};
+use crate::{FuncType, Instance, ValueType};
+use std::any::Any;
+use std::collections::HashMap;
 use std::error::Error;
 use std::fmt::{Debug, Display, Formatter};
+use std::io::{Read, Write};
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+
+/// GC heap and types, threaded through execution. Nothing to carry without the `gc` feature.
+#[cfg(feature = "gc")]
+pub(crate) use crate::gc::GcStore;
+#[cfg(not(feature = "gc"))]
+pub(crate) type GcStore = ();
+
+/// Counters gathered over a single `run`, for capacity planning.
+#[cfg(feature = "stats")]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExecutionStats {
+    pub ops_executed: u64,
+    /// The deepest the call stack got, counting the entry function as 1.
+    pub max_frame_depth: usize,
+    /// The most value stack slots in use by any one frame.
+    pub max_stack_slots: usize,
+    /// The size of memory, in pages, when the run finished.
+    pub mem_pages_end: usize,
+    /// How many successful `memory.grow`s there were.
+    pub grows: u32,
+}
+#[cfg(not(feature = "stats"))]
+type ExecutionStats = ();
+
+/// What to do with a `memory.grow` the guest has asked for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GrowDecision {
+    /// Grow, if the memory itself allows it.
+    Allow,
+    /// Leave memory as it is and have `memory.grow` return -1, as if its maximum was reached.
+    Deny,
+    /// Stop execution with `Fault::CannotGrowMemory`.
+    Trap,
+}
+
+/// How closely runs of the same program on the same inputs must agree.
+///
+/// Nearly everything the interpreter does is fixed by the spec and the program: locals, memory
+/// and tables start zeroed or null, the linker's and interceptors' maps are only ever looked up,
+/// never iterated, and a fault is always the first one the ops hit, in program order. What's
+/// left is:
+///
+/// - the payload of a NaN produced by float arithmetic, which the spec leaves open, and which
+///   here is whatever the host's FPU produces;
+/// - whether `memory.grow` succeeds, which is down to the grow hook and the memory's own limits,
+///   and so is as deterministic as those are.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Determinism {
+    /// NaN payloads are left as the host produces them.
+    #[default]
+    Relaxed,
+    /// Every NaN produced by float arithmetic or conversion is replaced by the canonical NaN of
+    /// its type, so results are bit-identical across hosts.
+    Strict,
+}
+
+/// What happens to a float result too small to be normal. The spec keeps subnormals, but some
+/// FPUs flush them to zero for speed; a host mirroring one can have the interpreter do the same.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Subnormals {
+    /// Subnormal results are kept, as the spec requires.
+    #[default]
+    Preserve,
+    /// A subnormal result of float arithmetic or demotion becomes zero of the same sign.
+    FlushToZero,
+}
+
+/// How float results are adjusted after the ops which compute them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct FloatPolicy {
+    determinism: Determinism,
+    subnormals: Subnormals,
+}
+
+impl FloatPolicy {
+    /// Adjust the float of type `ty` on top of the stack to fit the policy.
+    fn apply<S: SlotStorage>(self, stack: &mut Stack<S>, ty: ValueType) -> Result<(), Fault> {
+        let strict = self.determinism == Determinism::Strict;
+        let flush = self.subnormals == Subnormals::FlushToZero;
+        match ty {
+            ValueType::F32 => {
+                let value = stack.pop_f32()?;
+                stack.push_f32(match value {
+                    v if strict && v.is_nan() => f32::from_bits(0x7fc0_0000),
+                    v if flush && v.is_subnormal() => 0.0f32.copysign(v),
+                    v => v,
+                });
+            }
+            ValueType::F64 => {
+                let value = stack.pop_f64()?;
+                stack.push_f64(match value {
+                    v if strict && v.is_nan() => f64::from_bits(0x7ff8_0000_0000_0000),
+                    v if flush && v.is_subnormal() => 0.0f64.copysign(v),
+                    v => v,
+                });
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+/// Bounds on how far an execution's stacks may grow, for hosts with a fixed amount of memory to
+/// give it. They're checked as each call is made, so a guest which recurses too deeply faults
+/// with `Fault::StackExhausted` rather than taking more memory. Value stacks and locals live
+/// wherever the execution's `SlotStorage` puts them: on the heap, growing as they need to, by
+/// default, or in arrays of a fixed size with `FixedSlots`, which fault the same way when a
+/// frame needs more than they hold. The frames themselves are on the heap either way; a
+/// `FramePool` preallocated to match saves most of their growth.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StackLimits {
+    /// The most frames live at once.
+    pub max_frames: Option<usize>,
+    /// The most value stack and local slots live at once, over every frame. A single function's
+    /// operand stack can only grow as far as its body allows between calls, so this is checked
+    /// as frames are pushed.
+    pub max_slots: Option<usize>,
+}
+
+/// The value stack and local slots `frame` holds.
+fn frame_slots<S: SlotStorage>(frame: &Frame<S>) -> usize {
+    frame.stack.width() + frame.locals.width()
+}
+
+/// What a call made on the host's behalf, nested in another, hands back when it fails.
+fn nested_fault(e: ExecError) -> Fault {
+    match e {
+        ExecError::ExecutionFault(fault) => fault,
+        ExecError::Suspended(reason) => Fault::UnexpectedResult(Continuation::Suspend(reason)),
+        ExecError::LinkageError(e) => Fault::HostAbort(e.to_string()),
+    }
+}
+
+/// The float type an op leaves on the stack, for those ops whose NaN results the spec allows to
+/// carry any payload, and which can round to a subnormal. Abs, neg, copysign and reinterpret
+/// only ever move bits, so aren't here.
+fn float_result_type(op: &Op) -> Option<ValueType> {
+    match op {
+        Op::F32Ceil
+        | Op::F32Floor
+        | Op::F32Trunc
+        | Op::F32Nearest
+        | Op::F32Sqrt
+        | Op::F32Add
+        | Op::F32Sub
+        | Op::F32Mul
+        | Op::F32Div
+        | Op::F32Min
+        | Op::F32Max
+        | Op::F32DemoteF64 => Some(ValueType::F32),
+        Op::F64Ceil
+        | Op::F64Floor
+        | Op::F64Trunc
+        | Op::F64Nearest
+        | Op::F64Sqrt
+        | Op::F64Add
+        | Op::F64Sub
+        | Op::F64Mul
+        | Op::F64Div
+        | Op::F64Min
+        | Op::F64Max
+        | Op::F64PromoteF32 => Some(ValueType::F64),
+        _ => None,
+    }
+}
+
+/// Called with the current and requested size of memory, in pages, before every `memory.grow`
+/// that fits in the address space. Memory can still refuse an allowed grow past its maximum.
+pub type MemoryGrowHook = Box<dyn FnMut(usize, usize) -> GrowDecision + Send>;
+
+/// A memory access whose effective address isn't a multiple of the alignment its instruction
+/// declared. Wasm runs these fine, but they're usually a sign of undefined behaviour in the
+/// guest's source language, which can break on hardware which is strict about alignment.
+#[cfg(feature = "alignment-diagnostics")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MisalignedAccess {
+    /// The effective address accessed.
+    pub address: usize,
+    /// The alignment the instruction declared, in bytes.
+    pub align: u64,
+    /// The function making the access.
+    pub funcidx: u32,
+    /// The index of the access's op in that function's decoded body.
+    pub pc: usize,
+}
+
+/// Called with every misaligned access, with the `alignment-diagnostics` feature.
+#[cfg(feature = "alignment-diagnostics")]
+pub type MisalignedAccessHook = Box<dyn FnMut(MisalignedAccess) + Send>;
+
+/// Where misaligned accesses get reported, threaded through execution. Nothing to carry without
+/// the `alignment-diagnostics` feature.
+#[cfg(feature = "alignment-diagnostics")]
+type AlignmentHook = Option<MisalignedAccessHook>;
+#[cfg(not(feature = "alignment-diagnostics"))]
+type AlignmentHook = ();
+
+/// What to do with a call to a guest function that's been intercepted.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Intercept {
+    /// Run the function as normal.
+    Proceed,
+    /// Skip the function, and have the call produce these results instead.
+    Return(Vec<Value>),
+}
+
+/// Called with the arguments of each call to the guest function it was registered for.
+pub type CallInterceptor = Box<dyn FnMut(&[Value]) -> Intercept + Send>;
+
+/// What to do about a fault in a guest function.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TrapDecision {
+    /// Stop the run with the fault, as if there were no policy.
+    Abort,
+    /// Unwind the function which faulted, and have its call produce these results instead, so
+    /// its caller carries on as if it had returned them, e.g. with an error code.
+    Return(Vec<Value>),
+}
+
+/// Called with each fault a guest function's ops raise, and the index of that function, to decide
+/// what becomes of it. It's free to log or report the fault whatever it decides.
+pub type TrapPolicy = Box<dyn FnMut(&Fault, u32) -> TrapDecision + Send>;
 
 /// How many ticks we allow before we stop execution when running expressions during the link
 /// phase (Active data expressions etc)
-const EXPR_TICK_LIMIT: usize = 1 << 10;
+const EXPR_TICK_LIMIT: u64 = 1 << 10;
+
+/// How many ops a run may go through between calls and returns before it's stopped.
+const RUN_TICK_LIMIT: u64 = 1000000;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub enum Continuation {
     Call(u32),
+    /// A call to an import given a `Caller`, which only the execution can provide.
+    HostCall(u32),
     /// Program ran out of instructions
     ProgramEnd,
     /// An explicit return instruction was encountered.
     /// Stack should contain the return value.
     DoneReturn,
+    /// Execution stopped where it can be picked up again.
+    Suspend(SuspendReason),
+}
+
+/// Why a run stopped part way through. Nothing is lost: the frames are left as they are, and the
+/// next `run` carries on from where this one stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SuspendReason {
+    /// The guest wrote to something being watched.
+    Watchpoint(WatchHit),
+    /// The guest called the built-in `wasbox.yield` import, giving the host a chance to run
+    /// something else. See [`crate::YIELD_IMPORT`].
+    GuestYield,
+    /// `Execution::step` ran its one op.
+    Stepped,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[non_exhaustive]
 pub enum Fault {
     /// Ran out of execution ticks
     OutOfTicks,
@@ -53,8 +311,22 @@ pub enum Fault {
     GlobalIndexOutOfBounds,
     /// Memory access out of bounds
     MemoryOutOfBounds,
+    /// Store into a range of memory protected by the host
+    ReadOnlyMemory,
+    /// An interceptor or trap policy returned results which don't match the type of the function
+    /// it stood in for
+    InterceptedResultMismatch(u32),
+    /// Call to an import, by module and field name, which nothing was provided for
+    UnresolvedImport(String, String),
+    /// A host function was called again while it was still running
+    ReentrantHostCall,
+    /// Atomic access to an address not aligned to its size
+    #[cfg(feature = "atomics")]
+    UnalignedAtomic,
     /// Memory growth not supported for this memory type, or memory is at maximum size
     CannotGrowMemory,
+    /// Table is at its maximum size
+    CannotGrowTable,
     /// Unresolvable type index
     UnresolvableTypeIndex(u32),
     /// Invalid reference type
@@ -75,6 +347,53 @@ pub enum Fault {
     IndirectCallTypeMismatch,
     /// Unreachable instruction executed
     Unreachable,
+    /// An earlier fault left the execution frozen, and it must be reset before reuse
+    Poisoned,
+    /// GC array access out of bounds
+    #[cfg(feature = "gc")]
+    ArrayOutOfBounds,
+    /// `ref.cast` on a reference of the wrong type
+    #[cfg(feature = "gc")]
+    CastFailure,
+    /// A GC allocation would have taken the heap past its limit; see
+    /// `InstanceLimits::max_gc_values`
+    #[cfg(feature = "gc")]
+    GcHeapExhausted,
+    /// A host function stopped execution on the guest's behalf, e.g. for an `abort` import
+    HostAbort(String),
+    /// The guest asked to exit, with this status
+    Exit(i32),
+    /// A table access, such as an element segment's initialization, past the end of the table
+    TableOutOfBounds,
+    /// `global.set` on a global, by index, which isn't mutable
+    ImmutableGlobal(u32),
+    /// A run being checked against a trace stopped matching it, at this event
+    TraceDivergence(u64),
+    /// A call to the function, by index, ran past the op budget set for it
+    BudgetExceeded(u32),
+    /// A call would have taken the stacks past the execution's `StackLimits`
+    StackExhausted,
+    /// The guest's operand stack was reached into other than while suspended at a host call
+    NotAtHostCall,
+    /// A host function called back into the guest past the execution's re-entry limit
+    ReentryLimit,
+    /// A host function wanted state of a type other than what the execution carries. This is
+    /// only found out when the function is called; see `Linker::func_with_data`.
+    HostDataMismatch,
+    /// The host function for the import of module and field name returned values which don't
+    /// match the import's result types
+    HostResultMismatch(String, String),
+    /// An exception was thrown, with the tag at this index if it's known. Exceptions can't be
+    /// caught, so this ends the run.
+    UnsupportedThrow(Option<u32>),
+    /// A call was prepared while another was still in progress, suspended. It has to be run to
+    /// the end, or dropped with `reset`, first.
+    Busy,
+    /// `run` was called with no call prepared
+    NothingToRun,
+    /// A value was used as a type other than the one it was pushed as, which only a module that
+    /// doesn't validate can do. Only debug builds keep track of this.
+    StackKindMismatch,
 }
 
 impl Display for Fault {
@@ -87,7 +406,21 @@ impl Display for Fault {
             Fault::LocalIndexOutOfBounds => write!(f, "Local index out of bounds"),
             Fault::GlobalIndexOutOfBounds => write!(f, "Global index out of bounds"),
             Fault::MemoryOutOfBounds => write!(f, "Memory out of bounds"),
+            Fault::ReadOnlyMemory => write!(f, "Write to read-only memory"),
+            Fault::InterceptedResultMismatch(funcidx) => {
+                write!(
+                    f,
+                    "Intercepted call to function {funcidx} returned the wrong types"
+                )
+            }
+            Fault::UnresolvedImport(module, name) => {
+                write!(f, "Call to unresolved import {module}.{name}")
+            }
+            Fault::ReentrantHostCall => write!(f, "Re-entrant host function call"),
+            #[cfg(feature = "atomics")]
+            Fault::UnalignedAtomic => write!(f, "unaligned atomic"),
             Fault::CannotGrowMemory => write!(f, "Cannot grow memory"),
+            Fault::CannotGrowTable => write!(f, "Cannot grow table"),
             Fault::UnresolvableTypeIndex(idx) => write!(f, "Unresolvable type index: {idx}"),
             Fault::InvalidRefType => write!(f, "Invalid reference type"),
             Fault::NullReference => write!(f, "Null reference dereference"),
@@ -98,213 +431,132 @@ impl Display for Fault {
             Fault::InvalidConversion => write!(f, "invalid conversion to integer"),
             Fault::IndirectCallTypeMismatch => write!(f, "indirect call type mismatch"),
             Fault::Unreachable => write!(f, "unreachable"),
+            Fault::Poisoned => write!(f, "Execution poisoned by an earlier fault"),
+            #[cfg(feature = "gc")]
+            Fault::ArrayOutOfBounds => write!(f, "out of bounds array access"),
+            #[cfg(feature = "gc")]
+            Fault::CastFailure => write!(f, "cast failure"),
+            #[cfg(feature = "gc")]
+            Fault::GcHeapExhausted => write!(f, "GC heap exhausted"),
+            Fault::HostAbort(reason) => write!(f, "Aborted: {reason}"),
+            Fault::Exit(status) => write!(f, "Exited with status {status}"),
+            Fault::TableOutOfBounds => write!(f, "out of bounds table access"),
+            Fault::ImmutableGlobal(idx) => write!(f, "global {idx} is immutable"),
+            Fault::TraceDivergence(event) => write!(f, "run diverged from trace at event {event}"),
+            Fault::BudgetExceeded(funcidx) => {
+                write!(f, "Function {funcidx} ran past its op budget")
+            }
+            Fault::StackExhausted => write!(f, "call stack exhausted"),
+            Fault::NotAtHostCall => write!(f, "not suspended at a host call"),
+            Fault::ReentryLimit => write!(f, "host calls back into the guest nested too deeply"),
+            Fault::HostDataMismatch => {
+                write!(f, "host function expected state of another type")
+            }
+            Fault::HostResultMismatch(module, name) => {
+                write!(
+                    f,
+                    "host function {module}.{name} returned the wrong results"
+                )
+            }
+            Fault::UnsupportedThrow(Some(tag)) => {
+                write!(f, "exception thrown with tag {tag}, which isn't supported")
+            }
+            Fault::UnsupportedThrow(None) => write!(f, "exception thrown, which isn't supported"),
+            Fault::Busy => write!(f, "another call is still in progress"),
+            Fault::NothingToRun => write!(f, "no call prepared to run"),
+            Fault::StackKindMismatch => write!(f, "stack value used as the wrong type"),
         }
     }
 }
 
-impl Error for Fault {}
-
-/// Helper function for WASM float-to-signed-int truncation
-/// WASM spec: i32.trunc_f32_s traps if value is NaN, ±∞, or outside [-2^31, 2^31)
-fn trunc_f32_to_i32(value: f32) -> Result<i32, Fault> {
-    if value.is_nan() {
-        return Err(Fault::InvalidConversion);
-    }
-    if value.is_infinite() {
-        return Err(Fault::IntegerOverflow);
-    }
-    // WASM spec: Use exact bounds from wasm3 reference implementation
-    // i32.trunc_f32_s: RMIN = -2147483904.0f, RMAX = 2147483648.0f
-    if value <= -2147483904.0f32 || value >= 2147483648.0f32 {
-        return Err(Fault::IntegerOverflow);
-    }
-    Ok(value.trunc() as i32)
-}
-
-/// Helper function for WASM float-to-unsigned-int truncation
-/// WASM spec: i32.trunc_f32_u traps if value is NaN, ±∞, or outside [0, 2^32)
-fn trunc_f32_to_u32(value: f32) -> Result<u32, Fault> {
-    if value.is_nan() {
-        return Err(Fault::InvalidConversion);
-    }
-    if value.is_infinite() {
-        return Err(Fault::IntegerOverflow);
-    }
-    // WASM spec: Use exact bounds from wasm3 - i32.trunc_f32_u
-    if value <= -1.0f32 || value >= 4294967296.0f32 {
-        return Err(Fault::IntegerOverflow);
-    }
-    Ok(value.trunc() as u32)
-}
-
-/// WASM spec: i32.trunc_f64_s traps if value is NaN, ±∞, or outside [-2^31, 2^31)
-fn trunc_f64_to_i32(value: f64) -> Result<i32, Fault> {
-    if value.is_nan() {
-        return Err(Fault::InvalidConversion);
-    }
-    if value.is_infinite() {
-        return Err(Fault::IntegerOverflow);
-    }
-    // WASM spec: Use exact bounds from wasm3 - i32.trunc_f64_s
-    if value <= -2147483649.0 || value >= 2147483648.0 {
-        return Err(Fault::IntegerOverflow);
-    }
-    Ok(value.trunc() as i32)
-}
-
-/// WASM spec: i32.trunc_f64_u traps if value is NaN, ±∞, or outside [0, 2^32)
-fn trunc_f64_to_u32(value: f64) -> Result<u32, Fault> {
-    if value.is_nan() {
-        return Err(Fault::InvalidConversion);
-    }
-    if value.is_infinite() {
-        return Err(Fault::IntegerOverflow);
-    }
-    // WASM spec: Use exact bounds from wasm3 - i32.trunc_f64_u
-    if value <= -1.0 || value >= 4294967296.0 {
-        return Err(Fault::IntegerOverflow);
-    }
-    Ok(value.trunc() as u32)
-}
-
-/// WASM spec: i64.trunc_f32_s traps if value is NaN, ±∞, or outside [-2^63, 2^63)
-fn trunc_f32_to_i64(value: f32) -> Result<i64, Fault> {
-    if value.is_nan() {
-        return Err(Fault::InvalidConversion);
-    }
-    if value.is_infinite() {
-        return Err(Fault::IntegerOverflow);
-    }
-    // WASM spec: Use exact bounds from wasm3 - i64.trunc_f32_s
-    if value <= -9223373136366403584.0f32 || value >= 9223372036854775808.0f32 {
-        return Err(Fault::IntegerOverflow);
-    }
-    Ok(value.trunc() as i64)
-}
-
-/// WASM spec: i64.trunc_f32_u traps if value is NaN, ±∞, or outside [0, 2^64)
-fn trunc_f32_to_u64(value: f32) -> Result<u64, Fault> {
-    if value.is_nan() {
-        return Err(Fault::InvalidConversion);
-    }
-    if value.is_infinite() {
-        return Err(Fault::IntegerOverflow);
-    }
-    // WASM spec: Use exact bounds from wasm3 - i64.trunc_f32_u
-    if value <= -1.0f32 || value >= 18446744073709551616.0f32 {
-        return Err(Fault::IntegerOverflow);
-    }
-    Ok(value.trunc() as u64)
-}
-
-/// WASM spec: i64.trunc_f64_s traps if value is NaN, ±∞, or outside [-2^63, 2^63)
-fn trunc_f64_to_i64(value: f64) -> Result<i64, Fault> {
-    if value.is_nan() {
-        return Err(Fault::InvalidConversion);
-    }
-    if value.is_infinite() {
-        return Err(Fault::IntegerOverflow);
-    }
-    // WASM spec: Use exact bounds from wasm3 - i64.trunc_f64_s
-    if value <= -9223372036854777856.0 || value >= 9223372036854775808.0 {
-        return Err(Fault::IntegerOverflow);
-    }
-    Ok(value.trunc() as i64)
-}
-
-/// WASM spec: i64.trunc_f64_u traps if value is NaN, ±∞, or outside [0, 2^64)
-fn trunc_f64_to_u64(value: f64) -> Result<u64, Fault> {
-    if value.is_nan() {
-        return Err(Fault::InvalidConversion);
-    }
-    if value.is_infinite() {
-        return Err(Fault::IntegerOverflow);
-    }
-    // WASM spec: Use exact bounds from wasm3 - i64.trunc_f64_u
-    if value <= -1.0 || value >= 18446744073709551616.0 {
-        return Err(Fault::IntegerOverflow);
-    }
-    Ok(value.trunc() as u64)
-}
-
-fn resolve_type(types: &[FuncType], ts: TypeSignature) -> Result<Type, Fault> {
-    match ts {
-        TypeSignature::ValueType(v) => Ok(Type::ValueType(v)),
-        TypeSignature::Index(idx) => {
-            let ft = types
-                .get(idx as usize)
-                .ok_or(Fault::UnresolvableTypeIndex(idx))
-                .cloned();
-            Ok(Type::FunctionType(ft?))
+impl Fault {
+    /// Stable numeric code for this fault, in the 4000s. Codes are assigned whether or not the
+    /// feature a fault belongs to is enabled.
+    pub fn code(&self) -> u32 {
+        match self {
+            Fault::OutOfTicks => 4001,
+            Fault::UnexpectedResult(_) => 4002,
+            Fault::StackUnderflow => 4003,
+            Fault::ControlStackUnderflow => 4004,
+            Fault::LocalIndexOutOfBounds => 4005,
+            Fault::GlobalIndexOutOfBounds => 4006,
+            Fault::MemoryOutOfBounds => 4007,
+            Fault::ReadOnlyMemory => 4008,
+            Fault::InterceptedResultMismatch(_) => 4009,
+            Fault::UnresolvedImport(_, _) => 4010,
+            Fault::ReentrantHostCall => 4011,
+            #[cfg(feature = "atomics")]
+            Fault::UnalignedAtomic => 4012,
+            Fault::CannotGrowMemory => 4013,
+            Fault::CannotGrowTable => 4014,
+            Fault::UnresolvableTypeIndex(_) => 4015,
+            Fault::InvalidRefType => 4016,
+            Fault::NullReference => 4017,
+            Fault::IntegerDivisionByZero => 4018,
+            Fault::IntegerOverflow => 4019,
+            Fault::UndefinedElement => 4020,
+            Fault::UninitializedElement => 4021,
+            Fault::InvalidConversion => 4022,
+            Fault::IndirectCallTypeMismatch => 4023,
+            Fault::Unreachable => 4024,
+            Fault::Poisoned => 4025,
+            #[cfg(feature = "gc")]
+            Fault::ArrayOutOfBounds => 4026,
+            #[cfg(feature = "gc")]
+            Fault::CastFailure => 4027,
+            Fault::HostAbort(_) => 4028,
+            Fault::Exit(_) => 4029,
+            Fault::TableOutOfBounds => 4030,
+            Fault::ImmutableGlobal(_) => 4031,
+            Fault::TraceDivergence(_) => 4032,
+            Fault::BudgetExceeded(_) => 4033,
+            Fault::StackExhausted => 4034,
+            Fault::NotAtHostCall => 4035,
+            Fault::ReentryLimit => 4036,
+            Fault::HostDataMismatch => 4037,
+            Fault::HostResultMismatch(_, _) => 4038,
+            Fault::UnsupportedThrow(_) => 4039,
+            #[cfg(feature = "gc")]
+            Fault::GcHeapExhausted => 4040,
+            Fault::Busy => 4041,
+            Fault::NothingToRun => 4042,
+            Fault::StackKindMismatch => 4043,
         }
     }
 }
 
+impl Error for Fault {}
+
 /// Unified branch execution using structured control flow
-fn execute_branch(frame: &mut Frame, depth: usize) -> Result<(), Fault> {
+fn execute_branch<S: SlotStorage>(
+    frame: &mut Frame<S>,
+    depth: usize,
+    anomalies: &mut Option<AnomalyMonitor>,
+) -> Result<(), Fault> {
     if depth >= frame.control_stack.len() {
         return Err(Fault::ControlStackUnderflow);
     }
 
     // Find the target control block using WASM branch semantics
     // All scopes are valid branch targets: Block, Loop, Function, and IfElse
-    let mut target_idx = None;
-
-    for (branch_target_count, (i, _control)) in
-        frame.control_stack.iter().enumerate().rev().enumerate()
-    {
-        // All control scopes are valid branch targets
-        if branch_target_count == depth {
-            target_idx = Some(i);
-            break;
-        }
-    }
-
-    let target_idx = target_idx.ok_or(Fault::ControlStackUnderflow)?;
-    let target_scope_type = frame.control_stack[target_idx].scope_type;
+    let target_idx = frame.control_stack.len() - 1 - depth;
+    let target = &frame.control_stack[target_idx];
+    let target_scope_type = target.scope_type;
+    let target_start_pc = target.start_pc;
     let pop_depth = match target_scope_type {
-        ScopeType::Loop => frame.control_stack.len() - 1 - target_idx, // Don't pop the loop
-        _ => frame.control_stack.len() - target_idx, // Pop the target block/function too
-    };
-
-    // Get the target control block's signature to know what values it expects
-    let target_signature = frame.control_stack[target_idx].signature.clone();
-    let target_stack_width = frame.control_stack[target_idx].stack_width;
-
-    // Pop the branch values from the stack (these will be provided to the target)
-    let branch_values = match &target_signature {
-        Type::ValueType(vt) => {
-            if *vt != ValueType::Unit && frame.stack.width() > 0 {
-                vec![Value::pop_from(*vt, &mut frame.stack)?]
-            } else {
-                vec![]
-            }
-        }
-        Type::FunctionType(ft) => {
-            // Pre-allocate and assign by index to avoid double-reverse
-            let mut branch_values = vec![Value::Unit; ft.results.len()];
-            for (i, vt) in ft.results.iter().enumerate().rev() {
-                branch_values[i] = Value::pop_from(*vt, &mut frame.stack)?;
-            }
-            branch_values
-        }
+        ScopeType::Loop => depth, // Don't pop the loop
+        _ => depth + 1,           // Pop the target block/function too
     };
 
-    // Pop all the control blocks up to (but not including) the target
-    for _ in 0..pop_depth {
-        let _c = frame
-            .control_stack
-            .pop()
-            .ok_or(Fault::ControlStackUnderflow)?;
-    }
-
-    // Shrink stack to the target block's width
-    frame.stack.shrink_to(target_stack_width);
+    // Carry the branch values over to the target, dropping whatever else the scopes we're leaving
+    // had on the stack.
+    frame
+        .stack
+        .keep_top(target.arity as usize, target.stack_width)?;
 
-    // Now provide the branch values to the target block
-    for value in branch_values {
-        value.push_to(&mut frame.stack);
-    }
+    // Pop all the control blocks up to (and for anything but a loop, including) the target
+    let remaining = frame.control_stack.len() - pop_depth;
+    frame.control_stack.truncate(remaining);
 
     // For structured control flow, we need to find where to jump based on scope type
     match target_scope_type {
@@ -313,23 +565,15 @@ fn execute_branch(frame: &mut Frame, depth: usize) -> Result<(), Fault> {
             frame.pc = frame.program.ops.len(); // This will cause the main loop to exit
         }
         ScopeType::Loop => {
-            // For loops, branch to the beginning of the loop (where StartScope is)
-            // Scan backward to find the corresponding StartScope
-            let mut loop_depth = 0;
-            let mut target_pc = frame.pc;
-
-            for i in (0..frame.pc).rev() {
-                match &frame.program.ops[i] {
-                    Op::EndScope(ScopeType::Loop) => loop_depth += 1,
-                    Op::StartScope(_, ScopeType::Loop) if loop_depth == 0 => {
-                        target_pc = i + 1; // Jump to instruction after StartScope
-                        break;
-                    }
-                    Op::StartScope(_, ScopeType::Loop) => loop_depth -= 1,
-                    _ => {}
-                }
+            // For loops, branch back to just after the loop's StartScope, which the loop's
+            // control entry recorded when it was entered.
+            frame.pc = target_start_pc;
+            if let Some(control) = frame.control_stack.last_mut() {
+                control.back_edges += 1;
+            }
+            if let Some(monitor) = anomalies {
+                monitor.looped(frame);
             }
-            frame.pc = target_pc;
         }
         _ => {
             // For Block, IfElse, Function: branch to the end (after EndScope)
@@ -357,106 +601,111 @@ fn execute_branch(frame: &mut Frame, depth: usize) -> Result<(), Fault> {
     Ok(())
 }
 
-fn execute<M>(
-    frame: &mut Frame,
+#[allow(clippy::too_many_arguments)]
+#[cfg_attr(not(all(feature = "gc", feature = "stats")), allow(unused_variables))]
+fn execute<M, S: SlotStorage>(
+    frame: &mut Frame<S>,
     memory: &mut M,
     globals: &mut [GlobalVar],
     tables: &mut [TableInstance],
-    max_ticks: usize,
+    ticks: &mut u64,
+    max_ticks: u64,
     types: &[FuncType],
+    type_ids: &[u32],
     functions: &[usize],
+    gc: &mut GcStore,
+    stats: &mut ExecutionStats,
+    grow_hook: &mut Option<MemoryGrowHook>,
+    host_funcs: &mut [HostFunction],
+    floats: FloatPolicy,
+    alignment_hook: &mut AlignmentHook,
+    watchpoints: &Watchpoints,
+    tracer: &mut Option<Tracer>,
+    anomalies: &mut Option<AnomalyMonitor>,
 ) -> Result<Continuation, Fault>
 where
     M: Memory,
 {
-    let mut ticks_used = 0;
     loop {
+        // A push the last op made which didn't fit was dropped, so don't carry on without it.
+        if frame.stack.exhausted() {
+            return Err(Fault::StackExhausted);
+        }
         // Pull next opcode from the program
         let pc = frame.pc;
         if pc >= frame.program.ops.len() {
             // We've reached the end of the program
             return Ok(Continuation::ProgramEnd);
         }
-        ticks_used += 1;
-        if ticks_used >= max_ticks {
+        *ticks += 1;
+        if *ticks >= max_ticks {
             return Err(Fault::OutOfTicks);
         }
         frame.pc += 1;
         let op = frame.program.ops[pc].clone();
+        #[cfg(feature = "stats")]
+        {
+            stats.ops_executed += 1;
+            stats.max_stack_slots = stats.max_stack_slots.max(frame.stack.width());
+        }
+        #[cfg(feature = "coverage")]
+        crate::coverage::record(&op);
+        if let Some(tracer) = tracer {
+            tracer.observe(frame.funcidx, pc, &op, &frame.stack)?;
+        }
+        let float_result = match floats == FloatPolicy::default() {
+            true => None,
+            false => float_result_type(&op),
+        };
+        let watched = match watchpoints.is_empty() {
+            true => None,
+            false => watchpoints.check(&op, &frame.stack),
+        };
 
         match op {
             Op::Nop => {}
+            Op::Throw(tag_index) => {
+                return Err(Fault::UnsupportedThrow(Some(tag_index)));
+            }
+            Op::ThrowRef => {
+                return Err(Fault::UnsupportedThrow(None));
+            }
             Op::Unreachable => {
                 return Err(Fault::Unreachable);
             }
             Op::StartScope(sig, scope_type) => {
-                let resolved_type = resolve_type(types, sig)?;
-                frame.push_control(resolved_type, scope_type);
+                frame.push_control(sig, scope_type);
             }
             Op::EndScope(c) => {
-                // If this is EndScope(Program), we need to preserve the stack for return value.
-                if let ScopeType::Program = &c {
+                // The end of the function (or expression) leaves the stack with the return values.
+                if let ScopeType::Program | ScopeType::Function = &c {
                     return Ok(Continuation::DoneReturn);
                 }
-                let (end_scope, result_values) = frame.pop_control()?;
-
-                // Shrink-stack to the width declared in the control scope.
-                frame.stack.shrink_to(end_scope.stack_width);
-                for value in result_values {
-                    value.push_to(&mut frame.stack);
-                }
+                frame.pop_control()?;
             }
-            Op::If => {
+            Op::If(else_or_end) => {
                 // Pop condition from stack, evaluate.
                 let condition = frame.stack.pop_u32()?;
                 if condition == 0 {
-                    // Skip to else block or end of if - scan forward to find it
-                    let mut depth = 0;
-                    let _start_pc = frame.pc;
-
-                    while frame.pc < frame.program.ops.len() {
-                        match &frame.program.ops[frame.pc] {
-                            Op::StartScope(_, ScopeType::IfElse) => depth += 1,
-                            Op::Else if depth == 0 => {
-                                frame.pc += 1; // Move past the Else op
-                                break;
-                            }
-                            Op::EndScope(ScopeType::IfElse) if depth == 0 => {
-                                break; // No else block, go to end
-                            }
-                            Op::EndScope(ScopeType::IfElse) => depth -= 1,
-                            _ => {}
-                        }
-                        frame.pc += 1;
-                    }
-                } else {
-                    // Continue to then block (next instruction)
+                    // Skip the then arm. Without an else arm this lands on the `EndScope`, which
+                    // leaves the params behind as the results; decoding has checked that they're
+                    // the same types.
+                    frame.pc = else_or_end as usize;
                 }
             }
-            Op::Else => {
-                // Skip to end of if block - scan forward to find matching EndScope
-                let mut depth = 0;
-
-                while frame.pc < frame.program.ops.len() {
-                    match &frame.program.ops[frame.pc] {
-                        Op::StartScope(_, ScopeType::IfElse) => depth += 1,
-                        Op::EndScope(ScopeType::IfElse) if depth == 0 => {
-                            break; // Found the end of this if block
-                        }
-                        Op::EndScope(ScopeType::IfElse) => depth -= 1,
-                        _ => {}
-                    }
-                    frame.pc += 1;
-                }
+            Op::Else(end) => {
+                // The then arm is done, so skip the else arm and let the `EndScope` pop the scope
+                // down to the then arm's results.
+                frame.pc = end as usize;
             }
             Op::Br(depth) => {
-                execute_branch(frame, depth as usize)?;
+                execute_branch(frame, depth as usize, anomalies)?;
                 continue;
             }
             Op::BrIf(depth) => {
                 let condition = frame.stack.pop_u32()?;
                 if condition != 0 {
-                    execute_branch(frame, depth as usize)?;
+                    execute_branch(frame, depth as usize, anomalies)?;
                     continue;
                 }
             }
@@ -468,7 +717,7 @@ where
                     default
                 } as usize;
 
-                execute_branch(frame, depth)?;
+                execute_branch(frame, depth, anomalies)?;
                 continue;
             }
             Op::Return => {
@@ -477,9 +726,19 @@ where
                 return Ok(Continuation::DoneReturn);
             }
             Op::Call(c) => {
-                return Ok(Continuation::Call(c));
+                // Imports are run by the host right here; only guest functions need a frame.
+                match host_funcs.get_mut(c as usize) {
+                    Some(host) if host.yields => {
+                        return Ok(Continuation::Suspend(SuspendReason::GuestYield))
+                    }
+                    Some(host) if host.caller_func.is_some() => {
+                        return Ok(Continuation::HostCall(c))
+                    }
+                    Some(host) => host.call(&mut frame.stack)?,
+                    None => return Ok(Continuation::Call(c)),
+                }
             }
-            Op::CallIndirect(_type_idx, table_idx) => {
+            Op::CallIndirect(type_idx, table_idx) => {
                 // Pop the table index from the stack (the actual index to use)
                 let table_index = frame.stack.pop_u32()?;
 
@@ -489,36 +748,46 @@ where
                 }
                 let table = &tables[table_idx as usize];
 
-                if table_index as usize >= table.elements.len() {
-                    return Err(Fault::UndefinedElement); // Table index out of bounds
-                }
-
-                match &table.elements[table_index as usize] {
-                    None => {
-                        return Err(Fault::UninitializedElement); // Uninitialized table element
-                    }
-                    Some(Value::FuncRef(Some(func_index))) => {
-                        // Verify function signature matches type_idx
-                        if *func_index as usize >= functions.len() {
-                            return Err(Fault::UndefinedElement);
-                        }
-
-                        let func_type_idx = functions[*func_index as usize];
-                        if func_type_idx >= types.len() || _type_idx as usize >= types.len() {
-                            return Err(Fault::UnresolvableTypeIndex(_type_idx));
-                        }
-
-                        let expected_type = &types[_type_idx as usize];
-                        let actual_type = &types[func_type_idx];
+                let element = table
+                    .get(u64::from(table_index))
+                    .ok_or(Fault::UndefinedElement)?;
+                match element {
+                    Value::FuncRef(Some(func_index)) => {
+                        let expected_id = *type_ids
+                            .get(type_idx as usize)
+                            .ok_or(Fault::UnresolvableTypeIndex(type_idx))?;
 
-                        // Check if function signatures match (structural typing)
-                        if expected_type != actual_type {
+                        // Guest functions' types are in the module's own type space, so their
+                        // canonical IDs settle it. Host functions bring their own signatures,
+                        // which have to be compared in full.
+                        let matches = match host_funcs.get(func_index as usize) {
+                            Some(host) => types.get(type_idx as usize) == Some(&host.func_type),
+                            None => {
+                                let defined = func_index as usize - host_funcs.len();
+                                let func_type_idx =
+                                    *functions.get(defined).ok_or(Fault::UndefinedElement)?;
+                                let actual_id = *type_ids
+                                    .get(func_type_idx)
+                                    .ok_or(Fault::UnresolvableTypeIndex(type_idx))?;
+                                actual_id == expected_id
+                            }
+                        };
+                        if !matches {
                             return Err(Fault::IndirectCallTypeMismatch);
                         }
 
-                        return Ok(Continuation::Call(*func_index));
+                        match host_funcs.get_mut(func_index as usize) {
+                            Some(host) if host.yields => {
+                                return Ok(Continuation::Suspend(SuspendReason::GuestYield))
+                            }
+                            Some(host) if host.caller_func.is_some() => {
+                                return Ok(Continuation::HostCall(func_index))
+                            }
+                            Some(host) => host.call(&mut frame.stack)?,
+                            None => return Ok(Continuation::Call(func_index)),
+                        }
                     }
-                    Some(Value::FuncRef(None)) => {
+                    Value::FuncRef(None) => {
                         return Err(Fault::UninitializedElement); // Null function reference
                     }
                     _ => {
@@ -527,17 +796,20 @@ where
                 }
             }
             Op::Drop => {
-                frame.stack.pop_u64()?;
+                frame.stack.pop_slot()?;
             }
             Op::Select => {
                 //The select instruction returns its first operand if $condition is true, or its second operand otherwise.
                 let condition = frame.stack.pop_i32()?;
-                let val2 = frame.stack.pop_u64()?; // Second operand (popped first)
-                let val1 = frame.stack.pop_u64()?; // First operand (popped second)
+                let val2 = frame.stack.pop_slot()?; // Second operand (popped first)
+                let val1 = frame.stack.pop_slot()?; // First operand (popped second)
+                if !val1.same_kind(&val2) {
+                    return Err(Fault::StackKindMismatch);
+                }
                 if condition != 0 {
-                    frame.stack.push_u64(val1); // Return first operand if condition is true
+                    frame.stack.push_slot(val1); // Return first operand if condition is true
                 } else {
-                    frame.stack.push_u64(val2); // Return second operand if condition is false
+                    frame.stack.push_slot(val2); // Return second operand if condition is false
                 }
             }
             Op::GetLocal(idx) => {
@@ -560,8 +832,11 @@ where
                 if g as usize >= globals.len() {
                     return Err(Fault::GlobalIndexOutOfBounds);
                 }
-                let value = Value::pop_from(globals[g as usize].decl.ty, &mut frame.stack)?;
-                globals[g as usize].value = value;
+                let global = &mut globals[g as usize];
+                if !global.decl.mutable {
+                    return Err(Fault::ImmutableGlobal(g));
+                }
+                global.value = Value::pop_from(global.decl.ty, &mut frame.stack)?;
             }
             Op::TableGet(table_idx) => {
                 let idx = frame.stack.pop_u32()?;
@@ -569,25 +844,16 @@ where
                     return Err(Fault::GlobalIndexOutOfBounds);
                 }
                 let table = &tables[table_idx as usize];
-                if idx as usize >= table.elements.len() {
-                    return Err(Fault::MemoryOutOfBounds);
-                }
-                match &table.elements[idx as usize] {
-                    Some(value) => value.push_to(&mut frame.stack),
-                    None => frame.stack.push_ref(None), // null reference
-                }
+                let value = table.get(u64::from(idx)).ok_or(Fault::TableOutOfBounds)?;
+                value.push_to(&mut frame.stack);
             }
             Op::TableSet(table_idx) => {
-                let idx = frame.stack.pop_u32()?;
                 if table_idx as usize >= tables.len() {
                     return Err(Fault::GlobalIndexOutOfBounds);
                 }
                 let table = &mut tables[table_idx as usize];
-                if idx as usize >= table.elements.len() {
-                    return Err(Fault::MemoryOutOfBounds);
-                }
 
-                // Pop the appropriate type of value based on the table's reference type
+                // The value is on top, above the index. Pop it as the table's reference type.
                 let value = match table.ref_type {
                     crate::module::ReferenceType::FuncRef => {
                         let ref_val = frame.stack.pop_ref()?;
@@ -598,129 +864,32 @@ where
                         Value::ExternRef(ref_val)
                     }
                 };
-                table.elements[idx as usize] = Some(value);
-            }
-            Op::LoadI32(addr) => {
-                let addr = adjust_memarg(&mut frame.stack, &addr)?;
-                let value = memory.get_i32(addr)?;
-                frame.stack.push_i32(value);
-            }
-            Op::LoadI64(addr) => {
-                let addr = adjust_memarg(&mut frame.stack, &addr)?;
-                let value = memory.get_i64(addr)?;
-                frame.stack.push_i64(value);
-            }
-            Op::LoadF32(addr) => {
-                let addr = adjust_memarg(&mut frame.stack, &addr)?;
-                let value = memory.get_f32(addr)?;
-                frame.stack.push_f32(value);
-            }
-            Op::LoadF64(addr) => {
-                let addr = adjust_memarg(&mut frame.stack, &addr)?;
-                let value = memory.get_f64(addr)?;
-                frame.stack.push_f64(value);
-            }
-
-            // Extending load, signed
-            Op::Load8SE(addr) => {
-                let addr = adjust_memarg(&mut frame.stack, &addr)?;
-                let value = memory.get_u8(addr)? as i8 as i32;
-                frame.stack.push_i32(value);
-            }
-            Op::Load16Se(addr) => {
-                let addr = adjust_memarg(&mut frame.stack, &addr)?;
-                let value = memory.get_u16(addr)? as i16 as i32;
-                frame.stack.push_i32(value);
-            }
-            Op::Load8I64Se(addr) => {
-                let addr = adjust_memarg(&mut frame.stack, &addr)?;
-                let value = memory.get_u8(addr)? as i8 as i64;
-                frame.stack.push_i64(value);
-            }
-            Op::Load16I64Se(addr) => {
-                let addr = adjust_memarg(&mut frame.stack, &addr)?;
-                let value = memory.get_u16(addr)? as i16 as i64;
-                frame.stack.push_i64(value);
-            }
-            Op::Load32I64Se(addr) => {
-                let addr = adjust_memarg(&mut frame.stack, &addr)?;
-                let value = memory.get_u32(addr)? as i32 as i64;
-                frame.stack.push_i64(value);
-            }
-
-            // Extending load, unsigned
-            Op::Load8Ze(addr) => {
-                let addr = adjust_memarg(&mut frame.stack, &addr)?;
-                let value = memory.get_u8(addr)? as u32;
-                frame.stack.push_u32(value);
-            }
-            Op::Load16Ze(addr) => {
-                let addr = adjust_memarg(&mut frame.stack, &addr)?;
-                let value = memory.get_u16(addr)? as u32;
-                frame.stack.push_u32(value);
-            }
-            Op::Load8I64Ze(addr) => {
-                let addr = adjust_memarg(&mut frame.stack, &addr)?;
-                let value = memory.get_u8(addr)? as u64;
-                frame.stack.push_u64(value);
-            }
-            Op::Load16I64Ze(addr) => {
-                let addr = adjust_memarg(&mut frame.stack, &addr)?;
-                let value = memory.get_u16(addr)? as u64;
-                frame.stack.push_u64(value);
-            }
-            Op::Load32I64Ze(addr) => {
-                let addr = adjust_memarg(&mut frame.stack, &addr)?;
-                let value = memory.get_u32(addr)? as u64;
-                frame.stack.push_u64(value);
-            }
-            Op::StoreI32(addr) => {
-                let value = frame.stack.pop_i32()?;
-                let addr = adjust_memarg(&mut frame.stack, &addr)?;
-                memory.set_i32(addr, value)?;
-            }
-            Op::StoreI64(addr) => {
-                let value = frame.stack.pop_i64()?;
-                let addr = adjust_memarg(&mut frame.stack, &addr)?;
-                memory.set_i64(addr, value)?;
-            }
-            Op::StoreF32(addr) => {
-                let value = frame.stack.pop_f32()?;
-                let addr = adjust_memarg(&mut frame.stack, &addr)?;
-                memory.set_f32(addr, value)?;
-            }
-            Op::StoreF64(addr) => {
-                let value = frame.stack.pop_f64()?;
-                let addr = adjust_memarg(&mut frame.stack, &addr)?;
-                memory.set_f64(addr, value)?;
-            }
-
-            // Silently narrow the width of the value
-            Op::Store8_32(addr) => {
-                let value = frame.stack.pop_i32()? as u8;
-                let addr = adjust_memarg(&mut frame.stack, &addr)?;
-                memory.set_u8(addr, value)?;
-            }
-            Op::Store16_32(addr) => {
-                let value = frame.stack.pop_i32()? as u16;
-                let addr = adjust_memarg(&mut frame.stack, &addr)?;
-                memory.set_u16(addr, value)?;
-            }
-            Op::Store8_64(addr) => {
-                let value = frame.stack.pop_i64()? as u8;
-                let addr = adjust_memarg(&mut frame.stack, &addr)?;
-                memory.set_u8(addr, value)?;
-            }
-            Op::Store16_64(addr) => {
-                let value = frame.stack.pop_i64()? as u16;
-                let addr = adjust_memarg(&mut frame.stack, &addr)?;
-                memory.set_u16(addr, value)?;
-            }
-            Op::Store32_64(addr) => {
-                let value = frame.stack.pop_i64()? as u32;
-                let addr = adjust_memarg(&mut frame.stack, &addr)?;
-                memory.set_u32(addr, value)?;
-            }
+                let idx = frame.stack.pop_u32()?;
+                table.write(u64::from(idx), &[value])?;
+            }
+            Op::LoadI32(_)
+            | Op::LoadI64(_)
+            | Op::LoadF32(_)
+            | Op::LoadF64(_)
+            | Op::Load8SE(_)
+            | Op::Load16Se(_)
+            | Op::Load8I64Se(_)
+            | Op::Load16I64Se(_)
+            | Op::Load32I64Se(_)
+            | Op::Load8Ze(_)
+            | Op::Load16Ze(_)
+            | Op::Load8I64Ze(_)
+            | Op::Load16I64Ze(_)
+            | Op::Load32I64Ze(_)
+            | Op::StoreI32(_)
+            | Op::StoreI64(_)
+            | Op::StoreF32(_)
+            | Op::StoreF64(_)
+            | Op::Store8_32(_)
+            | Op::Store16_32(_)
+            | Op::Store8_64(_)
+            | Op::Store16_64(_)
+            | Op::Store32_64(_) => memory_op(&op, frame, memory, alignment_hook)?,
 
             Op::I32Const(v) => {
                 frame.stack.push_i32(v);
@@ -729,850 +898,482 @@ where
                 frame.stack.push_i64(v);
             }
             Op::F32Const(v) => {
-                frame.stack.push_u32(v.to_bits());
+                frame.stack.push_f32(v);
             }
             Op::F64Const(v) => {
-                frame.stack.push_u64(v.to_bits());
+                frame.stack.push_f64(v);
             }
             Op::MemorySize => {
-                let size_in_bytes = memory.size();
-                let size_in_pages = size_in_bytes / WASM_PAGE_SIZE;
-                frame.stack.push_u32(size_in_pages as u32);
+                frame.stack.push_u32(memory.pages() as u32);
             }
             Op::MemoryGrow => {
-                let delta = frame.stack.pop_i32()?;
-                if delta < 0 {
-                    frame.stack.push_i32(-1);
-                } else {
-                    let current_size = memory.size();
-                    let old_page_count = current_size / WASM_PAGE_SIZE;
-                    let new_size = current_size + (delta as usize * WASM_PAGE_SIZE);
-                    match memory.grow(new_size) {
-                        Ok(_) => frame.stack.push_i32(old_page_count as i32),
-                        Err(_) => frame.stack.push_i32(-1),
-                    }
+                let delta = frame.stack.pop_u32()?;
+                let result = memory_grow(memory, delta, grow_hook)?;
+                #[cfg(feature = "stats")]
+                if result >= 0 {
+                    stats.grows += 1;
                 }
+                frame.stack.push_i32(result);
             }
-            Op::I32Eqz => {
-                let value = frame.stack.pop_i32()?;
-                frame.stack.push_u32(if value == 0 { 1 } else { 0 });
-            }
-            Op::I32Eq => {
-                let b = frame.stack.pop_i32()?;
-                let a = frame.stack.pop_i32()?;
-                frame.stack.push_u32(if a == b { 1 } else { 0 });
-            }
-            Op::I32Ne => {
-                let b = frame.stack.pop_i32()?;
-                let a = frame.stack.pop_i32()?;
-                frame.stack.push_u32(if a != b { 1 } else { 0 });
-            }
-            Op::I32LtS => {
-                let b = frame.stack.pop_i32()?;
-                let a = frame.stack.pop_i32()?;
-                frame.stack.push_u32(if a < b { 1 } else { 0 });
-            }
-            Op::I32LtU => {
-                let b = frame.stack.pop_u32()?;
-                let a = frame.stack.pop_u32()?;
-                frame.stack.push_u32(if a < b { 1 } else { 0 });
-            }
-            Op::I32GtS => {
-                let b = frame.stack.pop_i32()?;
-                let a = frame.stack.pop_i32()?;
-                frame.stack.push_u32(if a > b { 1 } else { 0 });
-            }
-            Op::I32GtU => {
-                let b = frame.stack.pop_u32()?;
-                let a = frame.stack.pop_u32()?;
-                frame.stack.push_u32(if a > b { 1 } else { 0 });
-            }
-            Op::I32LeS => {
-                let b = frame.stack.pop_i32()?;
-                let a = frame.stack.pop_i32()?;
-                frame.stack.push_u32(if a <= b { 1 } else { 0 });
-            }
-            Op::I32LeU => {
-                let b = frame.stack.pop_u32()?;
-                let a = frame.stack.pop_u32()?;
-                frame.stack.push_u32(if a <= b { 1 } else { 0 });
-            }
-            Op::I32GeS => {
-                let b = frame.stack.pop_i32()?;
-                let a = frame.stack.pop_i32()?;
-                frame.stack.push_u32(if a >= b { 1 } else { 0 });
-            }
-            Op::I32GeU => {
-                let b = frame.stack.pop_u32()?;
-                let a = frame.stack.pop_u32()?;
-                frame.stack.push_u32(if a >= b { 1 } else { 0 });
-            }
-            Op::I64Eqz => {
-                let value = frame.stack.pop_i64()?;
-                frame.stack.push_u32(if value == 0 { 1 } else { 0 });
-            }
-            Op::I64Eq => {
-                let b = frame.stack.pop_i64()?;
-                let a = frame.stack.pop_i64()?;
-                frame.stack.push_u32(if a == b { 1 } else { 0 });
-            }
-            Op::I64Ne => {
-                let b = frame.stack.pop_i64()?;
-                let a = frame.stack.pop_i64()?;
-                frame.stack.push_u32(if a != b { 1 } else { 0 });
-            }
-            Op::I64LtS => {
-                let b = frame.stack.pop_i64()?;
-                let a = frame.stack.pop_i64()?;
-                frame.stack.push_u32(if a < b { 1 } else { 0 });
-            }
-            Op::I64LtU => {
-                let b = frame.stack.pop_u64()?;
-                let a = frame.stack.pop_u64()?;
-                frame.stack.push_u32(if a < b { 1 } else { 0 });
-            }
-            Op::I64GtS => {
-                let b = frame.stack.pop_i64()?;
-                let a = frame.stack.pop_i64()?;
-                frame.stack.push_u32(if a > b { 1 } else { 0 });
-            }
-            Op::I64GtU => {
-                let b = frame.stack.pop_u64()?;
-                let a = frame.stack.pop_u64()?;
-                frame.stack.push_u32(if a > b { 1 } else { 0 });
-            }
-            Op::I64LeS => {
-                let b = frame.stack.pop_i64()?;
-                let a = frame.stack.pop_i64()?;
-                frame.stack.push_u32(if a <= b { 1 } else { 0 });
-            }
-            Op::I64LeU => {
-                let b = frame.stack.pop_u64()?;
-                let a = frame.stack.pop_u64()?;
-                frame.stack.push_u32(if a <= b { 1 } else { 0 });
-            }
-            Op::I64GeS => {
-                let b = frame.stack.pop_i64()?;
-                let a = frame.stack.pop_i64()?;
-                frame.stack.push_u32(if a >= b { 1 } else { 0 });
-            }
-            Op::I64GeU => {
-                let b = frame.stack.pop_u64()?;
-                let a = frame.stack.pop_u64()?;
-                frame.stack.push_u32(if a >= b { 1 } else { 0 });
-            }
-            Op::F32Eq => {
-                let b = frame.stack.pop_f32()?;
-                let a = frame.stack.pop_f32()?;
-                frame.stack.push_u32(if a == b { 1 } else { 0 });
-            }
-            Op::F32Ne => {
-                let b = frame.stack.pop_f32()?;
-                let a = frame.stack.pop_f32()?;
-                frame.stack.push_u32(if a != b { 1 } else { 0 });
-            }
-            Op::F32Lt => {
-                let b = frame.stack.pop_f32()?;
-                let a = frame.stack.pop_f32()?;
-                frame.stack.push_u32(if a < b { 1 } else { 0 });
-            }
-            Op::F32Gt => {
-                let b = frame.stack.pop_f32()?;
-                let a = frame.stack.pop_f32()?;
-                frame.stack.push_u32(if a > b { 1 } else { 0 });
-            }
-            Op::F32Le => {
-                let b = frame.stack.pop_f32()?;
-                let a = frame.stack.pop_f32()?;
-                frame.stack.push_u32(if a <= b { 1 } else { 0 });
-            }
-            Op::F32Ge => {
-                let b = frame.stack.pop_f32()?;
-                let a = frame.stack.pop_f32()?;
-                frame.stack.push_u32(if a >= b { 1 } else { 0 });
-            }
-            Op::F64Eq => {
-                let b = frame.stack.pop_f64()?;
-                let a = frame.stack.pop_f64()?;
-                frame.stack.push_u32(if a == b { 1 } else { 0 });
-            }
-            Op::F64Ne => {
-                let b = frame.stack.pop_f64()?;
-                let a = frame.stack.pop_f64()?;
-                frame.stack.push_u32(if a != b { 1 } else { 0 });
-            }
-            Op::F64Lt => {
-                let b = frame.stack.pop_f64()?;
-                let a = frame.stack.pop_f64()?;
-                frame.stack.push_u32(if a < b { 1 } else { 0 });
-            }
-            Op::F64Gt => {
-                let b = frame.stack.pop_f64()?;
-                let a = frame.stack.pop_f64()?;
-                frame.stack.push_u32(if a > b { 1 } else { 0 });
-            }
-            Op::F64Le => {
-                let b = frame.stack.pop_f64()?;
-                let a = frame.stack.pop_f64()?;
-                frame.stack.push_u32(if a <= b { 1 } else { 0 });
-            }
-            Op::F64Ge => {
-                let b = frame.stack.pop_f64()?;
-                let a = frame.stack.pop_f64()?;
-                frame.stack.push_u32(if a >= b { 1 } else { 0 });
-            }
-            Op::I32Clz => {
-                let value = frame.stack.pop_i32()?;
-                frame.stack.push_u32(value.leading_zeros());
-            }
-            Op::I32Ctz => {
-                let value = frame.stack.pop_i32()?;
-                frame.stack.push_u32(value.trailing_zeros());
-            }
-            Op::I32Popcnt => {
-                let value = frame.stack.pop_i32()?;
-                frame.stack.push_u32(value.count_ones());
-            }
-            Op::I32Add => {
-                let b = frame.stack.pop_i32()?;
-                let a = frame.stack.pop_i32()?;
-                frame.stack.push_i32(a.wrapping_add(b));
-            }
-            Op::I32Sub => {
-                let b = frame.stack.pop_i32()?;
-                let a = frame.stack.pop_i32()?;
-                frame.stack.push_i32(a.wrapping_sub(b));
-            }
-            Op::I32Mul => {
-                let b = frame.stack.pop_i32()?;
-                let a = frame.stack.pop_i32()?;
-                frame.stack.push_i32(a.wrapping_mul(b));
-            }
-            Op::I32DivS => {
-                let b = frame.stack.pop_i32()?;
-                let a = frame.stack.pop_i32()?;
-                match a.checked_div(b) {
-                    Some(result) => frame.stack.push_i32(result),
-                    None => {
-                        if b == 0 {
-                            return Err(Fault::IntegerDivisionByZero);
-                        } else {
-                            return Err(Fault::IntegerOverflow);
-                        }
-                    }
+            Op::I32Eqz
+            | Op::I32Eq
+            | Op::I32Ne
+            | Op::I32LtS
+            | Op::I32LtU
+            | Op::I32GtS
+            | Op::I32GtU
+            | Op::I32LeS
+            | Op::I32LeU
+            | Op::I32GeS
+            | Op::I32GeU
+            | Op::I32Clz
+            | Op::I32Ctz
+            | Op::I32Popcnt
+            | Op::I32Add
+            | Op::I32Sub
+            | Op::I32Mul
+            | Op::I32DivS
+            | Op::I32DivU
+            | Op::I32RemS
+            | Op::I32RemU
+            | Op::I32And
+            | Op::I32Or
+            | Op::I32Xor
+            | Op::I32Shl
+            | Op::I32ShrS
+            | Op::I32ShrU
+            | Op::I32Rotl
+            | Op::I32Rotr
+            | Op::I32Extend8S
+            | Op::I32Extend16S => numeric::i32_op(&op, &mut frame.stack)?,
+            Op::I64Eqz
+            | Op::I64Eq
+            | Op::I64Ne
+            | Op::I64LtS
+            | Op::I64LtU
+            | Op::I64GtS
+            | Op::I64GtU
+            | Op::I64LeS
+            | Op::I64LeU
+            | Op::I64GeS
+            | Op::I64GeU
+            | Op::I64Clz
+            | Op::I64Ctz
+            | Op::I64Popcnt
+            | Op::I64Add
+            | Op::I64Sub
+            | Op::I64Mul
+            | Op::I64DivS
+            | Op::I64DivU
+            | Op::I64RemS
+            | Op::I64RemU
+            | Op::I64And
+            | Op::I64Or
+            | Op::I64Xor
+            | Op::I64Shl
+            | Op::I64ShrS
+            | Op::I64ShrU
+            | Op::I64Rotl
+            | Op::I64Rotr
+            | Op::I64Extend8S
+            | Op::I64Extend16S
+            | Op::I64Extend32S => numeric::i64_op(&op, &mut frame.stack)?,
+            Op::F32Eq
+            | Op::F32Ne
+            | Op::F32Lt
+            | Op::F32Gt
+            | Op::F32Le
+            | Op::F32Ge
+            | Op::F32Abs
+            | Op::F32Neg
+            | Op::F32Ceil
+            | Op::F32Floor
+            | Op::F32Trunc
+            | Op::F32Nearest
+            | Op::F32Sqrt
+            | Op::F32Add
+            | Op::F32Sub
+            | Op::F32Mul
+            | Op::F32Div
+            | Op::F32Min
+            | Op::F32Max
+            | Op::F32Copysign => numeric::f32_op(&op, &mut frame.stack)?,
+            Op::F64Eq
+            | Op::F64Ne
+            | Op::F64Lt
+            | Op::F64Gt
+            | Op::F64Le
+            | Op::F64Ge
+            | Op::F64Add
+            | Op::F64Sub
+            | Op::F64Mul
+            | Op::F64Div
+            | Op::F64Min
+            | Op::F64Max
+            | Op::F64Copysign
+            | Op::F64Abs
+            | Op::F64Neg
+            | Op::F64Ceil
+            | Op::F64Floor
+            | Op::F64Trunc
+            | Op::F64Nearest
+            | Op::F64Sqrt => numeric::f64_op(&op, &mut frame.stack)?,
+            Op::I32WrapI64
+            | Op::I32TruncF32S
+            | Op::I32TruncF32U
+            | Op::I32TruncF64S
+            | Op::I32TruncF64U
+            | Op::I64ExtendI32S
+            | Op::I64ExtendI32U
+            | Op::I64TruncF32S
+            | Op::I64TruncF32U
+            | Op::I64TruncF64S
+            | Op::I64TruncF64U
+            | Op::I32TruncSatF32S
+            | Op::I32TruncSatF32U
+            | Op::I32TruncSatF64S
+            | Op::I32TruncSatF64U
+            | Op::I64TruncSatF32S
+            | Op::I64TruncSatF32U
+            | Op::I64TruncSatF64S
+            | Op::I64TruncSatF64U
+            | Op::F32ConvertI32S
+            | Op::F32ConvertI32U
+            | Op::F32ConvertI64S
+            | Op::F32ConvertI64U
+            | Op::F32DemoteF64
+            | Op::F64ConvertI32S
+            | Op::F64ConvertI32U
+            | Op::F64ConvertI64S
+            | Op::F64ConvertI64U
+            | Op::F64PromoteF32
+            | Op::I32ReinterpretF32
+            | Op::I64ReinterpretF64
+            | Op::F32ReinterpretI32
+            | Op::F64ReinterpretI64 => numeric::conversion_op(&op, &mut frame.stack)?,
+
+            // Reference types operations
+            Op::RefNull(ref_type) => match ref_type {
+                crate::ValueType::FuncRef | crate::ValueType::ExternRef => {
+                    frame.stack.push_ref(None);
                 }
-            }
-            Op::I32DivU => {
-                let b = frame.stack.pop_u32()?;
-                let a = frame.stack.pop_u32()?;
-                match a.checked_div(b) {
-                    Some(result) => frame.stack.push_u32(result),
-                    None => return Err(Fault::IntegerDivisionByZero),
+                #[cfg(feature = "gc")]
+                crate::ValueType::AnyRef => {
+                    frame.stack.push_ref(None);
                 }
+                _ => return Err(Fault::InvalidRefType),
+            },
+            Op::RefFunc(func_index) => {
+                // TODO: Validate func_index exists in the module
+                frame.stack.push_ref(Some(func_index));
             }
-            Op::I32RemS => {
-                let b = frame.stack.pop_i32()?;
-                let a = frame.stack.pop_i32()?;
-                match a.checked_rem(b) {
-                    Some(result) => frame.stack.push_i32(result),
-                    None => {
-                        if b == 0 {
-                            return Err(Fault::IntegerDivisionByZero);
-                        } else {
-                            // i32::MIN % -1 = 0 by WASM spec
-                            frame.stack.push_i32(0);
-                        }
-                    }
-                }
+            Op::RefIsNull => {
+                let ref_val = frame.stack.pop_ref()?;
+                let is_null = if ref_val.is_none() { 1 } else { 0 };
+                frame.stack.push_i32(is_null);
             }
-            Op::I32RemU => {
-                let b = frame.stack.pop_u32()?;
-                let a = frame.stack.pop_u32()?;
-                match a.checked_rem(b) {
-                    Some(result) => frame.stack.push_u32(result),
-                    None => return Err(Fault::IntegerDivisionByZero),
+            Op::RefAsNonNull => {
+                let ref_val = frame.stack.pop_ref()?;
+                match ref_val {
+                    Some(val) => frame.stack.push_ref(Some(val)),
+                    None => return Err(Fault::NullReference),
                 }
             }
-            Op::I32And => {
-                let b = frame.stack.pop_i32()?;
-                let a = frame.stack.pop_i32()?;
-                frame.stack.push_i32(a & b);
-            }
-            Op::I32Or => {
-                let b = frame.stack.pop_i32()?;
-                let a = frame.stack.pop_i32()?;
-                frame.stack.push_i32(a | b);
-            }
-            Op::I32Xor => {
-                let b = frame.stack.pop_i32()?;
-                let a = frame.stack.pop_i32()?;
-                frame.stack.push_i32(a ^ b);
-            }
-            Op::I32Shl => {
-                let b = frame.stack.pop_u32()?;
-                let a = frame.stack.pop_i32()?;
-                frame.stack.push_i32(a.wrapping_shl(b));
-            }
-            Op::I32ShrS => {
-                let b = frame.stack.pop_u32()?;
-                let a = frame.stack.pop_i32()?;
-                frame.stack.push_i32(a.wrapping_shr(b));
+            Op::RefEq => {
+                let ref2 = frame.stack.pop_ref()?;
+                let ref1 = frame.stack.pop_ref()?;
+                #[cfg(feature = "gc")]
+                let are_equal = match (ref1, ref2) {
+                    (Some(a), Some(b)) => gc.ref_eq(a, b) as i32,
+                    _ => (ref1 == ref2) as i32,
+                };
+                #[cfg(not(feature = "gc"))]
+                let are_equal = if ref1 == ref2 { 1 } else { 0 };
+                frame.stack.push_i32(are_equal);
             }
-            Op::I32ShrU => {
-                let b = frame.stack.pop_u32()?;
-                let a = frame.stack.pop_u32()?;
-                frame.stack.push_u32(a.wrapping_shr(b));
+            #[cfg(feature = "gc")]
+            Op::Gc(ref op) => gc.execute(op, &mut frame.stack)?,
+            #[cfg(feature = "atomics")]
+            Op::Atomic(ref op) => op.execute(&mut frame.stack, memory)?,
+            #[cfg(feature = "optimize")]
+            Op::LocalI32AddConst(idx, c) => {
+                let value = frame.local_i32(idx)?;
+                frame.stack.push_i32(value.wrapping_add(c));
+            }
+            #[cfg(feature = "optimize")]
+            Op::LocalLoadI32(idx, memarg) => {
+                let addr = memarg_addr(frame.local_i32(idx)? as u32, &memarg, memory.size())?;
+                #[cfg(feature = "alignment-diagnostics")]
+                check_alignment(frame, addr, &memarg, alignment_hook);
+                let value = memory.get_i32(addr)?;
+                frame.stack.push_i32(value);
             }
-            Op::I32Rotl => {
-                let b = frame.stack.pop_u32()?;
-                let a = frame.stack.pop_i32()?;
-                frame.stack.push_i32(a.rotate_left(b));
+            #[cfg(feature = "optimize")]
+            Op::ConstStoreI32(value, memarg) => {
+                let addr = access_addr(frame, &memarg, memory.size(), alignment_hook)?;
+                memory.set_i32(addr, value)?;
             }
-            Op::I32Rotr => {
-                let b = frame.stack.pop_u32()?;
+            #[cfg(feature = "optimize")]
+            Op::BrIfI32Cmp(cmp, depth) => {
+                let b = frame.stack.pop_i32()?;
                 let a = frame.stack.pop_i32()?;
-                frame.stack.push_i32(a.rotate_right(b));
-            }
-            Op::I64Clz => {
-                let value = frame.stack.pop_i64()?;
-                frame.stack.push_i64(value.leading_zeros() as i64);
-            }
-            Op::I64Ctz => {
-                let value = frame.stack.pop_i64()?;
-                frame.stack.push_i64(value.trailing_zeros() as i64);
-            }
-            Op::I64Popcnt => {
-                let value = frame.stack.pop_i64()?;
-                frame.stack.push_i64(value.count_ones() as i64);
-            }
-            Op::I64Add => {
-                let b = frame.stack.pop_i64()?;
-                let a = frame.stack.pop_i64()?;
-                frame.stack.push_i64(a.wrapping_add(b));
-            }
-            Op::I64Sub => {
-                let b = frame.stack.pop_i64()?;
-                let a = frame.stack.pop_i64()?;
-                frame.stack.push_i64(a.wrapping_sub(b));
-            }
-            Op::I64Mul => {
-                let b = frame.stack.pop_i64()?;
-                let a = frame.stack.pop_i64()?;
-                frame.stack.push_i64(a.wrapping_mul(b));
-            }
-            Op::I64DivS => {
-                let b = frame.stack.pop_i64()?;
-                let a = frame.stack.pop_i64()?;
-                match a.checked_div(b) {
-                    Some(result) => frame.stack.push_i64(result),
-                    None => {
-                        if b == 0 {
-                            return Err(Fault::IntegerDivisionByZero);
-                        } else {
-                            return Err(Fault::IntegerOverflow);
-                        }
-                    }
+                if cmp.test(a, b) {
+                    execute_branch(frame, depth as usize, anomalies)?;
+                    continue;
                 }
             }
-            Op::I64DivU => {
-                let b = frame.stack.pop_u64()?;
-                let a = frame.stack.pop_u64()?;
-                match a.checked_div(b) {
-                    Some(result) => frame.stack.push_u64(result),
-                    None => return Err(Fault::IntegerDivisionByZero),
+            #[cfg(feature = "optimize")]
+            Op::BrIfEqz(depth) => {
+                if frame.stack.pop_i32()? == 0 {
+                    execute_branch(frame, depth as usize, anomalies)?;
+                    continue;
                 }
             }
-            Op::I64RemS => {
-                let b = frame.stack.pop_i64()?;
-                let a = frame.stack.pop_i64()?;
-                match a.checked_rem(b) {
-                    Some(result) => frame.stack.push_i64(result),
-                    None => {
-                        if b == 0 {
-                            return Err(Fault::IntegerDivisionByZero);
-                        } else {
-                            // i64::MIN % -1 = 0 by WASM spec
-                            frame.stack.push_i64(0);
-                        }
-                    }
-                }
-            }
-            Op::I64RemU => {
-                let b = frame.stack.pop_u64()?;
-                let a = frame.stack.pop_u64()?;
-                match a.checked_rem(b) {
-                    Some(result) => frame.stack.push_u64(result),
-                    None => return Err(Fault::IntegerDivisionByZero),
-                }
-            }
-            Op::I64And => {
-                let b = frame.stack.pop_i64()?;
-                let a = frame.stack.pop_i64()?;
-                frame.stack.push_i64(a & b);
-            }
-            Op::I64Or => {
-                let b = frame.stack.pop_i64()?;
-                let a = frame.stack.pop_i64()?;
-                frame.stack.push_i64(a | b);
-            }
-            Op::I64Xor => {
-                let b = frame.stack.pop_i64()?;
-                let a = frame.stack.pop_i64()?;
-                frame.stack.push_i64(a ^ b);
-            }
-            Op::I64Shl => {
-                let b = frame.stack.pop_u64()?;
-                let a = frame.stack.pop_i64()?;
-                frame.stack.push_i64(a.wrapping_shl(b as u32));
-            }
-            Op::I64ShrS => {
-                let b = frame.stack.pop_u64()?;
-                let a = frame.stack.pop_i64()?;
-                frame.stack.push_i64(a.wrapping_shr(b as u32));
-            }
-            Op::I64ShrU => {
-                let b = frame.stack.pop_u64()?;
-                let a = frame.stack.pop_u64()?;
-                frame.stack.push_u64(a.wrapping_shr(b as u32));
-            }
-            Op::I64Rotl => {
-                let b = frame.stack.pop_u64()?;
-                let a = frame.stack.pop_i64()?;
-                frame.stack.push_i64(a.rotate_left(b as u32));
-            }
-            Op::I64Rotr => {
-                let b = frame.stack.pop_u64()?;
-                let a = frame.stack.pop_i64()?;
-                frame.stack.push_i64(a.rotate_right(b as u32));
-            }
-            Op::F32Abs => {
-                let value = frame.stack.pop_f32()?;
-                frame.stack.push_f32(value.abs());
-            }
-            Op::F32Neg => {
-                let value = frame.stack.pop_f32()?;
-                frame.stack.push_f32(-value);
-            }
-            Op::F32Ceil => {
-                let value = frame.stack.pop_f32()?;
-                frame.stack.push_f32(value.ceil());
-            }
-            Op::F32Floor => {
-                let value = frame.stack.pop_f32()?;
-                frame.stack.push_f32(value.floor());
-            }
-            Op::F32Trunc => {
-                let value = frame.stack.pop_f32()?;
-                frame.stack.push_f32(value.trunc());
-            }
-            Op::F32Nearest => {
-                let value = frame.stack.pop_f32()?;
-                frame.stack.push_f32(value.round_ties_even());
-            }
-            Op::F32Sqrt => {
-                let value = frame.stack.pop_f32()?;
-                frame.stack.push_f32(value.sqrt());
-            }
-            Op::F32Add => {
-                let b = frame.stack.pop_f32()?;
-                let a = frame.stack.pop_f32()?;
-                frame.stack.push_f32(a + b);
-            }
-            Op::F32Sub => {
-                let b = frame.stack.pop_f32()?;
-                let a = frame.stack.pop_f32()?;
-                frame.stack.push_f32(a - b);
-            }
-            Op::F32Mul => {
-                let b = frame.stack.pop_f32()?;
-                let a = frame.stack.pop_f32()?;
-                frame.stack.push_f32(a * b);
-            }
-            Op::F32Div => {
-                let b = frame.stack.pop_f32()?;
-                let a = frame.stack.pop_f32()?;
-                frame.stack.push_f32(a / b);
-            }
-            Op::F32Min => {
-                let b = frame.stack.pop_f32()?;
-                let a = frame.stack.pop_f32()?;
-                let result = if a.is_nan() || b.is_nan() {
-                    f32::NAN
-                } else {
-                    a.min(b)
-                };
-                frame.stack.push_f32(result);
-            }
-            Op::F32Max => {
-                let b = frame.stack.pop_f32()?;
-                let a = frame.stack.pop_f32()?;
-                let result = if a.is_nan() || b.is_nan() {
-                    f32::NAN
-                } else {
-                    a.max(b)
-                };
-                frame.stack.push_f32(result);
-            }
-            Op::F32Copysign => {
-                let b = frame.stack.pop_f32()?;
-                let a = frame.stack.pop_f32()?;
-                frame.stack.push_f32(a.copysign(b));
-            }
-            Op::F64Add => {
-                let b = frame.stack.pop_f64()?;
-                let a = frame.stack.pop_f64()?;
-                frame.stack.push_f64(a + b);
-            }
-            Op::F64Sub => {
-                let b = frame.stack.pop_f64()?;
-                let a = frame.stack.pop_f64()?;
-                frame.stack.push_f64(a - b);
-            }
-            Op::F64Mul => {
-                let b = frame.stack.pop_f64()?;
-                let a = frame.stack.pop_f64()?;
-                frame.stack.push_f64(a * b);
-            }
-            Op::F64Div => {
-                let b = frame.stack.pop_f64()?;
-                let a = frame.stack.pop_f64()?;
-                frame.stack.push_f64(a / b);
-            }
-            Op::F64Min => {
-                let b = frame.stack.pop_f64()?;
-                let a = frame.stack.pop_f64()?;
-                let result = if a.is_nan() || b.is_nan() {
-                    f64::NAN
-                } else {
-                    a.min(b)
-                };
-                frame.stack.push_f64(result);
-            }
-            Op::F64Max => {
-                let b = frame.stack.pop_f64()?;
-                let a = frame.stack.pop_f64()?;
-                let result = if a.is_nan() || b.is_nan() {
-                    f64::NAN
-                } else {
-                    a.max(b)
-                };
-                frame.stack.push_f64(result);
-            }
-            Op::F64Copysign => {
-                let b = frame.stack.pop_f64()?;
-                let a = frame.stack.pop_f64()?;
-                frame.stack.push_f64(a.copysign(b));
-            }
-            Op::F64Abs => {
-                let value = frame.stack.pop_f64()?;
-                frame.stack.push_f64(value.abs());
-            }
-            Op::F64Neg => {
-                let value = frame.stack.pop_f64()?;
-                frame.stack.push_f64(-value);
-            }
-            Op::F64Ceil => {
-                let value = frame.stack.pop_f64()?;
-                frame.stack.push_f64(value.ceil());
-            }
-            Op::F64Floor => {
-                let value = frame.stack.pop_f64()?;
-                frame.stack.push_f64(value.floor());
-            }
-            Op::F64Trunc => {
-                let value = frame.stack.pop_f64()?;
-                frame.stack.push_f64(value.trunc());
-            }
-            Op::F64Nearest => {
-                let value = frame.stack.pop_f64()?;
-                frame.stack.push_f64(value.round_ties_even());
-            }
-            Op::F64Sqrt => {
-                let value = frame.stack.pop_f64()?;
-                frame.stack.push_f64(value.sqrt());
-            }
-            Op::I32WrapI64 => {
-                let value = frame.stack.pop_i64()?;
-                // Turn to i32, wrapping around if necessary
-                // TODO: I think this is wrong
-                frame.stack.push_i32(value as i32);
-            }
-            Op::I32TruncF32S => {
-                let value = frame.stack.pop_f32()?;
-                let result = trunc_f32_to_i32(value)?;
-                frame.stack.push_i32(result);
-            }
-            Op::I32TruncF32U => {
-                let value = frame.stack.pop_f32()?;
-                let result = trunc_f32_to_u32(value)?;
-                frame.stack.push_u32(result);
-            }
-            Op::I32TruncF64S => {
-                let value = frame.stack.pop_f64()?;
-                let result = trunc_f64_to_i32(value)?;
-                frame.stack.push_i32(result);
-            }
-            Op::I32TruncF64U => {
-                let value = frame.stack.pop_f64()?;
-                let result = trunc_f64_to_u32(value)?;
-                frame.stack.push_u32(result);
-            }
-            Op::I64ExtendI32S => {
-                let value = frame.stack.pop_i32()?;
-                frame.stack.push_i64(value as i64);
-            }
-            Op::I64ExtendI32U => {
-                let value = frame.stack.pop_u32()?;
-                frame.stack.push_u64(value as u64);
-            }
-            Op::I64TruncF32S => {
-                let value = frame.stack.pop_f32()?;
-                let result = trunc_f32_to_i64(value)?;
-                frame.stack.push_i64(result);
-            }
-            Op::I64TruncF32U => {
-                let value = frame.stack.pop_f32()?;
-                let result = trunc_f32_to_u64(value)?;
-                frame.stack.push_u64(result);
-            }
-            Op::I64TruncF64S => {
-                let value = frame.stack.pop_f64()?;
-                let result = trunc_f64_to_i64(value)?;
-                frame.stack.push_i64(result);
-            }
-            Op::I64TruncF64U => {
-                let value = frame.stack.pop_f64()?;
-                let result = trunc_f64_to_u64(value)?;
-                frame.stack.push_u64(result);
-            }
-
-            // Saturating truncation operations
-            Op::I32TruncSatF32S => {
-                let value = frame.stack.pop_f32()?;
-                let result = if value.is_nan() {
-                    0
-                } else if value <= (i32::MIN as f32) {
-                    i32::MIN
-                } else if value >= (i32::MAX as f32) {
-                    i32::MAX
-                } else {
-                    value as i32
-                };
-                frame.stack.push_i32(result);
-            }
-            Op::I32TruncSatF32U => {
-                let value = frame.stack.pop_f32()?;
-                let result = if value.is_nan() || value < 0.0 {
-                    0
-                } else if value >= (u32::MAX as f32) {
-                    u32::MAX
-                } else {
-                    value as u32
-                };
-                frame.stack.push_u32(result);
-            }
-            Op::I32TruncSatF64S => {
-                let value = frame.stack.pop_f64()?;
-                let result = if value.is_nan() {
-                    0
-                } else if value <= (i32::MIN as f64) {
-                    i32::MIN
-                } else if value >= (i32::MAX as f64) {
-                    i32::MAX
-                } else {
-                    value as i32
-                };
-                frame.stack.push_i32(result);
-            }
-            Op::I32TruncSatF64U => {
-                let value = frame.stack.pop_f64()?;
-                let result = if value.is_nan() || value < 0.0 {
-                    0
-                } else if value >= (u32::MAX as f64) {
-                    u32::MAX
-                } else {
-                    value as u32
-                };
-                frame.stack.push_u32(result);
-            }
-            Op::I64TruncSatF32S => {
-                let value = frame.stack.pop_f32()?;
-                let result = if value.is_nan() {
-                    0
-                } else if value <= (i64::MIN as f32) {
-                    i64::MIN
-                } else if value >= (i64::MAX as f32) {
-                    i64::MAX
-                } else {
-                    value as i64
-                };
-                frame.stack.push_i64(result);
-            }
-            Op::I64TruncSatF32U => {
-                let value = frame.stack.pop_f32()?;
-                let result = if value.is_nan() || value < 0.0 {
-                    0
-                } else if value >= (u64::MAX as f32) {
-                    u64::MAX
-                } else {
-                    value as u64
-                };
-                frame.stack.push_u64(result);
-            }
-            Op::I64TruncSatF64S => {
-                let value = frame.stack.pop_f64()?;
-                let result = if value.is_nan() {
-                    0
-                } else if value <= (i64::MIN as f64) {
-                    i64::MIN
-                } else if value >= (i64::MAX as f64) {
-                    i64::MAX
-                } else {
-                    value as i64
-                };
-                frame.stack.push_i64(result);
-            }
-            Op::I64TruncSatF64U => {
-                let value = frame.stack.pop_f64()?;
-                let result = if value.is_nan() || value < 0.0 {
-                    0
-                } else if value >= (u64::MAX as f64) {
-                    u64::MAX
-                } else {
-                    value as u64
-                };
-                frame.stack.push_u64(result);
-            }
-
-            Op::F32ConvertI32S => {
-                let value = frame.stack.pop_i32()?;
-                frame.stack.push_f32(value as f32);
-            }
-            Op::F32ConvertI32U => {
-                let value = frame.stack.pop_u32()?;
-                frame.stack.push_f32(value as f32);
-            }
-            Op::F32ConvertI64S => {
-                let value = frame.stack.pop_i64()?;
-                frame.stack.push_f32(value as f32);
-            }
-            Op::F32ConvertI64U => {
-                let value = frame.stack.pop_u64()?;
-                frame.stack.push_f32(value as f32);
-            }
-            Op::F32DemoteF64 => {
-                let value = frame.stack.pop_f64()?;
-                frame.stack.push_f32(value as f32);
-            }
-            Op::F64ConvertI32S => {
-                let value = frame.stack.pop_i32()?;
-                frame.stack.push_f64(value as f64);
-            }
-            Op::F64ConvertI32U => {
-                let value = frame.stack.pop_u32()?;
-                frame.stack.push_f64(value as f64);
-            }
-            Op::F64ConvertI64S => {
-                let value = frame.stack.pop_i64()?;
-                frame.stack.push_f64(value as f64);
-            }
-            Op::F64ConvertI64U => {
-                let value = frame.stack.pop_u64()?;
-                frame.stack.push_f64(value as f64);
-            }
-            Op::F64PromoteF32 => {
-                let value = frame.stack.pop_f32()?;
-                frame.stack.push_f64(value as f64);
-            }
-            Op::I32ReinterpretF32 => {
-                let value = frame.stack.pop_f32()?;
-                frame.stack.push_u32(value.to_bits());
-            }
-            Op::I64ReinterpretF64 => {
-                let value = frame.stack.pop_f64()?;
-                frame.stack.push_u64(value.to_bits());
-            }
-            Op::F32ReinterpretI32 => {
-                let value = frame.stack.pop_u32()?;
-                frame.stack.push_f32(f32::from_bits(value));
-            }
-            Op::F64ReinterpretI64 => {
-                let value = frame.stack.pop_u64()?;
-                frame.stack.push_f64(f64::from_bits(value));
-            }
-            Op::I32Extend8S => {
-                let value = frame.stack.pop_i32()?;
-                frame.stack.push_i32(value as i8 as i32);
-            }
-            Op::I32Extend16S => {
-                let value = frame.stack.pop_i32()?;
-                frame.stack.push_i32(value as i16 as i32);
-            }
-            Op::I64Extend8S => {
-                let value = frame.stack.pop_i64()?;
-                frame.stack.push_i64(value as i8 as i64);
-            }
-            Op::I64Extend16S => {
-                let value = frame.stack.pop_i64()?;
-                frame.stack.push_i64(value as i16 as i64);
-            }
-            Op::I64Extend32S => {
-                let value = frame.stack.pop_i64()?;
-                frame.stack.push_i64(value as i32 as i64);
-            }
-
-            // Reference types operations
-            Op::RefNull(ref_type) => match ref_type {
-                crate::ValueType::FuncRef | crate::ValueType::ExternRef => {
-                    frame.stack.push_ref(None);
-                }
-                _ => return Err(Fault::InvalidRefType),
-            },
-            Op::RefFunc(func_index) => {
-                // TODO: Validate func_index exists in the module
-                frame.stack.push_ref(Some(func_index));
-            }
-            Op::RefIsNull => {
-                let ref_val = frame.stack.pop_ref()?;
-                let is_null = if ref_val.is_none() { 1 } else { 0 };
-                frame.stack.push_i32(is_null);
-            }
-            Op::RefAsNonNull => {
-                let ref_val = frame.stack.pop_ref()?;
-                match ref_val {
-                    Some(val) => frame.stack.push_ref(Some(val)),
-                    None => return Err(Fault::NullReference),
-                }
-            }
-            Op::RefEq => {
-                let ref2 = frame.stack.pop_ref()?;
-                let ref1 = frame.stack.pop_ref()?;
-                let are_equal = if ref1 == ref2 { 1 } else { 0 };
-                frame.stack.push_i32(are_equal);
-            }
             Op::SelectT(ref _types) => {
                 // For now, implement same as regular select
                 // TODO: Add type validation
                 let condition = frame.stack.pop_i32()?;
-                let val2 = frame.stack.pop_u64()?;
-                let val1 = frame.stack.pop_u64()?;
+                let val2 = frame.stack.pop_slot()?;
+                let val1 = frame.stack.pop_slot()?;
                 if condition != 0 {
-                    frame.stack.push_u64(val1);
+                    frame.stack.push_slot(val1);
                 } else {
-                    frame.stack.push_u64(val2);
+                    frame.stack.push_slot(val2);
                 }
             }
         }
+        if let Some(ty) = float_result {
+            floats.apply(&mut frame.stack, ty)?;
+        }
+        if let Some(write) = watched {
+            return Ok(Continuation::Suspend(SuspendReason::Watchpoint(WatchHit {
+                write,
+                funcidx: frame.funcidx,
+                pc,
+            })));
+        }
+    }
+}
+
+/// Loads and stores to linear memory.
+#[cfg_attr(feature = "monolithic-dispatch", inline(always))]
+#[cfg_attr(not(feature = "monolithic-dispatch"), inline(never))]
+fn memory_op<M: Memory, S: SlotStorage>(
+    op: &Op,
+    frame: &mut Frame<S>,
+    memory: &mut M,
+    alignment_hook: &mut AlignmentHook,
+) -> Result<(), Fault> {
+    match *op {
+        Op::LoadI32(addr) => {
+            let addr = access_addr(frame, &addr, memory.size(), alignment_hook)?;
+            let value = memory.get_i32(addr)?;
+            frame.stack.push_i32(value);
+        }
+        Op::LoadI64(addr) => {
+            let addr = access_addr(frame, &addr, memory.size(), alignment_hook)?;
+            let value = memory.get_i64(addr)?;
+            frame.stack.push_i64(value);
+        }
+        Op::LoadF32(addr) => {
+            let addr = access_addr(frame, &addr, memory.size(), alignment_hook)?;
+            let value = memory.get_f32(addr)?;
+            frame.stack.push_f32(value);
+        }
+        Op::LoadF64(addr) => {
+            let addr = access_addr(frame, &addr, memory.size(), alignment_hook)?;
+            let value = memory.get_f64(addr)?;
+            frame.stack.push_f64(value);
+        }
+        // Extending load, signed
+        Op::Load8SE(addr) => {
+            let addr = access_addr(frame, &addr, memory.size(), alignment_hook)?;
+            let value = memory.get_u8(addr)? as i8 as i32;
+            frame.stack.push_i32(value);
+        }
+        Op::Load16Se(addr) => {
+            let addr = access_addr(frame, &addr, memory.size(), alignment_hook)?;
+            let value = memory.get_u16(addr)? as i16 as i32;
+            frame.stack.push_i32(value);
+        }
+        Op::Load8I64Se(addr) => {
+            let addr = access_addr(frame, &addr, memory.size(), alignment_hook)?;
+            let value = memory.get_u8(addr)? as i8 as i64;
+            frame.stack.push_i64(value);
+        }
+        Op::Load16I64Se(addr) => {
+            let addr = access_addr(frame, &addr, memory.size(), alignment_hook)?;
+            let value = memory.get_u16(addr)? as i16 as i64;
+            frame.stack.push_i64(value);
+        }
+        Op::Load32I64Se(addr) => {
+            let addr = access_addr(frame, &addr, memory.size(), alignment_hook)?;
+            let value = memory.get_u32(addr)? as i32 as i64;
+            frame.stack.push_i64(value);
+        }
+        // Extending load, unsigned
+        Op::Load8Ze(addr) => {
+            let addr = access_addr(frame, &addr, memory.size(), alignment_hook)?;
+            let value = memory.get_u8(addr)? as u32;
+            frame.stack.push_u32(value);
+        }
+        Op::Load16Ze(addr) => {
+            let addr = access_addr(frame, &addr, memory.size(), alignment_hook)?;
+            let value = memory.get_u16(addr)? as u32;
+            frame.stack.push_u32(value);
+        }
+        Op::Load8I64Ze(addr) => {
+            let addr = access_addr(frame, &addr, memory.size(), alignment_hook)?;
+            let value = memory.get_u8(addr)? as u64;
+            frame.stack.push_u64(value);
+        }
+        Op::Load16I64Ze(addr) => {
+            let addr = access_addr(frame, &addr, memory.size(), alignment_hook)?;
+            let value = memory.get_u16(addr)? as u64;
+            frame.stack.push_u64(value);
+        }
+        Op::Load32I64Ze(addr) => {
+            let addr = access_addr(frame, &addr, memory.size(), alignment_hook)?;
+            let value = memory.get_u32(addr)? as u64;
+            frame.stack.push_u64(value);
+        }
+        Op::StoreI32(addr) => {
+            let value = frame.stack.pop_i32()?;
+            let addr = access_addr(frame, &addr, memory.size(), alignment_hook)?;
+            memory.set_i32(addr, value)?;
+        }
+        Op::StoreI64(addr) => {
+            let value = frame.stack.pop_i64()?;
+            let addr = access_addr(frame, &addr, memory.size(), alignment_hook)?;
+            memory.set_i64(addr, value)?;
+        }
+        Op::StoreF32(addr) => {
+            let value = frame.stack.pop_f32()?;
+            let addr = access_addr(frame, &addr, memory.size(), alignment_hook)?;
+            memory.set_f32(addr, value)?;
+        }
+        Op::StoreF64(addr) => {
+            let value = frame.stack.pop_f64()?;
+            let addr = access_addr(frame, &addr, memory.size(), alignment_hook)?;
+            memory.set_f64(addr, value)?;
+        }
+        // Silently narrow the width of the value
+        Op::Store8_32(addr) => {
+            let value = frame.stack.pop_i32()? as u8;
+            let addr = access_addr(frame, &addr, memory.size(), alignment_hook)?;
+            memory.set_u8(addr, value)?;
+        }
+        Op::Store16_32(addr) => {
+            let value = frame.stack.pop_i32()? as u16;
+            let addr = access_addr(frame, &addr, memory.size(), alignment_hook)?;
+            memory.set_u16(addr, value)?;
+        }
+        Op::Store8_64(addr) => {
+            let value = frame.stack.pop_i64()? as u8;
+            let addr = access_addr(frame, &addr, memory.size(), alignment_hook)?;
+            memory.set_u8(addr, value)?;
+        }
+        Op::Store16_64(addr) => {
+            let value = frame.stack.pop_i64()? as u16;
+            let addr = access_addr(frame, &addr, memory.size(), alignment_hook)?;
+            memory.set_u16(addr, value)?;
+        }
+        Op::Store32_64(addr) => {
+            let value = frame.stack.pop_i64()? as u32;
+            let addr = access_addr(frame, &addr, memory.size(), alignment_hook)?;
+            memory.set_u32(addr, value)?;
+        }
+        _ => unreachable!("{op:?} isn't handled here"),
+    }
+    Ok(())
+}
+
+/// Grow memory by `delta` pages, returning the old size in pages, or -1 if it can't grow.
+fn memory_grow<M: Memory>(
+    memory: &mut M,
+    delta: u32,
+    grow_hook: &mut Option<MemoryGrowHook>,
+) -> Result<i32, Fault> {
+    let old_page_count = memory.pages();
+    let new_page_count = match old_page_count.checked_add(delta as usize) {
+        Some(pages) if pages <= MAX_WASM_PAGES => pages,
+        _ => return Ok(-1),
+    };
+    if let Some(hook) = grow_hook {
+        match hook(old_page_count, new_page_count) {
+            GrowDecision::Allow => {}
+            GrowDecision::Deny => return Ok(-1),
+            GrowDecision::Trap => return Err(Fault::CannotGrowMemory),
+        }
     }
+    match memory.grow(bytes_for_pages(new_page_count)) {
+        Ok(_) => Ok(old_page_count as i32),
+        Err(_) => Ok(-1),
+    }
+}
+
+/// Pop the base address for an access and produce its effective address.
+pub(crate) fn adjust_memarg<S: SlotStorage>(
+    stack: &mut Stack<S>,
+    memarg: &MemArg,
+    memory_size: usize,
+) -> Result<usize, Fault> {
+    let base_addr = stack.pop_u32()?;
+    memarg_addr(base_addr, memarg, memory_size)
+}
+
+/// Pop the base address for an access and produce its effective address, reporting it if it
+/// breaks the alignment the instruction promised.
+#[inline]
+#[cfg_attr(not(feature = "alignment-diagnostics"), allow(unused_variables))]
+fn access_addr<S: SlotStorage>(
+    frame: &mut Frame<S>,
+    memarg: &MemArg,
+    memory_size: usize,
+    alignment_hook: &mut AlignmentHook,
+) -> Result<usize, Fault> {
+    let addr = adjust_memarg(&mut frame.stack, memarg, memory_size)?;
+    #[cfg(feature = "alignment-diagnostics")]
+    check_alignment(frame, addr, memarg, alignment_hook);
+    Ok(addr)
 }
 
-fn adjust_memarg(stack: &mut Stack, memarg: &MemArg) -> Result<usize, Fault> {
-    let base_addr = stack.pop_i32()? as usize;
+/// Report an access whose address isn't a multiple of its alignment hint. The access itself still
+/// goes ahead: alignment never changes what an instruction does.
+#[cfg(feature = "alignment-diagnostics")]
+fn check_alignment<S: SlotStorage>(
+    frame: &Frame<S>,
+    address: usize,
+    memarg: &MemArg,
+    hook: &mut AlignmentHook,
+) {
+    let Some(hook) = hook else {
+        return;
+    };
+    let align = 1u64 << memarg.align.min(63);
+    if !(address as u64).is_multiple_of(align) {
+        hook(MisalignedAccess {
+            address,
+            align,
+            funcidx: frame.funcidx,
+            pc: frame.pc - 1,
+        });
+    }
+}
 
-    // Note: Alignment is only a "hint", we could issue a warning here, but that would just slow
-    //  down the interpreter.
+/// The unsigned base plus the offset, which can need 33 bits, so is worked out in u64. Anything
+/// starting at or past the end of memory traps here, before being narrowed to a usize, which
+/// leaves the accessors only the access's width to check.
+#[inline]
+fn memarg_addr(base_addr: u32, memarg: &MemArg, memory_size: usize) -> Result<usize, Fault> {
+    // Note: Alignment is only a "hint". Checking it would slow down every access, so it's only
+    //  looked at by `access_addr` with the `alignment-diagnostics` feature.
 
-    Ok(memarg.offset + base_addr)
+    let addr = base_addr as u64 + memarg.offset as u64;
+    if addr >= memory_size as u64 {
+        return Err(Fault::MemoryOutOfBounds);
+    }
+    Ok(addr as usize)
 }
 
 #[derive(Debug, Clone)]
@@ -1590,6 +1391,9 @@ pub enum Value {
     V128(u128),
     FuncRef(Option<u32>),
     ExternRef(Option<u32>),
+    /// A reference into the GC heap.
+    #[cfg(feature = "gc")]
+    AnyRef(Option<u32>),
     Unit,
 }
 
@@ -1605,63 +1409,73 @@ impl Value {
             Value::V128(_) => ValueType::V128,
             Value::FuncRef(_) => ValueType::FuncRef,
             Value::ExternRef(_) => ValueType::ExternRef,
+            #[cfg(feature = "gc")]
+            Value::AnyRef(_) => ValueType::AnyRef,
             Value::Unit => ValueType::Unit,
         }
     }
 
-    pub fn pop_from(ty: ValueType, stack: &mut Stack) -> Result<Self, Fault> {
+    /// The zero value for a type, as locals and table slots are initialized to.
+    pub(crate) fn default_for(ty: ValueType) -> Self {
+        match ty {
+            ValueType::I32 => Value::I32(0),
+            ValueType::I64 => Value::I64(0),
+            ValueType::F32 => Value::F32(0.0),
+            ValueType::F64 => Value::F64(0.0),
+            ValueType::Unit => Value::Unit,
+            ValueType::V128 => Value::V128(0),
+            ValueType::FuncRef => Value::FuncRef(None),
+            ValueType::ExternRef => Value::ExternRef(None),
+            #[cfg(feature = "gc")]
+            ValueType::AnyRef => Value::AnyRef(None),
+        }
+    }
+
+    pub fn pop_from<S: SlotStorage>(ty: ValueType, stack: &mut Stack<S>) -> Result<Self, Fault> {
         Ok(match ty {
             ValueType::Unit => {
-                stack.pop_u64()?;
+                stack.pop_unit()?;
                 Value::Unit
             }
             ValueType::I32 => Value::I32(stack.pop_i32()?),
             ValueType::I64 => Value::I64(stack.pop_i64()?),
             ValueType::F32 => Value::F32(stack.pop_f32()?),
             ValueType::F64 => Value::F64(stack.pop_f64()?),
-            ValueType::V128 => {
-                let (l, r) = (stack.pop_u64()?, stack.pop_u64()?);
-                Value::V128((r as u128) << 64 | l as u128)
-            }
+            ValueType::V128 => Value::V128(stack.pop_v128()?),
             ValueType::FuncRef => Value::FuncRef(stack.pop_ref()?),
             ValueType::ExternRef => Value::ExternRef(stack.pop_ref()?),
+            #[cfg(feature = "gc")]
+            ValueType::AnyRef => Value::AnyRef(stack.pop_ref()?),
         })
     }
 
-    pub fn top_of(ty: ValueType, stack: &mut Stack) -> Result<Self, Fault> {
+    pub fn top_of<S: SlotStorage>(ty: ValueType, stack: &mut Stack<S>) -> Result<Self, Fault> {
         Ok(match ty {
-            ValueType::Unit => {
-                stack.pop_u64()?;
-                Value::Unit
-            }
+            ValueType::Unit => Value::Unit,
             ValueType::I32 => Value::I32(stack.top_i32()?),
             ValueType::I64 => Value::I64(stack.top_i64()?),
             ValueType::F32 => Value::F32(stack.top_f32()?),
             ValueType::F64 => Value::F64(stack.top_f64()?),
-            ValueType::V128 => {
-                let (l, r) = (stack.top_u64()?, stack.top_u64()?);
-                Value::V128((r as u128) << 64 | l as u128)
-            }
-            ValueType::FuncRef => Value::FuncRef(Some(stack.top_u32()?)),
-            ValueType::ExternRef => Value::ExternRef(Some(stack.top_u32()?)),
+            ValueType::V128 => Value::V128(stack.top_v128()?),
+            ValueType::FuncRef => Value::FuncRef(stack.top_ref()?),
+            ValueType::ExternRef => Value::ExternRef(stack.top_ref()?),
+            #[cfg(feature = "gc")]
+            ValueType::AnyRef => Value::AnyRef(stack.top_ref()?),
         })
     }
 
-    pub fn push_to(&self, stack: &mut Stack) {
+    pub fn push_to<S: SlotStorage>(&self, stack: &mut Stack<S>) {
         match self {
             Value::I32(v) => stack.push_i32(*v),
             Value::I64(v) => stack.push_i64(*v),
             Value::F32(v) => stack.push_f32(*v),
             Value::F64(v) => stack.push_f64(*v),
-            Value::V128(v) => {
-                stack.push_u64(*v as u64);
-                stack.push_u64((*v >> 64) as u64);
-            }
+            Value::V128(v) => stack.push_v128(*v),
             Value::FuncRef(v) => stack.push_ref(*v),
             Value::ExternRef(v) => stack.push_ref(*v),
-            Value::Unit => {
-                stack.push_u64(0);
-            }
+            #[cfg(feature = "gc")]
+            Value::AnyRef(v) => stack.push_ref(*v),
+            Value::Unit => stack.push_unit(),
         }
     }
 
@@ -1688,49 +1502,87 @@ impl Value {
 }
 
 // For executing little fragments of code e.g. globals or data segments
-pub(crate) fn exec_fragment(program: &[u8], return_type: ValueType) -> Result<Value, Fault> {
-    let const_program = decode(program).unwrap();
+pub(crate) fn exec_fragment(
+    program: &[u8],
+    return_type: ValueType,
+    globals: &mut [GlobalVar],
+    fuel: &mut Fuel,
+) -> Result<Value, LinkError> {
+    let const_program = decode(program).map_err(LinkError::DecodeError)?;
     let return_types = vec![return_type];
     let mut global_exec_frame = Frame {
-        locals: vec![Value::Unit; 0],
-        program: const_program,
+        locals: Stack::new(),
+        program: Arc::new(const_program),
         stack: Stack::new(),
         pc: 0,
         control_stack: vec![],
         return_types,
+        funcidx: 0,
     };
-    // This little fragment, it doesn't get much memory and doesn't get *any* globals.
+    // This little fragment, it doesn't get much memory, and only gets the globals it's allowed to
+    // see.
     // TODO: I don't actually know what a reasonable amount of memory is, so we'll just default
     //   to one page.
     let mut const_prg_memory_vec = vec![0; WASM_PAGE_SIZE];
     let mut const_prg_memory = SliceMemory::new(&mut const_prg_memory_vec);
-    let mut const_prg_globals = vec![];
 
     // In this case the expectation is we run out of instructions, and the stack contains the return
     // value.
     let mut const_prg_tables = vec![];
+    // Held to whatever's left of the instantiation's fuel, if that's less. `execute` stops short
+    // of the op which reaches its limit, hence the one over.
+    let fuel_limited = fuel.remaining().is_some_and(|r| r < EXPR_TICK_LIMIT);
+    let max_ticks = match fuel.remaining() {
+        Some(remaining) if fuel_limited => remaining + 1,
+        _ => EXPR_TICK_LIMIT,
+    };
+    let mut ticks = 0;
     let result = execute(
         &mut global_exec_frame,
         &mut const_prg_memory,
-        &mut const_prg_globals,
+        globals,
         &mut const_prg_tables,
-        EXPR_TICK_LIMIT,
+        &mut ticks,
+        max_ticks,
+        &[],
         &[],
         &[],
-    )?;
+        &mut GcStore::default(),
+        &mut ExecutionStats::default(),
+        &mut None,
+        &mut [],
+        FloatPolicy::default(),
+        &mut AlignmentHook::default(),
+        &Watchpoints::default(),
+        &mut None,
+        &mut None,
+    )
+    .map_err(|fault| match fault {
+        Fault::OutOfTicks if fuel_limited => LinkError::InstantiationBudgetExceeded,
+        fault => LinkError::ActiveExpressionError(fault),
+    })?;
+    fuel.spend(ticks)?;
     // Must be `ProgramEnd`, or there's a bug, and that's UnexpectedResult
     match result {
         Continuation::ProgramEnd => {}
-        _ => return Err(Fault::UnexpectedResult(result)),
+        _ => {
+            return Err(LinkError::ActiveExpressionError(Fault::UnexpectedResult(
+                result,
+            )))
+        }
     }
 
     Value::pop_from(return_type, &mut global_exec_frame.stack)
+        .map_err(LinkError::ActiveExpressionError)
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[non_exhaustive]
 pub enum ExecError {
     LinkageError(LinkError),
     ExecutionFault(Fault),
+    /// Not a failure: the run stopped where it can be resumed. See `SuspendReason`.
+    Suspended(SuspendReason),
 }
 
 impl Display for ExecError {
@@ -1738,6 +1590,7 @@ impl Display for ExecError {
         match self {
             ExecError::LinkageError(e) => write!(f, "Linkage error: {e}"),
             ExecError::ExecutionFault(e) => write!(f, "Execution fault: {e}"),
+            ExecError::Suspended(reason) => write!(f, "Suspended: {reason:?}"),
         }
     }
 }
@@ -1745,38 +1598,256 @@ impl Display for ExecError {
 impl Error for ExecError {}
 
 /// A context for executing functions in an Instance derived from a module.
-pub struct Execution<M>
+///
+/// An `Execution` owns everything it runs against, so it is `Send` whenever its memory is. Between
+/// `run`s -- freshly prepared, finished, or stopped on a fault -- it can be handed to another thread
+/// and carried on there; it is not `Sync`, so only one thread drives it at a time.
+///
+/// It also carries whatever the embedder wants host functions to get at, as `T`: a database
+/// handle, the request being served. Host functions given a `Caller` reach it, along with the
+/// memory, through `Caller::data`; see `Linker::func_with_data`. Their `T` is only checked
+/// against this one when they're called, not when they're linked.
+///
+/// Its value stacks and locals keep their slots in `S`; see `SlotStorage`.
+pub struct Execution<M, T = (), S = HeapSlots>
 where
     M: Memory,
+    S: SlotStorage,
 {
     /// The linked module.
     // TODO: in the future this could be multiple instances?, one per module.
     instance: Instance,
     /// The stack of frames for the current execution.
-    frame_stack: Vec<Frame>,
+    frame_stack: Vec<Frame<S>>,
+    /// Value stack and local slots held by every frame but the top one, which can't change
+    /// until the frames above them return. Kept for checking `StackLimits::max_slots`.
+    suspended_slots: usize,
     /// The memory for the current execution.
     memory: M,
     /// Final result of execution when all frames have executed.
     result: Option<Vec<Value>>,
+    /// Buffers from finished frames, reused for new calls.
+    frame_pool: FramePool<S>,
+    /// Host values behind the externrefs handed to the guest.
+    externs: ExternTable,
+    /// Set when execution stops on an error. The frames are left exactly as they were at that
+    /// point, for inspection, and nothing more can run until `reset`.
+    poisoned: Option<ExecError>,
+    /// Counters for the current or last run.
+    stats: ExecutionStats,
+    /// Consulted before memory is grown.
+    grow_hook: Option<MemoryGrowHook>,
+    /// Consulted before calls to guest functions, by function index.
+    interceptors: HashMap<u32, CallInterceptor>,
+    /// Consulted when a guest function faults.
+    trap_policy: Option<TrapPolicy>,
+    /// How much float results may vary between hosts, and what becomes of subnormals.
+    floats: FloatPolicy,
+    /// Told about misaligned memory accesses.
+    alignment_hook: AlignmentHook,
+    /// Locations whose writes suspend execution.
+    watchpoints: Watchpoints,
+    /// The trace being recorded or checked, if any.
+    tracer: Option<Tracer>,
+    /// Op budgets for single calls to particular functions, by function index.
+    budgets: HashMap<u32, u64>,
+    /// The budgets of calls in progress, innermost last: the depth of the call's frame, its
+    /// function, and the op count it has to finish by.
+    active_budgets: Vec<(usize, u32, u64)>,
+    /// Ops run so far, over every run.
+    ops_run: u64,
+    /// Told about loops and calls going further than expected.
+    anomalies: Option<AnomalyMonitor>,
+    /// How far the stacks may grow.
+    stack_limits: StackLimits,
+    /// Why the last `run` suspended, until the next one starts.
+    suspended: Option<SuspendReason>,
+    /// How many calls back into the guest from host functions are in progress.
+    reentry_depth: usize,
+    /// The most calls back into the guest which may be in progress at once.
+    max_reentry: usize,
+    /// The embedder's own state, for host functions.
+    data: T,
+    /// Set while `step` is running: the op count to stop at.
+    step_deadline: Option<u64>,
+    /// Checkpoints for `step_back`, if they're being recorded.
+    history: Option<History<M, S>>,
+}
+
+impl Execution<VectorMemory> {
+    /// Carry on an execution from an image written by `hibernate`, with the binary of the
+    /// module it was running and a linker to provide its imports again. Whatever it was doing
+    /// resumes with the next `run`.
+    pub fn thaw(
+        module_bytes: &[u8],
+        linker: &Linker,
+        mut reader: impl Read,
+    ) -> Result<Self, ThawError> {
+        let mut image = vec![];
+        reader.read_to_end(&mut image).map_err(ThawError::Io)?;
+        let thawed = hibernate::read_image(module_bytes, linker, &image)?;
+        let mut execution = Execution::new(thawed.instance, thawed.memory);
+        execution.frame_stack = thawed.frames;
+        execution.count_suspended_slots();
+        execution.result = thawed.result;
+        Ok(execution)
+    }
 }
 
+// Keep the engine free of anything tied to the thread that created it.
+const _: fn() = || {
+    fn assert_send<T: Send>() {}
+    fn assert_sync<T: Sync>() {}
+    assert_send::<Execution<crate::memory::VectorMemory>>();
+    assert_send::<Instance>();
+    assert_send::<Frame>();
+    assert_send::<ExecError>();
+    assert_sync::<crate::Module>();
+};
+
 impl<M> Execution<M>
 where
     M: Memory,
 {
     pub fn new(linkage: Instance, memory: M) -> Self {
+        Execution::with_data(linkage, memory, ())
+    }
+}
+
+impl<M, T> Execution<M, T>
+where
+    M: Memory,
+    T: 'static,
+{
+    /// As `new`, carrying `data` for host functions to get at.
+    pub fn with_data(linkage: Instance, memory: M, data: T) -> Self {
+        Execution::with_storage(linkage, memory, data)
+    }
+}
+
+impl<M, T, S> Execution<M, T, S>
+where
+    M: Memory,
+    T: 'static,
+    S: SlotStorage,
+{
+    /// As `with_data`, keeping stack slots in `S`, which is named along with the rest of the
+    /// type: `Execution::<_, (), FixedSlots<256>>::with_storage(instance, memory, ())`.
+    pub fn with_storage(linkage: Instance, memory: M, data: T) -> Self {
         Execution {
             instance: linkage,
             frame_stack: vec![],
+            suspended_slots: 0,
             memory,
             result: None,
+            frame_pool: FramePool::default(),
+            externs: ExternTable::new(),
+            poisoned: None,
+            stats: ExecutionStats::default(),
+            grow_hook: None,
+            interceptors: HashMap::new(),
+            trap_policy: None,
+            floats: FloatPolicy::default(),
+            alignment_hook: AlignmentHook::default(),
+            watchpoints: Watchpoints::default(),
+            tracer: None,
+            budgets: HashMap::new(),
+            active_budgets: vec![],
+            ops_run: 0,
+            anomalies: None,
+            stack_limits: StackLimits::default(),
+            suspended: None,
+            reentry_depth: 0,
+            max_reentry: DEFAULT_MAX_REENTRY,
+            data,
+            step_deadline: None,
+            history: None,
         }
     }
 
+    /// The embedder's state this execution carries.
+    pub fn data(&self) -> &T {
+        &self.data
+    }
+
+    pub fn data_mut(&mut self) -> &mut T {
+        &mut self.data
+    }
+
     pub fn instance(&self) -> &Instance {
         &self.instance
     }
 
+    pub fn instance_mut(&mut self) -> &mut Instance {
+        &mut self.instance
+    }
+
+    /// The export `name`, as `Instance::export` has it, but sharing the memory this execution
+    /// runs against rather than the instance's copy.
+    pub fn export(&mut self, name: &str) -> Option<Extern> {
+        let export = self.instance.module.export(name)?;
+        if export.kind == ImportExportKind::Memory && export.index == 0 {
+            let memory = self.instance.links.share_memory(&self.memory);
+            return Some(Extern::SharedMemory(memory));
+        }
+        self.instance.export(name)
+    }
+
+    /// As `export`, but functions the module defines resolve too, to ones which run them on
+    /// `execution`. They take a `Caller`, so anything shared between the instances is brought
+    /// up to date on the way in and out; see [`crate::shared`].
+    ///
+    /// A call while `execution` is busy, either suspended or running the call which led to this
+    /// one, faults with `Fault::ReentrantHostCall` or `Fault::Busy`. A call which faults hands
+    /// the fault to its caller, and leaves `execution` reset, ready for the next.
+    pub fn export_shared(execution: &Arc<Mutex<Self>>, name: &str) -> Option<Extern>
+    where
+        M: Send + 'static,
+        T: Send + 'static,
+    {
+        let mut this = execution.lock().ok()?;
+        let func = this
+            .instance
+            .func_by_name(name)
+            .filter(|func| !this.instance.module.is_imported_func(func.index()));
+        let Some(func) = func else {
+            return this.export(name);
+        };
+        let callee = Arc::clone(execution);
+        let func_type = func.func_type().clone();
+        let call = move |_: &mut Caller, args: &[Value]| {
+            let Ok(mut callee) = callee.try_lock() else {
+                return Err(Fault::ReentrantHostCall);
+            };
+            callee.invoke_func(&func, args).map_err(|e| {
+                if !matches!(e, ExecError::ExecutionFault(Fault::Busy | Fault::Poisoned)) {
+                    callee.reset();
+                }
+                nested_fault(e)
+            })
+        };
+        Some(Extern::TypedFunc {
+            func_type,
+            func: Arc::new(call),
+        })
+    }
+
+    /// Bring this execution's copies of whatever it shares with other instances up to date.
+    fn pull_links(&mut self) {
+        if !self.instance.links.is_empty() {
+            let instance = &mut self.instance;
+            instance.links.pull(&mut instance.globals, &mut self.memory);
+        }
+    }
+
+    /// Hand back what this execution has written to whatever it shares with other instances.
+    fn push_links(&mut self) {
+        if !self.instance.links.is_empty() {
+            let instance = &mut self.instance;
+            instance.links.push(&instance.globals, &self.memory);
+        }
+    }
+
     pub fn into_instance(self) -> Instance {
         self.instance
     }
@@ -1793,6 +1864,22 @@ where
         instance
     }
 
+    pub fn memory(&self) -> &M {
+        &self.memory
+    }
+
+    pub fn memory_mut(&mut self) -> &mut M {
+        &mut self.memory
+    }
+
+    /// The pages stored to, by the guest or the host, since the last call, clearing them, or
+    /// `None` if the memory doesn't track them. See [`crate::DirtyTrackingMemory`].
+    pub fn take_dirty_pages(&mut self) -> Option<Vec<usize>> {
+        let pages = self.memory.dirty_pages()?;
+        self.memory.clear_dirty_pages();
+        Some(pages)
+    }
+
     pub fn result(&self) -> Option<&[Value]> {
         self.result.as_deref()
     }
@@ -1801,99 +1888,860 @@ where
         self.frame_stack.len()
     }
 
-    pub fn prepare(&mut self, funcidx: u32, args: &[Value]) -> Result<(), ExecError> {
-        let frame = self
-            .instance
-            .frame_for_funcidx(funcidx, args)
-            .map_err(ExecError::LinkageError)?;
+    pub fn externs(&self) -> &ExternTable {
+        &self.externs
+    }
 
-        // TODO: Need to fix the label mismatch properly
+    pub fn externs_mut(&mut self) -> &mut ExternTable {
+        &mut self.externs
+    }
 
-        self.frame_stack.push(frame);
-        Ok(())
+    /// Have `hook` decide whether each `memory.grow` goes ahead, in place of any previous hook.
+    pub fn on_memory_grow(
+        &mut self,
+        hook: impl FnMut(usize, usize) -> GrowDecision + Send + 'static,
+    ) {
+        self.grow_hook = Some(Box::new(hook));
     }
 
-    pub fn run(&mut self) -> Result<(), ExecError> {
-        loop {
-            let top_frame = self.frame_stack.last_mut().unwrap();
-            let result = execute(
-                top_frame,
-                &mut self.memory,
-                &mut self.instance.globals,
-                &mut self.instance.tables,
-                1000000, // Increased for memory checking loops
-                &self.instance.module.types,
-                &self.instance.module.functions,
-            );
-            match result {
-                Ok(Continuation::ProgramEnd) | Ok(Continuation::DoneReturn) => {
-                    // Stack is LIFO - pop values and assign to correct indices
-                    let mut return_values =
-                        vec![(ValueType::Unit, Value::Unit); top_frame.return_types.len()];
-                    for (i, rt) in top_frame.return_types.iter().enumerate().rev() {
-                        let value = Value::pop_from(*rt, &mut top_frame.stack)
-                            .map_err(ExecError::ExecutionFault)?;
-                        return_values[i] = (*rt, value);
-                    }
-                    let _popped_frame = self.frame_stack.pop();
-                    if let Some(frame) = self.frame_stack.last_mut() {
-                        for (_, v) in return_values {
-                            v.push_to(&mut frame.stack);
-                        }
-                        continue;
-                    } else {
-                        self.result = Some(return_values.into_iter().map(|(_, v)| v).collect());
-                        return Ok(());
-                    }
-                }
-                Ok(Continuation::Call(funcidx)) => {
-                    // Get the function signature to know what arguments to pop from the stack
-                    let current_frame = self.frame_stack.last_mut().unwrap();
+    /// Have `policy` decide what becomes of each fault raised by a guest function's ops, in place
+    /// of any previous policy. Running out of ticks isn't a fault of the function's, and is never
+    /// put to it.
+    pub fn set_trap_policy(
+        &mut self,
+        policy: impl FnMut(&Fault, u32) -> TrapDecision + Send + 'static,
+    ) {
+        self.trap_policy = Some(Box::new(policy));
+    }
 
-                    // Look up the function signature
-                    let func_index = if funcidx < self.instance.module.functions.len() as u32 {
-                        funcidx
-                    } else {
-                        return Err(ExecError::ExecutionFault(Fault::GlobalIndexOutOfBounds));
-                    };
+    /// Let faults stop runs again.
+    pub fn clear_trap_policy(&mut self) {
+        self.trap_policy = None;
+    }
 
-                    let type_idx = self.instance.module.functions[func_index as usize];
-                    let func_type = &self.instance.module.types[type_idx];
+    /// Have `hook` told about every load or store whose address isn't a multiple of the alignment
+    /// it declared, in place of any previous hook.
+    #[cfg(feature = "alignment-diagnostics")]
+    pub fn on_misaligned_access(&mut self, hook: impl FnMut(MisalignedAccess) + Send + 'static) {
+        self.alignment_hook = Some(Box::new(hook));
+    }
 
-                    // Pop arguments from the current frame's stack
-                    let mut args = vec![Value::Unit; func_type.params.len()];
-                    for (i, param_type) in func_type.params.iter().enumerate().rev() {
-                        let value = Value::pop_from(*param_type, &mut current_frame.stack)
-                            .map_err(ExecError::ExecutionFault)?;
-                        args[i] = value;
-                    }
+    /// Suspend execution just after any guest write touching `range` of memory, with
+    /// `ExecError::Suspended(SuspendReason::Watchpoint(..))`. Running again carries on.
+    pub fn watch_memory(&mut self, range: Range<usize>) {
+        self.watchpoints.watch_memory(range);
+    }
 
-                    let frame = self
-                        .instance
-                        .frame_for_funcidx(funcidx, &args)
-                        .map_err(ExecError::LinkageError)?;
-                    self.frame_stack.push(frame);
-                }
+    /// Suspend execution just after any `global.set` of global `index`, as for `watch_memory`.
+    pub fn watch_global(&mut self, index: u32) {
+        self.watchpoints.watch_global(index);
+    }
 
-                Err(fault) => {
-                    // Clean up the current frame when a fault occurs
-                    self.frame_stack.pop();
-                    return Err(ExecError::ExecutionFault(fault));
-                }
-            }
-        }
+    /// Remove every watchpoint.
+    pub fn clear_watchpoints(&mut self) {
+        self.watchpoints.clear();
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use crate::exec::{Execution, Value};
-    use crate::instance::mk_instance;
-    use crate::module::Module;
+    /// Start recording a trace of everything run from here on, at `level`, in place of any trace
+    /// being recorded or checked. See `crate::trace`.
+    pub fn record_trace(&mut self, level: TraceLevel) {
+        self.tracer = Some(Tracer::record(level));
+    }
 
-    #[test]
-    fn load_run_itoa() {
-        let module_data: Vec<u8> = include_bytes!("../tests/itoa.wasm").to_vec();
+    /// Check everything run from here on against `trace`, faulting with `Fault::TraceDivergence`
+    /// at the first event which doesn't match.
+    pub fn replay_trace(&mut self, trace: Trace) {
+        self.tracer = Some(Tracer::replay(trace));
+    }
+
+    /// Stop tracing. Returns the trace if one was being recorded. If one was being checked,
+    /// returns `Fault::TraceDivergence` if the runs since ended before getting through all of it.
+    pub fn finish_trace(&mut self) -> Result<Option<Trace>, Fault> {
+        match self.tracer.take() {
+            Some(Tracer::Cover(coverage)) => {
+                self.tracer = Some(Tracer::Cover(coverage));
+                Ok(None)
+            }
+            Some(tracer) => tracer.finish(),
+            None => Ok(None),
+        }
+    }
+
+    /// Start counting how often each op of each guest function runs, in place of any trace
+    /// being recorded or checked. See `crate::guest_coverage`.
+    pub fn collect_coverage(&mut self) {
+        self.tracer = Some(Tracer::Cover(GuestCoverage::default()));
+    }
+
+    /// Stop counting coverage, handing back what was counted, if it was being counted.
+    pub fn finish_coverage(&mut self) -> Option<GuestCoverage> {
+        match self.tracer.take() {
+            Some(Tracer::Cover(coverage)) => Some(coverage),
+            tracer => {
+                self.tracer = tracer;
+                None
+            }
+        }
+    }
+
+    /// Set how closely runs must agree across hosts. See `Determinism`.
+    pub fn set_determinism(&mut self, determinism: Determinism) {
+        self.floats.determinism = determinism;
+    }
+
+    pub fn determinism(&self) -> Determinism {
+        self.floats.determinism
+    }
+
+    /// Set whether subnormal float results are kept or flushed to zero. See `Subnormals`.
+    pub fn set_subnormals(&mut self, subnormals: Subnormals) {
+        self.floats.subnormals = subnormals;
+    }
+
+    pub fn subnormals(&self) -> Subnormals {
+        self.floats.subnormals
+    }
+
+    /// Have `interceptor` see the arguments of every call the guest makes to `funcidx`, and
+    /// possibly answer the call itself. The function the guest was entered at by `prepare` isn't
+    /// intercepted, only the calls made from there on.
+    pub fn intercept(
+        &mut self,
+        funcidx: u32,
+        interceptor: impl FnMut(&[Value]) -> Intercept + Send + 'static,
+    ) {
+        self.interceptors.insert(funcidx, Box::new(interceptor));
+    }
+
+    /// Stop intercepting calls to `funcidx`. Returns whether they were being intercepted.
+    pub fn remove_interceptor(&mut self, funcidx: u32) -> bool {
+        self.interceptors.remove(&funcidx).is_some()
+    }
+
+    #[cfg(feature = "gc")]
+    pub fn gc_heap(&self) -> &crate::GcHeap {
+        &self.instance.gc.heap
+    }
+
+    /// Free every GC object not reachable from globals, tables, the last result, or a reference
+    /// pinned with `pin_ref`, returning how many were freed. Handles to freed objects are reused,
+    /// so a host holding on to a reference it hasn't pinned can find it pointing somewhere else.
+    /// Values on the stack aren't tagged with their type, so we can't find roots there. That means
+    /// this can only be done between calls, and returns `None` if any frames are live.
+    #[cfg(feature = "gc")]
+    pub fn collect_garbage(&mut self) -> Option<usize> {
+        if !self.frame_stack.is_empty() {
+            return None;
+        }
+        let instance = &mut self.instance;
+        let globals = instance.globals.iter().map(|g| &g.value);
+        let tables = instance.tables.iter().flat_map(|t| t.elements.iter());
+        let result = self.result.iter().flatten();
+        let roots = globals.chain(tables).chain(result).filter_map(|v| match v {
+            Value::AnyRef(handle) => *handle,
+            _ => None,
+        });
+        Some(instance.gc.heap.collect(roots.collect::<Vec<_>>()))
+    }
+
+    /// Keep the GC object `value` refers to alive through `collect_garbage`, for as long as the
+    /// host holds on to it, until it's unpinned as many times as it's been pinned. Returns false
+    /// if `value` isn't a reference to a live GC object.
+    #[cfg(feature = "gc")]
+    pub fn pin_ref(&mut self, value: &Value) -> bool {
+        match value {
+            Value::AnyRef(Some(handle)) => self.instance.gc.heap.pin(*handle),
+            _ => false,
+        }
+    }
+
+    /// Undo one `pin_ref` of `value`. Returns whether it was pinned.
+    #[cfg(feature = "gc")]
+    pub fn unpin_ref(&mut self, value: &Value) -> bool {
+        match value {
+            Value::AnyRef(Some(handle)) => self.instance.gc.heap.unpin(*handle),
+            _ => false,
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn frame_stack(&self) -> &[Frame<S>] {
+        &self.frame_stack
+    }
+
+    /// The live frames, outermost first. After a fault, these are as they were when it happened.
+    /// Each frame's pc is just past the op it was executing: the call, for callers, and the
+    /// faulting op for the innermost frame.
+    pub fn frames(&self) -> impl Iterator<Item = FrameView<'_, S>> {
+        self.frame_stack.iter().map(FrameView::new)
+    }
+
+    /// The guest's call stack as it stands, innermost frame first. It can be taken whenever the
+    /// host has control, not just after a fault: while suspended, or from inside a host function
+    /// through `Caller::capture_stack_trace`.
+    pub fn capture_stack_trace(&self) -> Vec<TraceFrame> {
+        let module = &self.instance.module;
+        self.frame_stack
+            .iter()
+            .rev()
+            .map(|frame| TraceFrame {
+                funcidx: frame.funcidx,
+                name: module.func_name(frame.funcidx).map(str::to_string),
+                pc: frame.pc.saturating_sub(1),
+            })
+            .collect()
+    }
+
+    /// What happened over the last `run`.
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> &ExecutionStats {
+        &self.stats
+    }
+
+    pub fn is_poisoned(&self) -> bool {
+        self.poisoned.is_some()
+    }
+
+    /// The error which poisoned this execution, if any.
+    pub fn poisoned_by(&self) -> Option<&ExecError> {
+        self.poisoned.as_ref()
+    }
+
+    /// Have `finalizer` run when this execution is disposed of or dropped, to release host
+    /// resources, such as those behind externrefs, which the guest may have been holding on to.
+    /// Finalizers run most recently registered first. `reset` keeps them, as it keeps the externs.
+    pub fn on_drop(&mut self, finalizer: impl FnOnce(&mut ExternTable) + Send + 'static) {
+        self.externs.on_drop(finalizer);
+    }
+
+    /// Throw away any live frames and run every finalizer registered with `on_drop`, now rather
+    /// than whenever the execution happens to be dropped. The execution can still be used
+    /// afterwards, but any externrefs the guest kept may refer to released resources.
+    pub fn dispose(&mut self) {
+        self.reset();
+        self.externs.run_finalizers();
+    }
+
+    /// Write an image of the execution, frames and all, from which `thaw` can carry it on later.
+    /// See [`crate::hibernate`] for what is and isn't kept.
+    pub fn hibernate(&self, writer: impl Write) -> Result<(), HibernateError> {
+        self.hibernate_with(writer, HibernateOptions::default())
+    }
+
+    pub fn hibernate_with(
+        &self,
+        mut writer: impl Write,
+        options: HibernateOptions,
+    ) -> Result<(), HibernateError> {
+        if self.poisoned.is_some() {
+            return Err(HibernateError::Poisoned);
+        }
+        if !self.externs.is_empty() {
+            return Err(HibernateError::HostValues);
+        }
+        if !self.instance.links.is_empty() {
+            return Err(HibernateError::Shared);
+        }
+        let image = hibernate::write_image(
+            &self.instance,
+            &self.frame_stack,
+            &self.memory,
+            self.result.as_deref(),
+            options,
+        )?;
+        writer.write_all(&image).map_err(HibernateError::Io)
+    }
+
+    /// Run the next op and suspend with `SuspendReason::Stepped`, or finish the run if that was
+    /// the last of it. Calls into the guest are ops like any other, so stepping follows them in.
+    pub fn step(&mut self) -> Result<(), ExecError> {
+        if self.frame_stack.is_empty() {
+            return Ok(());
+        }
+        if self.history.as_ref().is_some_and(|h| h.due(self.ops_run)) {
+            self.checkpoint();
+        }
+        self.step_deadline = Some(self.ops_run + 1);
+        let result = self.run();
+        self.step_deadline = None;
+        result
+    }
+
+    /// Have `step` take a checkpoint every `interval` ops, keeping the last `window`, so that
+    /// `step_back` can go back up to `interval * window` ops. See [`crate::rewind`].
+    pub fn record_history(&mut self, interval: u64, window: usize)
+    where
+        M: Clone,
+    {
+        self.history = Some(History::new(interval, window, M::clone));
+    }
+
+    /// Stop taking checkpoints, and forget those taken.
+    pub fn clear_history(&mut self) {
+        self.history = None;
+    }
+
+    /// Undo the last op stepped, by going back to a checkpoint and stepping forward from there
+    /// to just before it. Returns false, changing nothing, if there's no checkpoint far enough
+    /// back.
+    pub fn step_back(&mut self) -> Result<bool, ExecError> {
+        let Some(target) = self.ops_run.checked_sub(1) else {
+            return Ok(false);
+        };
+        let Some(history) = &mut self.history else {
+            return Ok(false);
+        };
+        let copy_memory = history.copy_memory;
+        let Some(checkpoint) = history.rewind_to(target) else {
+            return Ok(false);
+        };
+        self.ops_run = checkpoint.ops_run;
+        self.frame_stack.clone_from(&checkpoint.frames);
+        self.memory = copy_memory(&checkpoint.memory);
+        for (global, value) in self.instance.globals.iter_mut().zip(&checkpoint.globals) {
+            global.value = *value;
+        }
+        self.instance.tables.clone_from(&checkpoint.tables);
+        self.result.clone_from(&checkpoint.result);
+        self.count_suspended_slots();
+        self.poisoned = None;
+        self.suspended = None;
+        while self.ops_run < target {
+            match self.step() {
+                Err(ExecError::Suspended(SuspendReason::Stepped)) => {}
+                other => other?,
+            }
+        }
+        self.suspended = Some(SuspendReason::Stepped);
+        Ok(true)
+    }
+
+    fn checkpoint(&mut self) {
+        let Some(history) = &mut self.history else {
+            return;
+        };
+        history.push(Checkpoint {
+            ops_run: self.ops_run,
+            frames: self.frame_stack.clone(),
+            memory: (history.copy_memory)(&self.memory),
+            globals: self.instance.globals.iter().map(|g| g.value).collect(),
+            tables: self.instance.tables.clone(),
+            result: self.result.clone(),
+        });
+    }
+
+    /// Throw away any live frames and the last result, and clear the poisoned state, leaving the
+    /// execution ready for the next `prepare`. The instance, memory and externs are kept as-is.
+    pub fn reset(&mut self) {
+        while self.pop_frame() {}
+        self.active_budgets.clear();
+        self.result = None;
+        self.poisoned = None;
+        self.suspended = None;
+    }
+
+    /// Build new frames from `pool`'s buffers, returning the pool used until now. Handing over
+    /// one made with `FramePool::with_capacity` keeps guest-to-guest calls which stay within it
+    /// off the allocator; see `FramePool` for what else still allocates.
+    pub fn set_frame_pool(&mut self, pool: FramePool<S>) -> FramePool<S> {
+        std::mem::replace(&mut self.frame_pool, pool)
+    }
+
+    pub fn frame_pool(&self) -> &FramePool<S> {
+        &self.frame_pool
+    }
+
+    /// Set up a call to the function at `funcidx` with `args`, for `run` to carry out. Fails
+    /// with `Fault::Busy`, leaving things as they were, if a call is already in progress.
+    pub fn prepare(&mut self, funcidx: u32, args: &[Value]) -> Result<(), ExecError> {
+        if self.is_poisoned() {
+            return Err(ExecError::ExecutionFault(Fault::Poisoned));
+        }
+        if !self.frame_stack.is_empty() {
+            return Err(ExecError::ExecutionFault(Fault::Busy));
+        }
+        let frame = self
+            .instance
+            .pooled_frame_for_funcidx(funcidx, args, &mut self.frame_pool)
+            .map_err(ExecError::LinkageError)?;
+
+        // TODO: Need to fix the label mismatch properly
+
+        self.push_frame(frame).map_err(ExecError::ExecutionFault)?;
+        self.start_budget(funcidx);
+        Ok(())
+    }
+
+    /// As `prepare`, for a function looked up with `Instance::func_by_name` or `func_by_index`.
+    /// One looked up in another instance is only taken if this one has the same function there.
+    pub fn prepare_func(&mut self, func: &FuncHandle, args: &[Value]) -> Result<(), ExecError> {
+        if !func.belongs_to(&self.instance) {
+            return Err(ExecError::LinkageError(LinkError::FunctionNotFound));
+        }
+        self.prepare(func.index(), args)
+    }
+
+    /// Call the function exported as `name` with `args`, running it to completion and returning
+    /// its results. For running a call a slice at a time, use `prepare` and `run`.
+    pub fn invoke(&mut self, name: &str, args: &[Value]) -> Result<Vec<Value>, ExecError> {
+        let func = self
+            .instance
+            .func_by_name(name)
+            .ok_or(ExecError::LinkageError(LinkError::FunctionNotFound))?;
+        self.invoke_func(&func, args)
+    }
+
+    /// As `invoke`, for a function looked up with `Instance::func_by_name` or `func_by_index`.
+    pub fn invoke_func(
+        &mut self,
+        func: &FuncHandle,
+        args: &[Value],
+    ) -> Result<Vec<Value>, ExecError> {
+        self.prepare_func(func, args)?;
+        self.run()?;
+        Ok(self.result.clone().unwrap_or_default())
+    }
+
+    /// Fault with `Fault::StackExhausted` on calls which would take the stacks past `limits`.
+    pub fn set_stack_limits(&mut self, limits: StackLimits) {
+        self.stack_limits = limits;
+    }
+
+    /// Fault with `Fault::ReentryLimit` on calls back into the guest from host functions which
+    /// would have more than `depth` of them in progress at once.
+    pub fn set_max_reentry(&mut self, depth: usize) {
+        self.max_reentry = depth;
+    }
+
+    /// Push `frame` if the stack limits leave room for it, else hand it back to the pool.
+    fn push_frame(&mut self, frame: Frame<S>) -> Result<(), Fault> {
+        let limits = self.stack_limits;
+        let too_deep = limits
+            .max_frames
+            .is_some_and(|max| self.frame_stack.len() >= max);
+        let top = self.frame_stack.last().map_or(0, frame_slots);
+        let too_wide = limits
+            .max_slots
+            .is_some_and(|max| self.suspended_slots + top + frame.locals.width() > max);
+        if too_deep || too_wide || frame.locals.exhausted() {
+            self.frame_pool.recycle(frame);
+            return Err(Fault::StackExhausted);
+        }
+        self.suspended_slots += top;
+        self.frame_stack.push(frame);
+        Ok(())
+    }
+
+    /// Pop the top frame back into the pool, if there is one.
+    fn pop_frame(&mut self) -> bool {
+        let Some(frame) = self.frame_stack.pop() else {
+            return false;
+        };
+        self.frame_pool.recycle(frame);
+        let top = self.frame_stack.last().map_or(0, frame_slots);
+        self.suspended_slots = self.suspended_slots.saturating_sub(top);
+        true
+    }
+
+    /// Work `suspended_slots` out afresh, after the frames were replaced wholesale.
+    fn count_suspended_slots(&mut self) {
+        let below_top = self.frame_stack.len().saturating_sub(1);
+        self.suspended_slots = self.frame_stack[..below_top].iter().map(frame_slots).sum();
+    }
+
+    /// Fault with `Fault::BudgetExceeded` if any one call to the function at `funcidx` runs
+    /// more than `ops` ops, counting those of the functions it calls, so a runaway callback is
+    /// caught and named even when the run as a whole is allowed to go on much longer.
+    pub fn set_budget(&mut self, funcidx: u32, ops: u64) {
+        self.budgets.insert(funcidx, ops);
+    }
+
+    /// As `set_budget`, for the function exported as `name`. Returns false if there's no such
+    /// export.
+    pub fn set_budget_by_name(&mut self, name: &str, ops: u64) -> bool {
+        match self.instance.find_funcidx(name) {
+            Some(funcidx) => {
+                self.set_budget(funcidx, ops);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Report loops and calls which go further than `thresholds` allow to `hook`, leaving them
+    /// to carry on. Replaces any earlier monitor.
+    pub fn on_anomaly(
+        &mut self,
+        thresholds: AnomalyThresholds,
+        hook: impl FnMut(Anomaly) + Send + 'static,
+    ) {
+        self.anomalies = Some(AnomalyMonitor {
+            thresholds,
+            hook: Box::new(hook),
+        });
+    }
+
+    /// Stop reporting anomalies.
+    pub fn clear_anomaly_hook(&mut self) {
+        self.anomalies = None;
+    }
+
+    /// Remove the budget for `funcidx`. Calls already in progress are still held to it.
+    pub fn clear_budget(&mut self, funcidx: u32) {
+        self.budgets.remove(&funcidx);
+    }
+
+    /// Start the clock on the call just pushed, if its function has a budget.
+    fn start_budget(&mut self, funcidx: u32) {
+        if let Some(ops) = self.budgets.get(&funcidx) {
+            let deadline = self.ops_run.saturating_add(*ops);
+            self.active_budgets
+                .push((self.frame_stack.len(), funcidx, deadline));
+        }
+    }
+
+    pub fn run(&mut self) -> Result<(), ExecError> {
+        if self.is_poisoned() {
+            return Err(ExecError::ExecutionFault(Fault::Poisoned));
+        }
+        if self.frame_stack.is_empty() {
+            return Err(ExecError::ExecutionFault(Fault::NothingToRun));
+        }
+        self.pull_links();
+        #[cfg(feature = "stats")]
+        {
+            self.stats = ExecutionStats {
+                max_frame_depth: self.frame_stack.len(),
+                ..Default::default()
+            };
+        }
+        let result = match self.run_frames(0) {
+            Ok(values) => {
+                self.result = Some(values);
+                Ok(())
+            }
+            Err(e) => Err(e),
+        };
+        #[cfg(feature = "stats")]
+        {
+            self.stats.mem_pages_end = self.memory.pages();
+        }
+        self.push_links();
+        self.suspended = None;
+        match &result {
+            Err(ExecError::Suspended(reason)) => self.suspended = Some(*reason),
+            Err(e) => self.poisoned = Some(e.clone()),
+            Ok(()) => {}
+        }
+        result
+    }
+
+    /// The operand stack of the guest function which made the host call `run` is suspended at.
+    fn host_call_stack(&mut self) -> Result<&mut Stack<S>, Fault> {
+        match (&self.suspended, self.frame_stack.last_mut()) {
+            (Some(SuspendReason::GuestYield), Some(frame)) => Ok(&mut frame.stack),
+            _ => Err(Fault::NotAtHostCall),
+        }
+    }
+
+    /// Push `value` for the guest to find on its operand stack when it resumes from the host call
+    /// it's suspended at, as for calling conventions which hand back more than the import's
+    /// signature says. The guest has to be expecting it: nothing checks it against the code.
+    pub fn push_value(&mut self, value: Value) -> Result<(), Fault> {
+        let stack = self.host_call_stack()?;
+        value.push_to(stack);
+        if stack.exhausted() {
+            return Err(Fault::StackExhausted);
+        }
+        Ok(())
+    }
+
+    /// Pop a value of type `ty` from the operand stack of the guest suspended at a host call.
+    /// Debug builds check the slots popped hold that type; release builds take the bits as they
+    /// are.
+    pub fn pop_value(&mut self, ty: ValueType) -> Result<Value, Fault> {
+        Value::pop_from(ty, self.host_call_stack()?)
+    }
+
+    /// The values at the top of the operand stack of the guest suspended at a host call, bottom
+    /// first, read as `types`, leaving them in place.
+    pub fn peek_values(&mut self, types: &[ValueType]) -> Result<Vec<Value>, Fault> {
+        let stack = self.host_call_stack()?;
+        let width = types.iter().map(|ty| ty.slot_width() as usize).sum();
+        let Some(at) = stack.width().checked_sub(width) else {
+            return Err(Fault::StackUnderflow);
+        };
+        let mut top = Stack::with_capacity(width);
+        top.push_copy(stack, at, width)?;
+        let mut values = types
+            .iter()
+            .rev()
+            .map(|ty| Value::pop_from(*ty, &mut top))
+            .collect::<Result<Vec<_>, _>>()?;
+        values.reverse();
+        Ok(values)
+    }
+
+    /// Run until the frame at `base` returns, handing back its results. Frames below it are
+    /// those of the guest calls a host function called back into the guest from.
+    fn run_frames(&mut self, base: usize) -> Result<Vec<Value>, ExecError> {
+        loop {
+            let Some(top_frame) = self.frame_stack.last_mut() else {
+                return Err(ExecError::ExecutionFault(Fault::NothingToRun));
+            };
+            // The tightest budget of the calls in progress, if it comes before the usual limit.
+            let budget = self.active_budgets.iter().min_by_key(|b| b.2).copied();
+            let limit = self.ops_run.saturating_add(RUN_TICK_LIMIT);
+            let limit = match budget {
+                Some((_, _, deadline)) => limit.min(deadline.saturating_add(1)),
+                None => limit,
+            };
+            let limit = match self.step_deadline {
+                Some(deadline) => limit.min(deadline.saturating_add(1)),
+                None => limit,
+            };
+            let result = execute(
+                top_frame,
+                &mut self.memory,
+                &mut self.instance.globals,
+                &mut self.instance.tables,
+                &mut self.ops_run,
+                limit,
+                &self.instance.module.types,
+                &self.instance.module.type_ids,
+                &self.instance.module.functions,
+                &mut self.instance.gc,
+                &mut self.stats,
+                &mut self.grow_hook,
+                &mut self.instance.host_funcs,
+                self.floats,
+                &mut self.alignment_hook,
+                &self.watchpoints,
+                &mut self.tracer,
+                &mut self.anomalies,
+            );
+            match result {
+                Ok(Continuation::ProgramEnd) | Ok(Continuation::DoneReturn) => {
+                    // Stack is LIFO - pop values and assign to correct indices
+                    let mut return_values =
+                        self.frame_pool.take_values(top_frame.return_types.len());
+                    for (i, rt) in top_frame.return_types.iter().enumerate().rev() {
+                        return_values[i] = Value::pop_from(*rt, &mut top_frame.stack)
+                            .map_err(ExecError::ExecutionFault)?;
+                    }
+                    if let Some(values) = self.finish_frame(return_values, base) {
+                        return Ok(values);
+                    }
+                }
+                Ok(Continuation::Call(funcidx)) => {
+                    // Get the function signature to know what arguments to pop from the stack
+                    let current_frame = self.frame_stack.last_mut().unwrap();
+
+                    // Look up the function signature. Calls to imports never get here, so this is
+                    // always a function defined in the module.
+                    let Some(func_type) = self.instance.module.func_type_of(funcidx) else {
+                        return Err(ExecError::ExecutionFault(Fault::GlobalIndexOutOfBounds));
+                    };
+
+                    // Pop arguments from the current frame's stack
+                    let mut args = self.frame_pool.take_values(func_type.params.len());
+                    for (i, param_type) in func_type.params.iter().enumerate().rev() {
+                        let value = Value::pop_from(*param_type, &mut current_frame.stack)
+                            .map_err(ExecError::ExecutionFault)?;
+                        args[i] = value;
+                    }
+
+                    if let Some(interceptor) = self.interceptors.get_mut(&funcidx) {
+                        if let Intercept::Return(results) = interceptor(&args) {
+                            let matches = results.len() == func_type.results.len()
+                                && results
+                                    .iter()
+                                    .zip(&func_type.results)
+                                    .all(|(v, ty)| v.type_of() == *ty);
+                            if !matches {
+                                return Err(ExecError::ExecutionFault(
+                                    Fault::InterceptedResultMismatch(funcidx),
+                                ));
+                            }
+                            for v in results {
+                                v.push_to(&mut current_frame.stack);
+                            }
+                            self.frame_pool.recycle_values(args);
+                            continue;
+                        }
+                    }
+
+                    let frame = self.instance.pooled_frame_for_funcidx(
+                        funcidx,
+                        &args,
+                        &mut self.frame_pool,
+                    );
+                    self.frame_pool.recycle_values(args);
+                    let frame = frame.map_err(ExecError::LinkageError)?;
+                    self.push_frame(frame).map_err(ExecError::ExecutionFault)?;
+                    self.start_budget(funcidx);
+                    if let Some(monitor) = &mut self.anomalies {
+                        monitor.called(funcidx, self.frame_stack.len());
+                    }
+                    #[cfg(feature = "stats")]
+                    {
+                        self.stats.max_frame_depth =
+                            self.stats.max_frame_depth.max(self.frame_stack.len());
+                    }
+                }
+
+                Ok(Continuation::HostCall(funcidx)) => {
+                    let host = &self.instance.host_funcs[funcidx as usize];
+                    let func = host.caller_func.clone().unwrap();
+                    let frame = self.frame_stack.last_mut().unwrap();
+                    let mut args = self.frame_pool.take_values(host.func_type.params.len());
+                    for (arg, ty) in args.iter_mut().zip(&host.func_type.params).rev() {
+                        *arg = Value::pop_from(*ty, &mut frame.stack)
+                            .map_err(ExecError::ExecutionFault)?;
+                    }
+                    // The host function may call into other instances this one shares with.
+                    self.push_links();
+                    let results = func(&mut Caller::new(self), &args);
+                    self.pull_links();
+                    self.frame_pool.recycle_values(args);
+                    let results = results.map_err(ExecError::ExecutionFault)?;
+                    self.instance.host_funcs[funcidx as usize]
+                        .check_results(&results)
+                        .map_err(ExecError::ExecutionFault)?;
+                    let frame = self.frame_stack.last_mut().unwrap();
+                    for result in results {
+                        result.push_to(&mut frame.stack);
+                    }
+                }
+                Ok(Continuation::Suspend(reason)) => return Err(ExecError::Suspended(reason)),
+                Err(Fault::OutOfTicks) if self.step_deadline.is_some_and(|d| self.ops_run > d) => {
+                    // The op which ran out wasn't run, so it isn't counted.
+                    self.ops_run -= 1;
+                    return Err(ExecError::Suspended(SuspendReason::Stepped));
+                }
+                Err(Fault::OutOfTicks) => {
+                    let fault = match budget {
+                        Some((_, funcidx, deadline)) if self.ops_run > deadline => {
+                            Fault::BudgetExceeded(funcidx)
+                        }
+                        _ => Fault::OutOfTicks,
+                    };
+                    return Err(ExecError::ExecutionFault(fault));
+                }
+                Err(fault) => {
+                    let funcidx = top_frame.funcidx;
+                    let decision = match &mut self.trap_policy {
+                        Some(policy) => policy(&fault, funcidx),
+                        None => TrapDecision::Abort,
+                    };
+                    let TrapDecision::Return(values) = decision else {
+                        return Err(ExecError::ExecutionFault(fault));
+                    };
+                    let return_types = &self.frame_stack.last().unwrap().return_types;
+                    let matches = values.len() == return_types.len()
+                        && values
+                            .iter()
+                            .zip(return_types)
+                            .all(|(v, ty)| v.type_of() == *ty);
+                    if !matches {
+                        return Err(ExecError::ExecutionFault(Fault::InterceptedResultMismatch(
+                            funcidx,
+                        )));
+                    }
+                    if let Some(values) = self.finish_frame(values, base) {
+                        return Ok(values);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Pop the top frame, which has returned `values`, and hand them to its caller; or back, if
+    /// it was the frame at `base`.
+    fn finish_frame(&mut self, values: Vec<Value>, base: usize) -> Option<Vec<Value>> {
+        self.pop_frame();
+        let depth = self.frame_stack.len();
+        self.active_budgets.retain(|b| b.0 <= depth);
+        match self.frame_stack.last_mut() {
+            Some(frame) if depth > base => {
+                for v in &values {
+                    v.push_to(&mut frame.stack);
+                }
+                self.frame_pool.recycle_values(values);
+                None
+            }
+            _ => Some(values),
+        }
+    }
+}
+
+impl<M, T, S> Reenter for Execution<M, T, S>
+where
+    M: Memory,
+    T: 'static,
+    S: SlotStorage,
+{
+    fn call_guest(&mut self, funcidx: u32, args: &[Value]) -> Result<Vec<Value>, Fault> {
+        if self.reentry_depth >= self.max_reentry {
+            return Err(Fault::ReentryLimit);
+        }
+        let base = self.frame_stack.len();
+        let frame = self
+            .instance
+            .pooled_frame_for_funcidx(funcidx, args, &mut self.frame_pool)
+            .map_err(|e| Fault::HostAbort(e.to_string()))?;
+        self.push_frame(frame)?;
+        self.start_budget(funcidx);
+        self.reentry_depth += 1;
+        self.pull_links();
+        let result = self.run_frames(base);
+        self.push_links();
+        self.reentry_depth -= 1;
+        result.map_err(|e| {
+            while self.frame_stack.len() > base {
+                self.pop_frame();
+            }
+            self.active_budgets.retain(|b| b.0 <= base);
+            nested_fault(e)
+        })
+    }
+
+    fn find_funcidx(&self, name: &str) -> Option<u32> {
+        self.instance.find_funcidx(name)
+    }
+
+    fn reentry_depth(&self) -> usize {
+        self.reentry_depth
+    }
+
+    fn capture_stack_trace(&self) -> Vec<TraceFrame> {
+        Execution::capture_stack_trace(self)
+    }
+
+    fn store(&mut self) -> (&mut dyn Any, &mut dyn Memory) {
+        (&mut self.data, &mut self.memory)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::decode::ScopeType;
+    use crate::exec::{
+        Determinism, ExecError, Execution, Fault, GrowDecision, Intercept, StackLimits, Subnormals,
+        SuspendReason, TrapDecision, Value,
+    };
+    use crate::frame::FramePool;
+    use crate::instance::{mk_instance, LinkError};
+    use crate::linker::Linker;
+    use crate::memory::WASM_PAGE_SIZE;
+    use crate::memory::{Memory, ProtectedMemory, VectorMemory};
+    use crate::module::Module;
+    use crate::stack::FixedSlots;
+    use crate::ValueType;
+
+    #[test]
+    fn load_run_itoa() {
+        let module_data: Vec<u8> = include_bytes!("../tests/itoa.wasm").to_vec();
         let module = Module::load(&module_data).unwrap();
 
         let linked = mk_instance(module).unwrap();
@@ -1902,4 +2750,1626 @@ mod tests {
         execution.prepare(1, &[Value::I32(123)]).unwrap();
         execution.run().unwrap();
     }
+
+    #[test]
+    fn invoke_by_name() {
+        let wat = r#"(module (func (export "divmod") (param i32 i32) (result i32 i32)
+            (i32.div_u (local.get 0) (local.get 1))
+            (i32.rem_u (local.get 0) (local.get 1))))"#;
+        let module = Module::load(&wat::parse_str(wat).unwrap()).unwrap();
+        let linked = mk_instance(module).unwrap();
+        let mut execution = Execution::new(linked, VectorMemory::new(0, None));
+        assert_eq!(
+            execution
+                .invoke("divmod", &[Value::I32(17), Value::I32(5)])
+                .unwrap(),
+            vec![Value::I32(3), Value::I32(2)]
+        );
+        assert!(matches!(
+            execution.invoke("missing", &[]),
+            Err(ExecError::LinkageError(LinkError::FunctionNotFound))
+        ));
+        assert!(matches!(
+            execution.invoke("divmod", &[Value::I32(17)]),
+            Err(ExecError::LinkageError(LinkError::ArgumentCountMismatch(
+                2, 1
+            )))
+        ));
+        assert!(matches!(
+            execution.invoke("divmod", &[Value::I32(17), Value::I64(5)]),
+            Err(ExecError::LinkageError(LinkError::ArgumentTypeMismatch(
+                1,
+                ..
+            )))
+        ));
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    fn ill_typed_code_faults() {
+        // Neither of these validates, and the loader doesn't check operand types.
+        let wat = r#"(module
+            (func (export "add") (result i32) (i32.add (f32.const 1) (i32.const 2)))
+            (func (export "select") (result i32)
+                (select (i32.const 1) (f32.const 2) (i32.const 1))))"#;
+        let module = Module::load(&wat::parse_str(wat).unwrap()).unwrap();
+        let linked = mk_instance(module).unwrap();
+        let mut execution = Execution::new(linked, VectorMemory::new(0, None));
+        for name in ["add", "select"] {
+            assert!(matches!(
+                execution.invoke(name, &[]),
+                Err(ExecError::ExecutionFault(Fault::StackKindMismatch))
+            ));
+            execution.reset();
+        }
+    }
+
+    #[test]
+    fn invoke_while_suspended_is_refused() {
+        let wat = r#"(module
+            (import "wasbox" "yield" (func $yield))
+            (func (export "slow") (result i32) (call $yield) (i32.const 7))
+            (func (export "fast") (result i32) (i32.const 3)))"#;
+        let module = Module::load(&wat::parse_str(wat).unwrap()).unwrap();
+        let instance = Linker::new().instantiate(module).unwrap();
+        let mut execution = Execution::new(instance, VectorMemory::new(0, None));
+        assert!(matches!(
+            execution.run(),
+            Err(ExecError::ExecutionFault(Fault::NothingToRun))
+        ));
+        assert!(!execution.is_poisoned());
+
+        assert!(matches!(
+            execution.invoke("slow", &[]),
+            Err(ExecError::Suspended(SuspendReason::GuestYield))
+        ));
+        assert!(matches!(
+            execution.invoke("fast", &[]),
+            Err(ExecError::ExecutionFault(Fault::Busy))
+        ));
+
+        // The suspended call is left as it was, to finish, after which others can be made.
+        execution.run().unwrap();
+        assert_eq!(execution.result().unwrap(), &[Value::I32(7)]);
+        assert_eq!(execution.invoke("fast", &[]).unwrap(), vec![Value::I32(3)]);
+    }
+
+    #[test]
+    fn tags_load_and_throws_fault_when_reached() {
+        // (type (func (param i32) (result i32))) (type (func (param i32))) (tag (type 1))
+        // (func (export "f") (type 0)
+        //     (if (local.get 0) (then (throw 0 (local.get 0))))
+        //     (i32.add (local.get 0) (i32.const 1)))
+        // (func (export "g") (type 0) (try_table (result i32) (local.get 0)))
+        #[rustfmt::skip]
+        let bytes = [
+            0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+            0x01, 0x0a, 0x02, 0x60, 0x01, 0x7f, 0x01, 0x7f, 0x60, 0x01, 0x7f, 0x00,
+            0x03, 0x03, 0x02, 0x00, 0x00,
+            0x0d, 0x03, 0x01, 0x00, 0x01,
+            0x07, 0x09, 0x02, 0x01, b'f', 0x00, 0x00, 0x01, b'g', 0x00, 0x01,
+            0x0a, 0x1b, 0x02,
+            0x10, 0x00, 0x20, 0x00, 0x04, 0x40, 0x20, 0x00, 0x08, 0x00, 0x0b,
+            0x20, 0x00, 0x41, 0x01, 0x6a, 0x0b,
+            0x08, 0x00, 0x1f, 0x7f, 0x00, 0x20, 0x00, 0x0b, 0x0b,
+        ];
+        let module = Module::load(&bytes).unwrap();
+        assert_eq!(module.tags, vec![1]);
+        assert_eq!(Module::load(&module.encode()).unwrap().tags, vec![1]);
+
+        let linked = mk_instance(module).unwrap();
+        let mut execution = Execution::new(linked, VectorMemory::new(0, None));
+        assert_eq!(
+            execution.invoke("f", &[Value::I32(0)]).unwrap(),
+            vec![Value::I32(1)]
+        );
+        assert_eq!(
+            execution.invoke("g", &[Value::I32(7)]).unwrap(),
+            vec![Value::I32(7)]
+        );
+        assert!(matches!(
+            execution.invoke("f", &[Value::I32(5)]),
+            Err(ExecError::ExecutionFault(Fault::UnsupportedThrow(Some(0))))
+        ));
+    }
+
+    #[test]
+    fn func_handles_by_name_or_index() {
+        let wat = r#"(module
+            (func $double (param i64) (result i64) (i64.mul (local.get 0) (i64.const 2)))
+            (func (export "double") (param i64) (result i64) (call $double (local.get 0))))"#;
+        let module = Module::load(&wat::parse_str(wat).unwrap()).unwrap();
+        let linked = mk_instance(module).unwrap();
+        let exported = linked.func_by_name("double").unwrap();
+        let internal = linked.func_by_index(0).unwrap();
+        assert_eq!(exported.index(), 1);
+        assert_eq!(exported.func_type(), internal.func_type());
+        assert_eq!(exported.func_type().results, vec![ValueType::I64]);
+        assert!(linked.func_by_name("missing").is_none());
+        assert!(linked.func_by_index(2).is_none());
+
+        // Handles outlive resets, and carry over to other instances of the module.
+        let mut execution = Execution::new(linked.clone(), VectorMemory::new(0, None));
+        for func in [&exported, &internal] {
+            assert_eq!(
+                execution.invoke_func(func, &[Value::I64(21)]).unwrap(),
+                vec![Value::I64(42)]
+            );
+        }
+        execution.reset();
+        execution.prepare_func(&internal, &[Value::I64(4)]).unwrap();
+        execution.run().unwrap();
+        assert_eq!(execution.result().unwrap(), &[Value::I64(8)]);
+
+        // But not to an instance with something else at that index.
+        let other = r#"(module (func (param i32)) (func (export "double") (param i64) (result i64)
+            (local.get 0)))"#;
+        let other = mk_instance(Module::load(&wat::parse_str(other).unwrap()).unwrap()).unwrap();
+        let mut execution = Execution::new(other, VectorMemory::new(0, None));
+        assert!(matches!(
+            execution.invoke_func(&internal, &[Value::I64(1)]),
+            Err(ExecError::LinkageError(LinkError::FunctionNotFound))
+        ));
+        assert!(execution.invoke_func(&exported, &[Value::I64(1)]).is_ok());
+    }
+
+    #[test]
+    fn recursive_calls_reuse_frames() {
+        let module_data = wat::parse_str(
+            r#"(module (func $fib (export "fib") (param i32) (result i32)
+                (if (result i32) (i32.lt_u (local.get 0) (i32.const 2))
+                    (then (local.get 0))
+                    (else (i32.add
+                        (call $fib (i32.sub (local.get 0) (i32.const 1)))
+                        (call $fib (i32.sub (local.get 0) (i32.const 2))))))))"#,
+        )
+        .unwrap();
+        let module = Module::load(&module_data).unwrap();
+        let linked = mk_instance(module).unwrap();
+        let funcidx = linked.find_funcidx("fib").unwrap();
+        let mut execution = Execution::new(linked, VectorMemory::new(0, None));
+        for _ in 0..2 {
+            execution.prepare(funcidx, &[Value::I32(15)]).unwrap();
+            execution.run().unwrap();
+            assert_eq!(execution.result().unwrap(), &[Value::I32(610)]);
+        }
+        // Every frame has finished, and at most the deepest point of recursion was ever live, so
+        // that's all the pool should have needed to allocate.
+        assert_eq!(execution.frame_stack_len(), 0);
+        assert_eq!(execution.frame_pool.len(), 15);
+        assert_eq!(execution.frame_pool().misses(), 15);
+
+        // With the buffers handed over up front, nothing needs allocating at all.
+        let previous = execution.set_frame_pool(FramePool::with_capacity(15, 8));
+        assert_eq!(previous.len(), 15);
+        execution.prepare(funcidx, &[Value::I32(15)]).unwrap();
+        execution.run().unwrap();
+        assert_eq!(execution.result().unwrap(), &[Value::I32(610)]);
+        assert_eq!(execution.frame_pool().misses(), 0);
+        assert_eq!(execution.frame_pool().len(), 15);
+    }
+
+    #[test]
+    fn block_type_indices_colliding_with_value_types() {
+        // Type 127 is written as a two byte LEB128, 0xff 0x00, whose value is also the encoding of
+        // i32; read as a value type, the block would take no params and leave the stack unbalanced.
+        let fillers = "(type (func))".repeat(127);
+        let wat = format!(
+            r#"(module {fillers}
+                (type $t (func (param i32 i32) (result i32)))
+                (func (export "f") (param i32) (result i32)
+                    (local.get 0)
+                    (i32.const 2)
+                    (block (type $t) (i32.mul))))"#
+        );
+        assert_eq!(run_unary(&wat, Value::I32(21)), Value::I32(42));
+    }
+
+    #[test]
+    fn block_params() {
+        let wat = r#"(module
+            (func (export "f") (param i32) (result i32)
+                (local.get 0)
+                (i32.const 1)
+                (block (param i32 i32) (result i32) (i32.add))
+                (i32.const 10)
+                (if (param i32 i32) (result i32) (local.get 0)
+                    (then (i32.mul))
+                    (else (i32.sub)))
+                (i32.const 0)
+                (loop $l (param i32 i32) (result i32)
+                    (i32.add (i32.const 1))
+                    (local.set 0 (i32.sub (local.get 0) (i32.const 1)))
+                    (br_if $l (i32.gt_s (local.get 0) (i32.const 0)))
+                    (i32.add))))"#;
+        // ((x + 1) * 10) + x, via a loop which carries its running count as a param.
+        assert_eq!(run_unary(wat, Value::I32(3)), Value::I32(43));
+        // x = 0 takes the else branch: (0 + 1) - 10, and then a single trip around the loop.
+        assert_eq!(run_unary(wat, Value::I32(0)), Value::I32(-8));
+    }
+
+    #[test]
+    fn multi_value_blocks_and_branches() {
+        let wat = r#"(module
+            (func $swap (param i64 i32) (result i32 i64) (local.get 1) (local.get 0))
+            ;; Branches out of nested scopes carry both values and drop whatever's underneath.
+            (func (export "nested") (param i32) (result i32 i64)
+                (block $out (result i32 i64)
+                    (i32.const 99)
+                    (block $mid (result i32 i64)
+                        (f64.const 1.5)
+                        (i64.const 7)
+                        (i32.const 1)
+                        (i64.const 2)
+                        (br_table $mid $out (local.get 0)))
+                    (i64.const 10)
+                    (i64.add)
+                    (br $out)))
+            ;; A loop's label carries its params back round, and its end hands on its results.
+            (func (export "sum") (param $n i32) (result i64 i32) (local $i i32) (local $acc i64)
+                (i32.const 0)
+                (i64.const 0)
+                (loop $l (param i32 i64) (result i64 i32)
+                    (local.set $acc)
+                    (local.set $i)
+                    (local.set $i (i32.add (local.get $i) (i32.const 1)))
+                    (local.set $acc (i64.add (local.get $acc) (i64.extend_i32_u (local.get $i))))
+                    (local.get $i)
+                    (local.get $acc)
+                    (br_if $l (i32.lt_u (local.get $i) (local.get $n)))
+                    (local.set $acc)
+                    (drop)
+                    (local.get $acc)
+                    (local.get $i)))
+            ;; Returning from inside a block passes on a call's results.
+            (func (export "early") (param i32) (result i32 i64)
+                (f32.const 0)
+                (block (result i32)
+                    (if (local.get 0)
+                        (then (return (call $swap (i64.const 5) (i32.const 6)))))
+                    (i32.const 1))
+                (drop)
+                (drop)
+                (i32.const 0)
+                (i64.const 0))
+            ;; So does branching to the function's own label.
+            (func (export "bail") (param i32) (result i32 i64)
+                (i64.const 3)
+                (i32.const 4)
+                (i64.const 5)
+                (br_if 0 (local.get 0))
+                (drop)
+                (drop)
+                (drop)
+                (i32.const 0)
+                (i64.const 0)))"#;
+        let module = Module::load(&wat::parse_str(wat).unwrap()).unwrap();
+        let linked = mk_instance(module).unwrap();
+        let mut execution = Execution::new(linked, VectorMemory::new(0, None));
+        let mut invoke = |name, arg| execution.invoke(name, &[Value::I32(arg)]).unwrap();
+        assert_eq!(invoke("nested", 0), vec![Value::I32(1), Value::I64(12)]);
+        assert_eq!(invoke("nested", 1), vec![Value::I32(1), Value::I64(2)]);
+        assert_eq!(invoke("sum", 4), vec![Value::I64(10), Value::I32(4)]);
+        assert_eq!(invoke("sum", 0), vec![Value::I64(1), Value::I32(1)]);
+        assert_eq!(invoke("early", 1), vec![Value::I32(6), Value::I64(5)]);
+        assert_eq!(invoke("early", 0), vec![Value::I32(0), Value::I64(0)]);
+        assert_eq!(invoke("bail", 1), vec![Value::I32(4), Value::I64(5)]);
+        assert_eq!(invoke("bail", 0), vec![Value::I32(0), Value::I64(0)]);
+    }
+
+    #[test]
+    fn step_back_retraces_steps() {
+        let wat = r#"(module
+            (memory 1)
+            (func (export "count") (param $n i32) (result i32) (local $i i32)
+                (loop $l
+                    (local.set $i (i32.add (local.get $i) (i32.const 1)))
+                    (i32.store (i32.const 0) (i32.mul (local.get $i) (i32.const 10)))
+                    (br_if $l (i32.lt_u (local.get $i) (local.get $n))))
+                (local.get $i)))"#;
+        let module = Module::load(&wat::parse_str(wat).unwrap()).unwrap();
+        let linked = mk_instance(module).unwrap();
+        let funcidx = linked.find_funcidx("count").unwrap();
+        let mut execution = Execution::new(linked, VectorMemory::new(WASM_PAGE_SIZE, None));
+        execution.record_history(4, 3);
+        execution.prepare(funcidx, &[Value::I32(5)]).unwrap();
+
+        let state = |execution: &Execution<VectorMemory>| {
+            let frame = execution.frames().last().unwrap();
+            (
+                frame.pc(),
+                frame.locals(),
+                execution.memory().get_i32(0).unwrap(),
+            )
+        };
+        let mut seen = vec![];
+        for _ in 0..20 {
+            seen.push(state(&execution));
+            assert!(matches!(
+                execution.step(),
+                Err(ExecError::Suspended(SuspendReason::Stepped))
+            ));
+        }
+        // The three checkpoints kept, four ops apart, reach back to the one taken after op 8.
+        for expected in seen[8..].iter().rev() {
+            assert!(execution.step_back().unwrap());
+            assert_eq!(&state(&execution), expected);
+        }
+        assert!(!execution.step_back().unwrap());
+        assert_eq!(state(&execution), seen[8]);
+
+        // Going forward again from a step back picks up where things were.
+        while execution.frame_stack_len() > 0 {
+            let _ = execution.step();
+        }
+        assert_eq!(execution.result().unwrap(), &[Value::I32(5)]);
+        assert_eq!(execution.memory().get_i32(0).unwrap(), 50);
+    }
+
+    #[test]
+    fn if_arms_with_params() {
+        let wat = r#"(module
+            (func (export "f") (param i32) (result i32)
+                (i32.const 5)
+                ;; No else: a false condition passes the param straight through.
+                (if (param i32) (result i32) (i32.and (local.get 0) (i32.const 1))
+                    (then (i32.add (i32.const 100))))
+                ;; An empty then arm, inside a block so the scan has another scope to step over.
+                (block (param i32) (result i32)
+                    (if (param i32) (result i32) (i32.and (local.get 0) (i32.const 2))
+                        (then)
+                        (else (block (param i32) (result i32) (i32.mul (i32.const 3))))))))"#;
+        assert_eq!(run_unary(wat, Value::I32(0)), Value::I32(15));
+        assert_eq!(run_unary(wat, Value::I32(1)), Value::I32(315));
+        assert_eq!(run_unary(wat, Value::I32(2)), Value::I32(5));
+        assert_eq!(run_unary(wat, Value::I32(3)), Value::I32(105));
+
+        // Leaving out the else arm is only valid when it would have nothing to do.
+        for wat in [
+            r#"(module (func (result i32) (if (result i32) (i32.const 1) (then (i32.const 1)))))"#,
+            r#"(module (func (result i64) (i32.const 0)
+                (if (param i32) (result i64) (i32.const 1) (then (i64.extend_i32_u)))))"#,
+        ] {
+            let module = Module::load(&wat::parse_str(wat).unwrap()).unwrap();
+            assert!(mk_instance(module).is_err());
+        }
+    }
+
+    #[test]
+    fn indirect_calls_match_identical_types() {
+        // `$a` and `$b` are the same signature under different indices, so calls through either
+        // reach `$inc`; `$c` isn't.
+        let wat = r#"(module
+            (type $a (func (param i32) (result i32)))
+            (type $b (func (param i32) (result i32)))
+            (type $c (func (param i64) (result i32)))
+            (table 1 funcref)
+            (elem (i32.const 0) $inc)
+            (func $inc (type $a) (i32.add (local.get 0) (i32.const 1)))
+            (func (export "via_b") (param i32) (result i32)
+                (call_indirect (type $b) (local.get 0) (i32.const 0)))
+            (func (export "via_c") (param i32) (result i32)
+                (call_indirect (type $c) (i64.const 0) (i32.const 0))))"#;
+        let module = Module::load(&wat::parse_str(wat).unwrap()).unwrap();
+        assert_eq!(&module.type_ids[..3], &[0, 0, 2]);
+        let instance = mk_instance(module).unwrap();
+        let via_b = instance.find_funcidx("via_b").unwrap();
+        let via_c = instance.find_funcidx("via_c").unwrap();
+        let mut execution = Execution::new(instance, VectorMemory::new(0, None));
+
+        execution.prepare(via_b, &[Value::I32(41)]).unwrap();
+        execution.run().unwrap();
+        assert_eq!(execution.result().unwrap(), &[Value::I32(42)]);
+
+        execution.prepare(via_c, &[Value::I32(41)]).unwrap();
+        assert!(matches!(
+            execution.run(),
+            Err(ExecError::ExecutionFault(Fault::IndirectCallTypeMismatch))
+        ));
+    }
+
+    #[test]
+    fn null_table_slots_are_uninitialized() {
+        // Slot 1 is never written, so it holds a null funcref; slot 2 is past the end.
+        let wat = r#"(module
+            (type $t (func (result i32)))
+            (table 2 funcref)
+            (elem (i32.const 0) $one)
+            (func $one (type $t) (i32.const 1))
+            (func (export "call") (param i32) (result i32)
+                (call_indirect (type $t) (local.get 0)))
+            (func (export "is_null") (param i32) (result i32)
+                (ref.is_null (table.get 0 (local.get 0)))))"#;
+        let module = Module::load(&wat::parse_str(wat).unwrap()).unwrap();
+        let instance = mk_instance(module).unwrap();
+        assert_eq!(instance.tables[0].get(1), Some(Value::FuncRef(None)));
+        let call = instance.find_funcidx("call").unwrap();
+        let is_null = instance.find_funcidx("is_null").unwrap();
+        let mut execution = Execution::new(instance, VectorMemory::new(0, None));
+
+        execution.prepare(call, &[Value::I32(0)]).unwrap();
+        execution.run().unwrap();
+        assert_eq!(execution.result().unwrap(), &[Value::I32(1)]);
+
+        execution.prepare(call, &[Value::I32(1)]).unwrap();
+        assert!(matches!(
+            execution.run(),
+            Err(ExecError::ExecutionFault(Fault::UninitializedElement))
+        ));
+        execution.reset();
+
+        execution.prepare(call, &[Value::I32(2)]).unwrap();
+        assert!(matches!(
+            execution.run(),
+            Err(ExecError::ExecutionFault(Fault::UndefinedElement))
+        ));
+        execution.reset();
+
+        execution.prepare(is_null, &[Value::I32(1)]).unwrap();
+        execution.run().unwrap();
+        assert_eq!(execution.result().unwrap(), &[Value::I32(1)]);
+
+        execution.prepare(is_null, &[Value::I32(2)]).unwrap();
+        assert!(matches!(
+            execution.run(),
+            Err(ExecError::ExecutionFault(Fault::TableOutOfBounds))
+        ));
+    }
+
+    #[test]
+    fn hosts_reach_operands_at_yields() {
+        // The host gets to rewrite the operands of the multiply when the guest yields.
+        let wat = r#"(module
+            (import "wasbox" "yield" (func $yield))
+            (func (export "f") (param i32 i32) (result i32)
+                (local.get 0)
+                (local.get 1)
+                (call $yield)
+                (i32.mul)))"#;
+        let module = Module::load(&wat::parse_str(wat).unwrap()).unwrap();
+        let instance = Linker::new().instantiate(module).unwrap();
+        let funcidx = instance.find_funcidx("f").unwrap();
+        let mut execution = Execution::new(instance, VectorMemory::new(0, None));
+        execution
+            .prepare(funcidx, &[Value::I32(6), Value::I32(7)])
+            .unwrap();
+        assert!(matches!(
+            execution.push_value(Value::I32(0)),
+            Err(Fault::NotAtHostCall)
+        ));
+
+        assert!(matches!(
+            execution.run(),
+            Err(ExecError::Suspended(SuspendReason::GuestYield))
+        ));
+        let types = [ValueType::I32, ValueType::I32];
+        let operands = execution.peek_values(&types).unwrap();
+        assert_eq!(operands, [Value::I32(6), Value::I32(7)]);
+        assert_eq!(execution.pop_value(ValueType::I32).unwrap(), Value::I32(7));
+        execution.push_value(Value::I32(10)).unwrap();
+        assert_eq!(
+            execution.peek_values(&types).unwrap(),
+            [Value::I32(6), Value::I32(10)]
+        );
+
+        execution.run().unwrap();
+        assert_eq!(execution.result().unwrap(), &[Value::I32(60)]);
+        assert!(matches!(
+            execution.pop_value(ValueType::I32),
+            Err(Fault::NotAtHostCall)
+        ));
+    }
+
+    #[test]
+    fn stack_limits_fault_deep_recursion() {
+        let wat = r#"(module (func $down (export "down") (param i32) (result i32)
+            (if (result i32) (i32.eqz (local.get 0))
+                (then (i32.const 0))
+                (else (i32.add (i32.const 1)
+                    (call $down (i32.sub (local.get 0) (i32.const 1))))))))"#;
+        let module = Module::load(&wat::parse_str(wat).unwrap()).unwrap();
+        let instance = mk_instance(module).unwrap();
+        let down = instance.find_funcidx("down").unwrap();
+        let mut execution = Execution::new(instance, VectorMemory::new(0, None));
+        execution.set_stack_limits(StackLimits {
+            max_frames: Some(10),
+            max_slots: None,
+        });
+
+        execution.prepare(down, &[Value::I32(9)]).unwrap();
+        execution.run().unwrap();
+        assert_eq!(execution.result().unwrap(), &[Value::I32(9)]);
+
+        execution.prepare(down, &[Value::I32(10)]).unwrap();
+        assert!(matches!(
+            execution.run(),
+            Err(ExecError::ExecutionFault(Fault::StackExhausted))
+        ));
+
+        // Frames waiting on a call hold their param and the 1 to add to its result, the last
+        // one just its param, so going 10 deep takes 19 slots.
+        execution.reset();
+        execution.set_stack_limits(StackLimits {
+            max_frames: None,
+            max_slots: Some(19),
+        });
+        execution.prepare(down, &[Value::I32(9)]).unwrap();
+        execution.run().unwrap();
+        execution.set_stack_limits(StackLimits {
+            max_frames: None,
+            max_slots: Some(18),
+        });
+        execution.prepare(down, &[Value::I32(9)]).unwrap();
+        assert!(matches!(
+            execution.run(),
+            Err(ExecError::ExecutionFault(Fault::StackExhausted))
+        ));
+
+        // The slots count is kept as frames come and go, so neither the fault nor runs which
+        // finished leave any behind.
+        execution.reset();
+        execution.set_stack_limits(StackLimits {
+            max_frames: None,
+            max_slots: Some(19),
+        });
+        for _ in 0..3 {
+            execution.prepare(down, &[Value::I32(9)]).unwrap();
+            execution.run().unwrap();
+            assert_eq!(execution.result().unwrap(), &[Value::I32(9)]);
+        }
+    }
+
+    #[test]
+    fn fixed_slots_fault_when_full() {
+        let wat = r#"(module
+            (func $down (export "down") (param i32) (result i32)
+                (if (result i32) (i32.eqz (local.get 0))
+                    (then (i32.const 0))
+                    (else (i32.add (i32.const 1)
+                        (call $down (i32.sub (local.get 0) (i32.const 1)))))))
+            (func $four (param i32 i32 i32 i32) (result i32) (local.get 0))
+            (func (export "deep") (result i32)
+                (call $four (i32.const 1) (i32.const 2) (i32.const 3) (i32.const 4)))
+            (func (export "locals") (result i32) (local i32 i32 i32 i32) (local.get 3)))"#;
+        let module = Module::load(&wat::parse_str(wat).unwrap()).unwrap();
+        let instance = mk_instance(module).unwrap();
+        let mut execution = Execution::<_, (), FixedSlots<3>>::with_storage(
+            instance,
+            VectorMemory::new(0, None),
+            (),
+        );
+
+        // Each frame gets slots of its own, so how deep calls go is up to the stack limits.
+        assert_eq!(
+            execution.invoke("down", &[Value::I32(50)]).unwrap(),
+            vec![Value::I32(50)]
+        );
+        for name in ["deep", "locals"] {
+            assert!(matches!(
+                execution.invoke(name, &[]),
+                Err(ExecError::ExecutionFault(Fault::StackExhausted))
+            ));
+            execution.reset();
+        }
+        assert_eq!(
+            execution.invoke("down", &[Value::I32(3)]).unwrap(),
+            vec![Value::I32(3)]
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "allocator-api")]
+    fn slots_from_an_allocator() {
+        use crate::stack::AllocatorSlots;
+        use allocator_api2::alloc::Global;
+
+        let wat = r#"(module (func $fac (export "fac") (param i64) (result i64)
+            (if (result i64) (i64.eqz (local.get 0))
+                (then (i64.const 1))
+                (else (i64.mul (local.get 0)
+                    (call $fac (i64.sub (local.get 0) (i64.const 1))))))))"#;
+        let module = Module::load(&wat::parse_str(wat).unwrap()).unwrap();
+        let instance = mk_instance(module).unwrap();
+        let mut execution = Execution::<_, (), AllocatorSlots<Global>>::with_storage(
+            instance,
+            VectorMemory::new(0, None),
+            (),
+        );
+        assert_eq!(
+            execution.invoke("fac", &[Value::I64(20)]).unwrap(),
+            vec![Value::I64(2_432_902_008_176_640_000)]
+        );
+    }
+
+    #[test]
+    fn per_function_budgets() {
+        // `dispatch` calls `on_event` once per event; each call spins for its argument's worth
+        // of iterations.
+        let wat = r#"(module
+            (func $spin (param i32)
+                (loop $l
+                    (local.set 0 (i32.sub (local.get 0) (i32.const 1)))
+                    (br_if $l (i32.gt_s (local.get 0) (i32.const 0)))))
+            (func $on_event (export "on_event") (param i32) (call $spin (local.get 0)))
+            (func (export "dispatch") (param i32 i32) (local $i i32)
+                (loop $l
+                    (call $on_event (local.get 1))
+                    (local.set $i (i32.add (local.get $i) (i32.const 1)))
+                    (br_if $l (i32.lt_u (local.get $i) (local.get 0))))))"#;
+        let module = Module::load(&wat::parse_str(wat).unwrap()).unwrap();
+        let instance = mk_instance(module).unwrap();
+        let on_event = instance.find_funcidx("on_event").unwrap();
+        let dispatch = instance.find_funcidx("dispatch").unwrap();
+        let mut execution = Execution::new(instance, VectorMemory::new(0, None));
+        assert!(execution.set_budget_by_name("on_event", 500));
+        assert!(!execution.set_budget_by_name("missing", 500));
+
+        // Many short events add up to far more than one budget, which is fine.
+        execution
+            .prepare(dispatch, &[Value::I32(100), Value::I32(10)])
+            .unwrap();
+        execution.run().unwrap();
+
+        // One long one isn't, and it's what gets the blame.
+        execution
+            .prepare(dispatch, &[Value::I32(3), Value::I32(1000)])
+            .unwrap();
+        assert!(matches!(
+            execution.run(),
+            Err(ExecError::ExecutionFault(Fault::BudgetExceeded(f))) if f == on_event
+        ));
+
+        execution.reset();
+        execution.clear_budget(on_event);
+        execution
+            .prepare(dispatch, &[Value::I32(3), Value::I32(1000)])
+            .unwrap();
+        execution.run().unwrap();
+    }
+
+    #[test]
+    fn nested_if_else_chains() {
+        // Ifs nested in blocks and loops in both arms, so each `If` and `Else` has to jump to
+        // its own arm's end rather than an inner or outer one.
+        let wat = r#"(module
+            (func (export "f") (param i32) (result i32)
+                (if (result i32) (i32.and (local.get 0) (i32.const 1))
+                    (then
+                        (block (result i32)
+                            (if (result i32) (i32.and (local.get 0) (i32.const 2))
+                                (then (i32.const 3))
+                                (else (loop (result i32) (i32.const 1))))))
+                    (else
+                        (if (result i32) (i32.and (local.get 0) (i32.const 2))
+                            (then (block (result i32)
+                                (if (i32.const 0) (then (unreachable)))
+                                (i32.const 2)))
+                            (else (i32.const 0)))))))"#;
+        for (arg, expected) in [(0, 0), (1, 1), (2, 2), (3, 3)] {
+            assert_eq!(run_unary(wat, Value::I32(arg)), Value::I32(expected));
+        }
+    }
+
+    #[test]
+    fn nested_loop_back_edges() {
+        // The outer loop's back-edge comes after a complete inner loop, and the inner loop's
+        // after a block, so each branch has to land on its own loop's head.
+        let wat = r#"(module
+            (func (export "f") (param i32) (result i32)
+                (local $i i32) (local $j i32) (local $sum i32)
+                (loop $outer
+                    (local.set $j (i32.const 0))
+                    (loop $inner
+                        (block (local.set $sum (i32.add (local.get $sum) (local.get $j))))
+                        (local.set $j (i32.add (local.get $j) (i32.const 1)))
+                        (br_if $inner (i32.lt_s (local.get $j) (local.get $i))))
+                    (local.set $i (i32.add (local.get $i) (i32.const 1)))
+                    (br_if $outer (i32.lt_s (local.get $i) (local.get 0))))
+                (local.get $sum)))"#;
+        // Sum over i < n of 0 + 1 + ... + (i - 1).
+        assert_eq!(run_unary(wat, Value::I32(5)), Value::I32(10));
+    }
+
+    /// Every float op run in strict mode, over inputs chosen for their awkwardness, hashed (with
+    /// FNV-1a, which unlike `DefaultHasher` won't change between Rust releases) to a digest which
+    /// has to come out the same on every run and every host.
+    #[test]
+    fn strict_float_results_match_golden_digest() {
+        const GOLDEN: u64 = 0x9aac_8785_fd35_336f;
+        let f64_inputs: [u64; 14] = [
+            0x0000_0000_0000_0000,
+            0x8000_0000_0000_0000,
+            0x3ff0_0000_0000_0000,
+            0xc004_0000_0000_0000,
+            0x3fb9_9999_9999_999a,
+            0x4340_0000_0000_0001,
+            0x7fef_ffff_ffff_ffff,
+            0x0000_0000_0000_0001,
+            0x7ff0_0000_0000_0000,
+            0xfff0_0000_0000_0000,
+            0x7ff8_0000_0000_0000,
+            0xfff8_0000_0000_0000,
+            0x7ff4_0000_0000_0001,
+            0x7ff8_0000_0000_0123,
+        ];
+        let f32_inputs: [u32; 14] = [
+            0x0000_0000,
+            0x8000_0000,
+            0x3f80_0000,
+            0xc020_0000,
+            0x3dcc_cccd,
+            0x4b80_0001,
+            0x7f7f_ffff,
+            0x0000_0001,
+            0x7f80_0000,
+            0xff80_0000,
+            0x7fc0_0000,
+            0xffc0_0000,
+            0x7fa0_0001,
+            0x7fc0_0123,
+        ];
+        let binary = ["add", "sub", "mul", "div", "min", "max", "copysign"];
+        let unary = ["sqrt", "ceil", "floor", "trunc", "nearest", "abs", "neg"];
+        let mut wat = String::from("(module");
+        for ty in ["f32", "f64"] {
+            for op in binary {
+                wat += &format!(
+                    r#"(func (export "{ty}.{op}") (param {ty} {ty}) (result {ty})
+                        ({ty}.{op} (local.get 0) (local.get 1)))"#
+                );
+            }
+            for op in unary {
+                wat += &format!(
+                    r#"(func (export "{ty}.{op}") (param {ty}) (result {ty})
+                        ({ty}.{op} (local.get 0)))"#
+                );
+            }
+        }
+        wat += r#"(func (export "demote") (param f64) (result f32) (f32.demote_f64 (local.get 0)))
+            (func (export "promote") (param f32) (result f64) (f64.promote_f32 (local.get 0)))
+            (func (export "convert") (param f64) (result f32)
+                (f32.convert_i64_u (i64.reinterpret_f64 (local.get 0))))
+            (func (export "trunc_sat") (param f64) (result i64)
+                (i64.trunc_sat_f64_s (local.get 0))))"#;
+        let module = Module::load(&wat::parse_str(&wat).unwrap()).unwrap();
+        let linked = mk_instance(module).unwrap();
+        let mut execution = Execution::new(linked, VectorMemory::new(0, None));
+        execution.set_determinism(Determinism::Strict);
+
+        let mut digest = 0xcbf2_9ce4_8422_2325_u64;
+        let mut call = |name: &str, args: &[Value]| {
+            let bits = match execution.invoke(name, args).unwrap()[0] {
+                Value::F32(f) => f.to_bits() as u64,
+                Value::F64(f) => f.to_bits(),
+                Value::I64(i) => i as u64,
+                other => panic!("unexpected result {other:?}"),
+            };
+            for byte in bits.to_le_bytes() {
+                digest = (digest ^ byte as u64).wrapping_mul(0x0100_0000_01b3);
+            }
+        };
+        let f32s = f32_inputs.map(|b| Value::F32(f32::from_bits(b)));
+        let f64s = f64_inputs.map(|b| Value::F64(f64::from_bits(b)));
+        for (ty, inputs) in [("f32", &f32s), ("f64", &f64s)] {
+            for op in binary {
+                for a in inputs {
+                    for b in inputs {
+                        call(&format!("{ty}.{op}"), &[*a, *b]);
+                    }
+                }
+            }
+            for op in unary {
+                for a in inputs {
+                    call(&format!("{ty}.{op}"), &[*a]);
+                }
+            }
+        }
+        for a in &f64s {
+            call("demote", &[*a]);
+            call("convert", &[*a]);
+            call("trunc_sat", &[*a]);
+        }
+        for a in &f32s {
+            call("promote", &[*a]);
+        }
+        assert_eq!(digest, GOLDEN, "{digest:#018x}");
+    }
+
+    #[test]
+    fn strict_determinism_canonicalizes_nans() {
+        let wat = r#"(module
+            (func (export "add") (param f32) (result f32)
+                (f32.add (local.get 0) (f32.const 1)))
+            (func (export "sqrt") (param f64) (result f64)
+                (f64.sqrt (local.get 0)))
+            (func (export "neg") (param f32) (result f32)
+                (f32.neg (local.get 0))))"#;
+        let module = Module::load(&wat::parse_str(wat).unwrap()).unwrap();
+        let linked = mk_instance(module).unwrap();
+        let mut execution = Execution::new(linked, VectorMemory::new(0, None));
+        execution.set_determinism(Determinism::Strict);
+        let mut call = |name: &str, arg: Value| {
+            let funcidx = execution.instance().find_funcidx(name).unwrap();
+            execution.prepare(funcidx, &[arg]).unwrap();
+            execution.run().unwrap();
+            execution.result().unwrap()[0]
+        };
+
+        // A payload on the way in doesn't survive arithmetic.
+        let Value::F32(sum) = call("add", Value::F32(f32::from_bits(0x7fc0_0123))) else {
+            panic!("expected an f32");
+        };
+        assert_eq!(sum.to_bits(), 0x7fc0_0000);
+        // x86 produces a negative NaN here.
+        let Value::F64(root) = call("sqrt", Value::F64(-1.0)) else {
+            panic!("expected an f64");
+        };
+        assert_eq!(root.to_bits(), 0x7ff8_0000_0000_0000);
+        // Non-NaN results are untouched.
+        assert_eq!(call("sqrt", Value::F64(4.0)), Value::F64(2.0));
+        // Neg only flips the sign bit, which the spec pins down, so is left alone.
+        let Value::F32(negated) = call("neg", Value::F32(f32::from_bits(0x7fc0_0123))) else {
+            panic!("expected an f32");
+        };
+        assert_eq!(negated.to_bits(), 0xffc0_0123);
+    }
+
+    #[test]
+    fn subnormal_results_flush_to_zero() {
+        let wat = r#"(module
+            (func (export "div") (param f32 f32) (result f32)
+                (f32.div (local.get 0) (local.get 1)))
+            (func (export "mul") (param f64 f64) (result f64)
+                (f64.mul (local.get 0) (local.get 1)))
+            (func (export "abs") (param f32) (result f32)
+                (f32.abs (local.get 0))))"#;
+        let module = Module::load(&wat::parse_str(wat).unwrap()).unwrap();
+        let linked = mk_instance(module).unwrap();
+        let mut execution = Execution::new(linked, VectorMemory::new(0, None));
+        let call = |execution: &mut Execution<VectorMemory>, name: &str, args: &[Value]| {
+            let funcidx = execution.instance().find_funcidx(name).unwrap();
+            execution.prepare(funcidx, args).unwrap();
+            execution.run().unwrap();
+            execution.result().unwrap()[0]
+        };
+        let tiny = [Value::F32(f32::MIN_POSITIVE), Value::F32(-4.0)];
+        let Value::F32(kept) = call(&mut execution, "div", &tiny) else {
+            panic!("expected an f32");
+        };
+        assert!(kept.is_subnormal());
+
+        execution.set_subnormals(Subnormals::FlushToZero);
+        let Value::F32(flushed) = call(&mut execution, "div", &tiny) else {
+            panic!("expected an f32");
+        };
+        assert_eq!(flushed.to_bits(), (-0.0f32).to_bits());
+        let Value::F64(flushed) = call(
+            &mut execution,
+            "mul",
+            &[Value::F64(f64::MIN_POSITIVE), Value::F64(0.5)],
+        ) else {
+            panic!("expected an f64");
+        };
+        assert_eq!(flushed, 0.0);
+        // Normal results, and ops which only move bits, are left alone.
+        assert_eq!(
+            call(&mut execution, "div", &[Value::F32(1.0), Value::F32(4.0)]),
+            Value::F32(0.25)
+        );
+        let subnormal = f32::from_bits(1);
+        assert_eq!(
+            call(&mut execution, "abs", &[Value::F32(subnormal)]),
+            Value::F32(subnormal)
+        );
+    }
+
+    fn run_unary(wat: &str, arg: Value) -> Value {
+        let module_data = wat::parse_str(wat).unwrap();
+        let module = Module::load(&module_data).unwrap();
+        let linked = mk_instance(module).unwrap();
+        let funcidx = linked.find_funcidx("f").unwrap();
+        let mut execution = Execution::new(linked, VectorMemory::new(0, None));
+        execution.prepare(funcidx, &[arg]).unwrap();
+        execution.run().unwrap();
+        execution.result().unwrap()[0]
+    }
+
+    #[test]
+    fn externref_round_trips_host_data() {
+        let wat = r#"(module
+            (table $t 1 externref)
+            (func (export "f") (param externref) (result externref)
+                (table.set $t (i32.const 0) (local.get 0))
+                (table.get $t (i32.const 0))))"#;
+        let module = Module::load(&wat::parse_str(wat).unwrap()).unwrap();
+        let linked = mk_instance(module).unwrap();
+        let funcidx = linked.find_funcidx("f").unwrap();
+        let mut execution = Execution::new(linked, VectorMemory::new(0, None));
+        let handle = execution.externs_mut().create(vec![1u8, 2, 3]).unwrap();
+        execution.prepare(funcidx, &[handle]).unwrap();
+        execution.run().unwrap();
+        let result = execution.result().unwrap()[0];
+        assert_eq!(result, handle);
+        assert_eq!(
+            execution.externs().get::<Vec<u8>>(&result),
+            Some(&vec![1, 2, 3])
+        );
+    }
+
+    #[test]
+    fn fault_poisons_until_reset() {
+        let wat = r#"(module
+            (func $inner (param i32) (result i32)
+                (i32.div_s (i32.const 100) (local.get 0)))
+            (func (export "f") (param i32) (result i32)
+                (i32.add (i32.const 1) (call $inner (local.get 0)))))"#;
+        let module = Module::load(&wat::parse_str(wat).unwrap()).unwrap();
+        let linked = mk_instance(module).unwrap();
+        let funcidx = linked.find_funcidx("f").unwrap();
+        let mut execution = Execution::new(linked, VectorMemory::new(0, None));
+        execution.prepare(funcidx, &[Value::I32(0)]).unwrap();
+        assert!(matches!(
+            execution.run(),
+            Err(ExecError::ExecutionFault(Fault::IntegerDivisionByZero))
+        ));
+
+        // Both frames are still there: the caller with its pending 1 on the stack, and the callee
+        // stopped on the div.
+        assert!(execution.is_poisoned());
+        let frames = execution.frame_stack();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].stack.slots(), &[1]);
+        assert_eq!(frames[1].local(0).unwrap(), Value::I32(0));
+        assert_eq!(
+            frames[1].program.ops[frames[1].pc - 1],
+            crate::op::Op::I32DivS
+        );
+
+        // The same, through the public view.
+        let views: Vec<_> = execution.frames().collect();
+        assert_eq!(views[0].func_index(), funcidx);
+        assert_eq!(views[1].func_index(), 0);
+        assert_eq!(views[0].stack_depth(), 1);
+        assert_eq!(views[1].locals(), vec![Value::I32(0)]);
+        assert_eq!(views[1].pc(), frames[1].pc);
+        assert_eq!(
+            views[1].scopes().collect::<Vec<_>>(),
+            vec![ScopeType::Function]
+        );
+
+        assert!(matches!(
+            execution.prepare(funcidx, &[Value::I32(5)]),
+            Err(ExecError::ExecutionFault(Fault::Poisoned))
+        ));
+        execution.reset();
+        assert_eq!(execution.frame_stack_len(), 0);
+        execution.prepare(funcidx, &[Value::I32(5)]).unwrap();
+        execution.run().unwrap();
+        assert_eq!(execution.result().unwrap(), &[Value::I32(21)]);
+    }
+
+    #[test]
+    fn store_into_read_only_range_traps() {
+        let wat = r#"(module
+            (memory 1)
+            (data (i32.const 8) "config")
+            (func (export "f") (param i32) (result i32)
+                (i32.store8 (local.get 0) (i32.const 0x58))
+                (i32.load8_u (local.get 0))))"#;
+        let module = Module::load(&wat::parse_str(wat).unwrap()).unwrap();
+        let linked = mk_instance(module).unwrap();
+        let funcidx = linked.find_funcidx("f").unwrap();
+        let mut memory = ProtectedMemory::new(linked.memories[0].clone());
+        memory.protect(8..14);
+        let mut execution = Execution::new(linked, memory);
+
+        execution.prepare(funcidx, &[Value::I32(14)]).unwrap();
+        execution.run().unwrap();
+        assert_eq!(execution.result().unwrap(), &[Value::I32(0x58)]);
+
+        execution.prepare(funcidx, &[Value::I32(10)]).unwrap();
+        assert!(matches!(
+            execution.run(),
+            Err(ExecError::ExecutionFault(Fault::ReadOnlyMemory))
+        ));
+        let instance = execution.into_instance_with_memory();
+        assert_eq!(&instance.memories[0].data()[8..14], b"config");
+    }
+
+    #[test]
+    fn trap_policy_turns_faults_into_results() {
+        let wat = r#"(module
+            (func $div (param i32) (result i32)
+                (i32.div_s (i32.const 100) (local.get 0)))
+            (func (export "f") (param i32) (result i32)
+                (i32.add (call $div (local.get 0)) (i32.const 1))))"#;
+        let module = Module::load(&wat::parse_str(wat).unwrap()).unwrap();
+        let linked = mk_instance(module).unwrap();
+        let funcidx = linked.find_funcidx("f").unwrap();
+        let mut execution = Execution::new(linked, VectorMemory::new(0, None));
+        let call = |execution: &mut Execution<VectorMemory>, arg: i32| {
+            execution.reset();
+            execution.prepare(funcidx, &[Value::I32(arg)]).unwrap();
+            execution
+                .run()
+                .map(|_| execution.result().unwrap().to_vec())
+        };
+
+        // The faulting function returns -1 instead, and its caller carries on.
+        let reports = std::sync::Arc::new(std::sync::Mutex::new(vec![]));
+        let report = reports.clone();
+        execution.set_trap_policy(move |fault, funcidx| {
+            report.lock().unwrap().push(funcidx);
+            match fault {
+                Fault::IntegerDivisionByZero => TrapDecision::Return(vec![Value::I32(-1)]),
+                _ => TrapDecision::Abort,
+            }
+        });
+        assert_eq!(call(&mut execution, 0).unwrap(), &[Value::I32(0)]);
+        assert_eq!(call(&mut execution, 10).unwrap(), &[Value::I32(11)]);
+        assert_eq!(*reports.lock().unwrap(), vec![0]);
+
+        execution.set_trap_policy(|_, _| TrapDecision::Return(vec![]));
+        assert!(matches!(
+            call(&mut execution, 0),
+            Err(ExecError::ExecutionFault(Fault::InterceptedResultMismatch(
+                0
+            )))
+        ));
+        execution.clear_trap_policy();
+        assert!(matches!(
+            call(&mut execution, 0),
+            Err(ExecError::ExecutionFault(Fault::IntegerDivisionByZero))
+        ));
+    }
+
+    #[test]
+    fn intercepted_calls() {
+        let wat = r#"(module
+            (func $square (param i32) (result i32)
+                (i32.mul (local.get 0) (local.get 0)))
+            (func (export "f") (param i32) (result i32)
+                (i32.add (call $square (local.get 0)) (call $square (i32.const 3)))))"#;
+        let module = Module::load(&wat::parse_str(wat).unwrap()).unwrap();
+        let linked = mk_instance(module).unwrap();
+        let funcidx = linked.find_funcidx("f").unwrap();
+        let mut execution = Execution::new(linked, VectorMemory::new(0, None));
+        let call = |execution: &mut Execution<VectorMemory>, arg: i32| {
+            execution.reset();
+            execution.prepare(funcidx, &[Value::I32(arg)]).unwrap();
+            execution
+                .run()
+                .map(|_| execution.result().unwrap().to_vec())
+        };
+
+        // Trace the arguments, and answer for 3 from a "cache".
+        let traced = std::sync::Arc::new(std::sync::Mutex::new(vec![]));
+        let trace = traced.clone();
+        execution.intercept(0, move |args| {
+            trace.lock().unwrap().push(args.to_vec());
+            match args {
+                [Value::I32(3)] => Intercept::Return(vec![Value::I32(1000)]),
+                _ => Intercept::Proceed,
+            }
+        });
+        assert_eq!(call(&mut execution, 4).unwrap(), &[Value::I32(1016)]);
+        assert_eq!(call(&mut execution, 3).unwrap(), &[Value::I32(2000)]);
+        assert_eq!(
+            *traced.lock().unwrap(),
+            vec![
+                vec![Value::I32(4)],
+                vec![Value::I32(3)],
+                vec![Value::I32(3)],
+                vec![Value::I32(3)]
+            ]
+        );
+
+        execution.intercept(0, |_| Intercept::Return(vec![Value::I64(1)]));
+        assert!(matches!(
+            call(&mut execution, 4),
+            Err(ExecError::ExecutionFault(Fault::InterceptedResultMismatch(
+                0
+            )))
+        ));
+        assert!(execution.remove_interceptor(0));
+        assert!(!execution.remove_interceptor(0));
+        assert_eq!(call(&mut execution, 4).unwrap(), &[Value::I32(25)]);
+    }
+
+    #[test]
+    fn effective_address_does_not_wrap() {
+        // Cases from address.wast: the base is unsigned, and base + offset is never truncated.
+        let wat = r#"(module
+            (memory 1)
+            (data (i32.const 0) "abcdefghijklmnopqrstuvwxyz")
+            (func (export "8u_good") (param i32) (result i32)
+                (i32.load8_u offset=25 (local.get 0)))
+            (func (export "8u_bad") (param i32) (result i32)
+                (i32.load8_u offset=4294967295 (local.get 0)))
+            (func (export "32_good") (param i32) (result i32)
+                (i32.load offset=65532 (local.get 0)))
+            (func (export "64_store") (param i32)
+                (i64.store offset=65528 (local.get 0) (i64.const 0))))"#;
+        let module = Module::load(&wat::parse_str(wat).unwrap()).unwrap();
+        let linked = mk_instance(module).unwrap();
+        let memory = linked.memories[0].clone();
+        let mut execution = Execution::new(linked, memory);
+        let mut call = |name: &str, base: i32| {
+            let funcidx = execution.instance().find_funcidx(name).unwrap();
+            execution.reset();
+            execution.prepare(funcidx, &[Value::I32(base)]).unwrap();
+            execution
+                .run()
+                .map(|_| execution.result().unwrap().to_vec())
+        };
+        let oob = |r: Result<Vec<Value>, ExecError>| {
+            matches!(r, Err(ExecError::ExecutionFault(Fault::MemoryOutOfBounds)))
+        };
+
+        assert_eq!(call("8u_good", 0).unwrap(), &[Value::I32(b'z' as i32)]);
+        assert_eq!(call("8u_good", 65510).unwrap(), &[Value::I32(0)]);
+        assert!(oob(call("8u_good", 65511)));
+        assert!(oob(call("8u_good", -1)));
+        assert!(oob(call("8u_bad", 0)));
+        assert!(oob(call("8u_bad", 1)));
+        assert_eq!(call("32_good", 0).unwrap(), &[Value::I32(0)]);
+        assert!(oob(call("32_good", 1)));
+        assert!(oob(call("32_good", -4)));
+        assert_eq!(call("64_store", 0).unwrap(), &[]);
+        assert!(oob(call("64_store", 1)));
+        assert!(oob(call("64_store", i32::MIN)));
+    }
+
+    #[test]
+    fn memory_grow_overflow_and_hook() {
+        let wat = r#"(module
+            (memory 1 3)
+            (func (export "grow") (param i32) (result i32)
+                (memory.grow (local.get 0))))"#;
+        let module = Module::load(&wat::parse_str(wat).unwrap()).unwrap();
+        let linked = mk_instance(module).unwrap();
+        let funcidx = linked.find_funcidx("grow").unwrap();
+        let memory = linked.memories[0].clone();
+        let mut execution = Execution::new(linked, memory);
+        let grow = |execution: &mut Execution<VectorMemory>, delta: i32| {
+            execution.prepare(funcidx, &[Value::I32(delta)]).unwrap();
+            execution
+                .run()
+                .map(|_| execution.result().unwrap().to_vec())
+        };
+
+        // Deltas that would overflow, or pass 4GiB, fail without touching memory.
+        for delta in [-1, i32::MIN, 0x1_0000] {
+            assert_eq!(grow(&mut execution, delta).unwrap(), &[Value::I32(-1)]);
+        }
+
+        let requests = std::sync::Arc::new(std::sync::Mutex::new(vec![]));
+        let seen = requests.clone();
+        execution.on_memory_grow(move |old, new| {
+            seen.lock().unwrap().push((old, new));
+            match new {
+                2 => GrowDecision::Allow,
+                3 => GrowDecision::Deny,
+                _ => GrowDecision::Trap,
+            }
+        });
+        assert_eq!(grow(&mut execution, 1).unwrap(), &[Value::I32(1)]);
+        assert_eq!(grow(&mut execution, 1).unwrap(), &[Value::I32(-1)]);
+        assert!(matches!(
+            grow(&mut execution, 2),
+            Err(ExecError::ExecutionFault(Fault::CannotGrowMemory))
+        ));
+        assert_eq!(*requests.lock().unwrap(), vec![(1, 2), (2, 3), (2, 4)]);
+        assert_eq!(execution.memory.size(), 2 * WASM_PAGE_SIZE);
+    }
+
+    #[test]
+    fn execution_moves_between_threads() {
+        let wat = r#"(module
+            (memory 1)
+            (global $count (mut i32) (i32.const 0))
+            (func (export "bump") (param i32) (result i32)
+                (global.set $count (i32.add (global.get $count) (i32.const 1)))
+                (i32.store (local.get 0) (global.get $count))
+                (i32.div_u (global.get $count) (local.get 0))))"#;
+        let module = Module::load(&wat::parse_str(wat).unwrap()).unwrap();
+        let linked = mk_instance(module).unwrap();
+        let funcidx = linked.find_funcidx("bump").unwrap();
+        let memory = linked.memories[0].clone();
+        let mut execution = Execution::new(linked, memory);
+
+        // Prepared here, run on another thread.
+        execution.prepare(funcidx, &[Value::I32(4)]).unwrap();
+        let mut execution = std::thread::spawn(move || {
+            execution.run().unwrap();
+            assert_eq!(execution.result().unwrap(), &[Value::I32(0)]);
+            // Leave it stopped on a fault for the next owner.
+            execution.prepare(funcidx, &[Value::I32(0)]).unwrap();
+            assert!(execution.run().is_err());
+            execution
+        })
+        .join()
+        .unwrap();
+
+        assert!(execution.is_poisoned());
+        assert_eq!(execution.frame_stack().len(), 1);
+        execution.reset();
+        execution.prepare(funcidx, &[Value::I32(8)]).unwrap();
+        execution.run().unwrap();
+        assert_eq!(execution.result().unwrap(), &[Value::I32(0)]);
+        let instance = execution.into_instance_with_memory();
+        assert_eq!(instance.globals[0].value, Value::I32(3));
+        assert_eq!(instance.memories[0].data()[4], 1);
+        assert_eq!(instance.memories[0].data()[8], 3);
+    }
+
+    #[test]
+    #[cfg(feature = "alignment-diagnostics")]
+    fn misaligned_accesses_are_reported() {
+        use crate::exec::MisalignedAccess;
+        use std::sync::{Arc, Mutex};
+
+        let wat = r#"(module
+            (memory 1)
+            (func (export "f") (param i32) (result i32)
+                (i32.store (local.get 0) (i32.const 7))
+                (drop (i64.load align=1 (i32.const 1)))
+                (i32.add
+                    (i32.load8_u (local.get 0))
+                    (i32.load16_u offset=1 (local.get 0)))))"#;
+        let module = Module::load(&wat::parse_str(wat).unwrap()).unwrap();
+        let linked = mk_instance(module).unwrap();
+        let funcidx = linked.find_funcidx("f").unwrap();
+        let memory = linked.memories[0].clone();
+        let mut execution = Execution::new(linked, memory);
+        let seen = Arc::new(Mutex::new(vec![]));
+        let log = seen.clone();
+        execution.on_misaligned_access(move |access| log.lock().unwrap().push(access));
+
+        // Byte accesses and ones which declared no alignment never are misaligned, so only the
+        // 16-bit load past an odd offset is reported.
+        execution.prepare(funcidx, &[Value::I32(8)]).unwrap();
+        execution.run().unwrap();
+        assert_eq!(execution.result().unwrap(), &[Value::I32(7)]);
+        assert_eq!(seen.lock().unwrap().len(), 1);
+
+        seen.lock().unwrap().clear();
+        execution.prepare(funcidx, &[Value::I32(6)]).unwrap();
+        execution.run().unwrap();
+        let seen = seen.lock().unwrap();
+        assert_eq!(seen.len(), 2);
+        assert!(matches!(
+            seen[0],
+            MisalignedAccess {
+                address: 6,
+                align: 4,
+                ..
+            }
+        ));
+        assert_eq!(seen[0].funcidx, funcidx);
+        assert!(matches!(
+            seen[1],
+            MisalignedAccess {
+                address: 7,
+                align: 2,
+                ..
+            }
+        ));
+        assert!(seen[1].pc > seen[0].pc);
+    }
+
+    #[test]
+    #[cfg(feature = "atomics")]
+    fn atomics_on_shared_memory() {
+        let wat = r#"(module
+            (memory 1 1 shared)
+            (func (export "add") (param i32 i32) (result i32)
+                (i32.atomic.rmw.add (local.get 0) (local.get 1)))
+            (func (export "add8") (param i32 i32) (result i32)
+                (i32.atomic.rmw8.add_u (local.get 0) (local.get 1)))
+            (func (export "load") (param i32) (result i32)
+                (i32.atomic.load (local.get 0)))
+            (func (export "cas") (param i32 i64 i64) (result i64)
+                (i64.atomic.rmw16.cmpxchg_u (local.get 0) (local.get 1) (local.get 2)))
+            (func (export "xchg") (param i32 i64) (result i64)
+                (i64.atomic.rmw32.xchg_u (local.get 0) (local.get 1)))
+            (func (export "wait") (param i32 i32) (result i32)
+                (memory.atomic.wait32 (local.get 0) (local.get 1) (i64.const -1)))
+            (func (export "notify") (param i32) (result i32)
+                (atomic.fence)
+                (memory.atomic.notify (local.get 0) (i32.const 1))))"#;
+        let module = Module::load(&wat::parse_str(wat).unwrap()).unwrap();
+        assert!(module.memories[0].shared);
+        let linked = mk_instance(module).unwrap();
+        let memory = linked.memories[0].clone();
+        let mut execution = Execution::new(linked, memory);
+        let mut call = |name: &str, args: &[Value]| {
+            let funcidx = execution.instance().find_funcidx(name).unwrap();
+            execution.prepare(funcidx, args).unwrap();
+            execution
+                .run()
+                .map(|_| execution.result().unwrap().to_vec())
+        };
+
+        // Read-modify-write ops hand back the old value.
+        assert_eq!(
+            call("add", &[Value::I32(8), Value::I32(5)]).unwrap(),
+            &[Value::I32(0)]
+        );
+        assert_eq!(
+            call("add", &[Value::I32(8), Value::I32(-1)]).unwrap(),
+            &[Value::I32(5)]
+        );
+        assert_eq!(call("load", &[Value::I32(8)]).unwrap(), &[Value::I32(4)]);
+        assert_eq!(
+            call("add8", &[Value::I32(8), Value::I32(0xff)]).unwrap(),
+            &[Value::I32(4)]
+        );
+        assert_eq!(call("load", &[Value::I32(8)]).unwrap(), &[Value::I32(3)]);
+
+        // cmpxchg only stores when the expected value matches, narrowed to the access width.
+        let cas = |expected: i64, replacement: i64| {
+            [Value::I32(8), Value::I64(expected), Value::I64(replacement)]
+        };
+        assert_eq!(call("cas", &cas(7, 9)).unwrap(), &[Value::I64(3)]);
+        assert_eq!(
+            call("cas", &cas(0x1_0003, 0x2_abcd)).unwrap(),
+            &[Value::I64(3)]
+        );
+        assert_eq!(
+            call("load", &[Value::I32(8)]).unwrap(),
+            &[Value::I32(0xabcd)]
+        );
+        assert_eq!(
+            call("xchg", &[Value::I32(8), Value::I64(-1)]).unwrap(),
+            &[Value::I64(0xabcd)]
+        );
+        assert_eq!(call("load", &[Value::I32(8)]).unwrap(), &[Value::I32(-1)]);
+
+        // With one thread, a wait either sees a different value or would block forever.
+        assert_eq!(
+            call("wait", &[Value::I32(8), Value::I32(0)]).unwrap(),
+            &[Value::I32(1)]
+        );
+        assert_eq!(
+            call("wait", &[Value::I32(8), Value::I32(-1)]).unwrap(),
+            &[Value::I32(2)]
+        );
+        assert_eq!(call("notify", &[Value::I32(8)]).unwrap(), &[Value::I32(0)]);
+
+        assert!(matches!(
+            call("add", &[Value::I32(6), Value::I32(1)]),
+            Err(ExecError::ExecutionFault(Fault::UnalignedAtomic))
+        ));
+        execution.reset();
+        let funcidx = execution.instance().find_funcidx("load").unwrap();
+        execution.prepare(funcidx, &[Value::I32(65536)]).unwrap();
+        assert!(matches!(
+            execution.run(),
+            Err(ExecError::ExecutionFault(Fault::MemoryOutOfBounds))
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "stats")]
+    fn run_collects_stats() {
+        let wat = r#"(module
+            (memory 1)
+            (func $down (export "f") (param i32) (result i32)
+                (if (result i32) (local.get 0)
+                    (then (call $down (i32.sub (local.get 0) (i32.const 1))))
+                    (else (memory.grow (i32.const 2))))))"#;
+        let module = Module::load(&wat::parse_str(wat).unwrap()).unwrap();
+        let linked = mk_instance(module).unwrap();
+        let funcidx = linked.find_funcidx("f").unwrap();
+        let memory = linked.memories[0].clone();
+        let mut execution = Execution::new(linked, memory);
+        execution.prepare(funcidx, &[Value::I32(3)]).unwrap();
+        execution.run().unwrap();
+        assert_eq!(execution.result().unwrap(), &[Value::I32(1)]);
+        let stats = execution.stats();
+        assert_eq!(stats.max_frame_depth, 4);
+        assert_eq!(stats.grows, 1);
+        assert_eq!(stats.mem_pages_end, 3);
+        assert!(stats.max_stack_slots >= 2);
+        let ops_first = stats.ops_executed;
+        assert!(ops_first > 0);
+
+        // Stats are per run, not cumulative.
+        execution.prepare(funcidx, &[Value::I32(0)]).unwrap();
+        execution.run().unwrap();
+        assert_eq!(execution.stats().max_frame_depth, 1);
+        assert!(execution.stats().ops_executed < ops_first);
+        assert_eq!(execution.stats().mem_pages_end, 5);
+    }
+
+    #[test]
+    fn i32_wrap_i64() {
+        let wat = r#"(module (func (export "f") (param i64) (result i32)
+            (i32.wrap_i64 (local.get 0))))"#;
+        // Cases from conversions.wast
+        let cases: &[(i64, i32)] = &[
+            (-1, -1),
+            (-100000, -100000),
+            (0x80000000, i32::MIN),
+            (0xffffffff7fffffff_u64 as i64, 0x7fffffff),
+            (0xffffffff00000000_u64 as i64, 0),
+            (0xfffffffeffffffff_u64 as i64, -1),
+            (0xffffffff00000001_u64 as i64, 1),
+            (0, 0),
+            (1311768467463790320, 0x9abcdef0_u32 as i32),
+            (0x00000000ffffffff, -1),
+            (0x0000000100000000, 0),
+            (0x0000000100000001, 1),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(
+                run_unary(wat, Value::I64(*input)),
+                Value::I32(*expected),
+                "i32.wrap_i64 {input:#x}"
+            );
+        }
+    }
+
+    #[test]
+    fn i64_extend_i32() {
+        let signed = r#"(module (func (export "f") (param i32) (result i64)
+            (i64.extend_i32_s (local.get 0))))"#;
+        let unsigned = r#"(module (func (export "f") (param i32) (result i64)
+            (i64.extend_i32_u (local.get 0))))"#;
+        let cases: &[(i32, i64, i64)] = &[
+            (0, 0, 0),
+            (10000, 10000, 10000),
+            (-10000, -10000, 0x00000000ffffd8f0),
+            (-1, -1, 0xffffffff),
+            (0x7fffffff, 0x7fffffff, 0x7fffffff),
+            (i32::MIN, -0x80000000, 0x80000000),
+        ];
+        for (input, expected_s, expected_u) in cases {
+            assert_eq!(
+                run_unary(signed, Value::I32(*input)),
+                Value::I64(*expected_s)
+            );
+            assert_eq!(
+                run_unary(unsigned, Value::I32(*input)),
+                Value::I64(*expected_u)
+            );
+        }
+    }
+
+    #[test]
+    fn table_set_takes_the_index_below_the_value() {
+        let wat = r#"(module
+            (table $t 2 externref)
+            (func (export "f") (param externref) (result externref)
+                (table.set $t (i32.const 1) (local.get 0))
+                (table.get $t (i32.const 1))))"#;
+        assert_eq!(
+            run_unary(wat, Value::ExternRef(Some(7))),
+            Value::ExternRef(Some(7))
+        );
+    }
+
+    #[cfg(feature = "gc")]
+    const GC_MODULE: &str = r#"(module
+        (type $pair (struct (field i32) (field (mut i32))))
+        (type $bytes (array (mut i8)))
+        (global $kept (mut anyref) (ref.null any))
+        (func (export "f") (param i32) (result i32)
+            ;; Only the last of these survives past the call.
+            (global.set $kept (struct.new $pair (i32.const 1) (i32.const 2)))
+            (global.set $kept (struct.new $pair (local.get 0) (i32.const 7)))
+            (i32.add
+                (i32.add
+                    (struct.get $pair 0 (ref.cast (ref $pair) (global.get $kept)))
+                    (array.get_s $bytes
+                        (array.new $bytes (i32.const 0xff) (i32.const 4))
+                        (i32.const 2)))
+                (i32.add
+                    (i31.get_s (ref.i31 (i32.const -2)))
+                    (ref.test (ref $bytes) (struct.new_default $pair))))))"#;
+
+    #[test]
+    #[cfg(feature = "gc")]
+    fn gc_structs_and_arrays() {
+        // x + (i8)0xff + -2 + 0
+        assert_eq!(run_unary(GC_MODULE, Value::I32(10)), Value::I32(7));
+    }
+
+    #[test]
+    #[cfg(feature = "gc")]
+    fn gc_collects_unreachable() {
+        let module = Module::load(&wat::parse_str(GC_MODULE).unwrap()).unwrap();
+        let linked = mk_instance(module).unwrap();
+        let funcidx = linked.find_funcidx("f").unwrap();
+        let mut execution = Execution::new(linked, VectorMemory::new(0, None));
+        execution.prepare(funcidx, &[Value::I32(10)]).unwrap();
+        execution.run().unwrap();
+        assert_eq!(execution.gc_heap().len(), 5);
+        assert_eq!(execution.collect_garbage(), Some(4));
+        assert_eq!(execution.gc_heap().len(), 1);
+    }
+
+    #[test]
+    #[cfg(feature = "gc")]
+    fn pinned_refs_survive_collection() {
+        let wat = r#"(module
+            (type $pair (struct (field i32) (field i32)))
+            (func (export "make") (param i32) (result anyref)
+                (struct.new $pair (local.get 0) (i32.const 0)))
+            (func (export "first") (param anyref) (result i32)
+                (struct.get $pair 0 (ref.cast (ref $pair) (local.get 0)))))"#;
+        let module = Module::load(&wat::parse_str(wat).unwrap()).unwrap();
+        let instance = mk_instance(module).unwrap();
+        let mut execution = Execution::new(instance, VectorMemory::new(0, None));
+        let held = execution.invoke("make", &[Value::I32(5)]).unwrap()[0];
+        assert!(execution.pin_ref(&held));
+        assert!(execution.pin_ref(&held));
+        assert!(!execution.pin_ref(&Value::I32(0)));
+
+        // Neither the result nor anything else in the guest refers to `held` after this.
+        execution.invoke("make", &[Value::I32(6)]).unwrap();
+        assert_eq!(execution.collect_garbage(), Some(0));
+        assert_eq!(
+            execution.invoke("first", &[held]).unwrap(),
+            vec![Value::I32(5)]
+        );
+        assert_eq!(execution.collect_garbage(), Some(1));
+        assert_eq!(
+            execution.invoke("first", &[held]).unwrap(),
+            vec![Value::I32(5)]
+        );
+
+        // Pins count.
+        assert!(execution.unpin_ref(&held));
+        assert_eq!(execution.collect_garbage(), Some(0));
+        assert!(execution.unpin_ref(&held));
+        assert!(!execution.unpin_ref(&held));
+        assert_eq!(execution.collect_garbage(), Some(1));
+    }
+
+    #[test]
+    #[cfg(feature = "gc")]
+    fn gc_heap_limit() {
+        use crate::{InstanceBuilder, InstanceLimits};
+        let wat = r#"(module
+            (type $bytes (array (mut i8)))
+            (func (export "new") (param i32) (result i32)
+                (array.len (array.new_default $bytes (local.get 0)))))"#;
+        let module = Module::load(&wat::parse_str(wat).unwrap()).unwrap();
+        let new = |execution: &mut Execution<VectorMemory>, len: u32| {
+            execution.reset();
+            execution.invoke("new", &[Value::I32(len as i32)])
+        };
+        let fault = |result| {
+            matches!(
+                result,
+                Err(ExecError::ExecutionFault(Fault::GcHeapExhausted))
+            )
+        };
+
+        // The guest asking for 4 billion elements faults rather than taking the host down.
+        let instance = mk_instance(module.clone()).unwrap();
+        let mut execution = Execution::new(instance, VectorMemory::new(0, None));
+        assert!(fault(new(&mut execution, u32::MAX)));
+        assert_eq!(execution.gc_heap().values(), 0);
+
+        let instance = InstanceBuilder::new(module)
+            .limits(InstanceLimits {
+                max_gc_values: Some(10),
+                ..Default::default()
+            })
+            .build()
+            .unwrap();
+        let mut execution = Execution::new(instance, VectorMemory::new(0, None));
+        assert_eq!(new(&mut execution, 6).unwrap(), vec![Value::I32(6)]);
+        assert_eq!(new(&mut execution, 4).unwrap(), vec![Value::I32(4)]);
+        assert_eq!(execution.gc_heap().values(), 10);
+        assert!(fault(new(&mut execution, 1)));
+        // Collecting makes room again.
+        execution.reset();
+        assert_eq!(execution.collect_garbage(), Some(2));
+        assert_eq!(execution.gc_heap().values(), 0);
+        assert_eq!(new(&mut execution, 10).unwrap(), vec![Value::I32(10)]);
+    }
 }
@@ -0,0 +1,266 @@
+// Copyright (C) 2025 Ryan Daum <ryan.daum@gmail.com> This program is free
+// software: you can redistribute it and/or modify it under the terms of the GNU
+// General Public License as published by the Free Software Foundation, version
+// 3.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+//! Host data behind `externref`s.
+//! To the guest an `externref` is just an opaque handle; the `ExternTable` is where the host keeps
+//! the Rust values those handles stand for.
+
+use crate::exec::Value;
+use std::any::Any;
+
+const INDEX_BITS: u32 = 20;
+const INDEX_MASK: u32 = (1 << INDEX_BITS) - 1;
+/// Generations wrap before reaching this, so that no handle is ever `u32::MAX`, which is how
+/// null is represented on the stack.
+const GENERATION_LIMIT: u32 = (1 << (32 - INDEX_BITS)) - 1;
+
+struct ExternSlot {
+    generation: u32,
+    refs: u32,
+    value: Option<Box<dyn Any + Send>>,
+}
+
+/// Run when an `ExternTable` is disposed of or dropped, with the table, so that whatever the host
+/// tied to the guest's lifetime can be released.
+pub type Finalizer = Box<dyn FnOnce(&mut ExternTable) + Send>;
+
+/// Reference counted storage for host values handed to the guest as `externref`s.
+///
+/// Handles carry a generation alongside the slot index, so a handle the guest kept hold of after
+/// its value was dropped will resolve to nothing rather than to whatever reused the slot.
+///
+/// The counts are only the host's: copies of a handle on the guest's stack, in its locals, or in
+/// its tables aren't counted. A host that gives the guest a handle it may keep around must keep a
+/// reference of its own for as long as it wants that handle to stay valid.
+///
+/// Finalizers registered with `on_drop` run when the table is dropped, or earlier through
+/// `run_finalizers`, most recently registered first.
+#[derive(Default)]
+pub struct ExternTable {
+    slots: Vec<ExternSlot>,
+    free: Vec<u32>,
+    finalizers: Vec<Finalizer>,
+}
+
+impl ExternTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Store `value`, returning an `externref` to it with a single reference held. Returns
+    /// `None`, dropping `value`, if the table already holds as many live values as handles can
+    /// tell apart.
+    pub fn create<T: Any + Send>(&mut self, value: T) -> Option<Value> {
+        let value: Box<dyn Any + Send> = Box::new(value);
+        let index = match self.free.pop() {
+            Some(index) => {
+                let slot = &mut self.slots[index as usize];
+                slot.refs = 1;
+                slot.value = Some(value);
+                index
+            }
+            None => {
+                if self.slots.len() > INDEX_MASK as usize {
+                    return None;
+                }
+                self.slots.push(ExternSlot {
+                    generation: 0,
+                    refs: 1,
+                    value: Some(value),
+                });
+                (self.slots.len() - 1) as u32
+            }
+        };
+        let generation = self.slots[index as usize].generation;
+        Some(Value::ExternRef(Some(generation << INDEX_BITS | index)))
+    }
+
+    fn slot(&self, handle: u32) -> Option<&ExternSlot> {
+        let slot = self.slots.get((handle & INDEX_MASK) as usize)?;
+        (slot.value.is_some() && slot.generation == handle >> INDEX_BITS).then_some(slot)
+    }
+
+    fn slot_mut(&mut self, handle: u32) -> Option<&mut ExternSlot> {
+        let slot = self.slots.get_mut((handle & INDEX_MASK) as usize)?;
+        (slot.value.is_some() && slot.generation == handle >> INDEX_BITS).then_some(slot)
+    }
+
+    fn handle_of(reference: &Value) -> Option<u32> {
+        match reference {
+            Value::ExternRef(Some(handle)) => Some(*handle),
+            _ => None,
+        }
+    }
+
+    /// The value behind `reference`, if it's a live externref holding a `T`.
+    pub fn get<T: Any>(&self, reference: &Value) -> Option<&T> {
+        let slot = self.slot(Self::handle_of(reference)?)?;
+        slot.value.as_ref()?.downcast_ref()
+    }
+
+    pub fn get_mut<T: Any>(&mut self, reference: &Value) -> Option<&mut T> {
+        let slot = self.slot_mut(Self::handle_of(reference)?)?;
+        slot.value.as_mut()?.downcast_mut()
+    }
+
+    /// Take another reference to the value behind `reference`. Returns false if it's not live.
+    pub fn retain(&mut self, reference: &Value) -> bool {
+        let Some(slot) = Self::handle_of(reference).and_then(|h| self.slot_mut(h)) else {
+            return false;
+        };
+        slot.refs += 1;
+        true
+    }
+
+    /// Give up a reference to the value behind `reference`. When the last reference goes, the
+    /// value is removed and handed back. Dropping a dead or null reference does nothing.
+    pub fn drop_ref(&mut self, reference: &Value) -> Option<Box<dyn Any + Send>> {
+        let handle = Self::handle_of(reference)?;
+        let slot = self.slot_mut(handle)?;
+        slot.refs -= 1;
+        if slot.refs > 0 {
+            return None;
+        }
+        slot.generation = (slot.generation + 1) % GENERATION_LIMIT;
+        let value = slot.value.take();
+        self.free.push(handle & INDEX_MASK);
+        value
+    }
+
+    /// The number of live values.
+    pub fn len(&self) -> usize {
+        self.slots.len() - self.free.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Have `finalizer` run when the table goes away, after any registered before it.
+    pub fn on_drop(&mut self, finalizer: impl FnOnce(&mut ExternTable) + Send + 'static) {
+        self.finalizers.push(Box::new(finalizer));
+    }
+
+    /// Run every finalizer now, most recently registered first, including any registered by the
+    /// finalizers themselves. Each runs only once: they're gone from the table afterwards.
+    pub fn run_finalizers(&mut self) {
+        while let Some(finalizer) = self.finalizers.pop() {
+            finalizer(self);
+        }
+    }
+}
+
+impl Drop for ExternTable {
+    fn drop(&mut self) {
+        self.run_finalizers();
+    }
+}
+
+impl std::fmt::Debug for ExternTable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ExternTable")
+            .field("live", &self.len())
+            .field("finalizers", &self.finalizers.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::exec::{Execution, Value};
+    use crate::externs::{ExternTable, INDEX_MASK};
+    use crate::instance::mk_instance;
+    use crate::memory::VectorMemory;
+    use crate::module::Module;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn refcounted_host_values() {
+        let mut externs = ExternTable::new();
+        let name = externs.create(String::from("hello")).unwrap();
+        let count = externs.create(5u32).unwrap();
+        assert_eq!(externs.get::<String>(&name).unwrap(), "hello");
+        // Asking for the wrong type, or for a null, gets nothing.
+        assert!(externs.get::<u32>(&name).is_none());
+        assert!(externs.get::<u32>(&Value::ExternRef(None)).is_none());
+
+        *externs.get_mut::<u32>(&count).unwrap() += 1;
+        assert_eq!(externs.get::<u32>(&count), Some(&6));
+
+        assert!(externs.retain(&name));
+        assert!(externs.drop_ref(&name).is_none());
+        assert_eq!(externs.len(), 2);
+        let dropped = externs.drop_ref(&name).unwrap();
+        assert_eq!(dropped.downcast_ref::<String>().unwrap(), "hello");
+        assert_eq!(externs.len(), 1);
+        assert!(externs.get::<String>(&name).is_none());
+        assert!(!externs.retain(&name));
+    }
+
+    #[test]
+    fn stale_handles_do_not_alias() {
+        let mut externs = ExternTable::new();
+        let first = externs.create(1i64).unwrap();
+        externs.drop_ref(&first);
+        let second = externs.create(2i64).unwrap();
+        assert_ne!(first, second);
+        assert!(externs.get::<i64>(&first).is_none());
+        assert!(externs.drop_ref(&first).is_none());
+        assert_eq!(externs.get::<i64>(&second), Some(&2));
+    }
+
+    #[test]
+    fn full_table_refuses_new_values() {
+        let mut externs = ExternTable::new();
+        let handles: Vec<_> = (0..=INDEX_MASK)
+            .map(|_| externs.create(()).unwrap())
+            .collect();
+        assert!(externs.create(()).is_none());
+
+        // Dropping one frees its slot for the next.
+        externs.drop_ref(&handles[7]);
+        assert!(externs.create(()).is_some());
+        assert!(externs.create(()).is_none());
+    }
+
+    #[test]
+    fn finalizers_run_once_in_reverse() {
+        let module = Module::load(&wat::parse_str("(module)").unwrap()).unwrap();
+        let mut execution =
+            Execution::new(mk_instance(module).unwrap(), VectorMemory::new(0, None));
+        let log = Arc::new(Mutex::new(vec![]));
+
+        let file = execution
+            .externs_mut()
+            .create(String::from("file"))
+            .unwrap();
+        let l = log.clone();
+        execution.on_drop(move |externs| {
+            let name = externs.drop_ref(&file).unwrap();
+            l.lock().unwrap().push(*name.downcast::<String>().unwrap());
+        });
+        let l = log.clone();
+        execution.on_drop(move |_| l.lock().unwrap().push("second".to_string()));
+
+        execution.dispose();
+        assert_eq!(*log.lock().unwrap(), ["second", "file"]);
+        assert!(execution.externs().is_empty());
+
+        // Disposing again has nothing left to run, but dropping runs anything registered since.
+        execution.dispose();
+        let l = log.clone();
+        execution.on_drop(move |_| l.lock().unwrap().push("dropped".to_string()));
+        drop(execution);
+        assert_eq!(*log.lock().unwrap(), ["second", "file", "dropped"]);
+    }
+}
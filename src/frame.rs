@@ -11,99 +11,320 @@
 // this program. If not, see <https://www.gnu.org/licenses/>.
 //
 
-use crate::decode::{Program, ScopeType};
+use crate::decode::{Program, ScopeSig, ScopeType};
 use crate::exec::{Fault, Value};
-use crate::stack::Stack;
-use crate::{Type, ValueType};
-
-pub struct Frame {
-    pub locals: Vec<Value>,
-    pub return_types: Vec<ValueType>,
-    pub program: Program,
-    pub stack: Stack,
-    pub pc: usize,
-    pub control_stack: Vec<Control>,
+use crate::stack::{HeapSlots, SlotStorage, Stack};
+use crate::ValueType;
+use std::fmt::{Display, Formatter};
+use std::sync::Arc;
+
+#[derive(Clone)]
+pub struct Frame<S: SlotStorage = HeapSlots> {
+    /// Locals, as stack slots laid out according to `program.local_offsets`.
+    pub(crate) locals: Stack<S>,
+    pub(crate) return_types: Vec<ValueType>,
+    pub(crate) program: Arc<Program>,
+    pub(crate) stack: Stack<S>,
+    pub(crate) pc: usize,
+    pub(crate) control_stack: Vec<Control>,
+    /// The function this frame is running, counting imports; 0 for a free-standing expression.
+    pub(crate) funcidx: u32,
 }
 
+#[derive(Clone)]
 pub struct Control {
-    pub signature: Type,
     pub scope_type: ScopeType,
+    /// How many slots a branch to this scope carries: its params for a loop, its results for
+    /// anything else.
+    pub arity: u32,
+    /// How many slots are left on the stack when the scope ends.
+    pub results: u32,
     pub stack_width: usize,
+    /// The pc just past the scope's `StartScope`, where a branch back to a loop resumes.
+    pub start_pc: usize,
+    /// How many times a branch has gone back to the start of this loop since it was entered.
+    pub back_edges: u64,
+}
+
+/// How many finished frames' worth of buffers we hang on to. Deep recursion will allocate past
+/// this, but the common case of a shallow call tree being entered over and over won't.
+const MAX_POOLED_FRAMES: usize = 64;
+
+/// Storage recycled from frames which have finished executing, so that call-heavy code isn't
+/// allocating a fresh value stack, locals and control stack on every call.
+///
+/// An execution starts with an empty pool which fills as frames finish. Embedders who want to
+/// keep calls off the allocator can instead hand it one filled up front with `with_capacity`,
+/// via `Execution::set_frame_pool`, and use `misses` to size it. Frames still on the stack go
+/// back to the pool on `Execution::reset`.
+///
+/// That covers calls from guest functions to guest functions, which is where the allocations
+/// were. Calls to host functions still allocate the results they return, lazily decoded
+/// functions allocate when first called, and memory growth, GC objects and a run's own results
+/// allocate as always. Where the value stacks and locals come from in the first place is up to
+/// the execution's `SlotStorage`, which can draw them from an arena of the embedder's own with
+/// `AllocatorSlots`; frames' other buffers, decoded functions and the rest still come from the
+/// global allocator.
+pub struct FramePool<S: SlotStorage = HeapSlots> {
+    stacks: Vec<Stack<S>>,
+    locals: Vec<Stack<S>>,
+    return_types: Vec<Vec<ValueType>>,
+    control_stacks: Vec<Vec<Control>>,
+    /// Scratch space for the arguments or results being passed between two frames.
+    values: Vec<Value>,
+    /// The most frames' worth of buffers kept.
+    limit: usize,
+    /// How many frames were built from fresh buffers, for want of any to reuse.
+    misses: u64,
+}
+
+impl<S: SlotStorage> Default for FramePool<S> {
+    fn default() -> Self {
+        FramePool {
+            stacks: vec![],
+            locals: vec![],
+            return_types: vec![],
+            control_stacks: vec![],
+            values: vec![],
+            limit: MAX_POOLED_FRAMES,
+            misses: 0,
+        }
+    }
+}
+
+impl<S: SlotStorage> FramePool<S> {
+    /// A pool holding buffers for calls `frames` deep, each with room for `slots` values, locals
+    /// and nested blocks. Frames needing more than that grow their buffers as usual.
+    pub fn with_capacity(frames: usize, slots: usize) -> Self {
+        FramePool {
+            stacks: (0..frames).map(|_| Stack::with_capacity(slots)).collect(),
+            locals: (0..frames).map(|_| Stack::with_capacity(slots)).collect(),
+            return_types: (0..frames).map(|_| Vec::with_capacity(slots)).collect(),
+            control_stacks: (0..frames).map(|_| Vec::with_capacity(slots)).collect(),
+            values: Vec::with_capacity(slots),
+            limit: frames.max(MAX_POOLED_FRAMES),
+            misses: 0,
+        }
+    }
+
+    /// How many frames' worth of buffers are ready to be reused.
+    pub fn len(&self) -> usize {
+        self.stacks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stacks.is_empty()
+    }
+
+    /// How many frames have been given freshly allocated buffers because the pool was empty.
+    pub fn misses(&self) -> u64 {
+        self.misses
+    }
+
+    pub(crate) fn take_stack(&mut self) -> Stack<S> {
+        self.stacks.pop().unwrap_or_else(|| {
+            self.misses += 1;
+            Stack::default()
+        })
+    }
+
+    pub(crate) fn take_locals(&mut self) -> Stack<S> {
+        self.locals.pop().unwrap_or_default()
+    }
+
+    pub(crate) fn take_return_types(&mut self) -> Vec<ValueType> {
+        self.return_types.pop().unwrap_or_default()
+    }
+
+    pub(crate) fn take_control_stack(&mut self) -> Vec<Control> {
+        self.control_stacks.pop().unwrap_or_default()
+    }
+
+    /// An empty buffer for `values` values passed between frames, to hand back with
+    /// `recycle_values` once they've been.
+    pub(crate) fn take_values(&mut self, values: usize) -> Vec<Value> {
+        let mut buffer = std::mem::take(&mut self.values);
+        buffer.resize(values, Value::Unit);
+        buffer
+    }
+
+    pub(crate) fn recycle_values(&mut self, mut buffer: Vec<Value>) {
+        if buffer.capacity() > self.values.capacity() {
+            buffer.clear();
+            self.values = buffer;
+        }
+    }
+
+    /// Return a finished frame's buffers to the pool, emptied but with their capacity intact.
+    pub(crate) fn recycle(&mut self, frame: Frame<S>) {
+        if self.stacks.len() >= self.limit {
+            return;
+        }
+        let Frame {
+            mut locals,
+            mut return_types,
+            mut stack,
+            mut control_stack,
+            ..
+        } = frame;
+        locals.shrink_to(0);
+        return_types.clear();
+        stack.shrink_to(0);
+        control_stack.clear();
+        self.stacks.push(stack);
+        self.locals.push(locals);
+        self.return_types.push(return_types);
+        self.control_stacks.push(control_stack);
+    }
 }
 
 impl Frame {
-    pub fn new(num_locals: usize, program: Program) -> Self {
+    /// A frame with its locals all zeroed.
+    pub fn new(program: Program) -> Self {
         let return_types = program.return_types.clone();
+        let mut locals = Stack::new();
+        for local_type in &program.local_types {
+            Value::default_for(*local_type).push_to(&mut locals);
+        }
         Frame {
-            locals: vec![Value::Unit; num_locals],
+            locals,
             stack: Stack::new(),
             pc: 0,
-            program,
+            program: Arc::new(program),
             control_stack: vec![],
             return_types,
+            funcidx: 0,
         }
     }
+}
 
-    pub fn push_control(&mut self, signature: Type, scope_type: ScopeType) {
+impl<S: SlotStorage> Frame<S> {
+    pub fn push_control(&mut self, signature: ScopeSig, scope_type: ScopeType) {
+        let arity = signature.branch_arity(scope_type);
+        // A scope's params are already on the stack when it's entered, and belong to the scope
+        // rather than to what's underneath it. So does an if's condition, which sits on top of
+        // them and is consumed by the `If` op which follows.
+        let condition = match scope_type {
+            ScopeType::IfElse => 1,
+            _ => 0,
+        };
+        let inputs = signature.params as usize + condition;
         self.control_stack.push(Control {
-            signature,
             scope_type,
-            stack_width: self.stack.width(),
+            arity,
+            results: signature.results,
+            stack_width: self.stack.width().saturating_sub(inputs),
+            start_pc: self.pc,
+            back_edges: 0,
         });
     }
 
-    pub fn pop_control(&mut self) -> Result<(Control, Vec<Value>), Fault> {
+    /// Leave the innermost scope, leaving its results on top of the stack as it was when the
+    /// scope was entered.
+    pub fn pop_control(&mut self) -> Result<Control, Fault> {
         let c = self
             .control_stack
             .pop()
             .ok_or(Fault::ControlStackUnderflow)?;
+        self.stack.keep_top(c.results as usize, c.stack_width)?;
+        Ok(c)
+    }
 
-        // Pop the result values BEFORE shrinking the stack
-        let results = match &c.signature {
-            Type::ValueType(vt) => {
-                if *vt != ValueType::Unit {
-                    vec![Value::pop_from(*vt, &mut self.stack)?]
-                } else {
-                    vec![]
-                }
-            }
-            Type::FunctionType(ft) => {
-                // Pre-allocate and assign by index to avoid double-reverse
-                let mut results = vec![Value::Unit; ft.results.len()];
-                for (i, vt) in ft.results.iter().enumerate().rev() {
-                    results[i] = Value::pop_from(*vt, &mut self.stack)?;
-                }
-                results
-            }
-        };
-
-        // Note: We don't shrink the stack here - that's the caller's responsibility
-        // The caller can decide whether to shrink the stack and push results back
-
-        Ok((c, results))
+    /// The start and width of a local's slots.
+    #[inline]
+    fn local_slots(&self, local_index: u32) -> Result<(usize, usize), Fault> {
+        let offsets = &self.program.local_offsets;
+        let i = local_index as usize;
+        match (offsets.get(i), offsets.get(i + 1)) {
+            (Some(&start), Some(&end)) => Ok((start, end - start)),
+            _ => Err(Fault::LocalIndexOutOfBounds),
+        }
     }
 
     pub fn push_local_to_stack(&mut self, local_index: u32) -> Result<(), Fault> {
-        if local_index as usize >= self.locals.len() {
-            return Err(Fault::LocalIndexOutOfBounds);
-        }
-        self.locals[local_index as usize].push_to(&mut self.stack);
-        Ok(())
+        let (at, n) = self.local_slots(local_index)?;
+        self.stack.push_copy(&self.locals, at, n)
     }
 
     pub fn set_local_from_stack(&mut self, local_index: u32, pop: bool) -> Result<(), Fault> {
-        if local_index as usize >= self.locals.len() {
-            return Err(Fault::LocalIndexOutOfBounds);
+        let (at, n) = self.local_slots(local_index)?;
+        self.stack.store_top(&mut self.locals, at, n, pop)
+    }
+
+    pub fn local_i32(&self, local_index: u32) -> Result<i32, Fault> {
+        let (at, _) = self.local_slots(local_index)?;
+        self.locals.i32_at(at)
+    }
+
+    /// The current value of a local, for inspection.
+    pub fn local(&self, local_index: u32) -> Result<Value, Fault> {
+        let (at, n) = self.local_slots(local_index)?;
+        let mut scratch = Stack::<S>::with_capacity(n);
+        scratch.push_copy(&self.locals, at, n)?;
+        Value::pop_from(self.program.local_types[local_index as usize], &mut scratch)
+    }
+}
+
+/// One frame of a guest stack trace, as `Execution::capture_stack_trace` takes it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceFrame {
+    /// The function the frame is running, counting imports.
+    pub funcidx: u32,
+    /// The name the module exports the function under, if it does.
+    pub name: Option<String>,
+    /// The index in the function's decoded ops of the op the frame last ran: the call to the
+    /// frame above, for all but the innermost.
+    pub pc: usize,
+}
+
+impl Display for TraceFrame {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.name {
+            Some(name) => write!(f, "{name} (func {}) at op {}", self.funcidx, self.pc),
+            None => write!(f, "func {} at op {}", self.funcidx, self.pc),
         }
-        let type_of_local = self.program.local_types[local_index as usize];
+    }
+}
 
-        let value = if pop {
-            Value::pop_from(type_of_local, &mut self.stack)?
-        } else {
-            Value::top_of(type_of_local, &mut self.stack)?
-        };
+/// A read-only view of a live frame, for debuggers and the like.
+#[derive(Clone, Copy)]
+pub struct FrameView<'a, S: SlotStorage = HeapSlots> {
+    frame: &'a Frame<S>,
+}
+
+impl<'a, S: SlotStorage> FrameView<'a, S> {
+    pub(crate) fn new(frame: &'a Frame<S>) -> Self {
+        FrameView { frame }
+    }
+
+    /// The function the frame is running, counting imports.
+    pub fn func_index(&self) -> u32 {
+        self.frame.funcidx
+    }
+
+    /// Position in the function's decoded ops, just past the op being executed.
+    pub fn pc(&self) -> usize {
+        self.frame.pc
+    }
+
+    /// Parameters then declared locals, by local index.
+    pub fn locals(&self) -> Vec<Value> {
+        (0..self.frame.program.local_types.len() as u32)
+            .filter_map(|idx| self.frame.local(idx).ok())
+            .collect()
+    }
+
+    pub fn local(&self, idx: u32) -> Option<Value> {
+        self.frame.local(idx).ok()
+    }
+
+    /// The scopes the pc is inside, outermost (the function's own) first.
+    pub fn scopes(&self) -> impl Iterator<Item = ScopeType> + 'a {
+        self.frame.control_stack.iter().map(|c| c.scope_type)
+    }
 
-        self.locals[local_index as usize] = value;
-        Ok(())
+    /// How many slots are on the frame's operand stack.
+    pub fn stack_depth(&self) -> usize {
+        self.frame.stack.width()
     }
 }
@@ -0,0 +1,793 @@
+// Copyright (C) 2025 Ryan Daum <ryan.daum@gmail.com> This program is free
+// software: you can redistribute it and/or modify it under the terms of the GNU
+// General Public License as published by the Free Software Foundation, version
+// 3.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+//! Groundwork for the garbage collection proposal: struct and array types, typed references to
+//! them, and a simple non-moving heap to hold the objects themselves.
+//!
+//! GC references are held on the stack as plain handles into the `GcHeap`. Nothing is ever
+//! collected behind the guest's back; collection only happens when the embedder asks for it via
+//! `Execution::collect_garbage`, at a point where no guest code is running and so the only roots
+//! are globals and tables, the last result, and whatever the host has pinned.
+
+use crate::exec::{Fault, Value};
+use crate::module::LEB128Reader;
+use crate::opcode::GcOpCode;
+use crate::stack::{SlotStorage, Stack};
+use crate::{DecodeError, FuncType, ValueType};
+use std::collections::HashMap;
+
+pub(crate) const COMP_FUNC: u8 = 0x60;
+pub(crate) const COMP_STRUCT: u8 = 0x5f;
+pub(crate) const COMP_ARRAY: u8 = 0x5e;
+pub(crate) const REC_GROUP: u8 = 0x4e;
+pub(crate) const SUB: u8 = 0x50;
+pub(crate) const SUB_FINAL: u8 = 0x4f;
+pub(crate) const REF_NON_NULL: u8 = 0x64;
+pub(crate) const REF_NULLABLE: u8 = 0x63;
+
+/// What a struct field or array element holds. Packed types are stored widened to i32.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StorageType {
+    Val(ValueType),
+    I8,
+    I16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FieldType {
+    pub storage: StorageType,
+    pub mutable: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum CompositeType {
+    Func(FuncType),
+    Struct(Vec<FieldType>),
+    Array(FieldType),
+}
+
+/// An entry in the type section, which under the GC proposal can be any composite type, and can
+/// declare supertypes.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SubType {
+    pub is_final: bool,
+    pub supertypes: Vec<u32>,
+    pub composite: CompositeType,
+}
+
+/// The target of a reference type, either one of the abstract heap types or a type index.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeapType {
+    Func,
+    Extern,
+    Any,
+    Eq,
+    I31,
+    Struct,
+    Array,
+    None,
+    NoFunc,
+    NoExtern,
+    Concrete(u32),
+}
+
+impl HeapType {
+    fn from_abstract(byte: u8) -> Result<Self, DecodeError> {
+        Ok(match byte {
+            0x70 => HeapType::Func,
+            0x6f => HeapType::Extern,
+            0x6e => HeapType::Any,
+            0x6d => HeapType::Eq,
+            0x6c => HeapType::I31,
+            0x6b => HeapType::Struct,
+            0x6a => HeapType::Array,
+            0x71 => HeapType::None,
+            0x73 => HeapType::NoFunc,
+            0x72 => HeapType::NoExtern,
+            _ => return Err(DecodeError::InvalidSignature(byte as u32)),
+        })
+    }
+
+    /// Heap types are encoded as a signed 33-bit LEB, where the abstract types are the negative
+    /// single-byte values and anything else is a type index.
+    pub(crate) fn read(reader: &mut LEB128Reader) -> Result<Self, DecodeError> {
+        let value = reader.load_imm_signed_varint64()?;
+        if value < 0 {
+            if value < -64 {
+                return Err(DecodeError::InvalidSignature(value as u32));
+            }
+            return Self::from_abstract((value + 0x80) as u8);
+        }
+        if value > u32::MAX as i64 {
+            return Err(DecodeError::InvalidSignature(value as u32));
+        }
+        Ok(HeapType::Concrete(value as u32))
+    }
+
+    /// The value type a reference to this heap type is held as. We don't track concrete types for
+    /// values, so these collapse down to the three reference hierarchies.
+    pub(crate) fn value_type(&self) -> ValueType {
+        match self {
+            HeapType::Func | HeapType::NoFunc => ValueType::FuncRef,
+            HeapType::Extern | HeapType::NoExtern => ValueType::ExternRef,
+            _ => ValueType::AnyRef,
+        }
+    }
+}
+
+/// Read the remainder of a `(ref null? ht)` value type, after its 0x63/0x64 prefix.
+pub(crate) fn read_ref_type(reader: &mut LEB128Reader) -> Result<ValueType, DecodeError> {
+    Ok(HeapType::read(reader)?.value_type())
+}
+
+fn read_field_type(reader: &mut LEB128Reader) -> Result<FieldType, DecodeError> {
+    let storage = match reader.load_imm_u8()? {
+        0x78 => StorageType::I8,
+        0x77 => StorageType::I16,
+        REF_NON_NULL | REF_NULLABLE => StorageType::Val(read_ref_type(reader)?),
+        other => StorageType::Val(ValueType::from_u32(other as u32)?),
+    };
+    let mutable = match reader.load_imm_u8()? {
+        0 => false,
+        1 => true,
+        m => {
+            return Err(DecodeError::FailedToDecode(format!(
+                "Invalid field mutability: {m:#0x}"
+            )))
+        }
+    };
+    Ok(FieldType { storage, mutable })
+}
+
+fn read_func_type(reader: &mut LEB128Reader) -> Result<FuncType, DecodeError> {
+    let num_params = reader.load_imm_varuint32()?;
+    let mut params = reader.with_capacity_for(num_params as usize);
+    for _ in 0..num_params {
+        params.push(ValueType::read(reader)?);
+    }
+    let num_results = reader.load_imm_varuint32()?;
+    let mut results = reader.with_capacity_for(num_results as usize);
+    for _ in 0..num_results {
+        results.push(ValueType::read(reader)?);
+    }
+    Ok(FuncType { params, results })
+}
+
+fn read_composite(marker: u8, reader: &mut LEB128Reader) -> Result<CompositeType, DecodeError> {
+    match marker {
+        COMP_FUNC => Ok(CompositeType::Func(read_func_type(reader)?)),
+        COMP_STRUCT => {
+            let num_fields = reader.load_imm_varuint32()?;
+            let mut fields = reader.with_capacity_for(num_fields as usize);
+            for _ in 0..num_fields {
+                fields.push(read_field_type(reader)?);
+            }
+            Ok(CompositeType::Struct(fields))
+        }
+        COMP_ARRAY => Ok(CompositeType::Array(read_field_type(reader)?)),
+        _ => Err(DecodeError::FailedToDecode(format!(
+            "Invalid composite type marker: {marker:#0x}"
+        ))),
+    }
+}
+
+fn read_sub_type(marker: u8, reader: &mut LEB128Reader) -> Result<SubType, DecodeError> {
+    match marker {
+        SUB | SUB_FINAL => {
+            let supertypes = reader.load_array_varu32()?;
+            let composite_marker = reader.load_imm_u8()?;
+            Ok(SubType {
+                is_final: marker == SUB_FINAL,
+                supertypes,
+                composite: read_composite(composite_marker, reader)?,
+            })
+        }
+        _ => Ok(SubType {
+            is_final: true,
+            supertypes: vec![],
+            composite: read_composite(marker, reader)?,
+        }),
+    }
+}
+
+/// Read a single entry from the type section, which may be a recursion group defining several
+/// types at once.
+pub(crate) fn read_type_entry(reader: &mut LEB128Reader) -> Result<Vec<SubType>, DecodeError> {
+    let marker = reader.load_imm_u8()?;
+    if marker == REC_GROUP {
+        let count = reader.load_imm_varuint32()?;
+        let mut types = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let marker = reader.load_imm_u8()?;
+            types.push(read_sub_type(marker, reader)?);
+        }
+        return Ok(types);
+    }
+    Ok(vec![read_sub_type(marker, reader)?])
+}
+
+impl StorageType {
+    pub(crate) fn default_value(&self) -> Value {
+        match self {
+            StorageType::Val(vt) => Value::default_for(*vt),
+            StorageType::I8 | StorageType::I16 => Value::I32(0),
+        }
+    }
+
+    /// Narrow a value to what this storage type can hold.
+    pub(crate) fn pack(&self, value: Value) -> Value {
+        match (self, value) {
+            (StorageType::I8, Value::I32(v)) => Value::I32(v & 0xff),
+            (StorageType::I16, Value::I32(v)) => Value::I32(v & 0xffff),
+            _ => value,
+        }
+    }
+
+    /// Widen a stored value back out to an operand, sign extending packed values if asked.
+    pub(crate) fn unpack(&self, value: Value, signed: bool) -> Value {
+        match (self, value, signed) {
+            (StorageType::I8, Value::I32(v), true) => Value::I32(v as i8 as i32),
+            (StorageType::I16, Value::I32(v), true) => Value::I32(v as i16 as i32),
+            _ => value,
+        }
+    }
+
+    /// The type used to move values of this storage type on and off the stack.
+    pub(crate) fn operand_type(&self) -> ValueType {
+        match self {
+            StorageType::Val(vt) => *vt,
+            StorageType::I8 | StorageType::I16 => ValueType::I32,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum GcObjectKind {
+    Struct(Vec<Value>),
+    Array(Vec<Value>),
+    I31(i32),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GcObject {
+    /// The index of the object's type in the module's type section. Meaningless for i31s.
+    pub type_idx: u32,
+    pub kind: GcObjectKind,
+}
+
+impl GcObject {
+    fn values(&self) -> &[Value] {
+        match &self.kind {
+            GcObjectKind::Struct(fields) => fields,
+            GcObjectKind::Array(elements) => elements,
+            GcObjectKind::I31(_) => &[],
+        }
+    }
+
+    fn references(&self) -> impl Iterator<Item = u32> + '_ {
+        self.values().iter().filter_map(|v| match v {
+            Value::AnyRef(Some(handle)) => Some(*handle),
+            _ => None,
+        })
+    }
+}
+
+/// The most values, struct fields and array elements together, an instance's GC heap holds
+/// unless `InstanceLimits::max_gc_values` says otherwise. At 16 bytes a value, that's 256MiB.
+pub const DEFAULT_MAX_GC_VALUES: u64 = 1 << 24;
+
+/// A simple non-moving heap of GC objects, addressed by handle. Freed slots are reused.
+#[derive(Debug, Clone, Default)]
+pub struct GcHeap {
+    objects: Vec<Option<GcObject>>,
+    free: Vec<u32>,
+    /// How many values the live objects hold between them.
+    values: u64,
+    /// Objects the host holds references to, and how many times each has been pinned.
+    pinned: HashMap<u32, usize>,
+}
+
+impl GcHeap {
+    pub fn alloc(&mut self, object: GcObject) -> u32 {
+        self.values += object.values().len() as u64;
+        if let Some(handle) = self.free.pop() {
+            self.objects[handle as usize] = Some(object);
+            return handle;
+        }
+        self.objects.push(Some(object));
+        (self.objects.len() - 1) as u32
+    }
+
+    pub fn get(&self, handle: u32) -> Result<&GcObject, Fault> {
+        self.objects
+            .get(handle as usize)
+            .and_then(|o| o.as_ref())
+            .ok_or(Fault::InvalidRefType)
+    }
+
+    pub fn get_mut(&mut self, handle: u32) -> Result<&mut GcObject, Fault> {
+        self.objects
+            .get_mut(handle as usize)
+            .and_then(|o| o.as_mut())
+            .ok_or(Fault::InvalidRefType)
+    }
+
+    /// The number of values, struct fields and array elements, the live objects hold.
+    pub fn values(&self) -> u64 {
+        self.values
+    }
+
+    /// The number of live objects.
+    pub fn len(&self) -> usize {
+        self.objects.len() - self.free.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Keep the object behind `handle` through collections until it's unpinned as many times as
+    /// it's been pinned. Returns false, pinning nothing, if there's no such object.
+    pub fn pin(&mut self, handle: u32) -> bool {
+        if self.get(handle).is_err() {
+            return false;
+        }
+        *self.pinned.entry(handle).or_default() += 1;
+        true
+    }
+
+    /// Undo one `pin` of `handle`. Returns whether it was pinned.
+    pub fn unpin(&mut self, handle: u32) -> bool {
+        let Some(count) = self.pinned.get_mut(&handle) else {
+            return false;
+        };
+        *count -= 1;
+        if *count == 0 {
+            self.pinned.remove(&handle);
+        }
+        true
+    }
+
+    /// Mark everything reachable from `roots` or pinned, and free the rest. Returns the number of
+    /// objects freed.
+    pub fn collect(&mut self, roots: impl IntoIterator<Item = u32>) -> usize {
+        let mut marked = vec![false; self.objects.len()];
+        let mut worklist: Vec<u32> = roots.into_iter().collect();
+        worklist.extend(self.pinned.keys());
+        while let Some(handle) = worklist.pop() {
+            let Some(Some(object)) = self.objects.get(handle as usize) else {
+                continue;
+            };
+            if std::mem::replace(&mut marked[handle as usize], true) {
+                continue;
+            }
+            worklist.extend(object.references());
+        }
+        let mut freed = 0;
+        for (handle, slot) in self.objects.iter_mut().enumerate() {
+            if marked[handle] {
+                continue;
+            }
+            if let Some(object) = slot.take() {
+                self.values -= object.values().len() as u64;
+                self.free.push(handle as u32);
+                freed += 1;
+            }
+        }
+        freed
+    }
+
+    /// Whether the (non-null) reference `handle` is an instance of `heap_type`.
+    pub(crate) fn ref_matches(
+        &self,
+        handle: u32,
+        heap_type: HeapType,
+        types: &[SubType],
+    ) -> Result<bool, Fault> {
+        // Function and extern references don't live in the heap, and there's only the one kind of
+        // each, so any non-null one matches.
+        match heap_type {
+            HeapType::Func | HeapType::Extern => return Ok(true),
+            HeapType::NoFunc | HeapType::NoExtern | HeapType::None => return Ok(false),
+            HeapType::Concrete(idx) => {
+                if let Some(SubType {
+                    composite: CompositeType::Func(_),
+                    ..
+                }) = types.get(idx as usize)
+                {
+                    return Ok(true);
+                }
+            }
+            _ => {}
+        }
+        let object = self.get(handle)?;
+        Ok(match (heap_type, &object.kind) {
+            (HeapType::Any | HeapType::Eq, _) => true,
+            (HeapType::I31, GcObjectKind::I31(_)) => true,
+            (HeapType::Struct, GcObjectKind::Struct(_)) => true,
+            (HeapType::Array, GcObjectKind::Array(_)) => true,
+            (HeapType::Concrete(idx), GcObjectKind::Struct(_) | GcObjectKind::Array(_)) => {
+                is_subtype(types, object.type_idx, idx)
+            }
+            _ => false,
+        })
+    }
+}
+
+/// Whether type `sub` is `sup` or declares it as a (transitive) supertype.
+fn is_subtype(types: &[SubType], sub: u32, sup: u32) -> bool {
+    let mut current = sub;
+    // Supertype chains can't be cyclic in a valid module, but don't trust that.
+    for _ in 0..=types.len() {
+        if current == sup {
+            return true;
+        }
+        match types
+            .get(current as usize)
+            .and_then(|t| t.supertypes.first())
+        {
+            Some(next) => current = *next,
+            None => return false,
+        }
+    }
+    false
+}
+
+/// The decoded form of the 0xFB-prefixed GC instructions we support.
+#[derive(Clone, Debug, PartialEq)]
+pub enum GcOp {
+    StructNew(u32),
+    StructNewDefault(u32),
+    /// Type index, field index, and for packed fields whether to sign extend.
+    StructGet(u32, u32, bool),
+    StructSet(u32, u32),
+    ArrayNew(u32),
+    ArrayNewDefault(u32),
+    ArrayNewFixed(u32, u32),
+    /// Type index, and for packed elements whether to sign extend.
+    ArrayGet(u32, bool),
+    ArraySet(u32),
+    ArrayLen,
+    ArrayFill(u32),
+    /// Destination type index, source type index.
+    ArrayCopy(u32, u32),
+    /// Target heap type, and whether null passes.
+    RefTest(HeapType, bool),
+    RefCast(HeapType, bool),
+    RefI31,
+    I31Get(bool),
+}
+
+impl GcOp {
+    /// Decode the instruction following an 0xFB prefix.
+    pub(crate) fn read(reader: &mut LEB128Reader) -> Result<Self, DecodeError> {
+        Ok(match GcOpCode::read(reader)? {
+            GcOpCode::StructNew => GcOp::StructNew(reader.load_imm_varuint32()?),
+            GcOpCode::StructNewDefault => GcOp::StructNewDefault(reader.load_imm_varuint32()?),
+            code @ (GcOpCode::StructGet | GcOpCode::StructGetS | GcOpCode::StructGetU) => {
+                let type_idx = reader.load_imm_varuint32()?;
+                let field = reader.load_imm_varuint32()?;
+                GcOp::StructGet(type_idx, field, code == GcOpCode::StructGetS)
+            }
+            GcOpCode::StructSet => {
+                let type_idx = reader.load_imm_varuint32()?;
+                GcOp::StructSet(type_idx, reader.load_imm_varuint32()?)
+            }
+            GcOpCode::ArrayNew => GcOp::ArrayNew(reader.load_imm_varuint32()?),
+            GcOpCode::ArrayNewDefault => GcOp::ArrayNewDefault(reader.load_imm_varuint32()?),
+            GcOpCode::ArrayNewFixed => {
+                let type_idx = reader.load_imm_varuint32()?;
+                GcOp::ArrayNewFixed(type_idx, reader.load_imm_varuint32()?)
+            }
+            code @ (GcOpCode::ArrayGet | GcOpCode::ArrayGetS | GcOpCode::ArrayGetU) => {
+                GcOp::ArrayGet(reader.load_imm_varuint32()?, code == GcOpCode::ArrayGetS)
+            }
+            GcOpCode::ArraySet => GcOp::ArraySet(reader.load_imm_varuint32()?),
+            GcOpCode::ArrayLen => GcOp::ArrayLen,
+            GcOpCode::ArrayFill => GcOp::ArrayFill(reader.load_imm_varuint32()?),
+            GcOpCode::ArrayCopy => {
+                let dst = reader.load_imm_varuint32()?;
+                GcOp::ArrayCopy(dst, reader.load_imm_varuint32()?)
+            }
+            code @ (GcOpCode::RefTest | GcOpCode::RefTestNull) => {
+                GcOp::RefTest(HeapType::read(reader)?, code == GcOpCode::RefTestNull)
+            }
+            code @ (GcOpCode::RefCast | GcOpCode::RefCastNull) => {
+                GcOp::RefCast(HeapType::read(reader)?, code == GcOpCode::RefCastNull)
+            }
+            GcOpCode::RefI31 => GcOp::RefI31,
+            GcOpCode::I31GetS => GcOp::I31Get(true),
+            GcOpCode::I31GetU => GcOp::I31Get(false),
+            other => return Err(other.unimplemented()),
+        })
+    }
+}
+
+/// The GC heap for an instance, along with the type section needed to interpret its objects.
+#[derive(Debug, Clone, Default)]
+pub struct GcStore {
+    pub heap: GcHeap,
+    pub types: Vec<SubType>,
+    /// Allocations which would take the heap past this many values fault with
+    /// `Fault::GcHeapExhausted`.
+    pub max_values: u64,
+}
+
+impl GcStore {
+    pub(crate) fn new(types: Vec<SubType>, max_values: u64) -> Self {
+        GcStore {
+            heap: GcHeap::default(),
+            types,
+            max_values,
+        }
+    }
+
+    /// Fail unless the heap has room for `values` more values. Checked before allocating, since
+    /// array lengths come from the guest.
+    fn reserve(&self, values: u64) -> Result<(), Fault> {
+        match self.heap.values.checked_add(values) {
+            Some(total) if total <= self.max_values => Ok(()),
+            _ => Err(Fault::GcHeapExhausted),
+        }
+    }
+
+    fn struct_fields(&self, type_idx: u32) -> Result<&[FieldType], Fault> {
+        match self.types.get(type_idx as usize).map(|t| &t.composite) {
+            Some(CompositeType::Struct(fields)) => Ok(fields),
+            _ => Err(Fault::UnresolvableTypeIndex(type_idx)),
+        }
+    }
+
+    fn array_element(&self, type_idx: u32) -> Result<FieldType, Fault> {
+        match self.types.get(type_idx as usize).map(|t| &t.composite) {
+            Some(CompositeType::Array(element)) => Ok(*element),
+            _ => Err(Fault::UnresolvableTypeIndex(type_idx)),
+        }
+    }
+
+    fn pop_object<S: SlotStorage>(&mut self, stack: &mut Stack<S>) -> Result<&mut GcObject, Fault> {
+        let handle = stack.pop_ref()?.ok_or(Fault::NullReference)?;
+        self.heap.get_mut(handle)
+    }
+
+    fn pop_array<S: SlotStorage>(
+        &mut self,
+        stack: &mut Stack<S>,
+    ) -> Result<&mut Vec<Value>, Fault> {
+        match &mut self.pop_object(stack)?.kind {
+            GcObjectKind::Array(elements) => Ok(elements),
+            _ => Err(Fault::InvalidRefType),
+        }
+    }
+
+    fn alloc(&mut self, type_idx: u32, kind: GcObjectKind) -> Value {
+        Value::AnyRef(Some(self.heap.alloc(GcObject { type_idx, kind })))
+    }
+
+    pub(crate) fn execute<S: SlotStorage>(
+        &mut self,
+        op: &GcOp,
+        stack: &mut Stack<S>,
+    ) -> Result<(), Fault> {
+        match *op {
+            GcOp::StructNew(type_idx) => {
+                let fields = self.struct_fields(type_idx)?;
+                self.reserve(fields.len() as u64)?;
+                let mut values = vec![Value::Unit; fields.len()];
+                for (i, field) in fields.iter().enumerate().rev() {
+                    let value = Value::pop_from(field.storage.operand_type(), stack)?;
+                    values[i] = field.storage.pack(value);
+                }
+                self.alloc(type_idx, GcObjectKind::Struct(values))
+                    .push_to(stack);
+            }
+            GcOp::StructNewDefault(type_idx) => {
+                self.reserve(self.struct_fields(type_idx)?.len() as u64)?;
+                let values = self
+                    .struct_fields(type_idx)?
+                    .iter()
+                    .map(|f| f.storage.default_value())
+                    .collect();
+                self.alloc(type_idx, GcObjectKind::Struct(values))
+                    .push_to(stack);
+            }
+            GcOp::StructGet(type_idx, field, signed) => {
+                let storage = self
+                    .struct_fields(type_idx)?
+                    .get(field as usize)
+                    .ok_or(Fault::InvalidRefType)?
+                    .storage;
+                let GcObjectKind::Struct(values) = &self.pop_object(stack)?.kind else {
+                    return Err(Fault::InvalidRefType);
+                };
+                let value = *values.get(field as usize).ok_or(Fault::InvalidRefType)?;
+                storage.unpack(value, signed).push_to(stack);
+            }
+            GcOp::StructSet(type_idx, field) => {
+                let storage = self
+                    .struct_fields(type_idx)?
+                    .get(field as usize)
+                    .ok_or(Fault::InvalidRefType)?
+                    .storage;
+                let value = Value::pop_from(storage.operand_type(), stack)?;
+                let GcObjectKind::Struct(values) = &mut self.pop_object(stack)?.kind else {
+                    return Err(Fault::InvalidRefType);
+                };
+                let slot = values
+                    .get_mut(field as usize)
+                    .ok_or(Fault::InvalidRefType)?;
+                *slot = storage.pack(value);
+            }
+            GcOp::ArrayNew(type_idx) => {
+                let storage = self.array_element(type_idx)?.storage;
+                let len = stack.pop_u32()?;
+                let value = storage.pack(Value::pop_from(storage.operand_type(), stack)?);
+                self.reserve(len.into())?;
+                self.alloc(type_idx, GcObjectKind::Array(vec![value; len as usize]))
+                    .push_to(stack);
+            }
+            GcOp::ArrayNewDefault(type_idx) => {
+                let storage = self.array_element(type_idx)?.storage;
+                let len = stack.pop_u32()?;
+                self.reserve(len.into())?;
+                let elements = vec![storage.default_value(); len as usize];
+                self.alloc(type_idx, GcObjectKind::Array(elements))
+                    .push_to(stack);
+            }
+            GcOp::ArrayNewFixed(type_idx, len) => {
+                let storage = self.array_element(type_idx)?.storage;
+                self.reserve(len.into())?;
+                let mut elements = vec![Value::Unit; len as usize];
+                for element in elements.iter_mut().rev() {
+                    *element = storage.pack(Value::pop_from(storage.operand_type(), stack)?);
+                }
+                self.alloc(type_idx, GcObjectKind::Array(elements))
+                    .push_to(stack);
+            }
+            GcOp::ArrayGet(type_idx, signed) => {
+                let storage = self.array_element(type_idx)?.storage;
+                let index = stack.pop_u32()?;
+                let elements = self.pop_array(stack)?;
+                let value = *elements
+                    .get(index as usize)
+                    .ok_or(Fault::ArrayOutOfBounds)?;
+                storage.unpack(value, signed).push_to(stack);
+            }
+            GcOp::ArraySet(type_idx) => {
+                let storage = self.array_element(type_idx)?.storage;
+                let value = storage.pack(Value::pop_from(storage.operand_type(), stack)?);
+                let index = stack.pop_u32()?;
+                let elements = self.pop_array(stack)?;
+                let slot = elements
+                    .get_mut(index as usize)
+                    .ok_or(Fault::ArrayOutOfBounds)?;
+                *slot = value;
+            }
+            GcOp::ArrayLen => {
+                let len = self.pop_array(stack)?.len();
+                stack.push_u32(len as u32);
+            }
+            GcOp::ArrayFill(type_idx) => {
+                let storage = self.array_element(type_idx)?.storage;
+                let len = stack.pop_u32()? as usize;
+                let value = storage.pack(Value::pop_from(storage.operand_type(), stack)?);
+                let offset = stack.pop_u32()? as usize;
+                let elements = self.pop_array(stack)?;
+                if offset + len > elements.len() {
+                    return Err(Fault::ArrayOutOfBounds);
+                }
+                elements[offset..offset + len].fill(value);
+            }
+            GcOp::ArrayCopy(_, _) => {
+                let len = stack.pop_u32()? as usize;
+                let src_offset = stack.pop_u32()? as usize;
+                let src = self.pop_array(stack)?;
+                if src_offset + len > src.len() {
+                    return Err(Fault::ArrayOutOfBounds);
+                }
+                // The source and destination may be the same array, so take a copy first.
+                let values = src[src_offset..src_offset + len].to_vec();
+                let dst_offset = stack.pop_u32()? as usize;
+                let dst = self.pop_array(stack)?;
+                if dst_offset + len > dst.len() {
+                    return Err(Fault::ArrayOutOfBounds);
+                }
+                dst[dst_offset..dst_offset + len].copy_from_slice(&values);
+            }
+            GcOp::RefTest(heap_type, nullable) => {
+                let matches = match stack.pop_ref()? {
+                    None => nullable,
+                    Some(handle) => self.heap.ref_matches(handle, heap_type, &self.types)?,
+                };
+                stack.push_i32(matches as i32);
+            }
+            GcOp::RefCast(heap_type, nullable) => {
+                let reference = stack.top_ref()?;
+                let matches = match reference {
+                    None => nullable,
+                    Some(handle) => self.heap.ref_matches(handle, heap_type, &self.types)?,
+                };
+                if !matches {
+                    return Err(Fault::CastFailure);
+                }
+            }
+            GcOp::RefI31 => {
+                let value = stack.pop_i32()?;
+                self.alloc(0, GcObjectKind::I31(value & 0x7fff_ffff))
+                    .push_to(stack);
+            }
+            GcOp::I31Get(signed) => {
+                let GcObjectKind::I31(value) = self.pop_object(stack)?.kind else {
+                    return Err(Fault::InvalidRefType);
+                };
+                stack.push_i32(if signed { (value << 1) >> 1 } else { value });
+            }
+        }
+        Ok(())
+    }
+
+    /// `ref.eq` on two non-null references. i31s are boxed in the heap, so compare by value.
+    pub(crate) fn ref_eq(&self, a: u32, b: u32) -> bool {
+        if a == b {
+            return true;
+        }
+        match (self.heap.get(a), self.heap.get(b)) {
+            (
+                Ok(GcObject {
+                    kind: GcObjectKind::I31(x),
+                    ..
+                }),
+                Ok(GcObject {
+                    kind: GcObjectKind::I31(y),
+                    ..
+                }),
+            ) => x == y,
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::exec::Value;
+    use crate::gc::{GcHeap, GcObject, GcObjectKind};
+
+    #[test]
+    fn collect_frees_unreachable() {
+        let mut heap = GcHeap::default();
+        let leaf = heap.alloc(GcObject {
+            type_idx: 0,
+            kind: GcObjectKind::Struct(vec![Value::I32(1)]),
+        });
+        let root = heap.alloc(GcObject {
+            type_idx: 1,
+            kind: GcObjectKind::Array(vec![Value::AnyRef(Some(leaf)), Value::AnyRef(None)]),
+        });
+        let garbage = heap.alloc(GcObject {
+            type_idx: 0,
+            kind: GcObjectKind::I31(5),
+        });
+        assert_eq!(heap.len(), 3);
+        assert_eq!(heap.collect([root]), 1);
+        assert!(heap.get(garbage).is_err());
+        assert!(heap.get(leaf).is_ok());
+        // The freed slot gets reused.
+        let again = heap.alloc(GcObject {
+            type_idx: 0,
+            kind: GcObjectKind::I31(6),
+        });
+        assert_eq!(again, garbage);
+        assert_eq!(heap.collect([]), 3);
+        assert!(heap.is_empty());
+    }
+}
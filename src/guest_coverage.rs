@@ -0,0 +1,183 @@
+// Copyright (C) 2025 Ryan Daum <ryan.daum@gmail.com> This program is free
+// software: you can redistribute it and/or modify it under the terms of the GNU
+// General Public License as published by the Free Software Foundation, version
+// 3.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+//! Which parts of the guest a test suite ran, for when wasbox is the backend running a guest's
+//! own tests. Unlike `crate::coverage`, which is about the interpreter, this counts how often
+//! each op of each guest function ran, and is collected per execution.
+//!
+//! ```ignore
+//! execution.collect_coverage();
+//! // ... run the guest's tests ...
+//! let coverage = execution.finish_coverage().unwrap();
+//! std::fs::write("lcov.info", coverage.to_lcov(execution.instance(), "guest"))?;
+//! ```
+//!
+//! We don't read DWARF, so there are no source lines to report against. Instead each function
+//! is reported as a file of its own, named after its export if it has one, whose "lines" are its
+//! decoded ops, numbered from 1.
+
+use crate::instance::Instance;
+use crate::module::ImportExportKind;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// How many times each op of each guest function has run.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GuestCoverage {
+    /// By function index, the hit count of each op, as far as the last one to run.
+    hits: BTreeMap<u32, Vec<u64>>,
+}
+
+impl GuestCoverage {
+    #[inline]
+    pub(crate) fn hit(&mut self, funcidx: u32, pc: usize) {
+        let hits = self.hits.entry(funcidx).or_default();
+        if hits.len() <= pc {
+            hits.resize(pc + 1, 0);
+        }
+        hits[pc] += 1;
+    }
+
+    /// How many times the op at `pc` of function `funcidx` has run.
+    pub fn count(&self, funcidx: u32, pc: usize) -> u64 {
+        self.hits
+            .get(&funcidx)
+            .and_then(|hits| hits.get(pc))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// How many times function `funcidx` has been called: how often its first op ran.
+    pub fn calls(&self, funcidx: u32) -> u64 {
+        self.count(funcidx, 0)
+    }
+
+    /// Add in what `other` counted, e.g. from executions run on other threads.
+    pub fn merge(&mut self, other: &GuestCoverage) {
+        for (funcidx, theirs) in &other.hits {
+            let ours = self.hits.entry(*funcidx).or_default();
+            if ours.len() < theirs.len() {
+                ours.resize(theirs.len(), 0);
+            }
+            for (ours, theirs) in ours.iter_mut().zip(theirs) {
+                *ours += theirs;
+            }
+        }
+    }
+
+    /// An lcov tracefile, as `genhtml` and coverage services read, covering every function
+    /// `instance` defines, named `test_name`. A function whose body doesn't decode is left out.
+    pub fn to_lcov(&self, instance: &Instance, test_name: &str) -> String {
+        let mut out = String::new();
+        // Writing to a string can't fail.
+        let _ = self.write_lcov(&mut out, instance, test_name);
+        out
+    }
+
+    /// As `to_lcov`, writing to `out`.
+    pub fn write_lcov(
+        &self,
+        out: &mut impl Write,
+        instance: &Instance,
+        test_name: &str,
+    ) -> std::fmt::Result {
+        let module = &instance.module;
+        let mut names = BTreeMap::new();
+        for export in module.exports() {
+            if export.kind == ImportExportKind::Function {
+                names.entry(export.index).or_insert(export.name.as_str());
+            }
+        }
+        let num_imported = module.num_imported_funcs();
+        for defined in 0..module.functions.len() {
+            let Ok(program) = instance.program(defined) else {
+                continue;
+            };
+            let funcidx = (num_imported + defined) as u32;
+            let name = match names.get(&funcidx) {
+                Some(name) => name.to_string(),
+                None => format!("func[{funcidx}]"),
+            };
+            let ops = program.ops.len();
+            let called = self.calls(funcidx);
+            writeln!(out, "TN:{test_name}")?;
+            writeln!(out, "SF:{name}")?;
+            writeln!(out, "FN:1,{name}")?;
+            writeln!(out, "FNDA:{called},{name}")?;
+            writeln!(out, "FNF:1")?;
+            writeln!(out, "FNH:{}", u8::from(called > 0))?;
+            let mut hit = 0;
+            for pc in 0..ops {
+                let count = self.count(funcidx, pc);
+                hit += usize::from(count > 0);
+                writeln!(out, "DA:{},{count}", pc + 1)?;
+            }
+            writeln!(out, "LF:{ops}")?;
+            writeln!(out, "LH:{hit}")?;
+            writeln!(out, "end_of_record")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::exec::{Execution, Value};
+    use crate::instance::mk_instance;
+    use crate::memory::VectorMemory;
+    use crate::module::Module;
+
+    #[test]
+    fn counts_ops_and_writes_lcov() {
+        let wat = r#"(module
+            (func $unused (result i32) (i32.const 0))
+            (func (export "abs") (param i32) (result i32)
+                (if (result i32) (i32.lt_s (local.get 0) (i32.const 0))
+                    (then (i32.sub (i32.const 0) (local.get 0)))
+                    (else (local.get 0)))))"#;
+        let module = Module::load(&wat::parse_str(wat).unwrap()).unwrap();
+        let linked = mk_instance(module).unwrap();
+        let mut execution = Execution::new(linked, VectorMemory::new(0, None));
+        execution.collect_coverage();
+        for arg in [3, 4] {
+            execution.invoke("abs", &[Value::I32(arg)]).unwrap();
+        }
+        let coverage = execution.finish_coverage().unwrap();
+        assert!(execution.finish_coverage().is_none());
+        assert_eq!(coverage.calls(1), 2);
+        assert_eq!(coverage.calls(0), 0);
+
+        let lcov = coverage.to_lcov(execution.instance(), "abs");
+        let records: Vec<_> = lcov.split_terminator("end_of_record\n").collect();
+        assert_eq!(records.len(), 2);
+        assert!(records[0].contains("SF:func[0]\n"));
+        assert!(records[0].contains("FNDA:0,func[0]\n"));
+        assert!(records[0].contains("LH:0\n"));
+        assert!(records[1].starts_with("TN:abs\nSF:abs\nFN:1,abs\nFNDA:2,abs\n"));
+        assert!(records[1].contains("DA:1,2\n"));
+        // The then arm never ran, so some ops weren't hit.
+        let lines = records[1].lines().filter(|l| l.starts_with("DA:")).count();
+        let hit = records[1]
+            .lines()
+            .find_map(|l| l.strip_prefix("LH:"))
+            .unwrap();
+        assert!(records[1].contains(",0\n"));
+        assert!(hit.parse::<usize>().unwrap() < lines);
+        assert!(records[1].contains(&format!("LF:{lines}\n")));
+
+        // Counts from elsewhere add up.
+        let mut merged = coverage.clone();
+        merged.merge(&coverage);
+        assert_eq!(merged.calls(1), 4);
+    }
+}
@@ -0,0 +1,713 @@
+// Copyright (C) 2025 Ryan Daum <ryan.daum@gmail.com> This program is free
+// software: you can redistribute it and/or modify it under the terms of the GNU
+// General Public License as published by the Free Software Foundation, version
+// 3.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+//! Hibernation: a compact binary image of an execution's state, from which it can be thawed
+//! and carried on with later, in another process or on another host.
+//!
+//! The image holds the call frames with their stacks, locals and control stacks, the values of
+//! globals, the contents of tables, and memory. It doesn't hold the module, which has to be
+//! provided again to thaw, or anything belonging to the host: host functions come from the
+//! linker given to `thaw`, and hooks, interceptors, watchpoints and traces have to be set up
+//! again. Executions holding host values as externrefs, or with objects on the GC heap, can't
+//! be hibernated.
+//!
+//! Everything is little-endian, with counts and most numbers as LEB128, so images are the same
+//! across hosts. Pages of memory which are all zeroes are left out. Optionally only the pages a
+//! [`crate::DirtyTrackingMemory`] has seen written are saved, which thaw lays over the memory
+//! as the module's data segments leave it, so tracking has to have started at instantiation.
+//!
+//! An image records which module it was taken from: a hash of its binary, the layout of its
+//! sections, and the length of each function with a frame in the image. A binary that hashes
+//! the same thaws as is. One that doesn't still thaws if it's laid out the same, section for
+//! section, ignoring custom sections, and the functions being run are the same length, so
+//! stripping debug info is fine, but anything else fails with `ThawError::ModuleMismatch`
+//! rather than resuming into code it wasn't running.
+//!
+//! Debug builds check the kind of every stack slot, and so can only thaw images written by
+//! other debug builds.
+
+use crate::decode::ScopeType;
+use crate::exec::Value;
+use crate::frame::{Control, Frame};
+use crate::instance::{Instance, LinkError};
+use crate::linker::Linker;
+use crate::memory::Memory;
+use crate::memory::{pages_for_bytes, WASM_PAGE_SIZE};
+use crate::module::{write_sleb128, write_uleb128, LEB128Reader, LoaderError};
+use crate::stack::{SlotKind, SlotStorage, Stack};
+use crate::{DecodeError, Module, ValueType, VectorMemory};
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+const MAGIC: &[u8; 4] = b"WBXH";
+const VERSION: u8 = 2;
+/// The most memory a 32-bit address space can use, for memories with no maximum of their own.
+const MAX_MEMORY_SIZE: usize = 1 << 32;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HibernateOptions {
+    /// Save only the pages of memory the guest has written since instantiation, as reported by
+    /// [`Memory::dirty_pages`] along with those the start function wrote, rather than all of
+    /// them.
+    pub dirty_pages_only: bool,
+}
+
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum HibernateError {
+    /// The execution stopped on an error and has to be reset first.
+    Poisoned,
+    /// The host has handed the guest values as externrefs, which can't be saved.
+    HostValues,
+    /// There are objects on the GC heap, which can't be saved.
+    GcHeap,
+    /// Only dirty pages were asked for, but the memory doesn't track them.
+    Untracked,
+    /// The instance shares globals or memory with others, which a thawed one couldn't.
+    Shared,
+    Io(std::io::Error),
+}
+
+impl Display for HibernateError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HibernateError::Poisoned => write!(f, "Execution is poisoned"),
+            HibernateError::HostValues => write!(f, "Execution holds host values"),
+            HibernateError::GcHeap => write!(f, "Execution has objects on the GC heap"),
+            HibernateError::Untracked => write!(f, "Memory doesn't track dirty pages"),
+            HibernateError::Shared => write!(f, "Execution shares state with other instances"),
+            HibernateError::Io(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl Error for HibernateError {}
+
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum ThawError {
+    /// The image doesn't start with the hibernation magic number.
+    NotAnImage,
+    /// The image was written by a newer, or otherwise unknown, version of the format.
+    UnsupportedVersion(u8),
+    /// The image is truncated, corrupt, or doesn't fit the module.
+    Malformed(String),
+    /// The image was taken from a different module than the one given, in the ways listed.
+    ModuleMismatch(String),
+    Load(LoaderError),
+    Link(LinkError),
+    Io(std::io::Error),
+}
+
+impl Display for ThawError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ThawError::NotAnImage => write!(f, "Not a hibernated execution"),
+            ThawError::UnsupportedVersion(v) => write!(f, "Unsupported image version {v}"),
+            ThawError::Malformed(msg) => write!(f, "Malformed image: {msg}"),
+            ThawError::ModuleMismatch(diff) => write!(f, "Image is of another module: {diff}"),
+            ThawError::Load(e) => write!(f, "{e}"),
+            ThawError::Link(e) => write!(f, "{e}"),
+            ThawError::Io(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl Error for ThawError {}
+
+impl From<DecodeError> for ThawError {
+    fn from(e: DecodeError) -> Self {
+        ThawError::Malformed(e.to_string())
+    }
+}
+
+/// What an image thaws into, for `Execution::thaw` to put together.
+pub(crate) struct Thawed {
+    pub(crate) instance: Instance,
+    pub(crate) frames: Vec<Frame>,
+    pub(crate) memory: VectorMemory,
+    pub(crate) result: Option<Vec<Value>>,
+}
+
+pub(crate) fn write_image<M: Memory, S: SlotStorage>(
+    instance: &Instance,
+    frames: &[Frame<S>],
+    memory: &M,
+    result: Option<&[Value]>,
+    options: HibernateOptions,
+) -> Result<Vec<u8>, HibernateError> {
+    #[cfg(feature = "gc")]
+    if !instance.gc.heap.is_empty() {
+        return Err(HibernateError::GcHeap);
+    }
+    let mut out = MAGIC.to_vec();
+    out.push(VERSION);
+    write_uleb128(&mut out, module_hash(&instance.module.module_data));
+    let layout = section_layout(&instance.module);
+    write_uleb128(&mut out, layout.len() as u64);
+    for (id, size) in layout {
+        out.push(id);
+        write_uleb128(&mut out, size as u64);
+    }
+
+    match result {
+        Some(values) => {
+            out.push(1);
+            write_values(&mut out, values.iter().copied());
+        }
+        None => out.push(0),
+    }
+    write_values(&mut out, instance.globals.iter().map(|g| g.value));
+    write_uleb128(&mut out, instance.tables.len() as u64);
+    for table in &instance.tables {
+        write_uleb128(&mut out, table.elements.len() as u64);
+        // Each element used to be flagged as set or not; they're all set now, but the flag
+        // stays so that older images still thaw.
+        for element in &table.elements {
+            out.push(1);
+            write_value(&mut out, *element);
+        }
+    }
+
+    let data = memory.data();
+    let num_pages = pages_for_bytes(data.len());
+    let pages: Vec<usize> = if options.dirty_pages_only {
+        // Thawing starts over from the data segments, so whatever was written before the memory
+        // was tracked has to go in as well.
+        let mut dirty = memory.dirty_pages().ok_or(HibernateError::Untracked)?;
+        dirty.extend_from_slice(&instance.start_pages);
+        dirty.sort_unstable();
+        dirty.dedup();
+        dirty.into_iter().filter(|p| *p < num_pages).collect()
+    } else {
+        (0..num_pages)
+            .filter(|p| page_of(data, *p).iter().any(|b| *b != 0))
+            .collect()
+    };
+    out.push(options.dirty_pages_only as u8);
+    write_uleb128(&mut out, data.len() as u64);
+    write_uleb128(&mut out, pages.len() as u64);
+    for page in pages {
+        write_uleb128(&mut out, page as u64);
+        out.extend_from_slice(page_of(data, page));
+    }
+
+    write_uleb128(&mut out, frames.len() as u64);
+    for frame in frames {
+        write_uleb128(&mut out, frame.funcidx as u64);
+        write_uleb128(&mut out, frame.program.ops.len() as u64);
+        write_uleb128(&mut out, frame.pc as u64);
+        write_uleb128(&mut out, frame.return_types.len() as u64);
+        out.extend(frame.return_types.iter().map(|ty| ty.to_u8()));
+        write_stack(&mut out, &frame.locals);
+        write_stack(&mut out, &frame.stack);
+        write_uleb128(&mut out, frame.control_stack.len() as u64);
+        for control in &frame.control_stack {
+            out.push(match control.scope_type {
+                ScopeType::Program => 0,
+                ScopeType::Function => 1,
+                ScopeType::Loop => 2,
+                ScopeType::Block => 3,
+                ScopeType::IfElse => 4,
+            });
+            write_uleb128(&mut out, control.arity as u64);
+            write_uleb128(&mut out, control.results as u64);
+            write_uleb128(&mut out, control.stack_width as u64);
+            write_uleb128(&mut out, control.start_pc as u64);
+            write_uleb128(&mut out, control.back_edges);
+        }
+    }
+    Ok(out)
+}
+
+pub(crate) fn read_image(
+    module_bytes: &[u8],
+    linker: &Linker,
+    image: &[u8],
+) -> Result<Thawed, ThawError> {
+    if image.get(..4) != Some(MAGIC.as_slice()) {
+        return Err(ThawError::NotAnImage);
+    }
+    let mut reader = LEB128Reader::new(image, 4);
+    let version = reader.load_imm_u8()?;
+    if version != VERSION {
+        return Err(ThawError::UnsupportedVersion(version));
+    }
+
+    let hash = reader.load_imm_varuint64()?;
+    let layout = read_vec(&mut reader, |reader| {
+        Ok((reader.load_imm_u8()?, read_len(reader)?))
+    })?;
+
+    // The start function already ran before the execution was hibernated, and running it again
+    // could call out to the host.
+    let mut module = Module::load(module_bytes).map_err(ThawError::Load)?;
+    let same_module = hash == module_hash(module_bytes);
+    if !same_module {
+        let differences = layout_differences(&layout, &section_layout(&module));
+        if !differences.is_empty() {
+            return Err(mismatch(hash, module_bytes, differences));
+        }
+    }
+    let start_function = module.start_function.take();
+    let mut instance = linker.instantiate(module).map_err(ThawError::Link)?;
+    instance.module.start_function = start_function;
+
+    let result = match reader.load_imm_u8()? {
+        0 => None,
+        _ => Some(read_values(&mut reader)?),
+    };
+    let globals = read_values(&mut reader)?;
+    if globals.len() != instance.globals.len() {
+        return Err(malformed("the number of globals"));
+    }
+    for (global, value) in instance.globals.iter_mut().zip(globals) {
+        global.value = value;
+    }
+    let num_tables = read_len(&mut reader)?;
+    if num_tables != instance.tables.len() {
+        return Err(malformed("the number of tables"));
+    }
+    for table in &mut instance.tables {
+        let null = table.null();
+        table.elements = read_vec(&mut reader, |reader| match reader.load_imm_u8()? {
+            0 => Ok(null),
+            _ => read_value(reader),
+        })?;
+    }
+
+    let dirty_pages_only = reader.load_imm_u8()? != 0;
+    let size = read_len(&mut reader)?;
+    let mut memory = instance
+        .memories
+        .first()
+        .cloned()
+        .unwrap_or_else(|| VectorMemory::new(0, None));
+    if size > memory.max_bounds().unwrap_or(MAX_MEMORY_SIZE) {
+        return Err(malformed("memory size"));
+    }
+    if !dirty_pages_only {
+        memory.data_mut().clear();
+    }
+    memory.data_mut().resize(size, 0);
+    instance.start_pages.clear();
+    for _ in 0..read_len(&mut reader)? {
+        let page = read_len(&mut reader)?;
+        instance.start_pages.push(page);
+        let start = page
+            .checked_mul(WASM_PAGE_SIZE)
+            .filter(|start| *start < size)
+            .ok_or_else(|| malformed("a page past the end of memory"))?;
+        let len = (size - start).min(WASM_PAGE_SIZE);
+        let bytes = image
+            .get(reader.position()..reader.position() + len)
+            .ok_or_else(|| malformed("a truncated page"))?;
+        memory.data_mut()[start..start + len].copy_from_slice(bytes);
+        reader.advance(len)?;
+    }
+
+    let num_frames = read_len(&mut reader)?;
+    let mut frames = Vec::with_capacity(num_frames.min(1024));
+    for _ in 0..num_frames {
+        let funcidx = reader.load_imm_varuint32()?;
+        let program = instance
+            .module
+            .defined_func_index(funcidx)
+            .and_then(|i| instance.shared_program(i).ok())
+            .ok_or_else(|| malformed("a frame for a function the module doesn't define"))?
+            .clone();
+        let num_ops = read_len(&mut reader)?;
+        if num_ops != program.ops.len() {
+            let difference = format!(
+                "function {funcidx} is {} ops long, but was {num_ops}",
+                program.ops.len()
+            );
+            return Err(mismatch(hash, module_bytes, vec![difference]));
+        }
+        let pc = read_len(&mut reader)?;
+        if pc > program.ops.len() {
+            return Err(malformed("a frame past the end of its function"));
+        }
+        let return_types = read_vec(&mut reader, |reader| {
+            Ok(ValueType::from_u32(reader.load_imm_varuint32()?)?)
+        })?;
+        let locals = read_stack(&mut reader)?;
+        let stack = read_stack(&mut reader)?;
+        let control_stack = read_vec(&mut reader, |reader| {
+            let scope_type = match reader.load_imm_u8()? {
+                0 => ScopeType::Program,
+                1 => ScopeType::Function,
+                2 => ScopeType::Loop,
+                3 => ScopeType::Block,
+                4 => ScopeType::IfElse,
+                other => return Err(malformed(&format!("scope type {other}"))),
+            };
+            Ok(Control {
+                scope_type,
+                arity: reader.load_imm_varuint32()?,
+                results: reader.load_imm_varuint32()?,
+                stack_width: read_len(reader)?,
+                start_pc: read_len(reader)?,
+                back_edges: reader.load_imm_varuint64()?,
+            })
+        })?;
+        frames.push(Frame {
+            locals,
+            return_types,
+            program,
+            stack,
+            pc,
+            control_stack,
+            funcidx,
+        });
+    }
+    if reader.remaining() != 0 {
+        return Err(malformed("trailing bytes"));
+    }
+    Ok(Thawed {
+        instance,
+        frames,
+        memory,
+        result,
+    })
+}
+
+/// FNV-1a, which unlike std's hashers is the same everywhere and always will be.
+fn module_hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// The id and size of each of the module's sections, leaving out custom sections.
+fn section_layout(module: &Module) -> Vec<(u8, usize)> {
+    module
+        .sections
+        .iter()
+        .filter(|section| section.id != 0)
+        .map(|section| (section.id, section.size))
+        .collect()
+}
+
+/// How the section layout an image was taken with differs from the module's.
+fn layout_differences(image: &[(u8, usize)], module: &[(u8, usize)]) -> Vec<String> {
+    let mut differences = vec![];
+    if image.len() != module.len() {
+        differences.push(format!(
+            "{} sections, but was {}",
+            module.len(),
+            image.len()
+        ));
+    }
+    for (i, ((was_id, was_size), (id, size))) in image.iter().zip(module).enumerate() {
+        if was_id != id {
+            differences.push(format!("section {i} has id {id}, but was {was_id}"));
+        } else if was_size != size {
+            differences.push(format!(
+                "section {i} (id {id}) is {size} bytes, but was {was_size}"
+            ));
+        }
+    }
+    differences
+}
+
+fn mismatch(hash: u64, module_bytes: &[u8], mut differences: Vec<String>) -> ThawError {
+    differences.insert(
+        0,
+        format!(
+            "module hash is {:016x}, but was {hash:016x}",
+            module_hash(module_bytes)
+        ),
+    );
+    ThawError::ModuleMismatch(differences.join("; "))
+}
+
+fn malformed(what: &str) -> ThawError {
+    ThawError::Malformed(format!("unexpected {what}"))
+}
+
+fn page_of(data: &[u8], page: usize) -> &[u8] {
+    let start = page * WASM_PAGE_SIZE;
+    &data[start..(start + WASM_PAGE_SIZE).min(data.len())]
+}
+
+fn read_len(reader: &mut LEB128Reader) -> Result<usize, ThawError> {
+    Ok(reader.load_imm_varuint64()? as usize)
+}
+
+/// A length followed by that many items. Nothing is reserved up front, as the length hasn't
+/// been checked against anything yet.
+fn read_vec<T>(
+    reader: &mut LEB128Reader,
+    mut read: impl FnMut(&mut LEB128Reader) -> Result<T, ThawError>,
+) -> Result<Vec<T>, ThawError> {
+    let len = read_len(reader)?;
+    let mut items = vec![];
+    for _ in 0..len {
+        items.push(read(reader)?);
+    }
+    Ok(items)
+}
+
+fn write_values(out: &mut Vec<u8>, values: impl ExactSizeIterator<Item = Value>) {
+    write_uleb128(out, values.len() as u64);
+    for value in values {
+        write_value(out, value);
+    }
+}
+
+fn read_values(reader: &mut LEB128Reader) -> Result<Vec<Value>, ThawError> {
+    read_vec(reader, read_value)
+}
+
+/// A reference as 0 for null, or one more than its index.
+fn write_ref(out: &mut Vec<u8>, reference: Option<u32>) {
+    write_uleb128(out, reference.map_or(0, |r| r as u64 + 1));
+}
+
+fn read_ref(reader: &mut LEB128Reader) -> Result<Option<u32>, ThawError> {
+    Ok(reader.load_imm_varuint32()?.checked_sub(1))
+}
+
+fn write_value(out: &mut Vec<u8>, value: Value) {
+    out.push(value.type_of().to_u8());
+    match value {
+        Value::I32(v) => write_sleb128(out, v as i64),
+        Value::I64(v) => write_sleb128(out, v),
+        Value::F32(v) => write_uleb128(out, v.to_bits() as u64),
+        Value::F64(v) => write_uleb128(out, v.to_bits()),
+        Value::V128(v) => out.extend_from_slice(&v.to_le_bytes()),
+        Value::FuncRef(r) | Value::ExternRef(r) => write_ref(out, r),
+        #[cfg(feature = "gc")]
+        Value::AnyRef(r) => write_ref(out, r),
+        Value::Unit => {}
+    }
+}
+
+fn read_value(reader: &mut LEB128Reader) -> Result<Value, ThawError> {
+    let ty = ValueType::from_u32(reader.load_imm_u8()? as u32)?;
+    Ok(match ty {
+        ValueType::I32 => Value::I32(reader.load_imm_signed_varint32()?),
+        ValueType::I64 => Value::I64(reader.load_imm_signed_varint64()?),
+        ValueType::F32 => Value::F32(f32::from_bits(reader.load_imm_varuint32()?)),
+        ValueType::F64 => Value::F64(f64::from_bits(reader.load_imm_varuint64()?)),
+        ValueType::V128 => {
+            let mut bytes = [0; 16];
+            for byte in &mut bytes {
+                *byte = reader.load_imm_u8()?;
+            }
+            Value::V128(u128::from_le_bytes(bytes))
+        }
+        ValueType::FuncRef => Value::FuncRef(read_ref(reader)?),
+        ValueType::ExternRef => Value::ExternRef(read_ref(reader)?),
+        #[cfg(feature = "gc")]
+        ValueType::AnyRef => Value::AnyRef(read_ref(reader)?),
+        ValueType::Unit => Value::Unit,
+    })
+}
+
+fn slot_kind_to_u8(kind: SlotKind) -> u8 {
+    match kind {
+        SlotKind::I32 => 0,
+        SlotKind::I64 => 1,
+        SlotKind::F32 => 2,
+        SlotKind::F64 => 3,
+        SlotKind::Ref => 4,
+        SlotKind::V128 => 5,
+        SlotKind::Unit => 6,
+    }
+}
+
+fn slot_kind_from_u8(kind: u8) -> Result<SlotKind, ThawError> {
+    Ok(match kind {
+        0 => SlotKind::I32,
+        1 => SlotKind::I64,
+        2 => SlotKind::F32,
+        3 => SlotKind::F64,
+        4 => SlotKind::Ref,
+        5 => SlotKind::V128,
+        6 => SlotKind::Unit,
+        other => return Err(malformed(&format!("slot kind {other}"))),
+    })
+}
+
+/// The raw slots, followed by their kinds if the build keeps track of them.
+fn write_stack<S: SlotStorage>(out: &mut Vec<u8>, stack: &Stack<S>) {
+    write_uleb128(out, stack.width() as u64);
+    for slot in stack.slots() {
+        write_uleb128(out, *slot);
+    }
+    match stack.kinds() {
+        Some(kinds) => {
+            out.push(1);
+            out.extend(kinds.iter().map(|k| slot_kind_to_u8(*k)));
+        }
+        None => out.push(0),
+    }
+}
+
+fn read_stack<S: SlotStorage>(reader: &mut LEB128Reader) -> Result<Stack<S>, ThawError> {
+    let slots = read_vec(reader, |reader| Ok(reader.load_imm_varuint64()?))?;
+    let kinds = match reader.load_imm_u8()? {
+        0 => None,
+        _ => Some(
+            slots
+                .iter()
+                .map(|_| slot_kind_from_u8(reader.load_imm_u8()?))
+                .collect::<Result<Vec<_>, _>>()?,
+        ),
+    };
+    Stack::from_raw(slots, kinds).ok_or_else(|| {
+        ThawError::Malformed("image lacks the slot kinds debug builds check".to_string())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exec::{ExecError, Execution};
+    use crate::DirtyTrackingMemory;
+
+    const WAT: &str = r#"(module
+        (memory 1)
+        (global $count (mut i32) (i32.const 0))
+        (table 1 funcref)
+        (elem (i32.const 0) $step)
+        (data (i32.const 1000) "kept")
+        (type $t (func (param i32) (result i32)))
+        (func $step (param i32) (result i32)
+            (global.set $count (i32.add (global.get $count) (i32.const 1)))
+            (i32.store (i32.mul (local.get 0) (i32.const 4)) (local.get 0))
+            (i32.mul (local.get 0) (local.get 0)))
+        (func (export "sum") (param i32) (result i64) (local $i i32) (local $acc i64)
+            (loop $l
+                (local.set $acc (i64.add (local.get $acc)
+                    (i64.extend_i32_u (call_indirect (type $t) (local.get $i) (i32.const 0)))))
+                (local.set $i (i32.add (local.get $i) (i32.const 1)))
+                (br_if $l (i32.lt_u (local.get $i) (local.get 0))))
+            (i64.add (local.get $acc) (i64.extend_i32_u (global.get $count)))))"#;
+
+    /// Run `sum(10)` until it's about to store to word 5, and hibernate it there.
+    fn hibernate_midway<M: Memory>(
+        bytes: &[u8],
+        wrap: impl FnOnce(VectorMemory) -> M,
+        options: HibernateOptions,
+    ) -> Vec<u8> {
+        let instance = Linker::new()
+            .instantiate(Module::load(bytes).unwrap())
+            .unwrap();
+        let memory = wrap(instance.memories[0].clone());
+        let mut execution = Execution::new(instance, memory);
+        execution.watch_memory(20..24);
+        let funcidx = execution.instance().find_funcidx("sum").unwrap();
+        execution.prepare(funcidx, &[Value::I32(10)]).unwrap();
+        assert!(matches!(execution.run(), Err(ExecError::Suspended(_))));
+        assert_eq!(execution.frame_stack_len(), 2);
+        let mut image = vec![];
+        execution.hibernate_with(&mut image, options).unwrap();
+        image
+    }
+
+    fn thaw_and_finish(bytes: &[u8], image: &[u8]) -> Execution<VectorMemory> {
+        let mut execution = Execution::thaw(bytes, &Linker::new(), image).unwrap();
+        assert_eq!(execution.frame_stack_len(), 2);
+        execution.run().unwrap();
+        // The squares of 0 to 9, plus a call count of 10.
+        assert_eq!(execution.result().unwrap(), &[Value::I64(285 + 10)]);
+        assert_eq!(execution.memory().get_i32(9 * 4).unwrap(), 9);
+        assert_eq!(execution.memory().data()[1000..1004], *b"kept");
+        assert_eq!(execution.instance().globals[0].value, Value::I32(10));
+        execution
+    }
+
+    #[test]
+    fn suspended_executions_thaw_and_finish() {
+        let bytes = wat::parse_str(WAT).unwrap();
+        let image = hibernate_midway(&bytes, |m| m, HibernateOptions::default());
+        // One page of memory, and not much else.
+        assert!(image.len() < WASM_PAGE_SIZE + 256);
+        thaw_and_finish(&bytes, &image);
+
+        let options = HibernateOptions {
+            dirty_pages_only: true,
+        };
+        let image = hibernate_midway(&bytes, DirtyTrackingMemory::new, options);
+        thaw_and_finish(&bytes, &image);
+
+        let mut untracked = vec![];
+        let execution = Execution::new(
+            crate::mk_instance(Module::load(&bytes).unwrap()).unwrap(),
+            VectorMemory::new(0, None),
+        );
+        assert!(matches!(
+            execution.hibernate_with(&mut untracked, options),
+            Err(HibernateError::Untracked)
+        ));
+
+        assert!(matches!(
+            Execution::thaw(&bytes, &Linker::new(), &b"not an image"[..]),
+            Err(ThawError::NotAnImage)
+        ));
+
+        // Custom sections don't matter, but the code being run does.
+        let mut with_custom = bytes.clone();
+        with_custom.extend_from_slice(b"\x00\x05\x04note");
+        thaw_and_finish(&with_custom, &image);
+        let edited = wat::parse_str(WAT.replace("(i32.const 1)", "(i32.const 1000)")).unwrap();
+        let Err(ThawError::ModuleMismatch(diff)) =
+            Execution::thaw(&edited, &Linker::new(), &image[..])
+        else {
+            panic!("thawed against an edited module");
+        };
+        assert!(diff.contains("bytes, but was"), "{diff}");
+        assert!(matches!(
+            Execution::thaw(&bytes, &Linker::new(), &image[..image.len() - 1]),
+            Err(ThawError::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn start_function_writes_survive_dirty_only_images() {
+        let wat = r#"(module
+            (import "wasbox" "yield" (func $yield))
+            (memory 3)
+            (data (i32.const 8) "seg")
+            (func $start (i32.store (i32.const 131080) (i32.const 1234)))
+            (start $start)
+            (func (export "f") (result i32) (call $yield) (i32.load (i32.const 131080))))"#;
+        let bytes = wat::parse_str(wat).unwrap();
+        let instance = Linker::new()
+            .instantiate(Module::load(&bytes).unwrap())
+            .unwrap();
+        let memory = DirtyTrackingMemory::new(instance.memories[0].clone());
+        let mut execution = Execution::new(instance, memory);
+        assert!(matches!(
+            execution.invoke("f", &[]),
+            Err(ExecError::Suspended(_))
+        ));
+        // Nothing's been written since the memory was tracked, but the start function's store
+        // still has to be saved.
+        assert_eq!(execution.memory().dirty_pages(), Some(vec![]));
+        let options = HibernateOptions {
+            dirty_pages_only: true,
+        };
+        let mut image = vec![];
+        execution.hibernate_with(&mut image, options).unwrap();
+
+        let mut thawed = Execution::thaw(&bytes, &Linker::new(), &image[..]).unwrap();
+        assert_eq!(thawed.instance().start_pages, [2]);
+        thawed.run().unwrap();
+        assert_eq!(thawed.result().unwrap(), &[Value::I32(1234)]);
+        assert_eq!(thawed.memory().data()[8..11], *b"seg");
+    }
+}
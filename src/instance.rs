@@ -11,33 +11,173 @@
 // this program. If not, see <https://www.gnu.org/licenses/>.
 //
 
-use crate::decode::{decode, Program, ScopeType};
-use crate::exec::{exec_fragment, Fault, GlobalVar, Value};
-use crate::frame::Frame;
-use crate::module::{Data, ReferenceType};
-use crate::stack::Stack;
-use crate::{DecodeError, Module, Type, ValueType, VectorMemory};
+use crate::builder::{InstanceBuilder, InstanceLimits, MemoryBackend};
+use crate::decode::{decode_function, Program};
+use crate::exec::{exec_fragment, Continuation, ExecError, Fault, GcStore, GlobalVar, Value};
+use crate::frame::{Frame, FramePool};
+use crate::linker::{Extern, HostFunction, Imports};
+use crate::memory::{bytes_for_pages, Memory, WASM_PAGE_SIZE};
+use crate::module::{Data, ElementSegment, Elements, ImportExportKind, ReferenceType, Region};
+use crate::shared::{shareable, Links};
+use crate::stack::SlotStorage;
+use crate::{DecodeError, FuncType, Module, ValueType, VectorMemory};
 use std::error::Error;
 use std::fmt::{Display, Formatter};
-
-pub const WASM_PAGE_SIZE: usize = 1 << 16;
+use std::sync::{Arc, OnceLock};
 
 /// Runtime representation of a table
+///
+/// Sizes and indices are 64-bit, as table64 needs, though tables are only declared with 32-bit
+/// limits for now. An index the host can't address is out of bounds, never truncated.
 #[derive(Debug, Clone)]
 pub struct TableInstance {
-    pub elements: Vec<Option<Value>>,
+    /// Every element is a reference of the table's type, null until something is stored there,
+    /// as the spec has it. A null funcref is what `call_indirect` calls an uninitialized element.
+    pub elements: Vec<Value>,
     pub ref_type: ReferenceType,
-    pub limits: (u32, Option<u32>),
+    pub limits: (u64, Option<u64>),
+}
+
+/// Where in `elements` the element at `idx` would be, if the host can address it at all.
+fn slot(idx: u64) -> Option<usize> {
+    usize::try_from(idx).ok()
+}
+
+/// The null reference of type `ref_type`, which a table's elements start out as.
+fn null_of(ref_type: ReferenceType) -> Value {
+    match ref_type {
+        ReferenceType::FuncRef => Value::FuncRef(None),
+        ReferenceType::ExternRef => Value::ExternRef(None),
+    }
+}
+
+/// 32-bit table limits, as modules declare them, widened to a table's own.
+pub(crate) fn table_limits(limits: (u32, Option<u32>)) -> (u64, Option<u64>) {
+    (limits.0.into(), limits.1.map(u64::from))
+}
+
+/// What a table too large for the host to address fails instantiation with.
+pub(crate) fn table_too_large(min: u64) -> LinkError {
+    LinkError::LimitExceeded(format!(
+        "{min} table elements requested, more than the host can hold"
+    ))
+}
+
+impl TableInstance {
+    /// A table of `limits.0` null elements, or None if the host can't hold that many.
+    pub fn new(ref_type: ReferenceType, limits: (u64, Option<u64>)) -> Option<Self> {
+        let min = slot(limits.0)?;
+        let mut elements = Vec::new();
+        elements.try_reserve_exact(min).ok()?;
+        elements.resize(min, null_of(ref_type));
+        Some(TableInstance {
+            elements,
+            ref_type,
+            limits,
+        })
+    }
+
+    /// A table of `limits.0` elements set to `init`.
+    pub fn with_init(
+        ref_type: ReferenceType,
+        limits: (u64, Option<u64>),
+        init: Value,
+    ) -> Result<Self, Fault> {
+        let mut table = TableInstance::new(ref_type, limits).ok_or(Fault::CannotGrowTable)?;
+        table.check_type(&init)?;
+        table.elements.fill(init);
+        Ok(table)
+    }
+
+    pub fn size(&self) -> u64 {
+        self.elements.len() as u64
+    }
+
+    /// The null reference of the table's type.
+    pub(crate) fn null(&self) -> Value {
+        null_of(self.ref_type)
+    }
+
+    fn check_type(&self, value: &Value) -> Result<(), Fault> {
+        match (self.ref_type, value) {
+            (ReferenceType::FuncRef, Value::FuncRef(_))
+            | (ReferenceType::ExternRef, Value::ExternRef(_)) => Ok(()),
+            _ => Err(Fault::InvalidRefType),
+        }
+    }
+
+    /// The element at `idx`, or None if it's out of bounds.
+    pub fn get(&self, idx: u64) -> Option<Value> {
+        self.elements.get(slot(idx)?).copied()
+    }
+
+    /// Store `value` at `idx`, which must be in bounds and of the table's reference type.
+    pub fn set(&mut self, idx: u64, value: Value) -> Result<(), Fault> {
+        self.check_type(&value)?;
+        let element = slot(idx)
+            .and_then(|idx| self.elements.get_mut(idx))
+            .ok_or(Fault::UndefinedElement)?;
+        *element = value;
+        Ok(())
+    }
+
+    /// Write `values` from `offset` on, as an element segment or `table.set` does. Every value
+    /// must be of the table's reference type, and all must fit, or nothing is written.
+    pub(crate) fn write(&mut self, offset: u64, values: &[Value]) -> Result<(), Fault> {
+        for value in values {
+            self.check_type(value)?;
+        }
+        let slots = slot(offset)
+            .and_then(|offset| Some(offset..offset.checked_add(values.len())?))
+            .and_then(|range| self.elements.get_mut(range))
+            .ok_or(Fault::TableOutOfBounds)?;
+        slots.copy_from_slice(values);
+        Ok(())
+    }
+
+    /// Add `delta` elements set to `init`, returning the previous size. Fails without growing if
+    /// that would take the table past its maximum.
+    pub fn grow(&mut self, delta: u64, init: Value) -> Result<u64, Fault> {
+        self.check_type(&init)?;
+        let old_size = self.size();
+        let new_len = old_size
+            .checked_add(delta)
+            .filter(|size| self.limits.1.is_none_or(|max| *size <= max))
+            .and_then(slot)
+            .ok_or(Fault::CannotGrowTable)?;
+        self.elements
+            .try_reserve(new_len - self.elements.len())
+            .map_err(|_| Fault::CannotGrowTable)?;
+        self.elements.resize(new_len, init);
+        Ok(old_size)
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[non_exhaustive]
 pub enum LinkError {
     ActiveExpressionError(Fault),
     DecodeError(DecodeError),
     FunctionNotFound,
     UnsupportedFeature(String),
     ArgumentTypeMismatch(usize, ValueType, ValueType),
+    /// A function was called with the wrong number of arguments: how many it takes, and how many
+    /// it was given.
+    ArgumentCountMismatch(usize, usize),
     MissingMemory,
+    /// Nothing was provided for the import of module and field name.
+    UnresolvedImport(String, String),
+    /// What was provided for the import of module and field name is the wrong kind or type, or
+    /// doesn't fit the import's limits.
+    IncompatibleImport(String, String),
+    /// The module asks for more than the instance's limits allow.
+    LimitExceeded(String),
+    /// The module refers to something it doesn't have, found when validating.
+    InvalidModule(String),
+    /// The instance wasn't built to be reset; see `InstanceBuilder::resettable`.
+    NotResettable,
+    /// Instantiating the module took more than `InstanceLimits::instantiation_fuel`.
+    InstantiationBudgetExceeded,
 }
 
 impl Display for LinkError {
@@ -50,172 +190,397 @@ impl Display for LinkError {
                 f,
                 "Argument type mismatch at index {idx}: expected {expected:?}, got {actual:?}"
             ),
+            LinkError::ArgumentCountMismatch(expected, actual) => {
+                write!(f, "Expected {expected} arguments, got {actual}")
+            }
             LinkError::MissingMemory => write!(f, "No memory found"),
+            LinkError::UnresolvedImport(module, name) => {
+                write!(f, "Unresolved import: {module}.{name}")
+            }
+            LinkError::IncompatibleImport(module, name) => {
+                write!(f, "Incompatible import: {module}.{name}")
+            }
+            LinkError::LimitExceeded(s) => write!(f, "Limit exceeded: {s}"),
+            LinkError::InvalidModule(s) => write!(f, "Invalid module: {s}"),
             LinkError::DecodeError(e) => write!(f, "Decode error: {e}"),
+            LinkError::NotResettable => write!(f, "Instance can't be reset"),
+            LinkError::InstantiationBudgetExceeded => write!(f, "Instantiation budget exceeded"),
         }
     }
 }
 
 impl Error for LinkError {}
 
+impl LinkError {
+    /// Stable numeric code for this error, in the 3000s. Decode errors keep their own code.
+    pub fn code(&self) -> u32 {
+        match self {
+            LinkError::ActiveExpressionError(_) => 3001,
+            LinkError::FunctionNotFound => 3002,
+            LinkError::UnsupportedFeature(_) => 3003,
+            LinkError::ArgumentTypeMismatch(_, _, _) => 3004,
+            LinkError::MissingMemory => 3005,
+            LinkError::UnresolvedImport(_, _) => 3006,
+            LinkError::IncompatibleImport(_, _) => 3007,
+            LinkError::LimitExceeded(_) => 3008,
+            LinkError::InvalidModule(_) => 3009,
+            LinkError::ArgumentCountMismatch(_, _) => 3010,
+            LinkError::NotResettable => 3011,
+            LinkError::InstantiationBudgetExceeded => 3012,
+            LinkError::DecodeError(e) => e.code(),
+        }
+    }
+}
+
+/// A function looked up in an instance, by export name or by index, along with its type. It's
+/// only the function's index and type, so it stays good across resets of the instance or of an
+/// execution of it, and for any other instance of the same module.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct FuncHandle {
+    funcidx: u32,
+    func_type: FuncType,
+}
+
+impl FuncHandle {
+    pub fn index(&self) -> u32 {
+        self.funcidx
+    }
+
+    pub fn func_type(&self) -> &FuncType {
+        &self.func_type
+    }
+
+    /// Whether `instance` has this function, with this type, at this index.
+    pub fn belongs_to(&self, instance: &Instance) -> bool {
+        instance.module.func_type_of(self.funcidx) == Some(&self.func_type)
+    }
+}
+
+#[derive(Clone)]
 pub struct Instance {
     pub module: Module,
     pub memories: Vec<VectorMemory>,
     pub globals: Vec<GlobalVar>,
-    pub programs: Vec<Program>,
+    /// The decoded body of each function the module defines, filled in on first call if
+    /// decoding is lazy. Shared with the frames running them, and with clones of the instance.
+    pub(crate) programs: Vec<OnceLock<Arc<Program>>>,
     pub tables: Vec<TableInstance>,
+    pub(crate) gc: GcStore,
+    /// One per function import, which take up the lowest function indices.
+    pub(crate) host_funcs: Vec<HostFunction>,
+    /// The state the instance was in once instantiated, if it's to be reset to it.
+    pristine: Option<Arc<Pristine>>,
+    /// Pages of the first memory which hold more than the data segments put there, from before
+    /// any execution could track them: those the start function wrote, or that were thawed
+    /// from an image. Hibernating only dirty pages saves these too.
+    pub(crate) start_pages: Vec<usize>,
+    /// The globals and memory shared with other instances.
+    pub(crate) links: Links,
 }
 
-/// Produce an instance from a module.
-pub fn mk_instance(module: Module) -> Result<Instance, LinkError> {
-    let mut programs = Vec::with_capacity(module.code.len());
+/// What `Instance::reset` puts back: everything the guest can change, as it was once the
+/// instance had been instantiated and its start function run. Clones of an instance share it.
+struct Pristine {
+    memories: Vec<VectorMemory>,
+    globals: Vec<Value>,
+    tables: Vec<TableInstance>,
+    #[cfg(feature = "gc")]
+    gc: GcStore,
+}
 
-    for (i, code) in module.code.iter().enumerate() {
-        let program_memory = module.code(i);
-        let mut program = decode(program_memory).map_err(LinkError::DecodeError)?;
+impl Pristine {
+    fn of(instance: &Instance) -> Self {
+        Pristine {
+            memories: instance.memories.clone(),
+            globals: instance.globals.iter().map(|g| g.value).collect(),
+            tables: instance.tables.clone(),
+            #[cfg(feature = "gc")]
+            gc: instance.gc.clone(),
+        }
+    }
+}
+
+/// Produce an instance from a module. Its function imports are left unresolved, and trap if
+/// called; use an `InstanceBuilder` to provide them, or for any other configuration.
+pub fn mk_instance(module: Module) -> Result<Instance, LinkError> {
+    InstanceBuilder::new(module).build()
+}
 
-        // Make local types from function signatures + code local signatures
-        let typeidx = module.functions[i];
-        let num_locals = code.locals.len() + module.types[typeidx].params.len();
-        let mut local_types = Vec::with_capacity(num_locals);
-        for param_type in &module.types[typeidx].params {
-            local_types.push(*param_type);
+/// What went wrong running the start function. Nothing is watched during instantiation, so it
+/// can't be suspended, but if it somehow was it isn't going to be resumed.
+fn start_error(e: ExecError) -> LinkError {
+    match e {
+        ExecError::ExecutionFault(Fault::BudgetExceeded(_)) => {
+            LinkError::InstantiationBudgetExceeded
         }
-        for local_type in &module.code[i].locals {
-            local_types.push(*local_type);
+        ExecError::ExecutionFault(f) => LinkError::ActiveExpressionError(f),
+        ExecError::LinkageError(l) => l,
+        ExecError::Suspended(reason) => {
+            LinkError::ActiveExpressionError(Fault::UnexpectedResult(Continuation::Suspend(reason)))
         }
+    }
+}
 
-        program.local_types = local_types;
-        program.return_types = module.types[typeidx].results.clone();
+/// How many bytes of an active data segment copying in costs a tick.
+const SEGMENT_BYTES_PER_TICK: usize = 64;
 
-        programs.push(program);
+/// What's left of `InstanceLimits::instantiation_fuel` as instantiation goes along, if there's
+/// a limit at all.
+pub(crate) struct Fuel(Option<u64>);
+
+impl Fuel {
+    pub(crate) fn remaining(&self) -> Option<u64> {
+        self.0
     }
 
-    let mut memories: Vec<_> = module
-        .memories
-        .iter()
-        .map(|m_decl| {
-            let min_pages = m_decl.limits.0;
-            let max_pages = m_decl.limits.1;
-            VectorMemory::new(
-                min_pages as usize * WASM_PAGE_SIZE,
-                max_pages.map(|x| x as usize * WASM_PAGE_SIZE),
-            )
-        })
-        .collect();
+    /// Use up `ticks`, or fail if there aren't that many left.
+    pub(crate) fn spend(&mut self, ticks: u64) -> Result<(), LinkError> {
+        if let Some(remaining) = &mut self.0 {
+            *remaining = remaining
+                .checked_sub(ticks)
+                .ok_or(LinkError::InstantiationBudgetExceeded)?;
+        }
+        Ok(())
+    }
+}
 
-    // Initialize tables
-    let mut tables: Vec<_> = module
-        .tables
-        .iter()
-        .map(|t_decl| {
-            let min_size = t_decl.limits.0;
-            let max_size = t_decl.limits.1;
-            TableInstance {
-                elements: vec![None; min_size as usize],
-                ref_type: t_decl.ty,
-                limits: (min_size, max_size),
-            }
-        })
-        .collect();
+/// Evaluate the offset expression of an active data or element segment.
+fn segment_offset(
+    module: &Module,
+    expr: &Region,
+    globals: &mut [GlobalVar],
+    fuel: &mut Fuel,
+) -> Result<usize, LinkError> {
+    let expr = module.get_expr(expr).map_err(LinkError::DecodeError)?;
+    match exec_fragment(expr, ValueType::I32, globals, fuel)? {
+        Value::I32(offset) => Ok(offset as u32 as usize),
+        other => Err(LinkError::DecodeError(DecodeError::FailedToDecode(
+            format!("segment offset {other:?} isn't an i32"),
+        ))),
+    }
+}
 
-    // Apply active element segments to initialize tables
-    for element_segment in &module.element_segments {
-        if let crate::module::ElementMode::Active { table_index, expr } = &element_segment.mode {
-            let table_idx = *table_index as usize;
-            if table_idx < tables.len() {
-                if let crate::module::Elements::Function(func_indices) = &element_segment.elements {
-                    // Evaluate the init expression to get the offset
-                    let offset_value = exec_fragment(module.get_expr(expr), ValueType::I32)
-                        .map_err(LinkError::ActiveExpressionError)?;
-                    let Value::I32(offset) = offset_value else {
-                        panic!("Element segment offset must be i32");
-                    };
-                    let offset = offset as usize;
-                    for (i, &func_idx) in func_indices.iter().enumerate() {
-                        if offset + i < tables[table_idx].elements.len() {
-                            tables[table_idx].elements[offset + i] =
-                                Some(Value::FuncRef(Some(func_idx)));
-                        }
-                    }
-                }
-            }
+/// The references an element segment holds, evaluating its expressions if it has them.
+fn element_values(
+    module: &Module,
+    segment: &ElementSegment,
+    globals: &mut [GlobalVar],
+    fuel: &mut Fuel,
+) -> Result<Vec<Value>, LinkError> {
+    match &segment.elements {
+        Elements::Function(indices) => Ok(indices
+            .iter()
+            .map(|idx| Value::FuncRef(Some(*idx)))
+            .collect()),
+        Elements::Expression(exprs) => {
+            let ty = match segment.reftype {
+                ReferenceType::FuncRef => ValueType::FuncRef,
+                ReferenceType::ExternRef => ValueType::ExternRef,
+            };
+            exprs
+                .iter()
+                .map(|expr| {
+                    let expr = module.get_expr(expr).map_err(LinkError::DecodeError)?;
+                    exec_fragment(expr, ty, globals, fuel)
+                })
+                .collect()
         }
     }
+}
 
-    // Support modules without memory
-    if memories.len() > 1 {
-        return Err(LinkError::UnsupportedFeature(
-            "Multiple memories not supported yet".to_string(),
-        ));
+/// Copy the bytes of a data segment into memory at `offset`.
+fn copy_segment(
+    module: &Module,
+    data: &Region,
+    memory: &mut VectorMemory,
+    offset: usize,
+) -> Result<(), LinkError> {
+    let bytes = module.module_data.get(data.0..data.1).ok_or_else(|| {
+        LinkError::DecodeError(DecodeError::MalformedMemory(format!(
+            "data segment at {:#x} runs past the end of the module",
+            data.0
+        )))
+    })?;
+    let dest = offset
+        .checked_add(bytes.len())
+        .and_then(|end| memory.data_mut().get_mut(offset..end))
+        .ok_or(LinkError::ActiveExpressionError(Fault::MemoryOutOfBounds))?;
+    dest.copy_from_slice(bytes);
+    Ok(())
+}
+
+/// Decode the body of the `i`th function the module defines.
+fn decode_program(module: &Module, i: usize) -> Result<Program, LinkError> {
+    // `Module::load` checks these line up, but the fields are public, so don't count on it.
+    let (Some(program_memory), Some(code)) = (module.body(i), module.code.get(i)) else {
+        return Err(LinkError::InvalidModule(format!(
+            "no body for function {i}"
+        )));
+    };
+    let typeidx = module.functions.get(i).copied().unwrap_or(usize::MAX);
+    let Some(func_type) = module.types.get(typeidx) else {
+        return Err(LinkError::InvalidModule(format!(
+            "function {i} has unknown type {typeidx}"
+        )));
+    };
+    // Make local types from function signatures + code local signatures
+    let mut program = decode_function(program_memory, &module.types, func_type)
+        .map_err(LinkError::DecodeError)?;
+
+    let num_locals = code.locals.len() + func_type.params.len();
+    let mut local_types = Vec::with_capacity(num_locals);
+    for param_type in &func_type.params {
+        local_types.push(*param_type);
+    }
+    for local_type in &code.locals {
+        local_types.push(*local_type);
     }
 
-    // Populate memory from global data (only if memory exists).
-    if !memories.is_empty() {
-        for data_segment in &module.data {
-            match data_segment {
-                Data::Active { expr, data } => {
-                    // We have to execute the program located at expr in order to get the address
-                    // of the data segment.
-                    let data_offset = exec_fragment(module.get_expr(expr), ValueType::I32)
-                        .map_err(LinkError::ActiveExpressionError)?;
-                    let Value::I32(data_offset) = data_offset else {
-                        panic!("Data segment offset must be i32");
-                    };
-                    let data_offset = data_offset as usize;
-                    // Read from program memory @ data offset into memory_vec
-                    let data_len = data.1 - data.0;
-                    memories[0].data_mut()[data_offset..data_offset + data_len]
-                        .copy_from_slice(&module.module_data[data.0..data.1]);
-                }
-                Data::ActiveMemIdx { memidx, expr, data } => {
-                    // This is identical to above but with a memory index set. But standard doesn't
-                    // support multiple memories yet. But we'll just go ahead and implement it.
-                    let data_offset = exec_fragment(module.get_expr(expr), ValueType::I32)
-                        .map_err(LinkError::ActiveExpressionError)?;
-                    let Value::I32(data_offset) = data_offset else {
-                        panic!("Data segment offset must be i32");
-                    };
-                    let data_offset = data_offset as usize;
-                    memories[*memidx as usize].data_mut()
-                        [data_offset..data_offset + data.1 - data.0]
-                        .copy_from_slice(&module.module_data[data.0..data.1]);
-                }
-                Data::Passive { data } => {
-                    let offset = data.0;
-                    let end = data.1;
-                    memories[0].data_mut()[offset..end]
-                        .copy_from_slice(&module.module_data[offset..end]);
-                }
-            }
+    program.set_local_types(local_types);
+    #[cfg(feature = "optimize")]
+    crate::optimize::optimize(&mut program);
+    Ok(program)
+}
+
+/// Instantiate `module`. Function bodies are decoded up front, unless `lazy`, in which case each
+/// is decoded the first time it's called.
+pub(crate) fn link(
+    module: Module,
+    imports: Imports,
+    limits: &InstanceLimits,
+    memory_backend: &mut MemoryBackend,
+    lazy: bool,
+    resettable: bool,
+) -> Result<Instance, LinkError> {
+    let mut programs = Vec::with_capacity(module.code.len());
+    for i in 0..module.code.len() {
+        let program = OnceLock::new();
+        if !lazy {
+            let _ = program.set(Arc::new(decode_program(&module, i)?));
         }
+        programs.push(program);
+    }
+
+    let mut memories: Vec<_> = imports.memories;
+    for m_decl in &module.memories {
+        let (min_pages, max_pages) = limits.memory(m_decl.limits)?;
+        memories.push(memory_backend(
+            bytes_for_pages(min_pages as usize),
+            max_pages.map(|x| bytes_for_pages(x as usize)),
+        ));
     }
 
-    // Populate globals.
-    let mut globals = Vec::with_capacity(module.globals.len());
+    // Populate globals first, as segment offsets may refer to them. Each global's initializer
+    // can see the globals before it.
+    let mut fuel = Fuel(limits.instantiation_fuel);
+    let mut globals = imports.globals;
     for global_segment in &module.globals {
         // Execute the expression in the global
-        let program = module.get_expr(&global_segment.expr);
-        let result =
-            exec_fragment(program, global_segment.ty).map_err(LinkError::ActiveExpressionError)?;
+        let program = module
+            .get_expr(&global_segment.expr)
+            .map_err(LinkError::DecodeError)?;
+        let result = exec_fragment(program, global_segment.ty, &mut globals, &mut fuel)?;
         globals.push(GlobalVar {
             decl: global_segment.clone(),
             value: result,
         });
     }
 
+    // Initialize tables
+    let mut tables: Vec<_> = imports.tables;
+    for t_decl in &module.tables {
+        let table_limits = table_limits(limits.table(t_decl.limits)?);
+        let table = match &t_decl.init {
+            Some(expr) => {
+                let ty = match t_decl.ty {
+                    ReferenceType::FuncRef => ValueType::FuncRef,
+                    ReferenceType::ExternRef => ValueType::ExternRef,
+                };
+                let expr = module.get_expr(expr).map_err(LinkError::DecodeError)?;
+                let init = exec_fragment(expr, ty, &mut globals, &mut fuel)?;
+                TableInstance::with_init(t_decl.ty, table_limits, init)
+                    .map_err(LinkError::ActiveExpressionError)?
+            }
+            None => TableInstance::new(t_decl.ty, table_limits)
+                .ok_or_else(|| table_too_large(table_limits.0))?,
+        };
+        tables.push(table);
+    }
+
+    // Apply active element segments to initialize tables, in order. As with data segments, one
+    // that doesn't fit, or holds references of the wrong type for its table, traps instantiation
+    // without writing any of its own elements.
+    for element_segment in &module.element_segments {
+        let crate::module::ElementMode::Active { table_index, expr } = &element_segment.mode else {
+            continue;
+        };
+        // Evaluate the init expression to get the offset
+        let offset = segment_offset(&module, expr, &mut globals, &mut fuel)?;
+        let values = element_values(&module, element_segment, &mut globals, &mut fuel)?;
+        fuel.spend(values.len() as u64)?;
+        let table = tables
+            .get_mut(*table_index as usize)
+            .ok_or_else(|| LinkError::InvalidModule(format!("unknown table {table_index}")))?;
+        table
+            .write(offset as u64, &values)
+            .map_err(LinkError::ActiveExpressionError)?;
+    }
+
+    // Populate memory from active data segments, in order. One that doesn't fit in its memory
+    // traps instantiation with an out of bounds access, as `data.wast` expects, leaving any
+    // earlier segments written.
+    for data_segment in &module.data {
+        let (memidx, expr, data) = match data_segment {
+            Data::Active { expr, data } => (0, expr, data),
+            Data::ActiveMemIdx { memidx, expr, data } => (*memidx as usize, expr, data),
+            // Only copied in by `memory.init`.
+            Data::Passive { .. } => continue,
+        };
+        // We have to execute the program located at expr in order to get the address of the
+        // data segment.
+        let offset = segment_offset(&module, expr, &mut globals, &mut fuel)?;
+        fuel.spend((data.1.saturating_sub(data.0)).div_ceil(SEGMENT_BYTES_PER_TICK) as u64)?;
+        let memory = memories.get_mut(memidx).ok_or(LinkError::MissingMemory)?;
+        copy_segment(&module, data, memory, offset)?;
+    }
+
+    #[cfg(feature = "gc")]
+    let instance_types = module.sub_types.clone();
     let instance = Instance {
         module,
         memories,
         globals,
         programs,
         tables,
+        #[cfg(feature = "gc")]
+        gc: GcStore::new(
+            instance_types,
+            limits
+                .max_gc_values
+                .unwrap_or(crate::gc::DEFAULT_MAX_GC_VALUES),
+        ),
+        #[cfg(not(feature = "gc"))]
+        gc: (),
+        host_funcs: imports.funcs,
+        pristine: None,
+        start_pages: vec![],
+        links: imports.links,
     };
 
-    // Execute start function if present
+    let mut instance = run_start(instance, fuel)?;
+    // Data segments write to an imported memory too.
+    instance.push_links();
+    if resettable {
+        instance.pristine = Some(Arc::new(Pristine::of(&instance)));
+    }
+    Ok(instance)
+}
+
+/// Run the instance's start function, if it has one, on whatever fuel is left.
+fn run_start(instance: Instance, fuel: Fuel) -> Result<Instance, LinkError> {
     if let Some(start_func_idx) = instance.module.start_function {
-        // Create execution context and run the start function
-        use crate::{Execution, VectorMemory};
+        // Create execution context and run the start function, noting which pages it writes.
+        use crate::{DirtyTrackingMemory, Execution, VectorMemory};
 
         let memory = if !instance.memories.is_empty() {
             instance.memories[0].clone()
@@ -223,20 +588,19 @@ pub fn mk_instance(module: Module) -> Result<Instance, LinkError> {
             VectorMemory::new(0, None)
         };
 
-        let mut execution = Execution::new(instance, memory);
+        let mut execution = Execution::new(instance, DirtyTrackingMemory::new(memory));
+        if let Some(remaining) = fuel.remaining() {
+            execution.set_budget(start_func_idx as u32, remaining);
+        }
         execution
             .prepare(start_func_idx as u32, &[])
-            .map_err(|e| match e {
-                crate::exec::ExecError::ExecutionFault(f) => LinkError::ActiveExpressionError(f),
-                crate::exec::ExecError::LinkageError(l) => l,
-            })?;
-        execution.run().map_err(|e| match e {
-            crate::exec::ExecError::ExecutionFault(f) => LinkError::ActiveExpressionError(f),
-            crate::exec::ExecError::LinkageError(l) => l,
-        })?;
+            .map_err(start_error)?;
+        execution.run().map_err(start_error)?;
 
         // Extract the instance back from execution and return it
-        let updated_instance = execution.into_instance_with_memory();
+        let start_pages = execution.memory().pages_written();
+        let mut updated_instance = execution.into_instance_with_memory();
+        updated_instance.start_pages = start_pages;
         Ok(updated_instance)
     } else {
         Ok(instance)
@@ -244,50 +608,190 @@ pub fn mk_instance(module: Module) -> Result<Instance, LinkError> {
 }
 
 impl Instance {
+    /// Put the instance's memories, globals and tables back as they were once it had been
+    /// instantiated, throwing away whatever has been done with it since, without decoding or
+    /// linking anything again. Only instances built with `InstanceBuilder::resettable` keep what
+    /// that takes. An execution keeps its own copy of the memory it runs against, so take the
+    /// instance back with `Execution::into_instance_with_memory` before resetting it.
+    pub fn reset(&mut self) -> Result<(), LinkError> {
+        let pristine = self.pristine.clone().ok_or(LinkError::NotResettable)?;
+        self.memories.clone_from(&pristine.memories);
+        self.reset_non_memory(&pristine);
+        Ok(())
+    }
+
+    /// As `reset`, but copying back only the pages of the first memory listed in `dirty`, which
+    /// has to hold every page stored to since the instance was instantiated or last reset: what
+    /// a `DirtyTrackingMemory` it was run against reports, say. Growth is undone all the same.
+    /// Any other memory is copied back in full.
+    pub fn reset_pages(&mut self, dirty: &[usize]) -> Result<(), LinkError> {
+        let pristine = self.pristine.clone().ok_or(LinkError::NotResettable)?;
+        for (i, (memory, image)) in self.memories.iter_mut().zip(&pristine.memories).enumerate() {
+            if i > 0 || memory.size() < image.size() {
+                memory.clone_from(image);
+                continue;
+            }
+            let data = memory.data_mut();
+            data.truncate(image.size());
+            for &page in dirty {
+                let start = page.saturating_mul(WASM_PAGE_SIZE);
+                let end = start.saturating_add(WASM_PAGE_SIZE).min(data.len());
+                if start < end {
+                    data[start..end].copy_from_slice(&image.data()[start..end]);
+                }
+            }
+        }
+        self.reset_non_memory(&pristine);
+        Ok(())
+    }
+
+    fn reset_non_memory(&mut self, pristine: &Pristine) {
+        for (global, value) in self.globals.iter_mut().zip(&pristine.globals) {
+            global.value = *value;
+        }
+        self.tables.clone_from(&pristine.tables);
+        #[cfg(feature = "gc")]
+        self.gc.clone_from(&pristine.gc);
+    }
+
+    /// The decoded body of the `index`th function the module defines, not counting imports,
+    /// decoding it now if it hasn't been yet.
+    pub fn program(&self, index: usize) -> Result<&Program, LinkError> {
+        Ok(self.shared_program(index)?)
+    }
+
+    /// As `program`, as the handle frames hold on to.
+    pub(crate) fn shared_program(&self, index: usize) -> Result<&Arc<Program>, LinkError> {
+        let cell = self
+            .programs
+            .get(index)
+            .ok_or(LinkError::FunctionNotFound)?;
+        if let Some(program) = cell.get() {
+            return Ok(program);
+        }
+        let program = Arc::new(decode_program(&self.module, index)?);
+        Ok(cell.get_or_init(|| program))
+    }
+
     pub fn find_funcidx(&self, name: &str) -> Option<u32> {
-        for export in &self.module.exports {
-            if export.name == name {
-                match export.kind {
-                    crate::module::ImportExportKind::Function => {
-                        return Some(export.index);
-                    }
-                    _ => continue,
+        self.find_export(name, ImportExportKind::Function)
+    }
+
+    /// The function exported as `name`.
+    pub fn func_by_name(&self, name: &str) -> Option<FuncHandle> {
+        self.func_by_index(self.find_funcidx(name)?)
+    }
+
+    /// The function with index `funcidx`, counting imported functions first.
+    pub fn func_by_index(&self, funcidx: u32) -> Option<FuncHandle> {
+        let func_type = self.module.func_type_of(funcidx)?.clone();
+        Some(FuncHandle { funcidx, func_type })
+    }
+
+    /// The table exported as `name`.
+    pub fn table(&self, name: &str) -> Option<&TableInstance> {
+        let idx = self.find_export(name, ImportExportKind::Table)?;
+        self.tables.get(idx as usize)
+    }
+
+    pub fn table_mut(&mut self, name: &str) -> Option<&mut TableInstance> {
+        let idx = self.find_export(name, ImportExportKind::Table)?;
+        self.tables.get_mut(idx as usize)
+    }
+
+    /// The export `name`, as something another instance can import through a `Linker`.
+    ///
+    /// A mutable global, or the first memory, is shared from then on: what the importer writes
+    /// to it this instance sees, and the other way around; see [`crate::shared`]. An execution
+    /// runs against its own copy of the memory, so export it through `Execution::export` once
+    /// there is one. Immutable globals are copied, which comes to the same thing, and so are
+    /// tables and any other memory: the functions in a table are only meaningful to the
+    /// instance they belong to.
+    ///
+    /// Functions the module defines can only be run by an execution of this instance, so only
+    /// re-exported imports resolve here; see `Execution::export_shared` for the rest.
+    pub fn export(&mut self, name: &str) -> Option<Extern> {
+        let export = self.module.export(name)?;
+        let index = export.index as usize;
+        match export.kind {
+            ImportExportKind::Function => {
+                let host = self.host_funcs.get(index)?;
+                match (&host.func, &host.caller_func) {
+                    (Some(func), _) => Some(Extern::Func(func.clone())),
+                    (None, Some(func)) => Some(Extern::TypedFunc {
+                        func_type: host.func_type.clone(),
+                        func: func.clone(),
+                    }),
+                    (None, None) => None,
+                }
+            }
+            ImportExportKind::Table => self.tables.get(index).cloned().map(Extern::Table),
+            ImportExportKind::Memory if index == 0 => {
+                let memory = self.memories.first()?;
+                Some(Extern::SharedMemory(self.links.share_memory(memory)))
+            }
+            ImportExportKind::Memory => self.memories.get(index).cloned().map(Extern::Memory),
+            ImportExportKind::Global => {
+                let global = self.globals.get(index)?;
+                if global.decl.mutable && shareable(global.decl.ty) {
+                    let shared = self.links.share_global(export.index, global.value);
+                    return Some(Extern::SharedGlobal(shared));
                 }
+                Some(Extern::Global {
+                    value: global.value,
+                    mutable: global.decl.mutable,
+                })
             }
         }
-        None
+    }
+
+    /// Hand back what's been written to the globals and memory shared with other instances.
+    pub(crate) fn push_links(&mut self) {
+        match self.memories.first() {
+            Some(memory) => self.links.push(&self.globals, memory),
+            None => self.links.push(&self.globals, &VectorMemory::new(0, None)),
+        }
+    }
+
+    fn find_export(&self, name: &str, kind: ImportExportKind) -> Option<u32> {
+        self.module
+            .export(name)
+            .filter(|export| export.kind == kind)
+            .map(|export| export.index)
     }
 
     pub fn frame_for_funcidx(&self, index: u32, args: &[Value]) -> Result<Frame, LinkError> {
+        self.pooled_frame_for_funcidx(index, args, &mut FramePool::default())
+    }
+
+    /// As `frame_for_funcidx`, but building the frame out of buffers recycled from `pool`.
+    pub(crate) fn pooled_frame_for_funcidx<S: SlotStorage>(
+        &self,
+        index: u32,
+        args: &[Value],
+        pool: &mut FramePool<S>,
+    ) -> Result<Frame<S>, LinkError> {
         // Funcidx must consider also the imports, it isn't just an offset into `code` section.
-        // So to find the function index, scan imports first
-        // Then scan functions/code.
-        // We don't actually handle imports yet, so that's a panic if it's in that space.
-        // We could make this more efficient by precomputing the number of imported functions, and
-        // stashing that in the linked struct, or even having a map of funcidx to code idx.
-        let mut num_imported_funcs = 0;
-        for (_, _, import) in self.module.imports.iter() {
-            match import {
-                crate::module::Import::Func(idx) => {
-                    num_imported_funcs += 1;
-                    if *idx == index {
-                        return Err(LinkError::UnsupportedFeature(
-                            "Imported functions not supported yet".to_string(),
-                        ));
-                    }
-                }
-                _ => continue,
-            }
+        // Imports come first, and are run by the host rather than in a frame of their own.
+        if self.module.is_imported_func(index) {
+            return Err(LinkError::UnsupportedFeature(
+                "Imported functions can't be entered directly".to_string(),
+            ));
+        }
+        let defined = self
+            .module
+            .defined_func_index(index)
+            .ok_or(LinkError::FunctionNotFound)?;
+        let params = &self
+            .module
+            .func_type_of(index)
+            .ok_or(LinkError::FunctionNotFound)?
+            .params;
+        if params.len() != args.len() {
+            return Err(LinkError::ArgumentCountMismatch(params.len(), args.len()));
         }
-        let funcidx = index - num_imported_funcs;
-        let typeindx = self.module.functions[funcidx as usize];
         // Types of arguments must match the function signature
-        for (i, (expected, actual)) in self.module.types[typeindx]
-            .params
-            .iter()
-            .zip(args.iter())
-            .enumerate()
-        {
+        for (i, (expected, actual)) in params.iter().zip(args.iter()).enumerate() {
             // TODO: this doesn't seem to work with the itoa example?!
             if *expected != actual.type_of() {
                 return Err(LinkError::ArgumentTypeMismatch(
@@ -297,67 +801,290 @@ impl Instance {
                 ));
             }
         }
-        let index = (index - num_imported_funcs) as usize;
-        if index >= self.programs.len() {
-            return Err(LinkError::FunctionNotFound);
+        let program = self.shared_program(defined)?;
+        let mut locals = pool.take_locals();
+        for arg in args {
+            arg.push_to(&mut locals);
         }
-        let program = &self.programs[index];
-        let num_locals = program.local_types.len();
-        let mut locals = args.to_vec();
 
         // Initialize remaining local variables to their zero values based on their types
-        for i in args.len()..num_locals {
-            let local_type = program.local_types[i];
-            let zero_value = match local_type {
-                ValueType::I32 => Value::I32(0),
-                ValueType::I64 => Value::I64(0),
-                ValueType::F32 => Value::F32(0.0),
-                ValueType::F64 => Value::F64(0.0),
-                ValueType::Unit => Value::Unit,
-                ValueType::V128 => Value::V128(0),
-                ValueType::FuncRef => Value::FuncRef(None),
-                ValueType::ExternRef => Value::ExternRef(None),
-            };
-            locals.push(zero_value);
+        for local_type in &program.local_types[args.len()..] {
+            Value::default_for(*local_type).push_to(&mut locals);
         }
 
-        let return_types = program.return_types.clone();
-        let mut frame = Frame {
+        let mut return_types = pool.take_return_types();
+        return_types.extend_from_slice(&program.return_types);
+        // The function's own scope is pushed by the first op of its program.
+        Ok(Frame {
             locals,
             return_types,
-            program: program.clone(),
-            stack: Stack::new(),
+            program: Arc::clone(program),
+            stack: pool.take_stack(),
             pc: 0,
-            control_stack: vec![],
+            control_stack: pool.take_control_stack(),
+            funcidx: index,
+        })
+    }
+
+    pub fn frame_for_funcname(&self, name: &str, args: &[Value]) -> Result<Frame, LinkError> {
+        let funcidx = self.find_funcidx(name).ok_or(LinkError::FunctionNotFound)?;
+        self.frame_for_funcidx(funcidx, args)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::exec::{Fault, Value};
+    use crate::instance::{mk_instance, LinkError, TableInstance};
+    use crate::module::{Module, ReferenceType};
+    use crate::{DecodeError, LoaderError, Memory};
+
+    #[test]
+    fn host_table_access() {
+        let wat = r#"(module
+            (func $a) (func $b)
+            (table (export "t") 2 3 funcref)
+            (elem (i32.const 1) $b))"#;
+        let module = Module::load(&wat::parse_str(wat).unwrap()).unwrap();
+        let mut instance = mk_instance(module).unwrap();
+        assert!(instance.table("missing").is_none());
+
+        let table = instance.table_mut("t").unwrap();
+        assert_eq!(table.get(0), Some(Value::FuncRef(None)));
+        assert_eq!(table.get(1), Some(Value::FuncRef(Some(1))));
+        assert_eq!(table.get(2), None);
+
+        table.set(0, Value::FuncRef(Some(0))).unwrap();
+        assert_eq!(table.get(0), Some(Value::FuncRef(Some(0))));
+        assert!(matches!(
+            table.set(0, Value::ExternRef(None)),
+            Err(Fault::InvalidRefType)
+        ));
+        assert!(matches!(
+            table.set(2, Value::FuncRef(None)),
+            Err(Fault::UndefinedElement)
+        ));
+
+        assert_eq!(table.grow(1, Value::FuncRef(Some(1))).unwrap(), 2);
+        assert_eq!(table.get(2), Some(Value::FuncRef(Some(1))));
+        // At the declared maximum of 3 now.
+        assert!(matches!(
+            table.grow(1, Value::FuncRef(None)),
+            Err(Fault::CannotGrowTable)
+        ));
+        assert_eq!(table.size(), 3);
+    }
+
+    #[test]
+    fn table_indices_past_32_bits() {
+        let mut table = TableInstance::new(ReferenceType::FuncRef, (1, None)).unwrap();
+        table.set(0, Value::FuncRef(Some(7))).unwrap();
+        // On a 32-bit host these would wrap around to index 0 if cut down to a usize.
+        let wrapped = 1u64 << 32;
+        assert_eq!(table.get(wrapped), None);
+        assert!(matches!(
+            table.set(wrapped, Value::FuncRef(None)),
+            Err(Fault::UndefinedElement)
+        ));
+        assert!(matches!(
+            table.write(wrapped, &[Value::FuncRef(None)]),
+            Err(Fault::TableOutOfBounds)
+        ));
+        assert!(matches!(
+            table.write(u64::MAX, &[Value::FuncRef(None)]),
+            Err(Fault::TableOutOfBounds)
+        ));
+        assert_eq!(table.get(0), Some(Value::FuncRef(Some(7))));
+
+        // Growth that overflows, or that the host can't hold, fails and leaves the table as is.
+        assert!(matches!(
+            table.grow(u64::MAX, Value::FuncRef(None)),
+            Err(Fault::CannotGrowTable)
+        ));
+        assert!(matches!(
+            table.grow(u64::MAX / 2, Value::FuncRef(None)),
+            Err(Fault::CannotGrowTable)
+        ));
+        assert_eq!(table.size(), 1);
+
+        let mut bounded =
+            TableInstance::new(ReferenceType::FuncRef, (0, Some(u64::from(u32::MAX) + 1))).unwrap();
+        assert_eq!(bounded.grow(2, Value::FuncRef(None)).unwrap(), 0);
+        assert_eq!(bounded.size(), 2);
+
+        // Nor can a table start out with more elements than the host can hold.
+        assert!(TableInstance::new(ReferenceType::FuncRef, (u64::MAX, None)).is_none());
+        assert!(matches!(
+            TableInstance::with_init(
+                ReferenceType::FuncRef,
+                (u64::MAX, None),
+                Value::FuncRef(None)
+            ),
+            Err(Fault::CannotGrowTable)
+        ));
+    }
+
+    #[test]
+    fn table_initializer() {
+        #[rustfmt::skip]
+        let module_data = [
+            0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+            // Type section: () -> ()
+            0x01, 0x04, 0x01, 0x60, 0x00, 0x00,
+            // Function section
+            0x03, 0x02, 0x01, 0x00,
+            // Table section: (table 2 funcref (ref.func 0))
+            0x04, 0x09, 0x01, 0x40, 0x00, 0x70, 0x00, 0x02, 0xd2, 0x00, 0x0b,
+            // Export section: the table as "t"
+            0x07, 0x05, 0x01, 0x01, b't', 0x01, 0x00,
+            // Code section
+            0x0a, 0x04, 0x01, 0x02, 0x00, 0x0b,
+        ];
+        let module = Module::load(&module_data).unwrap();
+        let mut instance = mk_instance(module).unwrap();
+        let table = instance.table_mut("t").unwrap();
+        assert_eq!(table.get(0), Some(Value::FuncRef(Some(0))));
+        assert_eq!(table.get(1), Some(Value::FuncRef(Some(0))));
+
+        // Growing takes its own initial value, and the table has no maximum.
+        assert_eq!(table.grow(2, Value::FuncRef(None)).unwrap(), 2);
+        assert_eq!(table.get(3), Some(Value::FuncRef(None)));
+
+        assert!(matches!(
+            TableInstance::with_init(ReferenceType::FuncRef, (1, None), Value::ExternRef(None)),
+            Err(Fault::InvalidRefType)
+        ));
+    }
+
+    #[test]
+    fn extended_const_initializers() {
+        let wat = r#"(module
+            (memory 1)
+            (global $a i32 (i32.const 10))
+            (global $b i32 (i32.add (global.get $a) (i32.mul (i32.const 3) (i32.const 4))))
+            (global $c i64 (i64.sub (i64.const 0) (i64.const 1)))
+            (data (i32.sub (global.get $b) (i32.const 2)) "hi"))"#;
+        let module = Module::load(&wat::parse_str(wat).unwrap()).unwrap();
+        let instance = mk_instance(module).unwrap();
+        assert_eq!(instance.globals[1].value, Value::I32(22));
+        assert_eq!(instance.globals[2].value, Value::I64(-1));
+        assert_eq!(&instance.memories[0].data()[20..22], b"hi");
+
+        let wat = r#"(module (global i32 (i32.div_s (i32.const 1) (i32.const 1))))"#;
+        assert!(matches!(
+            Module::load(&wat::parse_str(wat).unwrap()),
+            Err(LoaderError::DecoderError(
+                DecodeError::NonConstantInstruction(0x6d)
+            ))
+        ));
+    }
+
+    #[test]
+    fn hostile_segments_fail_to_link() {
+        // Runs off the end of memory.
+        let wat = r#"(module (memory 1) (data (i32.const 65535) "ab"))"#;
+        let module = Module::load(&wat::parse_str(wat).unwrap()).unwrap();
+        assert!(matches!(
+            mk_instance(module),
+            Err(LinkError::ActiveExpressionError(Fault::MemoryOutOfBounds))
+        ));
+        // A negative offset is a large unsigned one.
+        let wat = r#"(module (memory 1) (data (i32.const -1) "a"))"#;
+        let module = Module::load(&wat::parse_str(wat).unwrap()).unwrap();
+        assert!(mk_instance(module).is_err());
+        // Passive segments aren't copied anywhere, however little memory there is.
+        let wat = r#"(module (memory 0) (data "passive"))"#;
+        let module = Module::load(&wat::parse_str(wat).unwrap()).unwrap();
+        let instance = mk_instance(module).unwrap();
+        assert_eq!(instance.memories[0].size(), 0);
+        // An expression region that doesn't end with `end`.
+        let wat = r#"(module (global i32 (i32.const 1)))"#;
+        let mut module = Module::load(&wat::parse_str(wat).unwrap()).unwrap();
+        module.globals[0].expr.1 -= 1;
+        assert!(matches!(
+            mk_instance(module),
+            Err(LinkError::DecodeError(DecodeError::FailedToDecode(_)))
+        ));
+    }
+
+    #[test]
+    fn data_segments_checked_against_memory_size() {
+        // Empty segments may sit right at the end, but not past it.
+        let wat = r#"(module (memory 1) (data (i32.const 65536) ""))"#;
+        let module = Module::load(&wat::parse_str(wat).unwrap()).unwrap();
+        assert!(mk_instance(module).is_ok());
+        let wat = r#"(module (memory 1) (data (i32.const 65537) ""))"#;
+        let module = Module::load(&wat::parse_str(wat).unwrap()).unwrap();
+        let Err(LinkError::ActiveExpressionError(fault)) = mk_instance(module) else {
+            panic!("expected an out of bounds trap");
         };
+        assert!(matches!(fault, Fault::MemoryOutOfBounds));
+        assert_eq!(fault.to_string(), "Memory out of bounds");
+        // The bound is the memory's initial size, not its maximum.
+        let wat = r#"(module (memory 0 1) (data (i32.const 0) "x"))"#;
+        let module = Module::load(&wat::parse_str(wat).unwrap()).unwrap();
+        assert!(matches!(
+            mk_instance(module),
+            Err(LinkError::ActiveExpressionError(Fault::MemoryOutOfBounds))
+        ));
+    }
 
-        // Add function scope to control stack for proper branching
-        // The function signature for branching purposes uses the function's return type
-        let func_signature = if frame.return_types.is_empty() {
-            Type::ValueType(ValueType::Unit)
-        } else if frame.return_types.len() == 1 {
-            Type::ValueType(frame.return_types[0])
-        } else {
-            // For multiple return values, we'd need a function type, but for now assume single return
-            Type::ValueType(frame.return_types[0])
+    #[test]
+    fn element_segments_checked_against_table_size() {
+        // The second segment overruns, so none of it is written, but the first stays.
+        let wat = r#"(module
+            (table 3 funcref)
+            (func $f)
+            (elem (i32.const 0) $f)
+            (elem (i32.const 2) $f $f))"#;
+        let module = Module::load(&wat::parse_str(wat).unwrap()).unwrap();
+        let Err(LinkError::ActiveExpressionError(fault)) = mk_instance(module) else {
+            panic!("expected an out of bounds trap");
         };
+        assert!(matches!(fault, Fault::TableOutOfBounds));
+        assert_eq!(fault.to_string(), "out of bounds table access");
 
-        // Add function scope for proper control flow management
-        frame.push_control(func_signature, ScopeType::Function);
-        Ok(frame)
+        // Exactly filling the table is fine, and segments of expressions are applied too.
+        let wat = r#"(module
+            (table $t (export "t") 3 funcref)
+            (func $f)
+            (func $g)
+            (elem (table $t) (i32.const 1) funcref (ref.func $g) (ref.null func)))"#;
+        let module = Module::load(&wat::parse_str(wat).unwrap()).unwrap();
+        let mut instance = mk_instance(module).unwrap();
+        let table = instance.table_mut("t").unwrap();
+        assert_eq!(table.get(1), Some(Value::FuncRef(Some(1))));
+        assert_eq!(table.get(2), Some(Value::FuncRef(None)));
     }
 
-    pub fn frame_for_funcname(&self, name: &str, args: &[Value]) -> Result<Frame, LinkError> {
-        for export in &self.module.exports {
-            if export.name == name {
-                match export.kind {
-                    crate::module::ImportExportKind::Function => {
-                        return self.frame_for_funcidx(export.index, args);
-                    }
-                    _ => continue,
-                }
-            }
-        }
-        Err(LinkError::FunctionNotFound)
+    #[test]
+    fn element_types_must_match_table() {
+        // Function indices can't go in an externref table.
+        let bytes = wat::parse_str(
+            r#"(module
+                (table 2 externref)
+                (func $f)
+                (elem (i32.const 0) funcref (ref.func $f)))"#,
+        )
+        .unwrap();
+        let module = Module::load(&bytes).unwrap();
+        assert!(matches!(
+            mk_instance(module),
+            Err(LinkError::ActiveExpressionError(Fault::InvalidRefType))
+        ));
+
+        let mut table = TableInstance::new(ReferenceType::ExternRef, (2, None)).unwrap();
+        assert!(matches!(
+            table.write(0, &[Value::ExternRef(Some(1)), Value::FuncRef(None)]),
+            Err(Fault::InvalidRefType)
+        ));
+        // Nothing was written, even the value that was fine.
+        assert_eq!(table.get(0), Some(Value::ExternRef(None)));
+        assert!(matches!(
+            table.write(1, &[Value::ExternRef(None), Value::ExternRef(None)]),
+            Err(Fault::TableOutOfBounds)
+        ));
+        table.write(1, &[Value::ExternRef(Some(3))]).unwrap();
+        assert_eq!(table.get(1), Some(Value::ExternRef(Some(3))));
     }
 }
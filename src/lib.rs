@@ -21,44 +21,104 @@
 //!     Main opcode interpreter can be externally driven on a tick slice
 //!     Execution can be stopped and restarted
 //!     Entire engine / stack is both `Send` and serializable/deserializable
-//!     No SIMD, no Threads, no exceptions proposal, no tail call proposal
-//!          MAYBE GC proposal, but not sure yet
+//!     No SIMD, no exceptions proposal, no tail call proposal
+//!     Threads only as far as running atomics single-threaded, behind the `atomics` feature
+//!          GC proposal only partially, behind the `gc` feature
+//!     No unsafe code, unless the `unsafe-backends` feature lets individual backends opt in
 
+#![cfg_attr(not(feature = "unsafe-backends"), forbid(unsafe_code))]
+#![cfg_attr(feature = "unsafe-backends", deny(unsafe_code))]
+
+mod anomaly;
+#[cfg(feature = "atomics")]
+mod atomics;
+mod builder;
+mod cache;
+#[cfg(feature = "coverage")]
+pub mod coverage;
 mod decode;
+mod error;
 mod exec;
+mod externs;
 mod frame;
+#[cfg(feature = "gc")]
+mod gc;
+pub mod guest_coverage;
+pub mod hibernate;
 mod instance;
+mod linker;
+pub mod marshal;
 mod memory;
 mod module;
+mod numeric;
 mod op;
 mod opcode;
+#[cfg(feature = "optimize")]
+mod optimize;
+pub mod prelude;
+pub mod presets;
+pub mod rewind;
+pub mod shared;
+pub mod snapshot;
+mod spectest;
 mod stack;
+pub mod trace;
+mod visit;
+mod watch;
 
-pub use crate::decode::DecodeError;
+pub use crate::anomaly::{Anomaly, AnomalyHook, AnomalyThresholds};
+pub use crate::builder::{InstanceBuilder, InstanceLimits, MemoryBackend};
+pub use crate::cache::{ModuleCache, PooledExecution, DEFAULT_MAX_POOLED};
+pub use crate::decode::{decode, decode_function, DecodeError, Program, ScopeSig, ScopeType};
+pub use crate::error::{Error, ErrorCategory};
 use crate::module::LEB128Reader;
-pub use exec::{ExecError, Execution, Value};
+#[cfg(feature = "stats")]
+pub use exec::ExecutionStats;
+pub use exec::{
+    CallInterceptor, Determinism, ExecError, Execution, Fault, GrowDecision, Intercept,
+    MemoryGrowHook, StackLimits, Subnormals, SuspendReason, TrapDecision, TrapPolicy, Value,
+};
+#[cfg(feature = "alignment-diagnostics")]
+pub use exec::{MisalignedAccess, MisalignedAccessHook};
+pub use externs::{ExternTable, Finalizer};
+#[doc(hidden)]
 pub use frame::Frame;
+pub use frame::{FramePool, FrameView, TraceFrame};
+#[cfg(feature = "gc")]
+pub use gc::{
+    CompositeType, FieldType, GcHeap, GcObject, GcObjectKind, HeapType, StorageType, SubType,
+    DEFAULT_MAX_GC_VALUES,
+};
 pub use instance::LinkError;
-pub use instance::{mk_instance, Instance, TableInstance};
-pub use memory::{Memory, SliceMemory, VectorMemory};
+pub use instance::{mk_instance, FuncHandle, Instance, TableInstance};
+pub use linker::{Caller, CallerFunc, Extern, HostFunc, Linker, DEFAULT_MAX_REENTRY, YIELD_IMPORT};
+#[doc(hidden)]
+pub use memory::SliceMemory;
+pub use memory::{
+    bytes_for_pages, pages_for_bytes, DirtyTrackingMemory, Memory, PageOwner, ProtectedMemory,
+    VectorMemory, MAX_WASM_PAGES, WASM_PAGE_SIZE,
+};
 pub use module::{
-    Code, Data, ElementMode, ElementSegment, Elements, Global, ImportExportKind, LoaderError,
-    MemorySection, Module, ReferenceType, SectionInfo,
+    BinaryKind, CallGraph, Code, Data, ElementMode, ElementSegment, Elements, Global,
+    ImportExportKind, LoadConfig, LoaderError, MemorySection, Module, ModuleSummary, Proposal,
+    ReferenceType, SectionInfo, UnsupportedFeature,
 };
+pub use op::{MemArg, Op};
+pub use shared::{SharedGlobal, SharedMemory};
+pub use spectest::{spectest, spectest_with_print};
+#[cfg(feature = "allocator-api")]
+pub use stack::{AllocatorBuffer, AllocatorSlots};
+pub use stack::{FixedBuffer, FixedSlots, HeapSlots, SlotBuffer, SlotStorage};
+pub use visit::OpVisitor;
+pub use watch::{WatchHit, WatchedWrite};
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub enum Type {
-    ValueType(ValueType),
-    FunctionType(FuncType),
-}
-
-#[derive(Debug, Clone, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
 pub struct FuncType {
     pub params: Vec<ValueType>,
     pub results: Vec<ValueType>,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum ValueType {
     Unit,
     I32,
@@ -68,10 +128,14 @@ pub enum ValueType {
     V128,
     FuncRef,
     ExternRef,
+    /// Any reference into the GC heap: `anyref`, `eqref`, `i31ref`, `structref`, `arrayref`,
+    /// and concrete `(ref $t)` types. We don't distinguish between these at runtime.
+    #[cfg(feature = "gc")]
+    AnyRef,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum TypeSignature {
+pub(crate) enum TypeSignature {
     ValueType(ValueType),
     Index(u32),
 }
@@ -87,36 +151,68 @@ impl ValueType {
             0x7D => Ok(ValueType::F32),
             0x7C => Ok(ValueType::F64),
             0x7B => Ok(ValueType::V128),
+            #[cfg(feature = "gc")]
+            0x6e | 0x6d | 0x6c | 0x6b | 0x6a | 0x71 => Ok(ValueType::AnyRef),
+            #[cfg(feature = "gc")]
+            0x73 => Ok(ValueType::FuncRef),
+            #[cfg(feature = "gc")]
+            0x72 => Ok(ValueType::ExternRef),
             _ => Err(DecodeError::InvalidSignature(value)),
         }
     }
 
+    /// The binary encoding of this type. Under `gc` every GC reference encodes as `anyref`.
+    pub(crate) fn to_u8(self) -> u8 {
+        match self {
+            ValueType::Unit => 0x40,
+            ValueType::I32 => 0x7F,
+            ValueType::I64 => 0x7E,
+            ValueType::F32 => 0x7D,
+            ValueType::F64 => 0x7C,
+            ValueType::V128 => 0x7B,
+            ValueType::FuncRef => 0x70,
+            ValueType::ExternRef => 0x6f,
+            #[cfg(feature = "gc")]
+            ValueType::AnyRef => 0x6e,
+        }
+    }
+
+    /// How many stack slots a value of this type takes up.
+    pub(crate) fn slot_width(&self) -> u32 {
+        match self {
+            ValueType::Unit => 0,
+            ValueType::V128 => 2,
+            _ => 1,
+        }
+    }
+
     /// Read a value type from a reader without allowing for type index indirection.
     fn read(reader: &mut LEB128Reader) -> Result<Self, DecodeError> {
         let value = reader.load_imm_varuint32()?;
         if value == 0x40 {
             return Ok(ValueType::Unit);
         }
+        #[cfg(feature = "gc")]
+        if value == gc::REF_NULLABLE as u32 || value == gc::REF_NON_NULL as u32 {
+            return gc::read_ref_type(reader);
+        }
         Self::from_u32(value)
     }
 
-    /// Read a full signature, allowing for type index indirection.
+    /// Read a block type, which is either a value type, or a type index for a full signature.
+    ///
+    /// It's encoded as a signed 33-bit LEB128: the value types, and the empty type, are all single
+    /// bytes which read as negative, and a type index is non-negative, so an index which happens to
+    /// share a value type's byte, like 0x7f, is written out long enough to tell them apart.
     fn read_signature(reader: &mut LEB128Reader) -> Result<TypeSignature, DecodeError> {
-        let value = reader.load_imm_varint32()?;
-        if value == 0x40 {
-            return Ok(TypeSignature::ValueType(ValueType::Unit));
-        }
-        if value < 0 {
-            return Self::from_u32(value.unsigned_abs()).map(TypeSignature::ValueType);
+        let first = reader.peek_byte()?;
+        if first & 0xc0 == 0x40 {
+            return Self::read(reader).map(TypeSignature::ValueType);
         }
-        // Also check if it's a value type when interpreted as unsigned
-        if let Ok(vt) = Self::from_u32(value as u32) {
-            return Ok(TypeSignature::ValueType(vt));
+        let value = reader.load_imm_signed_varint64()?;
+        match u32::try_from(value) {
+            Ok(index) => Ok(TypeSignature::Index(index)),
+            Err(_) => Err(DecodeError::InvalidSignature(value as u32)),
         }
-        Ok(TypeSignature::Index(value as u32))
     }
 }
-
-pub fn add(left: usize, right: usize) -> usize {
-    left + right
-}
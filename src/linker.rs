@@ -0,0 +1,817 @@
+// Copyright (C) 2025 Ryan Daum <ryan.daum@gmail.com> This program is free
+// software: you can redistribute it and/or modify it under the terms of the GNU
+// General Public License as published by the Free Software Foundation, version
+// 3.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+use crate::builder::{InstanceBuilder, MemoryBackend};
+use crate::exec::{Fault, GlobalVar, Value};
+use crate::frame::TraceFrame;
+use crate::instance::{table_limits, table_too_large, Instance, LinkError, TableInstance};
+use crate::memory::{bytes_for_pages, Memory, VectorMemory};
+use crate::module::{Global, Import};
+use crate::shared::{Links, SharedGlobal, SharedMemory};
+use crate::stack::{SlotStorage, Stack};
+use crate::{FuncType, Module};
+use std::any::Any;
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
+use std::sync::Arc;
+
+/// A function provided by the host for a guest to import. It's handed the call's arguments in
+/// order, and returns its results.
+pub type HostFunc = Arc<dyn Fn(&[Value]) -> Result<Vec<Value>, Fault> + Send + Sync>;
+
+/// A host function which can call back into the guest running it, through the `Caller` it's
+/// handed along with its arguments.
+pub type CallerFunc = Arc<dyn Fn(&mut Caller, &[Value]) -> Result<Vec<Value>, Fault> + Send + Sync>;
+
+/// How many calls back into the guest may be in progress at once, unless the execution is told
+/// otherwise with `Execution::set_max_reentry`.
+pub const DEFAULT_MAX_REENTRY: usize = 64;
+
+/// What a `CallerFunc` reaches the execution running it through.
+pub(crate) trait Reenter {
+    fn call_guest(&mut self, funcidx: u32, args: &[Value]) -> Result<Vec<Value>, Fault>;
+    fn find_funcidx(&self, name: &str) -> Option<u32>;
+    fn reentry_depth(&self) -> usize;
+    fn capture_stack_trace(&self) -> Vec<TraceFrame>;
+    /// The embedder's state the execution carries, and its memory.
+    fn store(&mut self) -> (&mut dyn Any, &mut dyn Memory);
+}
+
+/// The execution a `CallerFunc` was called from, for it to call guest functions with.
+///
+/// A call through `call` runs to completion before it returns, on top of the frames of the guest
+/// call which led here, so guest -> host -> guest chains nest as deep as they need to, up to the
+/// execution's re-entry limit, past which calls fault with `Fault::ReentryLimit`. A nested run
+/// can't be suspended, since the host function it's inside of can't be: anything which would
+/// suspend it faults with `Fault::UnexpectedResult` instead. A faulting call's frames are
+/// unwound before the fault is handed back, leaving the host function free to carry on or to
+/// return the fault and stop the run.
+pub struct Caller<'a> {
+    execution: &'a mut dyn Reenter,
+}
+
+impl<'a> Caller<'a> {
+    pub(crate) fn new(execution: &'a mut dyn Reenter) -> Self {
+        Caller { execution }
+    }
+
+    /// Call the guest function at `funcidx` with `args`, returning its results.
+    pub fn call(&mut self, funcidx: u32, args: &[Value]) -> Result<Vec<Value>, Fault> {
+        self.execution.call_guest(funcidx, args)
+    }
+
+    /// The function index of the guest's export `name`, if it has one.
+    pub fn find_funcidx(&self, name: &str) -> Option<u32> {
+        self.execution.find_funcidx(name)
+    }
+
+    /// How many calls back into the guest are in progress, counting none for the outermost run.
+    pub fn depth(&self) -> usize {
+        self.execution.reentry_depth()
+    }
+
+    /// The guest's call stack leading to this host function, innermost frame first. See
+    /// `Execution::capture_stack_trace`.
+    pub fn capture_stack_trace(&self) -> Vec<TraceFrame> {
+        self.execution.capture_stack_trace()
+    }
+
+    /// The state the execution carries for host functions, if it's a `T`, along with the memory
+    /// the guest is running against.
+    ///
+    /// `Caller` isn't generic over the state's type, so which `T` is asked for here isn't checked
+    /// against the execution's when either is compiled. The check is made here, at run time,
+    /// each call: asking for any type other than the one the execution was made with gets `None`.
+    pub fn data<T: 'static>(&mut self) -> Option<(&mut T, &mut dyn Memory)> {
+        let (data, memory) = self.execution.store();
+        Some((data.downcast_mut()?, memory))
+    }
+}
+
+/// The module and name of the built-in import every linker provides unless told otherwise: a
+/// function taking and returning nothing, which suspends the execution with
+/// `SuspendReason::GuestYield` so the host can schedule something else before resuming it.
+pub const YIELD_IMPORT: (&str, &str) = ("wasbox", "yield");
+
+/// A function import as resolved when an instance was linked.
+#[derive(Clone)]
+pub(crate) struct HostFunction {
+    pub(crate) module: String,
+    pub(crate) name: String,
+    pub(crate) func_type: FuncType,
+    /// None if nothing was provided for the import, in which case calling it traps.
+    pub(crate) func: Option<HostFunc>,
+    /// Set instead of `func` for a function given a `Caller`, which the execution runs itself.
+    pub(crate) caller_func: Option<CallerFunc>,
+    /// Set for as long as the host function is running.
+    active: bool,
+    /// This is the built-in `wasbox.yield`, which suspends rather than calling anything.
+    pub(crate) yields: bool,
+}
+
+impl HostFunction {
+    /// Pop the arguments for the host function off `stack`, run it, and push its results.
+    pub(crate) fn call<S: SlotStorage>(&mut self, stack: &mut Stack<S>) -> Result<(), Fault> {
+        let Some(func) = self.func.clone() else {
+            return Err(Fault::UnresolvedImport(
+                self.module.clone(),
+                self.name.clone(),
+            ));
+        };
+        // Only functions given a `Caller` can get back into the guest, and they're run by the
+        // execution rather than here, but this one mustn't ever find itself halfway through a
+        // call.
+        if self.active {
+            return Err(Fault::ReentrantHostCall);
+        }
+        let mut args = vec![Value::Unit; self.func_type.params.len()];
+        for (arg, ty) in args.iter_mut().zip(&self.func_type.params).rev() {
+            *arg = Value::pop_from(*ty, stack)?;
+        }
+        self.active = true;
+        let results = func(&args);
+        self.active = false;
+        let results = results?;
+        self.check_results(&results)?;
+        for result in results {
+            result.push_to(stack);
+        }
+        Ok(())
+    }
+
+    /// Fail unless `results` are what the import says the function returns, which is all that
+    /// stands between a host function's mistake and the guest's operand stack.
+    pub(crate) fn check_results(&self, results: &[Value]) -> Result<(), Fault> {
+        let matches = results.len() == self.func_type.results.len()
+            && results
+                .iter()
+                .zip(&self.func_type.results)
+                .all(|(v, ty)| v.type_of() == *ty);
+        if !matches {
+            return Err(Fault::HostResultMismatch(
+                self.module.clone(),
+                self.name.clone(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl Debug for HostFunction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HostFunction")
+            .field("module", &self.module)
+            .field("name", &self.name)
+            .field("func_type", &self.func_type)
+            .field(
+                "resolved",
+                &(self.func.is_some() || self.caller_func.is_some() || self.yields),
+            )
+            .finish()
+    }
+}
+
+/// Something provided by the host for a module to import.
+#[derive(Clone)]
+pub enum Extern {
+    Func(HostFunc),
+    CallerFunc(CallerFunc),
+    /// As `CallerFunc`, with the signature it has to be imported as: another instance's
+    /// function, as `Execution::export_shared` hands out.
+    TypedFunc {
+        func_type: FuncType,
+        func: CallerFunc,
+    },
+    /// A copy of a global's value; importers each get their own.
+    Global {
+        value: Value,
+        mutable: bool,
+    },
+    /// A mutable global which importers share with the instance exporting it.
+    SharedGlobal(SharedGlobal),
+    Table(TableInstance),
+    /// A copy of a memory; importers each get their own.
+    Memory(VectorMemory),
+    /// A memory which importers share with the instance exporting it.
+    SharedMemory(SharedMemory),
+}
+
+impl Debug for Extern {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Extern::Func(_) => write!(f, "Func"),
+            Extern::CallerFunc(_) => write!(f, "CallerFunc"),
+            Extern::TypedFunc { func_type, .. } => {
+                f.debug_tuple("TypedFunc").field(func_type).finish()
+            }
+            Extern::Global { value, mutable } => f
+                .debug_struct("Global")
+                .field("value", value)
+                .field("mutable", mutable)
+                .finish(),
+            Extern::SharedGlobal(global) => global.fmt(f),
+            Extern::Table(table) => f.debug_tuple("Table").field(table).finish(),
+            Extern::Memory(memory) => write!(f, "Memory({} bytes)", memory.size()),
+            Extern::SharedMemory(memory) => memory.fmt(f),
+        }
+    }
+}
+
+/// Definitions to satisfy the imports of modules as they're instantiated, by module and field
+/// name. Each instance gets its own copy of any global, table or memory it imports, unless it's
+/// an `Extern::SharedGlobal` or `Extern::SharedMemory`; see [`crate::shared`].
+#[derive(Default, Clone, Debug)]
+pub struct Linker {
+    defs: HashMap<(String, String), Extern>,
+    allow_unresolved: bool,
+}
+
+impl Linker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Provide `def` for imports of `module`.`name`, replacing anything provided before.
+    pub fn define(&mut self, module: &str, name: &str, def: Extern) -> &mut Self {
+        self.defs
+            .insert((module.to_string(), name.to_string()), def);
+        self
+    }
+
+    pub fn func(
+        &mut self,
+        module: &str,
+        name: &str,
+        func: impl Fn(&[Value]) -> Result<Vec<Value>, Fault> + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.define(module, name, Extern::Func(Arc::new(func)))
+    }
+
+    /// As `func`, for a function which may call back into the guest through its `Caller`.
+    pub fn func_with_caller(
+        &mut self,
+        module: &str,
+        name: &str,
+        func: impl Fn(&mut Caller, &[Value]) -> Result<Vec<Value>, Fault> + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.define(module, name, Extern::CallerFunc(Arc::new(func)))
+    }
+
+    /// As `func`, for a function which works on the state an `Execution<M, T>` carries and on
+    /// the guest's memory.
+    ///
+    /// The linker isn't generic over `T`, and one linker can serve executions carrying state of
+    /// different types, so nothing ties this `T` to the execution's when compiling. A mismatch is
+    /// only found when the function is called: run by an execution carrying anything other than
+    /// a `T`, it faults with `Fault::HostDataMismatch`, which ends the run like any other fault.
+    /// Linking and instantiating succeed either way.
+    pub fn func_with_data<T: 'static>(
+        &mut self,
+        module: &str,
+        name: &str,
+        func: impl Fn(&mut T, &mut dyn Memory, &[Value]) -> Result<Vec<Value>, Fault>
+            + Send
+            + Sync
+            + 'static,
+    ) -> &mut Self {
+        self.func_with_caller(module, name, move |caller, args| {
+            let (data, memory) = caller.data::<T>().ok_or(Fault::HostDataMismatch)?;
+            func(data, memory, args)
+        })
+    }
+
+    pub fn global(&mut self, module: &str, name: &str, value: Value, mutable: bool) -> &mut Self {
+        self.define(module, name, Extern::Global { value, mutable })
+    }
+
+    pub fn table(&mut self, module: &str, name: &str, table: TableInstance) -> &mut Self {
+        self.define(module, name, Extern::Table(table))
+    }
+
+    pub fn memory(&mut self, module: &str, name: &str, memory: VectorMemory) -> &mut Self {
+        self.define(module, name, Extern::Memory(memory))
+    }
+
+    /// Instantiate modules even if some of their imports haven't been provided. Functions trap
+    /// when called, and globals, tables and memories start out zeroed at their minimum size.
+    pub fn allow_unresolved(&mut self, allow: bool) -> &mut Self {
+        self.allow_unresolved = allow;
+        self
+    }
+
+    /// Link and instantiate `module`.
+    pub fn instantiate(&self, module: Module) -> Result<Instance, LinkError> {
+        InstanceBuilder::new(module).imports(self).build()
+    }
+
+    /// Resolve `module`'s imports, making any memory left unresolved with `memory_backend`.
+    pub(crate) fn resolve_imports(
+        &self,
+        module: &Module,
+        memory_backend: &mut MemoryBackend,
+    ) -> Result<Imports, LinkError> {
+        let mut imports = Imports::default();
+        for (module_name, name, import) in &module.imports {
+            let def = self.defs.get(&(module_name.clone(), name.clone()));
+            let yields = def.is_none() && (module_name.as_str(), name.as_str()) == YIELD_IMPORT;
+            if def.is_none() && !yields && !self.allow_unresolved {
+                return Err(LinkError::UnresolvedImport(
+                    module_name.clone(),
+                    name.clone(),
+                ));
+            }
+            let incompatible = || LinkError::IncompatibleImport(module_name.clone(), name.clone());
+            match (import, def) {
+                (Import::Func(type_idx), def) => {
+                    let func_type = module
+                        .types
+                        .get(*type_idx as usize)
+                        .cloned()
+                        .ok_or(LinkError::FunctionNotFound)?;
+                    let (func, caller_func) = match def {
+                        Some(Extern::Func(func)) => (Some(func.clone()), None),
+                        Some(Extern::CallerFunc(func)) => (None, Some(func.clone())),
+                        Some(Extern::TypedFunc {
+                            func_type: provided,
+                            func,
+                        }) if *provided == func_type => (None, Some(func.clone())),
+                        Some(_) => return Err(incompatible()),
+                        None => (None, None),
+                    };
+                    if yields && !(func_type.params.is_empty() && func_type.results.is_empty()) {
+                        return Err(incompatible());
+                    }
+                    imports.funcs.push(HostFunction {
+                        module: module_name.clone(),
+                        name: name.clone(),
+                        func_type,
+                        func,
+                        caller_func,
+                        active: false,
+                        yields,
+                    });
+                }
+                (Import::Global(ty, mutable), def) => {
+                    let value = match def {
+                        Some(Extern::Global { value, mutable: m })
+                            if value.type_of() == *ty && m == mutable =>
+                        {
+                            *value
+                        }
+                        Some(Extern::SharedGlobal(global)) if *mutable && global.ty() == *ty => {
+                            let index = imports.globals.len() as u32;
+                            imports.links.globals.push((index, global.clone()));
+                            global.get()
+                        }
+                        Some(_) => return Err(incompatible()),
+                        None => Value::default_for(*ty),
+                    };
+                    imports.globals.push(GlobalVar {
+                        decl: Global {
+                            ty: *ty,
+                            mutable: *mutable,
+                            expr: (0, 0),
+                        },
+                        value,
+                    });
+                }
+                (Import::Table(ref_type, limits), def) => {
+                    let table = match def {
+                        Some(Extern::Table(table))
+                            if table.ref_type == *ref_type
+                                && table.size() >= u64::from(limits.0)
+                                && within_max(table.limits.1, limits.1.map(u64::from)) =>
+                        {
+                            table.clone()
+                        }
+                        Some(_) => return Err(incompatible()),
+                        None => TableInstance::new(*ref_type, table_limits(*limits))
+                            .ok_or_else(|| table_too_large(limits.0.into()))?,
+                    };
+                    imports.tables.push(table);
+                }
+                (Import::Memory(limits), def) => {
+                    let memory = match def {
+                        Some(Extern::Memory(memory))
+                            if memory.pages() as u32 >= limits.0
+                                && within_max(memory.max_pages().map(|p| p as u32), limits.1) =>
+                        {
+                            memory.clone()
+                        }
+                        // Only the first memory is run against, so only it can be shared.
+                        Some(Extern::SharedMemory(memory))
+                            if memory.pages() as u32 >= limits.0
+                                && within_max(memory.max_pages().map(|p| p as u32), limits.1) =>
+                        {
+                            let (copy, version) = memory.import();
+                            if imports.memories.is_empty() {
+                                imports.links.memory = Some((memory.clone(), version));
+                            }
+                            copy
+                        }
+                        Some(_) => return Err(incompatible()),
+                        None => memory_backend(
+                            bytes_for_pages(limits.0 as usize),
+                            limits.1.map(|max| bytes_for_pages(max as usize)),
+                        ),
+                    };
+                    imports.memories.push(memory);
+                }
+            }
+        }
+        Ok(imports)
+    }
+}
+
+/// An import declaring a maximum can only be satisfied by something which can't grow past it.
+fn within_max<T: PartialOrd>(provided: Option<T>, required: Option<T>) -> bool {
+    match (provided, required) {
+        (_, None) => true,
+        (Some(provided), Some(required)) => provided <= required,
+        (None, Some(_)) => false,
+    }
+}
+
+/// A module's imports, resolved, in import order. Each comes before anything the module defines
+/// of the same kind in its index space.
+#[derive(Default)]
+pub(crate) struct Imports {
+    pub(crate) funcs: Vec<HostFunction>,
+    pub(crate) globals: Vec<GlobalVar>,
+    pub(crate) tables: Vec<TableInstance>,
+    pub(crate) memories: Vec<VectorMemory>,
+    /// Which of them are shared with other instances.
+    pub(crate) links: Links,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::exec::{ExecError, Execution, Fault, SuspendReason, Value};
+    use crate::frame::TraceFrame;
+    use crate::instance::{mk_instance, LinkError};
+    use crate::linker::Linker;
+    use crate::memory::VectorMemory;
+    use crate::module::Module;
+    use crate::op::Op;
+    use std::sync::atomic::{AtomicI32, Ordering};
+    use std::sync::Arc;
+
+    const WAT: &str = r#"(module
+        (import "env" "add" (func $add (param i32 i64) (result i64)))
+        (import "env" "note" (func $note (param i32)))
+        (type $binop (func (param i32 i64) (result i64)))
+        (table 1 funcref)
+        (elem (i32.const 0) $add)
+        (func (export "f") (param i32) (result i64)
+            (call $note (local.get 0))
+            (i64.add
+                (call $add (local.get 0) (i64.const 10))
+                (call_indirect (type $binop) (i32.const 1) (i64.const 100) (i32.const 0)))))"#;
+
+    #[test]
+    fn guest_calls_host_functions() {
+        let noted = Arc::new(AtomicI32::new(0));
+        let seen = noted.clone();
+        let mut linker = Linker::new();
+        linker
+            .func("env", "add", |args| match args {
+                [Value::I32(a), Value::I64(b)] => Ok(vec![Value::I64(*a as i64 + b)]),
+                _ => Err(Fault::StackUnderflow),
+            })
+            .func("env", "note", move |args| {
+                if let [Value::I32(v)] = args {
+                    seen.store(*v, Ordering::SeqCst);
+                }
+                Ok(vec![])
+            });
+
+        let module = Module::load(&wat::parse_str(WAT).unwrap()).unwrap();
+        let instance = linker.instantiate(module).unwrap();
+        let funcidx = instance.find_funcidx("f").unwrap();
+        let mut execution = Execution::new(instance, VectorMemory::new(0, None));
+        execution.prepare(funcidx, &[Value::I32(5)]).unwrap();
+        execution.run().unwrap();
+        assert_eq!(execution.result().unwrap(), &[Value::I64(116)]);
+        assert_eq!(noted.load(Ordering::SeqCst), 5);
+    }
+
+    #[test]
+    fn host_results_checked_against_import() {
+        let wat = r#"(module
+            (import "env" "divmod" (func $divmod (param i32 i32) (result i32 i32)))
+            (import "env" "split" (func $split (param i64) (result i32 i32)))
+            (func (export "divmod") (param i32 i32) (result i32 i32)
+                (call $divmod (local.get 0) (local.get 1)))
+            (func (export "split") (param i64) (result i32 i32)
+                (call $split (local.get 0))))"#;
+        let run = |linker: &Linker, name: &str, args: &[Value]| {
+            let module = Module::load(&wat::parse_str(wat).unwrap()).unwrap();
+            let instance = linker.instantiate(module).unwrap();
+            let funcidx = instance.find_funcidx(name).unwrap();
+            let mut execution = Execution::new(instance, VectorMemory::new(0, None));
+            execution.prepare(funcidx, args).unwrap();
+            execution
+                .run()
+                .map(|_| execution.result().unwrap().to_vec())
+        };
+
+        let mut linker = Linker::new();
+        linker
+            .func("env", "divmod", |args| match args {
+                [Value::I32(a), Value::I32(b)] => Ok(vec![Value::I32(a / b), Value::I32(a % b)]),
+                _ => Err(Fault::StackUnderflow),
+            })
+            .func_with_caller("env", "split", |_, args| match args {
+                [Value::I64(v)] => Ok(vec![Value::I32(*v as i32), Value::I32((v >> 32) as i32)]),
+                _ => Err(Fault::StackUnderflow),
+            });
+        assert_eq!(
+            run(&linker, "divmod", &[Value::I32(17), Value::I32(5)]).unwrap(),
+            vec![Value::I32(3), Value::I32(2)]
+        );
+        assert_eq!(
+            run(&linker, "split", &[Value::I64(0x1_0000_0002)]).unwrap(),
+            vec![Value::I32(2), Value::I32(1)]
+        );
+
+        // Too few results, or the wrong types, are caught rather than pushed.
+        linker
+            .func("env", "divmod", |_| Ok(vec![Value::I32(3)]))
+            .func_with_caller("env", "split", |_, _| {
+                Ok(vec![Value::I32(0), Value::I64(0)])
+            });
+        let result = run(&linker, "divmod", &[Value::I32(17), Value::I32(5)]);
+        assert!(matches!(
+            result,
+            Err(ExecError::ExecutionFault(Fault::HostResultMismatch(m, n)))
+                if m == "env" && n == "divmod"
+        ));
+        let result = run(&linker, "split", &[Value::I64(0)]);
+        assert!(matches!(
+            result,
+            Err(ExecError::ExecutionFault(Fault::HostResultMismatch(m, n)))
+                if m == "env" && n == "split"
+        ));
+    }
+
+    #[test]
+    fn unresolved_imports() {
+        let load = || Module::load(&wat::parse_str(WAT).unwrap()).unwrap();
+        let mut linker = Linker::new();
+        linker.func("env", "add", |_| Ok(vec![Value::I64(0)]));
+        assert!(matches!(
+            linker.instantiate(load()),
+            Err(LinkError::UnresolvedImport(m, n)) if m == "env" && n == "note"
+        ));
+
+        // Instantiating without a linker leaves the imports to trap when called.
+        let instance = mk_instance(load()).unwrap();
+        let funcidx = instance.find_funcidx("f").unwrap();
+        let mut execution = Execution::new(instance, VectorMemory::new(0, None));
+        execution.prepare(funcidx, &[Value::I32(5)]).unwrap();
+        assert!(matches!(
+            execution.run(),
+            Err(ExecError::ExecutionFault(Fault::UnresolvedImport(m, n))) if m == "env" && n == "note"
+        ));
+    }
+
+    #[test]
+    fn exports_link_into_other_instances() {
+        let provider = r#"(module
+            (import "env" "add" (func $add (param i64 i64) (result i64)))
+            (export "add" (func $add))
+            (func (export "local"))
+            (memory (export "mem") 1)
+            (global (export "g") i32 (i32.const 42))
+            (table (export "t") 2 funcref)
+            (data (i32.const 0) "hi"))"#;
+        let mut linker = Linker::new();
+        linker.func("env", "add", |args| match args {
+            [Value::I64(a), Value::I64(b)] => Ok(vec![Value::I64(a + b)]),
+            _ => Err(Fault::HostAbort("bad arguments".to_string())),
+        });
+        let mut provider = linker
+            .instantiate(Module::load(&wat::parse_str(provider).unwrap()).unwrap())
+            .unwrap();
+        assert!(provider.export("local").is_none());
+        assert!(provider.export("missing").is_none());
+
+        let mut registered = Linker::new();
+        for name in ["add", "mem", "g", "t"] {
+            registered.define("provider", name, provider.export(name).unwrap());
+        }
+        let consumer = r#"(module
+            (import "provider" "add" (func $add (param i64 i64) (result i64)))
+            (import "provider" "mem" (memory 1))
+            (import "provider" "g" (global $g i32))
+            (import "provider" "t" (table 2 funcref))
+            (func (export "f") (result i64)
+                (call $add
+                    (i64.extend_i32_u (global.get $g))
+                    (i64.load8_u (i32.const 1)))))"#;
+        let instance = registered
+            .instantiate(Module::load(&wat::parse_str(consumer).unwrap()).unwrap())
+            .unwrap();
+        let memory = instance.memories[0].clone();
+        let mut execution = Execution::new(instance, memory);
+        assert_eq!(
+            execution.invoke("f", &[]).unwrap(),
+            vec![Value::I64(42 + b'i' as i64)]
+        );
+    }
+
+    #[test]
+    fn host_functions_reach_execution_data() {
+        #[derive(Default)]
+        struct Session {
+            greetings: Vec<String>,
+        }
+        let wat = r#"(module
+            (import "env" "greet" (func $greet (param i32 i32)))
+            (memory 1)
+            (data (i32.const 16) "hello")
+            (func (export "f") (call $greet (i32.const 16) (i32.const 5))))"#;
+        let mut linker = Linker::new();
+        linker.func_with_data("env", "greet", |session: &mut Session, memory, args| {
+            let [Value::I32(ptr), Value::I32(len)] = args else {
+                return Err(Fault::HostAbort("bad arguments".to_string()));
+            };
+            let bytes = &memory.data()[*ptr as usize..(*ptr + *len) as usize];
+            session
+                .greetings
+                .push(String::from_utf8_lossy(bytes).into_owned());
+            Ok(vec![])
+        });
+        let load = || {
+            let module = Module::load(&wat::parse_str(wat).unwrap()).unwrap();
+            linker.instantiate(module).unwrap()
+        };
+
+        let instance = load();
+        let memory = instance.memories[0].clone();
+        let mut execution = Execution::with_data(instance, memory, Session::default());
+        execution.invoke("f", &[]).unwrap();
+        execution.invoke("f", &[]).unwrap();
+        assert_eq!(execution.data().greetings, ["hello", "hello"]);
+
+        // Linking doesn't know which state the execution will carry, so one carrying something
+        // else only finds out when the call is made.
+        let instance = load();
+        let memory = instance.memories[0].clone();
+        let mut execution = Execution::new(instance, memory);
+        assert!(matches!(
+            execution.invoke("f", &[]),
+            Err(ExecError::ExecutionFault(Fault::HostDataMismatch))
+        ));
+    }
+
+    #[test]
+    fn host_functions_capture_stack_traces() {
+        let wat = r#"(module
+            (import "env" "log" (func $log (param i32)))
+            (func $helper (export "helper") (param i32)
+                (nop)
+                (call $log (local.get 0)))
+            (func $quiet (call $helper (i32.const 2)))
+            (func (export "main")
+                (call $helper (i32.const 1))
+                (call $quiet)))"#;
+        let mut linker = Linker::new();
+        linker.func_with_caller("env", "log", |caller, _| {
+            let trace = caller.capture_stack_trace();
+            let (traces, _) = caller
+                .data::<Vec<Vec<TraceFrame>>>()
+                .ok_or(Fault::HostDataMismatch)?;
+            traces.push(trace);
+            Ok(vec![])
+        });
+        let module = Module::load(&wat::parse_str(wat).unwrap()).unwrap();
+        let instance = linker.instantiate(module).unwrap();
+        let traces: Vec<Vec<TraceFrame>> = vec![];
+        let mut execution = Execution::with_data(instance, VectorMemory::new(0, None), traces);
+        execution.invoke("main", &[]).unwrap();
+
+        let traces = execution.data();
+        assert_eq!(traces.len(), 2);
+        let funcs = |trace: &[TraceFrame]| trace.iter().map(|f| f.funcidx).collect::<Vec<_>>();
+        assert_eq!(funcs(&traces[0]), [1, 3]);
+        assert_eq!(funcs(&traces[1]), [1, 2, 3]);
+        // Each frame is at its call, which for the helper comes after the nop.
+        let program = execution.instance().program(0).unwrap();
+        assert!(matches!(program.ops[traces[0][0].pc], Op::Call(0)));
+        assert_eq!(
+            traces[0][0].to_string(),
+            format!("helper (func 1) at op {}", traces[0][0].pc)
+        );
+        assert_eq!(traces[1][1].name, None);
+        assert_eq!(traces[1][2].name.as_deref(), Some("main"));
+
+        // Nothing's running once the call is done.
+        assert!(execution.capture_stack_trace().is_empty());
+    }
+
+    #[test]
+    fn guests_yield_to_the_host() {
+        let wat = r#"(module
+            (import "wasbox" "yield" (func $yield))
+            (func (export "count") (param i32) (result i32) (local $i i32)
+                (loop $l
+                    (call $yield)
+                    (local.set $i (i32.add (local.get $i) (i32.const 1)))
+                    (br_if $l (i32.lt_u (local.get $i) (local.get 0))))
+                (local.get $i)))"#;
+        let module = Module::load(&wat::parse_str(wat).unwrap()).unwrap();
+        let instance = Linker::new().instantiate(module).unwrap();
+        let funcidx = instance.find_funcidx("count").unwrap();
+        let mut execution = Execution::new(instance, VectorMemory::new(0, None));
+        execution.prepare(funcidx, &[Value::I32(3)]).unwrap();
+        let mut yields = 0;
+        while let Err(e) = execution.run() {
+            assert!(matches!(e, ExecError::Suspended(SuspendReason::GuestYield)));
+            yields += 1;
+        }
+        assert_eq!(yields, 3);
+        assert_eq!(execution.result().unwrap(), &[Value::I32(3)]);
+
+        // It has to have the right signature, and can be replaced.
+        let wat = r#"(module (import "wasbox" "yield" (func (param i32))))"#;
+        let load = || Module::load(&wat::parse_str(wat).unwrap()).unwrap();
+        assert!(matches!(
+            Linker::new().instantiate(load()),
+            Err(LinkError::IncompatibleImport(..))
+        ));
+        let mut linker = Linker::new();
+        linker.func("wasbox", "yield", |_| Ok(vec![]));
+        assert!(linker.instantiate(load()).is_ok());
+    }
+
+    #[test]
+    fn hosts_call_back_into_the_guest() {
+        let wat = r#"(module
+            (import "env" "apply" (func $apply (param i32) (result i32)))
+            (import "env" "down" (func $down (param i32) (result i32)))
+            (func (export "double") (param i32) (result i32)
+                (i32.mul (local.get 0) (i32.const 2)))
+            (func (export "trap") (param i32) (result i32) (unreachable))
+            (func (export "f") (param i32) (result i32)
+                (i32.add (call $apply (local.get 0)) (i32.const 1)))
+            (func (export "countdown") (param i32) (result i32)
+                (if (result i32) (i32.eqz (local.get 0))
+                    (then (i32.const 0))
+                    (else (call $down (local.get 0))))))"#;
+        let deepest = Arc::new(AtomicI32::new(0));
+        let seen = deepest.clone();
+        let mut linker = Linker::new();
+        linker
+            .func_with_caller("env", "apply", |caller, args| {
+                // Faults in the guest come back here, with its frames already unwound.
+                let trap = caller.find_funcidx("trap").unwrap();
+                assert!(matches!(caller.call(trap, args), Err(Fault::Unreachable)));
+                let double = caller.find_funcidx("double").unwrap();
+                caller.call(double, args)
+            })
+            .func_with_caller("env", "down", move |caller, args| {
+                seen.fetch_max(caller.depth() as i32, Ordering::SeqCst);
+                let Value::I32(n) = args[0] else {
+                    return Err(Fault::StackUnderflow);
+                };
+                let countdown = caller.find_funcidx("countdown").unwrap();
+                match caller.call(countdown, &[Value::I32(n - 1)])?[..] {
+                    [Value::I32(r)] => Ok(vec![Value::I32(r + 1)]),
+                    _ => Err(Fault::StackUnderflow),
+                }
+            });
+        let module = Module::load(&wat::parse_str(wat).unwrap()).unwrap();
+        let instance = linker.instantiate(module).unwrap();
+        let f = instance.find_funcidx("f").unwrap();
+        let countdown = instance.find_funcidx("countdown").unwrap();
+        let mut execution = Execution::new(instance, VectorMemory::new(0, None));
+
+        execution.prepare(f, &[Value::I32(5)]).unwrap();
+        execution.run().unwrap();
+        assert_eq!(execution.result().unwrap(), &[Value::I32(11)]);
+
+        execution.set_max_reentry(10);
+        execution.prepare(countdown, &[Value::I32(10)]).unwrap();
+        execution.run().unwrap();
+        assert_eq!(execution.result().unwrap(), &[Value::I32(10)]);
+        assert_eq!(deepest.load(Ordering::SeqCst), 9);
+        assert_eq!(execution.frame_stack_len(), 0);
+
+        execution.prepare(countdown, &[Value::I32(11)]).unwrap();
+        assert!(matches!(
+            execution.run(),
+            Err(ExecError::ExecutionFault(Fault::ReentryLimit))
+        ));
+    }
+}
@@ -0,0 +1,280 @@
+// Copyright (C) 2025 Ryan Daum <ryan.daum@gmail.com> This program is free
+// software: you can redistribute it and/or modify it under the terms of the GNU
+// General Public License as published by the Free Software Foundation, version
+// 3.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+//! `wasbox MODULE.wasm`: load a module and poke at it from a prompt. Its imports are left
+//! unresolved, and trap if they're called. Type `help` at the prompt for the commands.
+
+#![forbid(unsafe_code)]
+
+use std::io::{BufRead, Write};
+use wasbox::{Execution, ImportExportKind, Linker, Memory, Module, Value, ValueType, VectorMemory};
+
+const HELP: &str = "\
+exports                  list the module's exports, with function signatures
+call NAME [ARG ...]      call the exported function NAME, with literal arguments
+mem OFFSET LEN           dump LEN bytes of memory from OFFSET
+dis NAME|INDEX           disassemble a function, by export name or function index
+help                     show this
+quit                     leave";
+
+/// A module being poked at, and an execution of it which lasts across commands, so that calls
+/// see what earlier ones did to memory and globals.
+struct Repl {
+    module: Module,
+    execution: Execution<VectorMemory>,
+}
+
+impl Repl {
+    fn new(bytes: &[u8]) -> Result<Self, String> {
+        let module = Module::load(bytes).map_err(|e| e.to_string())?;
+        let mut linker = Linker::new();
+        linker.allow_unresolved(true);
+        let instance = linker
+            .instantiate(module.clone())
+            .map_err(|e| e.to_string())?;
+        let memory = match instance.memories.first() {
+            Some(memory) => memory.clone(),
+            None => VectorMemory::new(0, None),
+        };
+        Ok(Repl {
+            module,
+            execution: Execution::new(instance, memory),
+        })
+    }
+
+    /// Run one line's command, returning what to print.
+    fn command(&mut self, line: &str) -> Result<String, String> {
+        let mut words = line.split_whitespace();
+        let Some(command) = words.next() else {
+            return Ok(String::new());
+        };
+        let words: Vec<_> = words.collect();
+        match (command, words.as_slice()) {
+            ("exports", []) => Ok(self.exports()),
+            ("call", [name, args @ ..]) => self.call(name, args),
+            ("mem", [offset, len]) => self.mem(parse_usize(offset)?, parse_usize(len)?),
+            ("dis", [func]) => self.dis(func),
+            ("help", []) => Ok(HELP.to_string()),
+            _ => Err(format!("don't know {line:?}; try `help`")),
+        }
+    }
+
+    fn exports(&self) -> String {
+        let instance = self.execution.instance();
+        let mut lines = vec![];
+        for (name, kind) in self.module.summary().exports {
+            let line = match (kind, instance.func_by_name(&name)) {
+                (ImportExportKind::Function, Some(func)) => {
+                    let ty = func.func_type();
+                    format!("func {name} {:?} -> {:?}", ty.params, ty.results)
+                }
+                (ImportExportKind::Function, None) => format!("func {name}"),
+                (ImportExportKind::Table, _) => format!("table {name}"),
+                (ImportExportKind::Memory, _) => format!("memory {name}"),
+                (ImportExportKind::Global, _) => format!("global {name}"),
+            };
+            lines.push(line);
+        }
+        lines.join("\n")
+    }
+
+    fn call(&mut self, name: &str, args: &[&str]) -> Result<String, String> {
+        let func = self
+            .execution
+            .instance()
+            .func_by_name(name)
+            .ok_or_else(|| format!("no function exported as {name:?}"))?;
+        let params = &func.func_type().params;
+        if params.len() != args.len() {
+            return Err(format!("{name} takes {} arguments", params.len()));
+        }
+        let args = params
+            .iter()
+            .zip(args)
+            .map(|(ty, arg)| parse_value(*ty, arg))
+            .collect::<Result<Vec<_>, _>>()?;
+        match self.execution.invoke_func(&func, &args) {
+            Ok(results) => Ok(results
+                .iter()
+                .map(|v| format!("{v:?}"))
+                .collect::<Vec<_>>()
+                .join(" ")),
+            Err(e) => {
+                // Leave the execution ready for the next call.
+                self.execution.reset();
+                Err(e.to_string())
+            }
+        }
+    }
+
+    fn mem(&self, offset: usize, len: usize) -> Result<String, String> {
+        let data = self.execution.memory().data();
+        let bytes = offset
+            .checked_add(len)
+            .and_then(|end| data.get(offset..end))
+            .ok_or_else(|| format!("memory is only {} bytes", data.len()))?;
+        let mut lines = vec![];
+        for (i, row) in bytes.chunks(16).enumerate() {
+            let hex: Vec<_> = row.iter().map(|b| format!("{b:02x}")).collect();
+            let text: String = row
+                .iter()
+                .map(|b| match b {
+                    0x20..=0x7e => *b as char,
+                    _ => '.',
+                })
+                .collect();
+            lines.push(format!(
+                "{:08x}  {:<47}  {text}",
+                offset + i * 16,
+                hex.join(" ")
+            ));
+        }
+        Ok(lines.join("\n"))
+    }
+
+    fn dis(&self, func: &str) -> Result<String, String> {
+        let instance = self.execution.instance();
+        let handle = match func.parse() {
+            Ok(funcidx) => instance.func_by_index(funcidx),
+            Err(_) => instance.func_by_name(func),
+        }
+        .ok_or_else(|| format!("no function {func}"))?;
+        let defined = self
+            .module
+            .defined_func_index(handle.index())
+            .ok_or_else(|| format!("function {func} is imported"))?;
+        let program = instance.program(defined).map_err(|e| e.to_string())?;
+        let lines: Vec<_> = program
+            .ops_with_depth()
+            .enumerate()
+            .map(|(pc, (depth, op))| format!("{pc:5}  {:indent$}{op:?}", "", indent = depth * 2))
+            .collect();
+        Ok(lines.join("\n"))
+    }
+}
+
+fn parse_usize(word: &str) -> Result<usize, String> {
+    match word.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => word.parse(),
+    }
+    .map_err(|_| format!("{word:?} isn't a number"))
+}
+
+/// Read `word` as a value of type `ty`. Integers can be given in hex, with `0x`.
+fn parse_value(ty: ValueType, word: &str) -> Result<Value, String> {
+    let bad = || format!("{word:?} isn't an {ty:?}");
+    let (negative, digits) = match word.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, word),
+    };
+    let integer = || -> Result<i128, String> {
+        let magnitude = match digits.strip_prefix("0x") {
+            Some(hex) => i128::from_str_radix(hex, 16),
+            None => digits.parse(),
+        }
+        .map_err(|_| bad())?;
+        Ok(if negative { -magnitude } else { magnitude })
+    };
+    // Either signed or unsigned spellings are taken, as the bits are the same.
+    match ty {
+        ValueType::I32 => match integer()? {
+            v @ -0x8000_0000..=0xffff_ffff => Ok(Value::I32(v as u32 as i32)),
+            _ => Err(bad()),
+        },
+        ValueType::I64 => match integer()? {
+            v @ -0x8000_0000_0000_0000..=0xffff_ffff_ffff_ffff => Ok(Value::I64(v as u64 as i64)),
+            _ => Err(bad()),
+        },
+        ValueType::F32 => word.parse().map(Value::F32).map_err(|_| bad()),
+        ValueType::F64 => word.parse().map(Value::F64).map_err(|_| bad()),
+        _ => Err(format!("can't give a {ty:?} from the command line")),
+    }
+}
+
+fn main() {
+    let Some(path) = std::env::args().nth(1) else {
+        eprintln!("usage: wasbox MODULE.wasm");
+        std::process::exit(2);
+    };
+    let bytes = match std::fs::read(&path) {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            eprintln!("{path}: {e}");
+            std::process::exit(1);
+        }
+    };
+    let mut repl = match Repl::new(&bytes) {
+        Ok(repl) => repl,
+        Err(e) => {
+            eprintln!("{path}: {e}");
+            std::process::exit(1);
+        }
+    };
+    let stdin = std::io::stdin();
+    let mut lines = stdin.lock().lines();
+    loop {
+        print!("> ");
+        let _ = std::io::stdout().flush();
+        let Some(Ok(line)) = lines.next() else {
+            break;
+        };
+        match line.trim() {
+            "quit" | "exit" => break,
+            line => match repl.command(line) {
+                Ok(out) if out.is_empty() => {}
+                Ok(out) => println!("{out}"),
+                Err(e) => println!("error: {e}"),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Repl;
+
+    #[test]
+    fn repl_commands() {
+        let wat = r#"(module
+            (memory (export "mem") 1)
+            (data (i32.const 16) "hi there")
+            (func (export "add") (param i32 i64) (result i64)
+                (i64.add (i64.extend_i32_s (local.get 0)) (local.get 1)))
+            (func (export "store") (param i32 i32) (i32.store (local.get 0) (local.get 1))))"#;
+        let mut repl = Repl::new(&wat::parse_str(wat).unwrap()).unwrap();
+        let exports = repl.command("exports").unwrap();
+        assert!(exports.contains("memory mem"));
+        assert!(exports.contains("func add [I32, I64] -> [I64]"));
+
+        assert_eq!(repl.command("call add -3 0x10").unwrap(), "I64(13)");
+        assert!(repl.command("call add 1").is_err());
+        assert!(repl.command("call add x 1").is_err());
+        assert!(repl.command("call nothing").is_err());
+
+        // Calls see what earlier ones did, and faults leave the REPL usable.
+        repl.command("call store 0 0x64636261").unwrap();
+        let dump = repl.command("mem 0 24").unwrap();
+        assert!(dump.starts_with("00000000  61 62 63 64 00"));
+        assert!(dump.contains("hi there"));
+        assert!(repl.command("call store 0x10000 0").is_err());
+        assert_eq!(repl.command("call add 1 2").unwrap(), "I64(3)");
+        assert!(repl.command("mem 65530 10").is_err());
+
+        let dis = repl.command("dis add").unwrap();
+        assert_eq!(dis, repl.command("dis 0").unwrap());
+        assert!(dis.contains("GetLocal(1)"));
+        assert!(repl.command("dis 5").is_err());
+        assert!(repl.command("frobnicate").is_err());
+    }
+}
@@ -0,0 +1,446 @@
+// Copyright (C) 2025 Ryan Daum <ryan.daum@gmail.com> This program is free
+// software: you can redistribute it and/or modify it under the terms of the GNU
+// General Public License as published by the Free Software Foundation, version
+// 3.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+//! Moving strings and plain structs in and out of guest memory, the way C-ish guests lay them
+//! out: little-endian, NUL-terminated or length-prefixed strings, and pointers as `u32` offsets.
+
+use crate::exec::{ExecError, Execution};
+use crate::memory::Memory;
+use crate::stack::SlotStorage;
+use crate::{Instance, Value};
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::str::Utf8Error;
+
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub enum MarshalError {
+    /// `len` bytes at `ptr` aren't all inside memory.
+    OutOfBounds {
+        ptr: u32,
+        len: usize,
+    },
+    /// No NUL between the string starting at the pointer and the end of memory.
+    Unterminated(u32),
+    InvalidUtf8(Utf8Error),
+    /// A string to be written as a C string has a NUL inside it.
+    InteriorNul,
+    /// The instance doesn't export an allocator function under this name.
+    MissingExport(String),
+    /// The allocator returned a null pointer for a request of this size.
+    AllocationFailed(u32),
+    /// The allocator returned something other than a single i32.
+    BadAllocatorResult,
+    /// The allocator can't be called while other guest code is mid-call.
+    Busy,
+    Exec(ExecError),
+}
+
+impl Display for MarshalError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MarshalError::OutOfBounds { ptr, len } => {
+                write!(f, "{len} bytes at {ptr:#x} out of bounds")
+            }
+            MarshalError::Unterminated(ptr) => write!(f, "unterminated string at {ptr:#x}"),
+            MarshalError::InvalidUtf8(e) => write!(f, "invalid UTF-8: {e}"),
+            MarshalError::InteriorNul => write!(f, "string contains a NUL"),
+            MarshalError::MissingExport(name) => write!(f, "no exported function {name:?}"),
+            MarshalError::AllocationFailed(size) => {
+                write!(f, "guest failed to allocate {size} bytes")
+            }
+            MarshalError::BadAllocatorResult => write!(f, "allocator didn't return an i32"),
+            MarshalError::Busy => write!(f, "execution has live frames"),
+            MarshalError::Exec(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl Error for MarshalError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            MarshalError::InvalidUtf8(e) => Some(e),
+            MarshalError::Exec(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<Utf8Error> for MarshalError {
+    fn from(e: Utf8Error) -> Self {
+        MarshalError::InvalidUtf8(e)
+    }
+}
+
+impl From<ExecError> for MarshalError {
+    fn from(e: ExecError) -> Self {
+        MarshalError::Exec(e)
+    }
+}
+
+/// A value with a fixed little-endian layout in guest memory. Implement it for a `#[repr(C)]`
+/// struct by reading and writing each field at its C offset, padding included in `SIZE`.
+pub trait Pod: Sized {
+    const SIZE: usize;
+    /// Decode from exactly `SIZE` bytes.
+    fn read_from(bytes: &[u8]) -> Self;
+    /// Encode into exactly `SIZE` bytes.
+    fn write_to(&self, bytes: &mut [u8]);
+}
+
+macro_rules! pod_primitive {
+    ($($t:ty),*) => {
+        $(impl Pod for $t {
+            const SIZE: usize = std::mem::size_of::<$t>();
+            fn read_from(bytes: &[u8]) -> Self {
+                <$t>::from_le_bytes(bytes.try_into().unwrap())
+            }
+            fn write_to(&self, bytes: &mut [u8]) {
+                bytes.copy_from_slice(&self.to_le_bytes());
+            }
+        })*
+    };
+}
+
+pod_primitive!(u8, i8, u16, i16, u32, i32, u64, i64, f32, f64);
+
+impl<T: Pod, const N: usize> Pod for [T; N] {
+    const SIZE: usize = T::SIZE * N;
+    fn read_from(bytes: &[u8]) -> Self {
+        std::array::from_fn(|i| T::read_from(&bytes[i * T::SIZE..(i + 1) * T::SIZE]))
+    }
+    fn write_to(&self, bytes: &mut [u8]) {
+        for (value, chunk) in self.iter().zip(bytes.chunks_exact_mut(T::SIZE)) {
+            value.write_to(chunk);
+        }
+    }
+}
+
+fn range(
+    memory: &impl Memory,
+    ptr: u32,
+    len: usize,
+) -> Result<std::ops::Range<usize>, MarshalError> {
+    let start = ptr as usize;
+    match start.checked_add(len) {
+        Some(end) if end <= memory.size() => Ok(start..end),
+        _ => Err(MarshalError::OutOfBounds { ptr, len }),
+    }
+}
+
+/// The `len` bytes at `ptr`, to write to, through `Memory::range_mut` so that only they count
+/// as written.
+fn range_mut(memory: &mut impl Memory, ptr: u32, len: usize) -> Result<&mut [u8], MarshalError> {
+    memory
+        .range_mut(ptr as usize, len)
+        .map_err(|_| MarshalError::OutOfBounds { ptr, len })
+}
+
+pub fn read_bytes(memory: &impl Memory, ptr: u32, len: usize) -> Result<&[u8], MarshalError> {
+    let range = range(memory, ptr, len)?;
+    Ok(&memory.data()[range])
+}
+
+pub fn write_bytes(memory: &mut impl Memory, ptr: u32, bytes: &[u8]) -> Result<(), MarshalError> {
+    range_mut(memory, ptr, bytes.len())?.copy_from_slice(bytes);
+    Ok(())
+}
+
+pub fn read<T: Pod>(memory: &impl Memory, ptr: u32) -> Result<T, MarshalError> {
+    Ok(T::read_from(read_bytes(memory, ptr, T::SIZE)?))
+}
+
+pub fn write<T: Pod>(memory: &mut impl Memory, ptr: u32, value: &T) -> Result<(), MarshalError> {
+    value.write_to(range_mut(memory, ptr, T::SIZE)?);
+    Ok(())
+}
+
+/// The UTF-8 string of `len` bytes at `ptr`.
+pub fn read_str(memory: &impl Memory, ptr: u32, len: usize) -> Result<&str, MarshalError> {
+    Ok(std::str::from_utf8(read_bytes(memory, ptr, len)?)?)
+}
+
+/// The NUL-terminated UTF-8 string at `ptr`, without its NUL.
+pub fn read_cstr(memory: &impl Memory, ptr: u32) -> Result<&str, MarshalError> {
+    let tail = memory
+        .data()
+        .get(ptr as usize..)
+        .ok_or(MarshalError::OutOfBounds { ptr, len: 0 })?;
+    let len = tail
+        .iter()
+        .position(|&b| b == 0)
+        .ok_or(MarshalError::Unterminated(ptr))?;
+    Ok(std::str::from_utf8(&tail[..len])?)
+}
+
+/// Write `s` at `ptr` followed by a NUL, returning how many bytes that took.
+pub fn write_cstr(memory: &mut impl Memory, ptr: u32, s: &str) -> Result<usize, MarshalError> {
+    if s.as_bytes().contains(&0) {
+        return Err(MarshalError::InteriorNul);
+    }
+    let dest = range_mut(memory, ptr, s.len() + 1)?;
+    dest[..s.len()].copy_from_slice(s.as_bytes());
+    dest[s.len()] = 0;
+    Ok(s.len() + 1)
+}
+
+/// The UTF-8 string at `ptr` preceded by its length in bytes, as a `u32`.
+pub fn read_prefixed_str(memory: &impl Memory, ptr: u32) -> Result<&str, MarshalError> {
+    let len = read::<u32>(memory, ptr)?;
+    let Some(start) = ptr.checked_add(4) else {
+        return Err(MarshalError::OutOfBounds {
+            ptr,
+            len: len as usize + 4,
+        });
+    };
+    read_str(memory, start, len as usize)
+}
+
+/// Write `s` at `ptr`, preceded by its length as a `u32`, returning how many bytes that took.
+pub fn write_prefixed_str(
+    memory: &mut impl Memory,
+    ptr: u32,
+    s: &str,
+) -> Result<usize, MarshalError> {
+    let len = u32::try_from(s.len()).map_err(|_| MarshalError::OutOfBounds {
+        ptr,
+        len: s.len() + 4,
+    })?;
+    let dest = range_mut(memory, ptr, s.len() + 4)?;
+    dest[..4].copy_from_slice(&len.to_le_bytes());
+    dest[4..].copy_from_slice(s.as_bytes());
+    Ok(s.len() + 4)
+}
+
+/// Allocation in guest memory through the guest's own exported allocator, by the usual
+/// `malloc(size: i32) -> i32` and `free(ptr: i32)` convention.
+///
+/// Calls run to completion on the execution between the host's own calls, so can't be made while
+/// it has live frames, and each replaces its last result.
+#[derive(Debug, Clone, Copy)]
+pub struct GuestAllocator {
+    malloc: u32,
+    free: Option<u32>,
+}
+
+impl GuestAllocator {
+    /// Use the instance's `malloc` and, if it has one, `free`.
+    pub fn find(instance: &Instance) -> Result<Self, MarshalError> {
+        Self::with_exports(instance, "malloc", Some("free"))
+    }
+
+    /// Use the allocator exported under other names, or one that can't free.
+    pub fn with_exports(
+        instance: &Instance,
+        malloc: &str,
+        free: Option<&str>,
+    ) -> Result<Self, MarshalError> {
+        let lookup = |name: &str| {
+            instance
+                .find_funcidx(name)
+                .ok_or_else(|| MarshalError::MissingExport(name.to_string()))
+        };
+        Ok(GuestAllocator {
+            malloc: lookup(malloc)?,
+            free: free.map(lookup).transpose()?,
+        })
+    }
+
+    /// Allocate `size` bytes, returning the guest pointer.
+    pub fn alloc<M: Memory, T: 'static, S: SlotStorage>(
+        &self,
+        execution: &mut Execution<M, T, S>,
+        size: u32,
+    ) -> Result<u32, MarshalError> {
+        match call(execution, self.malloc, &[Value::I32(size as i32)])? {
+            [Value::I32(0)] => Err(MarshalError::AllocationFailed(size)),
+            [Value::I32(ptr)] => Ok(*ptr as u32),
+            _ => Err(MarshalError::BadAllocatorResult),
+        }
+    }
+
+    /// Free `ptr`. Without a `free` export this does nothing.
+    pub fn free<M: Memory, T: 'static, S: SlotStorage>(
+        &self,
+        execution: &mut Execution<M, T, S>,
+        ptr: u32,
+    ) -> Result<(), MarshalError> {
+        if let Some(free) = self.free {
+            call(execution, free, &[Value::I32(ptr as i32)])?;
+        }
+        Ok(())
+    }
+
+    /// Copy `bytes` into a fresh allocation, returning its pointer.
+    pub fn alloc_bytes<M: Memory, T: 'static, S: SlotStorage>(
+        &self,
+        execution: &mut Execution<M, T, S>,
+        bytes: &[u8],
+    ) -> Result<u32, MarshalError> {
+        let len =
+            u32::try_from(bytes.len()).map_err(|_| MarshalError::AllocationFailed(u32::MAX))?;
+        let ptr = self.alloc(execution, len)?;
+        write_bytes(execution.memory_mut(), ptr, bytes)?;
+        Ok(ptr)
+    }
+
+    /// Copy `s` into a fresh allocation as a C string, returning its pointer.
+    pub fn alloc_cstr<M: Memory, T: 'static, S: SlotStorage>(
+        &self,
+        execution: &mut Execution<M, T, S>,
+        s: &str,
+    ) -> Result<u32, MarshalError> {
+        if s.as_bytes().contains(&0) {
+            return Err(MarshalError::InteriorNul);
+        }
+        let len =
+            u32::try_from(s.len() + 1).map_err(|_| MarshalError::AllocationFailed(u32::MAX))?;
+        let ptr = self.alloc(execution, len)?;
+        write_cstr(execution.memory_mut(), ptr, s)?;
+        Ok(ptr)
+    }
+}
+
+fn call<'a, M: Memory, T: 'static, S: SlotStorage>(
+    execution: &'a mut Execution<M, T, S>,
+    funcidx: u32,
+    args: &[Value],
+) -> Result<&'a [Value], MarshalError> {
+    if execution.frame_stack_len() != 0 {
+        return Err(MarshalError::Busy);
+    }
+    execution.prepare(funcidx, args)?;
+    execution.run()?;
+    Ok(execution.result().unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{mk_instance, Module, VectorMemory};
+
+    /// `struct { u8 tag; u32 value; u16 extra[2]; }`, with C's padding after `tag`.
+    #[derive(Debug, PartialEq)]
+    struct Record {
+        tag: u8,
+        value: u32,
+        extra: [u16; 2],
+    }
+
+    impl Pod for Record {
+        const SIZE: usize = 12;
+        fn read_from(bytes: &[u8]) -> Self {
+            Record {
+                tag: u8::read_from(&bytes[0..1]),
+                value: u32::read_from(&bytes[4..8]),
+                extra: <[u16; 2]>::read_from(&bytes[8..12]),
+            }
+        }
+        fn write_to(&self, bytes: &mut [u8]) {
+            self.tag.write_to(&mut bytes[0..1]);
+            self.value.write_to(&mut bytes[4..8]);
+            self.extra.write_to(&mut bytes[8..12]);
+        }
+    }
+
+    #[test]
+    fn strings_and_structs_round_trip() {
+        let mut memory = VectorMemory::new(64, None);
+        assert_eq!(write_cstr(&mut memory, 0, "hello").unwrap(), 6);
+        assert_eq!(read_cstr(&memory, 0).unwrap(), "hello");
+        assert_eq!(read_str(&memory, 1, 3).unwrap(), "ell");
+        assert!(matches!(
+            write_cstr(&mut memory, 0, "a\0b"),
+            Err(MarshalError::InteriorNul)
+        ));
+
+        assert_eq!(write_prefixed_str(&mut memory, 8, "wasm").unwrap(), 8);
+        assert_eq!(read::<u32>(&memory, 8).unwrap(), 4);
+        assert_eq!(read_prefixed_str(&memory, 8).unwrap(), "wasm");
+
+        let record = Record {
+            tag: 7,
+            value: 0xdead_beef,
+            extra: [1, 2],
+        };
+        write(&mut memory, 20, &record).unwrap();
+        assert_eq!(
+            read_bytes(&memory, 24, 4).unwrap(),
+            &[0xef, 0xbe, 0xad, 0xde]
+        );
+        assert_eq!(read::<Record>(&memory, 20).unwrap(), record);
+
+        // Nothing may run off the end.
+        assert!(matches!(
+            write(&mut memory, 60, &record),
+            Err(MarshalError::OutOfBounds { ptr: 60, len: 12 })
+        ));
+        assert!(matches!(
+            read_str(&memory, u32::MAX, 2),
+            Err(MarshalError::OutOfBounds { .. })
+        ));
+        memory.data_mut()[32..].fill(b'x');
+        assert!(matches!(
+            read_cstr(&memory, 32),
+            Err(MarshalError::Unterminated(32))
+        ));
+        memory.data_mut()[32] = 0xff;
+        memory.data_mut()[33] = 0;
+        assert!(matches!(
+            read_cstr(&memory, 32),
+            Err(MarshalError::InvalidUtf8(_))
+        ));
+    }
+
+    #[test]
+    fn guest_allocator() {
+        // A bump allocator which can't free, and a strlen to hand its allocations to.
+        let wat = r#"(module
+            (memory (export "memory") 1)
+            (global $next (mut i32) (i32.const 16))
+            (func (export "malloc") (param i32) (result i32)
+                (global.get $next)
+                (global.set $next (i32.add (global.get $next) (local.get 0))))
+            (func (export "strlen") (param i32) (result i32)
+                (local $n i32)
+                (block $done
+                    (loop $l
+                        (br_if $done (i32.eqz (i32.load8_u (i32.add (local.get 0) (local.get $n)))))
+                        (local.set $n (i32.add (local.get $n) (i32.const 1)))
+                        (br $l)))
+                (local.get $n)))"#;
+        let module = Module::load(&wat::parse_str(wat).unwrap()).unwrap();
+        let instance = mk_instance(module).unwrap();
+        assert!(matches!(
+            GuestAllocator::find(&instance),
+            Err(MarshalError::MissingExport(name)) if name == "free"
+        ));
+        let allocator = GuestAllocator::with_exports(&instance, "malloc", None).unwrap();
+        let strlen = instance.find_funcidx("strlen").unwrap();
+        let memory = instance.memories[0].clone();
+        let mut execution = Execution::new(instance, memory);
+
+        let greeting = allocator.alloc_cstr(&mut execution, "hi there").unwrap();
+        assert_eq!(greeting, 16);
+        let bytes = allocator.alloc_bytes(&mut execution, &[1, 2, 3]).unwrap();
+        assert_eq!(bytes, 25);
+        allocator.free(&mut execution, bytes).unwrap();
+
+        execution
+            .prepare(strlen, &[Value::I32(greeting as i32)])
+            .unwrap();
+        execution.run().unwrap();
+        assert_eq!(execution.result().unwrap(), &[Value::I32(8)]);
+        assert_eq!(read_cstr(execution.memory(), greeting).unwrap(), "hi there");
+    }
+}
@@ -0,0 +1,170 @@
+// Copyright (C) 2025 Ryan Daum <ryan.daum@gmail.com> This program is free
+// software: you can redistribute it and/or modify it under the terms of the GNU
+// General Public License as published by the Free Software Foundation, version
+// 3.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+use crate::exec::Fault;
+use crate::memory::WASM_PAGE_SIZE;
+use crate::{Memory, VectorMemory};
+
+/// Wraps a memory to remember which pages have been stored to, so a checkpoint only needs to
+/// save those. The host's writes count too: through `range_mut` just the pages written, and
+/// through `data_mut` every page, as there's no knowing which it wrote. Growth isn't tracked;
+/// compare `pages()` to see if the memory got bigger.
+///
+/// Two sets are kept: the dirty pages, which `clear_dirty_pages` empties, and every page written
+/// since the wrapper was made, which nothing does. The second is what's safe to reset from, as
+/// whoever clears the first may not be whoever has to undo the writes.
+pub struct DirtyTrackingMemory<M: Memory> {
+    inner: M,
+    /// One bit per page, grown as needed.
+    dirty: Vec<u64>,
+    /// As `dirty`, but never cleared.
+    written: Vec<u64>,
+}
+
+impl<M: Memory> DirtyTrackingMemory<M> {
+    pub fn new(inner: M) -> Self {
+        DirtyTrackingMemory {
+            inner,
+            dirty: vec![],
+            written: vec![],
+        }
+    }
+
+    pub fn is_dirty(&self, page: usize) -> bool {
+        self.dirty
+            .get(page / 64)
+            .is_some_and(|word| word & (1 << (page % 64)) != 0)
+    }
+
+    fn mark(&mut self, offset: usize, len: usize) {
+        if len == 0 {
+            return;
+        }
+        let first = offset / WASM_PAGE_SIZE;
+        let last = (offset + len - 1) / WASM_PAGE_SIZE;
+        for page in first..=last {
+            for bits in [&mut self.dirty, &mut self.written] {
+                if bits.len() <= page / 64 {
+                    bits.resize(page / 64 + 1, 0);
+                }
+                bits[page / 64] |= 1 << (page % 64);
+            }
+        }
+    }
+
+    /// Every page written since this wrapper was made, whether or not the dirty pages have been
+    /// cleared since.
+    pub fn pages_written(&self) -> Vec<usize> {
+        set_pages(&self.written)
+    }
+
+    pub fn inner(&self) -> &M {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> M {
+        self.inner
+    }
+}
+
+/// The pages whose bits are set in `bits`.
+fn set_pages(bits: &[u64]) -> Vec<usize> {
+    let pages = bits.iter().enumerate().flat_map(|(i, word)| {
+        (0..64)
+            .filter(move |bit| word & (1 << bit) != 0)
+            .map(move |bit| i * 64 + bit)
+    });
+    pages.collect()
+}
+
+impl<M: Memory> Memory for DirtyTrackingMemory<M> {
+    fn data(&self) -> &[u8] {
+        self.inner.data()
+    }
+
+    fn data_mut(&mut self) -> &mut [u8] {
+        self.mark(0, self.inner.size());
+        self.inner.data_mut()
+    }
+
+    fn range_mut(&mut self, offset: usize, len: usize) -> Result<&mut [u8], Fault> {
+        match offset.checked_add(len) {
+            Some(end) if end <= self.inner.size() => self.mark(offset, len),
+            _ => return Err(Fault::MemoryOutOfBounds),
+        }
+        self.inner.range_mut(offset, len)
+    }
+
+    fn size(&self) -> usize {
+        self.inner.size()
+    }
+
+    fn grow(&mut self, new_size: usize) -> Result<usize, Fault> {
+        self.inner.grow(new_size)
+    }
+
+    fn max_pages(&self) -> Option<usize> {
+        self.inner.max_pages()
+    }
+
+    fn dirty_pages(&self) -> Option<Vec<usize>> {
+        Some(set_pages(&self.dirty))
+    }
+
+    fn clear_dirty_pages(&mut self) {
+        self.dirty.clear();
+    }
+
+    fn set_u8(&mut self, offset: usize, value: u8) -> Result<(), Fault> {
+        self.inner.set_u8(offset, value)?;
+        self.mark(offset, 1);
+        Ok(())
+    }
+    fn set_u16(&mut self, offset: usize, value: u16) -> Result<(), Fault> {
+        self.inner.set_u16(offset, value)?;
+        self.mark(offset, 2);
+        Ok(())
+    }
+    fn set_i32(&mut self, offset: usize, value: i32) -> Result<(), Fault> {
+        self.inner.set_i32(offset, value)?;
+        self.mark(offset, 4);
+        Ok(())
+    }
+    fn set_i64(&mut self, offset: usize, value: i64) -> Result<(), Fault> {
+        self.inner.set_i64(offset, value)?;
+        self.mark(offset, 8);
+        Ok(())
+    }
+    fn set_u32(&mut self, offset: usize, value: u32) -> Result<(), Fault> {
+        self.inner.set_u32(offset, value)?;
+        self.mark(offset, 4);
+        Ok(())
+    }
+    fn set_u64(&mut self, offset: usize, value: u64) -> Result<(), Fault> {
+        self.inner.set_u64(offset, value)?;
+        self.mark(offset, 8);
+        Ok(())
+    }
+    fn set_f32(&mut self, offset: usize, value: f32) -> Result<(), Fault> {
+        self.set_u32(offset, value.to_bits())
+    }
+    fn set_f64(&mut self, offset: usize, value: f64) -> Result<(), Fault> {
+        self.set_u64(offset, value.to_bits())
+    }
+}
+
+impl From<DirtyTrackingMemory<VectorMemory>> for VectorMemory {
+    fn from(memory: DirtyTrackingMemory<VectorMemory>) -> Self {
+        memory.inner
+    }
+}
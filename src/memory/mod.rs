@@ -13,20 +13,70 @@
 
 use crate::exec::Fault;
 
+pub use dirty_mem::DirtyTrackingMemory;
+pub use protected_mem::{PageOwner, ProtectedMemory};
 pub use slice_mem::SliceMemory;
 pub use vector_mem::VectorMemory;
 
+mod dirty_mem;
+mod protected_mem;
 mod slice_mem;
 mod vector_mem;
 
 // TODO: MmapMemory, both file and anonymous
 
+/// The size of a WebAssembly page, the unit memories are sized and grown in.
+pub const WASM_PAGE_SIZE: usize = 1 << 16;
+
+/// The most pages a 32-bit memory can have, which covers its whole address space.
+pub const MAX_WASM_PAGES: usize = 1 << 16;
+
+/// How many pages it takes to hold `bytes`, counting a partly used last page.
+pub const fn pages_for_bytes(bytes: usize) -> usize {
+    bytes.div_ceil(WASM_PAGE_SIZE)
+}
+
+/// How many bytes `pages` pages span, saturating at `usize::MAX`, which no memory can grow to.
+pub const fn bytes_for_pages(pages: usize) -> usize {
+    pages.saturating_mul(WASM_PAGE_SIZE)
+}
+
 pub trait Memory {
     fn data(&self) -> &[u8];
+    /// All of memory, to write to. Backends which track writes have to assume every byte of it
+    /// is, so prefer `range_mut` for writing part of it.
     fn data_mut(&mut self) -> &mut [u8];
+    /// The `len` bytes at `offset`, to write to. Only those count as written.
+    fn range_mut(&mut self, offset: usize, len: usize) -> Result<&mut [u8], Fault> {
+        let end = offset.checked_add(len).ok_or(Fault::MemoryOutOfBounds)?;
+        self.data_mut()
+            .get_mut(offset..end)
+            .ok_or(Fault::MemoryOutOfBounds)
+    }
 
     fn size(&self) -> usize;
+    /// Grow to `new_size` bytes, returning the new size. Bytes up to the old size keep their
+    /// contents, and the new ones read as zero. Asking for less than the current size, or for more
+    /// than `max_pages` allows, fails with `Fault::CannotGrowMemory` and leaves memory as it was,
+    /// as does any growth at all for a backend which can't grow. Asking for the current size
+    /// always succeeds. `memory_backend_tests!` checks an implementation holds to all this.
     fn grow(&mut self, _new_size: usize) -> Result<usize, Fault>;
+
+    /// The size in WASM pages. Only whole pages count, for backends whose size isn't a multiple.
+    fn pages(&self) -> usize {
+        self.size() / WASM_PAGE_SIZE
+    }
+    /// The most pages this memory can grow to, if it's bounded.
+    fn max_pages(&self) -> Option<usize> {
+        None
+    }
+    /// The pages stored to since tracking began or was last cleared, in order, or `None` for
+    /// memories that don't track them.
+    fn dirty_pages(&self) -> Option<Vec<usize>> {
+        None
+    }
+    fn clear_dirty_pages(&mut self) {}
+
     fn get_u8(&self, offset: usize) -> Result<u8, Fault> {
         if offset >= self.size() {
             return Err(Fault::MemoryOutOfBounds);
@@ -158,11 +208,101 @@ pub trait Memory {
     }
 }
 
+/// Generate a module `$name` of tests checking a `Memory` implementation grows as the trait says
+/// it must. `$make` is called with a minimum and maximum size in bytes, and returns a zeroed
+/// memory of the minimum size which can grow no further than the maximum, if that's bounded.
+/// Backends which can't grow are held to refusing cleanly.
+#[macro_export]
+macro_rules! memory_backend_tests {
+    ($name:ident, $make:expr) => {
+        mod $name {
+            // `$make` is written in terms of whatever the invoking module has in scope.
+            #[allow(unused_imports)]
+            use super::*;
+            use $crate::{Fault, Memory};
+
+            const PAGE: usize = $crate::WASM_PAGE_SIZE;
+
+            fn make(min: usize, max: Option<usize>) -> impl Memory {
+                ($make)(min, max)
+            }
+
+            /// A memory of one page with its first and last bytes set.
+            fn marked(max: Option<usize>) -> impl Memory {
+                let mut memory = make(PAGE, max);
+                assert_eq!(memory.size(), PAGE);
+                assert!(memory.data().iter().all(|b| *b == 0));
+                memory.set_u8(0, 0xaa).unwrap();
+                memory.set_u8(PAGE - 1, 0xbb).unwrap();
+                memory
+            }
+
+            fn unchanged(memory: &impl Memory) {
+                assert_eq!(memory.size(), PAGE);
+                assert_eq!(memory.get_u8(0).unwrap(), 0xaa);
+                assert_eq!(memory.get_u8(PAGE - 1).unwrap(), 0xbb);
+            }
+
+            #[test]
+            fn grow_preserves_and_zero_fills() {
+                let mut memory = marked(Some(3 * PAGE));
+                match memory.grow(2 * PAGE) {
+                    Ok(size) => {
+                        assert_eq!(size, 2 * PAGE);
+                        assert_eq!(memory.size(), 2 * PAGE);
+                        assert_eq!(memory.get_u8(0).unwrap(), 0xaa);
+                        assert_eq!(memory.get_u8(PAGE - 1).unwrap(), 0xbb);
+                        assert!(memory.data()[PAGE..].iter().all(|b| *b == 0));
+                        memory.set_u8(2 * PAGE - 1, 1).unwrap();
+                    }
+                    Err(fault) => {
+                        assert!(matches!(fault, Fault::CannotGrowMemory));
+                        unchanged(&memory);
+                    }
+                }
+            }
+
+            #[test]
+            fn grow_respects_maximum() {
+                let mut memory = marked(Some(2 * PAGE));
+                if let Some(max) = memory.max_pages() {
+                    assert!(max <= 2);
+                }
+                assert!(matches!(
+                    memory.grow(3 * PAGE),
+                    Err(Fault::CannotGrowMemory)
+                ));
+                unchanged(&memory);
+            }
+
+            #[test]
+            fn grow_never_shrinks() {
+                let mut memory = marked(None);
+                assert_eq!(memory.grow(PAGE).unwrap(), PAGE);
+                unchanged(&memory);
+                assert!(matches!(memory.grow(0), Err(Fault::CannotGrowMemory)));
+                unchanged(&memory);
+            }
+        }
+    };
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::memory::VectorMemory;
 
+    crate::memory_backend_tests!(vector_backend, VectorMemory::new);
+    crate::memory_backend_tests!(slice_backend, |min: usize, _| {
+        SliceMemory::new(Box::leak(vec![0; min].into_boxed_slice()))
+    });
+    crate::memory_backend_tests!(dirty_tracking_backend, |min, max| {
+        DirtyTrackingMemory::new(VectorMemory::new(min, max))
+    });
+    crate::memory_backend_tests!(protected_backend, |min, max| {
+        ProtectedMemory::new(VectorMemory::new(min, max))
+    });
+
     #[test]
     fn test_vector_memory_creation() {
         let memory = VectorMemory::new(1024, Some(2048));
@@ -175,6 +315,18 @@ mod tests {
         }
     }
 
+    #[test]
+    fn page_math() {
+        assert_eq!(pages_for_bytes(0), 0);
+        assert_eq!(pages_for_bytes(1), 1);
+        assert_eq!(pages_for_bytes(WASM_PAGE_SIZE), 1);
+        assert_eq!(pages_for_bytes(WASM_PAGE_SIZE + 1), 2);
+        assert_eq!(bytes_for_pages(3), 3 * WASM_PAGE_SIZE);
+        assert_eq!(bytes_for_pages(usize::MAX), usize::MAX);
+        // Only whole pages count towards a memory's size in pages.
+        assert_eq!(VectorMemory::new(WASM_PAGE_SIZE + 1, None).pages(), 1);
+    }
+
     #[test]
     fn test_vector_memory_wasm_page_size() {
         const WASM_PAGE_SIZE: usize = 1 << 16; // 64KB
@@ -364,4 +516,93 @@ mod tests {
         assert_eq!(cloned.size(), WASM_PAGE_SIZE);
         assert_eq!(cloned.get_i32(1).unwrap(), 1);
     }
+
+    #[test]
+    fn test_protected_ranges_reject_stores() {
+        let mut memory = ProtectedMemory::new(VectorMemory::new(64, None));
+        memory.protect(16..32);
+
+        // Stores that overlap the range by even one byte trap, and don't write anything.
+        assert!(matches!(memory.set_i32(14, -1), Err(Fault::ReadOnlyMemory)));
+        assert!(matches!(memory.set_u8(31, 1), Err(Fault::ReadOnlyMemory)));
+        assert_eq!(memory.get_u32(12).unwrap(), 0);
+        assert_eq!(memory.get_u32(16).unwrap(), 0);
+
+        // Right up against either end is fine.
+        memory.set_i32(12, 7).unwrap();
+        memory.set_i64(32, 8).unwrap();
+        assert_eq!(memory.get_i32(12).unwrap(), 7);
+
+        // The host can still write there directly, and the guest can read it.
+        memory.data_mut()[16] = 0xaa;
+        assert_eq!(memory.get_u8(16).unwrap(), 0xaa);
+
+        assert!(memory.unprotect(16..32));
+        assert!(!memory.unprotect(16..32));
+        memory.set_u8(16, 1).unwrap();
+    }
+
+    #[test]
+    fn test_page_ownership_toggles_between_calls() {
+        let page = WASM_PAGE_SIZE;
+        let inner = DirtyTrackingMemory::new(VectorMemory::new(4 * page, None));
+        let mut memory = ProtectedMemory::new(inner);
+        memory.set_page_owner(1..3, PageOwner::Host);
+        assert_eq!(memory.page_owner(0), PageOwner::Guest);
+        assert_eq!(memory.page_owner(2), PageOwner::Host);
+        assert!(matches!(memory.set_u8(page, 1), Err(Fault::ReadOnlyMemory)));
+        assert!(matches!(
+            memory.set_i32(page - 2, 1),
+            Err(Fault::ReadOnlyMemory)
+        ));
+        memory.clear_dirty_pages();
+
+        // Handing back the middle of a host range leaves the rest of it with the host.
+        memory.set_page_owner(2..3, PageOwner::Guest);
+        assert_eq!(memory.page_owner(1), PageOwner::Host);
+        memory.set_u32(2 * page + 8, 0xfeed).unwrap();
+        assert_eq!(memory.dirty_pages(), Some(vec![2]));
+        assert_eq!(memory.protected_ranges().len(), 1);
+        assert_eq!(memory.protected_ranges()[0], page..2 * page);
+
+        memory.set_page_owner(0..4, PageOwner::Guest);
+        assert!(memory.protected_ranges().is_empty());
+        memory.set_u8(page, 1).unwrap();
+    }
+
+    #[test]
+    fn test_dirty_pages_track_guest_stores() {
+        use crate::exec::Execution;
+        use crate::{mk_instance, Module, Value};
+
+        let wat = r#"(module
+            (memory 1 4)
+            (func (export "store") (param i32) (i32.store (local.get 0) (i32.const -1)))
+            (func (export "grow") (drop (memory.grow (i32.const 2)))))"#;
+        let instance = mk_instance(Module::load(&wat::parse_str(wat).unwrap()).unwrap()).unwrap();
+        let memory = DirtyTrackingMemory::new(instance.memories[0].clone());
+        assert_eq!((memory.pages(), memory.max_pages()), (1, Some(4)));
+        let mut execution = Execution::new(instance, memory);
+        let mut call = |name: &str, args: &[Value]| {
+            let funcidx = execution.instance().find_funcidx(name).unwrap();
+            execution.prepare(funcidx, args).unwrap();
+            execution.run().unwrap();
+        };
+        call("grow", &[]);
+        // Straddles the first two pages.
+        call("store", &[Value::I32(WASM_PAGE_SIZE as i32 - 2)]);
+        call("store", &[Value::I32(2 * WASM_PAGE_SIZE as i32 + 8)]);
+
+        assert_eq!(execution.memory().pages(), 3);
+        assert!(execution.memory().is_dirty(2));
+        assert_eq!(execution.take_dirty_pages(), Some(vec![0, 1, 2]));
+        assert_eq!(execution.take_dirty_pages(), Some(vec![]));
+
+        // The host's writes count too, and through `data_mut` there's no telling where they went.
+        execution.memory_mut().range_mut(WASM_PAGE_SIZE, 1).unwrap()[0] = 1;
+        assert_eq!(execution.take_dirty_pages(), Some(vec![1]));
+        execution.memory_mut().data_mut()[0] = 1;
+        assert_eq!(execution.take_dirty_pages(), Some(vec![0, 1, 2]));
+        assert_eq!(VectorMemory::new(0, None).dirty_pages(), None);
+    }
 }
@@ -0,0 +1,187 @@
+// Copyright (C) 2025 Ryan Daum <ryan.daum@gmail.com> This program is free
+// software: you can redistribute it and/or modify it under the terms of the GNU
+// General Public License as published by the Free Software Foundation, version
+// 3.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+use crate::exec::Fault;
+use crate::{Memory, VectorMemory, WASM_PAGE_SIZE};
+use std::ops::Range;
+
+/// Which side may write a page of a `ProtectedMemory`. Handing pages back and forth between
+/// calls lets the host share a buffer in guest memory without copying it, and without the guest
+/// scribbling on it while the host is reading.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageOwner {
+    /// The guest can read the pages, but its stores to them trap.
+    Host,
+    /// The guest can read and write the pages as normal.
+    Guest,
+}
+
+/// Wraps another memory and makes some ranges of it read-only to the guest: a store touching any
+/// byte in a protected range traps with `Fault::ReadOnlyMemory`, and leaves memory untouched.
+/// Loads are unaffected, and the host can still write anywhere through `data_mut`.
+pub struct ProtectedMemory<M: Memory> {
+    inner: M,
+    read_only: Vec<Range<usize>>,
+}
+
+impl<M: Memory> ProtectedMemory<M> {
+    pub fn new(inner: M) -> Self {
+        ProtectedMemory {
+            inner,
+            read_only: vec![],
+        }
+    }
+
+    /// Make `range` read-only to the guest.
+    pub fn protect(&mut self, range: Range<usize>) {
+        if !range.is_empty() {
+            self.read_only.push(range);
+        }
+    }
+
+    /// Remove the protection on exactly `range`, as previously passed to `protect`. Returns false
+    /// if there was no such range.
+    pub fn unprotect(&mut self, range: Range<usize>) -> bool {
+        let before = self.read_only.len();
+        self.read_only.retain(|r| *r != range);
+        self.read_only.len() != before
+    }
+
+    /// Hand the wasm pages in `pages` to `owner`, whatever protection covered them before.
+    pub fn set_page_owner(&mut self, pages: Range<usize>, owner: PageOwner) {
+        let bytes =
+            pages.start.saturating_mul(WASM_PAGE_SIZE)..pages.end.saturating_mul(WASM_PAGE_SIZE);
+        self.release(&bytes);
+        if owner == PageOwner::Host {
+            self.protect(bytes);
+        }
+    }
+
+    /// Who can write the wasm page `page`. A page only partly protected is the host's.
+    pub fn page_owner(&self, page: usize) -> PageOwner {
+        match self.is_writable(page.saturating_mul(WASM_PAGE_SIZE), WASM_PAGE_SIZE) {
+            true => PageOwner::Guest,
+            false => PageOwner::Host,
+        }
+    }
+
+    /// Cut `bytes` out of every protected range, splitting those it falls in the middle of.
+    fn release(&mut self, bytes: &Range<usize>) {
+        let mut kept = Vec::with_capacity(self.read_only.len() + 1);
+        for r in self.read_only.drain(..) {
+            let before = r.start..r.end.min(bytes.start);
+            let after = r.start.max(bytes.end)..r.end;
+            kept.extend([before, after].into_iter().filter(|part| !part.is_empty()));
+        }
+        self.read_only = kept;
+    }
+
+    pub fn protected_ranges(&self) -> &[Range<usize>] {
+        &self.read_only
+    }
+
+    pub fn is_writable(&self, offset: usize, len: usize) -> bool {
+        let end = offset.saturating_add(len);
+        !self
+            .read_only
+            .iter()
+            .any(|r| offset < r.end && r.start < end)
+    }
+
+    fn check_write(&self, offset: usize, len: usize) -> Result<(), Fault> {
+        if self.is_writable(offset, len) {
+            Ok(())
+        } else {
+            Err(Fault::ReadOnlyMemory)
+        }
+    }
+
+    pub fn inner(&self) -> &M {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> M {
+        self.inner
+    }
+}
+
+impl<M: Memory> Memory for ProtectedMemory<M> {
+    fn data(&self) -> &[u8] {
+        self.inner.data()
+    }
+
+    fn data_mut(&mut self) -> &mut [u8] {
+        self.inner.data_mut()
+    }
+
+    fn range_mut(&mut self, offset: usize, len: usize) -> Result<&mut [u8], Fault> {
+        self.inner.range_mut(offset, len)
+    }
+
+    fn size(&self) -> usize {
+        self.inner.size()
+    }
+
+    fn grow(&mut self, new_size: usize) -> Result<usize, Fault> {
+        self.inner.grow(new_size)
+    }
+
+    fn max_pages(&self) -> Option<usize> {
+        self.inner.max_pages()
+    }
+
+    fn dirty_pages(&self) -> Option<Vec<usize>> {
+        self.inner.dirty_pages()
+    }
+
+    fn clear_dirty_pages(&mut self) {
+        self.inner.clear_dirty_pages()
+    }
+
+    fn set_u8(&mut self, offset: usize, value: u8) -> Result<(), Fault> {
+        self.check_write(offset, 1)?;
+        self.inner.set_u8(offset, value)
+    }
+    fn set_u16(&mut self, offset: usize, value: u16) -> Result<(), Fault> {
+        self.check_write(offset, 2)?;
+        self.inner.set_u16(offset, value)
+    }
+    fn set_i32(&mut self, offset: usize, value: i32) -> Result<(), Fault> {
+        self.check_write(offset, 4)?;
+        self.inner.set_i32(offset, value)
+    }
+    fn set_i64(&mut self, offset: usize, value: i64) -> Result<(), Fault> {
+        self.check_write(offset, 8)?;
+        self.inner.set_i64(offset, value)
+    }
+    fn set_u32(&mut self, offset: usize, value: u32) -> Result<(), Fault> {
+        self.check_write(offset, 4)?;
+        self.inner.set_u32(offset, value)
+    }
+    fn set_u64(&mut self, offset: usize, value: u64) -> Result<(), Fault> {
+        self.check_write(offset, 8)?;
+        self.inner.set_u64(offset, value)
+    }
+    fn set_f32(&mut self, offset: usize, value: f32) -> Result<(), Fault> {
+        self.set_u32(offset, value.to_bits())
+    }
+    fn set_f64(&mut self, offset: usize, value: f64) -> Result<(), Fault> {
+        self.set_u64(offset, value.to_bits())
+    }
+}
+
+impl From<ProtectedMemory<VectorMemory>> for VectorMemory {
+    fn from(memory: ProtectedMemory<VectorMemory>) -> Self {
+        memory.inner
+    }
+}
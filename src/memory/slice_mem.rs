@@ -41,7 +41,15 @@ impl Memory for SliceMemory<'_> {
     fn size(&self) -> usize {
         self.data.len()
     }
-    fn grow(&mut self, _new_size: usize) -> Result<usize, Fault> {
+    fn grow(&mut self, new_size: usize) -> Result<usize, Fault> {
+        // The slice is all there is, but growing by nothing is always allowed.
+        if new_size == self.data.len() {
+            return Ok(new_size);
+        }
         Err(Fault::CannotGrowMemory)
     }
+
+    fn max_pages(&self) -> Option<usize> {
+        Some(self.pages())
+    }
 }
@@ -12,6 +12,7 @@
 //
 
 use crate::exec::Fault;
+use crate::memory::WASM_PAGE_SIZE;
 use crate::Memory;
 
 /// Growable memory backed by a vector.
@@ -44,6 +45,11 @@ impl VectorMemory {
     pub fn data_mut(&mut self) -> &mut Vec<u8> {
         &mut self.data
     }
+
+    /// The most this memory can grow to, in bytes.
+    pub fn max_bounds(&self) -> Option<usize> {
+        self.max_bounds
+    }
 }
 
 impl Memory for VectorMemory {
@@ -59,6 +65,9 @@ impl Memory for VectorMemory {
         self.data.len()
     }
     fn grow(&mut self, new_size: usize) -> Result<usize, Fault> {
+        if new_size < self.data.len() {
+            return Err(Fault::CannotGrowMemory);
+        }
         if let Some(max) = self.max_bounds {
             if new_size > max {
                 return Err(Fault::CannotGrowMemory);
@@ -73,4 +82,8 @@ impl Memory for VectorMemory {
         self.data.resize(new_size, 0);
         Ok(self.data.len())
     }
+
+    fn max_pages(&self) -> Option<usize> {
+        self.max_bounds.map(|max| max / WASM_PAGE_SIZE)
+    }
 }
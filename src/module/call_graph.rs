@@ -0,0 +1,211 @@
+// Copyright (C) 2025 Ryan Daum <ryan.daum@gmail.com> This program is free
+// software: you can redistribute it and/or modify it under the terms of the GNU
+// General Public License as published by the Free Software Foundation, version
+// 3.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+//! Which functions call which, worked out from their decoded bodies without running anything.
+
+use crate::decode::{decode, decode_function, Program};
+use crate::module::{Elements, ImportExportKind, Region};
+use crate::op::Op;
+use crate::visit::OpVisitor;
+use crate::Module;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// The calls between a module's functions, indexed by function index, imports first.
+///
+/// Only calls made by the module's own code are seen. An imported function calling back into
+/// the module through an export, or the host filling a table, isn't.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CallGraph {
+    /// The functions each function calls directly. Imports have no calls of their own.
+    pub calls: Vec<BTreeSet<u32>>,
+    /// The signatures each function calls through a table, as canonical type IDs; see
+    /// `Module::type_ids`.
+    pub indirect_calls: Vec<BTreeSet<u32>>,
+    /// By canonical type ID, the functions an indirect call could land on: those put in tables
+    /// by element segments, taken by `ref.func`, or exported.
+    pub indirect_targets: BTreeMap<u32, BTreeSet<u32>>,
+    /// Defined functions, by function index, whose bodies couldn't be decoded. They have no
+    /// edges here, so `reachable_from` assumes they can call anything.
+    pub undecodable: Vec<u32>,
+}
+
+impl CallGraph {
+    /// The functions which call `funcidx` directly.
+    pub fn callers(&self, funcidx: u32) -> Vec<u32> {
+        (0..self.calls.len() as u32)
+            .filter(|caller| self.calls[*caller as usize].contains(&funcidx))
+            .collect()
+    }
+
+    /// Every function which calling `roots` could end up running, the roots included. An
+    /// indirect call reaches every function of its signature which could be in a table.
+    pub fn reachable_from(&self, roots: impl IntoIterator<Item = u32>) -> BTreeSet<u32> {
+        let mut reached = BTreeSet::new();
+        let mut pending: Vec<u32> = roots.into_iter().collect();
+        while let Some(funcidx) = pending.pop() {
+            if !reached.insert(funcidx) {
+                continue;
+            }
+            if self.undecodable.contains(&funcidx) {
+                return (0..self.calls.len() as u32).collect();
+            }
+            let Some(calls) = self.calls.get(funcidx as usize) else {
+                continue;
+            };
+            pending.extend(calls);
+            for type_id in &self.indirect_calls[funcidx as usize] {
+                pending.extend(self.indirect_targets.get(type_id).into_iter().flatten());
+            }
+        }
+        reached
+    }
+}
+
+/// The calls, and the functions taken by `ref.func`, in one body or expression.
+#[derive(Default)]
+struct CallSites {
+    calls: BTreeSet<u32>,
+    indirect: BTreeSet<u32>,
+    taken: BTreeSet<u32>,
+}
+
+impl OpVisitor for CallSites {
+    fn call(&mut self, _pc: usize, op: &Op) {
+        match op {
+            Op::Call(funcidx) => {
+                self.calls.insert(*funcidx);
+            }
+            Op::CallIndirect(typeidx, _) => {
+                self.indirect.insert(*typeidx);
+            }
+            _ => {}
+        }
+    }
+
+    fn reference(&mut self, _pc: usize, op: &Op) {
+        if let Op::RefFunc(funcidx) = op {
+            self.taken.insert(*funcidx);
+        }
+    }
+}
+
+impl Module {
+    /// Decode every function body and record the calls between them.
+    pub fn call_graph(&self) -> CallGraph {
+        let num_imported_funcs = self.num_imported_funcs();
+        let num_funcs = num_imported_funcs + self.functions.len();
+        let mut graph = CallGraph {
+            calls: vec![BTreeSet::new(); num_funcs],
+            indirect_calls: vec![BTreeSet::new(); num_funcs],
+            ..CallGraph::default()
+        };
+        let type_id = |typeidx: u32| self.type_ids.get(typeidx as usize).copied();
+
+        let mut taken = BTreeSet::new();
+        for (i, typeidx) in self.functions.iter().enumerate() {
+            let funcidx = num_imported_funcs + i;
+            let decoded = match (self.body(i), self.types.get(*typeidx)) {
+                (Some(body), Some(func_type)) => decode_function(body, &self.types, func_type).ok(),
+                _ => None,
+            };
+            let Some(program) = decoded else {
+                graph.undecodable.push(funcidx as u32);
+                continue;
+            };
+            let sites = visit_calls(&program);
+            graph.calls[funcidx] = sites.calls;
+            graph.indirect_calls[funcidx] =
+                sites.indirect.into_iter().filter_map(type_id).collect();
+            taken.extend(sites.taken);
+        }
+
+        // Functions in element segments, whether listed or given as expressions, and any taken
+        // by the initializer of a global.
+        let mut exprs: Vec<&Region> = self.globals.iter().map(|g| &g.expr).collect();
+        for segment in &self.element_segments {
+            match &segment.elements {
+                Elements::Function(funcs) => taken.extend(funcs),
+                Elements::Expression(regions) => exprs.extend(regions),
+            }
+        }
+        for expr in exprs {
+            if let Ok(program) = self.get_expr(expr).and_then(decode) {
+                taken.extend(visit_calls(&program).taken);
+            }
+        }
+        taken.extend(
+            self.exports
+                .iter()
+                .filter(|export| export.kind == ImportExportKind::Function)
+                .map(|export| export.index),
+        );
+
+        for funcidx in taken {
+            let Some(type_id) = self
+                .func_typeidx_of(funcidx)
+                .and_then(|typeidx| type_id(typeidx as u32))
+            else {
+                continue;
+            };
+            graph
+                .indirect_targets
+                .entry(type_id)
+                .or_default()
+                .insert(funcidx);
+        }
+        graph
+    }
+}
+
+fn visit_calls(program: &Program) -> CallSites {
+    let mut sites = CallSites::default();
+    program.visit(&mut sites);
+    sites
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Module;
+    use std::collections::BTreeSet;
+
+    #[test]
+    fn direct_and_indirect_calls() {
+        let wat = r#"(module
+            (type $t (func (result i32)))
+            (import "env" "log" (func $log (param i32)))
+            (table 2 funcref)
+            (elem (i32.const 0) $one)
+            (func $main (export "main") (result i32)
+                (call $log (i32.const 0))
+                (call $dispatch))
+            (func $dispatch (result i32) (call_indirect (type $t) (i32.const 0)))
+            (func $one (result i32) (i32.const 1))
+            (func $admin (export "admin_reset") (param i32) (call $log (local.get 0)))
+            (func $unused (result i32) (i32.const 2)))"#;
+        let module = Module::load(&wat::parse_str(wat).unwrap()).unwrap();
+        let graph = module.call_graph();
+
+        assert_eq!(graph.calls.len(), 6);
+        assert!(graph.calls[0].is_empty());
+        assert_eq!(graph.calls[1], BTreeSet::from([0, 2]));
+        assert_eq!(graph.indirect_calls[2], BTreeSet::from([0]));
+        assert_eq!(graph.callers(0), [1, 4]);
+        // `main` is exported with the same signature, so it could be in the table as well.
+        assert_eq!(graph.indirect_targets[&0], BTreeSet::from([1, 3]));
+
+        let reachable = graph.reachable_from([1]);
+        assert_eq!(reachable, BTreeSet::from([0, 1, 2, 3]));
+        assert!(!reachable.contains(&module.export("admin_reset").unwrap().index));
+        assert!(graph.undecodable.is_empty());
+    }
+}
@@ -0,0 +1,79 @@
+// Copyright (C) 2025 Ryan Daum <ryan.daum@gmail.com> This program is free
+// software: you can redistribute it and/or modify it under the terms of the GNU
+// General Public License as published by the Free Software Foundation, version
+// 3.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+/// Choices about what `Module::load_with` accepts, where the spec leaves room or an embedder wants
+/// tighter bounds than it does. `Default` is what `Module::load` has always done.
+#[derive(Debug, Clone)]
+pub struct LoadConfig {
+    /// Accept custom sections with names we don't recognize. The spec has them ignored, but a host
+    /// vetting what it's given may want to refuse anything it can't account for.
+    pub allow_unknown_custom: bool,
+    /// The most entries any one section may declare: types, imports, functions, tables, memories,
+    /// globals, exports, element or data segments, or function bodies.
+    pub max_section_entries: Option<u32>,
+    /// Refuse standard sections out of the spec's order, or repeated. Custom sections may go
+    /// anywhere.
+    pub strict_section_order: bool,
+    /// The largest binary accepted, in bytes.
+    pub max_module_size: Option<usize>,
+    /// The largest function body accepted, in bytes, locals included.
+    pub max_function_size: Option<u32>,
+    /// The most ops any function body may decode to. Setting this has `load_with` decode every
+    /// body to count them, stopping at the limit, rather than leaving that to instantiation.
+    pub max_function_ops: Option<u32>,
+}
+
+impl Default for LoadConfig {
+    fn default() -> Self {
+        LoadConfig {
+            allow_unknown_custom: true,
+            max_section_entries: None,
+            strict_section_order: false,
+            max_module_size: None,
+            max_function_size: None,
+            max_function_ops: None,
+        }
+    }
+}
+
+impl LoadConfig {
+    /// Everything the spec forbids refused, for running its test suite.
+    pub fn strict() -> Self {
+        LoadConfig {
+            strict_section_order: true,
+            ..LoadConfig::default()
+        }
+    }
+}
+
+/// Custom sections in common use by toolchains, accepted even when unknown ones aren't.
+const KNOWN_CUSTOM_SECTIONS: &[&str] = &[
+    "name",
+    "producers",
+    "target_features",
+    "sourceMappingURL",
+    "external_debug_info",
+    "build_id",
+    "linking",
+    "dylink.0",
+];
+
+/// Prefixes of families of custom sections, such as relocations and DWARF.
+const KNOWN_CUSTOM_PREFIXES: &[&str] = &["reloc.", ".debug_"];
+
+pub(crate) fn is_known_custom_section(name: &str) -> bool {
+    KNOWN_CUSTOM_SECTIONS.contains(&name)
+        || KNOWN_CUSTOM_PREFIXES
+            .iter()
+            .any(|prefix| name.starts_with(prefix))
+}
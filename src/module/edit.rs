@@ -0,0 +1,166 @@
+// Copyright (C) 2025 Ryan Daum <ryan.daum@gmail.com> This program is free
+// software: you can redistribute it and/or modify it under the terms of the GNU
+// General Public License as published by the Free Software Foundation, version
+// 3.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+use crate::module::{ExportEntry, ImportExportKind, LEB128Reader, SECTION_ID_CUSTOM};
+use crate::Module;
+
+/// Edits to a loaded module's interface, made before instantiating it or re-encoding it with
+/// [`Module::encode`]. Only the parsed structures change; `module_data` stays as loaded.
+impl Module {
+    /// Export the item of `kind` at `index` as `name`. Returns false, changing nothing, if
+    /// something is already exported under that name.
+    pub fn add_export(&mut self, name: &str, kind: ImportExportKind, index: u32) -> bool {
+        if self.export(name).is_some() {
+            return false;
+        }
+        self.export_index
+            .insert(name.to_string(), self.exports.len());
+        self.exports.push(ExportEntry {
+            name: name.to_string(),
+            kind,
+            index,
+        });
+        true
+    }
+
+    /// Stop exporting `name`. Returns whether it was exported.
+    pub fn remove_export(&mut self, name: &str) -> bool {
+        if self.export_index.remove(name).is_none() {
+            return false;
+        }
+        self.exports.retain(|e| e.name != name);
+        // What came after it has moved down, and there's nothing left to collide.
+        let _ = self.index_exports();
+        true
+    }
+
+    /// Export under `to` what was exported as `from`. Returns false, changing nothing, if
+    /// `from` isn't exported or `to` already is.
+    pub fn rename_export(&mut self, from: &str, to: &str) -> bool {
+        if from != to && self.export(to).is_some() {
+            return false;
+        }
+        let Some(i) = self.export_index.remove(from) else {
+            return false;
+        };
+        self.exports[i].name = to.to_string();
+        self.export_index.insert(to.to_string(), i);
+        true
+    }
+
+    /// Import from module `to` everything imported from module `from`, e.g. to move `env`
+    /// imports into a sandboxed namespace. Returns how many imports were changed.
+    pub fn rename_import_module(&mut self, from: &str, to: &str) -> usize {
+        let mut renamed = 0;
+        for (module, _, _) in self.imports.iter_mut().filter(|(m, _, _)| m == from) {
+            *module = to.to_string();
+            renamed += 1;
+        }
+        renamed
+    }
+
+    /// The names of the custom sections, in order.
+    pub fn custom_section_names(&self) -> Vec<String> {
+        self.sections
+            .iter()
+            .filter(|s| s.id == SECTION_ID_CUSTOM)
+            .filter_map(|s| {
+                LEB128Reader::new(&self.module_data, s.offset)
+                    .load_string()
+                    .ok()
+            })
+            .collect()
+    }
+
+    /// Drop the custom sections called `name`. Returns how many there were.
+    pub fn remove_custom_sections(&mut self, name: &str) -> usize {
+        let before = self.sections.len();
+        let module_data = &self.module_data;
+        self.sections.retain(|s| {
+            s.id != SECTION_ID_CUSTOM
+                || LEB128Reader::new(module_data, s.offset)
+                    .load_string()
+                    .is_ok_and(|n| n != name)
+        });
+        before - self.sections.len()
+    }
+
+    /// Drop every custom section, names and debug info included.
+    pub fn strip_custom_sections(&mut self) {
+        self.sections.retain(|s| s.id != SECTION_ID_CUSTOM);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::exec::Execution;
+    use crate::linker::Linker;
+    use crate::memory::VectorMemory;
+    use crate::module::SECTION_ID_CUSTOM;
+    use crate::module::{write_section, write_uleb128, ImportExportKind, Module};
+    use crate::Value;
+
+    #[test]
+    fn edited_modules_re_encode() {
+        let wat = r#"(module
+            (import "env" "offset" (func $offset (result i32)))
+            (func $add (export "add") (param i32 i32) (result i32)
+                (i32.add (i32.add (local.get 0) (local.get 1)) (call $offset)))
+            (func (export "internal") (result i32) (i32.const 0)))"#;
+        // wat writes a name section for `$offset` and `$add`; three more custom sections follow it.
+        let mut bytes = wat::parse_str(wat).unwrap();
+        for name in ["name", "producers", "name"] {
+            let mut custom = vec![];
+            write_uleb128(&mut custom, name.len() as u64);
+            custom.extend_from_slice(name.as_bytes());
+            write_section(&mut bytes, SECTION_ID_CUSTOM, &custom);
+        }
+        let mut module = Module::load(&bytes).unwrap();
+
+        assert_eq!(module.rename_import_module("env", "sandbox"), 1);
+        assert!(module.rename_export("add", "sum"));
+        assert!(!module.rename_export("internal", "sum"));
+        assert!(module.remove_export("internal"));
+        assert!(!module.remove_export("internal"));
+        assert!(module.add_export("sum_again", ImportExportKind::Function, 1));
+        assert!(!module.add_export("sum", ImportExportKind::Function, 1));
+        assert_eq!(
+            module.custom_section_names(),
+            ["name", "name", "producers", "name"]
+        );
+        assert_eq!(module.remove_custom_sections("name"), 3);
+        assert_eq!(module.custom_section_names(), ["producers"]);
+
+        let module = Module::load(&module.encode()).unwrap();
+        assert_eq!(module.custom_section_names(), ["producers"]);
+        let mut linker = Linker::new();
+        linker.func("sandbox", "offset", |_| Ok(vec![Value::I32(100)]));
+        let instance = linker.instantiate(module).unwrap();
+        assert_eq!(instance.find_funcidx("internal"), None);
+        assert_eq!(instance.find_funcidx("sum_again"), Some(1));
+        let funcidx = instance.find_funcidx("sum").unwrap();
+        let mut execution = Execution::new(instance, VectorMemory::new(0, None));
+        execution
+            .prepare(funcidx, &[Value::I32(2), Value::I32(3)])
+            .unwrap();
+        execution.run().unwrap();
+        assert_eq!(execution.result().unwrap(), &[Value::I32(105)]);
+
+        let mut module = Module::load(&bytes).unwrap();
+        module.strip_custom_sections();
+        assert!(Module::load(&module.encode())
+            .unwrap()
+            .custom_section_names()
+            .is_empty());
+    }
+}
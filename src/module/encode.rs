@@ -0,0 +1,366 @@
+// Copyright (C) 2025 Ryan Daum <ryan.daum@gmail.com> This program is free
+// software: you can redistribute it and/or modify it under the terms of the GNU
+// General Public License as published by the Free Software Foundation, version
+// 3.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+use crate::module::{
+    write_uleb128, Data, ElementMode, Elements, Import, ReferenceType, Region, SECTION_ID_CODE,
+    SECTION_ID_CUSTOM, SECTION_ID_DATA, SECTION_ID_DATA_COUNT, SECTION_ID_ELEMENT,
+    SECTION_ID_EXPORT, SECTION_ID_FUNCTION, SECTION_ID_GLOBAL, SECTION_ID_IMPORT,
+    SECTION_ID_MEMORY, SECTION_ID_START, SECTION_ID_TABLE, SECTION_ID_TAG, SECTION_ID_TYPE,
+    SECTION_ORDER,
+};
+use crate::{Module, ValueType};
+
+impl Module {
+    /// Serialize the parsed module back to a binary.
+    ///
+    /// Sections are rebuilt from `types`, `imports`, `exports` and the rest, so changes made to
+    /// them are reflected in the output; function bodies and constant expressions are copied
+    /// from `module_data`. Empty sections are left out, and custom sections keep their place
+    /// after whichever section preceded them in the original.
+    ///
+    /// Under `gc` the type section is copied as is, and GC reference types elsewhere encode as
+    /// `anyref`. Imported memories don't record whether they were shared, so are never
+    /// encoded as shared.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = match self.module_data.get(..8) {
+            Some(preamble) => preamble.to_vec(),
+            None => {
+                let mut preamble = b"\0asm".to_vec();
+                preamble.extend_from_slice(&self.version.to_le_bytes());
+                preamble
+            }
+        };
+
+        // Custom sections, grouped by the standard section they came after, if any.
+        let mut customs: Vec<(Option<u8>, Region)> = vec![];
+        let mut preceding = None;
+        for section in &self.sections {
+            if section.id == SECTION_ID_CUSTOM {
+                customs.push((preceding, (section.offset, section.offset + section.size)));
+            } else {
+                preceding = Some(section.id);
+            }
+        }
+        let write_customs = |out: &mut Vec<u8>, after: Option<u8>| {
+            for (_, (start, end)) in customs.iter().filter(|(a, _)| *a == after) {
+                write_section(out, SECTION_ID_CUSTOM, &self.module_data[*start..*end]);
+            }
+        };
+
+        write_customs(&mut out, None);
+        for id in SECTION_ORDER {
+            if let Some(payload) = self.encode_section(id) {
+                write_section(&mut out, id, &payload);
+            }
+            write_customs(&mut out, Some(id));
+        }
+        out
+    }
+
+    /// The contents of the standard section `id`, or `None` if it would be empty.
+    fn encode_section(&self, id: u8) -> Option<Vec<u8>> {
+        let mut out = vec![];
+        match id {
+            SECTION_ID_TYPE => {
+                #[cfg(feature = "gc")]
+                {
+                    let section = self.sections.iter().find(|s| s.id == SECTION_ID_TYPE)?;
+                    return Some(
+                        self.module_data[section.offset..section.offset + section.size].to_vec(),
+                    );
+                }
+                #[cfg(not(feature = "gc"))]
+                {
+                    write_count(&mut out, self.types.len())?;
+                    for ty in &self.types {
+                        out.push(0x60);
+                        write_value_types(&mut out, &ty.params);
+                        write_value_types(&mut out, &ty.results);
+                    }
+                }
+            }
+            SECTION_ID_IMPORT => {
+                write_count(&mut out, self.imports.len())?;
+                for (module, field, import) in &self.imports {
+                    write_name(&mut out, module);
+                    write_name(&mut out, field);
+                    match import {
+                        Import::Func(typeidx) => {
+                            out.push(0x00);
+                            write_uleb128(&mut out, *typeidx as u64);
+                        }
+                        Import::Table(reftype, limits) => {
+                            out.push(0x01);
+                            out.push(*reftype as u8);
+                            write_limits(&mut out, *limits, false);
+                        }
+                        Import::Memory(limits) => {
+                            out.push(0x02);
+                            write_limits(&mut out, *limits, false);
+                        }
+                        Import::Global(ty, mutable) => {
+                            out.push(0x03);
+                            out.push(ty.to_u8());
+                            out.push(*mutable as u8);
+                        }
+                    }
+                }
+            }
+            SECTION_ID_FUNCTION => {
+                write_count(&mut out, self.functions.len())?;
+                for typeidx in &self.functions {
+                    write_uleb128(&mut out, *typeidx as u64);
+                }
+            }
+            SECTION_ID_TABLE => {
+                write_count(&mut out, self.tables.len())?;
+                for table in &self.tables {
+                    if table.init.is_some() {
+                        out.extend_from_slice(&[0x40, 0x00]);
+                    }
+                    out.push(table.ty as u8);
+                    write_limits(&mut out, table.limits, false);
+                    if let Some(init) = &table.init {
+                        self.write_expr(&mut out, init);
+                    }
+                }
+            }
+            SECTION_ID_MEMORY => {
+                write_count(&mut out, self.memories.len())?;
+                for memory in &self.memories {
+                    write_limits(&mut out, memory.limits, memory.shared);
+                }
+            }
+            SECTION_ID_TAG => {
+                write_count(&mut out, self.tags.len())?;
+                for type_index in &self.tags {
+                    out.push(0x00);
+                    write_uleb128(&mut out, *type_index as u64);
+                }
+            }
+            SECTION_ID_GLOBAL => {
+                write_count(&mut out, self.globals.len())?;
+                for global in &self.globals {
+                    out.push(global.ty.to_u8());
+                    out.push(global.mutable as u8);
+                    self.write_expr(&mut out, &global.expr);
+                }
+            }
+            SECTION_ID_EXPORT => {
+                write_count(&mut out, self.exports.len())?;
+                for export in &self.exports {
+                    write_name(&mut out, &export.name);
+                    out.push(export.kind as u8);
+                    write_uleb128(&mut out, export.index as u64);
+                }
+            }
+            SECTION_ID_START => {
+                write_uleb128(&mut out, self.start_function? as u64);
+            }
+            SECTION_ID_ELEMENT => {
+                write_count(&mut out, self.element_segments.len())?;
+                for segment in &self.element_segments {
+                    let uses_exprs = matches!(segment.elements, Elements::Expression(_));
+                    let mut flags = match &segment.mode {
+                        ElementMode::Active { table_index: 0, .. }
+                            if segment.reftype == ReferenceType::FuncRef =>
+                        {
+                            0
+                        }
+                        ElementMode::Active { .. } => 2,
+                        ElementMode::Passive => 1,
+                        ElementMode::Declarative => 3,
+                    };
+                    if uses_exprs {
+                        flags |= 4;
+                    }
+                    out.push(flags);
+                    if let ElementMode::Active { table_index, expr } = &segment.mode {
+                        if flags & 2 != 0 {
+                            write_uleb128(&mut out, *table_index as u64);
+                        }
+                        self.write_expr(&mut out, expr);
+                    }
+                    if flags & 3 != 0 {
+                        // Function indices carry an element kind, expressions a reference type.
+                        if uses_exprs || segment.reftype != ReferenceType::FuncRef {
+                            out.push(segment.reftype as u8);
+                        } else {
+                            out.push(0x00);
+                        }
+                    }
+                    match &segment.elements {
+                        Elements::Function(indices) => {
+                            write_uleb128(&mut out, indices.len() as u64);
+                            for index in indices {
+                                write_uleb128(&mut out, *index as u64);
+                            }
+                        }
+                        Elements::Expression(exprs) => {
+                            write_uleb128(&mut out, exprs.len() as u64);
+                            for expr in exprs {
+                                self.write_expr(&mut out, expr);
+                            }
+                        }
+                    }
+                }
+            }
+            SECTION_ID_DATA_COUNT => {
+                // Only needed by bulk memory instructions, so only kept if it was there before.
+                self.sections
+                    .iter()
+                    .find(|s| s.id == SECTION_ID_DATA_COUNT)?;
+                write_uleb128(&mut out, self.data.len() as u64);
+            }
+            SECTION_ID_CODE => {
+                write_count(&mut out, self.code.len())?;
+                for code in &self.code {
+                    let mut body = vec![];
+                    let mut runs: Vec<(u32, ValueType)> = vec![];
+                    for local in &code.locals {
+                        match runs.last_mut() {
+                            Some((count, ty)) if ty == local => *count += 1,
+                            _ => runs.push((1, *local)),
+                        }
+                    }
+                    write_uleb128(&mut body, runs.len() as u64);
+                    for (count, ty) in runs {
+                        write_uleb128(&mut body, count as u64);
+                        body.push(ty.to_u8());
+                    }
+                    body.extend_from_slice(&self.module_data[code.code.0..code.code.1]);
+                    write_uleb128(&mut out, body.len() as u64);
+                    out.extend_from_slice(&body);
+                }
+            }
+            SECTION_ID_DATA => {
+                write_count(&mut out, self.data.len())?;
+                for datum in &self.data {
+                    let data = match datum {
+                        Data::Active { expr, data } => {
+                            out.push(0);
+                            self.write_expr(&mut out, expr);
+                            data
+                        }
+                        Data::Passive { data } => {
+                            out.push(1);
+                            data
+                        }
+                        Data::ActiveMemIdx { memidx, expr, data } => {
+                            out.push(2);
+                            write_uleb128(&mut out, *memidx as u64);
+                            self.write_expr(&mut out, expr);
+                            data
+                        }
+                    };
+                    let bytes = &self.module_data[data.0..data.1];
+                    write_uleb128(&mut out, bytes.len() as u64);
+                    out.extend_from_slice(bytes);
+                }
+            }
+            _ => return None,
+        }
+        Some(out)
+    }
+
+    /// A constant expression, including its terminating `end`.
+    fn write_expr(&self, out: &mut Vec<u8>, region: &Region) {
+        out.extend_from_slice(&self.module_data[region.0..=region.1]);
+    }
+}
+
+pub(crate) fn write_section(out: &mut Vec<u8>, id: u8, payload: &[u8]) {
+    out.push(id);
+    write_uleb128(out, payload.len() as u64);
+    out.extend_from_slice(payload);
+}
+
+/// Write the length of a vector, or `None` if it's empty and the section can be left out.
+fn write_count(out: &mut Vec<u8>, count: usize) -> Option<()> {
+    (count > 0).then(|| write_uleb128(out, count as u64))
+}
+
+#[cfg(not(feature = "gc"))]
+fn write_value_types(out: &mut Vec<u8>, types: &[ValueType]) {
+    write_uleb128(out, types.len() as u64);
+    out.extend(types.iter().map(|ty| ty.to_u8()));
+}
+
+fn write_name(out: &mut Vec<u8>, name: &str) {
+    write_uleb128(out, name.len() as u64);
+    out.extend_from_slice(name.as_bytes());
+}
+
+fn write_limits(out: &mut Vec<u8>, (min, max): (u32, Option<u32>), shared: bool) {
+    out.push(max.is_some() as u8 | if shared { 0x02 } else { 0 });
+    write_uleb128(out, min as u64);
+    if let Some(max) = max {
+        write_uleb128(out, max as u64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::exec::Execution;
+    use crate::module::{write_section, Module, SECTION_ID_CUSTOM};
+    use crate::Value;
+
+    #[test]
+    fn encoded_modules_round_trip() {
+        let wat = r#"(module
+            (import "spectest" "global_i32" (global $base i32))
+            (memory 1 2)
+            (table 2 funcref)
+            (global $g (mut i64) (i64.const -7))
+            (elem (i32.const 0) $double $triple)
+            (data (i32.const 16) "\2a\00\00\00")
+            (data $spare "spare")
+            (type $unary (func (param i32) (result i32)))
+            (func $double (param i32) (result i32) (i32.mul (local.get 0) (i32.const 2)))
+            (func $triple (param i32) (result i32) (i32.mul (local.get 0) (i32.const 3)))
+            (func (export "apply") (param i32 i32) (result i32)
+                (local i32 i32 i64)
+                (local.set 2 (i32.load (i32.const 16)))
+                (i32.add
+                    (call_indirect (type $unary) (local.get 1) (local.get 0))
+                    (i32.add (local.get 2) (global.get $base)))))"#;
+        let mut bytes = wat::parse_str(wat).unwrap();
+        let mut custom = vec![];
+        crate::module::write_uleb128(&mut custom, 4);
+        custom.extend_from_slice(b"note");
+        custom.extend_from_slice(b"kept verbatim");
+        write_section(&mut bytes, SECTION_ID_CUSTOM, &custom);
+
+        let module = Module::load(&bytes).unwrap();
+        let encoded = module.encode();
+        assert!(encoded.ends_with(b"notekept verbatim"));
+
+        let reloaded = Module::load(&encoded).unwrap();
+        assert_eq!(reloaded.encode(), encoded);
+        assert_eq!(reloaded.exports, module.exports);
+        assert_eq!(reloaded.imports, module.imports);
+        assert_eq!(reloaded.globals.len(), 1);
+        assert_eq!(reloaded.code[2].locals, module.code[2].locals);
+        assert_eq!(reloaded.data.len(), 2);
+
+        let instance = crate::spectest().instantiate(reloaded).unwrap();
+        let memory = instance.memories[0].clone();
+        let mut execution = Execution::new(instance, memory);
+        let funcidx = execution.instance().find_funcidx("apply").unwrap();
+        execution
+            .prepare(funcidx, &[Value::I32(1), Value::I32(5)])
+            .unwrap();
+        execution.run().unwrap();
+        assert_eq!(execution.result().unwrap()[0], Value::I32(57 + 666));
+    }
+}
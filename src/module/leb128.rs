@@ -38,8 +38,24 @@ impl<'a> LEB128Reader<'a> {
         self.cursor.position() as usize
     }
 
-    pub fn advance(&mut self, offset: usize) {
+    /// Skip `offset` bytes, failing rather than running past the end of the input.
+    pub fn advance(&mut self, offset: usize) -> Result<(), DecodeError> {
+        if offset > self.remaining().max(0) as usize {
+            return Err(DecodeError::MalformedMemory(format!(
+                "Skip of {} bytes at offset {} runs past the end",
+                offset,
+                self.cursor.position()
+            )));
+        }
         self.cursor.consume(offset);
+        Ok(())
+    }
+
+    /// The next byte, without moving past it.
+    pub(crate) fn peek_byte(&mut self) -> Result<u8, DecodeError> {
+        let byte = self.read_byte()?;
+        self.cursor.set_position(self.cursor.position() - 1);
+        Ok(byte)
     }
 
     fn read_byte(&mut self) -> Result<u8, DecodeError> {
@@ -72,6 +88,13 @@ impl LEB128Reader<'_> {
 
     pub fn load_data(&mut self) -> Result<(usize, usize), DecodeError> {
         let length = self.load_imm_varuint32()? as usize;
+        if length > self.remaining().max(0) as usize {
+            return Err(DecodeError::MalformedMemory(format!(
+                "Data of length {} at offset {} runs past the end",
+                length,
+                self.cursor.position()
+            )));
+        }
         let start = self.cursor.position() as usize;
         let end = start + length;
         self.cursor.consume(length);
@@ -80,6 +103,14 @@ impl LEB128Reader<'_> {
 
     pub fn load_string(&mut self) -> Result<String, DecodeError> {
         let length = self.load_imm_varuint32()? as usize;
+        // Don't take a corrupt length's word for how much to allocate.
+        if length > self.remaining().max(0) as usize {
+            return Err(DecodeError::MalformedMemory(format!(
+                "String of length {} at offset {} runs past the end",
+                length,
+                self.cursor.position()
+            )));
+        }
         let mut buffer = vec![0u8; length];
         self.cursor.read_exact(&mut buffer).map_err(|_| {
             DecodeError::MalformedMemory(format!(
@@ -348,16 +379,27 @@ impl LEB128Reader<'_> {
         Ok(f32::from_le_bytes(f32_buffer))
     }
 
-    pub fn load_imm_f64(&mut self) -> f64 {
+    pub fn load_imm_f64(&mut self) -> Result<f64, DecodeError> {
         let mut f64_buffer = [0u8; 8];
-        self.cursor.read_exact(&mut f64_buffer).unwrap();
-        f64::from_le_bytes(f64_buffer)
+        self.cursor.read_exact(&mut f64_buffer).map_err(|_| {
+            DecodeError::MalformedMemory(format!(
+                "Failed to decode f64 at offset {}",
+                self.cursor.position()
+            ))
+        })?;
+        Ok(f64::from_le_bytes(f64_buffer))
+    }
+
+    /// Room for `num_elements` entries of at least a byte each, or for as many as could fit in
+    /// what's left, whichever is less.
+    pub(crate) fn with_capacity_for<T>(&self, num_elements: usize) -> Vec<T> {
+        Vec::with_capacity(num_elements.min(self.remaining().max(0) as usize))
     }
 
     #[allow(dead_code)]
     pub fn load_array_i32(&mut self) -> Result<Vec<i32>, DecodeError> {
         let num_elements = self.load_imm_varuint32()? as usize;
-        let mut values = Vec::with_capacity(num_elements);
+        let mut values = self.with_capacity_for(num_elements);
         for _ in 0..num_elements {
             values.push(self.load_imm_varint32()?);
         }
@@ -366,10 +408,106 @@ impl LEB128Reader<'_> {
 
     pub fn load_array_varu32(&mut self) -> Result<Vec<u32>, DecodeError> {
         let num_elements = self.load_imm_varuint32()? as usize;
-        let mut values = Vec::with_capacity(num_elements);
+        let mut values = self.with_capacity_for(num_elements);
         for _ in 0..num_elements {
             values.push(self.load_imm_varuint32()?);
         }
         Ok(values)
     }
 }
+
+/// Append `value` to `out` as an unsigned LEB128.
+pub(crate) fn write_uleb128(out: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+/// Append `value` to `out` as a signed LEB128.
+pub(crate) fn write_sleb128(out: &mut Vec<u8>, mut value: i64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        let done = (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0);
+        if done {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::module::leb128::{write_sleb128, write_uleb128, LEB128Reader};
+
+    /// Run every reader method over `bytes`, which may be anything; they can fail, but not panic.
+    fn read_everything(bytes: &[u8]) {
+        type ReadFn = fn(&mut LEB128Reader) -> bool;
+        let reads: [ReadFn; 12] = [
+            |r| r.load_imm_varuint32().is_ok(),
+            |r| r.load_imm_varint32().is_ok(),
+            |r| r.load_imm_signed_varint32().is_ok(),
+            |r| r.load_imm_signed_varint64().is_ok(),
+            |r| r.load_imm_varuint64().is_ok(),
+            |r| r.load_imm_f32().is_ok(),
+            |r| r.load_imm_f64().is_ok(),
+            |r| r.load_string().is_ok(),
+            |r| r.load_array_i32().is_ok(),
+            |r| r.load_array_varu32().is_ok(),
+            |r| r.load_data().is_ok(),
+            |r| r.advance(3).is_ok(),
+        ];
+        for read in reads {
+            let mut reader = LEB128Reader::new(bytes, 0);
+            while reader.remaining() > 0 && read(&mut reader) {}
+        }
+    }
+
+    #[test]
+    fn truncated_and_garbage_input_fails_cleanly() {
+        let mut valid = vec![];
+        write_uleb128(&mut valid, u32::MAX as u64);
+        write_sleb128(&mut valid, i64::MIN);
+        valid.extend_from_slice(&1.5f64.to_le_bytes());
+        // A string, and an array, claiming far more than is there.
+        write_uleb128(&mut valid, 0xffff_fff0);
+        valid.extend_from_slice(b"abc");
+        for len in 0..=valid.len() {
+            read_everything(&valid[..len]);
+        }
+
+        let mut reader = LEB128Reader::new(&[0, 0, 0xf8, 0x3f][..], 0);
+        assert!(reader.load_imm_f64().is_err());
+
+        // Data and skips past the end are refused without moving the cursor.
+        let mut reader = LEB128Reader::new(&[0x05, 1, 2][..], 0);
+        assert!(reader.load_data().is_err());
+        assert_eq!(reader.position(), 1);
+        assert!(reader.advance(3).is_err());
+        assert!(reader.advance(2).is_ok());
+        assert_eq!(reader.remaining(), 0);
+
+        // Over-long encodings, and a deterministic spray of arbitrary bytes.
+        read_everything(&[0xff; 16]);
+        read_everything(&[0x80; 16]);
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        for _ in 0..2000 {
+            let bytes: Vec<u8> = (0..(state % 24))
+                .map(|_| {
+                    state ^= state << 13;
+                    state ^= state >> 7;
+                    state ^= state << 17;
+                    state as u8
+                })
+                .collect();
+            read_everything(&bytes);
+        }
+    }
+}
@@ -11,32 +11,68 @@
 // this program. If not, see <https://www.gnu.org/licenses/>.
 //
 
+mod call_graph;
+mod config;
+mod edit;
+mod encode;
 mod leb128;
 mod parse;
+mod summary;
+mod support;
 
+pub use crate::module::call_graph::CallGraph;
+pub use crate::module::config::LoadConfig;
+pub(crate) use crate::module::encode::write_section;
 pub use crate::module::leb128::LEB128Reader;
-use crate::module::parse::{
+pub(crate) use crate::module::leb128::{write_sleb128, write_uleb128};
+pub(crate) use crate::module::parse::{
     SECTION_ID_CODE, SECTION_ID_CUSTOM, SECTION_ID_DATA, SECTION_ID_DATA_COUNT, SECTION_ID_ELEMENT,
     SECTION_ID_EXPORT, SECTION_ID_FUNCTION, SECTION_ID_GLOBAL, SECTION_ID_IMPORT,
-    SECTION_ID_MEMORY, SECTION_ID_START, SECTION_ID_TABLE, SECTION_ID_TYPE,
+    SECTION_ID_MEMORY, SECTION_ID_START, SECTION_ID_TABLE, SECTION_ID_TAG, SECTION_ID_TYPE,
+    SECTION_ORDER,
 };
+pub use crate::module::summary::{ModuleSummary, Proposal};
+pub use crate::module::support::UnsupportedFeature;
 use crate::LoaderError::{DecoderError, UnsupportedSectionType};
 use crate::{DecodeError, FuncType, ValueType};
+use std::collections::HashMap;
 use std::error::Error;
 use std::fmt::{Display, Formatter};
+use std::hash::Hash;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[non_exhaustive]
 pub enum LoaderError {
     InvalidMagicNumber,
     InvalidVersion,
+    /// The binary is a component-model binary (version, layer) rather than a core module.
+    ComponentModelUnsupported(u16, u16),
     InvalidSectionType(u8),
     InvalidImportType(u8),
     InvalidReferenceType(u8),
     InvalidInstruction,
-    MismatchedBlockStack,
+    /// The body of the function with this index, counting imports, doesn't close all its scopes.
+    MismatchedBlockStack(u32),
     UnsupportedSectionType(SectionType),
     UnsupportedElementSegment(u8),
     DecoderError(DecodeError),
+    /// The module, a function body, or one of its sections is bigger than the `LoadConfig`
+    /// allows. For ops, `count` is as far as decoding got before stopping.
+    LimitExceeded {
+        what: &'static str,
+        count: u64,
+        limit: u64,
+    },
+    /// A custom section whose name we don't recognize, when the `LoadConfig` refuses those.
+    UnknownCustomSection(String),
+    /// A standard section, by ID, after one the spec has it come before.
+    SectionOutOfOrder(u8),
+    /// A standard section, by ID, appearing more than once.
+    DuplicateSection(u8),
+    /// Two exports with this name.
+    DuplicateExport(String),
+    /// A function, import or tag refers to a type index beyond the type section.
+    UnknownType(u32),
 }
 
 impl Display for LoaderError {
@@ -44,6 +80,11 @@ impl Display for LoaderError {
         match self {
             LoaderError::InvalidMagicNumber => write!(f, "Invalid magic number"),
             LoaderError::InvalidVersion => write!(f, "Invalid version"),
+            LoaderError::ComponentModelUnsupported(version, layer) => write!(
+                f,
+                "Binary is a component (version {version:#x}, layer {layer}), not a core module; \
+                 the component model is not supported"
+            ),
             LoaderError::InvalidSectionType(t) => write!(f, "Invalid section type: {t}"),
             UnsupportedSectionType(t) => {
                 write!(f, "Unsupported section type: {t:?}")
@@ -52,16 +93,66 @@ impl Display for LoaderError {
                 write!(f, "Unsupported element kind: {k}")
             }
             LoaderError::InvalidInstruction => write!(f, "Invalid instruction"),
-            LoaderError::MismatchedBlockStack => write!(f, "Mismatched block stack"),
+            LoaderError::MismatchedBlockStack(funcidx) => {
+                write!(f, "Mismatched block stack in function {funcidx}")
+            }
             LoaderError::InvalidReferenceType(t) => write!(f, "Invalid reference type: {t}"),
             LoaderError::InvalidImportType(t) => write!(f, "Invalid import type: {t}"),
             DecoderError(e) => write!(f, "Decode error: {e}"),
+            LoaderError::LimitExceeded { what, count, limit } => {
+                write!(f, "Too many {what}: {count}, over the limit of {limit}")
+            }
+            LoaderError::UnknownCustomSection(name) => {
+                write!(f, "Unknown custom section: {name:?}")
+            }
+            LoaderError::SectionOutOfOrder(id) => write!(f, "Section {id} is out of order"),
+            LoaderError::DuplicateSection(id) => write!(f, "Section {id} appears more than once"),
+            LoaderError::DuplicateExport(name) => write!(f, "Duplicate export: {name:?}"),
+            LoaderError::UnknownType(typeidx) => write!(f, "Unknown type: {typeidx}"),
         }
     }
 }
 
 impl Error for LoaderError {}
 
+impl LoaderError {
+    /// Stable numeric code for this error, in the 1000s. Decode errors keep their own code.
+    pub fn code(&self) -> u32 {
+        match self {
+            LoaderError::InvalidMagicNumber => 1001,
+            LoaderError::InvalidVersion => 1002,
+            LoaderError::ComponentModelUnsupported(_, _) => 1003,
+            LoaderError::InvalidSectionType(_) => 1004,
+            LoaderError::InvalidImportType(_) => 1005,
+            LoaderError::InvalidReferenceType(_) => 1006,
+            LoaderError::InvalidInstruction => 1007,
+            LoaderError::MismatchedBlockStack(_) => 1008,
+            LoaderError::UnsupportedSectionType(_) => 1009,
+            LoaderError::UnsupportedElementSegment(_) => 1010,
+            DecoderError(e) => e.code(),
+            LoaderError::LimitExceeded { .. } => 1011,
+            LoaderError::UnknownCustomSection(_) => 1012,
+            LoaderError::SectionOutOfOrder(_) => 1013,
+            LoaderError::DuplicateSection(_) => 1014,
+            LoaderError::DuplicateExport(_) => 1015,
+            LoaderError::UnknownType(_) => 1016,
+        }
+    }
+}
+
+/// What a binary claims to be, judged from its preamble alone.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BinaryKind {
+    /// A core module of the given version. Only version 1 is loadable.
+    CoreModule(u16),
+    /// A component-model binary, with its (pre-release) version.
+    Component(u16),
+    /// WASM magic, but a layer we don't recognize.
+    UnknownLayer { version: u16, layer: u16 },
+    /// Missing the magic number, or too short to carry a version.
+    NotWasm,
+}
+
 #[repr(u8)]
 #[derive(Debug, Copy, Clone)]
 pub enum SectionType {
@@ -78,6 +169,7 @@ pub enum SectionType {
     Code = SECTION_ID_CODE,
     Data = SECTION_ID_DATA,
     DataCount = SECTION_ID_DATA_COUNT,
+    Tag = SECTION_ID_TAG,
 }
 
 impl SectionType {
@@ -96,6 +188,7 @@ impl SectionType {
             SECTION_ID_CODE => Ok(SectionType::Code),
             SECTION_ID_DATA => Ok(SectionType::Data),
             SECTION_ID_DATA_COUNT => Ok(SectionType::DataCount),
+            SECTION_ID_TAG => Ok(SectionType::Tag),
             _ => Err(LoaderError::InvalidSectionType(value)),
         }
     }
@@ -118,23 +211,28 @@ impl ReferenceType {
     }
 }
 
+/// Where a section sits in the binary: its id, the offset of its contents and their length.
+#[derive(Debug, Clone, PartialEq)]
 pub struct SectionInfo {
     pub id: u8,
     pub offset: usize,
+    pub size: usize,
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct ExportEntry {
     // TODO: This could be offsets instead of copying...
-    pub(crate) name: String,
-    pub(crate) kind: ImportExportKind,
-    pub(crate) index: u32,
+    pub name: String,
+    pub kind: ImportExportKind,
+    pub index: u32,
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct Table {
     pub ty: ReferenceType,
     pub limits: (u32, Option<u32>),
+    /// Expression for the value elements start out as, rather than null.
+    pub init: Option<Region>,
 }
 
 /// Declaration of a memory section in the program.
@@ -142,6 +240,8 @@ pub struct Table {
 pub struct MemorySection {
     /// Min pages, optional max pages.
     pub limits: (u32, Option<u32>),
+    /// Declared shared, for use by atomics from multiple threads.
+    pub shared: bool,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -160,7 +260,7 @@ pub enum Data {
     },
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Code {
     pub locals: Vec<ValueType>,
     pub code: Region,
@@ -191,20 +291,20 @@ pub struct Global {
     pub expr: Region,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum ElementMode {
     Passive,
     Active { table_index: u32, expr: Region },
     Declarative,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Elements {
     Function(Vec<u32>),
     Expression(Vec<Region>),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ElementSegment {
     pub reftype: ReferenceType,
     pub elements: Elements,
@@ -226,18 +326,34 @@ impl ImportExportKind {
 /// Represents a WASM binary, loaded.
 /// Holds not just the program, but parsed data about the program such as its block structure,
 /// number of locals, etc.
+#[derive(Clone)]
 pub struct Module {
     // The original unmolested binary format.
     pub module_data: Vec<u8>,
     pub version: u32,
+    pub sections: Vec<SectionInfo>,
     pub types: Vec<FuncType>,
+    /// The canonical ID of each entry in `types`: the index of the first type identical to it,
+    /// so an indirect call's signature check is a comparison of two IDs.
+    pub type_ids: Vec<u32>,
+    /// The full type section, including struct and array types. `types` holds the function
+    /// signatures at the same indices.
+    #[cfg(feature = "gc")]
+    pub sub_types: Vec<crate::gc::SubType>,
     pub code: Vec<Code>,
     pub tables: Vec<Table>,
     pub functions: Vec<usize>,
-    pub exports: Vec<ExportEntry>,
+    /// Read through `exports()` and changed through `add_export` and the like, so that
+    /// `export_index` can't go stale.
+    exports: Vec<ExportEntry>,
+    /// Where in `exports` each name is, by its exact bytes.
+    export_index: HashMap<String, usize>,
     pub imports: Vec<(String, String, Import)>,
     pub memories: Vec<MemorySection>,
     pub globals: Vec<Global>,
+    /// The type index of each exception tag the module defines. They're loaded so that modules
+    /// with them can run, but throwing one faults; see `Fault::UnsupportedThrow`.
+    pub tags: Vec<u32>,
     pub data: Vec<Data>,
     pub start_function: Option<usize>,
     pub element_segments: Vec<ElementSegment>,
@@ -250,13 +366,106 @@ impl Module {
 
         (&self.module_data[start..end]) as _
     }
+
+    /// The body of the `index`th function the module defines, or `None` if there's no such body.
+    pub(crate) fn body(&self, index: usize) -> Option<&[u8]> {
+        let (start, end) = self.code.get(index)?.code;
+        self.module_data.get(start..end)
+    }
+
+    /// Everything the module exports, in the order of its export section.
+    pub fn exports(&self) -> &[ExportEntry] {
+        &self.exports
+    }
+
+    /// The export named `name`, compared byte for byte.
+    pub fn export(&self, name: &str) -> Option<&ExportEntry> {
+        self.exports.get(*self.export_index.get(name)?)
+    }
+
+    /// Index the names in `exports`, failing on the first that appears twice.
+    pub(crate) fn index_exports(&mut self) -> Result<(), LoaderError> {
+        self.export_index.clear();
+        for (i, export) in self.exports.iter().enumerate() {
+            if self.export_index.insert(export.name.clone(), i).is_some() {
+                return Err(LoaderError::DuplicateExport(export.name.clone()));
+            }
+        }
+        Ok(())
+    }
+
+    /// How many functions the module imports. Function indices count these first, so the
+    /// function at index `funcidx` is defined by the module only if `funcidx` is at least this,
+    /// and is then `functions[funcidx - num_imported_funcs()]`.
+    pub fn num_imported_funcs(&self) -> usize {
+        self.imports
+            .iter()
+            .filter(|(_, _, import)| matches!(import, Import::Func(_)))
+            .count()
+    }
+
+    /// Whether `funcidx` names one of the module's imported functions.
+    pub fn is_imported_func(&self, funcidx: u32) -> bool {
+        (funcidx as usize) < self.num_imported_funcs()
+    }
+
+    /// The index among the functions the module defines of `funcidx`, or `None` if it's an
+    /// import or out of range.
+    pub fn defined_func_index(&self, funcidx: u32) -> Option<usize> {
+        let defined = (funcidx as usize).checked_sub(self.num_imported_funcs())?;
+        (defined < self.functions.len()).then_some(defined)
+    }
+
+    /// The first name the function at `funcidx` is exported under, if it's exported.
+    pub fn func_name(&self, funcidx: u32) -> Option<&str> {
+        self.exports
+            .iter()
+            .find(|e| e.kind == ImportExportKind::Function && e.index == funcidx)
+            .map(|e| e.name.as_str())
+    }
+
+    /// The signature of the function at `funcidx`, whether imported or defined.
+    pub fn func_type_of(&self, funcidx: u32) -> Option<&FuncType> {
+        self.types.get(self.func_typeidx_of(funcidx)?)
+    }
+
+    /// The index in `types` of the signature of the function at `funcidx`.
+    pub(crate) fn func_typeidx_of(&self, funcidx: u32) -> Option<usize> {
+        match self.defined_func_index(funcidx) {
+            Some(defined) => Some(self.functions[defined]),
+            None => self
+                .imports
+                .iter()
+                .filter_map(|(_, _, import)| match import {
+                    Import::Func(typeidx) => Some(*typeidx as usize),
+                    _ => None,
+                })
+                .nth(funcidx as usize),
+        }
+    }
 }
 
 pub type Region = (usize, usize);
 
+/// Number each of `types` by the index of the first one equal to it.
+pub(crate) fn canonical_type_ids<T: Eq + Hash>(types: &[T]) -> Vec<u32> {
+    let mut first_seen = HashMap::new();
+    types
+        .iter()
+        .enumerate()
+        .map(|(index, ty)| *first_seen.entry(ty).or_insert(index as u32))
+        .collect()
+}
+
 impl Module {
-    pub fn get_expr(&self, region: &Region) -> &[u8] {
-        assert_eq!(self.module_data[region.1], 0x0b);
-        &self.module_data[region.0..region.1]
+    /// The bytes of the constant expression at `region`, without its terminating `end`.
+    pub fn get_expr(&self, region: &Region) -> Result<&[u8], DecodeError> {
+        match self.module_data.get(region.1) {
+            Some(0x0b) if region.0 <= region.1 => Ok(&self.module_data[region.0..region.1]),
+            _ => Err(DecodeError::FailedToDecode(format!(
+                "expression at {:#x} isn't terminated by end",
+                region.0
+            ))),
+        }
     }
 }
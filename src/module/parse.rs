@@ -11,14 +11,19 @@
 // this program. If not, see <https://www.gnu.org/licenses/>.
 //
 
+use crate::decode::decode_function_capped;
+use crate::module::config::is_known_custom_section;
 use crate::module::leb128::LEB128Reader;
 use crate::module::{
-    Code, Data, ElementMode, ElementSegment, Elements, ExportEntry, Import, ImportExportKind,
-    MemorySection, ReferenceType, Region, SectionType, Table,
+    canonical_type_ids, BinaryKind, Code, Data, ElementMode, ElementSegment, Elements, ExportEntry,
+    Import, ImportExportKind, LoadConfig, MemorySection, ReferenceType, Region, SectionInfo,
+    SectionType, Table,
 };
+use crate::opcode::OpCode;
 use crate::DecodeError::{FailedToDecode, InvalidDataSegmentType, MalformedMemory};
 use crate::LoaderError::DecoderError;
 use crate::{DecodeError, FuncType, Global, LoaderError, Module, ValueType};
+use std::collections::HashMap;
 
 pub const SECTION_ID_CUSTOM: u8 = 0;
 pub const SECTION_ID_TYPE: u8 = 1;
@@ -33,10 +38,54 @@ pub const SECTION_ID_ELEMENT: u8 = 9;
 pub const SECTION_ID_CODE: u8 = 10;
 pub const SECTION_ID_DATA: u8 = 11;
 pub const SECTION_ID_DATA_COUNT: u8 = 12;
+pub const SECTION_ID_TAG: u8 = 13;
+
+/// The standard sections in the order the spec requires them.
+pub(crate) const SECTION_ORDER: [u8; 13] = [
+    SECTION_ID_TYPE,
+    SECTION_ID_IMPORT,
+    SECTION_ID_FUNCTION,
+    SECTION_ID_TABLE,
+    SECTION_ID_MEMORY,
+    SECTION_ID_TAG,
+    SECTION_ID_GLOBAL,
+    SECTION_ID_EXPORT,
+    SECTION_ID_START,
+    SECTION_ID_ELEMENT,
+    SECTION_ID_DATA_COUNT,
+    SECTION_ID_CODE,
+    SECTION_ID_DATA,
+];
 
 fn read_limits(reader: &mut LEB128Reader) -> Result<(u32, Option<u32>), DecodeError> {
-    // "Limits are encoded with a preceding flag indicating whether a maximum is present."
     let has_maximum = reader.load_imm_u8()?;
+    read_limits_after_flag(reader, has_maximum)
+}
+
+/// Memory limits may also be flagged as shared, which requires a maximum.
+fn read_memory_limits(reader: &mut LEB128Reader) -> Result<MemorySection, DecodeError> {
+    let flags = reader.load_imm_u8()?;
+    let shared = flags & 0x02 != 0;
+    if shared && !cfg!(feature = "atomics") {
+        return Err(DecodeError::UnsupportedType(
+            flags as u32,
+            "Shared memory requires the atomics feature".to_string(),
+        ));
+    }
+    let limits = read_limits_after_flag(reader, flags & !0x02)?;
+    if shared && limits.1.is_none() {
+        return Err(MalformedMemory(
+            "Shared memory must have a maximum".to_string(),
+        ));
+    }
+    Ok(MemorySection { limits, shared })
+}
+
+fn read_limits_after_flag(
+    reader: &mut LEB128Reader,
+    has_maximum: u8,
+) -> Result<(u32, Option<u32>), DecodeError> {
+    // "Limits are encoded with a preceding flag indicating whether a maximum is present."
     let initial = reader.load_imm_varuint32()?;
     let maximum = if has_maximum == 1 {
         Some(reader.load_imm_varuint32()?)
@@ -63,35 +112,94 @@ fn read_limits(reader: &mut LEB128Reader) -> Result<(u32, Option<u32>), DecodeEr
     Ok(limits)
 }
 
+/// A table type, optionally prefixed by 0x40 0x00 and followed by an expression giving the
+/// initial value of its elements.
 fn read_table(reader: &mut LEB128Reader) -> Result<Table, LoaderError> {
-    let ty = reader.load_imm_u8().map_err(DecoderError)?;
+    let mut ty = reader.load_imm_u8().map_err(DecoderError)?;
+    let has_init = ty == 0x40;
+    if has_init {
+        let reserved = reader.load_imm_u8().map_err(DecoderError)?;
+        if reserved != 0x00 {
+            return Err(DecoderError(FailedToDecode(format!(
+                "Expected 0x00 after table initializer flag, got {reserved:#0x}"
+            ))));
+        }
+        ty = reader.load_imm_u8().map_err(DecoderError)?;
+    }
     let ty = ReferenceType::from_u8(ty)?;
 
     let limits = read_limits(reader).map_err(DecoderError)?;
-    Ok(Table { ty, limits })
+    let init = if has_init {
+        Some(reader.load_expr().map_err(DecoderError)?)
+    } else {
+        None
+    };
+    Ok(Table { ty, limits, init })
 }
 
 const MAX_MEMORY_SIZE_PAGES: u32 = 0x10000;
 
+/// Read the count of entries at the head of a section, holding it to `config`'s limit.
+fn read_count(
+    reader: &mut LEB128Reader,
+    config: &LoadConfig,
+    what: &'static str,
+) -> Result<u32, LoaderError> {
+    let count = reader.load_imm_varuint32().map_err(DecoderError)?;
+    match config.max_section_entries {
+        Some(limit) if count > limit => Err(LoaderError::LimitExceeded {
+            what,
+            count: count as u64,
+            limit: limit as u64,
+        }),
+        _ => Ok(count),
+    }
+}
+
 impl Module {
+    /// Identify a binary from its 8 byte preamble without parsing anything else.
+    pub fn sniff(bytes: &[u8]) -> BinaryKind {
+        if bytes.len() < 8 || &bytes[0..4] != b"\0asm" {
+            return BinaryKind::NotWasm;
+        }
+        let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+        let layer = u16::from_le_bytes([bytes[6], bytes[7]]);
+        match layer {
+            0 => BinaryKind::CoreModule(version),
+            1 => BinaryKind::Component(version),
+            _ => BinaryKind::UnknownLayer { version, layer },
+        }
+    }
+
     pub fn load(module_data: &[u8]) -> Result<Self, LoaderError> {
+        Self::load_with(module_data, &LoadConfig::default())
+    }
+
+    /// As `load`, with `config` deciding what's accepted beyond what the spec requires.
+    pub fn load_with(module_data: &[u8], config: &LoadConfig) -> Result<Self, LoaderError> {
+        if let Some(limit) = config.max_module_size {
+            if module_data.len() > limit {
+                return Err(LoaderError::LimitExceeded {
+                    what: "bytes in the module",
+                    count: module_data.len() as u64,
+                    limit: limit as u64,
+                });
+            }
+        }
+
         // Check for the WASM magic number
         if module_data.len() < 4 || &module_data[0..4] != b"\0asm" {
             return Err(LoaderError::InvalidMagicNumber);
         }
 
-        if module_data.len() < 8 {
-            return Err(LoaderError::InvalidVersion);
-        }
         // Check for the WASM version
-        let version = u32::from_le_bytes(
-            module_data[4..8]
-                .try_into()
-                .map_err(|_| LoaderError::InvalidVersion)?,
-        );
-        if version != 1 {
-            return Err(LoaderError::InvalidVersion);
-        }
+        let version = match Self::sniff(module_data) {
+            BinaryKind::CoreModule(1) => 1,
+            BinaryKind::Component(version) => {
+                return Err(LoaderError::ComponentModelUnsupported(version, 1))
+            }
+            _ => return Err(LoaderError::InvalidVersion),
+        };
 
         // Now start parsing sections, we'll use Memory to read the bytes, as it has the necessary
         // functions to read LEB128 encoded integers and so on.
@@ -100,29 +208,72 @@ impl Module {
         let mut exports = vec![];
         let mut imports = vec![];
         let mut types = vec![];
+        #[cfg(feature = "gc")]
+        let mut sub_types = vec![];
         let mut functions = vec![];
         let mut code = vec![];
         let mut memories = vec![];
         let mut globals = vec![];
+        let mut tags = vec![];
         let mut data = vec![];
         let mut element_segments = vec![];
         let mut start_function = None;
         let mut data_count = None;
+        let mut sections = vec![];
+        // Where the last standard section falls in `SECTION_ORDER`.
+        let mut last_position = None;
         while reader.remaining() > 0 {
             // Read the section ID
-            let section_type = reader.load_imm_u8().map_err(DecoderError)?;
+            let section_id = reader.load_imm_u8().map_err(DecoderError)?;
 
             // Read the section length
             let section_length = reader.load_imm_varuint32().map_err(DecoderError)?;
             let offset = reader.position();
+            sections.push(SectionInfo {
+                id: section_id,
+                offset,
+                size: section_length as usize,
+            });
 
-            let section_type = SectionType::from_u8(section_type)?;
+            let section_type = SectionType::from_u8(section_id)?;
+
+            if config.strict_section_order {
+                if let Some(position) = SECTION_ORDER.iter().position(|id| *id == section_id) {
+                    match last_position {
+                        Some(last) if position == last => {
+                            return Err(LoaderError::DuplicateSection(section_id))
+                        }
+                        Some(last) if position < last => {
+                            return Err(LoaderError::SectionOutOfOrder(section_id))
+                        }
+                        _ => last_position = Some(position),
+                    }
+                }
+            }
 
             match section_type {
                 SectionType::Type => {
                     // Type section
-                    let func_types = reader.load_imm_varuint32().map_err(DecoderError)?;
+                    let func_types = read_count(&mut reader, config, "types")?;
 
+                    #[cfg(feature = "gc")]
+                    for _ in 0..func_types {
+                        for sub_type in
+                            crate::gc::read_type_entry(&mut reader).map_err(DecoderError)?
+                        {
+                            // Keep `types` index-aligned with the type section, so non-function
+                            // entries get an empty signature which nothing should refer to.
+                            types.push(match &sub_type.composite {
+                                crate::gc::CompositeType::Func(func_type) => func_type.clone(),
+                                _ => FuncType {
+                                    params: vec![],
+                                    results: vec![],
+                                },
+                            });
+                            sub_types.push(sub_type);
+                        }
+                    }
+                    #[cfg(not(feature = "gc"))]
                     for _ in 0..func_types {
                         let func_type_marker = reader.load_imm_u8().map_err(DecoderError)?;
                         if func_type_marker != 0x60 {
@@ -132,14 +283,14 @@ impl Module {
                         }
 
                         let num_param_types = reader.load_imm_varuint32().map_err(DecoderError)?;
-                        let mut params = Vec::with_capacity(num_param_types as usize);
+                        let mut params = reader.with_capacity_for(num_param_types as usize);
                         for _ in 0..num_param_types {
                             let param_type = ValueType::read(&mut reader).map_err(DecoderError)?;
                             params.push(param_type);
                         }
 
                         let num_result_types = reader.load_imm_varuint32().map_err(DecoderError)?;
-                        let mut results = Vec::with_capacity(num_result_types as usize);
+                        let mut results = reader.with_capacity_for(num_result_types as usize);
                         for _ in 0..num_result_types {
                             let result_type = ValueType::read(&mut reader).map_err(DecoderError)?;
                             results.push(result_type);
@@ -150,7 +301,7 @@ impl Module {
                 }
                 SectionType::Function => {
                     // Function section, a vector of types
-                    let num_functions = reader.load_imm_varuint32().map_err(DecoderError)?;
+                    let num_functions = read_count(&mut reader, config, "functions")?;
 
                     for _ in 0..num_functions {
                         let type_index = reader.load_imm_varuint32().map_err(DecoderError)?;
@@ -159,7 +310,7 @@ impl Module {
                 }
                 SectionType::Export => {
                     // Export section
-                    let num_exports = reader.load_imm_varuint32().map_err(DecoderError)?;
+                    let num_exports = read_count(&mut reader, config, "exports")?;
 
                     for _ in 0..num_exports {
                         let name = reader.load_string().map_err(DecoderError)?;
@@ -172,14 +323,23 @@ impl Module {
                 }
                 SectionType::Code => {
                     // Code section
-                    let num_functions = reader.load_imm_varuint32().map_err(DecoderError)?;
+                    let num_functions = read_count(&mut reader, config, "function bodies")?;
                     for _ in 0..num_functions {
                         let mut code_size =
                             reader.load_imm_varuint32().map_err(DecoderError)? as usize;
+                        if let Some(limit) = config.max_function_size {
+                            if code_size > limit as usize {
+                                return Err(LoaderError::LimitExceeded {
+                                    what: "bytes in a function body",
+                                    count: code_size as u64,
+                                    limit: limit as u64,
+                                });
+                            }
+                        }
                         // Code size includes the locals block, so we chop that off after reading them.
                         let before_locals = reader.position();
                         let num_types = reader.load_imm_varuint32().map_err(DecoderError)?;
-                        let mut locals = Vec::with_capacity(num_types as usize);
+                        let mut locals = reader.with_capacity_for(num_types as usize);
                         for _ in 0..num_types {
                             let count = reader.load_imm_varuint32().map_err(DecoderError)?;
                             // This is an obscene number of locals, so we'll just fail here.
@@ -196,18 +356,24 @@ impl Module {
                                 locals.push(ty);
                             }
                         }
-                        code_size -= reader.position() - before_locals;
+                        code_size = code_size
+                            .checked_sub(reader.position() - before_locals)
+                            .ok_or_else(|| {
+                                DecoderError(FailedToDecode(
+                                    "Function locals run past the end of its body".to_string(),
+                                ))
+                            })?;
                         let func_offsets = (reader.position(), reader.position() + code_size);
                         code.push(Code {
                             locals,
                             code: func_offsets,
                         });
-                        reader.advance(code_size);
+                        reader.advance(code_size).map_err(DecoderError)?;
                     }
                 }
                 SectionType::Import => {
                     // Import section
-                    let num_imports = reader.load_imm_varuint32().map_err(DecoderError)?;
+                    let num_imports = read_count(&mut reader, config, "imports")?;
                     for _ in 0..num_imports {
                         let module = reader.load_string().map_err(DecoderError)?;
                         let field = reader.load_string().map_err(DecoderError)?;
@@ -227,7 +393,9 @@ impl Module {
                                 Import::Table(reftype, limits)
                             }
                             ImportExportKind::Memory => {
-                                let limits = read_limits(&mut reader).map_err(DecoderError)?;
+                                let limits = read_memory_limits(&mut reader)
+                                    .map_err(DecoderError)?
+                                    .limits;
 
                                 Import::Memory(limits)
                             }
@@ -242,7 +410,7 @@ impl Module {
                 }
                 SectionType::Table => {
                     // Table section
-                    let num_tables = reader.load_imm_varuint32().map_err(DecoderError)?;
+                    let num_tables = read_count(&mut reader, config, "tables")?;
                     for _ in 0..num_tables {
                         let t = read_table(&mut reader)?;
 
@@ -251,10 +419,9 @@ impl Module {
                 }
                 SectionType::Element => {
                     // A vector of element segments.
-                    let num_segments = reader.load_imm_varuint32().map_err(DecoderError)?;
+                    let num_segments = read_count(&mut reader, config, "element segments")?;
                     for _ in 0..num_segments {
                         let flags = reader.load_imm_varuint32().map_err(DecoderError)?;
-                        assert!(flags <= 7);
 
                         let es = match flags {
                             0 => {
@@ -420,15 +587,15 @@ impl Module {
                 }
                 SectionType::Memory => {
                     // Memory section
-                    let num_memories = reader.load_imm_varuint32().map_err(DecoderError)?;
+                    let num_memories = read_count(&mut reader, config, "memories")?;
                     for _ in 0..num_memories {
-                        let limits = read_limits(&mut reader).map_err(DecoderError)?;
-                        memories.push(MemorySection { limits });
+                        let memory = read_memory_limits(&mut reader).map_err(DecoderError)?;
+                        memories.push(memory);
                     }
                 }
                 SectionType::Global => {
                     // Global section
-                    let num_globals = reader.load_imm_varuint32().map_err(DecoderError)?;
+                    let num_globals = read_count(&mut reader, config, "globals")?;
                     for _ in 0..num_globals {
                         let ty = ValueType::read(&mut reader).map_err(DecoderError)?;
                         let mut_flag = reader.load_imm_u8().map_err(DecoderError)?;
@@ -438,7 +605,7 @@ impl Module {
                     }
                 }
                 SectionType::Data => {
-                    let num_data = reader.load_imm_varuint32().map_err(DecoderError)?;
+                    let num_data = read_count(&mut reader, config, "data segments")?;
                     for _ in 0..num_data {
                         let memtype = reader.load_imm_varuint32().map_err(DecoderError)?;
                         let datum = match memtype {
@@ -481,15 +648,37 @@ impl Module {
                     let section_end = reader.position() + section_length as usize;
 
                     // Read and validate the custom section name
-                    let _name = reader.load_string().map_err(DecoderError)?;
+                    let name = reader.load_string().map_err(DecoderError)?;
+                    if !config.allow_unknown_custom && !is_known_custom_section(&name) {
+                        return Err(LoaderError::UnknownCustomSection(name));
+                    }
 
                     // Skip the rest of the custom section content
-                    let remaining = section_end - reader.position();
-                    reader.advance(remaining);
+                    let remaining =
+                        section_end.checked_sub(reader.position()).ok_or_else(|| {
+                            DecoderError(FailedToDecode(
+                                "Custom section name runs past the end of the section".to_string(),
+                            ))
+                        })?;
+                    reader.advance(remaining).map_err(DecoderError)?;
                 }
                 SectionType::DataCount => {
                     data_count = Some(reader.load_imm_varuint32().map_err(DecoderError)?);
                 }
+                SectionType::Tag => {
+                    // Exception tags: an attribute, which is always 0 for an exception, and the
+                    // index of the tag's function type, whose params are what a throw carries.
+                    let num_tags = read_count(&mut reader, config, "tags")?;
+                    for _ in 0..num_tags {
+                        let attribute = reader.load_imm_u8().map_err(DecoderError)?;
+                        if attribute != 0 {
+                            return Err(DecoderError(FailedToDecode(format!(
+                                "unknown tag attribute {attribute}"
+                            ))));
+                        }
+                        tags.push(reader.load_imm_varuint32().map_err(DecoderError)?);
+                    }
+                }
             }
 
             let what_we_read = reader.position() - offset;
@@ -530,21 +719,102 @@ impl Module {
             }
         }
 
-        Ok(Module {
+        // Under GC, identical signatures can still differ in their supertypes or finality, so
+        // it's the whole type section entry that has to match.
+        #[cfg(feature = "gc")]
+        let type_ids = canonical_type_ids(&sub_types);
+        #[cfg(not(feature = "gc"))]
+        let type_ids = canonical_type_ids(&types);
+
+        let mut module = Module {
             module_data: module_data.to_vec(),
             version,
+            sections,
             tables,
             exports,
+            export_index: HashMap::new(),
             imports,
             types,
+            type_ids,
+            #[cfg(feature = "gc")]
+            sub_types,
             functions,
             code,
             memories,
             globals,
+            tags,
             data,
             start_function,
             element_segments,
-        })
+        };
+        module.index_exports()?;
+        module.check_type_indices()?;
+        module.check_body_ends()?;
+        if let Some(limit) = config.max_function_ops {
+            module.check_op_counts(limit)?;
+        }
+        Ok(module)
+    }
+}
+
+impl Module {
+    /// Every function, imported or defined, and every tag has its type in the type section.
+    /// Instantiation and the analyses look these up by index, so they're checked here, once.
+    fn check_type_indices(&self) -> Result<(), LoaderError> {
+        let imported = self
+            .imports
+            .iter()
+            .filter_map(|(_, _, import)| match import {
+                Import::Func(typeidx) => Some(*typeidx as usize),
+                _ => None,
+            });
+        let tags = self.tags.iter().map(|typeidx| *typeidx as usize);
+        for typeidx in imported.chain(self.functions.iter().copied()).chain(tags) {
+            if typeidx >= self.types.len() {
+                return Err(LoaderError::UnknownType(typeidx as u32));
+            }
+        }
+        Ok(())
+    }
+
+    /// Every function body has to finish with the `end` of its own scope. Bodies are only fully
+    /// decoded at instantiation, so this catches the truncated ones without decoding them.
+    fn check_body_ends(&self) -> Result<(), LoaderError> {
+        for i in 0..self.code.len() {
+            if self.code(i).last() != Some(&(OpCode::End as u8)) {
+                let funcidx = self.num_imported_funcs() + i;
+                return Err(LoaderError::MismatchedBlockStack(funcidx as u32));
+            }
+        }
+        Ok(())
+    }
+
+    /// Decode each function body far enough to know it has no more than `limit` ops. Anything
+    /// else wrong with a body is left for instantiation to report, as it would be without the
+    /// limit.
+    fn check_op_counts(&self, limit: u32) -> Result<(), LoaderError> {
+        for (i, typeidx) in self.functions.iter().enumerate() {
+            let Some(func_type) = self.types.get(*typeidx) else {
+                continue;
+            };
+            let decoded =
+                decode_function_capped(self.code(i), &self.types, func_type, Some(limit as usize));
+            match decoded {
+                Err(DecodeError::TooManyOps(ops)) => {
+                    return Err(LoaderError::LimitExceeded {
+                        what: "ops in a function",
+                        count: ops as u64,
+                        limit: limit as u64,
+                    })
+                }
+                Err(DecodeError::UnclosedScopes(_)) => {
+                    let funcidx = self.num_imported_funcs() + i;
+                    return Err(LoaderError::MismatchedBlockStack(funcidx as u32));
+                }
+                _ => {}
+            }
+        }
+        Ok(())
     }
 }
 
@@ -553,6 +823,219 @@ mod tests {
     use super::*;
     use crate::module::Module;
 
+    #[test]
+    fn sniff_component_preamble() {
+        let component = b"\0asm\x0d\x00\x01\x00";
+        assert_eq!(Module::sniff(component), BinaryKind::Component(0x0d));
+        assert!(matches!(
+            Module::load(component),
+            Err(LoaderError::ComponentModelUnsupported(0x0d, 1))
+        ));
+
+        let core = b"\0asm\x01\x00\x00\x00";
+        assert_eq!(Module::sniff(core), BinaryKind::CoreModule(1));
+        assert!(Module::load(core).is_ok());
+
+        assert_eq!(
+            Module::sniff(b"\0asm\x01\x00\x02\x00"),
+            BinaryKind::UnknownLayer {
+                version: 1,
+                layer: 2
+            }
+        );
+        assert_eq!(Module::sniff(b"\0asm\x01"), BinaryKind::NotWasm);
+        assert_eq!(
+            Module::sniff(b"\x7fELF\x02\x01\x01\x00"),
+            BinaryKind::NotWasm
+        );
+    }
+
+    #[test]
+    fn load_config_limits() {
+        let mut binary = wat::parse_str(
+            r#"(module (func) (func) (func (export "f")) (global i32 (i32.const 0)))"#,
+        )
+        .unwrap();
+        // A custom section named "vendor.blob", with a one byte payload.
+        binary.extend_from_slice(b"\x00\x0d\x0bvendor.blob\x01");
+
+        assert!(Module::load(&binary).is_ok());
+        let strict = LoadConfig {
+            allow_unknown_custom: false,
+            ..LoadConfig::default()
+        };
+        assert!(matches!(
+            Module::load_with(&binary, &strict),
+            Err(LoaderError::UnknownCustomSection(name)) if name == "vendor.blob"
+        ));
+
+        let capped = LoadConfig {
+            max_section_entries: Some(2),
+            ..LoadConfig::default()
+        };
+        assert!(matches!(
+            Module::load_with(&binary, &capped),
+            Err(LoaderError::LimitExceeded {
+                what: "functions",
+                count: 3,
+                limit: 2
+            })
+        ));
+    }
+
+    #[test]
+    fn load_config_size_limits() {
+        let binary = wat::parse_str(
+            r#"(module
+                (func (result i32) (i32.const 1))
+                (func (result i32)
+                    (i32.add (i32.add (i32.const 1) (i32.const 2)) (i32.const 3))))"#,
+        )
+        .unwrap();
+        let limits = |config: LoadConfig| match Module::load_with(&binary, &config) {
+            Err(LoaderError::LimitExceeded { what, .. }) => Some(what),
+            Ok(_) => None,
+            Err(e) => panic!("unexpected {e}"),
+        };
+
+        assert_eq!(limits(LoadConfig::default()), None);
+        let module_size = LoadConfig {
+            max_module_size: Some(binary.len() - 1),
+            ..LoadConfig::default()
+        };
+        assert_eq!(limits(module_size), Some("bytes in the module"));
+        let function_size = LoadConfig {
+            max_function_size: Some(8),
+            ..LoadConfig::default()
+        };
+        assert_eq!(limits(function_size), Some("bytes in a function body"));
+        // The second body is five ops, plus those marking the function's scope.
+        let ops = |max| LoadConfig {
+            max_function_ops: Some(max),
+            ..LoadConfig::default()
+        };
+        assert_eq!(limits(ops(7)), None);
+        assert_eq!(limits(ops(6)), Some("ops in a function"));
+    }
+
+    #[test]
+    fn strict_section_order() {
+        let header = b"\0asm\x01\x00\x00\x00".as_slice();
+        let types = b"\x01\x04\x01\x60\x00\x00".as_slice();
+        let functions = b"\x03\x02\x01\x00".as_slice();
+        let code = b"\x0a\x04\x01\x02\x00\x0b".as_slice();
+        let custom = b"\x00\x05\x04name".as_slice();
+        let strict = LoadConfig::strict();
+
+        let in_order = [header, types, custom, functions, custom, code].concat();
+        assert!(Module::load_with(&in_order, &strict).is_ok());
+
+        let swapped = [header, functions, types, code].concat();
+        assert!(Module::load(&swapped).is_ok());
+        assert!(matches!(
+            Module::load_with(&swapped, &strict),
+            Err(LoaderError::SectionOutOfOrder(SECTION_ID_TYPE))
+        ));
+
+        let repeated = [header, types, types, functions, code].concat();
+        assert!(matches!(
+            Module::load_with(&repeated, &strict),
+            Err(LoaderError::DuplicateSection(SECTION_ID_TYPE))
+        ));
+    }
+
+    #[test]
+    fn bodies_missing_their_end_rejected() {
+        let header = b"\0asm\x01\x00\x00\x00".as_slice();
+        let types = b"\x01\x04\x01\x60\x00\x00".as_slice();
+        let functions = b"\x03\x02\x01\x00".as_slice();
+        let load = |code: &[u8], config: &LoadConfig| {
+            Module::load_with(&[header, types, functions, code].concat(), config)
+        };
+
+        let truncated = b"\x0a\x03\x01\x01\x00".as_slice();
+        assert!(matches!(
+            load(truncated, &LoadConfig::default()),
+            Err(LoaderError::MismatchedBlockStack(0))
+        ));
+
+        // The block's end looks like the function's, so only decoding the body finds it.
+        let unbalanced = b"\x0a\x06\x01\x04\x00\x02\x40\x0b".as_slice();
+        let module = load(unbalanced, &LoadConfig::default()).unwrap();
+        let func_type = &module.types[0];
+        assert!(matches!(
+            crate::decode::decode_function(module.code(0), &module.types, func_type),
+            Err(DecodeError::UnclosedScopes(1))
+        ));
+        let capped = LoadConfig {
+            max_function_ops: Some(100),
+            ..LoadConfig::default()
+        };
+        assert!(matches!(
+            load(unbalanced, &capped),
+            Err(LoaderError::MismatchedBlockStack(0))
+        ));
+    }
+
+    #[test]
+    fn dangling_function_types_rejected() {
+        let header = b"\0asm\x01\x00\x00\x00".as_slice();
+        let types = b"\x01\x04\x01\x60\x00\x00".as_slice();
+        let code = b"\x0a\x04\x01\x02\x00\x0b".as_slice();
+        let load = |sections: &[&[u8]]| Module::load(&[&[header], sections].concat().concat());
+
+        assert!(matches!(
+            load(&[types, b"\x03\x02\x01\x05", code]),
+            Err(LoaderError::UnknownType(5))
+        ));
+        assert!(matches!(
+            load(&[types, b"\x02\x07\x01\x01m\x01f\x00\x01"]),
+            Err(LoaderError::UnknownType(1))
+        ));
+        // Element segment flags only go up to 7.
+        assert!(matches!(
+            load(&[b"\x09\x02\x01\x08"]),
+            Err(LoaderError::DecoderError(_))
+        ));
+
+        // Instantiation doesn't count on the checks, as the fields can be changed after loading.
+        let mut module = load(&[types, b"\x03\x02\x01\x00", code]).unwrap();
+        module.functions[0] = 5;
+        assert!(matches!(
+            crate::instance::mk_instance(module),
+            Err(crate::LinkError::InvalidModule(_))
+        ));
+    }
+
+    #[test]
+    fn duplicate_exports_rejected() {
+        let wat = r#"(module (func) (memory 1)
+            (export "h\u{e9}llo" (func 0)) (export "he\u{301}llo" (memory 0)) (export "" (func 0)))"#;
+        let mut module = Module::load(&wat::parse_str(wat).unwrap()).unwrap();
+        // Names are compared byte for byte, so these two spellings are different names.
+        assert_eq!(
+            module.export("h\u{e9}llo").unwrap().kind,
+            ImportExportKind::Function
+        );
+        assert_eq!(
+            module.export("he\u{301}llo").unwrap().kind,
+            ImportExportKind::Memory
+        );
+        assert!(module.export("").is_some());
+        assert!(module.export("HELLO").is_none());
+
+        assert!(!module.add_export("", ImportExportKind::Memory, 0));
+        assert!(module.remove_export("h\u{e9}llo"));
+        assert!(module.rename_export("", "f"));
+        assert_eq!(module.export("he\u{301}llo").unwrap().index, 0);
+        assert_eq!(module.export("f").unwrap().kind, ImportExportKind::Function);
+        assert!(module.export("").is_none());
+
+        let wat = r#"(module (func) (memory 1) (export "a" (func 0)) (export "a" (memory 0)))"#;
+        let result = Module::load(&wat::parse_str(wat).unwrap());
+        assert!(matches!(result, Err(LoaderError::DuplicateExport(name)) if name == "a"));
+    }
+
     #[test]
     fn verify_section_loading_table() {
         let mod_data = include_bytes!("../../tests/table.wasm").to_vec();
@@ -573,6 +1056,7 @@ mod tests {
             vec![Table {
                 ty: ReferenceType::FuncRef,
                 limits: (32, None),
+                init: None,
             }]
         );
 
@@ -608,6 +1092,16 @@ mod tests {
             vec![0, 0, 0] // 0 is the index into the types array
         );
 
+        // Function indices count the one imported function first.
+        assert_eq!(program.num_imported_funcs(), 1);
+        assert!(program.is_imported_func(0));
+        assert!(!program.is_imported_func(1));
+        assert_eq!(program.defined_func_index(0), None);
+        assert_eq!(program.defined_func_index(3), Some(2));
+        assert_eq!(program.defined_func_index(4), None);
+        assert_eq!(program.func_type_of(3), Some(&program.types[0]));
+        assert_eq!(program.func_type_of(4), None);
+
         // Verify code offsets
         assert_eq!(program.code[0].locals, vec![]);
         assert_eq!(program.code[1].locals, vec![]);
@@ -666,7 +1160,13 @@ mod tests {
         assert_eq!(program.functions, vec![1]);
 
         // Verify the memories
-        assert_eq!(program.memories, vec![MemorySection { limits: (1, None) }]);
+        assert_eq!(
+            program.memories,
+            vec![MemorySection {
+                limits: (1, None),
+                shared: false
+            }]
+        );
 
         // Verify the globals
         assert_eq!(
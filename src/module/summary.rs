@@ -0,0 +1,399 @@
+// Copyright (C) 2025 Ryan Daum <ryan.daum@gmail.com> This program is free
+// software: you can redistribute it and/or modify it under the terms of the GNU
+// General Public License as published by the Free Software Foundation, version
+// 3.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+use crate::decode::{decode, decode_function, ScopeType};
+use crate::module::{
+    Data, ElementMode, Elements, Import, ImportExportKind, ReferenceType, Region, SectionInfo,
+};
+use crate::op::Op;
+use crate::{DecodeError, Module, ValueType};
+use std::collections::BTreeSet;
+use std::fmt::{Display, Formatter};
+
+/// A post-MVP feature proposal a module relies on.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Proposal {
+    BulkMemory,
+    ExceptionHandling,
+    ExtendedConst,
+    FunctionReferences,
+    Gc,
+    MultiMemory,
+    MultiValue,
+    MutableGlobals,
+    NontrappingFloatToInt,
+    ReferenceTypes,
+    SignExtension,
+    Simd,
+    TailCall,
+    Threads,
+}
+
+impl Display for Proposal {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Proposal::BulkMemory => "bulk-memory",
+            Proposal::ExceptionHandling => "exception-handling",
+            Proposal::ExtendedConst => "extended-const",
+            Proposal::FunctionReferences => "function-references",
+            Proposal::Gc => "gc",
+            Proposal::MultiMemory => "multi-memory",
+            Proposal::MultiValue => "multi-value",
+            Proposal::MutableGlobals => "mutable-globals",
+            Proposal::NontrappingFloatToInt => "nontrapping-float-to-int",
+            Proposal::ReferenceTypes => "reference-types",
+            Proposal::SignExtension => "sign-extension",
+            Proposal::Simd => "simd",
+            Proposal::TailCall => "tail-call",
+            Proposal::Threads => "threads",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// Static facts about a module, gathered without instantiating it.
+#[derive(Debug, Clone)]
+pub struct ModuleSummary {
+    pub version: u32,
+    /// Every section in the binary in order, custom sections included.
+    pub sections: Vec<SectionInfo>,
+    pub num_types: usize,
+    /// Functions defined by the module, not counting imports.
+    pub num_functions: usize,
+    /// Globals defined by the module, not counting imports.
+    pub num_globals: usize,
+    pub num_data_segments: usize,
+    pub num_element_segments: usize,
+    /// Total size in bytes of all function bodies.
+    pub code_size: usize,
+    /// Min and optional max pages of each memory, imported memories first.
+    pub memories: Vec<(u32, Option<u32>)>,
+    /// Element type and min/optional max size of each table, imported tables first.
+    pub tables: Vec<(ReferenceType, (u32, Option<u32>))>,
+    /// (module, name, kind) of each import.
+    pub imports: Vec<(String, String, ImportExportKind)>,
+    /// (name, kind) of each export.
+    pub exports: Vec<(String, ImportExportKind)>,
+    /// Proposals the module uses beyond the MVP.
+    pub proposals: BTreeSet<Proposal>,
+    /// Defined functions, by function index, whose bodies we couldn't decode.
+    pub undecodable: Vec<(u32, DecodeError)>,
+}
+
+impl Module {
+    /// Summarize the module's sections, limits, imports, exports and the proposals it uses.
+    pub fn summary(&self) -> ModuleSummary {
+        let mut proposals = BTreeSet::new();
+
+        let mut memories = vec![];
+        let mut tables = vec![];
+        let mut imported_globals = vec![];
+        let imports = self
+            .imports
+            .iter()
+            .map(|(module, name, import)| {
+                let kind = match import {
+                    Import::Func(_) => ImportExportKind::Function,
+                    Import::Table(ty, limits) => {
+                        tables.push((*ty, *limits));
+                        ImportExportKind::Table
+                    }
+                    Import::Memory(limits) => {
+                        memories.push(*limits);
+                        ImportExportKind::Memory
+                    }
+                    Import::Global(_, mutable) => {
+                        imported_globals.push(*mutable);
+                        ImportExportKind::Global
+                    }
+                };
+                (module.clone(), name.clone(), kind)
+            })
+            .collect();
+        memories.extend(self.memories.iter().map(|m| m.limits));
+        tables.extend(self.tables.iter().map(|t| (t.ty, t.limits)));
+
+        if self.memories.iter().any(|m| m.shared) {
+            proposals.insert(Proposal::Threads);
+        }
+        if memories.len() > 1 {
+            proposals.insert(Proposal::MultiMemory);
+        }
+        if tables.len() > 1 || tables.iter().any(|(ty, _)| *ty != ReferenceType::FuncRef) {
+            proposals.insert(Proposal::ReferenceTypes);
+        }
+        if self.tables.iter().any(|t| t.init.is_some()) {
+            proposals.insert(Proposal::FunctionReferences);
+        }
+
+        // Mutable globals crossing the module boundary, in either direction.
+        let exports_mutable_global = self.exports.iter().any(|e| {
+            let index = e.index as usize;
+            e.kind == ImportExportKind::Global
+                && match imported_globals.get(index) {
+                    Some(mutable) => *mutable,
+                    None => self
+                        .globals
+                        .get(index - imported_globals.len())
+                        .is_some_and(|g| g.mutable),
+                }
+        });
+        if exports_mutable_global || imported_globals.contains(&true) {
+            proposals.insert(Proposal::MutableGlobals);
+        }
+
+        for ty in &self.types {
+            if ty.results.len() > 1 {
+                proposals.insert(Proposal::MultiValue);
+            }
+            if ty
+                .params
+                .iter()
+                .chain(&ty.results)
+                .any(|t| *t == ValueType::V128)
+            {
+                proposals.insert(Proposal::Simd);
+            }
+        }
+        #[cfg(feature = "gc")]
+        if self
+            .sub_types
+            .iter()
+            .any(|t| !matches!(t.composite, crate::gc::CompositeType::Func(_)))
+        {
+            proposals.insert(Proposal::Gc);
+        }
+        if self
+            .code
+            .iter()
+            .any(|c| c.locals.contains(&ValueType::V128))
+            || self.globals.iter().any(|g| g.ty == ValueType::V128)
+        {
+            proposals.insert(Proposal::Simd);
+        }
+
+        // Constant expressions: global initializers and active segment offsets.
+        let mut const_exprs: Vec<&Region> = self.globals.iter().map(|g| &g.expr).collect();
+        const_exprs.extend(self.tables.iter().filter_map(|t| t.init.as_ref()));
+        for data in &self.data {
+            match data {
+                Data::Active { expr, .. } => const_exprs.push(expr),
+                Data::Passive { .. } => {
+                    proposals.insert(Proposal::BulkMemory);
+                }
+                Data::ActiveMemIdx { memidx, expr, .. } => {
+                    if *memidx != 0 {
+                        proposals.insert(Proposal::MultiMemory);
+                    }
+                    const_exprs.push(expr);
+                }
+            }
+        }
+        for segment in &self.element_segments {
+            match &segment.mode {
+                ElementMode::Passive => {
+                    proposals.insert(Proposal::BulkMemory);
+                }
+                ElementMode::Declarative => {
+                    proposals.insert(Proposal::ReferenceTypes);
+                }
+                ElementMode::Active { table_index, expr } => {
+                    if *table_index != 0 {
+                        proposals.insert(Proposal::ReferenceTypes);
+                    }
+                    const_exprs.push(expr);
+                }
+            }
+            if let Elements::Expression(exprs) = &segment.elements {
+                proposals.insert(Proposal::ReferenceTypes);
+                const_exprs.extend(exprs);
+            }
+        }
+        for expr in const_exprs {
+            if let Ok(program) = self.get_expr(expr).and_then(decode) {
+                for op in &program.ops {
+                    // Arithmetic is only allowed in constant expressions under extended-const.
+                    if matches!(
+                        op,
+                        Op::I32Add | Op::I32Sub | Op::I32Mul | Op::I64Add | Op::I64Sub | Op::I64Mul
+                    ) {
+                        proposals.insert(Proposal::ExtendedConst);
+                    }
+                    note_op(op, &mut proposals);
+                }
+            }
+        }
+
+        let mut undecodable = vec![];
+        let num_imported_funcs = self.num_imported_funcs();
+        for (i, typeidx) in self.functions.iter().enumerate() {
+            let funcidx = (num_imported_funcs + i) as u32;
+            let Some(func_type) = self.types.get(*typeidx) else {
+                undecodable.push((funcidx, DecodeError::InvalidSignature(*typeidx as u32)));
+                continue;
+            };
+            let Some(body) = self.body(i) else {
+                let reason = format!("no body for function {funcidx}");
+                undecodable.push((funcidx, DecodeError::FailedToDecode(reason)));
+                continue;
+            };
+            match decode_function(body, &self.types, func_type) {
+                Ok(program) => {
+                    for op in &program.ops {
+                        note_op(op, &mut proposals);
+                    }
+                }
+                Err(e) => {
+                    undecodable.push((funcidx, e));
+                }
+            }
+        }
+
+        // Whatever stopped those from decoding, and anything else in them we don't support.
+        if !undecodable.is_empty() {
+            proposals.extend(self.check_support().iter().map(|u| u.proposal));
+        }
+
+        ModuleSummary {
+            version: self.version,
+            sections: self.sections.clone(),
+            num_types: self.types.len(),
+            num_functions: self.functions.len(),
+            num_globals: self.globals.len(),
+            num_data_segments: self.data.len(),
+            num_element_segments: self.element_segments.len(),
+            code_size: self.code.iter().map(|c| c.code.1 - c.code.0).sum(),
+            memories,
+            tables,
+            imports,
+            exports: self
+                .exports
+                .iter()
+                .map(|e| (e.name.clone(), e.kind))
+                .collect(),
+            proposals,
+            undecodable,
+        }
+    }
+}
+
+fn note_op(op: &Op, proposals: &mut BTreeSet<Proposal>) {
+    let proposal = match op {
+        Op::StartScope(sig, scope_type) if *scope_type != ScopeType::Function && sig.params > 0 => {
+            Proposal::MultiValue
+        }
+        Op::CallIndirect(_, table_idx) if *table_idx != 0 => Proposal::ReferenceTypes,
+        Op::I32TruncSatF32S
+        | Op::I32TruncSatF32U
+        | Op::I32TruncSatF64S
+        | Op::I32TruncSatF64U
+        | Op::I64TruncSatF32S
+        | Op::I64TruncSatF32U
+        | Op::I64TruncSatF64S
+        | Op::I64TruncSatF64U => Proposal::NontrappingFloatToInt,
+        Op::I32Extend8S
+        | Op::I32Extend16S
+        | Op::I64Extend8S
+        | Op::I64Extend16S
+        | Op::I64Extend32S => Proposal::SignExtension,
+        Op::RefNull(_)
+        | Op::RefFunc(_)
+        | Op::RefIsNull
+        | Op::SelectT(_)
+        | Op::TableGet(_)
+        | Op::TableSet(_) => Proposal::ReferenceTypes,
+        Op::RefAsNonNull => Proposal::FunctionReferences,
+        Op::RefEq => Proposal::Gc,
+        #[cfg(feature = "gc")]
+        Op::Gc(_) => Proposal::Gc,
+        #[cfg(feature = "atomics")]
+        Op::Atomic(_) => Proposal::Threads,
+        _ => return,
+    };
+    proposals.insert(proposal);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::module::{ImportExportKind, Module, Proposal, ReferenceType};
+    use crate::DecodeError;
+
+    #[test]
+    fn summarize_module() {
+        let wat = r#"(module
+            (import "env" "log" (func (param i32)))
+            (memory 1 2)
+            (table 3 funcref)
+            (global $g (export "counter") (mut i32) (i32.const 0))
+            (func (export "pair") (param i32) (result i32 i32)
+                (i32.extend8_s (local.get 0))
+                (local.get 0))
+            (func (export "sat") (param f32) (result i32)
+                (i32.trunc_sat_f32_s (local.get 0))))"#;
+        let module = Module::load(&wat::parse_str(wat).unwrap()).unwrap();
+        let summary = module.summary();
+
+        assert_eq!(summary.version, 1);
+        assert_eq!(summary.num_functions, 2);
+        assert_eq!(summary.num_globals, 1);
+        assert_eq!(summary.memories, vec![(1, Some(2))]);
+        assert_eq!(summary.tables, vec![(ReferenceType::FuncRef, (3, None))]);
+        assert_eq!(
+            summary.imports,
+            vec![(
+                "env".to_string(),
+                "log".to_string(),
+                ImportExportKind::Function
+            )]
+        );
+        assert_eq!(summary.exports.len(), 3);
+        assert!(summary.undecodable.is_empty());
+        assert_eq!(
+            summary.proposals.iter().copied().collect::<Vec<_>>(),
+            vec![
+                Proposal::MultiValue,
+                Proposal::MutableGlobals,
+                Proposal::NontrappingFloatToInt,
+                Proposal::SignExtension,
+            ]
+        );
+
+        // Sections are listed in order, and the code section covers both bodies. `$g` gets wat
+        // to add a name section, which is custom and trails the rest.
+        let ids: Vec<u8> = summary.sections.iter().map(|s| s.id).collect();
+        assert_eq!(ids.last(), Some(&0));
+        let known = &ids[..ids.len() - 1];
+        assert!(known.windows(2).all(|w| w[0] < w[1]));
+        let code = summary.sections.iter().find(|s| s.id == 10).unwrap();
+        assert!(code.size > summary.code_size);
+        let last = summary.sections.last().unwrap();
+        assert_eq!(last.offset + last.size, module.module_data.len());
+    }
+
+    #[test]
+    fn summarize_functions_without_type_or_body() {
+        let wat = r#"(module (func (export "f")))"#;
+        let mut module = Module::load(&wat::parse_str(wat).unwrap()).unwrap();
+        module.functions[0] = 5;
+        module.functions.push(0);
+        let summary = module.summary();
+
+        assert_eq!(summary.num_functions, 2);
+        let funcidxs: Vec<u32> = summary.undecodable.iter().map(|(f, _)| *f).collect();
+        assert_eq!(funcidxs, vec![0, 1]);
+        assert!(matches!(
+            summary.undecodable[0].1,
+            DecodeError::InvalidSignature(5)
+        ));
+    }
+}
@@ -0,0 +1,366 @@
+// Copyright (C) 2025 Ryan Daum <ryan.daum@gmail.com> This program is free
+// software: you can redistribute it and/or modify it under the terms of the GNU
+// General Public License as published by the Free Software Foundation, version
+// 3.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+use crate::module::{LEB128Reader, Proposal};
+use crate::opcode::{AtomicOpCode, GcOpCode, MiscOpCode, OpCode, SimdOpCode};
+use crate::{DecodeError, Module};
+use std::fmt::{Display, Formatter};
+
+/// An instruction in a function body that we can't decode or run, found by
+/// [`Module::check_support`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnsupportedFeature {
+    /// Index of the function, counting imported functions.
+    pub func_index: u32,
+    /// Offset of the instruction from the start of the module binary.
+    pub offset: usize,
+    pub opcode: u8,
+    /// The sub-opcode, for instructions behind a prefix byte.
+    pub sub_opcode: Option<u32>,
+    /// The proposal the instruction comes from.
+    pub proposal: Proposal,
+}
+
+impl Display for UnsupportedFeature {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "function {} at {:#x}: opcode {:#04x}",
+            self.func_index, self.offset, self.opcode
+        )?;
+        if let Some(sub_opcode) = self.sub_opcode {
+            write!(f, " {sub_opcode}")?;
+        }
+        write!(f, " needs {}", self.proposal)
+    }
+}
+
+impl Module {
+    /// Scan every function body and report each instruction we don't support, rather than
+    /// stopping at the first one like decoding does. A body that turns out to be malformed is
+    /// scanned only up to the malformed instruction.
+    pub fn check_support(&self) -> Vec<UnsupportedFeature> {
+        let num_imported_funcs = self.num_imported_funcs();
+        let mut unsupported = vec![];
+        for (i, code) in self.code.iter().enumerate() {
+            let func_index = (num_imported_funcs + i) as u32;
+            let mut reader = LEB128Reader::new(self.code(i), 0);
+            while reader.remaining() > 0 {
+                let offset = code.code.0 + reader.position();
+                let Ok(opcode) = reader.load_imm_u8() else {
+                    break;
+                };
+                match scan_instruction(opcode, &mut reader) {
+                    Ok(None) => {}
+                    Ok(Some((sub_opcode, proposal))) => unsupported.push(UnsupportedFeature {
+                        func_index,
+                        offset,
+                        opcode,
+                        sub_opcode,
+                        proposal,
+                    }),
+                    Err(_) => break,
+                }
+            }
+        }
+        unsupported
+    }
+}
+
+type Unsupported = Option<(Option<u32>, Proposal)>;
+
+/// Step over one instruction's immediates, saying which proposal it needs if we don't support
+/// it. This has to agree with `decode_function` about what's supported, except that throws are
+/// reported even though they decode, as they fault if they're reached.
+fn scan_instruction(opcode_o: u8, reader: &mut LEB128Reader) -> Result<Unsupported, DecodeError> {
+    let opcode = OpCode::from_repr(opcode_o).ok_or(DecodeError::InvalidOpcode(opcode_o))?;
+    let unsupported = |proposal| Ok(Some((None, proposal)));
+    match opcode {
+        OpCode::Block | OpCode::Loop | OpCode::If => skip_block_type(reader)?,
+        OpCode::Try => {
+            skip_block_type(reader)?;
+            return unsupported(Proposal::ExceptionHandling);
+        }
+        OpCode::TryTable => {
+            skip_block_type(reader)?;
+            for _ in 0..reader.load_imm_varuint32()? {
+                // catch and catch_ref name a tag, catch_all and catch_all_ref don't.
+                if reader.load_imm_u8()? < 2 {
+                    reader.load_imm_varuint32()?;
+                }
+                reader.load_imm_varuint32()?;
+            }
+        }
+        OpCode::Catch | OpCode::Throw | OpCode::Rethrow | OpCode::Delegate => {
+            reader.load_imm_varuint32()?;
+            return unsupported(Proposal::ExceptionHandling);
+        }
+        OpCode::CatchAll | OpCode::ThrowRef => return unsupported(Proposal::ExceptionHandling),
+        OpCode::ReturnCall | OpCode::ReturnCallRef => {
+            reader.load_imm_varuint32()?;
+            return unsupported(Proposal::TailCall);
+        }
+        OpCode::ReturnCallIndirect => {
+            reader.load_imm_varuint32()?;
+            reader.load_imm_varuint32()?;
+            return unsupported(Proposal::TailCall);
+        }
+        OpCode::CallRef | OpCode::BrOnNull | OpCode::BrOnNonNull => {
+            reader.load_imm_varuint32()?;
+            return unsupported(Proposal::FunctionReferences);
+        }
+        OpCode::Br
+        | OpCode::BrIf
+        | OpCode::Call
+        | OpCode::GetLocal
+        | OpCode::SetLocal
+        | OpCode::Tee
+        | OpCode::GetGlobal
+        | OpCode::SetGlobal
+        | OpCode::TableGet
+        | OpCode::TableSet
+        | OpCode::RefFunc => {
+            reader.load_imm_varuint32()?;
+        }
+        OpCode::BrTable => {
+            reader.load_array_varu32()?;
+            reader.load_imm_varuint32()?;
+        }
+        OpCode::CallIndirect => {
+            reader.load_imm_varuint32()?;
+            reader.load_imm_varuint32()?;
+        }
+        OpCode::SelectT => {
+            for _ in 0..reader.load_imm_varuint32()? {
+                skip_value_type(reader)?;
+            }
+        }
+        OpCode::RefNull => skip_heap_type(reader)?,
+        OpCode::CurrentMemorySize | OpCode::GrowMemory => {
+            let memidx = reader.load_imm_varuint32()?;
+            return Ok((memidx != 0).then_some((None, Proposal::MultiMemory)));
+        }
+        OpCode::I32Const => {
+            reader.load_imm_signed_varint32()?;
+        }
+        OpCode::I64Const => {
+            reader.load_imm_signed_varint64()?;
+        }
+        OpCode::F32Const => {
+            reader.load_imm_f32()?;
+        }
+        OpCode::F64Const => skip_bytes(reader, 8)?,
+        OpCode::FCExtension => return scan_fc(reader),
+        OpCode::SIMDExtension => return scan_simd(reader),
+        OpCode::GCExtension => {
+            let sub_opcode = scan_gc(reader)?;
+            if !cfg!(feature = "gc") {
+                return Ok(Some((Some(sub_opcode), Proposal::Gc)));
+            }
+        }
+        OpCode::ThreadsExtension => {
+            let sub_opcode = scan_threads(reader)?;
+            if !cfg!(feature = "atomics") {
+                return Ok(Some((Some(sub_opcode), Proposal::Threads)));
+            }
+        }
+        _ if (OpCode::LoadI32 as u8..=OpCode::Store32_64 as u8).contains(&opcode_o) => {
+            return Ok(skip_memarg(reader)?.then_some((None, Proposal::MultiMemory)));
+        }
+        _ => {}
+    }
+    Ok(None)
+}
+
+/// Step over a memarg, returning whether it names a memory other than the default one.
+fn skip_memarg(reader: &mut LEB128Reader) -> Result<bool, DecodeError> {
+    let align = reader.load_imm_varuint32()?;
+    let other_memory = align & 0x40 != 0 && reader.load_imm_varuint32()? != 0;
+    reader.load_imm_varuint64()?;
+    Ok(other_memory)
+}
+
+fn skip_bytes(reader: &mut LEB128Reader, n: usize) -> Result<(), DecodeError> {
+    for _ in 0..n {
+        reader.load_imm_u8()?;
+    }
+    Ok(())
+}
+
+fn skip_heap_type(reader: &mut LEB128Reader) -> Result<(), DecodeError> {
+    reader.load_imm_signed_varint64()?;
+    Ok(())
+}
+
+fn skip_value_type(reader: &mut LEB128Reader) -> Result<(), DecodeError> {
+    // (ref null ht) and (ref ht) are followed by their heap type.
+    if matches!(reader.load_imm_u8()?, 0x63 | 0x64) {
+        skip_heap_type(reader)?;
+    }
+    Ok(())
+}
+
+fn skip_block_type(reader: &mut LEB128Reader) -> Result<(), DecodeError> {
+    let first = reader.load_imm_u8()?;
+    if matches!(first, 0x63 | 0x64) {
+        return skip_heap_type(reader);
+    }
+    // Otherwise a single byte value type, or a type index as a signed LEB.
+    let mut byte = first;
+    while byte & 0x80 != 0 {
+        byte = reader.load_imm_u8()?;
+    }
+    Ok(())
+}
+
+fn scan_fc(reader: &mut LEB128Reader) -> Result<Unsupported, DecodeError> {
+    let code = MiscOpCode::read(reader)?;
+    let sub_opcode = code as u32;
+    let (immediates, proposal) = match code {
+        MiscOpCode::MemoryInit
+        | MiscOpCode::MemoryCopy
+        | MiscOpCode::TableInit
+        | MiscOpCode::TableCopy => (2, Proposal::BulkMemory),
+        MiscOpCode::DataDrop | MiscOpCode::MemoryFill | MiscOpCode::ElemDrop => {
+            (1, Proposal::BulkMemory)
+        }
+        MiscOpCode::TableGrow | MiscOpCode::TableSize | MiscOpCode::TableFill => {
+            (1, Proposal::ReferenceTypes)
+        }
+        // The saturating truncations, which we do support.
+        _ => return Ok(None),
+    };
+    for _ in 0..immediates {
+        reader.load_imm_varuint32()?;
+    }
+    Ok(Some((Some(sub_opcode), proposal)))
+}
+
+fn scan_simd(reader: &mut LEB128Reader) -> Result<Unsupported, DecodeError> {
+    let sub_opcode = SimdOpCode::read(reader)? as u32;
+    match sub_opcode {
+        // Loads and v128.store, and the zero-extending loads
+        0..=11 | 92 | 93 => {
+            skip_memarg(reader)?;
+        }
+        // v128.const, i8x16.shuffle
+        12 | 13 => skip_bytes(reader, 16)?,
+        // Lane extracts and replaces
+        21..=34 => skip_bytes(reader, 1)?,
+        // Lane loads and stores
+        84..=91 => {
+            skip_memarg(reader)?;
+            skip_bytes(reader, 1)?;
+        }
+        _ => {}
+    }
+    Ok(Some((Some(sub_opcode), Proposal::Simd)))
+}
+
+/// Step over a GC instruction, returning its sub-opcode.
+fn scan_gc(reader: &mut LEB128Reader) -> Result<u32, DecodeError> {
+    let sub_opcode = GcOpCode::read(reader)? as u32;
+    let immediates = match sub_opcode {
+        0 | 1 | 6 | 7 | 11..=14 | 16 => 1,
+        2..=5 | 8..=10 | 17..=19 => 2,
+        20..=23 => {
+            skip_heap_type(reader)?;
+            0
+        }
+        // br_on_cast and br_on_cast_fail: flags, label, and two heap types.
+        24 | 25 => {
+            reader.load_imm_u8()?;
+            reader.load_imm_varuint32()?;
+            skip_heap_type(reader)?;
+            skip_heap_type(reader)?;
+            0
+        }
+        // array.len, the extern conversions and the i31 ops.
+        _ => 0,
+    };
+    for _ in 0..immediates {
+        reader.load_imm_varuint32()?;
+    }
+    Ok(sub_opcode)
+}
+
+/// Step over an atomic instruction, returning its sub-opcode.
+fn scan_threads(reader: &mut LEB128Reader) -> Result<u32, DecodeError> {
+    let code = AtomicOpCode::read(reader)?;
+    match code {
+        AtomicOpCode::AtomicFence => skip_bytes(reader, 1)?,
+        _ => {
+            skip_memarg(reader)?;
+        }
+    }
+    Ok(code as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::module::{Module, Proposal, UnsupportedFeature};
+
+    #[test]
+    fn reports_every_unsupported_instruction() {
+        #[rustfmt::skip]
+        let module_data = [
+            0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+            // Type section: () -> ()
+            0x01, 0x04, 0x01, 0x60, 0x00, 0x00,
+            // Function section
+            0x03, 0x02, 0x01, 0x00,
+            // Code section, one body with no locals
+            0x0a, 0x22, 0x01, 0x20, 0x00,
+            // i32.const 0 (x3); memory.fill 0
+            0x41, 0x00, 0x41, 0x00, 0x41, 0x00, 0xfc, 0x0b, 0x00,
+            // v128.const 0; drop
+            0xfd, 0x0c, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x1a,
+            // return_call 0; end
+            0x12, 0x00, 0x0b,
+        ];
+        let module = Module::load(&module_data).unwrap();
+        let unsupported = |offset, opcode, sub_opcode, proposal| UnsupportedFeature {
+            func_index: 0,
+            offset,
+            opcode,
+            sub_opcode,
+            proposal,
+        };
+        assert_eq!(
+            module.check_support(),
+            vec![
+                unsupported(29, 0xfc, Some(11), Proposal::BulkMemory),
+                unsupported(32, 0xfd, Some(12), Proposal::Simd),
+                unsupported(51, 0x12, None, Proposal::TailCall),
+            ]
+        );
+
+        let summary = module.summary();
+        assert_eq!(summary.undecodable.len(), 1);
+        for proposal in [Proposal::BulkMemory, Proposal::Simd, Proposal::TailCall] {
+            assert!(summary.proposals.contains(&proposal));
+        }
+    }
+
+    #[test]
+    fn supported_module_has_nothing_to_report() {
+        let wat = r#"(module
+            (memory 1)
+            (func (param i32) (result i32)
+                (block (result i32)
+                    (i64.store offset=8 (local.get 0) (i64.const -1))
+                    (br_table 0 0 (i32.load (local.get 0)) (local.get 0)))))"#;
+        let module = Module::load(&wat::parse_str(wat).unwrap()).unwrap();
+        assert!(module.check_support().is_empty());
+    }
+}
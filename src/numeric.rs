@@ -0,0 +1,1030 @@
+// Copyright (C) 2025 Ryan Daum <ryan.daum@gmail.com> This program is free
+// software: you can redistribute it and/or modify it under the terms of the GNU
+// General Public License as published by the Free Software Foundation, version
+// 3.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+//! The numeric ops, which only ever work on the operand stack, in a handler per category. Kept
+//! out of `execute` so that its dispatch loop stays small enough for the compiler to keep its
+//! hot state in registers; the `monolithic-dispatch` feature folds them back in for comparison.
+
+use crate::exec::Fault;
+use crate::op::Op;
+use crate::stack::{SlotStorage, Stack};
+
+/// WASM `fmin`: NaN if either operand is NaN, and -0.0 is less than +0.0. Rust's `min` returns
+/// the non-NaN operand, and either zero, instead.
+fn f32_min(a: f32, b: f32) -> f32 {
+    if a.is_nan() || b.is_nan() {
+        // Quieted, and canonical if the input was.
+        return a + b;
+    }
+    if a == b {
+        // Equal, so either both the same value, or zeroes of which the negative wins.
+        return f32::from_bits(a.to_bits() | b.to_bits());
+    }
+    if a < b {
+        a
+    } else {
+        b
+    }
+}
+
+/// WASM `fmax`, as `f32_min` but +0.0 wins over -0.0.
+fn f32_max(a: f32, b: f32) -> f32 {
+    if a.is_nan() || b.is_nan() {
+        return a + b;
+    }
+    if a == b {
+        return f32::from_bits(a.to_bits() & b.to_bits());
+    }
+    if a > b {
+        a
+    } else {
+        b
+    }
+}
+
+fn f64_min(a: f64, b: f64) -> f64 {
+    if a.is_nan() || b.is_nan() {
+        return a + b;
+    }
+    if a == b {
+        return f64::from_bits(a.to_bits() | b.to_bits());
+    }
+    if a < b {
+        a
+    } else {
+        b
+    }
+}
+
+fn f64_max(a: f64, b: f64) -> f64 {
+    if a.is_nan() || b.is_nan() {
+        return a + b;
+    }
+    if a == b {
+        return f64::from_bits(a.to_bits() & b.to_bits());
+    }
+    if a > b {
+        a
+    } else {
+        b
+    }
+}
+
+/// Helper function for WASM float-to-signed-int truncation
+/// WASM spec: i32.trunc_f32_s traps if value is NaN, ±∞, or outside [-2^31, 2^31)
+fn trunc_f32_to_i32(value: f32) -> Result<i32, Fault> {
+    if value.is_nan() {
+        return Err(Fault::InvalidConversion);
+    }
+    if value.is_infinite() {
+        return Err(Fault::IntegerOverflow);
+    }
+    // WASM spec: Use exact bounds from wasm3 reference implementation
+    // i32.trunc_f32_s: RMIN = -2147483904.0f, RMAX = 2147483648.0f
+    if value <= -2147483904.0f32 || value >= 2147483648.0f32 {
+        return Err(Fault::IntegerOverflow);
+    }
+    Ok(value.trunc() as i32)
+}
+
+/// Helper function for WASM float-to-unsigned-int truncation
+/// WASM spec: i32.trunc_f32_u traps if value is NaN, ±∞, or outside [0, 2^32)
+fn trunc_f32_to_u32(value: f32) -> Result<u32, Fault> {
+    if value.is_nan() {
+        return Err(Fault::InvalidConversion);
+    }
+    if value.is_infinite() {
+        return Err(Fault::IntegerOverflow);
+    }
+    // WASM spec: Use exact bounds from wasm3 - i32.trunc_f32_u
+    if value <= -1.0f32 || value >= 4294967296.0f32 {
+        return Err(Fault::IntegerOverflow);
+    }
+    Ok(value.trunc() as u32)
+}
+
+/// WASM spec: i32.trunc_f64_s traps if value is NaN, ±∞, or outside [-2^31, 2^31)
+fn trunc_f64_to_i32(value: f64) -> Result<i32, Fault> {
+    if value.is_nan() {
+        return Err(Fault::InvalidConversion);
+    }
+    if value.is_infinite() {
+        return Err(Fault::IntegerOverflow);
+    }
+    // WASM spec: Use exact bounds from wasm3 - i32.trunc_f64_s
+    if value <= -2147483649.0 || value >= 2147483648.0 {
+        return Err(Fault::IntegerOverflow);
+    }
+    Ok(value.trunc() as i32)
+}
+
+/// WASM spec: i32.trunc_f64_u traps if value is NaN, ±∞, or outside [0, 2^32)
+fn trunc_f64_to_u32(value: f64) -> Result<u32, Fault> {
+    if value.is_nan() {
+        return Err(Fault::InvalidConversion);
+    }
+    if value.is_infinite() {
+        return Err(Fault::IntegerOverflow);
+    }
+    // WASM spec: Use exact bounds from wasm3 - i32.trunc_f64_u
+    if value <= -1.0 || value >= 4294967296.0 {
+        return Err(Fault::IntegerOverflow);
+    }
+    Ok(value.trunc() as u32)
+}
+
+/// WASM spec: i64.trunc_f32_s traps if value is NaN, ±∞, or outside [-2^63, 2^63)
+fn trunc_f32_to_i64(value: f32) -> Result<i64, Fault> {
+    if value.is_nan() {
+        return Err(Fault::InvalidConversion);
+    }
+    if value.is_infinite() {
+        return Err(Fault::IntegerOverflow);
+    }
+    // WASM spec: Use exact bounds from wasm3 - i64.trunc_f32_s
+    if value <= -9223373136366403584.0f32 || value >= 9223372036854775808.0f32 {
+        return Err(Fault::IntegerOverflow);
+    }
+    Ok(value.trunc() as i64)
+}
+
+/// WASM spec: i64.trunc_f32_u traps if value is NaN, ±∞, or outside [0, 2^64)
+fn trunc_f32_to_u64(value: f32) -> Result<u64, Fault> {
+    if value.is_nan() {
+        return Err(Fault::InvalidConversion);
+    }
+    if value.is_infinite() {
+        return Err(Fault::IntegerOverflow);
+    }
+    // WASM spec: Use exact bounds from wasm3 - i64.trunc_f32_u
+    if value <= -1.0f32 || value >= 18446744073709551616.0f32 {
+        return Err(Fault::IntegerOverflow);
+    }
+    Ok(value.trunc() as u64)
+}
+
+/// WASM spec: i64.trunc_f64_s traps if value is NaN, ±∞, or outside [-2^63, 2^63)
+fn trunc_f64_to_i64(value: f64) -> Result<i64, Fault> {
+    if value.is_nan() {
+        return Err(Fault::InvalidConversion);
+    }
+    if value.is_infinite() {
+        return Err(Fault::IntegerOverflow);
+    }
+    // WASM spec: Use exact bounds from wasm3 - i64.trunc_f64_s
+    if value <= -9223372036854777856.0 || value >= 9223372036854775808.0 {
+        return Err(Fault::IntegerOverflow);
+    }
+    Ok(value.trunc() as i64)
+}
+
+/// WASM spec: i64.trunc_f64_u traps if value is NaN, ±∞, or outside [0, 2^64)
+fn trunc_f64_to_u64(value: f64) -> Result<u64, Fault> {
+    if value.is_nan() {
+        return Err(Fault::InvalidConversion);
+    }
+    if value.is_infinite() {
+        return Err(Fault::IntegerOverflow);
+    }
+    // WASM spec: Use exact bounds from wasm3 - i64.trunc_f64_u
+    if value <= -1.0 || value >= 18446744073709551616.0 {
+        return Err(Fault::IntegerOverflow);
+    }
+    Ok(value.trunc() as u64)
+}
+
+/// i32 comparisons, arithmetic and bit operations.
+#[cfg_attr(feature = "monolithic-dispatch", inline(always))]
+#[cfg_attr(not(feature = "monolithic-dispatch"), inline(never))]
+pub(crate) fn i32_op<S: SlotStorage>(op: &Op, stack: &mut Stack<S>) -> Result<(), Fault> {
+    match op {
+        Op::I32Eqz => {
+            let value = stack.pop_i32()?;
+            stack.push_u32(if value == 0 { 1 } else { 0 });
+        }
+        Op::I32Eq => {
+            let b = stack.pop_i32()?;
+            let a = stack.pop_i32()?;
+            stack.push_u32(if a == b { 1 } else { 0 });
+        }
+        Op::I32Ne => {
+            let b = stack.pop_i32()?;
+            let a = stack.pop_i32()?;
+            stack.push_u32(if a != b { 1 } else { 0 });
+        }
+        Op::I32LtS => {
+            let b = stack.pop_i32()?;
+            let a = stack.pop_i32()?;
+            stack.push_u32(if a < b { 1 } else { 0 });
+        }
+        Op::I32LtU => {
+            let b = stack.pop_u32()?;
+            let a = stack.pop_u32()?;
+            stack.push_u32(if a < b { 1 } else { 0 });
+        }
+        Op::I32GtS => {
+            let b = stack.pop_i32()?;
+            let a = stack.pop_i32()?;
+            stack.push_u32(if a > b { 1 } else { 0 });
+        }
+        Op::I32GtU => {
+            let b = stack.pop_u32()?;
+            let a = stack.pop_u32()?;
+            stack.push_u32(if a > b { 1 } else { 0 });
+        }
+        Op::I32LeS => {
+            let b = stack.pop_i32()?;
+            let a = stack.pop_i32()?;
+            stack.push_u32(if a <= b { 1 } else { 0 });
+        }
+        Op::I32LeU => {
+            let b = stack.pop_u32()?;
+            let a = stack.pop_u32()?;
+            stack.push_u32(if a <= b { 1 } else { 0 });
+        }
+        Op::I32GeS => {
+            let b = stack.pop_i32()?;
+            let a = stack.pop_i32()?;
+            stack.push_u32(if a >= b { 1 } else { 0 });
+        }
+        Op::I32GeU => {
+            let b = stack.pop_u32()?;
+            let a = stack.pop_u32()?;
+            stack.push_u32(if a >= b { 1 } else { 0 });
+        }
+        Op::I32Clz => {
+            let value = stack.pop_i32()?;
+            stack.push_u32(value.leading_zeros());
+        }
+        Op::I32Ctz => {
+            let value = stack.pop_i32()?;
+            stack.push_u32(value.trailing_zeros());
+        }
+        Op::I32Popcnt => {
+            let value = stack.pop_i32()?;
+            stack.push_u32(value.count_ones());
+        }
+        Op::I32Add => {
+            let b = stack.pop_i32()?;
+            let a = stack.pop_i32()?;
+            stack.push_i32(a.wrapping_add(b));
+        }
+        Op::I32Sub => {
+            let b = stack.pop_i32()?;
+            let a = stack.pop_i32()?;
+            stack.push_i32(a.wrapping_sub(b));
+        }
+        Op::I32Mul => {
+            let b = stack.pop_i32()?;
+            let a = stack.pop_i32()?;
+            stack.push_i32(a.wrapping_mul(b));
+        }
+        Op::I32DivS => {
+            let b = stack.pop_i32()?;
+            let a = stack.pop_i32()?;
+            match a.checked_div(b) {
+                Some(result) => stack.push_i32(result),
+                None => {
+                    if b == 0 {
+                        return Err(Fault::IntegerDivisionByZero);
+                    } else {
+                        return Err(Fault::IntegerOverflow);
+                    }
+                }
+            }
+        }
+        Op::I32DivU => {
+            let b = stack.pop_u32()?;
+            let a = stack.pop_u32()?;
+            match a.checked_div(b) {
+                Some(result) => stack.push_u32(result),
+                None => return Err(Fault::IntegerDivisionByZero),
+            }
+        }
+        Op::I32RemS => {
+            let b = stack.pop_i32()?;
+            let a = stack.pop_i32()?;
+            match a.checked_rem(b) {
+                Some(result) => stack.push_i32(result),
+                None => {
+                    if b == 0 {
+                        return Err(Fault::IntegerDivisionByZero);
+                    } else {
+                        // i32::MIN % -1 = 0 by WASM spec
+                        stack.push_i32(0);
+                    }
+                }
+            }
+        }
+        Op::I32RemU => {
+            let b = stack.pop_u32()?;
+            let a = stack.pop_u32()?;
+            match a.checked_rem(b) {
+                Some(result) => stack.push_u32(result),
+                None => return Err(Fault::IntegerDivisionByZero),
+            }
+        }
+        Op::I32And => {
+            let b = stack.pop_i32()?;
+            let a = stack.pop_i32()?;
+            stack.push_i32(a & b);
+        }
+        Op::I32Or => {
+            let b = stack.pop_i32()?;
+            let a = stack.pop_i32()?;
+            stack.push_i32(a | b);
+        }
+        Op::I32Xor => {
+            let b = stack.pop_i32()?;
+            let a = stack.pop_i32()?;
+            stack.push_i32(a ^ b);
+        }
+        Op::I32Shl => {
+            let b = stack.pop_u32()?;
+            let a = stack.pop_i32()?;
+            stack.push_i32(a.wrapping_shl(b));
+        }
+        Op::I32ShrS => {
+            let b = stack.pop_u32()?;
+            let a = stack.pop_i32()?;
+            stack.push_i32(a.wrapping_shr(b));
+        }
+        Op::I32ShrU => {
+            let b = stack.pop_u32()?;
+            let a = stack.pop_u32()?;
+            stack.push_u32(a.wrapping_shr(b));
+        }
+        Op::I32Rotl => {
+            let b = stack.pop_u32()?;
+            let a = stack.pop_i32()?;
+            stack.push_i32(a.rotate_left(b));
+        }
+        Op::I32Rotr => {
+            let b = stack.pop_u32()?;
+            let a = stack.pop_i32()?;
+            stack.push_i32(a.rotate_right(b));
+        }
+        Op::I32Extend8S => {
+            let value = stack.pop_i32()?;
+            stack.push_i32(value as i8 as i32);
+        }
+        Op::I32Extend16S => {
+            let value = stack.pop_i32()?;
+            stack.push_i32(value as i16 as i32);
+        }
+        _ => unreachable!("{op:?} isn't handled here"),
+    }
+    Ok(())
+}
+
+/// i64 comparisons, arithmetic and bit operations.
+#[cfg_attr(feature = "monolithic-dispatch", inline(always))]
+#[cfg_attr(not(feature = "monolithic-dispatch"), inline(never))]
+pub(crate) fn i64_op<S: SlotStorage>(op: &Op, stack: &mut Stack<S>) -> Result<(), Fault> {
+    match op {
+        Op::I64Eqz => {
+            let value = stack.pop_i64()?;
+            stack.push_u32(if value == 0 { 1 } else { 0 });
+        }
+        Op::I64Eq => {
+            let b = stack.pop_i64()?;
+            let a = stack.pop_i64()?;
+            stack.push_u32(if a == b { 1 } else { 0 });
+        }
+        Op::I64Ne => {
+            let b = stack.pop_i64()?;
+            let a = stack.pop_i64()?;
+            stack.push_u32(if a != b { 1 } else { 0 });
+        }
+        Op::I64LtS => {
+            let b = stack.pop_i64()?;
+            let a = stack.pop_i64()?;
+            stack.push_u32(if a < b { 1 } else { 0 });
+        }
+        Op::I64LtU => {
+            let b = stack.pop_u64()?;
+            let a = stack.pop_u64()?;
+            stack.push_u32(if a < b { 1 } else { 0 });
+        }
+        Op::I64GtS => {
+            let b = stack.pop_i64()?;
+            let a = stack.pop_i64()?;
+            stack.push_u32(if a > b { 1 } else { 0 });
+        }
+        Op::I64GtU => {
+            let b = stack.pop_u64()?;
+            let a = stack.pop_u64()?;
+            stack.push_u32(if a > b { 1 } else { 0 });
+        }
+        Op::I64LeS => {
+            let b = stack.pop_i64()?;
+            let a = stack.pop_i64()?;
+            stack.push_u32(if a <= b { 1 } else { 0 });
+        }
+        Op::I64LeU => {
+            let b = stack.pop_u64()?;
+            let a = stack.pop_u64()?;
+            stack.push_u32(if a <= b { 1 } else { 0 });
+        }
+        Op::I64GeS => {
+            let b = stack.pop_i64()?;
+            let a = stack.pop_i64()?;
+            stack.push_u32(if a >= b { 1 } else { 0 });
+        }
+        Op::I64GeU => {
+            let b = stack.pop_u64()?;
+            let a = stack.pop_u64()?;
+            stack.push_u32(if a >= b { 1 } else { 0 });
+        }
+        Op::I64Clz => {
+            let value = stack.pop_i64()?;
+            stack.push_i64(value.leading_zeros() as i64);
+        }
+        Op::I64Ctz => {
+            let value = stack.pop_i64()?;
+            stack.push_i64(value.trailing_zeros() as i64);
+        }
+        Op::I64Popcnt => {
+            let value = stack.pop_i64()?;
+            stack.push_i64(value.count_ones() as i64);
+        }
+        Op::I64Add => {
+            let b = stack.pop_i64()?;
+            let a = stack.pop_i64()?;
+            stack.push_i64(a.wrapping_add(b));
+        }
+        Op::I64Sub => {
+            let b = stack.pop_i64()?;
+            let a = stack.pop_i64()?;
+            stack.push_i64(a.wrapping_sub(b));
+        }
+        Op::I64Mul => {
+            let b = stack.pop_i64()?;
+            let a = stack.pop_i64()?;
+            stack.push_i64(a.wrapping_mul(b));
+        }
+        Op::I64DivS => {
+            let b = stack.pop_i64()?;
+            let a = stack.pop_i64()?;
+            match a.checked_div(b) {
+                Some(result) => stack.push_i64(result),
+                None => {
+                    if b == 0 {
+                        return Err(Fault::IntegerDivisionByZero);
+                    } else {
+                        return Err(Fault::IntegerOverflow);
+                    }
+                }
+            }
+        }
+        Op::I64DivU => {
+            let b = stack.pop_u64()?;
+            let a = stack.pop_u64()?;
+            match a.checked_div(b) {
+                Some(result) => stack.push_u64(result),
+                None => return Err(Fault::IntegerDivisionByZero),
+            }
+        }
+        Op::I64RemS => {
+            let b = stack.pop_i64()?;
+            let a = stack.pop_i64()?;
+            match a.checked_rem(b) {
+                Some(result) => stack.push_i64(result),
+                None => {
+                    if b == 0 {
+                        return Err(Fault::IntegerDivisionByZero);
+                    } else {
+                        // i64::MIN % -1 = 0 by WASM spec
+                        stack.push_i64(0);
+                    }
+                }
+            }
+        }
+        Op::I64RemU => {
+            let b = stack.pop_u64()?;
+            let a = stack.pop_u64()?;
+            match a.checked_rem(b) {
+                Some(result) => stack.push_u64(result),
+                None => return Err(Fault::IntegerDivisionByZero),
+            }
+        }
+        Op::I64And => {
+            let b = stack.pop_i64()?;
+            let a = stack.pop_i64()?;
+            stack.push_i64(a & b);
+        }
+        Op::I64Or => {
+            let b = stack.pop_i64()?;
+            let a = stack.pop_i64()?;
+            stack.push_i64(a | b);
+        }
+        Op::I64Xor => {
+            let b = stack.pop_i64()?;
+            let a = stack.pop_i64()?;
+            stack.push_i64(a ^ b);
+        }
+        Op::I64Shl => {
+            let b = stack.pop_u64()?;
+            let a = stack.pop_i64()?;
+            stack.push_i64(a.wrapping_shl(b as u32));
+        }
+        Op::I64ShrS => {
+            let b = stack.pop_u64()?;
+            let a = stack.pop_i64()?;
+            stack.push_i64(a.wrapping_shr(b as u32));
+        }
+        Op::I64ShrU => {
+            let b = stack.pop_u64()?;
+            let a = stack.pop_u64()?;
+            stack.push_u64(a.wrapping_shr(b as u32));
+        }
+        Op::I64Rotl => {
+            let b = stack.pop_u64()?;
+            let a = stack.pop_i64()?;
+            stack.push_i64(a.rotate_left(b as u32));
+        }
+        Op::I64Rotr => {
+            let b = stack.pop_u64()?;
+            let a = stack.pop_i64()?;
+            stack.push_i64(a.rotate_right(b as u32));
+        }
+        Op::I64Extend8S => {
+            let value = stack.pop_i64()?;
+            stack.push_i64(value as i8 as i64);
+        }
+        Op::I64Extend16S => {
+            let value = stack.pop_i64()?;
+            stack.push_i64(value as i16 as i64);
+        }
+        Op::I64Extend32S => {
+            let value = stack.pop_i64()?;
+            stack.push_i64(value as i32 as i64);
+        }
+        _ => unreachable!("{op:?} isn't handled here"),
+    }
+    Ok(())
+}
+
+/// f32 comparisons and arithmetic.
+#[cfg_attr(feature = "monolithic-dispatch", inline(always))]
+#[cfg_attr(not(feature = "monolithic-dispatch"), inline(never))]
+pub(crate) fn f32_op<S: SlotStorage>(op: &Op, stack: &mut Stack<S>) -> Result<(), Fault> {
+    match op {
+        Op::F32Eq => {
+            let b = stack.pop_f32()?;
+            let a = stack.pop_f32()?;
+            stack.push_u32(if a == b { 1 } else { 0 });
+        }
+        Op::F32Ne => {
+            let b = stack.pop_f32()?;
+            let a = stack.pop_f32()?;
+            stack.push_u32(if a != b { 1 } else { 0 });
+        }
+        Op::F32Lt => {
+            let b = stack.pop_f32()?;
+            let a = stack.pop_f32()?;
+            stack.push_u32(if a < b { 1 } else { 0 });
+        }
+        Op::F32Gt => {
+            let b = stack.pop_f32()?;
+            let a = stack.pop_f32()?;
+            stack.push_u32(if a > b { 1 } else { 0 });
+        }
+        Op::F32Le => {
+            let b = stack.pop_f32()?;
+            let a = stack.pop_f32()?;
+            stack.push_u32(if a <= b { 1 } else { 0 });
+        }
+        Op::F32Ge => {
+            let b = stack.pop_f32()?;
+            let a = stack.pop_f32()?;
+            stack.push_u32(if a >= b { 1 } else { 0 });
+        }
+        Op::F32Abs => {
+            let value = stack.pop_f32()?;
+            stack.push_f32(value.abs());
+        }
+        Op::F32Neg => {
+            let value = stack.pop_f32()?;
+            stack.push_f32(-value);
+        }
+        Op::F32Ceil => {
+            let value = stack.pop_f32()?;
+            stack.push_f32(value.ceil());
+        }
+        Op::F32Floor => {
+            let value = stack.pop_f32()?;
+            stack.push_f32(value.floor());
+        }
+        Op::F32Trunc => {
+            let value = stack.pop_f32()?;
+            stack.push_f32(value.trunc());
+        }
+        Op::F32Nearest => {
+            let value = stack.pop_f32()?;
+            stack.push_f32(value.round_ties_even());
+        }
+        Op::F32Sqrt => {
+            let value = stack.pop_f32()?;
+            stack.push_f32(value.sqrt());
+        }
+        Op::F32Add => {
+            let b = stack.pop_f32()?;
+            let a = stack.pop_f32()?;
+            stack.push_f32(a + b);
+        }
+        Op::F32Sub => {
+            let b = stack.pop_f32()?;
+            let a = stack.pop_f32()?;
+            stack.push_f32(a - b);
+        }
+        Op::F32Mul => {
+            let b = stack.pop_f32()?;
+            let a = stack.pop_f32()?;
+            stack.push_f32(a * b);
+        }
+        Op::F32Div => {
+            let b = stack.pop_f32()?;
+            let a = stack.pop_f32()?;
+            stack.push_f32(a / b);
+        }
+        Op::F32Min => {
+            let b = stack.pop_f32()?;
+            let a = stack.pop_f32()?;
+            stack.push_f32(f32_min(a, b));
+        }
+        Op::F32Max => {
+            let b = stack.pop_f32()?;
+            let a = stack.pop_f32()?;
+            stack.push_f32(f32_max(a, b));
+        }
+        Op::F32Copysign => {
+            let b = stack.pop_f32()?;
+            let a = stack.pop_f32()?;
+            stack.push_f32(a.copysign(b));
+        }
+        _ => unreachable!("{op:?} isn't handled here"),
+    }
+    Ok(())
+}
+
+/// f64 comparisons and arithmetic.
+#[cfg_attr(feature = "monolithic-dispatch", inline(always))]
+#[cfg_attr(not(feature = "monolithic-dispatch"), inline(never))]
+pub(crate) fn f64_op<S: SlotStorage>(op: &Op, stack: &mut Stack<S>) -> Result<(), Fault> {
+    match op {
+        Op::F64Eq => {
+            let b = stack.pop_f64()?;
+            let a = stack.pop_f64()?;
+            stack.push_u32(if a == b { 1 } else { 0 });
+        }
+        Op::F64Ne => {
+            let b = stack.pop_f64()?;
+            let a = stack.pop_f64()?;
+            stack.push_u32(if a != b { 1 } else { 0 });
+        }
+        Op::F64Lt => {
+            let b = stack.pop_f64()?;
+            let a = stack.pop_f64()?;
+            stack.push_u32(if a < b { 1 } else { 0 });
+        }
+        Op::F64Gt => {
+            let b = stack.pop_f64()?;
+            let a = stack.pop_f64()?;
+            stack.push_u32(if a > b { 1 } else { 0 });
+        }
+        Op::F64Le => {
+            let b = stack.pop_f64()?;
+            let a = stack.pop_f64()?;
+            stack.push_u32(if a <= b { 1 } else { 0 });
+        }
+        Op::F64Ge => {
+            let b = stack.pop_f64()?;
+            let a = stack.pop_f64()?;
+            stack.push_u32(if a >= b { 1 } else { 0 });
+        }
+        Op::F64Add => {
+            let b = stack.pop_f64()?;
+            let a = stack.pop_f64()?;
+            stack.push_f64(a + b);
+        }
+        Op::F64Sub => {
+            let b = stack.pop_f64()?;
+            let a = stack.pop_f64()?;
+            stack.push_f64(a - b);
+        }
+        Op::F64Mul => {
+            let b = stack.pop_f64()?;
+            let a = stack.pop_f64()?;
+            stack.push_f64(a * b);
+        }
+        Op::F64Div => {
+            let b = stack.pop_f64()?;
+            let a = stack.pop_f64()?;
+            stack.push_f64(a / b);
+        }
+        Op::F64Min => {
+            let b = stack.pop_f64()?;
+            let a = stack.pop_f64()?;
+            stack.push_f64(f64_min(a, b));
+        }
+        Op::F64Max => {
+            let b = stack.pop_f64()?;
+            let a = stack.pop_f64()?;
+            stack.push_f64(f64_max(a, b));
+        }
+        Op::F64Copysign => {
+            let b = stack.pop_f64()?;
+            let a = stack.pop_f64()?;
+            stack.push_f64(a.copysign(b));
+        }
+        Op::F64Abs => {
+            let value = stack.pop_f64()?;
+            stack.push_f64(value.abs());
+        }
+        Op::F64Neg => {
+            let value = stack.pop_f64()?;
+            stack.push_f64(-value);
+        }
+        Op::F64Ceil => {
+            let value = stack.pop_f64()?;
+            stack.push_f64(value.ceil());
+        }
+        Op::F64Floor => {
+            let value = stack.pop_f64()?;
+            stack.push_f64(value.floor());
+        }
+        Op::F64Trunc => {
+            let value = stack.pop_f64()?;
+            stack.push_f64(value.trunc());
+        }
+        Op::F64Nearest => {
+            let value = stack.pop_f64()?;
+            stack.push_f64(value.round_ties_even());
+        }
+        Op::F64Sqrt => {
+            let value = stack.pop_f64()?;
+            stack.push_f64(value.sqrt());
+        }
+        _ => unreachable!("{op:?} isn't handled here"),
+    }
+    Ok(())
+}
+
+/// Conversions and reinterpretations between the numeric types.
+#[cfg_attr(feature = "monolithic-dispatch", inline(always))]
+#[cfg_attr(not(feature = "monolithic-dispatch"), inline(never))]
+pub(crate) fn conversion_op<S: SlotStorage>(op: &Op, stack: &mut Stack<S>) -> Result<(), Fault> {
+    match op {
+        Op::I32WrapI64 => {
+            // wrap is `value mod 2^32`, which is just keeping the low 32 bits.
+            let value = stack.pop_u64()?;
+            stack.push_u32(value as u32);
+        }
+        Op::I32TruncF32S => {
+            let value = stack.pop_f32()?;
+            let result = trunc_f32_to_i32(value)?;
+            stack.push_i32(result);
+        }
+        Op::I32TruncF32U => {
+            let value = stack.pop_f32()?;
+            let result = trunc_f32_to_u32(value)?;
+            stack.push_u32(result);
+        }
+        Op::I32TruncF64S => {
+            let value = stack.pop_f64()?;
+            let result = trunc_f64_to_i32(value)?;
+            stack.push_i32(result);
+        }
+        Op::I32TruncF64U => {
+            let value = stack.pop_f64()?;
+            let result = trunc_f64_to_u32(value)?;
+            stack.push_u32(result);
+        }
+        Op::I64ExtendI32S => {
+            let value = stack.pop_i32()?;
+            stack.push_i64(value as i64);
+        }
+        Op::I64ExtendI32U => {
+            let value = stack.pop_u32()?;
+            stack.push_u64(value as u64);
+        }
+        Op::I64TruncF32S => {
+            let value = stack.pop_f32()?;
+            let result = trunc_f32_to_i64(value)?;
+            stack.push_i64(result);
+        }
+        Op::I64TruncF32U => {
+            let value = stack.pop_f32()?;
+            let result = trunc_f32_to_u64(value)?;
+            stack.push_u64(result);
+        }
+        Op::I64TruncF64S => {
+            let value = stack.pop_f64()?;
+            let result = trunc_f64_to_i64(value)?;
+            stack.push_i64(result);
+        }
+        Op::I64TruncF64U => {
+            let value = stack.pop_f64()?;
+            let result = trunc_f64_to_u64(value)?;
+            stack.push_u64(result);
+        }
+        // Saturating truncation operations
+        Op::I32TruncSatF32S => {
+            let value = stack.pop_f32()?;
+            let result = if value.is_nan() {
+                0
+            } else if value <= (i32::MIN as f32) {
+                i32::MIN
+            } else if value >= (i32::MAX as f32) {
+                i32::MAX
+            } else {
+                value as i32
+            };
+            stack.push_i32(result);
+        }
+        Op::I32TruncSatF32U => {
+            let value = stack.pop_f32()?;
+            let result = if value.is_nan() || value < 0.0 {
+                0
+            } else if value >= (u32::MAX as f32) {
+                u32::MAX
+            } else {
+                value as u32
+            };
+            stack.push_u32(result);
+        }
+        Op::I32TruncSatF64S => {
+            let value = stack.pop_f64()?;
+            let result = if value.is_nan() {
+                0
+            } else if value <= (i32::MIN as f64) {
+                i32::MIN
+            } else if value >= (i32::MAX as f64) {
+                i32::MAX
+            } else {
+                value as i32
+            };
+            stack.push_i32(result);
+        }
+        Op::I32TruncSatF64U => {
+            let value = stack.pop_f64()?;
+            let result = if value.is_nan() || value < 0.0 {
+                0
+            } else if value >= (u32::MAX as f64) {
+                u32::MAX
+            } else {
+                value as u32
+            };
+            stack.push_u32(result);
+        }
+        Op::I64TruncSatF32S => {
+            let value = stack.pop_f32()?;
+            let result = if value.is_nan() {
+                0
+            } else if value <= (i64::MIN as f32) {
+                i64::MIN
+            } else if value >= (i64::MAX as f32) {
+                i64::MAX
+            } else {
+                value as i64
+            };
+            stack.push_i64(result);
+        }
+        Op::I64TruncSatF32U => {
+            let value = stack.pop_f32()?;
+            let result = if value.is_nan() || value < 0.0 {
+                0
+            } else if value >= (u64::MAX as f32) {
+                u64::MAX
+            } else {
+                value as u64
+            };
+            stack.push_u64(result);
+        }
+        Op::I64TruncSatF64S => {
+            let value = stack.pop_f64()?;
+            let result = if value.is_nan() {
+                0
+            } else if value <= (i64::MIN as f64) {
+                i64::MIN
+            } else if value >= (i64::MAX as f64) {
+                i64::MAX
+            } else {
+                value as i64
+            };
+            stack.push_i64(result);
+        }
+        Op::I64TruncSatF64U => {
+            let value = stack.pop_f64()?;
+            let result = if value.is_nan() || value < 0.0 {
+                0
+            } else if value >= (u64::MAX as f64) {
+                u64::MAX
+            } else {
+                value as u64
+            };
+            stack.push_u64(result);
+        }
+        Op::F32ConvertI32S => {
+            let value = stack.pop_i32()?;
+            stack.push_f32(value as f32);
+        }
+        Op::F32ConvertI32U => {
+            let value = stack.pop_u32()?;
+            stack.push_f32(value as f32);
+        }
+        Op::F32ConvertI64S => {
+            let value = stack.pop_i64()?;
+            stack.push_f32(value as f32);
+        }
+        Op::F32ConvertI64U => {
+            let value = stack.pop_u64()?;
+            stack.push_f32(value as f32);
+        }
+        Op::F32DemoteF64 => {
+            let value = stack.pop_f64()?;
+            stack.push_f32(value as f32);
+        }
+        Op::F64ConvertI32S => {
+            let value = stack.pop_i32()?;
+            stack.push_f64(value as f64);
+        }
+        Op::F64ConvertI32U => {
+            let value = stack.pop_u32()?;
+            stack.push_f64(value as f64);
+        }
+        Op::F64ConvertI64S => {
+            let value = stack.pop_i64()?;
+            stack.push_f64(value as f64);
+        }
+        Op::F64ConvertI64U => {
+            let value = stack.pop_u64()?;
+            stack.push_f64(value as f64);
+        }
+        Op::F64PromoteF32 => {
+            let value = stack.pop_f32()?;
+            stack.push_f64(value as f64);
+        }
+        Op::I32ReinterpretF32 => {
+            let value = stack.pop_f32()?;
+            stack.push_u32(value.to_bits());
+        }
+        Op::I64ReinterpretF64 => {
+            let value = stack.pop_f64()?;
+            stack.push_u64(value.to_bits());
+        }
+        Op::F32ReinterpretI32 => {
+            let value = stack.pop_u32()?;
+            stack.push_f32(f32::from_bits(value));
+        }
+        Op::F64ReinterpretI64 => {
+            let value = stack.pop_u64()?;
+            stack.push_f64(f64::from_bits(value));
+        }
+        _ => unreachable!("{op:?} isn't handled here"),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::numeric::{f32_max, f32_min, f64_max, f64_min};
+
+    #[test]
+    fn float_min_max_zeroes_and_nans() {
+        let bits32 = |v: f32| v.to_bits();
+        let bits64 = |v: f64| v.to_bits();
+
+        for (a, b) in [(0.0, -0.0), (-0.0, 0.0)] {
+            assert_eq!(bits32(f32_min(a, b)), bits32(-0.0));
+            assert_eq!(bits32(f32_max(a, b)), bits32(0.0));
+            assert_eq!(bits64(f64_min(a as f64, b as f64)), bits64(-0.0));
+            assert_eq!(bits64(f64_max(a as f64, b as f64)), bits64(0.0));
+        }
+        assert_eq!(f32_min(1.0, -2.5), -2.5);
+        assert_eq!(f64_max(f64::NEG_INFINITY, -1e300), -1e300);
+
+        // A NaN on either side wins, and comes out quiet; a canonical NaN stays canonical.
+        let canonical32 = f32::from_bits(0x7fc0_0000);
+        let signalling32 = f32::from_bits(0x7fa0_0000);
+        assert_eq!(bits32(f32_min(canonical32, 1.0)), 0x7fc0_0000);
+        assert_eq!(bits32(f32_max(-0.0, canonical32)), 0x7fc0_0000);
+        let quieted = bits32(f32_max(signalling32, 0.0));
+        assert!(f32::from_bits(quieted).is_nan() && quieted & 0x0040_0000 != 0);
+
+        let canonical64 = f64::from_bits(0x7ff8_0000_0000_0000);
+        assert_eq!(bits64(f64_min(0.0, canonical64)), 0x7ff8_0000_0000_0000);
+        assert_eq!(bits64(f64_max(canonical64, 0.0)), 0x7ff8_0000_0000_0000);
+    }
+}
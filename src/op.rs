@@ -11,8 +11,7 @@
 // this program. If not, see <https://www.gnu.org/licenses/>.
 //
 
-use crate::decode::ScopeType;
-use crate::TypeSignature;
+use crate::decode::{ScopeSig, ScopeType};
 
 #[derive(Clone, Debug, PartialEq, Copy)]
 pub struct MemArg {
@@ -23,19 +22,27 @@ pub struct MemArg {
 /// A semantically richer, decoded version of all the WASM opcodes.
 /// To avoid having varints and having to deal with block structuring issues.
 /// The program will take a sequence of raw OpCodes and turn them into this.
+/// New ops are added as proposals are supported, so matches on this outside the crate need a
+/// fallback arm.
 #[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
 pub enum Op {
     Nop,
     Unreachable,
+    /// Throw an exception with the tag at this index. Exceptions aren't supported, so this faults.
+    Throw(u32),
+    /// Throw the exception referenced on the stack. Faults, as `Throw` does.
+    ThrowRef,
 
     // Control flow.
     /// Block->End
-    StartScope(TypeSignature, ScopeType),
+    StartScope(ScopeSig, ScopeType),
     EndScope(ScopeType),
-    /// If with condition check - no labels needed
-    If,
-    /// Else marker - no labels needed  
-    Else,
+    /// If with condition check. Holds where to carry on when the condition is false: just past
+    /// the matching `Else`, or at the matching `EndScope` if there's no else arm.
+    If(u32),
+    /// Else marker. Holds the index of the matching `EndScope`, where the then arm carries on.
+    Else(u32),
     Br(u32),
     BrIf(u32),
     BrTable(Vec<u32>, u32),
@@ -278,4 +285,29 @@ pub enum Op {
     RefAsNonNull,
     RefEq,
     SelectT(Vec<crate::ValueType>),
+
+    // Garbage collection proposal, all prefixed by 0xFB
+    #[cfg(feature = "gc")]
+    Gc(crate::gc::GcOp),
+
+    // Threads proposal, all prefixed by 0xFE
+    #[cfg(feature = "atomics")]
+    Atomic(crate::atomics::AtomicOp),
+
+    // Common sequences fused into one op by the optimizer.
+    /// `local.get; i32.const; i32.add`
+    #[cfg(feature = "optimize")]
+    LocalI32AddConst(u32, i32),
+    /// `local.get; i32.load`
+    #[cfg(feature = "optimize")]
+    LocalLoadI32(u32, MemArg),
+    /// `i32.const; i32.store`
+    #[cfg(feature = "optimize")]
+    ConstStoreI32(i32, MemArg),
+    /// An i32 comparison followed by `br_if`
+    #[cfg(feature = "optimize")]
+    BrIfI32Cmp(crate::optimize::I32Cmp, u32),
+    /// `i32.eqz; br_if`
+    #[cfg(feature = "optimize")]
+    BrIfEqz(u32),
 }
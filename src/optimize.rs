@@ -0,0 +1,433 @@
+// Copyright (C) 2025 Ryan Daum <ryan.daum@gmail.com> This program is free
+// software: you can redistribute it and/or modify it under the terms of the GNU
+// General Public License as published by the Free Software Foundation, version
+// 3.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+//! A cleanup pass over decoded function bodies, for guests built without optimization.
+//!
+//! Branches find their targets by scanning for scope markers rather than by op index, so ops can
+//! be dropped or merged freely as long as every `StartScope`, `Else` and `EndScope` a live op
+//! could reach is left in place. The jump targets held by `If` and `Else` are re-matched once the
+//! pass is done.
+
+use crate::decode::{match_if_arms, Program};
+use crate::op::Op;
+use std::iter::Peekable;
+
+/// The i32 comparison fused into a `BrIfI32Cmp`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum I32Cmp {
+    Eq,
+    Ne,
+    LtS,
+    LtU,
+    GtS,
+    GtU,
+    LeS,
+    LeU,
+    GeS,
+    GeU,
+}
+
+impl I32Cmp {
+    fn from_op(op: &Op) -> Option<Self> {
+        Some(match op {
+            Op::I32Eq => I32Cmp::Eq,
+            Op::I32Ne => I32Cmp::Ne,
+            Op::I32LtS => I32Cmp::LtS,
+            Op::I32LtU => I32Cmp::LtU,
+            Op::I32GtS => I32Cmp::GtS,
+            Op::I32GtU => I32Cmp::GtU,
+            Op::I32LeS => I32Cmp::LeS,
+            Op::I32LeU => I32Cmp::LeU,
+            Op::I32GeS => I32Cmp::GeS,
+            Op::I32GeU => I32Cmp::GeU,
+            _ => return None,
+        })
+    }
+
+    #[inline]
+    pub(crate) fn test(self, a: i32, b: i32) -> bool {
+        let (ua, ub) = (a as u32, b as u32);
+        match self {
+            I32Cmp::Eq => a == b,
+            I32Cmp::Ne => a != b,
+            I32Cmp::LtS => a < b,
+            I32Cmp::LtU => ua < ub,
+            I32Cmp::GtS => a > b,
+            I32Cmp::GtU => ua > ub,
+            I32Cmp::LeS => a <= b,
+            I32Cmp::LeU => ua <= ub,
+            I32Cmp::GeS => a >= b,
+            I32Cmp::GeU => ua >= ub,
+        }
+    }
+}
+
+/// Fold constant integer arithmetic, resolve branches on constant conditions, strip code which
+/// can never run because of an unconditional branch ahead of it, and fuse common sequences into
+/// single ops.
+pub(crate) fn optimize(program: &mut Program) {
+    let mut ops = std::mem::take(&mut program.ops).into_iter().peekable();
+    let mut out = Vec::with_capacity(ops.len());
+    while let Some(op) = ops.next() {
+        let op = match (op, out.last()) {
+            (Op::BrIf(depth), Some(Op::I32Const(condition))) => {
+                let taken = *condition != 0;
+                out.pop();
+                if !taken {
+                    continue;
+                }
+                Op::Br(depth)
+            }
+            (Op::BrTable(table, default), Some(Op::I32Const(index))) => {
+                let depth = table
+                    .get(*index as u32 as usize)
+                    .copied()
+                    .unwrap_or(default);
+                out.pop();
+                Op::Br(depth)
+            }
+            (op, _) => op,
+        };
+        let diverges = matches!(
+            op,
+            Op::Br(_)
+                | Op::BrTable(..)
+                | Op::Return
+                | Op::Unreachable
+                | Op::Throw(_)
+                | Op::ThrowRef
+        );
+        out.push(op);
+        if diverges {
+            skip_dead(&mut ops);
+        } else {
+            fold_tail(&mut out);
+            fuse_tail(&mut out);
+        }
+    }
+    match_if_arms(&mut out);
+    program.ops = out;
+}
+
+/// Skip everything up to the end (or `else`) of the current scope, nested scopes included.
+fn skip_dead(ops: &mut Peekable<impl Iterator<Item = Op>>) {
+    let mut depth = 0;
+    while let Some(op) = ops.peek() {
+        match op {
+            Op::StartScope(..) => depth += 1,
+            Op::Else(_) | Op::EndScope(_) if depth == 0 => return,
+            Op::EndScope(_) => depth -= 1,
+            _ => {}
+        }
+        ops.next();
+    }
+}
+
+/// If the op just pushed works only on constants pushed immediately before it, replace them all
+/// with the result. Repeated as each op goes in, this folds whole constant expression trees.
+fn fold_tail(out: &mut Vec<Op>) {
+    let n = out.len();
+    let folded = match &out[..] {
+        [.., Op::I32Const(a), Op::I32Const(b), op] => fold_i32_binary(op, *a, *b).map(|v| (3, v)),
+        [.., Op::I64Const(a), Op::I64Const(b), op] => fold_i64_binary(op, *a, *b).map(|v| (3, v)),
+        [.., Op::I32Const(a), op] => fold_i32_unary(op, *a).map(|v| (2, v)),
+        [.., Op::I64Const(a), op] => fold_i64_unary(op, *a).map(|v| (2, v)),
+        _ => None,
+    };
+    if let Some((consumed, value)) = folded {
+        out.truncate(n - consumed);
+        out.push(value);
+    }
+}
+
+/// Replace a sequence ending in the op just pushed with its fused equivalent, where there is one.
+fn fuse_tail(out: &mut Vec<Op>) {
+    let n = out.len();
+    let fused = match &out[..] {
+        [.., Op::GetLocal(idx), Op::I32Const(c), Op::I32Add] => (3, Op::LocalI32AddConst(*idx, *c)),
+        [.., Op::GetLocal(idx), Op::LoadI32(memarg)] => (2, Op::LocalLoadI32(*idx, *memarg)),
+        [.., Op::I32Const(value), Op::StoreI32(memarg)] => (2, Op::ConstStoreI32(*value, *memarg)),
+        [.., Op::I32Eqz, Op::BrIf(depth)] => (2, Op::BrIfEqz(*depth)),
+        [.., cmp, Op::BrIf(depth)] => match I32Cmp::from_op(cmp) {
+            Some(cmp) => (2, Op::BrIfI32Cmp(cmp, *depth)),
+            None => return,
+        },
+        _ => return,
+    };
+    out.truncate(n - fused.0);
+    out.push(fused.1);
+}
+
+/// Division and remainder can trap, so are left for run time.
+fn fold_i32_binary(op: &Op, a: i32, b: i32) -> Option<Op> {
+    let (ua, ub) = (a as u32, b as u32);
+    let value = match op {
+        Op::I32Add => a.wrapping_add(b),
+        Op::I32Sub => a.wrapping_sub(b),
+        Op::I32Mul => a.wrapping_mul(b),
+        Op::I32And => a & b,
+        Op::I32Or => a | b,
+        Op::I32Xor => a ^ b,
+        Op::I32Shl => a.wrapping_shl(ub),
+        Op::I32ShrS => a.wrapping_shr(ub),
+        Op::I32ShrU => ua.wrapping_shr(ub) as i32,
+        Op::I32Rotl => a.rotate_left(ub),
+        Op::I32Rotr => a.rotate_right(ub),
+        Op::I32Eq => (a == b) as i32,
+        Op::I32Ne => (a != b) as i32,
+        Op::I32LtS => (a < b) as i32,
+        Op::I32LtU => (ua < ub) as i32,
+        Op::I32GtS => (a > b) as i32,
+        Op::I32GtU => (ua > ub) as i32,
+        Op::I32LeS => (a <= b) as i32,
+        Op::I32LeU => (ua <= ub) as i32,
+        Op::I32GeS => (a >= b) as i32,
+        Op::I32GeU => (ua >= ub) as i32,
+        _ => return None,
+    };
+    Some(Op::I32Const(value))
+}
+
+fn fold_i64_binary(op: &Op, a: i64, b: i64) -> Option<Op> {
+    let (ua, ub) = (a as u64, b as u64);
+    let value = match op {
+        Op::I64Add => a.wrapping_add(b),
+        Op::I64Sub => a.wrapping_sub(b),
+        Op::I64Mul => a.wrapping_mul(b),
+        Op::I64And => a & b,
+        Op::I64Or => a | b,
+        Op::I64Xor => a ^ b,
+        Op::I64Shl => a.wrapping_shl(ub as u32),
+        Op::I64ShrS => a.wrapping_shr(ub as u32),
+        Op::I64ShrU => ua.wrapping_shr(ub as u32) as i64,
+        Op::I64Rotl => a.rotate_left(ub as u32),
+        Op::I64Rotr => a.rotate_right(ub as u32),
+        _ => {
+            let test = match op {
+                Op::I64Eq => a == b,
+                Op::I64Ne => a != b,
+                Op::I64LtS => a < b,
+                Op::I64LtU => ua < ub,
+                Op::I64GtS => a > b,
+                Op::I64GtU => ua > ub,
+                Op::I64LeS => a <= b,
+                Op::I64LeU => ua <= ub,
+                Op::I64GeS => a >= b,
+                Op::I64GeU => ua >= ub,
+                _ => return None,
+            };
+            return Some(Op::I32Const(test as i32));
+        }
+    };
+    Some(Op::I64Const(value))
+}
+
+fn fold_i32_unary(op: &Op, a: i32) -> Option<Op> {
+    Some(match op {
+        Op::I32Eqz => Op::I32Const((a == 0) as i32),
+        Op::I32Clz => Op::I32Const(a.leading_zeros() as i32),
+        Op::I32Ctz => Op::I32Const(a.trailing_zeros() as i32),
+        Op::I32Popcnt => Op::I32Const(a.count_ones() as i32),
+        Op::I32Extend8S => Op::I32Const(a as i8 as i32),
+        Op::I32Extend16S => Op::I32Const(a as i16 as i32),
+        Op::I64ExtendI32S => Op::I64Const(a as i64),
+        Op::I64ExtendI32U => Op::I64Const(a as u32 as i64),
+        _ => return None,
+    })
+}
+
+fn fold_i64_unary(op: &Op, a: i64) -> Option<Op> {
+    Some(match op {
+        Op::I64Eqz => Op::I32Const((a == 0) as i32),
+        Op::I64Clz => Op::I64Const(a.leading_zeros() as i64),
+        Op::I64Ctz => Op::I64Const(a.trailing_zeros() as i64),
+        Op::I64Popcnt => Op::I64Const(a.count_ones() as i64),
+        Op::I64Extend8S => Op::I64Const(a as i8 as i64),
+        Op::I64Extend16S => Op::I64Const(a as i16 as i64),
+        Op::I64Extend32S => Op::I64Const(a as i32 as i64),
+        Op::I32WrapI64 => Op::I32Const(a as i32),
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::decode::{Program, ScopeSig, ScopeType};
+    use crate::exec::{Execution, Value};
+    use crate::instance::mk_instance;
+    use crate::memory::VectorMemory;
+    use crate::module::Module;
+    use crate::op::{MemArg, Op};
+    use crate::optimize::{optimize, I32Cmp};
+
+    fn optimized(ops: Vec<Op>) -> Vec<Op> {
+        let mut program = Program::new();
+        program.ops = ops;
+        optimize(&mut program);
+        program.ops
+    }
+
+    #[test]
+    fn folds_constants_and_constant_branches() {
+        let block = Op::StartScope(ScopeSig::default(), ScopeType::Block);
+        let ops = optimized(vec![
+            block.clone(),
+            Op::I32Const(2),
+            Op::I32Const(3),
+            Op::I32Mul,
+            Op::I32Const(6),
+            Op::I32Eq,
+            Op::BrIf(0),
+            Op::I32Const(1),
+            Op::I32Const(0),
+            Op::I32DivU,
+            Op::Drop,
+            Op::EndScope(ScopeType::Block),
+            Op::I64Const(-1),
+            Op::I32WrapI64,
+            Op::I32Eqz,
+            Op::BrIf(0),
+        ]);
+        // The first branch is always taken, leaving the rest of its block dead. The second is
+        // never taken, so goes entirely.
+        assert_eq!(ops, vec![block, Op::Br(0), Op::EndScope(ScopeType::Block)]);
+
+        // Division by zero has to trap when it's reached, so isn't folded.
+        let trapping = vec![Op::I32Const(1), Op::I32Const(0), Op::I32DivU];
+        assert_eq!(optimized(trapping.clone()), trapping);
+    }
+
+    #[test]
+    fn strips_dead_code_up_to_end_of_scope() {
+        let sig = ScopeSig::default();
+        let ops = optimized(vec![
+            Op::StartScope(sig, ScopeType::IfElse),
+            Op::If(7),
+            Op::Return,
+            Op::StartScope(sig, ScopeType::Loop),
+            Op::Br(0),
+            Op::EndScope(ScopeType::Loop),
+            Op::Nop,
+            Op::Else(11),
+            Op::I32Const(7),
+            Op::BrTable(vec![1, 2], 3),
+            Op::Drop,
+            Op::EndScope(ScopeType::IfElse),
+            Op::Nop,
+        ]);
+        assert_eq!(
+            ops,
+            vec![
+                Op::StartScope(sig, ScopeType::IfElse),
+                Op::If(4),
+                Op::Return,
+                Op::Else(5),
+                Op::Br(3),
+                Op::EndScope(ScopeType::IfElse),
+                Op::Nop,
+            ]
+        );
+    }
+
+    #[test]
+    fn fuses_common_sequences() {
+        let memarg = MemArg {
+            offset: 4,
+            align: 2,
+        };
+        let ops = optimized(vec![
+            Op::GetLocal(0),
+            Op::I32Const(1),
+            Op::I32Add,
+            Op::GetLocal(1),
+            Op::LoadI32(memarg),
+            Op::I32LtU,
+            Op::BrIf(1),
+            Op::GetLocal(1),
+            Op::I32Const(9),
+            Op::StoreI32(memarg),
+            Op::GetLocal(0),
+            Op::I32Eqz,
+            Op::BrIf(0),
+        ]);
+        assert_eq!(
+            ops,
+            vec![
+                Op::LocalI32AddConst(0, 1),
+                Op::LocalLoadI32(1, memarg),
+                Op::BrIfI32Cmp(I32Cmp::LtU, 1),
+                Op::GetLocal(1),
+                Op::ConstStoreI32(9, memarg),
+                Op::GetLocal(0),
+                Op::BrIfEqz(0),
+            ]
+        );
+    }
+
+    #[test]
+    fn optimized_functions_run_the_same() {
+        let wat = r#"(module (func (export "f") (param i32) (result i32)
+            (local $sum i32)
+            (block $done
+                (loop $again
+                    (br_if $done (i32.eqz (local.get 0)))
+                    (local.set $sum (i32.add (local.get $sum)
+                        (i32.mul (i32.const 3) (i32.add (i32.const 1) (i32.const 1)))))
+                    (local.set 0 (i32.sub (local.get 0) (i32.const 1)))
+                    (br_if $again (i32.const 1))
+                    (unreachable)))
+            (if (i32.ne (i32.const 2) (i32.const 2)) (then (unreachable)))
+            (local.get $sum)))"#;
+        let module = Module::load(&wat::parse_str(wat).unwrap()).unwrap();
+        let linked = mk_instance(module).unwrap();
+        let funcidx = linked.find_funcidx("f").unwrap();
+        let mut execution = Execution::new(linked, VectorMemory::new(0, None));
+        execution.prepare(funcidx, &[Value::I32(5)]).unwrap();
+        execution.run().unwrap();
+        assert_eq!(execution.result().unwrap(), &[Value::I32(30)]);
+    }
+
+    #[test]
+    fn fused_ops_run_the_same() {
+        // Sum the i32s in memory from the address given up to 64, storing a marker past the end.
+        let wat = r#"(module
+            (memory 1)
+            (data (i32.const 0) "\01\00\00\00\02\00\00\00\03\00\00\00\fc\ff\ff\ff")
+            (func (export "f") (param i32) (result i32)
+                (local $sum i32)
+                (block $done
+                    (loop $again
+                        (br_if $done (i32.ge_u (local.get 0) (i32.const 16)))
+                        (local.set $sum (i32.add (local.get $sum) (i32.load (local.get 0))))
+                        (local.set 0 (i32.add (local.get 0) (i32.const 4)))
+                        (br_if $again (i32.eqz (i32.eqz (local.get 0))))))
+                (i32.store offset=16 (local.get 0) (i32.const 77))
+                (i32.add (local.get $sum) (i32.load (i32.const 32)))))"#;
+        let module = Module::load(&wat::parse_str(wat).unwrap()).unwrap();
+        let linked = mk_instance(module).unwrap();
+        let funcidx = linked.find_funcidx("f").unwrap();
+        assert!(linked
+            .program(0)
+            .unwrap()
+            .ops
+            .iter()
+            .any(|op| matches!(op, Op::BrIfI32Cmp(I32Cmp::GeU, 1))));
+        let memory = linked.memories[0].clone();
+        let mut execution = Execution::new(linked, memory);
+        for (start, sum) in [(0, 2), (8, -1), (16, 0)] {
+            execution.prepare(funcidx, &[Value::I32(start)]).unwrap();
+            execution.run().unwrap();
+            assert_eq!(execution.result().unwrap(), &[Value::I32(sum + 77)]);
+        }
+    }
+}
@@ -0,0 +1,21 @@
+// Copyright (C) 2025 Ryan Daum <ryan.daum@gmail.com> This program is free
+// software: you can redistribute it and/or modify it under the terms of the GNU
+// General Public License as published by the Free Software Foundation, version
+// 3.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+//! The types most embeddings need, to bring in with `use wasbox::prelude::*`: loading and linking
+//! a module, running its functions, and the errors along the way.
+
+pub use crate::{
+    Caller, Error, ExecError, Execution, Extern, Fault, FuncType, Instance, InstanceBuilder,
+    LinkError, Linker, LoadConfig, LoaderError, Memory, Module, SuspendReason, Value, ValueType,
+    VectorMemory,
+};
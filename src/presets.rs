@@ -0,0 +1,284 @@
+// Copyright (C) 2025 Ryan Daum <ryan.daum@gmail.com> This program is free
+// software: you can redistribute it and/or modify it under the terms of the GNU
+// General Public License as published by the Free Software Foundation, version
+// 3.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+//! Linkers providing the `env` imports the common toolchains' runtime support expects, so their
+//! modules instantiate without the embedder writing each shim by hand. Host functions only see
+//! their arguments, so anything passing a string passes the pointer on to the callback, to be read
+//! out of memory with [`crate::marshal`] once control is back with the host.
+
+use crate::exec::{Fault, Value};
+use crate::linker::Linker;
+use std::sync::Arc;
+
+type AbortFn = Arc<dyn Fn(u32, u32, u32, u32) -> Fault + Send + Sync>;
+type TraceFn = Arc<dyn Fn(u32, &[f64]) + Send + Sync>;
+type SeedFn = Arc<dyn Fn() -> f64 + Send + Sync>;
+type ExitFn = Arc<dyn Fn(i32) -> Fault + Send + Sync>;
+type GrowthFn = Arc<dyn Fn(u32) + Send + Sync>;
+
+/// The imports of modules built by AssemblyScript with its default runtime: `env.abort`,
+/// `env.trace` and `env.seed`.
+#[derive(Clone)]
+pub struct AssemblyScriptEnv {
+    abort: AbortFn,
+    trace: TraceFn,
+    seed: SeedFn,
+}
+
+impl Default for AssemblyScriptEnv {
+    fn default() -> Self {
+        AssemblyScriptEnv {
+            abort: Arc::new(|_, _, line, column| {
+                Fault::HostAbort(format!("AssemblyScript abort at {line}:{column}"))
+            }),
+            trace: Arc::new(|_, _| {}),
+            // Fixed, so runs are reproducible unless the embedder wants otherwise.
+            seed: Arc::new(|| 0.0),
+        }
+    }
+}
+
+impl AssemblyScriptEnv {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Called with the message and file name string pointers, line and column of an `abort`,
+    /// returning the fault to stop with. By default that's a `HostAbort` naming the position.
+    pub fn on_abort(
+        mut self,
+        abort: impl Fn(u32, u32, u32, u32) -> Fault + Send + Sync + 'static,
+    ) -> Self {
+        self.abort = Arc::new(abort);
+        self
+    }
+
+    /// Called with the message string pointer and the numbers passed to each `trace`. Ignored by
+    /// default.
+    pub fn on_trace(mut self, trace: impl Fn(u32, &[f64]) + Send + Sync + 'static) -> Self {
+        self.trace = Arc::new(trace);
+        self
+    }
+
+    /// Supplies the seed for `Math.random`, which is 0 by default.
+    pub fn on_seed(mut self, seed: impl Fn() -> f64 + Send + Sync + 'static) -> Self {
+        self.seed = Arc::new(seed);
+        self
+    }
+
+    pub fn linker(&self) -> Linker {
+        let mut linker = Linker::new();
+        let abort = self.abort.clone();
+        linker.func("env", "abort", move |args| {
+            let [message, file, line, column] = [0, 1, 2, 3].map(|i| arg_u32(args, i));
+            Err(abort(message, file, line, column))
+        });
+        let trace = self.trace.clone();
+        linker.func("env", "trace", move |args| {
+            let count = (arg_u32(args, 1) as usize).min(5);
+            let values: Vec<f64> = args
+                .iter()
+                .skip(2)
+                .take(count)
+                .map(|v| match v {
+                    Value::F64(f) => *f,
+                    _ => 0.0,
+                })
+                .collect();
+            trace(arg_u32(args, 0), &values);
+            Ok(vec![])
+        });
+        let seed = self.seed.clone();
+        linker.func("env", "seed", move |_| Ok(vec![Value::F64(seed())]));
+        linker
+    }
+}
+
+/// The handful of imports a standalone Emscripten build with no filesystem or JS glue needs:
+/// `env.abort`, `env.exit`, `wasi_snapshot_preview1.proc_exit`,
+/// `env.emscripten_notify_memory_growth` and `env.emscripten_resize_heap`.
+#[derive(Clone)]
+pub struct EmscriptenEnv {
+    abort: Arc<dyn Fn() -> Fault + Send + Sync>,
+    exit: ExitFn,
+    memory_growth: GrowthFn,
+}
+
+impl Default for EmscriptenEnv {
+    fn default() -> Self {
+        EmscriptenEnv {
+            abort: Arc::new(|| Fault::HostAbort("Emscripten abort".to_string())),
+            exit: Arc::new(Fault::Exit),
+            memory_growth: Arc::new(|_| {}),
+        }
+    }
+}
+
+impl EmscriptenEnv {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the fault to stop with on `abort`. By default a `HostAbort`.
+    pub fn on_abort(mut self, abort: impl Fn() -> Fault + Send + Sync + 'static) -> Self {
+        self.abort = Arc::new(abort);
+        self
+    }
+
+    /// Returns the fault to stop with on `exit` or `proc_exit`, given the status. By default
+    /// `Fault::Exit`.
+    pub fn on_exit(mut self, exit: impl Fn(i32) -> Fault + Send + Sync + 'static) -> Self {
+        self.exit = Arc::new(exit);
+        self
+    }
+
+    /// Called with the memory index after the guest has grown its memory itself.
+    pub fn on_memory_growth(mut self, growth: impl Fn(u32) + Send + Sync + 'static) -> Self {
+        self.memory_growth = Arc::new(growth);
+        self
+    }
+
+    pub fn linker(&self) -> Linker {
+        let mut linker = Linker::new();
+        let abort = self.abort.clone();
+        linker.func("env", "abort", move |_| Err(abort()));
+        for (module, name) in [("env", "exit"), ("wasi_snapshot_preview1", "proc_exit")] {
+            let exit = self.exit.clone();
+            linker.func(module, name, move |args| Err(exit(arg_u32(args, 0) as i32)));
+        }
+        let growth = self.memory_growth.clone();
+        linker.func("env", "emscripten_notify_memory_growth", move |args| {
+            growth(arg_u32(args, 0));
+            Ok(vec![])
+        });
+        // The host can't grow guest memory from here, so refuse, and let malloc fail with NULL.
+        linker.func("env", "emscripten_resize_heap", |_| Ok(vec![Value::I32(0)]));
+        linker
+    }
+}
+
+/// A linker for AssemblyScript modules, with the default callbacks.
+pub fn assemblyscript() -> Linker {
+    AssemblyScriptEnv::default().linker()
+}
+
+/// A linker for minimal standalone Emscripten modules, with the default callbacks.
+pub fn emscripten_minimal() -> Linker {
+    EmscriptenEnv::default().linker()
+}
+
+fn arg_u32(args: &[Value], i: usize) -> u32 {
+    match args.get(i) {
+        Some(Value::I32(v)) => *v as u32,
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exec::{ExecError, Execution};
+    use crate::memory::VectorMemory;
+    use crate::module::Module;
+    use std::sync::Mutex;
+
+    fn execution(linker: &Linker, wat: &str) -> Execution<VectorMemory> {
+        let module = Module::load(&wat::parse_str(wat).unwrap()).unwrap();
+        let instance = linker.instantiate(module).unwrap();
+        Execution::new(instance, VectorMemory::new(0, None))
+    }
+
+    fn call(
+        execution: &mut Execution<VectorMemory>,
+        name: &str,
+        args: &[Value],
+    ) -> Result<(), ExecError> {
+        execution.reset();
+        let funcidx = execution.instance().find_funcidx(name).unwrap();
+        execution.prepare(funcidx, args)?;
+        execution.run()
+    }
+
+    #[test]
+    fn assemblyscript_env() {
+        let wat = r#"(module
+            (import "env" "abort" (func $abort (param i32 i32 i32 i32)))
+            (import "env" "trace" (func $trace (param i32 i32 f64 f64 f64 f64 f64)))
+            (import "env" "seed" (func $seed (result f64)))
+            (func (export "seed") (result f64) (call $seed))
+            (func (export "trace")
+                (call $trace (i32.const 16) (i32.const 2)
+                    (f64.const 1.5) (f64.const 2.5) (f64.const 0) (f64.const 0) (f64.const 0)))
+            (func (export "fail") (call $abort (i32.const 32) (i32.const 48) (i32.const 7) (i32.const 3))))"#;
+
+        let mut defaults = execution(&assemblyscript(), wat);
+        call(&mut defaults, "seed", &[]).unwrap();
+        assert_eq!(defaults.result().unwrap(), &[Value::F64(0.0)]);
+        call(&mut defaults, "trace", &[]).unwrap();
+        let Err(ExecError::ExecutionFault(Fault::HostAbort(reason))) =
+            call(&mut defaults, "fail", &[])
+        else {
+            panic!("abort should stop execution");
+        };
+        assert!(reason.contains("7:3"), "{reason}");
+
+        let traced = Arc::new(Mutex::new(vec![]));
+        let sink = traced.clone();
+        let env = AssemblyScriptEnv::new()
+            .on_seed(|| 42.0)
+            .on_trace(move |message, values| sink.lock().unwrap().push((message, values.to_vec())))
+            .on_abort(|message, file, _, _| Fault::HostAbort(format!("{message}/{file}")));
+        let mut custom = execution(&env.linker(), wat);
+        call(&mut custom, "seed", &[]).unwrap();
+        assert_eq!(custom.result().unwrap(), &[Value::F64(42.0)]);
+        call(&mut custom, "trace", &[]).unwrap();
+        assert_eq!(*traced.lock().unwrap(), vec![(16, vec![1.5, 2.5])]);
+        assert!(matches!(
+            call(&mut custom, "fail", &[]),
+            Err(ExecError::ExecutionFault(Fault::HostAbort(reason))) if reason == "32/48"
+        ));
+    }
+
+    #[test]
+    fn emscripten_env() {
+        let wat = r#"(module
+            (import "wasi_snapshot_preview1" "proc_exit" (func $exit (param i32)))
+            (import "env" "emscripten_notify_memory_growth" (func $grown (param i32)))
+            (import "env" "emscripten_resize_heap" (func $resize (param i32) (result i32)))
+            (func (export "resize") (result i32) (call $resize (i32.const 1000000)))
+            (func (export "grown") (call $grown (i32.const 0)))
+            (func (export "exit") (param i32) (call $exit (local.get 0))))"#;
+
+        let mut defaults = execution(&emscripten_minimal(), wat);
+        call(&mut defaults, "resize", &[]).unwrap();
+        assert_eq!(defaults.result().unwrap(), &[Value::I32(0)]);
+        call(&mut defaults, "grown", &[]).unwrap();
+        assert!(matches!(
+            call(&mut defaults, "exit", &[Value::I32(3)]),
+            Err(ExecError::ExecutionFault(Fault::Exit(3)))
+        ));
+
+        let growths = Arc::new(Mutex::new(0));
+        let counter = growths.clone();
+        let env = EmscriptenEnv::new()
+            .on_memory_growth(move |_| *counter.lock().unwrap() += 1)
+            .on_exit(|status| Fault::HostAbort(format!("exit {status}")));
+        let mut custom = execution(&env.linker(), wat);
+        call(&mut custom, "grown", &[]).unwrap();
+        assert_eq!(*growths.lock().unwrap(), 1);
+        assert!(matches!(
+            call(&mut custom, "exit", &[Value::I32(0)]),
+            Err(ExecError::ExecutionFault(Fault::HostAbort(reason))) if reason == "exit 0"
+        ));
+    }
+}
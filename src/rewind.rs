@@ -0,0 +1,80 @@
+// Copyright (C) 2025 Ryan Daum <ryan.daum@gmail.com> This program is free
+// software: you can redistribute it and/or modify it under the terms of the GNU
+// General Public License as published by the Free Software Foundation, version
+// 3.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+//! Stepping backwards through a run. While single-stepping with `Execution::step`, a checkpoint
+//! of everything the guest can change is taken every so many ops, and only the most recent are
+//! kept. `Execution::step_back` goes back to the last checkpoint before the op it's undoing and
+//! steps forward again from there, so how far back it can go is bounded by how many are kept.
+//!
+//! Stepping forward again runs host functions again too, so going back is only faithful if they
+//! answer the same way the second time.
+
+use crate::exec::Value;
+use crate::frame::Frame;
+use crate::instance::TableInstance;
+use crate::stack::{HeapSlots, SlotStorage};
+use std::collections::VecDeque;
+
+/// The state of an execution as it was after `ops_run` ops.
+pub(crate) struct Checkpoint<M, S: SlotStorage = HeapSlots> {
+    pub(crate) ops_run: u64,
+    pub(crate) frames: Vec<Frame<S>>,
+    pub(crate) memory: M,
+    pub(crate) globals: Vec<Value>,
+    pub(crate) tables: Vec<TableInstance>,
+    pub(crate) result: Option<Vec<Value>>,
+}
+
+/// The checkpoints being kept, oldest first.
+pub(crate) struct History<M, S: SlotStorage = HeapSlots> {
+    interval: u64,
+    window: usize,
+    /// Copies the memory into a checkpoint. Taken when recording starts, which needs the memory
+    /// to be `Clone`, so that stepping doesn't.
+    pub(crate) copy_memory: fn(&M) -> M,
+    checkpoints: VecDeque<Checkpoint<M, S>>,
+}
+
+impl<M, S: SlotStorage> History<M, S> {
+    pub(crate) fn new(interval: u64, window: usize, copy_memory: fn(&M) -> M) -> Self {
+        History {
+            interval: interval.max(1),
+            window: window.max(1),
+            copy_memory,
+            checkpoints: VecDeque::new(),
+        }
+    }
+
+    /// Whether a checkpoint should be taken before running the op after `ops_run`.
+    pub(crate) fn due(&self, ops_run: u64) -> bool {
+        self.checkpoints
+            .back()
+            .is_none_or(|last| ops_run >= last.ops_run.saturating_add(self.interval))
+    }
+
+    pub(crate) fn push(&mut self, checkpoint: Checkpoint<M, S>) {
+        self.checkpoints.push_back(checkpoint);
+        while self.checkpoints.len() > self.window {
+            self.checkpoints.pop_front();
+        }
+    }
+
+    /// The latest checkpoint taken after no more than `ops_run` ops, throwing away any taken
+    /// after it, which a different path forward would leave stale.
+    pub(crate) fn rewind_to(&mut self, ops_run: u64) -> Option<&Checkpoint<M, S>> {
+        while self.checkpoints.back().is_some_and(|c| c.ops_run > ops_run) {
+            self.checkpoints.pop_back();
+        }
+        self.checkpoints.back()
+    }
+}
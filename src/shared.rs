@@ -0,0 +1,372 @@
+// Copyright (C) 2025 Ryan Daum <ryan.daum@gmail.com> This program is free
+// software: you can redistribute it and/or modify it under the terms of the GNU
+// General Public License as published by the Free Software Foundation, version
+// 3.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+//! Globals and memories one instance exports and others import, so that a write made by any of
+//! them is seen by the rest.
+//!
+//! Each instance, and each execution, still runs against its own copy: the interpreter reads
+//! globals and memory directly, and doesn't go through a lock. The copies are brought into line
+//! with the shared one whenever control could pass from one instance to another, which is as a
+//! run starts and ends, and around calls to host functions given a `Caller`. Functions exported
+//! with `Execution::export_shared` are such host functions, so a call from one instance into
+//! another sees everything the caller wrote, and the caller sees everything the callee wrote
+//! once it returns. A plain `Extern::Func` which reaches into another instance by some other
+//! route doesn't get this.
+//!
+//! Bringing a memory into line compares it page by page with the shared copy, so it costs time
+//! in proportion to its size, at each of those points, for instances which share one.
+
+use crate::exec::{GlobalVar, Value};
+use crate::memory::{bytes_for_pages, Memory, WASM_PAGE_SIZE};
+use crate::{ValueType, VectorMemory};
+use std::fmt::{Debug, Formatter};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// A mutable global shared between instances, as `Extern::SharedGlobal`.
+#[derive(Clone)]
+pub struct SharedGlobal {
+    value: Arc<Mutex<Value>>,
+}
+
+impl SharedGlobal {
+    pub(crate) fn new(value: Value) -> Self {
+        SharedGlobal {
+            value: Arc::new(Mutex::new(value)),
+        }
+    }
+
+    /// The value as the last instance to hand back control left it.
+    pub fn get(&self) -> Value {
+        *lock(&self.value)
+    }
+
+    pub fn ty(&self) -> ValueType {
+        self.get().type_of()
+    }
+
+    fn set(&self, value: Value) {
+        *lock(&self.value) = value;
+    }
+}
+
+impl Debug for SharedGlobal {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("SharedGlobal").field(&self.get()).finish()
+    }
+}
+
+/// The shared copy of a memory, and when each of its pages last changed.
+struct Pages {
+    memory: VectorMemory,
+    /// Bumped each time any instance's writes are brought in.
+    version: u64,
+    /// The `version` at which each page last changed.
+    changed: Vec<u64>,
+}
+
+/// A memory shared between instances, as `Extern::SharedMemory`.
+#[derive(Clone)]
+pub struct SharedMemory {
+    pages: Arc<Mutex<Pages>>,
+}
+
+impl SharedMemory {
+    pub(crate) fn new(memory: &impl Memory) -> Self {
+        let max_bounds = memory.max_pages().map(bytes_for_pages);
+        let mut copy = VectorMemory::new(memory.size(), max_bounds);
+        copy.data_mut().copy_from_slice(memory.data());
+        SharedMemory {
+            pages: Arc::new(Mutex::new(Pages {
+                changed: vec![0; memory.size().div_ceil(WASM_PAGE_SIZE)],
+                memory: copy,
+                version: 0,
+            })),
+        }
+    }
+
+    /// A copy of the memory as the last instance to hand back control left it.
+    pub fn snapshot(&self) -> VectorMemory {
+        lock(&self.pages).memory.clone()
+    }
+
+    /// A copy for an instance importing the memory to run against, and its version.
+    pub(crate) fn import(&self) -> (VectorMemory, u64) {
+        let pages = lock(&self.pages);
+        (pages.memory.clone(), pages.version)
+    }
+
+    pub fn size(&self) -> usize {
+        lock(&self.pages).memory.size()
+    }
+
+    pub fn pages(&self) -> usize {
+        lock(&self.pages).memory.pages()
+    }
+
+    pub fn max_pages(&self) -> Option<usize> {
+        lock(&self.pages).memory.max_pages()
+    }
+}
+
+impl Debug for SharedMemory {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "SharedMemory({} bytes)", self.size())
+    }
+}
+
+/// Whether globals of type `ty` can be shared. References are to things in the instance they
+/// came from, such as its functions, so would mean something else to another.
+pub(crate) fn shareable(ty: ValueType) -> bool {
+    matches!(
+        ty,
+        ValueType::I32 | ValueType::I64 | ValueType::F32 | ValueType::F64 | ValueType::V128
+    )
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    // Nothing is left half done while these are held, so a panic elsewhere doesn't matter.
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// What an instance shares with others, and how up to date its own copies are.
+#[derive(Clone, Default, Debug)]
+pub(crate) struct Links {
+    /// The instance's globals which are shared, by index.
+    pub(crate) globals: Vec<(u32, SharedGlobal)>,
+    /// Its first memory, if that's shared, and the version of it last brought in.
+    pub(crate) memory: Option<(SharedMemory, u64)>,
+}
+
+impl Links {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.globals.is_empty() && self.memory.is_none()
+    }
+
+    /// The handle for global `index`, sharing it from now on if it isn't already.
+    pub(crate) fn share_global(&mut self, index: u32, value: Value) -> SharedGlobal {
+        if let Some((_, global)) = self.globals.iter().find(|(i, _)| *i == index) {
+            return global.clone();
+        }
+        let global = SharedGlobal::new(value);
+        self.globals.push((index, global.clone()));
+        global
+    }
+
+    /// The handle for the first memory, `memory`, sharing it from now on if it isn't already.
+    pub(crate) fn share_memory(&mut self, memory: &impl Memory) -> SharedMemory {
+        if let Some((shared, _)) = &self.memory {
+            return shared.clone();
+        }
+        let shared = SharedMemory::new(memory);
+        self.memory = Some((shared.clone(), 0));
+        shared
+    }
+
+    /// Bring the instance's copies up to date with changes other instances have handed back.
+    pub(crate) fn pull(&mut self, globals: &mut [GlobalVar], memory: &mut impl Memory) {
+        for (index, shared) in &self.globals {
+            if let Some(global) = globals.get_mut(*index as usize) {
+                global.value = shared.get();
+            }
+        }
+        let Some((shared, seen)) = &mut self.memory else {
+            return;
+        };
+        let pages = lock(&shared.pages);
+        if pages.version == *seen {
+            return;
+        }
+        let size = pages.memory.size();
+        if memory.size() < size {
+            // The shared memory can't be past what this one may grow to: they're the same memory.
+            let _ = memory.grow(size);
+        }
+        for (page, _) in pages
+            .changed
+            .iter()
+            .enumerate()
+            .filter(|(_, v)| **v > *seen)
+        {
+            let start = page * WASM_PAGE_SIZE;
+            let end = (start + WASM_PAGE_SIZE).min(size);
+            if let Ok(local) = memory.range_mut(start, end - start) {
+                local.copy_from_slice(&pages.memory.data()[start..end]);
+            }
+        }
+        *seen = pages.version;
+    }
+
+    /// Hand back the instance's changes for other instances to pull in.
+    pub(crate) fn push(&mut self, globals: &[GlobalVar], memory: &impl Memory) {
+        for (index, shared) in &self.globals {
+            if let Some(global) = globals.get(*index as usize) {
+                shared.set(global.value);
+            }
+        }
+        let Some((shared, seen)) = &mut self.memory else {
+            return;
+        };
+        let mut pages = lock(&shared.pages);
+        let pages = &mut *pages;
+        let up_to_date = *seen == pages.version;
+        let version = pages.version + 1;
+        let mut changed = false;
+        if memory.size() > pages.memory.size() {
+            let _ = pages.memory.grow(memory.size());
+            pages
+                .changed
+                .resize(memory.size().div_ceil(WASM_PAGE_SIZE), version);
+            changed = true;
+        }
+        let size = pages.memory.size().min(memory.size());
+        for page in 0..size.div_ceil(WASM_PAGE_SIZE) {
+            let start = page * WASM_PAGE_SIZE;
+            let end = (start + WASM_PAGE_SIZE).min(size);
+            let local = &memory.data()[start..end];
+            if pages.memory.data()[start..end] != *local {
+                pages.memory.data_mut()[start..end].copy_from_slice(local);
+                pages.changed[page] = version;
+                changed = true;
+            }
+        }
+        if changed {
+            pages.version = version;
+        }
+        // This instance's copy has everything it just handed back, and so is still up to date
+        // if it was before.
+        if up_to_date {
+            *seen = pages.version;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::exec::{ExecError, Execution, Fault, Value};
+    use crate::instance::LinkError;
+    use crate::linker::Linker;
+    use crate::memory::{Memory, VectorMemory};
+    use crate::module::Module;
+    use std::sync::{Arc, Mutex};
+
+    fn load(wat: &str) -> Module {
+        Module::load(&wat::parse_str(wat).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn importers_write_through_to_exporters() {
+        let a = Linker::new()
+            .instantiate(load(
+                r#"(module
+                (memory (export "mem") 1)
+                (global $g (export "g") (mut i32) (i32.const 1))
+                (func (export "sum") (result i32)
+                    (i32.add (global.get $g) (i32.load (i32.const 100))))
+                (func (export "bump") (global.set $g (i32.add (global.get $g) (i32.const 1)))))"#,
+            ))
+            .unwrap();
+        let memory = a.memories[0].clone();
+        let a = Arc::new(Mutex::new(Execution::new(a, memory)));
+        let mut linker = Linker::new();
+        for name in ["mem", "g", "sum", "bump"] {
+            let export = Execution::export_shared(&a, name).unwrap();
+            linker.define("a", name, export);
+        }
+
+        // B's data segment goes into A's memory.
+        let b = linker
+            .instantiate(load(
+                r#"(module
+                (import "a" "mem" (memory 1))
+                (import "a" "g" (global $g (mut i32)))
+                (import "a" "sum" (func $sum (result i32)))
+                (import "a" "bump" (func $bump))
+                (data (i32.const 100) "\04")
+                (func (export "run") (result i32)
+                    (global.set $g (i32.const 100))
+                    (i32.store (i32.const 100) (i32.const 5))
+                    (call $bump)
+                    (i32.add (call $sum) (global.get $g))))"#,
+            ))
+            .unwrap();
+        assert_eq!(
+            a.lock().unwrap().invoke("sum", &[]).unwrap(),
+            vec![Value::I32(1 + 4)]
+        );
+
+        // A sees B's writes when B calls into it, and B sees A's once it returns.
+        let memory = b.memories[0].clone();
+        let mut b = Execution::new(b, memory);
+        assert_eq!(b.invoke("run", &[]).unwrap(), vec![Value::I32(106 + 101)]);
+        let mut a = a.lock().unwrap();
+        assert_eq!(a.invoke("sum", &[]).unwrap(), vec![Value::I32(101 + 5)]);
+        assert_eq!(a.memory().get_i32(100).unwrap(), 5);
+        assert_eq!(a.instance().globals[0].value, Value::I32(101));
+    }
+
+    #[test]
+    fn shared_functions_keep_their_signatures() {
+        let a = Linker::new()
+            .instantiate(load(
+                r#"(module
+                (import "wasbox" "yield" (func $yield))
+                (func (export "nap") (call $yield))
+                (func (export "div") (param i32) (result i32)
+                    (i32.div_u (i32.const 12) (local.get 0))))"#,
+            ))
+            .unwrap();
+        let a = Arc::new(Mutex::new(Execution::new(a, VectorMemory::new(0, None))));
+        let mut linker = Linker::new();
+        linker.define("a", "div", Execution::export_shared(&a, "div").unwrap());
+
+        let wrong = load(r#"(module (import "a" "div" (func (param i64) (result i32))))"#);
+        assert!(matches!(
+            linker.instantiate(wrong),
+            Err(LinkError::IncompatibleImport(_, _))
+        ));
+
+        let b = linker
+            .instantiate(load(
+                r#"(module
+                (import "a" "div" (func $div (param i32) (result i32)))
+                (func (export "f") (param i32) (result i32) (call $div (local.get 0))))"#,
+            ))
+            .unwrap();
+        let mut b = Execution::new(b, VectorMemory::new(0, None));
+        assert_eq!(
+            b.invoke("f", &[Value::I32(4)]).unwrap(),
+            vec![Value::I32(3)]
+        );
+
+        // A fault in A ends B's run, but A can still be called.
+        assert!(matches!(
+            b.invoke("f", &[Value::I32(0)]),
+            Err(ExecError::ExecutionFault(Fault::IntegerDivisionByZero))
+        ));
+        b.reset();
+        assert_eq!(
+            b.invoke("f", &[Value::I32(6)]).unwrap(),
+            vec![Value::I32(2)]
+        );
+
+        // While A is suspended, calls into it are refused.
+        assert!(a.lock().unwrap().invoke("nap", &[]).is_err());
+        b.reset();
+        assert!(matches!(
+            b.invoke("f", &[Value::I32(6)]),
+            Err(ExecError::ExecutionFault(Fault::Busy))
+        ));
+    }
+}
@@ -0,0 +1,375 @@
+// Copyright (C) 2025 Ryan Daum <ryan.daum@gmail.com> This program is free
+// software: you can redistribute it and/or modify it under the terms of the GNU
+// General Public License as published by the Free Software Foundation, version
+// 3.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+//! Pre-initialization: run a module's start function, and optionally an init export, once, then
+//! bake the resulting globals and memory back into a new module, so instances of that start out
+//! already initialized.
+//!
+//! Only state that a module can declare is captured: the values of its own globals and the
+//! contents and size of its own memory. Tables, imported globals and memories, and anything the
+//! host holds are left as declared, so init code which changes those won't be reproduced.
+
+use crate::exec::{ExecError, Execution};
+use crate::instance::LinkError;
+use crate::linker::Linker;
+use crate::memory::Memory;
+use crate::module::{
+    write_section, write_sleb128, write_uleb128, Data, LEB128Reader, LoaderError, Module,
+    SECTION_ID_CODE, SECTION_ID_DATA, SECTION_ID_DATA_COUNT, SECTION_ID_GLOBAL, SECTION_ID_MEMORY,
+    SECTION_ID_START,
+};
+use crate::stack::SlotStorage;
+use crate::{DecodeError, Value};
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+/// Runs of zeroes shorter than this are kept inside a data segment rather than splitting it, as
+/// each segment costs a few bytes of header.
+const MIN_ZERO_GAP: usize = 16;
+
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub enum SnapshotError {
+    /// The module imports its memory, or has more than one, which we can't bake in.
+    UnsupportedMemory,
+    /// The global, by index, holds a value a constant expression can't produce, such as a
+    /// non-null externref.
+    UnencodableGlobal(u32),
+    /// The named init function isn't exported.
+    MissingExport(String),
+    /// The execution still has live frames.
+    Busy,
+    Link(LinkError),
+    Exec(ExecError),
+    Decode(DecodeError),
+    /// The rewritten module failed to load.
+    Load(LoaderError),
+}
+
+impl Display for SnapshotError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SnapshotError::UnsupportedMemory => {
+                write!(
+                    f,
+                    "Only a single memory defined by the module can be snapshotted"
+                )
+            }
+            SnapshotError::UnencodableGlobal(idx) => {
+                write!(f, "Global {idx} holds a value with no constant expression")
+            }
+            SnapshotError::MissingExport(name) => write!(f, "No exported function {name:?}"),
+            SnapshotError::Busy => write!(f, "Execution has live frames"),
+            SnapshotError::Link(e) => write!(f, "{e}"),
+            SnapshotError::Exec(e) => write!(f, "{e}"),
+            SnapshotError::Decode(e) => write!(f, "{e}"),
+            SnapshotError::Load(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl Error for SnapshotError {}
+
+/// Instantiate `module` against `linker`, which runs its start function, then call the `init`
+/// export if given, and snapshot the result.
+pub fn preinitialize(
+    module: Module,
+    linker: &Linker,
+    init: Option<&str>,
+) -> Result<Module, SnapshotError> {
+    let instance = linker.instantiate(module).map_err(SnapshotError::Link)?;
+    let memory = instance
+        .memories
+        .first()
+        .cloned()
+        .unwrap_or_else(|| crate::VectorMemory::new(0, None));
+    let mut execution = Execution::new(instance, memory);
+    if let Some(name) = init {
+        let funcidx = execution
+            .instance()
+            .find_funcidx(name)
+            .ok_or_else(|| SnapshotError::MissingExport(name.to_string()))?;
+        execution
+            .prepare(funcidx, &[])
+            .and_then(|_| execution.run())
+            .map_err(SnapshotError::Exec)?;
+    }
+    snapshot(&execution)
+}
+
+/// A copy of the execution's module with no start function, whose globals are initialized to
+/// their current values and whose memory starts out as it is now. Active data segments have
+/// already been applied, so become empty passive ones, keeping the indices of the rest.
+pub fn snapshot<M: Memory, T: 'static, S: SlotStorage>(
+    execution: &Execution<M, T, S>,
+) -> Result<Module, SnapshotError> {
+    if execution.frame_stack_len() != 0 {
+        return Err(SnapshotError::Busy);
+    }
+    let instance = execution.instance();
+    let module = &instance.module;
+    if module.memories.len() > 1 || (module.memories.is_empty() && !instance.memories.is_empty()) {
+        return Err(SnapshotError::UnsupportedMemory);
+    }
+    let memory = (!module.memories.is_empty()).then(|| execution.memory());
+    let image = memory.map(|m| image_segments(m.data())).unwrap_or_default();
+    let num_data = module.data.len() + image.len();
+
+    let mut out = module.module_data[..8].to_vec();
+    let mut wrote_data = false;
+    for section in &module.sections {
+        let payload = &module.module_data[section.offset..section.offset + section.size];
+        let replacement = match section.id {
+            SECTION_ID_START => continue,
+            SECTION_ID_GLOBAL => global_section(execution)?,
+            SECTION_ID_MEMORY => {
+                let pages = memory.map_or(0, |m| m.pages());
+                memory_section(module, pages)
+            }
+            SECTION_ID_DATA_COUNT => {
+                let mut count = vec![];
+                write_uleb128(&mut count, num_data as u64);
+                count
+            }
+            SECTION_ID_DATA => {
+                wrote_data = true;
+                data_section(module, &image)
+            }
+            _ => payload.to_vec(),
+        };
+        write_section(&mut out, section.id, &replacement);
+        // A module with no segments of its own has no data section to rewrite, and it comes
+        // straight after the code.
+        if section.id == SECTION_ID_CODE && module.data.is_empty() && !image.is_empty() {
+            write_section(&mut out, SECTION_ID_DATA, &data_section(module, &image));
+            wrote_data = true;
+        }
+    }
+    if !wrote_data && !image.is_empty() {
+        write_section(&mut out, SECTION_ID_DATA, &data_section(module, &image));
+    }
+    Module::load(&out).map_err(SnapshotError::Load)
+}
+
+/// The non-zero stretches of memory, by offset.
+fn image_segments(data: &[u8]) -> Vec<(usize, &[u8])> {
+    let mut segments: Vec<(usize, usize)> = vec![];
+    let mut pos = 0;
+    while let Some(start) = data[pos..].iter().position(|&b| b != 0).map(|i| pos + i) {
+        let end = data[start..]
+            .iter()
+            .position(|&b| b == 0)
+            .map_or(data.len(), |i| start + i);
+        match segments.last_mut() {
+            Some((_, last_end)) if start - *last_end < MIN_ZERO_GAP => *last_end = end,
+            _ => segments.push((start, end)),
+        }
+        pos = end;
+    }
+    segments
+        .into_iter()
+        .map(|(start, end)| (start, &data[start..end]))
+        .collect()
+}
+
+/// The global section with each global's type copied from the original, and its initializer
+/// replaced by a constant for its current value.
+fn global_section<M: Memory, T: 'static, S: SlotStorage>(
+    execution: &Execution<M, T, S>,
+) -> Result<Vec<u8>, SnapshotError> {
+    let instance = execution.instance();
+    let module = &instance.module;
+    let num_imported = instance.globals.len() - module.globals.len();
+    let section = module
+        .sections
+        .iter()
+        .find(|s| s.id == SECTION_ID_GLOBAL)
+        .expect("only called for a module with a global section");
+    let mut reader = LEB128Reader::new(&module.module_data, section.offset);
+    reader.load_imm_varuint32().map_err(SnapshotError::Decode)?;
+
+    let mut out = vec![];
+    write_uleb128(&mut out, module.globals.len() as u64);
+    let mut entry_start = reader.position();
+    for (i, global) in module.globals.iter().enumerate() {
+        // The type and mutability, which come right before the initializer.
+        out.extend_from_slice(&module.module_data[entry_start..global.expr.0]);
+        let globalidx = num_imported + i;
+        const_expr(&mut out, &instance.globals[globalidx].value)
+            .ok_or(SnapshotError::UnencodableGlobal(globalidx as u32))?;
+        // Past the initializer's `end`.
+        entry_start = global.expr.1 + 1;
+    }
+    Ok(out)
+}
+
+/// Append a constant expression producing `value`, or None if there isn't one.
+fn const_expr(out: &mut Vec<u8>, value: &Value) -> Option<()> {
+    match value {
+        Value::I32(v) => {
+            out.push(0x41);
+            write_sleb128(out, *v as i64);
+        }
+        Value::I64(v) => {
+            out.push(0x42);
+            write_sleb128(out, *v);
+        }
+        Value::F32(v) => {
+            out.push(0x43);
+            out.extend_from_slice(&v.to_bits().to_le_bytes());
+        }
+        Value::F64(v) => {
+            out.push(0x44);
+            out.extend_from_slice(&v.to_bits().to_le_bytes());
+        }
+        Value::FuncRef(None) => out.extend_from_slice(&[0xd0, 0x70]),
+        Value::ExternRef(None) => out.extend_from_slice(&[0xd0, 0x6f]),
+        Value::FuncRef(Some(funcidx)) => {
+            out.push(0xd2);
+            write_uleb128(out, *funcidx as u64);
+        }
+        _ => return None,
+    }
+    out.push(0x0b);
+    Some(())
+}
+
+/// The memory section for the module's one memory, grown to `pages` if it has been.
+fn memory_section(module: &Module, pages: usize) -> Vec<u8> {
+    let memory = &module.memories[0];
+    let (min, max) = memory.limits;
+    let mut flags = 0;
+    if max.is_some() {
+        flags |= 0x01;
+    }
+    if memory.shared {
+        flags |= 0x02;
+    }
+    let mut out = vec![1, flags];
+    write_uleb128(&mut out, (min as u64).max(pages as u64));
+    if let Some(max) = max {
+        write_uleb128(&mut out, max as u64);
+    }
+    out
+}
+
+/// The module's own data segments, with the active ones emptied out as already applied, followed
+/// by active segments for the memory image.
+fn data_section(module: &Module, image: &[(usize, &[u8])]) -> Vec<u8> {
+    let mut out = vec![];
+    write_uleb128(&mut out, (module.data.len() + image.len()) as u64);
+    for data in &module.data {
+        out.push(0x01);
+        match data {
+            Data::Passive { data } => {
+                let bytes = &module.module_data[data.0..data.1];
+                write_uleb128(&mut out, bytes.len() as u64);
+                out.extend_from_slice(bytes);
+            }
+            Data::Active { .. } | Data::ActiveMemIdx { .. } => out.push(0),
+        }
+    }
+    for (offset, bytes) in image {
+        out.push(0x00);
+        const_expr(&mut out, &Value::I32(*offset as i32));
+        write_uleb128(&mut out, bytes.len() as u64);
+        out.extend_from_slice(bytes);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mk_instance;
+
+    #[test]
+    fn preinitialized_module_skips_init() {
+        // The start function counts its runs in a global and in memory, and `init` writes a
+        // table of squares far enough along to need its own segment.
+        let wat = r#"(module
+            (memory 1 4)
+            (global $runs (mut i32) (i32.const 0))
+            (global $big (mut i64) (i64.const 0))
+            (global $f (mut f64) (f64.const 0))
+            (data (i32.const 0) "hello")
+            (data $kept "passive")
+            (func $start
+                (global.set $runs (i32.add (global.get $runs) (i32.const 1)))
+                (i32.store8 (i32.const 5) (global.get $runs)))
+            (func (export "init") (local $i i32)
+                (global.set $big (i64.const -1234567890123))
+                (global.set $f (f64.const 2.5))
+                (drop (memory.grow (i32.const 1)))
+                (loop $l
+                    (i32.store (i32.add (i32.const 70000) (i32.shl (local.get $i) (i32.const 2)))
+                        (i32.mul (local.get $i) (local.get $i)))
+                    (local.set $i (i32.add (local.get $i) (i32.const 1)))
+                    (br_if $l (i32.lt_u (local.get $i) (i32.const 8)))))
+            (func (export "runs") (result i32) (global.get $runs))
+            (func (export "big") (result i64) (global.get $big))
+            (func (export "f") (result f64) (global.get $f))
+            (func (export "load") (param i32) (result i32) (i32.load (local.get 0)))
+            (start $start))"#;
+        let module = Module::load(&wat::parse_str(wat).unwrap()).unwrap();
+        let snapshotted = preinitialize(module, &Linker::new(), Some("init")).unwrap();
+        assert_eq!(snapshotted.start_function, None);
+        assert_eq!(snapshotted.memories[0].limits, (2, Some(4)));
+        // Passive segments are kept at their old indices, and the applied active one is emptied.
+        let Data::Passive { data } = snapshotted.data[1] else {
+            panic!("expected the passive segment");
+        };
+        assert_eq!(&snapshotted.module_data[data.0..data.1], b"passive");
+        assert!(
+            matches!(snapshotted.data[0], Data::Passive { data: (start, end) } if start == end)
+        );
+
+        let instance = mk_instance(snapshotted).unwrap();
+        let memory = instance.memories[0].clone();
+        assert_eq!(&memory.data()[..6], b"hello\x01");
+        let mut execution = Execution::new(instance, memory);
+        let mut call = |name: &str, args: &[Value]| {
+            let funcidx = execution.instance().find_funcidx(name).unwrap();
+            execution.prepare(funcidx, args).unwrap();
+            execution.run().unwrap();
+            execution.result().unwrap()[0]
+        };
+        // The start function ran once, before the snapshot, and not again since.
+        assert_eq!(call("runs", &[]), Value::I32(1));
+        assert_eq!(call("big", &[]), Value::I64(-1234567890123));
+        assert_eq!(call("f", &[]), Value::F64(2.5));
+        assert_eq!(call("load", &[Value::I32(70000 + 7 * 4)]), Value::I32(49));
+    }
+
+    #[test]
+    fn image_segments_split_on_long_gaps() {
+        let mut data = vec![0u8; 100];
+        data[3] = 1;
+        data[10] = 2; // Close enough to join the first.
+        data[50] = 3;
+        data[99] = 4;
+        let segments = image_segments(&data);
+        let spans: Vec<_> = segments.iter().map(|(o, b)| (*o, b.len())).collect();
+        assert_eq!(spans, vec![(3, 8), (50, 1), (99, 1)]);
+    }
+
+    #[test]
+    fn imported_memory_is_refused() {
+        let wat = r#"(module (import "spectest" "memory" (memory 1)))"#;
+        let module = Module::load(&wat::parse_str(wat).unwrap()).unwrap();
+        assert!(matches!(
+            preinitialize(module, &crate::spectest(), None),
+            Err(SnapshotError::UnsupportedMemory)
+        ));
+    }
+}
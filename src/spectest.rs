@@ -0,0 +1,122 @@
+// Copyright (C) 2025 Ryan Daum <ryan.daum@gmail.com> This program is free
+// software: you can redistribute it and/or modify it under the terms of the GNU
+// General Public License as published by the Free Software Foundation, version
+// 3.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+//! The `spectest` module which the WebAssembly spec test suite expects to be able to import from.
+
+use crate::exec::Value;
+use crate::instance::TableInstance;
+use crate::linker::Linker;
+use crate::memory::VectorMemory;
+use crate::memory::WASM_PAGE_SIZE;
+use crate::module::ReferenceType;
+use std::sync::Arc;
+
+/// A linker providing the canonical `spectest` imports: the `print` functions, `global_i32` and
+/// friends, a 10 to 20 element funcref `table`, and a 1 to 2 page `memory`. What's printed is
+/// thrown away; see `spectest_with_print` to see it.
+pub fn spectest() -> Linker {
+    spectest_with_print(|_| {})
+}
+
+/// As `spectest`, with the `print` functions handing their arguments to `print`.
+pub fn spectest_with_print(print: impl Fn(&[Value]) + Send + Sync + 'static) -> Linker {
+    let print = Arc::new(print);
+    let mut linker = Linker::new();
+    for name in [
+        "print",
+        "print_i32",
+        "print_i64",
+        "print_f32",
+        "print_f64",
+        "print_i32_f32",
+        "print_f64_f64",
+    ] {
+        let print = print.clone();
+        linker.func("spectest", name, move |args| {
+            print(args);
+            Ok(vec![])
+        });
+    }
+    linker
+        .global("spectest", "global_i32", Value::I32(666), false)
+        .global("spectest", "global_i64", Value::I64(666), false)
+        .global("spectest", "global_f32", Value::F32(666.6), false)
+        .global("spectest", "global_f64", Value::F64(666.6), false)
+        .table(
+            "spectest",
+            "table",
+            TableInstance::new(ReferenceType::FuncRef, (10, Some(20))).expect("ten elements fit"),
+        )
+        .memory(
+            "spectest",
+            "memory",
+            VectorMemory::new(WASM_PAGE_SIZE, Some(2 * WASM_PAGE_SIZE)),
+        );
+    linker
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::exec::{Execution, Value};
+    use crate::instance::LinkError;
+    use crate::memory::Memory;
+    use crate::module::Module;
+    use crate::spectest::{spectest, spectest_with_print};
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn spectest_imports() {
+        let wat = r#"(module
+            (import "spectest" "print_i32" (func $print (param i32)))
+            (import "spectest" "global_i32" (global $g i32))
+            (import "spectest" "table" (table 10 funcref))
+            (import "spectest" "memory" (memory 1))
+            (global $h i32 (i32.add (global.get $g) (i32.const 1)))
+            (func (export "f") (result i32)
+                (call $print (global.get $g))
+                (i32.add (global.get $h) (memory.size))))"#;
+        let module = Module::load(&wat::parse_str(wat).unwrap()).unwrap();
+        let printed = Arc::new(Mutex::new(vec![]));
+        let p = printed.clone();
+        let linker = spectest_with_print(move |args| p.lock().unwrap().extend_from_slice(args));
+        let instance = linker.instantiate(module).unwrap();
+        assert_eq!(instance.memories[0].size(), 65536);
+        let funcidx = instance.find_funcidx("f").unwrap();
+        let memory = instance.memories[0].clone();
+        let mut execution = Execution::new(instance, memory);
+        execution.prepare(funcidx, &[]).unwrap();
+        execution.run().unwrap();
+        assert_eq!(execution.result().unwrap(), &[Value::I32(667 + 1)]);
+        assert_eq!(*printed.lock().unwrap(), [Value::I32(666)]);
+        assert_eq!(execution.instance().tables[0].size(), 10);
+
+        // Imports have to match what's provided.
+        for wat in [
+            r#"(module (import "spectest" "global_i32" (global (mut i32))))"#,
+            r#"(module (import "spectest" "global_i32" (global i64)))"#,
+            r#"(module (import "spectest" "table" (table 11 funcref)))"#,
+            r#"(module (import "spectest" "table" (table 10 15 funcref)))"#,
+            r#"(module (import "spectest" "memory" (memory 2)))"#,
+            r#"(module (import "spectest" "memory" (func)))"#,
+        ] {
+            let module = Module::load(&wat::parse_str(wat).unwrap()).unwrap();
+            assert!(
+                matches!(
+                    spectest().instantiate(module),
+                    Err(LinkError::IncompatibleImport(_, _))
+                ),
+                "{wat}"
+            );
+        }
+    }
+}
@@ -11,140 +11,496 @@
 // this program. If not, see <https://www.gnu.org/licenses/>.
 //
 
-use crate::exec::{Fault, Value};
+use crate::exec::Fault;
+#[cfg(feature = "allocator-api")]
+use allocator_api2::alloc::Allocator;
+use std::fmt::Debug;
 
-/// Entries in the stack are raw u64s and are interpreted as the appropriate type when popped.
-/// We could store `Value` here, but it doesn't have a u32/u64 variant, and all uses are explicitly
-/// already casting to the appropriate type, anyway, so no need packing/unpacking a variant everywhere.
-#[derive(Debug)]
-pub struct Stack {
-    data: Vec<u64>,
+/// What a stack slot was pushed as. Slots are stored as raw u64s either way, but in debug builds
+/// we keep the kind of each slot alongside it and fault with `Fault::StackKindMismatch` if it's
+/// popped as anything else, so an f32 being read back as an i32 (or a ref as an integer) shows up
+/// as a failure rather than as silently reinterpreted bits.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SlotKind {
+    I32,
+    I64,
+    F32,
+    F64,
+    Ref,
+    /// Either half of a v128, which takes up two slots.
+    V128,
+    #[default]
+    Unit,
+}
+
+/// Where an execution's value stacks and locals keep their slots, chosen by the `S` parameter of
+/// `Execution`. `HeapSlots`, the default, grows them on the heap as far as they need to go;
+/// `FixedSlots` holds them in arrays of a fixed size, for hosts which can't have them allocate
+/// while running; and with the `allocator-api` feature, `AllocatorSlots` takes them from an
+/// allocator of the embedder's choosing.
+pub trait SlotStorage: Debug + Clone + Default + Send + 'static {
+    /// Whether pushes can run out of room. If so, they're checked for after every op, and the
+    /// run faults with `Fault::StackExhausted`.
+    const BOUNDED: bool;
+    type Buffer<E: Copy + Default + Debug + Send + 'static>: SlotBuffer<E>;
+}
+
+/// The storage for one stack's worth of slots.
+pub trait SlotBuffer<E>: Debug + Clone + Default + Send {
+    /// An empty buffer, with room for `slots` if it can make it up front.
+    fn with_capacity(slots: usize) -> Self;
+    fn as_slice(&self) -> &[E];
+    fn as_mut_slice(&mut self) -> &mut [E];
+    /// Add `value` on the end, or fault with `Fault::StackExhausted` if there's no room.
+    fn push(&mut self, value: E) -> Result<(), Fault>;
+    fn truncate(&mut self, len: usize);
+}
+
+/// Slots in a `Vec`, which grows as needed.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HeapSlots;
+
+impl SlotStorage for HeapSlots {
+    const BOUNDED: bool = false;
+    type Buffer<E: Copy + Default + Debug + Send + 'static> = Vec<E>;
+}
+
+impl<E: Copy + Debug + Send> SlotBuffer<E> for Vec<E> {
+    fn with_capacity(slots: usize) -> Self {
+        Vec::with_capacity(slots)
+    }
+
+    fn as_slice(&self) -> &[E] {
+        self
+    }
+
+    fn as_mut_slice(&mut self) -> &mut [E] {
+        self
+    }
+
+    #[inline]
+    fn push(&mut self, value: E) -> Result<(), Fault> {
+        Vec::push(self, value);
+        Ok(())
+    }
+
+    fn truncate(&mut self, len: usize) {
+        Vec::truncate(self, len);
+    }
+}
+
+/// At most `N` slots to each frame's value stack, and `N` to its locals, held inline in the
+/// frame. A function which needs more faults with `Fault::StackExhausted`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FixedSlots<const N: usize>;
+
+impl<const N: usize> SlotStorage for FixedSlots<N> {
+    const BOUNDED: bool = true;
+    type Buffer<E: Copy + Default + Debug + Send + 'static> = FixedBuffer<E, N>;
+}
+
+/// Up to `N` slots in an array.
+#[derive(Debug, Clone)]
+pub struct FixedBuffer<E, const N: usize> {
+    slots: [E; N],
+    len: usize,
+}
+
+impl<E: Copy + Default, const N: usize> Default for FixedBuffer<E, N> {
+    fn default() -> Self {
+        FixedBuffer {
+            slots: [E::default(); N],
+            len: 0,
+        }
+    }
+}
+
+impl<E: Copy + Default + Debug + Send, const N: usize> SlotBuffer<E> for FixedBuffer<E, N> {
+    fn with_capacity(_slots: usize) -> Self {
+        Self::default()
+    }
+
+    fn as_slice(&self) -> &[E] {
+        &self.slots[..self.len]
+    }
+
+    fn as_mut_slice(&mut self) -> &mut [E] {
+        &mut self.slots[..self.len]
+    }
+
+    #[inline]
+    fn push(&mut self, value: E) -> Result<(), Fault> {
+        let slot = self.slots.get_mut(self.len).ok_or(Fault::StackExhausted)?;
+        *slot = value;
+        self.len += 1;
+        Ok(())
+    }
+
+    fn truncate(&mut self, len: usize) {
+        self.len = self.len.min(len);
+    }
 }
 
-impl Default for Stack {
+/// Slots in vectors from the allocator `A`, which grow as needed. Every stack gets its allocator
+/// from `A::default()`, so an arena to allocate from has to be reachable from there: a handle to
+/// one the embedder set up beforehand, say, and resets between runs.
+#[cfg(feature = "allocator-api")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AllocatorSlots<A>(std::marker::PhantomData<A>);
+
+#[cfg(feature = "allocator-api")]
+impl<A> SlotStorage for AllocatorSlots<A>
+where
+    A: Allocator + Debug + Clone + Default + Send + 'static,
+{
+    const BOUNDED: bool = false;
+    type Buffer<E: Copy + Default + Debug + Send + 'static> = AllocatorBuffer<E, A>;
+}
+
+/// Slots in a vector from the allocator `A`.
+#[cfg(feature = "allocator-api")]
+#[derive(Debug, Clone)]
+pub struct AllocatorBuffer<E, A: Allocator>(allocator_api2::vec::Vec<E, A>);
+
+#[cfg(feature = "allocator-api")]
+impl<E, A: Allocator + Default> Default for AllocatorBuffer<E, A> {
     fn default() -> Self {
-        Self::new()
+        AllocatorBuffer(allocator_api2::vec::Vec::new_in(A::default()))
+    }
+}
+
+#[cfg(feature = "allocator-api")]
+impl<E, A> SlotBuffer<E> for AllocatorBuffer<E, A>
+where
+    E: Copy + Debug + Send,
+    A: Allocator + Debug + Clone + Default + Send,
+{
+    fn with_capacity(slots: usize) -> Self {
+        AllocatorBuffer(allocator_api2::vec::Vec::with_capacity_in(
+            slots,
+            A::default(),
+        ))
+    }
+
+    fn as_slice(&self) -> &[E] {
+        &self.0
+    }
+
+    fn as_mut_slice(&mut self) -> &mut [E] {
+        &mut self.0
+    }
+
+    #[inline]
+    fn push(&mut self, value: E) -> Result<(), Fault> {
+        self.0.push(value);
+        Ok(())
+    }
+
+    fn truncate(&mut self, len: usize) {
+        self.0.truncate(len);
     }
 }
 
+/// A single stack slot, moved around without interpretation by ops like `drop` and `select`
+/// which don't care about the type of their operands.
+#[derive(Debug, Clone, Copy)]
+pub struct Slot {
+    bits: u64,
+    #[cfg(debug_assertions)]
+    kind: SlotKind,
+}
+
+/// Entries in the stack are uniform 64-bit slots, interpreted as the appropriate type when popped.
+/// We could store `Value` here, but it doesn't have a u32/u64 variant, and all uses are explicitly
+/// already casting to the appropriate type, anyway, so no need packing/unpacking a variant everywhere.
+/// Every value takes up exactly one slot, except v128 which takes two.
+///
+/// Pushes return nothing, so that the ops don't all have to check them. A push which doesn't fit
+/// in bounded storage is dropped and marks the stack `exhausted` instead, for the interpreter to
+/// fault on before the next op.
+#[derive(Debug, Clone, Default)]
+pub struct Stack<S: SlotStorage = HeapSlots> {
+    data: S::Buffer<u64>,
+    #[cfg(debug_assertions)]
+    kinds: S::Buffer<SlotKind>,
+    exhausted: bool,
+}
+
 impl Stack {
     pub fn new() -> Self {
-        Stack { data: vec![] }
+        Self::default()
+    }
+}
+
+impl<S: SlotStorage> Stack<S> {
+    pub fn with_capacity(slots: usize) -> Self {
+        Stack {
+            data: SlotBuffer::with_capacity(slots),
+            #[cfg(debug_assertions)]
+            kinds: SlotBuffer::with_capacity(slots),
+            exhausted: false,
+        }
     }
 
     pub fn width(&self) -> usize {
-        self.data.len()
+        self.data.as_slice().len()
+    }
+
+    /// The raw bits of every slot, bottom first, for inspection.
+    pub fn slots(&self) -> &[u64] {
+        self.data.as_slice()
+    }
+
+    /// The kind of every slot, bottom first. Only debug builds keep track of these.
+    pub(crate) fn kinds(&self) -> Option<&[SlotKind]> {
+        #[cfg(debug_assertions)]
+        return Some(self.kinds.as_slice());
+        #[cfg(not(debug_assertions))]
+        None
+    }
+
+    /// Whether a push has been dropped for want of room since the stack was last emptied.
+    #[inline]
+    pub(crate) fn exhausted(&self) -> bool {
+        S::BOUNDED && self.exhausted
+    }
+
+    /// A stack holding `data`. Debug builds need the kind of each slot as well, and return
+    /// `None` without them, as does storage without room for them all.
+    pub(crate) fn from_raw(data: Vec<u64>, _kinds: Option<Vec<SlotKind>>) -> Option<Self> {
+        let mut stack = Self::with_capacity(data.len());
+        #[cfg(debug_assertions)]
+        {
+            let kinds = _kinds.filter(|k| k.len() == data.len())?;
+            for (bits, kind) in data.into_iter().zip(kinds) {
+                stack.push(bits, kind);
+            }
+        }
+        #[cfg(not(debug_assertions))]
+        for bits in data {
+            stack.push(bits, SlotKind::Unit);
+        }
+        (!stack.exhausted()).then_some(stack)
     }
 
     pub fn shrink_to(&mut self, width: usize) {
         self.data.truncate(width);
+        #[cfg(debug_assertions)]
+        self.kinds.truncate(width);
+        if width == 0 {
+            self.exhausted = false;
+        }
+    }
+
+    /// Discard everything above `width` except the top `n` slots, which are moved down to sit
+    /// directly on top of `width`. This is how results are carried out of a scope.
+    pub fn keep_top(&mut self, n: usize, width: usize) -> Result<(), Fault> {
+        let len = self.width();
+        if len < width + n {
+            return Err(Fault::StackUnderflow);
+        }
+        self.data.as_mut_slice().copy_within(len - n..len, width);
+        self.data.truncate(width + n);
+        #[cfg(debug_assertions)]
+        {
+            self.kinds.as_mut_slice().copy_within(len - n..len, width);
+            self.kinds.truncate(width + n);
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn push(&mut self, bits: u64, _kind: SlotKind) {
+        let pushed = self.data.push(bits);
+        #[cfg(debug_assertions)]
+        let pushed = pushed.and_then(|_| self.kinds.push(_kind));
+        if pushed.is_err() {
+            self.exhausted = true;
+        }
+    }
+
+    /// Check that the `n` slots at `at` were all pushed as `kind`. Only debug builds can tell.
+    #[inline]
+    fn check_kinds(&self, _at: usize, _n: usize, _kind: SlotKind) -> Result<(), Fault> {
+        #[cfg(debug_assertions)]
+        if self.kinds.as_slice()[_at.._at + _n]
+            .iter()
+            .any(|k| *k != _kind)
+        {
+            return Err(Fault::StackKindMismatch);
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn pop(&mut self, kind: SlotKind) -> Result<u64, Fault> {
+        let bits = self.top(kind)?;
+        self.data.truncate(self.width() - 1);
+        #[cfg(debug_assertions)]
+        self.kinds.truncate(self.width());
+        Ok(bits)
+    }
+
+    #[inline]
+    fn top(&self, kind: SlotKind) -> Result<u64, Fault> {
+        let bits = *self.slots().last().ok_or(Fault::StackUnderflow)?;
+        self.check_kinds(self.width() - 1, 1, kind)?;
+        Ok(bits)
+    }
+
+    /// Pop the top slot, whatever it holds, so long as it isn't half of a v128.
+    pub fn pop_slot(&mut self) -> Result<Slot, Fault> {
+        let bits = *self.slots().last().ok_or(Fault::StackUnderflow)?;
+        #[cfg(debug_assertions)]
+        {
+            let kind = *self
+                .kinds
+                .as_slice()
+                .last()
+                .expect("stack kinds out of sync");
+            if kind == SlotKind::V128 {
+                return Err(Fault::StackKindMismatch);
+            }
+            self.data.truncate(self.width() - 1);
+            self.kinds.truncate(self.width());
+            Ok(Slot { bits, kind })
+        }
+        #[cfg(not(debug_assertions))]
+        {
+            self.data.truncate(self.width() - 1);
+            Ok(Slot { bits })
+        }
+    }
+
+    pub fn push_slot(&mut self, slot: Slot) {
+        #[cfg(debug_assertions)]
+        self.push(slot.bits, slot.kind);
+        #[cfg(not(debug_assertions))]
+        self.push(slot.bits, SlotKind::Unit);
+    }
+
+    /// Push a copy of the `n` slots at `at` in `from`, bottom first.
+    pub fn push_copy(&mut self, from: &Stack<S>, at: usize, n: usize) -> Result<(), Fault> {
+        let slots = from.slots().get(at..at + n).ok_or(Fault::StackUnderflow)?;
+        for &bits in slots {
+            self.data.push(bits)?;
+        }
+        #[cfg(debug_assertions)]
+        for &kind in &from.kinds.as_slice()[at..at + n] {
+            self.kinds.push(kind)?;
+        }
+        Ok(())
+    }
+
+    /// Overwrite the `n` slots at `at` in `to` with the top `n` slots of this stack, which are
+    /// popped if `pop` is set. The slots being overwritten must be of the same kinds.
+    pub fn store_top(
+        &mut self,
+        to: &mut Stack<S>,
+        at: usize,
+        n: usize,
+        pop: bool,
+    ) -> Result<(), Fault> {
+        let len = self.width();
+        if len < n || to.width() < at + n {
+            return Err(Fault::StackUnderflow);
+        }
+        #[cfg(debug_assertions)]
+        if to.kinds.as_slice()[at..at + n] != self.kinds.as_slice()[len - n..] {
+            return Err(Fault::StackKindMismatch);
+        }
+        to.data.as_mut_slice()[at..at + n].copy_from_slice(&self.slots()[len - n..]);
+        if pop {
+            self.shrink_to(len - n);
+        }
+        Ok(())
     }
 }
-impl Stack {
+
+impl Slot {
+    /// Whether two slots hold the same kind of value. Only meaningful in debug builds; always true
+    /// otherwise.
+    pub fn same_kind(&self, _other: &Slot) -> bool {
+        #[cfg(debug_assertions)]
+        return self.kind == _other.kind;
+        #[cfg(not(debug_assertions))]
+        true
+    }
+}
+
+impl<S: SlotStorage> Stack<S> {
     pub fn push_i32(&mut self, value: i32) {
-        let value = value as i64;
-        self.data.push(value as u64);
+        self.push(value as u32 as u64, SlotKind::I32);
     }
 
     pub fn push_i64(&mut self, value: i64) {
-        self.data.push(value as u64);
+        self.push(value as u64, SlotKind::I64);
     }
 
     pub fn push_u32(&mut self, value: u32) {
-        let value = value as u64;
-        self.data.push(value);
+        self.push(value as u64, SlotKind::I32);
     }
 
     pub fn push_u64(&mut self, value: u64) {
-        self.data.push(value);
+        self.push(value, SlotKind::I64);
     }
 
     pub fn push_f32(&mut self, value: f32) {
-        let bits = value.to_bits();
-        let value = bits as u64;
-        self.data.push(value);
+        self.push(value.to_bits() as u64, SlotKind::F32);
     }
 
     pub fn push_f64(&mut self, value: f64) {
-        self.data.push(value.to_bits());
+        self.push(value.to_bits(), SlotKind::F64);
+    }
+
+    /// Read the i32 in the slot at `at`, counting from the bottom.
+    pub fn i32_at(&self, at: usize) -> Result<i32, Fault> {
+        let bits = *self.slots().get(at).ok_or(Fault::StackUnderflow)?;
+        self.check_kinds(at, 1, SlotKind::I32)?;
+        Ok(bits as u32 as i32)
     }
 
     pub fn top_i32(&self) -> Result<i32, Fault> {
-        self.data
-            .last()
-            .cloned()
-            .ok_or(Fault::StackUnderflow)
-            .map(|v| v as u32 as i32)
+        self.top(SlotKind::I32).map(|v| v as u32 as i32)
     }
 
     pub fn pop_i32(&mut self) -> Result<i32, Fault> {
-        self.data
-            .pop()
-            .ok_or(Fault::StackUnderflow)
-            .map(|v| v as u32 as i32)
+        self.pop(SlotKind::I32).map(|v| v as u32 as i32)
     }
 
     pub fn pop_i64(&mut self) -> Result<i64, Fault> {
-        self.data
-            .pop()
-            .ok_or(Fault::StackUnderflow)
-            .map(|v| v as i64)
+        self.pop(SlotKind::I64).map(|v| v as i64)
     }
 
     pub fn top_f32(&self) -> Result<f32, Fault> {
-        self.data
-            .last()
-            .cloned()
-            .ok_or(Fault::StackUnderflow)
-            .map(|v| f32::from_bits(v as u32))
+        self.top(SlotKind::F32).map(|v| f32::from_bits(v as u32))
     }
 
     pub fn pop_u32(&mut self) -> Result<u32, Fault> {
-        self.data
-            .pop()
-            .ok_or(Fault::StackUnderflow)
-            .map(|v| v as u32)
+        self.pop(SlotKind::I32).map(|v| v as u32)
     }
 
     pub fn top_f64(&self) -> Result<f64, Fault> {
-        self.data
-            .last()
-            .cloned()
-            .ok_or(Fault::StackUnderflow)
-            .map(f64::from_bits)
+        self.top(SlotKind::F64).map(f64::from_bits)
     }
 
     pub fn top_u32(&self) -> Result<u32, Fault> {
-        self.data
-            .last()
-            .cloned()
-            .ok_or(Fault::StackUnderflow)
-            .map(|v| v as u32)
+        self.top(SlotKind::I32).map(|v| v as u32)
     }
 
     pub fn pop_u64(&mut self) -> Result<u64, Fault> {
-        self.data.pop().ok_or(Fault::StackUnderflow)
+        self.pop(SlotKind::I64)
     }
 
     pub fn pop_f32(&mut self) -> Result<f32, Fault> {
-        self.data
-            .pop()
-            .ok_or(Fault::StackUnderflow)
-            .map(|v| f32::from_bits(v as u32))
+        self.pop(SlotKind::F32).map(|v| f32::from_bits(v as u32))
     }
 
     pub fn top_i64(&self) -> Result<i64, Fault> {
-        self.data
-            .last()
-            .cloned()
-            .ok_or(Fault::StackUnderflow)
-            .map(|v| v as i64)
+        self.top(SlotKind::I64).map(|v| v as i64)
     }
     pub fn pop_f64(&mut self) -> Result<f64, Fault> {
-        self.data
-            .pop()
-            .ok_or(Fault::StackUnderflow)
-            .map(f64::from_bits)
+        self.pop(SlotKind::F64).map(f64::from_bits)
     }
 
     pub fn push_ref(&mut self, value: Option<u32>) {
@@ -153,25 +509,144 @@ impl Stack {
             Some(v) => v as u64,
             None => u32::MAX as u64,
         };
-        self.data.push(encoded);
+        self.push(encoded, SlotKind::Ref);
     }
 
-    pub fn pop_ref(&mut self) -> Result<Option<u32>, Fault> {
-        let raw = self.data.pop().ok_or(Fault::StackUnderflow)?;
+    fn decode_ref(raw: u64) -> Option<u32> {
         if raw == u32::MAX as u64 {
-            Ok(None)
+            None
         } else {
-            Ok(Some(raw as u32))
+            Some(raw as u32)
+        }
+    }
+
+    pub fn pop_ref(&mut self) -> Result<Option<u32>, Fault> {
+        self.pop(SlotKind::Ref).map(Self::decode_ref)
+    }
+
+    pub fn top_ref(&self) -> Result<Option<u32>, Fault> {
+        self.top(SlotKind::Ref).map(Self::decode_ref)
+    }
+
+    /// v128s are pushed low half first, so the high half is on top.
+    pub fn push_v128(&mut self, value: u128) {
+        self.push(value as u64, SlotKind::V128);
+        self.push((value >> 64) as u64, SlotKind::V128);
+    }
+
+    pub fn pop_v128(&mut self) -> Result<u128, Fault> {
+        let hi = self.pop(SlotKind::V128)?;
+        let lo = self.pop(SlotKind::V128)?;
+        Ok((hi as u128) << 64 | lo as u128)
+    }
+
+    pub fn top_v128(&self) -> Result<u128, Fault> {
+        let slots = self.slots();
+        let len = slots.len();
+        if len < 2 {
+            return Err(Fault::StackUnderflow);
         }
+        self.check_kinds(len - 2, 2, SlotKind::V128)?;
+        Ok((slots[len - 1] as u128) << 64 | slots[len - 2] as u128)
     }
 
-    pub fn pop_value(&mut self) -> Result<Value, Fault> {
-        let raw = self.data.pop().ok_or(Fault::StackUnderflow)?;
-        // For now, assume it's an i32 (could be improved to track types)
-        Ok(Value::I32(raw as i32))
+    pub fn push_unit(&mut self) {
+        self.push(0, SlotKind::Unit);
+    }
+
+    pub fn pop_unit(&mut self) -> Result<(), Fault> {
+        self.pop(SlotKind::Unit).map(|_| ())
     }
 
     pub fn top_u64(&self) -> Result<u64, Fault> {
-        self.data.last().cloned().ok_or(Fault::StackUnderflow)
+        self.top(SlotKind::I64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::stack::{FixedSlots, Stack};
+
+    #[test]
+    fn slots_round_trip_bits() {
+        let mut stack = Stack::new();
+        stack.push_f32(-0.0);
+        stack.push_ref(None);
+        stack.push_i32(-1);
+
+        let a = stack.pop_slot().unwrap();
+        let b = stack.pop_slot().unwrap();
+        let c = stack.pop_slot().unwrap();
+        assert!(!a.same_kind(&b) || cfg!(not(debug_assertions)));
+        stack.push_slot(c);
+        stack.push_slot(b);
+        stack.push_slot(a);
+
+        assert_eq!(stack.pop_i32().unwrap(), -1);
+        assert_eq!(stack.pop_ref().unwrap(), None);
+        assert_eq!(stack.pop_f32().unwrap().to_bits(), (-0.0f32).to_bits());
+        assert_eq!(stack.width(), 0);
+    }
+
+    #[test]
+    fn v128_takes_two_slots() {
+        let mut stack = Stack::new();
+        let v = 0x0011_2233_4455_6677_8899_aabb_ccdd_eeff_u128;
+        stack.push_v128(v);
+        assert_eq!(stack.width(), 2);
+        assert_eq!(stack.top_v128().unwrap(), v);
+        assert_eq!(stack.pop_v128().unwrap(), v);
+        assert_eq!(stack.width(), 0);
+    }
+
+    #[test]
+    fn slots_copy_between_stacks() {
+        let mut locals = Stack::new();
+        locals.push_i64(7);
+        locals.push_v128(u128::MAX - 1);
+        let mut stack = Stack::new();
+        stack.push_copy(&locals, 1, 2).unwrap();
+        stack.push_v128(3);
+        stack.store_top(&mut locals, 1, 2, true).unwrap();
+        assert_eq!(stack.pop_v128().unwrap(), u128::MAX - 1);
+        assert_eq!(locals.pop_v128().unwrap(), 3);
+        assert!(stack.push_copy(&locals, 1, 1).is_err());
+    }
+
+    #[test]
+    fn fixed_slots_run_out_of_room() {
+        let mut stack = Stack::<FixedSlots<2>>::default();
+        stack.push_v128(1);
+        assert!(!stack.exhausted());
+        stack.push_i32(2);
+        assert!(stack.exhausted());
+        assert_eq!(stack.width(), 2);
+        assert_eq!(stack.pop_v128().unwrap(), 1);
+        stack.shrink_to(0);
+        assert!(!stack.exhausted());
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    fn mismatched_kinds_fault() {
+        use crate::exec::Fault;
+
+        let mut stack = Stack::new();
+        stack.push_f32(1.0);
+        assert!(matches!(stack.pop_i32(), Err(Fault::StackKindMismatch)));
+        assert!(matches!(stack.i32_at(0), Err(Fault::StackKindMismatch)));
+        // Nothing is popped by a pop which faults.
+        assert_eq!(stack.pop_f32().unwrap(), 1.0);
+
+        stack.push_v128(1);
+        assert!(matches!(stack.pop_slot(), Err(Fault::StackKindMismatch)));
+        let mut locals = Stack::new();
+        locals.push_i64(0);
+        locals.push_i64(0);
+        assert!(matches!(
+            stack.store_top(&mut locals, 0, 2, true),
+            Err(Fault::StackKindMismatch)
+        ));
+        assert_eq!(stack.pop_v128().unwrap(), 1);
     }
 }
@@ -0,0 +1,342 @@
+// Copyright (C) 2025 Ryan Daum <ryan.daum@gmail.com> This program is free
+// software: you can redistribute it and/or modify it under the terms of the GNU
+// General Public License as published by the Free Software Foundation, version
+// 3.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+//! Recording what a run did, op by op, and checking that a later run does exactly the same.
+//!
+//! A trace is a sequence of events, one per op executed (or one per call and branch, at lower
+//! fidelity), each taken just before its op runs: which function and op, how far the value stack
+//! has moved since the previous event, and the slot on top of it. That's enough to pin down
+//! where two runs which were meant to agree, such as a report from the field and a local
+//! reproduction, first part ways.
+
+use crate::exec::Fault;
+use crate::guest_coverage::GuestCoverage;
+use crate::module::{write_sleb128, write_uleb128, LEB128Reader};
+use crate::op::Op;
+use crate::stack::{SlotStorage, Stack};
+use crate::DecodeError;
+
+/// How much of a run goes into a trace.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceLevel {
+    /// Every op.
+    Ops,
+    /// Only calls, branches, returns and `if`/`else`: enough to follow the path taken through
+    /// the code, at a fraction of the size.
+    Control,
+}
+
+/// The state of a run as one op was about to execute.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceEvent {
+    pub funcidx: u32,
+    /// The index of the op in its function's decoded body.
+    pub pc: u32,
+    /// How many slots the value stack has grown (or, negative, shrunk) by since the previous
+    /// event. Calls start each function on a fresh stack, so this jumps across them.
+    pub stack_delta: i64,
+    /// The raw bits of the slot on top of the value stack, or 0 if it's empty.
+    pub top: u64,
+}
+
+/// A recorded trace, in a compact binary form: a level byte, and then each event as LEB128
+/// numbers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Trace {
+    level: TraceLevel,
+    data: Vec<u8>,
+    events: u64,
+}
+
+impl Trace {
+    fn new(level: TraceLevel) -> Self {
+        Trace {
+            level,
+            data: vec![],
+            events: 0,
+        }
+    }
+
+    pub fn level(&self) -> TraceLevel {
+        self.level
+    }
+
+    /// How many events were recorded.
+    pub fn len(&self) -> u64 {
+        self.events
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events == 0
+    }
+
+    /// The trace's binary form, for saving or sending elsewhere.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.data.len() + 1);
+        bytes.push(match self.level {
+            TraceLevel::Ops => 0,
+            TraceLevel::Control => 1,
+        });
+        bytes.extend_from_slice(&self.data);
+        bytes
+    }
+
+    /// Read back a trace from `to_bytes`, checking every event is well formed.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let (&level, data) = bytes
+            .split_first()
+            .ok_or_else(|| DecodeError::FailedToDecode("empty trace".to_string()))?;
+        let level = match level {
+            0 => TraceLevel::Ops,
+            1 => TraceLevel::Control,
+            _ => {
+                return Err(DecodeError::FailedToDecode(format!(
+                    "unknown trace level {level}"
+                )))
+            }
+        };
+        let mut trace = Trace {
+            level,
+            data: data.to_vec(),
+            events: 0,
+        };
+        let mut reader = LEB128Reader::new(data, 0);
+        while reader.remaining() > 0 {
+            read_event(&mut reader)?;
+            trace.events += 1;
+        }
+        Ok(trace)
+    }
+
+    /// Every event, in the order they happened.
+    pub fn events(&self) -> impl Iterator<Item = TraceEvent> + '_ {
+        let mut reader = LEB128Reader::new(&self.data, 0);
+        std::iter::from_fn(move || match reader.remaining() > 0 {
+            // Events were either written by us or checked by `from_bytes`.
+            true => read_event(&mut reader).ok(),
+            false => None,
+        })
+    }
+
+    fn push(&mut self, event: &TraceEvent) {
+        write_uleb128(&mut self.data, event.funcidx as u64);
+        write_uleb128(&mut self.data, event.pc as u64);
+        write_sleb128(&mut self.data, event.stack_delta);
+        write_uleb128(&mut self.data, event.top);
+        self.events += 1;
+    }
+}
+
+fn read_event(reader: &mut LEB128Reader) -> Result<TraceEvent, DecodeError> {
+    Ok(TraceEvent {
+        funcidx: reader.load_imm_varuint32()?,
+        pc: reader.load_imm_varuint32()?,
+        stack_delta: reader.load_imm_signed_varint64()?,
+        top: reader.load_imm_varuint64()?,
+    })
+}
+
+/// Whether `op` shows up in a `TraceLevel::Control` trace.
+fn is_control(op: &Op) -> bool {
+    match op {
+        Op::Call(_)
+        | Op::CallIndirect(..)
+        | Op::Br(_)
+        | Op::BrIf(_)
+        | Op::BrTable(..)
+        | Op::Return
+        | Op::If(_)
+        | Op::Else(_) => true,
+        #[cfg(feature = "optimize")]
+        Op::BrIfI32Cmp(..) | Op::BrIfEqz(_) => true,
+        _ => false,
+    }
+}
+
+/// A trace being recorded, or one a run is being checked against, or coverage being counted,
+/// threaded through execution.
+#[derive(Debug, Clone)]
+pub(crate) enum Tracer {
+    Record {
+        trace: Trace,
+        last_width: usize,
+    },
+    Replay {
+        trace: Trace,
+        /// How far through the trace's data we've checked.
+        position: usize,
+        /// How many events matched so far.
+        matched: u64,
+        last_width: usize,
+    },
+    Cover(GuestCoverage),
+}
+
+impl Tracer {
+    pub(crate) fn record(level: TraceLevel) -> Self {
+        Tracer::Record {
+            trace: Trace::new(level),
+            last_width: 0,
+        }
+    }
+
+    pub(crate) fn replay(trace: Trace) -> Self {
+        Tracer::Replay {
+            trace,
+            position: 0,
+            matched: 0,
+            last_width: 0,
+        }
+    }
+
+    /// Note that `op`, at `pc` in `funcidx`, is about to run. When replaying, faults with the
+    /// number of the first event which doesn't match.
+    #[inline]
+    pub(crate) fn observe<S: SlotStorage>(
+        &mut self,
+        funcidx: u32,
+        pc: usize,
+        op: &Op,
+        stack: &Stack<S>,
+    ) -> Result<(), Fault> {
+        let (level, last_width) = match self {
+            Tracer::Record {
+                trace, last_width, ..
+            }
+            | Tracer::Replay {
+                trace, last_width, ..
+            } => (trace.level, last_width),
+            Tracer::Cover(coverage) => {
+                coverage.hit(funcidx, pc);
+                return Ok(());
+            }
+        };
+        if level == TraceLevel::Control && !is_control(op) {
+            return Ok(());
+        }
+        let width = stack.width();
+        let event = TraceEvent {
+            funcidx,
+            pc: pc as u32,
+            stack_delta: width as i64 - *last_width as i64,
+            top: stack.slots().last().copied().unwrap_or(0),
+        };
+        *last_width = width;
+        match self {
+            Tracer::Record { trace, .. } => trace.push(&event),
+            Tracer::Cover(_) => {}
+            Tracer::Replay {
+                trace,
+                position,
+                matched,
+                ..
+            } => {
+                let mut reader = LEB128Reader::new(&trace.data, *position);
+                let expected = match reader.remaining() > 0 {
+                    true => read_event(&mut reader).ok(),
+                    false => None,
+                };
+                if expected != Some(event) {
+                    return Err(Fault::TraceDivergence(*matched));
+                }
+                *position = reader.position();
+                *matched += 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// Stop tracing, handing back what was recorded, or when replaying, checking nothing was
+    /// left over from the trace.
+    pub(crate) fn finish(self) -> Result<Option<Trace>, Fault> {
+        match self {
+            Tracer::Record { trace, .. } => Ok(Some(trace)),
+            Tracer::Replay { trace, matched, .. } if matched < trace.events => {
+                Err(Fault::TraceDivergence(matched))
+            }
+            Tracer::Replay { .. } | Tracer::Cover(_) => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::exec::{ExecError, Execution, Fault, Value};
+    use crate::instance::mk_instance;
+    use crate::memory::VectorMemory;
+    use crate::module::Module;
+    use crate::trace::{Trace, TraceLevel};
+
+    fn execution() -> (Execution<VectorMemory>, u32) {
+        let wat = r#"(module
+            (func $double (param i32) (result i32) (i32.add (local.get 0) (local.get 0)))
+            (func (export "f") (param i32) (result i32)
+                (if (result i32) (i32.gt_s (local.get 0) (i32.const 10))
+                    (then (call $double (local.get 0)))
+                    (else (i32.sub (local.get 0) (i32.const 1))))))"#;
+        let module = Module::load(&wat::parse_str(wat).unwrap()).unwrap();
+        let linked = mk_instance(module).unwrap();
+        let funcidx = linked.find_funcidx("f").unwrap();
+        (Execution::new(linked, VectorMemory::new(0, None)), funcidx)
+    }
+
+    fn record(level: TraceLevel, arg: i32) -> Trace {
+        let (mut execution, f) = execution();
+        execution.record_trace(level);
+        execution.prepare(f, &[Value::I32(arg)]).unwrap();
+        execution.run().unwrap();
+        execution.finish_trace().unwrap().unwrap()
+    }
+
+    fn replay(trace: Trace, arg: i32) -> Result<(), Fault> {
+        let (mut execution, f) = execution();
+        execution.replay_trace(trace);
+        execution.prepare(f, &[Value::I32(arg)]).unwrap();
+        match execution.run() {
+            Ok(()) => {}
+            Err(ExecError::ExecutionFault(fault)) => return Err(fault),
+            Err(e) => panic!("unexpected error: {e}"),
+        }
+        execution.finish_trace().map(|t| assert!(t.is_none()))
+    }
+
+    #[test]
+    fn traces_round_trip_and_replay() {
+        let trace = record(TraceLevel::Ops, 20);
+        let restored = Trace::from_bytes(&trace.to_bytes()).unwrap();
+        assert_eq!(restored, trace);
+        assert_eq!(restored.events().count() as u64, trace.len());
+        // The second function's body shows up, with the doubled argument on top of its stack
+        // just before it returns.
+        assert!(trace.events().any(|e| e.funcidx == 0 && e.top == 40));
+
+        assert!(replay(trace.clone(), 20).is_ok());
+        // 21 takes the same path, so the first difference is the argument on top of the stack
+        // once it's been pushed, as the function scope and `local.get` are events 0 and 1.
+        assert!(matches!(
+            replay(trace.clone(), 21),
+            Err(Fault::TraceDivergence(2))
+        ));
+        // A run which takes the other arm diverges too.
+        assert!(matches!(replay(trace, 3), Err(Fault::TraceDivergence(_))));
+
+        let control = record(TraceLevel::Control, 20);
+        assert!(control.len() < record(TraceLevel::Ops, 20).len());
+        assert!(replay(control.clone(), 20).is_ok());
+        assert!(matches!(replay(control, 3), Err(Fault::TraceDivergence(_))));
+
+        assert!(Trace::from_bytes(&[]).is_err());
+        assert!(Trace::from_bytes(&[7]).is_err());
+        assert!(Trace::from_bytes(&[0, 0x80]).is_err());
+    }
+}
@@ -0,0 +1,328 @@
+// Copyright (C) 2025 Ryan Daum <ryan.daum@gmail.com> This program is free
+// software: you can redistribute it and/or modify it under the terms of the GNU
+// General Public License as published by the Free Software Foundation, version
+// 3.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+//! Walking a decoded `Program` an op at a time, for analyses which only care about some kinds of
+//! op: what memory a function touches, whether it uses floats, which globals it sets.
+
+use crate::decode::{Program, ScopeSig, ScopeType};
+use crate::op::{MemArg, Op};
+
+/// Callbacks for `Program::visit`, one for each kind of op, plus entry to and exit from scopes.
+/// Every callback does nothing by default, so a visitor implements only those it needs. `pc` is
+/// the op's index in `Program::ops`.
+pub trait OpVisitor {
+    /// A `StartScope`: a block, loop, if or the function itself is entered.
+    fn enter_scope(&mut self, _pc: usize, _sig: ScopeSig, _scope_type: ScopeType) {}
+    /// The `EndScope` matching an earlier `enter_scope`.
+    fn exit_scope(&mut self, _pc: usize, _scope_type: ScopeType) {}
+    /// Branches, returns, traps and the arms of an if.
+    fn control(&mut self, _pc: usize, _op: &Op) {}
+    fn call(&mut self, _pc: usize, _op: &Op) {}
+    /// `drop` and `select`.
+    fn parametric(&mut self, _pc: usize, _op: &Op) {}
+    /// Getting and setting locals and globals.
+    fn variable(&mut self, _pc: usize, _op: &Op) {}
+    fn table(&mut self, _pc: usize, _op: &Op) {}
+    /// Loads, stores, atomics, and the size and growth of memory. Those with an address have
+    /// their memarg passed along.
+    fn memory(&mut self, _pc: usize, _op: &Op, _memarg: Option<&MemArg>) {}
+    /// Integer constants, arithmetic, comparisons, and conversions between integer types.
+    fn integer(&mut self, _pc: usize, _op: &Op) {}
+    /// Anything taking or producing an `f32` or `f64` other than through memory, conversions to
+    /// and from integers included.
+    fn float(&mut self, _pc: usize, _op: &Op) {}
+    /// Reference types, and the GC proposal's ops on the heap.
+    fn reference(&mut self, _pc: usize, _op: &Op) {}
+}
+
+impl Program {
+    /// Call the `visitor` callback matching each op, in order.
+    pub fn visit(&self, visitor: &mut impl OpVisitor) {
+        for (pc, op) in self.ops.iter().enumerate() {
+            visit_op(visitor, pc, op);
+        }
+    }
+}
+
+fn visit_op(visitor: &mut impl OpVisitor, pc: usize, op: &Op) {
+    match op {
+        Op::StartScope(sig, scope_type) => visitor.enter_scope(pc, *sig, *scope_type),
+        Op::EndScope(scope_type) => visitor.exit_scope(pc, *scope_type),
+
+        Op::Nop
+        | Op::Unreachable
+        | Op::Throw(_)
+        | Op::ThrowRef
+        | Op::If(_)
+        | Op::Else(_)
+        | Op::Br(_)
+        | Op::BrIf(_)
+        | Op::BrTable(..)
+        | Op::Return => visitor.control(pc, op),
+        #[cfg(feature = "optimize")]
+        Op::BrIfI32Cmp(..) | Op::BrIfEqz(_) => visitor.control(pc, op),
+
+        Op::Call(_) | Op::CallIndirect(..) => visitor.call(pc, op),
+
+        Op::Drop | Op::Select | Op::SelectT(_) => visitor.parametric(pc, op),
+
+        Op::GetLocal(_)
+        | Op::SetLocal(_)
+        | Op::TeeLocal(_)
+        | Op::GetGlobal(_)
+        | Op::SetGlobal(_) => visitor.variable(pc, op),
+
+        Op::TableGet(_) | Op::TableSet(_) => visitor.table(pc, op),
+
+        Op::LoadI32(memarg)
+        | Op::LoadI64(memarg)
+        | Op::LoadF32(memarg)
+        | Op::LoadF64(memarg)
+        | Op::Load8SE(memarg)
+        | Op::Load8Ze(memarg)
+        | Op::Load16Se(memarg)
+        | Op::Load16Ze(memarg)
+        | Op::Load8I64Se(memarg)
+        | Op::Load8I64Ze(memarg)
+        | Op::Load16I64Se(memarg)
+        | Op::Load16I64Ze(memarg)
+        | Op::Load32I64Se(memarg)
+        | Op::Load32I64Ze(memarg)
+        | Op::StoreI32(memarg)
+        | Op::StoreI64(memarg)
+        | Op::StoreF32(memarg)
+        | Op::StoreF64(memarg)
+        | Op::Store8_32(memarg)
+        | Op::Store16_32(memarg)
+        | Op::Store8_64(memarg)
+        | Op::Store16_64(memarg)
+        | Op::Store32_64(memarg) => visitor.memory(pc, op, Some(memarg)),
+        Op::MemorySize | Op::MemoryGrow => visitor.memory(pc, op, None),
+        #[cfg(feature = "atomics")]
+        Op::Atomic(atomic) => visitor.memory(pc, op, atomic.memarg()),
+        #[cfg(feature = "optimize")]
+        Op::LocalLoadI32(_, memarg) | Op::ConstStoreI32(_, memarg) => {
+            visitor.memory(pc, op, Some(memarg))
+        }
+
+        Op::I32Const(_)
+        | Op::I64Const(_)
+        | Op::I32Eqz
+        | Op::I32Eq
+        | Op::I32Ne
+        | Op::I32LtS
+        | Op::I32LtU
+        | Op::I32GtS
+        | Op::I32GtU
+        | Op::I32LeS
+        | Op::I32LeU
+        | Op::I32GeS
+        | Op::I32GeU
+        | Op::I64Eqz
+        | Op::I64Eq
+        | Op::I64Ne
+        | Op::I64LtS
+        | Op::I64LtU
+        | Op::I64GtS
+        | Op::I64GtU
+        | Op::I64LeS
+        | Op::I64LeU
+        | Op::I64GeS
+        | Op::I64GeU
+        | Op::I32Clz
+        | Op::I32Ctz
+        | Op::I32Popcnt
+        | Op::I32Add
+        | Op::I32Sub
+        | Op::I32Mul
+        | Op::I32DivS
+        | Op::I32DivU
+        | Op::I32RemS
+        | Op::I32RemU
+        | Op::I32And
+        | Op::I32Or
+        | Op::I32Xor
+        | Op::I32Shl
+        | Op::I32ShrS
+        | Op::I32ShrU
+        | Op::I32Rotl
+        | Op::I32Rotr
+        | Op::I64Clz
+        | Op::I64Ctz
+        | Op::I64Popcnt
+        | Op::I64Add
+        | Op::I64Sub
+        | Op::I64Mul
+        | Op::I64DivS
+        | Op::I64DivU
+        | Op::I64RemS
+        | Op::I64RemU
+        | Op::I64And
+        | Op::I64Or
+        | Op::I64Xor
+        | Op::I64Shl
+        | Op::I64ShrS
+        | Op::I64ShrU
+        | Op::I64Rotl
+        | Op::I64Rotr
+        | Op::I32WrapI64
+        | Op::I64ExtendI32S
+        | Op::I64ExtendI32U
+        | Op::I32Extend8S
+        | Op::I32Extend16S
+        | Op::I64Extend8S
+        | Op::I64Extend16S
+        | Op::I64Extend32S => visitor.integer(pc, op),
+        #[cfg(feature = "optimize")]
+        Op::LocalI32AddConst(..) => visitor.integer(pc, op),
+
+        Op::F32Const(_)
+        | Op::F64Const(_)
+        | Op::F32Eq
+        | Op::F32Ne
+        | Op::F32Lt
+        | Op::F32Gt
+        | Op::F32Le
+        | Op::F32Ge
+        | Op::F64Eq
+        | Op::F64Ne
+        | Op::F64Lt
+        | Op::F64Gt
+        | Op::F64Le
+        | Op::F64Ge
+        | Op::F32Abs
+        | Op::F32Neg
+        | Op::F32Ceil
+        | Op::F32Floor
+        | Op::F32Trunc
+        | Op::F32Nearest
+        | Op::F32Sqrt
+        | Op::F32Add
+        | Op::F32Sub
+        | Op::F32Mul
+        | Op::F32Div
+        | Op::F32Min
+        | Op::F32Max
+        | Op::F32Copysign
+        | Op::F64Add
+        | Op::F64Sub
+        | Op::F64Mul
+        | Op::F64Div
+        | Op::F64Min
+        | Op::F64Max
+        | Op::F64Copysign
+        | Op::F64Abs
+        | Op::F64Neg
+        | Op::F64Ceil
+        | Op::F64Floor
+        | Op::F64Trunc
+        | Op::F64Nearest
+        | Op::F64Sqrt
+        | Op::I32TruncF32S
+        | Op::I32TruncF32U
+        | Op::I32TruncF64S
+        | Op::I32TruncF64U
+        | Op::I64TruncF32S
+        | Op::I64TruncF32U
+        | Op::I64TruncF64S
+        | Op::I64TruncF64U
+        | Op::I32TruncSatF32S
+        | Op::I32TruncSatF32U
+        | Op::I32TruncSatF64S
+        | Op::I32TruncSatF64U
+        | Op::I64TruncSatF32S
+        | Op::I64TruncSatF32U
+        | Op::I64TruncSatF64S
+        | Op::I64TruncSatF64U
+        | Op::F32ConvertI32S
+        | Op::F32ConvertI32U
+        | Op::F32ConvertI64S
+        | Op::F32ConvertI64U
+        | Op::F32DemoteF64
+        | Op::F64ConvertI32S
+        | Op::F64ConvertI32U
+        | Op::F64ConvertI64S
+        | Op::F64ConvertI64U
+        | Op::F64PromoteF32
+        | Op::I32ReinterpretF32
+        | Op::I64ReinterpretF64
+        | Op::F32ReinterpretI32
+        | Op::F64ReinterpretI64 => visitor.float(pc, op),
+
+        Op::RefNull(_) | Op::RefFunc(_) | Op::RefIsNull | Op::RefAsNonNull | Op::RefEq => {
+            visitor.reference(pc, op)
+        }
+        #[cfg(feature = "gc")]
+        Op::Gc(_) => visitor.reference(pc, op),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::visit::OpVisitor;
+    use crate::{decode_function, MemArg, Module, Op, ScopeSig, ScopeType};
+
+    /// Whether a function uses floats, the furthest it addresses memory, and how deep it nests.
+    #[derive(Default)]
+    struct Survey {
+        floats: usize,
+        furthest: Option<usize>,
+        depth: usize,
+        max_depth: usize,
+    }
+
+    impl OpVisitor for Survey {
+        fn enter_scope(&mut self, _pc: usize, _sig: ScopeSig, _scope_type: ScopeType) {
+            self.depth += 1;
+            self.max_depth = self.max_depth.max(self.depth);
+        }
+        fn exit_scope(&mut self, _pc: usize, _scope_type: ScopeType) {
+            self.depth -= 1;
+        }
+        fn memory(&mut self, _pc: usize, _op: &Op, memarg: Option<&MemArg>) {
+            if let Some(memarg) = memarg {
+                self.furthest = self.furthest.max(Some(memarg.offset));
+            }
+        }
+        fn float(&mut self, _pc: usize, _op: &Op) {
+            self.floats += 1;
+        }
+    }
+
+    #[test]
+    fn visitor_sees_each_kind_of_op() {
+        let wat = r#"(module (memory 1)
+            (func (param i32) (result f32)
+                (i32.store offset=64 (local.get 0) (i32.load offset=8 (local.get 0)))
+                (block (result f32)
+                    (f32.add (f32.load offset=16 (local.get 0)) (f32.const 1.5))))
+            (func (result i32) (i32.add (i32.const 1) (i32.const 2))))"#;
+        let module = Module::load(&wat::parse_str(wat).unwrap()).unwrap();
+        let survey = |i: usize| {
+            let func_type = &module.types[module.functions[i]];
+            let program = decode_function(module.code(i), &module.types, func_type).unwrap();
+            let mut survey = Survey::default();
+            program.visit(&mut survey);
+            survey
+        };
+
+        let first = survey(0);
+        assert_eq!(first.floats, 2);
+        assert_eq!(first.furthest, Some(64));
+        assert_eq!((first.depth, first.max_depth), (0, 2));
+
+        let second = survey(1);
+        assert_eq!(second.floats, 0);
+        assert_eq!(second.furthest, None);
+    }
+}
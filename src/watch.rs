@@ -0,0 +1,174 @@
+// Copyright (C) 2025 Ryan Daum <ryan.daum@gmail.com> This program is free
+// software: you can redistribute it and/or modify it under the terms of the GNU
+// General Public License as published by the Free Software Foundation, version
+// 3.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+//! Watchpoints, which suspend execution just after the guest writes to a watched range of memory
+//! or a watched global, so whatever's corrupting it can be caught in the act.
+
+use crate::op::{MemArg, Op};
+use crate::stack::{SlotStorage, Stack};
+use std::ops::Range;
+
+/// The write which tripped a watchpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchedWrite {
+    /// `len` bytes written to memory from `address` on, some of which are watched.
+    Memory { address: usize, len: usize },
+    /// A `global.set` of this global.
+    Global(u32),
+}
+
+/// Where a watchpoint was hit. Execution stops just after the write, with the writing frame's pc
+/// on the op after it, so running again carries on from there.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchHit {
+    pub write: WatchedWrite,
+    /// The function which made the write.
+    pub funcidx: u32,
+    /// The index of the writing op in that function's decoded body.
+    pub pc: usize,
+}
+
+/// The locations being watched. Only writes by guest code are caught; host functions writing to
+/// memory directly don't trip anything.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Watchpoints {
+    memory: Vec<Range<usize>>,
+    globals: Vec<u32>,
+}
+
+impl Watchpoints {
+    pub(crate) fn watch_memory(&mut self, range: Range<usize>) {
+        if !range.is_empty() {
+            self.memory.push(range);
+        }
+    }
+
+    pub(crate) fn watch_global(&mut self, index: u32) {
+        if !self.globals.contains(&index) {
+            self.globals.push(index);
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.memory.clear();
+        self.globals.clear();
+    }
+
+    #[inline]
+    pub(crate) fn is_empty(&self) -> bool {
+        self.memory.is_empty() && self.globals.is_empty()
+    }
+
+    /// The watched write `op` is about to make, worked out from its operands on `stack`. Writes
+    /// which turn out to be out of bounds fault before they can be reported.
+    pub(crate) fn check<S: SlotStorage>(&self, op: &Op, stack: &Stack<S>) -> Option<WatchedWrite> {
+        if let Op::SetGlobal(index) = op {
+            return self
+                .globals
+                .contains(index)
+                .then_some(WatchedWrite::Global(*index));
+        }
+        let (above, memarg, len) = memory_write(op)?;
+        let slots = stack.slots();
+        let base = *slots.get(slots.len().checked_sub(above + 1)?)? as u32;
+        let address = base as u64 + memarg.offset as u64;
+        let end = address + len as u64;
+        self.memory
+            .iter()
+            .any(|r| address < r.end as u64 && (r.start as u64) < end)
+            .then_some(WatchedWrite::Memory {
+                address: address as usize,
+                len,
+            })
+    }
+}
+
+/// For an op which writes to memory: how many stack slots sit above its address operand, its
+/// memarg, and how many bytes it writes.
+fn memory_write(op: &Op) -> Option<(usize, &MemArg, usize)> {
+    Some(match op {
+        Op::StoreI32(memarg) | Op::StoreF32(memarg) | Op::Store32_64(memarg) => (1, memarg, 4),
+        Op::StoreI64(memarg) | Op::StoreF64(memarg) => (1, memarg, 8),
+        Op::Store8_32(memarg) | Op::Store8_64(memarg) => (1, memarg, 1),
+        Op::Store16_32(memarg) | Op::Store16_64(memarg) => (1, memarg, 2),
+        #[cfg(feature = "optimize")]
+        Op::ConstStoreI32(_, memarg) => (0, memarg, 4),
+        #[cfg(feature = "atomics")]
+        Op::Atomic(op) => return op.memory_write(),
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::exec::{ExecError, Execution, SuspendReason, Value};
+    use crate::instance::mk_instance;
+    use crate::memory::Memory;
+    use crate::module::Module;
+    use crate::watch::{WatchHit, WatchedWrite};
+
+    fn next_hit<M: Memory>(execution: &mut Execution<M>) -> Option<WatchHit> {
+        match execution.run() {
+            Ok(()) => None,
+            Err(ExecError::Suspended(SuspendReason::Watchpoint(hit))) => Some(hit),
+            Err(e) => panic!("unexpected error: {e}"),
+        }
+    }
+
+    #[test]
+    fn writes_suspend_and_resume() {
+        let wat = r#"(module
+            (memory 1)
+            (global $g (mut i32) (i32.const 0))
+            (func (export "f") (param i32) (result i32)
+                (i32.store8 (i32.const 15) (i32.const 1))
+                (i64.store offset=4 (local.get 0) (i64.const -1))
+                (global.set $g (i32.const 9))
+                (i32.add (i32.load (i32.const 16)) (global.get $g))))"#;
+        let module = Module::load(&wat::parse_str(wat).unwrap()).unwrap();
+        let linked = mk_instance(module).unwrap();
+        let funcidx = linked.find_funcidx("f").unwrap();
+        let memory = linked.memories[0].clone();
+        let mut execution = Execution::new(linked, memory);
+        execution.watch_memory(16..20);
+        execution.watch_global(0);
+
+        // The byte store just misses the range, and the i64 store at 12..20 overlaps it.
+        execution.prepare(funcidx, &[Value::I32(8)]).unwrap();
+        let hit = next_hit(&mut execution).unwrap();
+        assert_eq!(
+            hit.write,
+            WatchedWrite::Memory {
+                address: 12,
+                len: 8
+            }
+        );
+        assert_eq!(hit.funcidx, funcidx);
+        // Stopped after the write, and without poisoning anything.
+        assert_eq!(execution.memory().get_u8(16).unwrap(), 0xff);
+        assert!(!execution.is_poisoned());
+
+        let next = next_hit(&mut execution).unwrap();
+        assert_eq!(next.write, WatchedWrite::Global(0));
+        assert!(next.pc > hit.pc);
+
+        assert_eq!(next_hit(&mut execution), None);
+        assert_eq!(execution.result().unwrap(), &[Value::I32(8)]);
+
+        // With nothing watched, the same call runs straight through.
+        execution.clear_watchpoints();
+        execution.prepare(funcidx, &[Value::I32(8)]).unwrap();
+        assert_eq!(next_hit(&mut execution), None);
+        assert_eq!(execution.result().unwrap(), &[Value::I32(8)]);
+    }
+}
@@ -13,27 +13,43 @@
 
 #[cfg(test)]
 mod tests {
+    use std::collections::HashMap;
     use std::fmt::{Debug, Formatter};
     use std::path::Path;
+    use std::sync::{Arc, Mutex, MutexGuard};
     use wasbox::{
-        mk_instance, DecodeError, Execution, LinkError, LoaderError, Module, VectorMemory,
+        spectest, DecodeError, Determinism, Execution, Instance, LinkError, Linker, LoadConfig,
+        LoaderError, Module, VectorMemory,
     };
     use wast::core::{NanPattern, WastArgCore, WastRetCore};
     use wast::lexer::Lexer;
+    use wast::token::Id;
     use wast::{
         parser, QuoteWat, Wast, WastArg, WastDirective, WastExecute, WastInvoke, WastRet, Wat,
     };
 
-    macro_rules! wast_test {
-        ($test_name:ident, $wast_file:literal) => {
-            #[test]
-            fn $test_name() {
-                let path = Path::new(concat!("tests/testsuite/", $wast_file));
-                perform_wast(path);
-            }
+    macro_rules! wast_tests {
+        ($($test_name:ident => $wast_file:literal),* $(,)?) => {
+            $(
+                #[test]
+                fn $test_name() {
+                    let path = Path::new(concat!("tests/testsuite/", $wast_file));
+                    perform_wast(path);
+                }
+            )*
+
+            /// Every wast file with a test of its own.
+            #[cfg_attr(not(feature = "coverage"), allow(dead_code))]
+            const WAST_FILES: &[&str] = &[$($wast_file),*];
         };
     }
 
+    /// Instantiate against the `spectest` module alone. Imports it can't satisfy are left
+    /// unresolved, and trap if called.
+    fn instantiate(module: Module) -> Result<Instance, LinkError> {
+        spectest().allow_unresolved(true).instantiate(module)
+    }
+
     enum DecodeResult {
         Success(()),
         Failure(LoaderError),
@@ -111,7 +127,8 @@ mod tests {
                         DecodeError::MalformedMemory(_),
                     )) => {}
                     DecodeResult::Failure(LoaderError::DecoderError(
-                        DecodeError::UnimplementedOpcode(_, _),
+                        DecodeError::UnimplementedOpcode(_, _)
+                        | DecodeError::UnimplementedSubOpcode(_, _, _),
                     )) => {}
                     DecodeResult::Failure(e) => {
                         failures.push((n, path.clone(), name, e, bin));
@@ -184,8 +201,7 @@ mod tests {
     }
 
     enum TestModule {
-        None,
-        Loaded(Box<Execution<VectorMemory>>),
+        Loaded(Arc<Mutex<Execution<VectorMemory>>>),
         LoadFailed(LoaderError),
         LinkFailed(LinkError),
     }
@@ -193,7 +209,6 @@ mod tests {
     impl Debug for TestModule {
         fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
             match self {
-                TestModule::None => write!(f, "None"),
                 TestModule::Loaded(_) => write!(f, "Loaded"),
                 TestModule::LoadFailed(e) => write!(f, "LoadFailed({e:?})"),
                 TestModule::LinkFailed(e) => write!(f, "LinkFailed({e:?})"),
@@ -203,9 +218,9 @@ mod tests {
 
     impl TestModule {
         fn load(binary: &[u8]) -> Self {
-            let m = Module::load(binary);
+            let m = Module::load_with(binary, &LoadConfig::strict());
             match m {
-                Ok(m) => match mk_instance(m) {
+                Ok(m) => match instantiate(m) {
                     Ok(i) => {
                         // Use first memory if available, otherwise create a dummy memory
                         let memory = if !i.memories.is_empty() {
@@ -213,7 +228,7 @@ mod tests {
                         } else {
                             VectorMemory::new(0, None)
                         };
-                        TestModule::Loaded(Box::new(Execution::new(i, memory)))
+                        TestModule::Loaded(Arc::new(Mutex::new(Execution::new(i, memory))))
                     }
                     Err(e) => TestModule::LinkFailed(e),
                 },
@@ -222,6 +237,93 @@ mod tests {
         }
     }
 
+    /// The set of live modules in a wast script. Modules are addressable by their `$id`, or by
+    /// the name they were `register`ed under, and directives which don't name a module act on
+    /// the most recently defined one. The exports of registered modules are offered to modules
+    /// instantiated after them, alongside `spectest`.
+    struct ModuleRegistry {
+        modules: Vec<TestModule>,
+        names: HashMap<String, usize>,
+        current: Option<usize>,
+        linker: Linker,
+    }
+
+    impl Default for ModuleRegistry {
+        fn default() -> Self {
+            let mut linker = spectest();
+            linker.allow_unresolved(true);
+            ModuleRegistry {
+                modules: vec![],
+                names: HashMap::new(),
+                current: None,
+                linker,
+            }
+        }
+    }
+
+    impl ModuleRegistry {
+        fn define(&mut self, id: Option<Id>, module: TestModule) {
+            let idx = self.modules.len();
+            self.modules.push(module);
+            if let Some(id) = id {
+                self.names.insert(id.name().to_string(), idx);
+            }
+            self.current = Some(idx);
+        }
+
+        fn register(&mut self, name: &str, id: Option<Id>) {
+            let idx = self
+                .index_of(id)
+                .unwrap_or_else(|| panic!("No module to register as {name:?}"));
+            self.names.insert(name.to_string(), idx);
+            let Some(TestModule::Loaded(execution)) = self.modules.get(idx) else {
+                return;
+            };
+            let exports: Vec<_> = {
+                let execution = execution.lock().unwrap();
+                execution
+                    .instance()
+                    .module
+                    .exports()
+                    .iter()
+                    .map(|e| e.name.clone())
+                    .collect()
+            };
+            for export in exports {
+                if let Some(def) = Execution::export_shared(execution, &export) {
+                    self.linker.define(name, &export, def);
+                }
+            }
+        }
+
+        /// Instantiate against `spectest` and the modules registered so far.
+        fn instantiate(&self, module: Module) -> Result<Instance, LinkError> {
+            self.linker.instantiate(module)
+        }
+
+        fn index_of(&self, id: Option<Id>) -> Option<usize> {
+            match id {
+                Some(id) => self.names.get(id.name()).copied(),
+                None => self.current,
+            }
+        }
+
+        fn get_mut(&mut self, id: Option<Id>) -> Option<&mut TestModule> {
+            let idx = self.index_of(id)?;
+            self.modules.get_mut(idx)
+        }
+
+        /// Find the execution for the (possibly named) module an invoke is aimed at.
+        fn execution(&mut self, id: Option<Id>) -> MutexGuard<'_, Execution<VectorMemory>> {
+            let name = id.map(|id| id.name().to_string());
+            match self.get_mut(id) {
+                Some(TestModule::Loaded(execution)) => execution.lock().unwrap(),
+                Some(other) => panic!("Module {name:?} is not loaded: {other:?}"),
+                None => panic!("Unknown module {name:?}"),
+            }
+        }
+    }
+
     fn perform_wast(path: &Path) {
         let file = std::fs::File::open(path).unwrap();
         let input = std::io::read_to_string(file).unwrap();
@@ -231,17 +333,21 @@ mod tests {
         let ast = parser::parse::<Wast>(&pb)
             .unwrap_or_else(|_| panic!("Failed to parse WAST file {path:?}"));
 
-        let mut execution = TestModule::None;
+        let mut registry = ModuleRegistry::default();
         for (directive_num, directive) in ast.directives.into_iter().enumerate() {
             let directive_span = directive.span();
             let linecol = directive_span.linecol_in(&input);
 
             match directive {
                 WastDirective::Module(mut module) => {
+                    let id = match &module {
+                        QuoteWat::Wat(Wat::Module(m)) => m.id,
+                        _ => None,
+                    };
                     let encoded = module.encode().unwrap();
                     let m = Module::load(&encoded);
-                    execution = match m {
-                        Ok(m) => match mk_instance(m) {
+                    let loaded = match m {
+                        Ok(m) => match registry.instantiate(m) {
                             Ok(i) => {
                                 // Use first memory if available, otherwise create a dummy memory
                                 let memory = if !i.memories.is_empty() {
@@ -249,7 +355,7 @@ mod tests {
                                 } else {
                                     VectorMemory::new(0, None)
                                 };
-                                TestModule::Loaded(Box::new(Execution::new(i, memory)))
+                                TestModule::Loaded(Arc::new(Mutex::new(Execution::new(i, memory))))
                             }
                             Err(e) => {
                                 eprintln!("Link failed at directive #{directive_num} @ {linecol:?}: {e:?}");
@@ -263,18 +369,20 @@ mod tests {
                             TestModule::LoadFailed(e)
                         }
                     };
+                    registry.define(id, loaded);
+                }
+                WastDirective::Register { name, module, .. } => {
+                    registry.register(name, module);
                 }
                 WastDirective::AssertReturn { exec, results, .. } => match exec {
-                    // Invoke runs executions on the last loaded module.
+                    // Invoke runs executions on the named module, or the last loaded one.
                     WastExecute::Invoke(WastInvoke {
                         span: _,
-                        module: _,
+                        module,
                         name,
                         args,
                     }) => {
-                        let TestModule::Loaded(ref mut execution) = execution else {
-                            panic!("Expected a loaded module for invocation @ {linecol:?}");
-                        };
+                        let mut execution = registry.execution(module);
                         let funcidx = execution
                             .instance()
                             .find_funcidx(name)
@@ -311,26 +419,24 @@ mod tests {
                             continue;
                         }
                     };
-                    execution = TestModule::load(&encoding);
+                    let malformed = TestModule::load(&encoding);
                     // There has to be a LoadFailed or LinkError for this to be malformed.
-                    match execution {
+                    match malformed {
                         TestModule::LoadFailed(_) | TestModule::LinkFailed(_) => {
                             // All good.
                         }
                         _ => panic!(
-                            "Expected a load error w/ {message}, got {execution:?} for directive #{directive_num} @ {linecol:?}",
+                            "Expected a load error w/ {message}, got {malformed:?} for directive #{directive_num} @ {linecol:?}",
                         ),
                     }
                 }
                 WastDirective::Invoke(WastInvoke {
                     span: _,
-                    module: _,
+                    module,
                     name,
                     args,
                 }) => {
-                    let TestModule::Loaded(ref mut execution) = execution else {
-                        panic!("Expected a loaded module for invocation @ {linecol:?}");
-                    };
+                    let mut execution = registry.execution(module);
                     let funcidx = execution
                         .instance()
                         .find_funcidx(name)
@@ -344,13 +450,11 @@ mod tests {
                 WastDirective::AssertTrap { exec, message, .. } => match exec {
                     WastExecute::Invoke(WastInvoke {
                         span: _,
-                        module: _,
+                        module,
                         name,
                         args,
                     }) => {
-                        let TestModule::Loaded(ref mut execution) = execution else {
-                            panic!("Expected a loaded module for invocation @ {linecol:?}");
-                        };
+                        let mut execution = registry.execution(module);
                         let funcidx = execution
                             .instance()
                             .find_funcidx(name)
@@ -359,6 +463,8 @@ mod tests {
                         let arg_set: Vec<_> = args.iter().map(convert_value).collect();
                         execution.prepare(funcidx, &arg_set).unwrap();
                         let result = execution.run();
+                        // A trap leaves the execution poisoned until it's reset.
+                        execution.reset();
 
                         // We expect this to fail with a trap
                         match result {
@@ -390,6 +496,35 @@ mod tests {
                             ),
                         }
                     }
+                    // A module whose instantiation should trap, e.g. on an out of bounds segment.
+                    WastExecute::Wat(mut module) => {
+                        let encoded = module.encode().unwrap();
+                        let m = Module::load(&encoded).unwrap_or_else(|e| {
+                            panic!(
+                                "Load failed for directive #{directive_num} @ {linecol:?}: {e:?}"
+                            )
+                        });
+                        match registry.instantiate(m) {
+                            Err(LinkError::ActiveExpressionError(fault)) => {
+                                let expected_message = message.to_string();
+                                let fault_message = fault.to_string();
+                                let is_compatible = match (expected_message.as_str(), fault_message.as_str()) {
+                                    ("out of bounds memory access", "Memory out of bounds") => true,
+                                    (expected, actual) => actual.contains(expected),
+                                };
+                                assert!(
+                                    is_compatible,
+                                    "Expected trap message '{expected_message}', got '{fault_message}' for directive #{directive_num} @ {linecol:?}"
+                                );
+                            }
+                            Ok(_) => panic!(
+                                "Expected instantiation to trap with '{message}' for directive #{directive_num} @ {linecol:?}"
+                            ),
+                            Err(other) => panic!(
+                                "Expected instantiation to trap with '{message}', got {other:?} for directive #{directive_num} @ {linecol:?}"
+                            ),
+                        }
+                    }
                     _ => {
                         panic!("Unsupported exec directive in assert_trap: {exec:?} @ {linecol:?}")
                     }
@@ -399,63 +534,155 @@ mod tests {
         }
     }
 
+    /// Run every `assert_return` in a script in strict mode, checking that NaNs the script expects
+    /// to be canonical are exactly that. That results come out bit-for-bit the same on every run
+    /// and host is checked against a golden digest in `exec`'s own tests, which don't need the
+    /// testsuite checked out.
+    fn check_strict_nans(path: &Path) {
+        let input = std::fs::read_to_string(path).unwrap();
+        let pb = wast::parser::ParseBuffer::new(&input).unwrap();
+        let ast = parser::parse::<Wast>(&pb)
+            .unwrap_or_else(|_| panic!("Failed to parse WAST file {path:?}"));
+
+        let mut registry = ModuleRegistry::default();
+        for directive in ast.directives {
+            match directive {
+                WastDirective::Module(mut module) => {
+                    let id = match &module {
+                        QuoteWat::Wat(Wat::Module(m)) => m.id,
+                        _ => None,
+                    };
+                    let mut loaded = TestModule::load(&module.encode().unwrap());
+                    if let TestModule::Loaded(execution) = &mut loaded {
+                        execution
+                            .lock()
+                            .unwrap()
+                            .set_determinism(Determinism::Strict);
+                    }
+                    registry.define(id, loaded);
+                }
+                WastDirective::AssertReturn {
+                    exec: WastExecute::Invoke(invoke),
+                    results,
+                    ..
+                } => {
+                    let mut execution = registry.execution(invoke.module);
+                    let funcidx = execution.instance().find_funcidx(invoke.name).unwrap();
+                    let args: Vec<_> = invoke.args.iter().map(convert_value).collect();
+                    execution.prepare(funcidx, &args).unwrap();
+                    execution.run().unwrap();
+                    for (expected, actual) in results.iter().zip(execution.result().unwrap()) {
+                        let bits = match actual {
+                            wasbox::Value::F32(f) => f.to_bits() as u64,
+                            wasbox::Value::F64(f) => f.to_bits(),
+                            _ => continue,
+                        };
+                        match expected {
+                            WastRet::Core(WastRetCore::F32(NanPattern::CanonicalNan)) => {
+                                assert_eq!(bits & 0x7fff_ffff, 0x7fc0_0000, "{}", invoke.name)
+                            }
+                            WastRet::Core(WastRetCore::F64(NanPattern::CanonicalNan)) => {
+                                assert_eq!(
+                                    bits & 0x7fff_ffff_ffff_ffff,
+                                    0x7ff8_0000_0000_0000,
+                                    "{}",
+                                    invoke.name
+                                )
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    #[test]
+    fn strict_float_nans_are_canonical() {
+        for file in [
+            "f32.wast",
+            "f64.wast",
+            "float_exprs.wast",
+            "conversions.wast",
+        ] {
+            let path = Path::new("tests/testsuite").join(file);
+            check_strict_nans(&path);
+        }
+    }
+
     // WAST test suite tests
-    wast_test!(address_test, "address.wast");
-    wast_test!(align_test, "align.wast");
-    wast_test!(binary_test, "binary.wast");
-    wast_test!(binary_leb128_test, "binary-leb128.wast");
-    wast_test!(block_test, "block.wast");
-    wast_test!(br_test, "br.wast");
-    wast_test!(br_if_test, "br_if.wast");
-    wast_test!(br_table_test, "br_table.wast");
-    wast_test!(call_test, "call.wast");
-    wast_test!(const_test, "const.wast");
-    wast_test!(data_test, "data.wast");
-    wast_test!(f32_test, "f32.wast");
-    wast_test!(f32_bitwise_test, "f32_bitwise.wast");
-    wast_test!(f64_test, "f64.wast");
-    wast_test!(f64_bitwise_test, "f64_bitwise.wast");
-    wast_test!(global_test, "global.wast");
-    wast_test!(i32_test, "i32.wast");
-    wast_test!(i64_test, "i64.wast");
-    wast_test!(if_test, "if.wast");
-    wast_test!(local_get_test, "local_get.wast");
-    wast_test!(local_set_test, "local_set.wast");
-    wast_test!(loop_test, "loop.wast");
-    wast_test!(ref_func_test, "ref_func.wast");
-    wast_test!(ref_is_null_test, "ref_is_null.wast");
-    wast_test!(ref_null_test, "ref_null.wast");
-    wast_test!(nop_test, "nop.wast");
-    wast_test!(return_test, "return.wast");
-    wast_test!(select_test, "select.wast");
-    wast_test!(local_tee_test, "local_tee.wast");
-    wast_test!(call_indirect_test, "call_indirect.wast");
-    wast_test!(unreachable_test, "unreachable.wast");
-    wast_test!(traps_test, "traps.wast");
-    wast_test!(store_test, "store.wast");
-    wast_test!(load_test, "load.wast");
-    wast_test!(stack_test, "stack.wast");
-    wast_test!(type_test, "type.wast");
-    wast_test!(comments_test, "comments.wast");
-    wast_test!(conversions_test, "conversions.wast");
-    wast_test!(memory_test, "memory.wast");
-    wast_test!(memory_size_test, "memory_size.wast");
-    wast_test!(memory_grow_test, "memory_grow.wast");
-    wast_test!(elem_test, "elem.wast");
-    wast_test!(table_test, "table.wast");
-    wast_test!(exports_test, "exports.wast");
-    wast_test!(imports_test, "imports.wast");
-    wast_test!(start_test, "start.wast");
-    wast_test!(func_test, "func.wast");
-    wast_test!(linking_test, "linking.wast");
-    wast_test!(custom_test, "custom.wast");
-    wast_test!(endianness_test, "endianness.wast");
-    wast_test!(int_literals_test, "int_literals.wast");
-    wast_test!(float_literals_test, "float_literals.wast");
-    wast_test!(int_exprs_test, "int_exprs.wast");
-    wast_test!(float_exprs_test, "float_exprs.wast");
-    wast_test!(labels_test, "labels.wast");
-    wast_test!(left_to_right_test, "left-to-right.wast");
+    wast_tests! {
+        address_test => "address.wast",
+        align_test => "align.wast",
+        binary_test => "binary.wast",
+        binary_leb128_test => "binary-leb128.wast",
+        block_test => "block.wast",
+        br_test => "br.wast",
+        br_if_test => "br_if.wast",
+        br_table_test => "br_table.wast",
+        call_test => "call.wast",
+        const_test => "const.wast",
+        data_test => "data.wast",
+        f32_test => "f32.wast",
+        f32_bitwise_test => "f32_bitwise.wast",
+        f64_test => "f64.wast",
+        f64_bitwise_test => "f64_bitwise.wast",
+        global_test => "global.wast",
+        i32_test => "i32.wast",
+        i64_test => "i64.wast",
+        if_test => "if.wast",
+        local_get_test => "local_get.wast",
+        local_set_test => "local_set.wast",
+        loop_test => "loop.wast",
+        ref_func_test => "ref_func.wast",
+        ref_is_null_test => "ref_is_null.wast",
+        ref_null_test => "ref_null.wast",
+        nop_test => "nop.wast",
+        return_test => "return.wast",
+        select_test => "select.wast",
+        local_tee_test => "local_tee.wast",
+        call_indirect_test => "call_indirect.wast",
+        unreachable_test => "unreachable.wast",
+        traps_test => "traps.wast",
+        store_test => "store.wast",
+        load_test => "load.wast",
+        stack_test => "stack.wast",
+        type_test => "type.wast",
+        comments_test => "comments.wast",
+        conversions_test => "conversions.wast",
+        memory_test => "memory.wast",
+        memory_size_test => "memory_size.wast",
+        memory_grow_test => "memory_grow.wast",
+        elem_test => "elem.wast",
+        table_test => "table.wast",
+        exports_test => "exports.wast",
+        imports_test => "imports.wast",
+        start_test => "start.wast",
+        func_test => "func.wast",
+        linking_test => "linking.wast",
+        custom_test => "custom.wast",
+        endianness_test => "endianness.wast",
+        int_literals_test => "int_literals.wast",
+        float_literals_test => "float_literals.wast",
+        int_exprs_test => "int_exprs.wast",
+        float_exprs_test => "float_exprs.wast",
+        labels_test => "labels.wast",
+        left_to_right_test => "left-to-right.wast",
+    }
+
+    /// Run every wast file, then report the ops none of them executed. Tests running alongside
+    /// this one can only add to what's covered, never take away.
+    #[cfg(feature = "coverage")]
+    #[test]
+    fn op_coverage() {
+        for file in WAST_FILES {
+            perform_wast(&Path::new("tests/testsuite").join(file));
+        }
+        let report = wasbox::coverage::report();
+        eprintln!("{report}");
+        assert!(!report.executed.is_empty());
+    }
 
     #[test]
     fn test_start_function_execution() {